@@ -0,0 +1,193 @@
+//! The `apply-patches` subcommand fetches the sources of a recipe into a directory and
+//! applies the patches of the recipe, without building (e.g. to check the patches after
+//! a version bump).
+//!
+//! Unlike a build, which stops at the first patch that fails, every patch is tried and
+//! the patches that do not apply are listed at the end. With `--rejects`, the hunks of a
+//! failing patch that apply are kept and the rejected hunks are written to `.rej` files,
+//! so that the sources can be fixed by hand and the patch regenerated with
+//! `rattler-build generate-patch`.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use fs_err as fs;
+use miette::IntoDiagnostic;
+
+use crate::{
+    console_utils::LoggingOutputHandler,
+    get_build_output, get_recipe_paths, get_tool_config,
+    metadata::Directories,
+    opt::BuildOpts,
+    recipe::parser::Source,
+    source::{
+        fetch_sources,
+        patch::{apply_patch, apply_patch_with_rejects},
+    },
+};
+
+/// Options for the `apply-patches` subcommand.
+#[derive(Parser)]
+pub struct ApplyPatchesOpts {
+    /// The directory to fetch the sources into (must not exist or be empty)
+    #[arg(long)]
+    pub work_dir: PathBuf,
+
+    /// The name of the output, if the recipe has multiple outputs
+    #[arg(long)]
+    pub output_name: Option<String>,
+
+    /// Keep the hunks of a failing patch that apply and write the rejected hunks to
+    /// `.rej` files
+    #[arg(long)]
+    pub rejects: bool,
+
+    /// Build options, used to render the recipe and fetch the sources
+    #[clap(flatten)]
+    pub build: BuildOpts,
+}
+
+/// All `.rej` files in a directory.
+fn reject_files(dir: &Path) -> BTreeSet<PathBuf> {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rej"))
+        .collect()
+}
+
+/// Fetch the sources of a recipe into the work directory and apply all patches,
+/// reporting every patch that does not apply.
+pub async fn apply_patches_from_args(
+    args: ApplyPatchesOpts,
+    fancy_log_handler: LoggingOutputHandler,
+) -> miette::Result<()> {
+    if args.work_dir.exists()
+        && fs::read_dir(&args.work_dir)
+            .into_diagnostic()?
+            .next()
+            .is_some()
+    {
+        return Err(miette::miette!(
+            "The work directory {} is not empty",
+            args.work_dir.display()
+        ));
+    }
+    fs::create_dir_all(&args.work_dir).into_diagnostic()?;
+    let work_dir = dunce::canonicalize(&args.work_dir).into_diagnostic()?;
+
+    let tool_config = get_tool_config(&args.build, &fancy_log_handler)?;
+    let mut outputs = Vec::new();
+    for recipe_path in get_recipe_paths(&args.build)? {
+        outputs.extend(get_build_output(&args.build, &recipe_path, &tool_config).await?);
+    }
+    if let Some(output_name) = &args.output_name {
+        outputs.retain(|output| output.name().as_normalized() == output_name);
+    }
+    let Some(output) = outputs.into_iter().next() else {
+        return Err(miette::miette!("No matching output found in the recipe"));
+    };
+
+    let sources = output.recipe.sources();
+    if sources.iter().all(|source| source.patches().is_empty()) {
+        return Err(miette::miette!("The recipe has no patches to apply"));
+    }
+
+    // fetch the sources without their patches, they are applied one by one below
+    let unpatched = sources
+        .iter()
+        .map(Source::without_patches)
+        .collect::<Vec<_>>();
+    let directories = Directories {
+        work_dir: work_dir.clone(),
+        ..output.build_configuration.directories.clone()
+    };
+    fetch_sources(
+        &unpatched,
+        &directories,
+        output.build_configuration.host_platform,
+        &output.system_tools,
+        &tool_config,
+        false,
+    )
+    .await
+    .into_diagnostic()?;
+
+    let recipe_dir = &output.build_configuration.directories.recipe_dir;
+    let mut failed = Vec::new();
+    let mut total = 0;
+    for source in sources {
+        let dest_dir = match source.target_directory() {
+            Some(target_directory) => work_dir.join(target_directory),
+            None => work_dir.clone(),
+        };
+        for patch in source.patches() {
+            total += 1;
+            let err = match apply_patch(&output.system_tools, patch, &dest_dir, recipe_dir) {
+                Ok(()) => {
+                    println!("Applied {}", patch.describe());
+                    continue;
+                }
+                Err(err) => err,
+            };
+            println!("Failed to apply {}", patch.describe());
+            tracing::error!("{}", err);
+
+            if args.rejects {
+                let before = reject_files(&dest_dir);
+                let log =
+                    apply_patch_with_rejects(&output.system_tools, patch, &dest_dir, recipe_dir)
+                        .into_diagnostic()?;
+                tracing::info!("{}", log.trim());
+                for reject in reject_files(&dest_dir).difference(&before) {
+                    println!("  rejected hunks: {}", reject.display());
+                }
+            }
+            failed.push(patch.path().to_path_buf());
+        }
+    }
+
+    println!("\nSources are in {}", work_dir.display());
+    if failed.is_empty() {
+        println!("All {} patches applied", total);
+        Ok(())
+    } else {
+        Err(miette::miette!(
+            "{} of {} patches could not be applied: {}",
+            failed.len(),
+            total,
+            failed
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reject_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/foo.c"), "").unwrap();
+        fs::write(dir.path().join("src/foo.c.rej"), "").unwrap();
+        fs::write(dir.path().join("Makefile.rej"), "").unwrap();
+
+        assert_eq!(
+            reject_files(dir.path()),
+            BTreeSet::from([
+                dir.path().join("Makefile.rej"),
+                dir.path().join("src/foo.c.rej")
+            ])
+        );
+    }
+}
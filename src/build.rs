@@ -1,17 +1,62 @@
 //! The build module contains the code for running the build process for a given [`Output`]
-use rattler_conda_types::{Channel, MatchSpec, ParseStrictness};
-use std::path::PathBuf;
+use rattler_conda_types::{Channel, MatchSpec, ParseStrictness, Platform};
+use std::path::{Path, PathBuf};
 use std::vec;
 
 use miette::IntoDiagnostic;
-use rattler_index::index;
 
-use crate::metadata::Output;
+use crate::build_stats::{disk_usage, ResourceStats, StatsRecorder};
+use crate::cache_lock::CacheLock;
+use crate::gpu::{self, GpuVariant};
+use crate::metadata::{Output, ScanResult};
 use crate::package_test::TestConfiguration;
-use crate::recipe::parser::TestType;
+use crate::recipe::parser::{ScriptPhase, TestType};
 use crate::render::solver::load_repodatas;
+use crate::tool_configuration::TestPolicy;
 use crate::utils::remove_dir_all_force;
-use crate::{package_test, tool_configuration};
+use crate::{hooks, index, package_test, provenance, tool_configuration};
+
+/// The platform that the tests of an output run on: the platform of the machine for
+/// `noarch` packages, and the host platform otherwise.
+fn test_platform(output: &Output) -> Platform {
+    if output.recipe.build().noarch().is_none() {
+        output.build_configuration.host_platform
+    } else {
+        Platform::current()
+    }
+}
+
+/// Check whether the tests of an output can run on this machine, given the test policy.
+/// Returns the reason why the tests are skipped, or an error if the policy requires
+/// tests that cannot run.
+fn tests_skipped_reason(
+    output: &Output,
+    test_policy: TestPolicy,
+) -> miette::Result<Option<String>> {
+    // an emulated build (`--emulate`) already made sure that the platform can run
+    if output.build_configuration.emulation.is_some() {
+        return Ok(None);
+    }
+    test_policy.skipped_reason(Platform::current(), test_platform(output))
+}
+
+/// Check whether the tests of a GPU variant can run on this machine. Without a GPU of
+/// the variant the tests are skipped, unless `CONDA_OVERRIDE_CUDA` mocks the driver of
+/// a CUDA variant.
+fn gpu_tests_skipped_reason(gpu_variant: GpuVariant) -> Option<String> {
+    if gpu_variant.is_available()
+        || (gpu_variant == GpuVariant::Cuda && gpu::cuda_override().is_some())
+    {
+        return None;
+    }
+    Some(format!(
+        "no GPU for the {} variant is available on this machine (in a container, pass the GPU \
+         through, e.g. with `docker run --gpus all`, or set {} to test a CUDA variant with a \
+         mocked driver)",
+        gpu_variant,
+        gpu::CUDA_OVERRIDE_ENV
+    ))
+}
 
 /// Check if the build should be skipped because it already exists in any of the channels
 pub async fn skip_existing(
@@ -32,7 +77,10 @@ pub async fn skip_existing(
         return Ok(outputs);
     };
 
-    let all_channels = first_output.reindex_channels().into_diagnostic()?;
+    let all_channels = first_output
+        .channels(tool_configuration)
+        .await
+        .into_diagnostic()?;
 
     let match_specs = outputs
         .iter()
@@ -41,11 +89,15 @@ pub async fn skip_existing(
         })
         .collect::<Result<Vec<_>, _>>()?;
 
+    // the output directory is missing from the channels if a dry run finds it unindexed
     let channels = if only_local {
-        vec![
+        let output_channel =
             Channel::from_directory(&first_output.build_configuration.directories.output_dir)
-                .base_url,
-        ]
+                .base_url;
+        all_channels
+            .into_iter()
+            .filter(|channel| *channel == output_channel)
+            .collect()
     } else {
         all_channels
     };
@@ -103,6 +155,26 @@ pub async fn run_build(
         miette::bail!("Build string is not set for {:?}", output.name());
     }
 
+    // Concurrent incremental builds of the same recipe would share the build directory
+    let _build_lock = if tool_configuration.incremental {
+        let build_dir = &output.build_configuration.directories.build_dir;
+        Some(
+            CacheLock::acquire_async(&build_dir.with_extension("lock"), "build directory")
+                .await
+                .into_diagnostic()?,
+        )
+    } else {
+        None
+    };
+
+    // Incremental builds keep the environments, but start from fresh sources (unless
+    // only the later phases of the build script are run again)
+    let work_dir = &output.build_configuration.directories.work_dir;
+    let reuse_work_dir = tool_configuration.from_phase.is_some() && work_dir.exists();
+    if tool_configuration.incremental && work_dir.exists() && !reuse_work_dir {
+        remove_dir_all_force(work_dir).into_diagnostic()?;
+    }
+
     output
         .build_configuration
         .directories
@@ -112,26 +184,56 @@ pub async fn run_build(
     let span = tracing::info_span!("Running build for", recipe = output.identifier().unwrap());
     let _enter = span.enter();
     output.record_build_start();
+    let build_stats = StatsRecorder::start();
 
     let directories = output.build_configuration.directories.clone();
 
-    index(
-        &directories.output_dir,
-        Some(&output.build_configuration.target_platform.clone()),
-    )
-    .into_diagnostic()?;
+    output.index_output_dir().await.into_diagnostic()?;
 
-    let output = output
-        .fetch_sources(tool_configuration)
-        .await
-        .into_diagnostic()?;
+    let output = if reuse_work_dir {
+        tracing::info!("Reusing the sources in {}", work_dir.display());
+        output
+    } else {
+        output
+            .fetch_sources(tool_configuration)
+            .await
+            .into_diagnostic()?
+    };
+
+    // The `cache` build of a multi-output recipe runs once for all outputs
+    let build_cache = output.build_or_fetch_cache(tool_configuration).await?;
 
     let output = output
         .resolve_dependencies(tool_configuration)
         .await
         .into_diagnostic()?;
 
-    output.run_build_script().await.into_diagnostic()?;
+    if let Some(build_cache) = &build_cache {
+        output.restore_cache(build_cache).into_diagnostic()?;
+    }
+
+    if let Some(deprecated) = output.recipe.deprecated() {
+        tracing::info!(
+            "Creating a tombstone package: {}",
+            deprecated.message(output.name())
+        );
+    } else if let Err(err) = output
+        .run_build_script(
+            tool_configuration.from_phase,
+            tool_configuration.log_sink.as_ref(),
+            tool_configuration.environment.as_deref(),
+        )
+        .await
+    {
+        if tool_configuration.debug_shell {
+            tracing::error!("Build script failed: {}", err);
+            output
+                .run_debug_shell(&tool_configuration.fancy_log_handler)
+                .await
+                .into_diagnostic()?;
+        }
+        return Err(err).into_diagnostic();
+    }
 
     // Package all the new files
     let (result, paths_json) = output
@@ -140,6 +242,39 @@ pub async fn run_build(
         .into_diagnostic()?;
 
     output.record_artifact(&result, &paths_json);
+    output.record_build_stats(ResourceStats {
+        disk: tool_configuration
+            .stats_file
+            .is_some()
+            .then(|| disk_usage(&directories.build_dir)),
+        ..build_stats.finish()
+    });
+
+    if let Some(scan_cmd) = &tool_configuration.scan_cmd {
+        let scan = scan_artifact(scan_cmd, &result).await.into_diagnostic()?;
+        let passed = scan.passed;
+        output.record_scan(scan);
+        if !passed {
+            reject_artifact(&output, &result).await.into_diagnostic()?;
+            let _ = output.log_build_summary();
+            miette::bail!("Scanning {} with `{}` failed", result.display(), scan_cmd);
+        }
+    }
+
+    if output.build_configuration.provenance {
+        let provenance = provenance::write_statement(&output, &result).into_diagnostic()?;
+        tracing::info!("Provenance written to {}", provenance.display());
+        output.record_provenance(&provenance);
+    }
+
+    if let Some(signing) = &tool_configuration.signing {
+        let provenance = output.build_summary.lock().unwrap().provenance.clone();
+        let mut signatures = Vec::new();
+        for path in std::iter::once(result.as_path()).chain(provenance.as_deref()) {
+            signatures.push(signing.sign(path).await?);
+        }
+        output.record_signatures(signatures);
+    }
 
     let span = tracing::info_span!("Running package tests");
     let enter = span.enter();
@@ -149,7 +284,11 @@ pub async fn run_build(
         // TODO we could also run each of the (potentially multiple) test scripts and collect the errors
         if let TestType::PackageContents(package_contents) = test {
             package_contents
-                .run_test(&paths_json, &output.build_configuration.target_platform)
+                .run_test(
+                    &paths_json,
+                    &result,
+                    &output.build_configuration.target_platform,
+                )
                 .into_diagnostic()?;
         }
     }
@@ -158,21 +297,45 @@ pub async fn run_build(
         remove_dir_all_force(&directories.build_dir).into_diagnostic()?;
     }
 
+    let gpu_variant = GpuVariant::from_variant(&output.build_configuration.variant);
     if tool_configuration.no_test {
         tracing::info!("Skipping tests");
+    } else if let Some(reason) = tests_skipped_reason(&output, tool_configuration.test_policy)? {
+        tracing::warn!("Skipping tests: {}", reason);
+        output.record_test_skipped(&reason);
+    } else if let Some(reason) = gpu_tests_skipped_reason(gpu_variant) {
+        tracing::warn!("Skipping tests: {}", reason);
+        output.record_test_skipped(&reason);
     } else {
-        package_test::run_test(
+        let mut test_tool_configuration = tool_configuration.clone();
+        if !gpu_variant.is_available() {
+            // only reached with `CONDA_OVERRIDE_CUDA`
+            let mocked = gpu::cuda_override();
+            tracing::warn!(
+                "No GPU for the {} variant is available on this machine, running the tests with \
+                 the mocked driver of {}",
+                gpu_variant,
+                gpu::CUDA_OVERRIDE_ENV
+            );
+            test_tool_configuration.mock_virtual_packages = mocked.into_iter().collect();
+        }
+
+        let test_stats = StatsRecorder::start();
+        let test_result = package_test::run_test(
             &result,
             &TestConfiguration {
                 test_prefix: directories.work_dir.join("test"),
-                target_platform: Some(output.build_configuration.host_platform),
+                target_platform: Some(test_platform(&output)),
                 keep_test_prefix: tool_configuration.no_clean,
-                channels: output.reindex_channels().into_diagnostic()?,
-                tool_configuration: tool_configuration.clone(),
+                channels: output.reindex_channels().await.into_diagnostic()?,
+                tool_configuration: test_tool_configuration,
+                skip_downstream_tests: false,
+                lockfile_dir: output.build_configuration.lockfile_dir.clone(),
             },
         )
-        .await
-        .into_diagnostic()?;
+        .await;
+        output.record_test_stats(test_stats.finish(), test_result.is_ok());
+        test_result.into_diagnostic()?;
     }
 
     drop(enter);
@@ -181,5 +344,92 @@ pub async fn run_build(
         remove_dir_all_force(&directories.build_dir).into_diagnostic()?;
     }
 
+    hooks::run_post_build_hooks(&output, &result, tool_configuration).await?;
+
     Ok((output, result))
 }
+
+/// Remove an artifact that was rejected (and the SBOM next to it) from the output
+/// directory and index it again, so that it cannot end up in later builds or uploads.
+async fn reject_artifact(output: &Output, artifact: &Path) -> std::io::Result<()> {
+    tracing::warn!("Removing the rejected artifact {}", artifact.display());
+    if let (Some(format), Some(identifier)) = (output.build_configuration.sbom, output.identifier())
+    {
+        let sbom = artifact.with_file_name(format!("{}.{}", identifier, format.file_name()));
+        if sbom.exists() {
+            fs_err::remove_file(sbom)?;
+        }
+    }
+
+    let output_dir = output.build_configuration.directories.output_dir.clone();
+    let artifact = artifact.to_path_buf();
+    tokio::task::spawn_blocking(move || index::remove_package(&output_dir, &artifact))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+}
+
+/// Run the `--scan-cmd` on the artifact. The command is run through the shell with the
+/// path of the artifact appended as the last argument.
+async fn scan_artifact(scan_cmd: &str, artifact: &Path) -> std::io::Result<ScanResult> {
+    let span = tracing::info_span!("Scanning artifact");
+    let _enter = span.enter();
+    tracing::info!("Running `{}` on {}", scan_cmd, artifact.display());
+
+    let output = tokio::process::Command::from(hooks::shell_command(scan_cmd, artifact))
+        .output()
+        .await?;
+
+    for line in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+    {
+        tracing::info!("{}", line);
+    }
+
+    Ok(ScanResult {
+        command: scan_cmd.to_string(),
+        passed: output.status.success(),
+        exit_code: output.status.code(),
+    })
+}
+
+/// Prepare the build for the given output without running the build script. This
+/// fetches the sources, installs the build and host environments and writes the build
+/// script and the script that sets up the build environment to the work directory.
+/// Returns the phases of the build script with the paths of their scripts.
+pub async fn prepare_debug_build(
+    output: Output,
+    tool_configuration: &tool_configuration::Configuration,
+) -> miette::Result<(Output, Vec<(Option<ScriptPhase>, PathBuf)>)> {
+    if output.build_string().is_none() {
+        miette::bail!("Build string is not set for {:?}", output.name());
+    }
+
+    output
+        .build_configuration
+        .directories
+        .create_build_dir()
+        .into_diagnostic()?;
+
+    let span = tracing::info_span!(
+        "Preparing debug environment for",
+        recipe = output.identifier().unwrap()
+    );
+    let _enter = span.enter();
+
+    output.index_output_dir().await.into_diagnostic()?;
+
+    let output = output
+        .fetch_sources(tool_configuration)
+        .await
+        .into_diagnostic()?;
+
+    let output = output
+        .resolve_dependencies(tool_configuration)
+        .await
+        .into_diagnostic()?;
+
+    let build_scripts = output.write_build_script().await.into_diagnostic()?;
+
+    Ok((output, build_scripts))
+}
@@ -0,0 +1,296 @@
+//! The build cache of multi-output recipes.
+//!
+//! The `cache` section of a recipe is built once, and the files that it installs into
+//! the host prefix are stored in the output directory. Every output then starts from
+//! a copy of these files and packages its part of them. The cache is keyed by a hash
+//! of the rendered cache section, the rendered sources and version of the recipe (that
+//! the cache build uses), the variant values that apply to its requirements and the
+//! platforms, so changing the script, the sources or the requirements rebuilds it.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache_lock::CacheLock,
+    metadata::Output,
+    packaging::Files,
+    recipe::parser::{Cache, Dependency},
+    tool_configuration,
+};
+
+/// The file in the cache directory that describes the cached files.
+const CACHE_FILE: &str = "cache.json";
+
+/// The files that a cache build installed into the host prefix.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildCache {
+    /// The key of the cache
+    pub key: String,
+    /// The host prefix that the cache was built in. It is replaced with the host prefix
+    /// of the output when the files are restored.
+    pub prefix: PathBuf,
+    /// The cached files, relative to the prefix
+    pub files: Vec<PathBuf>,
+}
+
+impl Output {
+    /// The directory of the build cache of this output.
+    fn build_cache_dir(&self, key: &str) -> PathBuf {
+        self.build_configuration
+            .directories
+            .output_dir
+            .join("build_cache")
+            .join(key)
+    }
+
+    /// Hash everything that goes into the cache build.
+    fn build_cache_key(&self, cache: &Cache) -> String {
+        let build_configuration = &self.build_configuration;
+        // only the variant values that apply to the requirements of the cache
+        let variant = cache
+            .requirements
+            .build
+            .iter()
+            .chain(cache.requirements.host.iter())
+            .filter_map(|dependency| match dependency {
+                Dependency::Spec(spec) => spec.name.as_ref(),
+                _ => None,
+            })
+            .filter_map(|name| {
+                build_configuration
+                    .variant
+                    .get_key_value(name.as_normalized())
+            })
+            .collect::<std::collections::BTreeMap<_, _>>();
+        let input = serde_json::json!({
+            "cache": cache,
+            "sources": self.recipe.sources(),
+            "version": self.recipe.package().version(),
+            "variant": variant,
+            "channels": build_configuration.channels,
+            "build_platform": build_configuration.build_platform,
+            "host_platform": build_configuration.host_platform,
+            "target_platform": build_configuration.target_platform,
+        });
+        format!(
+            "{:x}",
+            rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(input.to_string())
+        )
+    }
+
+    /// Run the `cache` build of the recipe, unless the files of an identical cache build
+    /// were stored before. Returns `None` if the recipe has no cache.
+    pub async fn build_or_fetch_cache(
+        &self,
+        tool_configuration: &tool_configuration::Configuration,
+    ) -> miette::Result<Option<BuildCache>> {
+        let (Some(cache), Some(cache_recipe)) = (self.recipe.cache(), self.recipe.cache_recipe())
+        else {
+            return Ok(None);
+        };
+
+        let key = self.build_cache_key(cache);
+        let cache_dir = self.build_cache_dir(&key);
+
+        // outputs that are built in parallel share the cache
+        let _lock = CacheLock::acquire_async(&cache_dir.with_extension("lock"), "build cache")
+            .await
+            .into_diagnostic()?;
+
+        match fs::read_to_string(cache_dir.join(CACHE_FILE)) {
+            Ok(content) => {
+                tracing::info!("Using the build cache {}", key);
+                return serde_json::from_str(&content).into_diagnostic().map(Some);
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).into_diagnostic(),
+        }
+
+        let span = tracing::info_span!("Running the cache build");
+        let _enter = span.enter();
+
+        let cache_output = Output {
+            recipe: cache_recipe,
+            finalized_dependencies: None,
+            ..self.clone()
+        }
+        .resolve_dependencies(tool_configuration)
+        .await
+        .into_diagnostic()?;
+        cache_output
+            .run_build_script(
+                None,
+                tool_configuration.log_sink.as_ref(),
+                tool_configuration.environment.as_deref(),
+            )
+            .await
+            .into_diagnostic()?;
+
+        let prefix = &self.build_configuration.directories.host_prefix;
+        let new_files = Files::from_prefix(prefix, None).into_diagnostic()?;
+
+        // copy to a temporary directory first, so that a failed copy is not used later
+        let tmp_dir = cache_dir.with_extension("tmp");
+        if tmp_dir.exists() {
+            fs::remove_dir_all(&tmp_dir).into_diagnostic()?;
+        }
+        fs::create_dir_all(tmp_dir.join("prefix")).into_diagnostic()?;
+        let mut files = Vec::new();
+        for file in &new_files.new_files {
+            let relative = file.strip_prefix(prefix).expect("File should be in prefix");
+            let metadata = fs::symlink_metadata(file).into_diagnostic()?;
+            if metadata.is_dir() {
+                continue;
+            }
+            copy_file(file, &tmp_dir.join("prefix").join(relative)).into_diagnostic()?;
+            files.push(relative.to_path_buf());
+        }
+        files.sort();
+
+        let build_cache = BuildCache {
+            key,
+            prefix: prefix.clone(),
+            files,
+        };
+        fs::write(
+            tmp_dir.join(CACHE_FILE),
+            serde_json::to_string_pretty(&build_cache).into_diagnostic()?,
+        )
+        .into_diagnostic()?;
+        if cache_dir.exists() {
+            fs::remove_dir_all(&cache_dir).into_diagnostic()?;
+        }
+        fs::rename(&tmp_dir, &cache_dir).into_diagnostic()?;
+
+        tracing::info!(
+            "Stored {} files in the build cache {}",
+            build_cache.files.len(),
+            build_cache.key
+        );
+
+        Ok(Some(build_cache))
+    }
+
+    /// Copy the files of the cache into the host prefix of this output. This has to run
+    /// after the host environment of the output was installed.
+    pub fn restore_cache(&self, build_cache: &BuildCache) -> Result<(), std::io::Error> {
+        let cache_dir = self.build_cache_dir(&build_cache.key).join("prefix");
+        let prefix = &self.build_configuration.directories.host_prefix;
+        let old_prefix = build_cache.prefix.to_string_lossy();
+        let new_prefix = prefix.to_string_lossy();
+
+        tracing::info!(
+            "Copying {} files from the build cache",
+            build_cache.files.len()
+        );
+
+        for file in &build_cache.files {
+            let source = cache_dir.join(file);
+            let dest = prefix.join(file);
+            copy_file(&source, &dest)?;
+
+            if old_prefix == new_prefix || fs::symlink_metadata(&dest)?.is_symlink() {
+                continue;
+            }
+
+            // the files may contain the prefix that the cache was built in
+            let content = fs::read(&dest)?;
+            let Some(replaced) = replace_prefix(&content, &old_prefix, &new_prefix) else {
+                continue;
+            };
+            match replaced {
+                Some(replaced) => fs::write(&dest, replaced)?,
+                None => tracing::warn!(
+                    "Could not replace the prefix in the binary file {} (the prefix length differs)",
+                    file.display()
+                ),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace the old prefix in the content of a file. Returns `None` if the file does not
+/// contain the prefix, and `Some(None)` if the prefix can not be replaced because the
+/// file is binary and the prefixes differ in length.
+fn replace_prefix(content: &[u8], old_prefix: &str, new_prefix: &str) -> Option<Option<Vec<u8>>> {
+    let old = old_prefix.as_bytes();
+    if !content.windows(old.len()).any(|window| window == old) {
+        return None;
+    }
+
+    if content_inspector::inspect(content).is_text() {
+        let text = String::from_utf8_lossy(content);
+        return Some(Some(text.replace(old_prefix, new_prefix).into_bytes()));
+    }
+
+    if old.len() != new_prefix.len() {
+        return Some(None);
+    }
+    let mut replaced = content.to_vec();
+    let mut start = 0;
+    while let Some(position) = replaced[start..]
+        .windows(old.len())
+        .position(|window| window == old)
+    {
+        let position = start + position;
+        replaced[position..position + old.len()].copy_from_slice(new_prefix.as_bytes());
+        start = position + old.len();
+    }
+    Some(Some(replaced))
+}
+
+/// Copy a file or symlink, creating the parent directories.
+fn copy_file(source: &Path, dest: &Path) -> Result<(), std::io::Error> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if dest.exists() || dest.is_symlink() {
+        fs::remove_file(dest)?;
+    }
+
+    if source.is_symlink() {
+        let target = fs::read_link(source)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, dest)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(target, dest)?;
+    } else {
+        fs::copy(source, dest)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_prefix() {
+        let text = b"prefix=/old/prefix\nlibdir=/old/prefix/lib\n";
+        assert_eq!(
+            replace_prefix(text, "/old/prefix", "/new/longer/prefix"),
+            Some(Some(
+                b"prefix=/new/longer/prefix\nlibdir=/new/longer/prefix/lib\n".to_vec()
+            ))
+        );
+        assert_eq!(replace_prefix(text, "/other", "/new"), None);
+
+        let binary = b"\x7fELF\x00\x00/old/prefix/lib\x00\x01";
+        assert_eq!(
+            replace_prefix(binary, "/old/prefix", "/new/prefix"),
+            Some(Some(b"\x7fELF\x00\x00/new/prefix/lib\x00\x01".to_vec()))
+        );
+        assert_eq!(
+            replace_prefix(binary, "/old/prefix", "/new/longer/prefix"),
+            Some(None)
+        );
+    }
+}
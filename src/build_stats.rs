@@ -0,0 +1,217 @@
+//! Resource usage statistics of the builds and tests, written to the `--stats-file` in
+//! the format of the stats file of conda-build.
+//!
+//! Every built output gets a `build_<name><name>-<version>-<variant>` entry and, if its
+//! tests ran, a `test_<name><name>-<version>-<variant>` entry (the keys are formed like
+//! the ones of conda-build). Besides the resource usage, the build entry contains the
+//! path of the package and the test entry whether the tests passed.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::Output;
+
+/// The resource usage of a build or test.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceStats {
+    /// The wall clock time in seconds
+    pub elapsed: f64,
+    /// The user CPU time of the child processes in seconds
+    pub cpu_user: f64,
+    /// The system CPU time of the child processes in seconds
+    pub cpu_sys: f64,
+    /// The peak resident set size of the child processes in bytes
+    pub rss: u64,
+    /// The disk usage of the build directory in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk: Option<u64>,
+}
+
+/// Measures the resource usage from its creation until [`StatsRecorder::finish`].
+///
+/// The CPU times are taken from the child processes of rattler-build, so they are only
+/// accurate if a single output is built at a time (`--jobs 1`). They are not available
+/// on Windows.
+#[derive(Debug)]
+pub struct StatsRecorder {
+    start: Instant,
+    start_usage: ChildUsage,
+}
+
+impl StatsRecorder {
+    /// Start measuring
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            start_usage: ChildUsage::now(),
+        }
+    }
+
+    /// Returns the resource usage since the recorder was started
+    pub fn finish(&self) -> ResourceStats {
+        let usage = ChildUsage::now();
+        ResourceStats {
+            elapsed: self.start.elapsed().as_secs_f64(),
+            cpu_user: (usage.cpu_user - self.start_usage.cpu_user).max(0.0),
+            cpu_sys: (usage.cpu_sys - self.start_usage.cpu_sys).max(0.0),
+            rss: usage.max_rss,
+            disk: None,
+        }
+    }
+}
+
+/// The accumulated resource usage of the terminated child processes.
+#[derive(Debug, Default, Clone, Copy)]
+struct ChildUsage {
+    cpu_user: f64,
+    cpu_sys: f64,
+    max_rss: u64,
+}
+
+impl ChildUsage {
+    #[cfg(unix)]
+    fn now() -> Self {
+        let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+        // SAFETY: `getrusage` only writes to the given struct
+        if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, usage.as_mut_ptr()) } != 0 {
+            return Self::default();
+        }
+        // SAFETY: `getrusage` succeeded, so the struct is initialized
+        let usage = unsafe { usage.assume_init() };
+        let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+        // `ru_maxrss` is in kilobytes on Linux and in bytes on macOS
+        let max_rss = usage.ru_maxrss.max(0) as u64;
+        Self {
+            cpu_user: seconds(usage.ru_utime),
+            cpu_sys: seconds(usage.ru_stime),
+            max_rss: if cfg!(target_os = "macos") {
+                max_rss
+            } else {
+                max_rss * 1024
+            },
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn now() -> Self {
+        Self::default()
+    }
+}
+
+/// Returns the size of all files in a directory (without following symlinks).
+pub fn disk_usage(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// The key of a stats entry, formed like the keys of conda-build: the description
+/// followed by the name, the version and the variant (without `target_platform`).
+fn stats_key(desc: &str, name: &str, version: &str, variant: &BTreeMap<String, String>) -> String {
+    let build_vars = variant
+        .iter()
+        .filter(|(key, _)| key.as_str() != "target_platform" && key.as_str() != "build_platform")
+        .map(|(key, value)| format!("{}_{}", key, value))
+        .collect::<Vec<_>>()
+        .join("-");
+    let mut key = vec![name, version];
+    if !build_vars.is_empty() {
+        key.push(&build_vars);
+    }
+    format!("{}{}", desc, key.join("-"))
+}
+
+/// The entry of a build in the stats file
+#[derive(Debug, Serialize)]
+struct BuildEntry<'a> {
+    #[serde(flatten)]
+    stats: &'a ResourceStats,
+    /// The path of the created package
+    package: Option<&'a PathBuf>,
+}
+
+/// The entry of a test run in the stats file
+#[derive(Debug, Serialize)]
+struct TestEntry<'a> {
+    #[serde(flatten)]
+    stats: &'a ResourceStats,
+    /// Whether all tests passed
+    passed: bool,
+}
+
+/// Write the build and test statistics of the outputs to a JSON file. Outputs that did
+/// not get to the build (e.g. because an earlier build failed) are left out.
+pub fn write_stats_file(path: &Path, outputs: &[Output]) -> Result<(), std::io::Error> {
+    let mut stats = BTreeMap::new();
+    for output in outputs {
+        let summary = output.build_summary.lock().unwrap();
+        let name = output.name().as_normalized();
+        let version = output.version().to_string();
+        let variant = output.variant();
+
+        if let Some(build_stats) = &summary.build_stats {
+            stats.insert(
+                stats_key(&format!("build_{}", name), name, &version, variant),
+                serde_json::to_value(BuildEntry {
+                    stats: build_stats,
+                    package: summary.artifact.as_ref(),
+                })?,
+            );
+        }
+        if let Some((test_stats, passed)) = &summary.test_stats {
+            stats.insert(
+                stats_key(&format!("test_{}", name), name, &version, variant),
+                serde_json::to_value(TestEntry {
+                    stats: test_stats,
+                    passed: *passed,
+                })?,
+            );
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&stats)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_key() {
+        let variant = BTreeMap::from([
+            ("python".to_string(), "3.12".to_string()),
+            ("target_platform".to_string(), "linux-64".to_string()),
+            ("numpy".to_string(), "2".to_string()),
+        ]);
+        assert_eq!(
+            stats_key("build_foo", "foo", "1.0", &variant),
+            "build_foofoo-1.0-numpy_2-python_3.12"
+        );
+        assert_eq!(
+            stats_key("test_foo", "foo", "1.0", &BTreeMap::new()),
+            "test_foofoo-1.0"
+        );
+    }
+
+    #[test]
+    fn test_stats_recorder() {
+        let recorder = StatsRecorder::start();
+        let stats = recorder.finish();
+        assert!(stats.elapsed >= 0.0);
+        assert!(stats.cpu_user >= 0.0 && stats.cpu_sys >= 0.0);
+        assert!(stats.disk.is_none());
+    }
+}
@@ -0,0 +1,279 @@
+//! The `cache` subcommand to inspect and clean the caches of rattler-build.
+//!
+//! The package and repodata caches are the caches of rattler (`--cache-dir` or
+//! `RATTLER_CACHE_DIR`), which pixi uses as well. The source cache belongs to
+//! rattler-build only.
+
+use std::{
+    env::current_dir,
+    path::{Path, PathBuf},
+};
+
+use clap::{Parser, Subcommand};
+use fs_err as fs;
+use miette::IntoDiagnostic;
+
+use crate::{
+    cache_lock::CacheLock,
+    tool_configuration::{rattler_cache_dir, Configuration, CACHE_LOCK_FILE},
+};
+
+/// Options for the `cache` command.
+#[derive(Parser)]
+pub struct CacheOpts {
+    /// The cache command to run
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+/// The `cache` subcommands.
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Print the locations of the caches
+    Info(CacheLocationOpts),
+
+    /// Remove the contents of the caches
+    Clean(CacheCleanOpts),
+}
+
+/// The options that determine the location of the caches.
+#[derive(Parser)]
+pub struct CacheLocationOpts {
+    /// The directory of the source cache. Defaults to `src_cache` in the output directory.
+    #[arg(long, env = "RATTLER_BUILD_SOURCE_CACHE_DIR")]
+    pub source_cache_dir: Option<PathBuf>,
+
+    /// Output directory for build artifacts. Defaults to `./output`.
+    #[arg(long, env = "CONDA_BLD_PATH")]
+    pub output_dir: Option<PathBuf>,
+
+    /// The directory of the package and repodata caches. Defaults to the cache directory
+    /// of rattler, which pixi uses as well
+    #[arg(long, env = "RATTLER_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// Options for `rattler-build cache clean`.
+#[derive(Parser)]
+pub struct CacheCleanOpts {
+    /// Remove the downloaded sources and git checkouts
+    #[arg(long)]
+    pub sources: bool,
+
+    /// Remove the extracted packages of the package cache
+    #[arg(long)]
+    pub packages: bool,
+
+    /// The location of the caches
+    #[command(flatten)]
+    pub location: CacheLocationOpts,
+}
+
+impl CacheLocationOpts {
+    /// The tool configuration with the cache locations of these options, and the output
+    /// directory.
+    fn configuration(&self) -> miette::Result<(Configuration, PathBuf)> {
+        let output_dir = match &self.output_dir {
+            Some(output_dir) => output_dir.clone(),
+            None => current_dir().into_diagnostic()?.join("output"),
+        };
+        let config = Configuration {
+            source_cache: self.source_cache_dir.clone(),
+            cache_dir: self.cache_dir.clone(),
+            ..Configuration::default()
+        };
+        Ok((config, output_dir))
+    }
+}
+
+/// The number of entries in a cache directory (without its lock file) and their total
+/// size in bytes.
+fn cache_usage(dir: &Path) -> (usize, u64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return (0, 0);
+    };
+    let entries = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name() != CACHE_LOCK_FILE)
+        .count();
+    let size = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+    (entries, size)
+}
+
+/// Print the location and the usage of a cache.
+fn print_cache_info(what: &str, dir: &Path) {
+    let (entries, size) = cache_usage(dir);
+    println!(
+        "{:<16}{} ({} entries, {})",
+        format!("{what}:"),
+        dir.display(),
+        entries,
+        indicatif::HumanBytes(size)
+    );
+}
+
+/// The lock file of an entry of the package cache. rattler (and pixi) lock every
+/// package directory with a `<directory>.lock` file while they read or write it.
+fn entry_lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+/// Remove everything in a cache directory, except for its lock file. The lock is held
+/// while the directory is cleaned, so running builds are not disturbed.
+///
+/// With `entry_locks` (for the package cache that is shared with other rattler-based
+/// tools), every entry is locked with its own lock file before it is removed and
+/// entries that another process uses are skipped. The lock files are kept, removing
+/// them could give two processes a lock on the same entry.
+async fn clean_cache_dir(
+    config: &Configuration,
+    dir: &Path,
+    what: &str,
+    entry_locks: bool,
+) -> miette::Result<()> {
+    if !dir.exists() {
+        tracing::info!("The {} ({}) does not exist", what, dir.display());
+        return Ok(());
+    }
+
+    let _lock = config.lock_cache(dir, what).await.into_diagnostic()?;
+    let (removed, skipped) = remove_entries(dir, entry_locks)?;
+
+    tracing::info!(
+        "Removed {} entries from the {} ({})",
+        removed,
+        what,
+        dir.display()
+    );
+    if skipped > 0 {
+        tracing::warn!(
+            "Skipped {} entries of the {} that are in use by another process",
+            skipped,
+            what
+        );
+    }
+    Ok(())
+}
+
+/// Remove the entries of a cache directory and return the number of removed and
+/// skipped entries.
+fn remove_entries(dir: &Path, entry_locks: bool) -> miette::Result<(usize, usize)> {
+    let mut removed = 0;
+    let mut skipped = 0;
+    for entry in fs::read_dir(dir).into_diagnostic()? {
+        let path = entry.into_diagnostic()?.path();
+        if path
+            .file_name()
+            .map_or(false, |name| name == CACHE_LOCK_FILE)
+        {
+            continue;
+        }
+
+        let _entry_lock = if entry_locks {
+            if path.extension().map_or(false, |ext| ext == "lock") {
+                continue;
+            }
+            match CacheLock::try_acquire(&entry_lock_path(&path)).into_diagnostic()? {
+                Some(lock) => Some(lock),
+                None => {
+                    tracing::info!("Skipping {}, it is in use", path.display());
+                    skipped += 1;
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(&path).into_diagnostic()?;
+        } else {
+            fs::remove_file(&path).into_diagnostic()?;
+        }
+        removed += 1;
+    }
+    Ok((removed, skipped))
+}
+
+/// Run the `cache` command.
+pub async fn cache_from_args(args: CacheOpts) -> miette::Result<()> {
+    match args.command {
+        CacheCommand::Info(location) => {
+            let (config, output_dir) = location.configuration()?;
+            print_cache_info("Package cache", &config.package_cache_dir());
+            print_cache_info("Repodata cache", &config.repodata_cache_dir());
+            print_cache_info("Source cache", &config.source_cache_dir(&output_dir));
+
+            // pixi prefers its own variable over `RATTLER_CACHE_DIR`
+            let cache_dir = rattler_cache_dir(config.cache_dir.as_deref());
+            match std::env::var_os("PIXI_CACHE_DIR").map(PathBuf::from) {
+                Some(pixi_cache_dir) if pixi_cache_dir != cache_dir => println!(
+                    "\nThe caches are not shared with pixi, which uses {} (PIXI_CACHE_DIR)",
+                    pixi_cache_dir.display()
+                ),
+                _ => println!(
+                    "\nThe package and repodata caches are shared with pixi and other \
+                     rattler-based tools"
+                ),
+            }
+            Ok(())
+        }
+        CacheCommand::Clean(clean) => {
+            if !clean.sources && !clean.packages {
+                return Err(miette::miette!(
+                    help = "pass `--sources`, `--packages` or both",
+                    "No cache selected to clean"
+                ));
+            }
+
+            let (config, output_dir) = clean.location.configuration()?;
+            if clean.sources {
+                clean_cache_dir(
+                    &config,
+                    &config.source_cache_dir(&output_dir),
+                    "source cache",
+                    false,
+                )
+                .await?;
+            }
+            if clean.packages {
+                clean_cache_dir(&config, &config.package_cache_dir(), "package cache", true)
+                    .await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_skips_locked_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["used-1.0-0", "unused-1.0-0"] {
+            fs::create_dir_all(dir.path().join(name).join("info")).unwrap();
+        }
+        let lock = CacheLock::try_acquire(&dir.path().join("used-1.0-0.lock"))
+            .unwrap()
+            .unwrap();
+
+        let (removed, skipped) = remove_entries(dir.path(), true).unwrap();
+        assert_eq!((removed, skipped), (1, 1));
+        assert!(dir.path().join("used-1.0-0").exists());
+        assert!(!dir.path().join("unused-1.0-0").exists());
+
+        drop(lock);
+        assert_eq!(remove_entries(dir.path(), true).unwrap(), (1, 0));
+        assert!(!dir.path().join("used-1.0-0").exists());
+    }
+}
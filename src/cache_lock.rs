@@ -0,0 +1,197 @@
+//! Cross-process locks for state that is shared between concurrent rattler-build
+//! processes on one machine (the package cache, the source cache, the index of the
+//! output channel and the build directories).
+//!
+//! The locks are advisory file locks. The operating system releases them when the
+//! process that holds them exits, so a crashed build never leaves a lock behind. The
+//! lock file records which process holds the lock, which is reported while waiting.
+
+use std::{
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use fs4::FileExt;
+use fs_err as fs;
+
+/// How long to wait for a lock before warning that its holder might be stuck.
+const STALE_WARNING_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// How often to check whether a contended lock was released.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// An exclusive lock on a lock file. The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct CacheLock {
+    file: fs::File,
+    path: PathBuf,
+}
+
+impl CacheLock {
+    /// Acquire an exclusive lock on the lock file at `path` (which is created if it
+    /// does not exist), blocking until other processes release it. `what` describes
+    /// the locked state in log messages, e.g. "source cache".
+    pub fn acquire(path: &Path, what: &str) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let start = Instant::now();
+        let mut waiting = false;
+        let mut warned = false;
+        loop {
+            match file.file().try_lock_exclusive() {
+                Ok(()) => break,
+                Err(e) if e.kind() == fs4::lock_contended_error().kind() => {
+                    if !waiting {
+                        tracing::info!(
+                            "Waiting for the lock on the {} ({}) held by {}",
+                            what,
+                            path.display(),
+                            read_owner(&mut file)
+                        );
+                        waiting = true;
+                    } else if !warned && start.elapsed() > STALE_WARNING_AFTER {
+                        tracing::warn!(
+                            "The lock on the {} ({}) is held by {} for more than {} seconds. \
+                             If that process is stuck, stop it to continue.",
+                            what,
+                            path.display(),
+                            read_owner(&mut file),
+                            STALE_WARNING_AFTER.as_secs()
+                        );
+                        warned = true;
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        // record the owner of the lock for other processes that are waiting for it
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(
+            file,
+            "pid {} on {}",
+            std::process::id(),
+            hostname().unwrap_or_else(|| "unknown host".to_string())
+        )?;
+        file.flush()?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Acquire an exclusive lock on the lock file at `path` if no other process holds
+    /// it, without waiting. Unlike [`Self::acquire`], the content of the lock file is
+    /// left alone, so this can lock the files of other tools (e.g. the `<entry>.lock`
+    /// files of the rattler package cache).
+    pub fn try_acquire(path: &Path) -> std::io::Result<Option<Self>> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        match file.file().try_lock_exclusive() {
+            Ok(()) => Ok(Some(Self {
+                file,
+                path: path.to_path_buf(),
+            })),
+            Err(e) if e.kind() == fs4::lock_contended_error().kind() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Acquire the lock from an async context without blocking the runtime.
+    pub async fn acquire_async(path: &Path, what: &str) -> std::io::Result<Self> {
+        let path = path.to_path_buf();
+        let what = what.to_string();
+        tokio::task::spawn_blocking(move || Self::acquire(&path, &what))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    }
+
+    /// The path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.file().unlock() {
+            tracing::warn!("Failed to release the lock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Read the description of the process that holds the lock.
+fn read_owner(file: &mut fs::File) -> String {
+    let mut owner = String::new();
+    if file.rewind().is_err() || file.read_to_string(&mut owner).is_err() || owner.is_empty() {
+        return "another process".to_string();
+    }
+    owner
+}
+
+/// The hostname of the machine, if it can be determined.
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|s| s.trim().to_string())
+        })
+        .filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacheLock;
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join(".lock");
+
+        let lock = CacheLock::acquire(&path, "test cache").unwrap();
+        assert!(path.exists());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(&std::process::id().to_string()));
+        drop(lock);
+
+        // the lock can be acquired again once it was released
+        let _lock = CacheLock::acquire(&path, "test cache").unwrap();
+    }
+
+    #[test]
+    fn try_acquire_does_not_wait() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entry.lock");
+        std::fs::write(&path, "rattler lock").unwrap();
+
+        let lock = CacheLock::try_acquire(&path).unwrap().unwrap();
+        assert!(CacheLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(CacheLock::try_acquire(&path).unwrap().is_some());
+
+        // the content of the lock file is kept
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "rattler lock");
+    }
+}
@@ -0,0 +1,447 @@
+//! Dependency-aware change detection for repositories that contain many recipes.
+//!
+//! Given a git reference, this module figures out which recipes are affected by the
+//! files that changed since that reference. A recipe is affected if one of its files
+//! (or one of its local `path` sources) changed, or if it depends on a package that is
+//! produced by another affected recipe in the same directory tree.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use dunce::canonicalize;
+use fs_err as fs;
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+use serde::Serialize;
+
+use crate::{
+    get_recipe_path,
+    recipe::{
+        parser::{find_outputs_from_src, Dependency, Recipe, Source},
+        ParsingError,
+    },
+    selectors::SelectorConfig,
+    system_tools::{SystemTools, Tool},
+    variant_config::{ParseErrors, VariantConfig},
+};
+
+/// Options for the `changed` subcommand.
+#[derive(Parser, Clone, Debug)]
+pub struct ChangedOpts {
+    /// The git reference to compare against (e.g. `origin/main` or a commit hash).
+    #[arg(long)]
+    pub since: String,
+
+    /// The directory that contains the recipes. Defaults to the current directory.
+    #[arg(long, default_value = ".")]
+    pub recipe_dir: PathBuf,
+
+    /// The target platform used to evaluate selectors in the recipes.
+    #[arg(long, default_value_t = Platform::current())]
+    pub target_platform: Platform,
+
+    /// Variant configuration files used to evaluate the recipes.
+    #[arg(short = 'm', long)]
+    pub variant_config: Vec<PathBuf>,
+}
+
+/// The reason a recipe ended up in the build set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ChangeReason {
+    /// One of the files of the recipe (or of its local sources) changed.
+    FilesChanged {
+        /// The changed files, relative to the recipe directory.
+        files: Vec<PathBuf>,
+    },
+    /// The recipe depends on a package produced by another affected recipe.
+    DependencyChanged {
+        /// The names of the affected packages this recipe depends on.
+        packages: Vec<String>,
+    },
+}
+
+/// A recipe that needs to be rebuilt.
+#[derive(Debug, Clone, Serialize)]
+pub struct AffectedRecipe {
+    /// The path to the recipe file.
+    pub recipe: PathBuf,
+    /// The names of the packages produced by this recipe.
+    pub outputs: Vec<String>,
+    /// Why this recipe needs to be rebuilt.
+    #[serde(flatten)]
+    pub reason: ChangeReason,
+    /// The error if the recipe could not be parsed (its outputs and dependencies are
+    /// unknown).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Information about a single recipe in the workspace that is needed to compute
+/// the build set.
+#[derive(Debug, Default)]
+struct RecipeInfo {
+    /// Directories (or files) that belong to this recipe.
+    roots: Vec<PathBuf>,
+    /// The package names produced by this recipe.
+    outputs: BTreeSet<String>,
+    /// The package names this recipe depends on at build or host time, or through
+    /// `pin_subpackage` / `pin_compatible`.
+    dependencies: BTreeSet<String>,
+    /// The error if the recipe could not be parsed. Such a recipe is only affected by
+    /// changes of its own files.
+    error: Option<String>,
+}
+
+/// Run git in the given directory and return the non-empty lines of its output.
+fn git_lines(args: &[&str], cwd: &Path) -> miette::Result<Vec<String>> {
+    let output = SystemTools::new()
+        .call(Tool::Git)
+        .into_diagnostic()?
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .into_diagnostic()?;
+    if !output.status.success() {
+        return Err(miette::miette!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Returns the list of files changed since the given git reference, including
+/// uncommitted changes in the working tree and untracked files (that are not
+/// ignored). The paths are absolute.
+fn changed_files(since: &str, cwd: &Path) -> miette::Result<Vec<PathBuf>> {
+    let toplevel = match git_lines(&["rev-parse", "--show-toplevel"], cwd) {
+        Ok(lines) if !lines.is_empty() => PathBuf::from(&lines[0]),
+        _ => {
+            return Err(miette::miette!(
+                "{} is not inside a git repository",
+                cwd.display()
+            ))
+        }
+    };
+
+    let changed = git_lines(&["diff", "--name-only", since], &toplevel).map_err(|err| {
+        miette::miette!("failed to compute changed files since `{}`: {}", since, err)
+    })?;
+    let untracked = git_lines(&["ls-files", "--others", "--exclude-standard"], &toplevel)?;
+
+    Ok(changed
+        .into_iter()
+        .chain(untracked)
+        .map(|line| toplevel.join(line))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect())
+}
+
+/// Find all `recipe.yaml` files below the given directory.
+fn find_recipes(recipe_dir: &Path) -> miette::Result<Vec<PathBuf>> {
+    let mut recipe_paths = Vec::new();
+    for entry in ignore::Walk::new(recipe_dir) {
+        let entry = entry.into_diagnostic()?;
+        if entry.path().is_dir() {
+            if let Ok(recipe_path) = get_recipe_path(entry.path()) {
+                recipe_paths.push(recipe_path);
+            }
+        }
+    }
+    Ok(recipe_paths)
+}
+
+/// Parse a recipe for all variants and collect the produced package names and their
+/// dependencies.
+fn recipe_info(
+    recipe_path: &Path,
+    variant_config: &VariantConfig,
+    selector_config: &SelectorConfig,
+) -> miette::Result<RecipeInfo> {
+    let recipe_text = fs::read_to_string(recipe_path).into_diagnostic()?;
+    let recipe_dir = recipe_path
+        .parent()
+        .expect("recipe path should have a parent");
+
+    let outputs = find_outputs_from_src(&recipe_text)?;
+    let discovered_outputs =
+        variant_config.find_variants(&outputs, &recipe_text, selector_config)?;
+
+    let mut info = RecipeInfo {
+        roots: vec![recipe_dir.to_path_buf()],
+        ..RecipeInfo::default()
+    };
+
+    for discovered_output in discovered_outputs {
+        let selector_config = SelectorConfig {
+            variant: discovered_output.used_vars.clone(),
+            ..selector_config.clone()
+        };
+        let recipe =
+            Recipe::from_node(&discovered_output.node, selector_config).map_err(|err| {
+                let errs: ParseErrors = err
+                    .into_iter()
+                    .map(|err| ParsingError::from_partial(&recipe_text, err))
+                    .collect::<Vec<ParsingError>>()
+                    .into();
+                errs
+            })?;
+
+        info.outputs
+            .insert(recipe.package().name().as_normalized().to_string());
+
+        // build and host dependencies are always relevant, run dependencies only
+        // when they are expressed as a pin
+        let requirements = recipe.requirements();
+        for dep in requirements.build_time() {
+            if let Dependency::Spec(spec) = dep {
+                info.dependencies
+                    .extend(spec.name.as_ref().map(|n| n.as_normalized().to_string()));
+            }
+        }
+        for dep in requirements.all() {
+            match dep {
+                Dependency::Spec(_) => {}
+                Dependency::PinSubpackage(pin) => {
+                    info.dependencies
+                        .insert(pin.pin_value().name.as_normalized().to_string());
+                }
+                Dependency::PinCompatible(pin) => {
+                    info.dependencies
+                        .insert(pin.pin_value().name.as_normalized().to_string());
+                }
+            }
+        }
+
+        for source in recipe.sources() {
+            if let Source::Path(path_source) = source {
+                let path = recipe_dir.join(path_source.path());
+                info.roots.push(canonicalize(&path).unwrap_or(path));
+            }
+        }
+    }
+
+    // a recipe never depends on itself
+    let outputs = info.outputs.clone();
+    info.dependencies.retain(|dep| !outputs.contains(dep));
+
+    Ok(info)
+}
+
+/// Compute the set of recipes affected by changes since the given git reference.
+///
+/// The result is sorted by recipe path. Recipes that are directly affected by a
+/// file change carry [`ChangeReason::FilesChanged`], recipes that are only affected
+/// through their dependencies carry [`ChangeReason::DependencyChanged`].
+pub fn affected_recipes(args: &ChangedOpts) -> miette::Result<Vec<AffectedRecipe>> {
+    let recipe_dir = canonicalize(&args.recipe_dir).into_diagnostic()?;
+    let changed = changed_files(&args.since, &recipe_dir)?;
+
+    let selector_config = SelectorConfig {
+        target_platform: args.target_platform,
+        host_platform: args.target_platform,
+        build_platform: Platform::current(),
+        hash: None,
+        variant: BTreeMap::new(),
+        experimental: false,
+        allow_undefined: true,
+        lenient_skip: false,
+        subpackages: BTreeMap::new(),
+        environment: None,
+    };
+    let variant_config =
+        VariantConfig::from_files(&args.variant_config, &selector_config).into_diagnostic()?;
+
+    let mut recipes = BTreeMap::new();
+    for recipe_path in find_recipes(&recipe_dir)? {
+        let info = match recipe_info(&recipe_path, &variant_config, &selector_config) {
+            Ok(info) => info,
+            Err(err) => {
+                tracing::warn!(
+                    "Could not parse {}, only changes of its own files are detected: {}",
+                    recipe_path.display(),
+                    err
+                );
+                RecipeInfo {
+                    roots: recipe_path
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .into_iter()
+                        .collect(),
+                    error: Some(err.to_string()),
+                    ..RecipeInfo::default()
+                }
+            }
+        };
+        recipes.insert(recipe_path, info);
+    }
+
+    let mut reasons: BTreeMap<&Path, ChangeReason> = BTreeMap::new();
+    let mut queue = VecDeque::new();
+
+    // directly affected recipes: one of their roots contains a changed file. The most
+    // specific root wins so that nested recipes are not attributed to their parent.
+    for file in &changed {
+        let owner = recipes
+            .iter()
+            .flat_map(|(path, info)| info.roots.iter().map(move |root| (path, root)))
+            .filter(|(_, root)| file.starts_with(root))
+            .max_by_key(|(_, root)| root.components().count())
+            .map(|(path, _)| path.as_path());
+
+        if let Some(owner) = owner {
+            let relative = file
+                .strip_prefix(&recipe_dir)
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|_| file.clone());
+            match reasons.entry(owner).or_insert_with(|| {
+                queue.push_back(owner);
+                ChangeReason::FilesChanged { files: Vec::new() }
+            }) {
+                ChangeReason::FilesChanged { files } => files.push(relative),
+                ChangeReason::DependencyChanged { .. } => unreachable!(),
+            }
+        }
+    }
+
+    // propagate to all recipes that depend on the outputs of affected recipes
+    while let Some(affected) = queue.pop_front() {
+        let affected_outputs = &recipes[affected].outputs;
+        for (path, info) in &recipes {
+            let packages = info
+                .dependencies
+                .iter()
+                .filter(|dep| affected_outputs.contains(*dep))
+                .cloned()
+                .collect::<Vec<_>>();
+            if packages.is_empty() {
+                continue;
+            }
+
+            match reasons.get_mut(path.as_path()) {
+                Some(ChangeReason::DependencyChanged { packages: existing }) => {
+                    for package in packages {
+                        if !existing.contains(&package) {
+                            existing.push(package);
+                        }
+                    }
+                }
+                Some(ChangeReason::FilesChanged { .. }) => {}
+                None => {
+                    reasons.insert(path, ChangeReason::DependencyChanged { packages });
+                    queue.push_back(path);
+                }
+            }
+        }
+    }
+
+    Ok(reasons
+        .into_iter()
+        .map(|(path, reason)| AffectedRecipe {
+            recipe: path.to_path_buf(),
+            outputs: recipes[path].outputs.iter().cloned().collect(),
+            reason,
+            error: recipes[path].error.clone(),
+        })
+        .collect())
+}
+
+/// Print the set of affected recipes as JSON.
+pub fn changed_from_args(args: ChangedOpts) -> miette::Result<()> {
+    let affected = affected_recipes(&args)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&affected).into_diagnostic()?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    fn write_recipe(dir: &Path, name: &str, content: &str) {
+        fs::create_dir_all(dir.join(name)).unwrap();
+        fs::write(dir.join(name).join("recipe.yaml"), content).unwrap();
+    }
+
+    #[test]
+    fn test_affected_recipes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = canonicalize(tmp.path()).unwrap();
+        write_recipe(
+            &repo,
+            "libfoo",
+            "package:\n  name: libfoo\n  version: 1.0.0\n",
+        );
+        write_recipe(
+            &repo,
+            "pyfoo",
+            "package:\n  name: pyfoo\n  version: 1.0.0\nrequirements:\n  host:\n    - libfoo\n",
+        );
+        write_recipe(&repo, "broken", "package: [\n");
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["add", "-A"]);
+        git(&repo, &["commit", "-q", "-m", "init"]);
+
+        // an untracked file of libfoo and a change of the unparsable recipe
+        fs::write(repo.join("libfoo").join("build.sh"), "make install\n").unwrap();
+        write_recipe(&repo, "broken", "package: [\n  \n");
+
+        let affected = affected_recipes(&ChangedOpts {
+            since: "HEAD".to_string(),
+            recipe_dir: repo.clone(),
+            target_platform: Platform::current(),
+            variant_config: Vec::new(),
+        })
+        .unwrap();
+
+        let by_name = affected
+            .iter()
+            .map(|recipe| {
+                let name = recipe.recipe.parent().unwrap().file_name().unwrap();
+                (name.to_string_lossy().to_string(), recipe)
+            })
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(by_name.len(), 3);
+
+        assert_eq!(
+            by_name["libfoo"].reason,
+            ChangeReason::FilesChanged {
+                files: vec![PathBuf::from("libfoo/build.sh")]
+            }
+        );
+        assert_eq!(
+            by_name["pyfoo"].reason,
+            ChangeReason::DependencyChanged {
+                packages: vec!["libfoo".to_string()]
+            }
+        );
+        assert!(matches!(
+            by_name["broken"].reason,
+            ChangeReason::FilesChanged { .. }
+        ));
+        assert!(by_name["broken"].error.is_some());
+        assert!(by_name["libfoo"].error.is_none());
+    }
+}
@@ -339,6 +339,18 @@ impl LoggingOutputHandler {
             )
     }
 
+    /// Returns the style to use for a progressbar that counts bytes towards a known total
+    /// (e.g. the bytes of a package that are compressed).
+    pub fn determinate_bytes_style(&self) -> indicatif::ProgressStyle {
+        let template_str = self.with_indent_levels(
+            "{spinner:.green} {prefix:20!} [{elapsed_precise}] [{bar:40!.bright.yellow/dim.white}] {bytes:>8}/{total_bytes:8} (eta {eta})"
+        );
+        indicatif::ProgressStyle::default_bar()
+            .template(&template_str)
+            .unwrap()
+            .progress_chars("━━╾─")
+    }
+
     /// Returns the style to use for a progressbar that is currently in progress.
     pub fn default_progress_style(&self) -> indicatif::ProgressStyle {
         let template_str = self.with_indent_levels(
@@ -397,6 +409,31 @@ impl LoggingOutputHandler {
         self.progress_bars.add(progress_bar)
     }
 
+    /// Adds a progress bar that counts items (e.g. files) towards a known total.
+    pub fn add_counting_progress_bar(
+        &self,
+        prefix: impl Into<Cow<'static, str>>,
+        total: u64,
+    ) -> indicatif::ProgressBar {
+        let pb = self.add_progress_bar(
+            ProgressBar::new(total)
+                .with_prefix(prefix)
+                .with_style(self.default_progress_style()),
+        );
+        pb.enable_steady_tick(Duration::from_millis(100));
+        pb
+    }
+
+    /// Marks a progress bar as finished and shows the message instead of the bar.
+    pub fn finish_progress_bar(
+        &self,
+        progress_bar: &indicatif::ProgressBar,
+        msg: impl Into<Cow<'static, str>>,
+    ) {
+        progress_bar.set_style(self.finished_progress_style());
+        progress_bar.finish_with_message(msg);
+    }
+
     /// Set progress bars to hidden
     pub fn set_progress_bars_hidden(&self, hidden: bool) {
         self.progress_bars.set_draw_target(if hidden {
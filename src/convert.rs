@@ -0,0 +1,852 @@
+//! The `convert` subcommand translates a conda-build `meta.yaml` into a `recipe.yaml`.
+//!
+//! The conversion happens in two steps. First the Jinja and the selectors are
+//! translated line by line: `{% set %}` statements become the `context`, `{{ ... }}`
+//! becomes `${{ ... }}` and selectors in comments become `if` / `then` list items (or,
+//! for mapping keys, a Jinja `if` / `else` expression). The result is then parsed as
+//! YAML and restructured into the sections of the new format. Everything that cannot be
+//! translated is collected in a report.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use fs_err as fs;
+use indexmap::IndexMap;
+use miette::{Context, IntoDiagnostic};
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+
+/// Options for the `convert` subcommand.
+#[derive(Parser)]
+pub struct ConvertOpts {
+    /// The conda-build `meta.yaml` file, or the directory that contains it
+    #[arg(default_value = ".")]
+    pub recipe: PathBuf,
+
+    /// Write the converted recipe to this file instead of printing it
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// The result of converting a `meta.yaml`.
+#[derive(Debug)]
+pub struct Conversion {
+    /// The converted `recipe.yaml`
+    pub recipe: String,
+    /// The constructs that could not be translated (or need a second look)
+    pub report: Vec<String>,
+}
+
+/// The prefix of mapping keys that had a selector, the key is
+/// `__selector_<index>__<original key>`.
+const SELECTOR_KEY_PREFIX: &str = "__selector_";
+
+#[derive(Default)]
+struct Converter {
+    context: IndexMap<String, Value>,
+    selectors: Vec<String>,
+    report: Vec<String>,
+}
+
+/// Translate a conda-build selector into a rattler-build selector.
+pub(crate) fn translate_selector(selector: &str) -> String {
+    let python = Regex::new(r"\bpy\s*(==|!=|>=|<=|<|>)?\s*(\d)(\d+)\b").unwrap();
+    let selector = python.replace_all(selector, |caps: &regex::Captures| {
+        let major = &caps[2];
+        let minor = caps[3].parse::<u32>().unwrap_or_default();
+        match caps.get(1).map_or("==", |op| op.as_str()) {
+            "==" => format!("match(python, \"{major}.{minor}.*\")"),
+            "!=" => format!("not match(python, \"{major}.{minor}.*\")"),
+            ">" => format!("match(python, \">={major}.{}\")", minor + 1),
+            "<=" => format!("match(python, \"<{major}.{}\")", minor + 1),
+            op => format!("match(python, \"{op}{major}.{minor}\")"),
+        }
+    });
+
+    let replacements = [
+        (r"\bpy2k\b", "match(python, \"<3\")"),
+        (r"\bpy3k\b", "match(python, \">=3\")"),
+        (r"\bwin32\b", "(win and x86)"),
+        (r"\bwin64\b", "(win and x86_64)"),
+        (r"\blinux32\b", "(linux and x86)"),
+        (r"\blinux64\b", "(linux and x86_64)"),
+        (r"\bosx64\b", "(osx and x86_64)"),
+    ];
+    let mut selector = selector.trim().to_string();
+    for (pattern, replacement) in replacements {
+        selector = Regex::new(pattern)
+            .unwrap()
+            .replace_all(&selector, replacement)
+            .to_string();
+    }
+    selector
+}
+
+/// Whether a YAML value is "true" (for `skip: true`).
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::String(s) => matches!(s.as_str(), "true" | "True" | "yes"),
+        Value::Number(n) => n.as_i64() != Some(0),
+        _ => false,
+    }
+}
+
+/// A string as a Jinja string literal.
+fn jinja_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A YAML scalar as a Jinja expression. Strings with `${{ ... }}` parts are
+/// concatenated with `~`.
+fn jinja_expression(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => {
+            let expression = Regex::new(r"\$\{\{(.*?)\}\}").unwrap();
+            let mut parts = Vec::new();
+            let mut last = 0;
+            for caps in expression.captures_iter(s) {
+                let whole = caps.get(0).expect("match");
+                if whole.start() > last {
+                    parts.push(jinja_string(&s[last..whole.start()]));
+                }
+                parts.push(format!("({})", caps[1].trim()));
+                last = whole.end();
+            }
+            if last < s.len() || parts.is_empty() {
+                parts.push(jinja_string(&s[last..]));
+            }
+            Some(parts.join(" ~ "))
+        }
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Null => Some("none".to_string()),
+        _ => None,
+    }
+}
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_string())
+}
+
+/// The mapping at `name` in `map`, created if it does not exist.
+fn sub_mapping<'a>(map: &'a mut Mapping, name: &str) -> &'a mut Mapping {
+    let entry = map
+        .entry(key(name))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if !entry.is_mapping() {
+        *entry = Value::Mapping(Mapping::new());
+    }
+    entry.as_mapping_mut().expect("is a mapping")
+}
+
+/// Apply `f` to every mapping of a value: the value itself, the items of a list and
+/// the `then` / `else` branches of conditional items.
+fn map_conditional(value: Value, f: &mut impl FnMut(Mapping) -> Mapping) -> Value {
+    match value {
+        Value::Sequence(items) => Value::Sequence(
+            items
+                .into_iter()
+                .map(|item| map_conditional(item, f))
+                .collect(),
+        ),
+        Value::Mapping(mut map) if map.contains_key("if") && map.contains_key("then") => {
+            for branch in ["then", "else"] {
+                if let Some(value) = map.remove(branch) {
+                    map.insert(key(branch), map_conditional(value, f));
+                }
+            }
+            Value::Mapping(map)
+        }
+        Value::Mapping(map) => Value::Mapping(f(map)),
+        value => value,
+    }
+}
+
+/// A value as a list (a single value becomes a list with one item).
+fn as_sequence(value: Value) -> Vec<Value> {
+    match value {
+        Value::Sequence(items) => items,
+        Value::Null => Vec::new(),
+        value => vec![value],
+    }
+}
+
+impl Converter {
+    fn unsupported(&mut self, path: &str) {
+        self.report.push(format!(
+            "`{path}` has no equivalent in recipe.yaml and was dropped"
+        ));
+    }
+
+    /// Translate the Jinja expressions of a line (`{{ ... }}` to `${{ ... }}`).
+    fn translate_jinja(&mut self, line: &str, line_number: usize) -> String {
+        let expression = Regex::new(r"\$?\{\{(.*?)\}\}").unwrap();
+        expression
+            .replace_all(line, |caps: &regex::Captures| {
+                let mut expr = caps[1].trim().to_string();
+                for (from, to) in [
+                    ("max_pin=", "upper_bound="),
+                    ("min_pin=", "lower_bound="),
+                    ("environ.get(", "env.get("),
+                ] {
+                    expr = expr.replace(from, to);
+                }
+                expr = Regex::new(r#"environ\[\s*(["'][^"']*["'])\s*\]"#)
+                    .unwrap()
+                    .replace_all(&expr, "env.get($1)")
+                    .to_string();
+                if ["load_setup_py_data", "load_file_data", "load_file_regex"]
+                    .iter()
+                    .any(|function| expr.contains(function))
+                {
+                    self.report.push(format!(
+                        "line {line_number}: `{expr}` is not available in recipe.yaml, the value has to be filled in by hand"
+                    ));
+                }
+                format!("${{{{ {expr} }}}}")
+            })
+            .to_string()
+    }
+
+    /// The value of a `{% set %}` statement in the context.
+    fn context_value(&mut self, value: &str, line_number: usize) -> Value {
+        let quoted = Regex::new(r#"^(?:"([^"]*)"|'([^']*)')$"#).unwrap();
+        if let Some(caps) = quoted.captures(value) {
+            let string = caps.get(1).or(caps.get(2)).map_or("", |m| m.as_str());
+            return Value::String(string.to_string());
+        }
+        if let Ok(number) = serde_yaml::from_str::<serde_yaml::Number>(value) {
+            return Value::Number(number);
+        }
+        Value::String(self.translate_jinja(&format!("{{{{ {value} }}}}"), line_number))
+    }
+
+    /// Translate the Jinja and the selectors line by line, so that the result can be
+    /// parsed as YAML.
+    fn preprocess(&mut self, meta_yaml: &str) -> String {
+        let set_statement = Regex::new(r"^\{%-?\s*set\s+(\w+)\s*=\s*(.*?)\s*-?%\}$").unwrap();
+        let selector_comment = Regex::new(r"^(.*?)\s*#\s*\[(.+)\]\s*$").unwrap();
+        let list_item = Regex::new(r"^(\s*)- (.*)$").unwrap();
+        let mapping_key = Regex::new(r"^(\s*)(- )?([\w.-]+):(\s.*|)$").unwrap();
+
+        let mut lines = Vec::new();
+        for (idx, line) in meta_yaml.lines().enumerate() {
+            let line_number = idx + 1;
+            let trimmed = line.trim();
+            let indent = &line[..line.len() - line.trim_start().len()];
+
+            // Jinja comments
+            if trimmed.starts_with("{#") && trimmed.ends_with("#}") {
+                continue;
+            }
+
+            if trimmed.starts_with("{%") {
+                if let Some(caps) = set_statement.captures(trimmed) {
+                    let value = self.context_value(&caps[2], line_number);
+                    self.context.insert(caps[1].to_string(), value);
+                } else {
+                    self.report.push(format!(
+                        "line {line_number}: the Jinja statement `{trimmed}` can not be translated and was commented out"
+                    ));
+                    lines.push(format!("{indent}# {trimmed}"));
+                }
+                continue;
+            }
+
+            let (content, selector) = match selector_comment.captures(line) {
+                Some(caps) if !line.trim_start().starts_with('#') => {
+                    (caps[1].to_string(), Some(translate_selector(&caps[2])))
+                }
+                _ => (line.to_string(), None),
+            };
+            let content = self.translate_jinja(&content, line_number);
+
+            let Some(selector) = selector else {
+                lines.push(content);
+                continue;
+            };
+
+            if let Some(caps) = mapping_key.captures(&content) {
+                // mapping keys are resolved after parsing, see `resolve_selectors`
+                let index = self.selectors.len();
+                self.selectors.push(selector);
+                lines.push(format!(
+                    "{}{}{SELECTOR_KEY_PREFIX}{index}__{}:{}",
+                    &caps[1],
+                    caps.get(2).map_or("", |m| m.as_str()),
+                    &caps[3],
+                    &caps[4]
+                ));
+            } else if let Some(caps) = list_item.captures(&content) {
+                lines.push(format!("{}- if: {selector}", &caps[1]));
+                lines.push(format!("{}  then: {}", &caps[1], &caps[2]));
+            } else {
+                self.report.push(format!(
+                    "line {line_number}: the selector `{selector}` can not be translated and was dropped"
+                ));
+                lines.push(content);
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Turn the mapping keys with selectors into `if` / `then` list items, `skip`
+    /// conditions or Jinja `if` / `else` expressions.
+    fn resolve_selectors(&mut self, value: Value) -> Value {
+        let selector_key = Regex::new(r"^__selector_(\d+)__(.*)$").unwrap();
+        match value {
+            Value::Sequence(items) => Value::Sequence(
+                items
+                    .into_iter()
+                    .map(|item| self.resolve_selectors(item))
+                    .collect(),
+            ),
+            Value::Mapping(map) => {
+                let mut plain = Mapping::new();
+                let mut selected: IndexMap<String, Vec<(String, Value)>> = IndexMap::new();
+                for (k, v) in map {
+                    let v = self.resolve_selectors(v);
+                    match k.as_str().and_then(|k| selector_key.captures(k)) {
+                        Some(caps) => {
+                            let index = caps[1].parse::<usize>().unwrap_or_default();
+                            selected
+                                .entry(caps[2].to_string())
+                                .or_default()
+                                .push((self.selectors[index].clone(), v));
+                        }
+                        None => {
+                            plain.insert(k, v);
+                        }
+                    }
+                }
+                for (name, values) in selected {
+                    let value =
+                        self.resolve_selected_key(&name, plain.remove(name.as_str()), values);
+                    plain.insert(key(&name), value);
+                }
+                Value::Mapping(plain)
+            }
+            value => value,
+        }
+    }
+
+    fn resolve_selected_key(
+        &mut self,
+        name: &str,
+        default: Option<Value>,
+        values: Vec<(String, Value)>,
+    ) -> Value {
+        if name == "skip" {
+            let mut conditions = match default {
+                Some(value) if is_truthy(&value) => vec![Value::Bool(true)],
+                Some(Value::Sequence(items)) => items,
+                _ => Vec::new(),
+            };
+            conditions.extend(
+                values
+                    .into_iter()
+                    .filter(|(_, value)| is_truthy(value))
+                    .map(|(selector, _)| Value::String(selector)),
+            );
+            return Value::Sequence(conditions);
+        }
+
+        if values.iter().all(|(_, value)| value.is_sequence()) {
+            let mut items = default.map(as_sequence).unwrap_or_default();
+            for (selector, value) in values {
+                let mut conditional = Mapping::new();
+                conditional.insert(key("if"), Value::String(selector));
+                conditional.insert(key("then"), value);
+                items.push(Value::Mapping(conditional));
+            }
+            return Value::Sequence(items);
+        }
+
+        // scalars become a chain of Jinja `if` / `else` expressions
+        let expressions = values
+            .iter()
+            .map(|(selector, value)| jinja_expression(value).map(|expr| (selector, expr)))
+            .collect::<Option<Vec<_>>>();
+        let fallback = match &default {
+            Some(value) => jinja_expression(value).map(Some),
+            None => Some(None),
+        };
+        match (expressions, fallback) {
+            (Some(expressions), Some(fallback)) => {
+                let (expressions, fallback) = match fallback {
+                    Some(fallback) => (&expressions[..], fallback),
+                    None if expressions.len() > 1 => {
+                        let (last, rest) = expressions.split_last().expect("not empty");
+                        (rest, last.1.clone())
+                    }
+                    None => {
+                        let (selector, expr) = &expressions[0];
+                        self.report.push(format!(
+                            "`{name}` is only set if `{selector}`, check the translated expression"
+                        ));
+                        return Value::String(format!("${{{{ {expr} if {selector} }}}}"));
+                    }
+                };
+                let chain = expressions
+                    .iter()
+                    .map(|(selector, expr)| format!("{expr} if {selector} else "))
+                    .collect::<String>();
+                Value::String(format!("${{{{ {chain}{fallback} }}}}"))
+            }
+            _ => {
+                let selectors = values.iter().map(|(selector, _)| selector.as_str());
+                self.report.push(format!(
+                    "the selectors of `{name}` ({}) can not be translated, the first value was kept",
+                    selectors.collect::<Vec<_>>().join(", ")
+                ));
+                default.unwrap_or_else(|| values.into_iter().next().expect("not empty").1)
+            }
+        }
+    }
+
+    fn convert_source(&mut self, source: Mapping) -> Mapping {
+        let mut result = Mapping::new();
+        for (k, v) in source {
+            let name = k.as_str().unwrap_or_default().to_string();
+            let new_name = match name.as_str() {
+                "url" | "sha256" | "md5" | "patches" | "path" => name.as_str(),
+                "fn" => "file_name",
+                "folder" => "target_directory",
+                "git_url" => "git",
+                "git_rev" | "git_tag" | "hg_tag" | "svn_rev" => "rev",
+                "git_depth" => "depth",
+                "hg_url" => "hg",
+                "svn_url" => "svn",
+                _ => {
+                    self.unsupported(&format!("source.{name}"));
+                    continue;
+                }
+            };
+            result.insert(key(new_name), v);
+        }
+        result
+    }
+
+    /// Convert the `build` section, the keys that moved to the requirements are added to
+    /// `requirements`.
+    fn convert_build(&mut self, build: Mapping, requirements: &mut Mapping) -> Mapping {
+        let mut result = Mapping::new();
+        for (k, v) in build {
+            let name = k.as_str().unwrap_or_default().to_string();
+            match name.as_str() {
+                "number"
+                | "string"
+                | "script"
+                | "noarch"
+                | "skip"
+                | "always_include_files"
+                | "track_features"
+                | "features" => {
+                    result.insert(k, v);
+                }
+                "merge_build_host" => {
+                    result.insert(key("merge_build_and_host_envs"), v);
+                }
+                "entry_points" => {
+                    sub_mapping(&mut result, "python").insert(key("entry_points"), v);
+                }
+                "run_exports" => {
+                    requirements.insert(key("run_exports"), v);
+                }
+                "ignore_run_exports" => {
+                    sub_mapping(requirements, "ignore_run_exports").insert(key("by_name"), v);
+                }
+                "ignore_run_exports_from" => {
+                    sub_mapping(requirements, "ignore_run_exports").insert(key("from_package"), v);
+                }
+                "missing_dso_whitelist" => {
+                    sub_mapping(&mut result, "dynamic_linking")
+                        .insert(key("missing_dso_allowlist"), v);
+                }
+                "rpaths" | "binary_relocation" => {
+                    sub_mapping(&mut result, "dynamic_linking").insert(k, v);
+                }
+                "ignore_prefix_files" => {
+                    sub_mapping(&mut result, "prefix_detection").insert(key("ignore"), v);
+                }
+                _ => self.unsupported(&format!("build.{name}")),
+            }
+        }
+        result
+    }
+
+    fn convert_requirements(&mut self, requirements: Value, result: &mut Mapping) {
+        let requirements = match requirements {
+            // the requirements of an output can be a list of run requirements
+            Value::Sequence(items) => {
+                result.insert(key("run"), Value::Sequence(items));
+                return;
+            }
+            Value::Mapping(map) => map,
+            _ => return,
+        };
+        for (k, v) in requirements {
+            let name = k.as_str().unwrap_or_default().to_string();
+            match name.as_str() {
+                "build" | "host" | "run" => {
+                    result.insert(k, v);
+                }
+                "run_constrained" => {
+                    result.insert(key("run_constraints"), v);
+                }
+                _ => self.unsupported(&format!("requirements.{name}")),
+            }
+        }
+    }
+
+    fn convert_test(&mut self, test: Mapping) -> Vec<Value> {
+        let mut tests = Vec::new();
+        let mut imports = None;
+        let mut commands = Vec::new();
+        let mut requires = None;
+        let mut files = Mapping::new();
+        for (k, v) in test {
+            let name = k.as_str().unwrap_or_default().to_string();
+            match name.as_str() {
+                "imports" => imports = Some(v),
+                "commands" => commands = as_sequence(v),
+                "requires" => requires = Some(v),
+                "files" => {
+                    files.insert(key("recipe"), v);
+                }
+                "source_files" => {
+                    files.insert(key("source"), v);
+                }
+                _ => self.unsupported(&format!("test.{name}")),
+            }
+        }
+
+        // `pip check` is part of the python test
+        let before = commands.len();
+        commands.retain(|command| command.as_str().map(str::trim) != Some("pip check"));
+        let pip_check = commands.len() != before;
+        if let Some(imports) = imports {
+            let mut python = Mapping::new();
+            python.insert(key("imports"), imports);
+            python.insert(key("pip_check"), Value::Bool(pip_check));
+            let mut test = Mapping::new();
+            test.insert(key("python"), Value::Mapping(python));
+            tests.push(Value::Mapping(test));
+        }
+
+        if !commands.is_empty() {
+            let mut test = Mapping::new();
+            test.insert(key("script"), Value::Sequence(commands));
+            if let Some(requires) = requires {
+                sub_mapping(&mut test, "requirements").insert(key("run"), requires);
+            }
+            if !files.is_empty() {
+                test.insert(key("files"), Value::Mapping(files));
+            }
+            tests.push(Value::Mapping(test));
+        } else if requires.is_some() || !files.is_empty() {
+            self.report.push(
+                "`test.requires` and `test.files` are only used by script tests and were dropped"
+                    .to_string(),
+            );
+        }
+        tests
+    }
+
+    fn convert_about(&mut self, about: Mapping) -> Mapping {
+        let mut result = Mapping::new();
+        for (k, v) in about {
+            let name = k.as_str().unwrap_or_default().to_string();
+            let new_name = match name.as_str() {
+                "home" => "homepage",
+                "doc_url" => "documentation",
+                "dev_url" => "repository",
+                "license" | "license_file" | "license_url" | "summary" | "description" => {
+                    name.as_str()
+                }
+                _ => {
+                    self.unsupported(&format!("about.{name}"));
+                    continue;
+                }
+            };
+            result.insert(key(new_name), v);
+        }
+        result
+    }
+
+    /// Convert the sections that are shared by the recipe and its outputs.
+    fn convert_sections(&mut self, mut map: Mapping, result: &mut Mapping) {
+        let mut requirements = Mapping::new();
+        if let Some(requirements_value) = map.remove("requirements") {
+            self.convert_requirements(requirements_value, &mut requirements);
+        }
+
+        let mut build = match map.remove("build") {
+            Some(Value::Mapping(build)) => self.convert_build(build, &mut requirements),
+            _ => Mapping::new(),
+        };
+        // the script of an output is a file name next to the recipe
+        if let Some(script) = map.remove("script") {
+            build.insert(key("script"), script);
+        }
+        if let Some(files) = map.remove("files") {
+            build.insert(key("files"), files);
+        }
+        if !build.is_empty() {
+            result.insert(key("build"), Value::Mapping(build));
+        }
+        if !requirements.is_empty() {
+            result.insert(key("requirements"), Value::Mapping(requirements));
+        }
+
+        if let Some(Value::Mapping(test)) = map.remove("test") {
+            let tests = self.convert_test(test);
+            if !tests.is_empty() {
+                result.insert(key("tests"), Value::Sequence(tests));
+            }
+        }
+        if let Some(Value::Mapping(about)) = map.remove("about") {
+            let about = self.convert_about(about);
+            result.insert(key("about"), Value::Mapping(about));
+        }
+        if let Some(extra) = map.remove("extra") {
+            result.insert(key("extra"), extra);
+        }
+
+        for (k, _) in map {
+            self.unsupported(k.as_str().unwrap_or_default());
+        }
+    }
+
+    fn convert_output(&mut self, mut output: Mapping) -> Mapping {
+        let mut result = Mapping::new();
+        let mut package = Mapping::new();
+        for field in ["name", "version"] {
+            if let Some(value) = output.remove(field) {
+                package.insert(key(field), value);
+            }
+        }
+        result.insert(key("package"), Value::Mapping(package));
+        if let Some(source) = output.remove("source") {
+            let source = map_conditional(source, &mut |s| self.convert_source(s));
+            result.insert(key("source"), Value::Sequence(as_sequence(source)));
+        }
+        self.convert_sections(output, &mut result);
+        result
+    }
+
+    fn convert(&mut self, mut meta: Mapping) -> Mapping {
+        let mut result = Mapping::new();
+        if !self.context.is_empty() {
+            let context = self
+                .context
+                .iter()
+                .map(|(k, v)| (key(k), v.clone()))
+                .collect();
+            result.insert(key("context"), Value::Mapping(context));
+        }
+
+        let outputs = meta.remove("outputs");
+        if let Some(package) = meta.remove("package") {
+            // with outputs, the top-level name and version are those of the recipe
+            let section = if outputs.is_some() {
+                "recipe"
+            } else {
+                "package"
+            };
+            result.insert(key(section), package);
+        }
+        if let Some(source) = meta.remove("source") {
+            let source = map_conditional(source, &mut |s| self.convert_source(s));
+            result.insert(key("source"), Value::Sequence(as_sequence(source)));
+        }
+        self.convert_sections(meta, &mut result);
+
+        if let Some(outputs) = outputs {
+            let outputs = map_conditional(outputs, &mut |o| self.convert_output(o));
+            result.insert(key("outputs"), outputs);
+        }
+        result
+    }
+}
+
+/// Convert the contents of a conda-build `meta.yaml` into a `recipe.yaml`.
+pub fn convert_recipe(meta_yaml: &str) -> miette::Result<Conversion> {
+    let mut converter = Converter::default();
+    let translated = converter.preprocess(meta_yaml);
+    let value: Value = serde_yaml::from_str(&translated)
+        .into_diagnostic()
+        .wrap_err("failed to parse the recipe after translating the Jinja and the selectors")?;
+    let Value::Mapping(meta) = converter.resolve_selectors(value) else {
+        return Err(miette::miette!("the recipe is not a mapping"));
+    };
+    let recipe = converter.convert(meta);
+
+    let yaml = serde_yaml::to_string(&Value::Mapping(recipe)).into_diagnostic()?;
+    // add an empty line before every top-level key
+    let mut recipe = String::new();
+    for line in yaml.lines() {
+        if !recipe.is_empty() && line.starts_with(|c: char| c.is_alphabetic()) {
+            recipe.push('\n');
+        }
+        recipe.push_str(line);
+        recipe.push('\n');
+    }
+
+    Ok(Conversion {
+        recipe,
+        report: converter.report,
+    })
+}
+
+/// Convert a `meta.yaml` and print (or write) the `recipe.yaml` and the report.
+pub fn convert_from_args(args: ConvertOpts) -> miette::Result<()> {
+    let path = if args.recipe.is_dir() {
+        args.recipe.join("meta.yaml")
+    } else {
+        args.recipe
+    };
+    let meta_yaml = fs::read_to_string(&path).into_diagnostic()?;
+    let conversion = convert_recipe(&meta_yaml)?;
+
+    match &args.output {
+        Some(output) => {
+            fs::write(output, &conversion.recipe).into_diagnostic()?;
+            eprintln!("Wrote {}", output.display());
+        }
+        None => print!("{}", conversion.recipe),
+    }
+
+    if !conversion.report.is_empty() {
+        eprintln!("\nThe following parts of the recipe need a second look:");
+        for entry in &conversion.report {
+            eprintln!(" - {entry}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_selector() {
+        assert_eq!(translate_selector("win"), "win");
+        assert_eq!(
+            translate_selector("py<38"),
+            "match(python, \"<3.8\")".to_string()
+        );
+        assert_eq!(
+            translate_selector("py>310 and not win64"),
+            "match(python, \">=3.11\") and not (win and x86_64)"
+        );
+        assert_eq!(translate_selector("py27"), "match(python, \"2.7.*\")");
+        assert_eq!(translate_selector("py3k"), "match(python, \">=3\")");
+    }
+
+    #[test]
+    fn test_convert_recipe() {
+        let meta_yaml = r#"{% set name = "foo" %}
+{% set version = "1.2.3" %}
+
+package:
+  name: {{ name|lower }}
+  version: {{ version }}
+
+source:
+  url: https://example.com/{{ name }}-{{ version }}.tar.gz
+  sha256: abc
+  folder: src
+
+build:
+  number: 0
+  skip: true  # [win]
+  skip: true  # [py<38]
+  entry_points:
+    - foo = foo:main
+  run_exports:
+    - {{ pin_subpackage('foo', max_pin='x.x') }}
+
+requirements:
+  build:
+    - {{ compiler('c') }}
+    - make  # [unix]
+  host:
+    - python
+  run:
+    - python
+  run_constrained:
+    - bar >=2
+
+test:
+  imports:
+    - foo
+  commands:
+    - pip check
+    - foo --help
+  requires:
+    - pip
+
+about:
+  home: https://example.com
+  license: MIT
+  license_family: MIT
+  summary: The foo package
+"#;
+        let conversion = convert_recipe(meta_yaml).unwrap();
+        let recipe: Value = serde_yaml::from_str(&conversion.recipe).unwrap();
+
+        assert_eq!(recipe["context"]["name"], "foo");
+        assert_eq!(recipe["package"]["name"], "${{ name|lower }}");
+        assert_eq!(recipe["source"][0]["target_directory"], "src");
+        assert_eq!(
+            recipe["build"]["skip"],
+            serde_yaml::from_str::<Value>("[win, 'match(python, \"<3.8\")']").unwrap()
+        );
+        assert_eq!(
+            recipe["build"]["python"]["entry_points"][0],
+            "foo = foo:main"
+        );
+        assert_eq!(
+            recipe["requirements"]["run_exports"][0],
+            "${{ pin_subpackage('foo', upper_bound='x.x') }}"
+        );
+        assert_eq!(recipe["requirements"]["build"][1]["if"], "unix");
+        assert_eq!(recipe["requirements"]["build"][1]["then"], "make");
+        assert_eq!(recipe["requirements"]["run_constraints"][0], "bar >=2");
+        assert_eq!(recipe["tests"][0]["python"]["pip_check"], true);
+        assert_eq!(recipe["tests"][1]["script"][0], "foo --help");
+        assert_eq!(recipe["tests"][1]["requirements"]["run"][0], "pip");
+        assert_eq!(recipe["about"]["homepage"], "https://example.com");
+        assert_eq!(conversion.report.len(), 1);
+        assert!(conversion.report[0].contains("about.license_family"));
+    }
+
+    #[test]
+    fn test_convert_selected_scalars() {
+        let meta_yaml = r#"package:
+  name: foo
+  version: 1.0.0
+
+source:
+  url: https://example.com/foo-win.zip  # [win]
+  url: https://example.com/foo-{{ version }}.tar.gz  # [not win]
+
+outputs:
+  - name: libfoo
+    requirements:
+      - bar
+"#;
+        let conversion = convert_recipe(meta_yaml).unwrap();
+        let recipe: Value = serde_yaml::from_str(&conversion.recipe).unwrap();
+        assert_eq!(
+            recipe["source"][0]["url"],
+            "${{ \"https://example.com/foo-win.zip\" if win else \"https://example.com/foo-\" ~ (version) ~ \".tar.gz\" }}"
+        );
+        assert_eq!(recipe["recipe"]["name"], "foo");
+        assert_eq!(recipe["outputs"][0]["package"]["name"], "libfoo");
+        assert_eq!(recipe["outputs"][0]["requirements"]["run"][0], "bar");
+    }
+}
@@ -0,0 +1,531 @@
+//! A long running build daemon for rapid successive builds.
+//!
+//! Starting `rattler-build` for every build of the same recipe means fetching and
+//! parsing repodata and reading the variant configs every time. The daemon keeps the
+//! repodata gateway (and with it the repodata in memory), the authenticated client
+//! and the parsed variant configs around, and accepts build requests from
+//! `rattler-build daemon build` over a local socket. Requests with other network
+//! settings than the daemon (e.g. `--offline`, `--proxy` or `--channel-mirror`) get a
+//! client of their own.
+//!
+//! The protocol is a single line of JSON per direction: the client sends a
+//! [`BuildRequest`] with its arguments and environment and the daemon answers with a
+//! [`BuildResponse`] once the build finished. Build logs are written to the output of the daemon. The daemon is only
+//! available on unix platforms.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use clap::{Parser, Subcommand};
+use miette::IntoDiagnostic;
+use rattler_conda_types::{ChannelConfig, Platform};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use url::Url;
+
+use crate::{
+    console_utils::LoggingOutputHandler,
+    get_build_output_with_variant_config, get_recipe_paths, get_tool_config,
+    opt::BuildOpts,
+    run_build_from_args, sort_build_outputs_topologically,
+    tool_configuration::{self, Configuration, NetworkSettings},
+    variant_config::VariantConfig,
+};
+
+/// Options for the `daemon` subcommand.
+#[derive(Parser)]
+pub struct DaemonOpts {
+    /// The daemon command to run
+    #[command(subcommand)]
+    pub command: DaemonCommand,
+}
+
+/// The daemon subcommands.
+#[derive(Subcommand)]
+pub enum DaemonCommand {
+    /// Start the daemon and wait for build requests
+    Start(DaemonStartOpts),
+
+    /// Build a recipe with a running daemon. Accepts the same arguments as `rattler-build build`
+    Build(DaemonBuildOpts),
+}
+
+/// Options for `rattler-build daemon start`.
+#[derive(Parser)]
+pub struct DaemonStartOpts {
+    /// The path of the socket to listen on. Defaults to `rattler-build-daemon.sock` in
+    /// the rattler cache directory.
+    #[arg(long)]
+    pub socket: Option<PathBuf>,
+
+    /// Path to an auth-file to read authentication information from
+    #[arg(long, env = "RATTLER_AUTH_FILE", hide = true)]
+    pub auth_file: Option<PathBuf>,
+
+    /// Proxy for the repodata and package downloads of the daemon
+    #[arg(long, env = "RATTLER_BUILD_PROXY")]
+    pub proxy: Option<Url>,
+
+    /// A PEM file with additional CA certificates to trust for the repodata and package
+    /// downloads of the daemon
+    #[arg(long, env = "RATTLER_BUILD_CA_CERT")]
+    pub ca_cert: Option<PathBuf>,
+}
+
+/// Options for `rattler-build daemon build`.
+#[derive(Parser)]
+pub struct DaemonBuildOpts {
+    /// The path of the socket the daemon listens on
+    #[arg(long)]
+    pub socket: Option<PathBuf>,
+
+    /// The arguments for the build, as passed to `rattler-build build`
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+}
+
+/// A build request that is sent to the daemon.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildRequest {
+    /// The working directory of the client. Relative paths in the arguments are
+    /// resolved against it.
+    pub cwd: PathBuf,
+    /// The arguments for the build, as passed to `rattler-build build`
+    pub args: Vec<String>,
+    /// The environment variables of the client. The recipe (`env.get`), the
+    /// `conda_build_config.yaml` files (`os.environ`) and the build scripts see these
+    /// instead of the environment of the daemon.
+    pub env: HashMap<String, String>,
+}
+
+/// The answer of the daemon to a [`BuildRequest`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildResponse {
+    /// Whether the build succeeded
+    pub success: bool,
+    /// The rendered error if the build failed
+    pub error: Option<String>,
+}
+
+/// The variant config files and the platforms and environment they were evaluated for.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+struct VariantConfigKey {
+    files: Vec<PathBuf>,
+    target_platform: Platform,
+    build_platform: Platform,
+    experimental: bool,
+    lenient_skip: bool,
+    environment: BTreeMap<String, String>,
+}
+
+/// Parsed variant configs, invalidated when one of the files is modified.
+#[derive(Default)]
+struct VariantConfigCache {
+    entries: HashMap<VariantConfigKey, (Vec<Option<SystemTime>>, VariantConfig)>,
+}
+
+impl VariantConfigCache {
+    /// Returns the variant config for the given build arguments and environment, parsing
+    /// the files only if they changed since they were last parsed.
+    fn get(
+        &mut self,
+        args: &BuildOpts,
+        environment: &Arc<HashMap<String, String>>,
+    ) -> miette::Result<&VariantConfig> {
+        let files = crate::variant_config_files(args)
+            .iter()
+            .map(|path| dunce::canonicalize(path).into_diagnostic())
+            .collect::<miette::Result<Vec<_>>>()?;
+        let modified = files
+            .iter()
+            .map(|path| fs_err::metadata(path).and_then(|m| m.modified()).ok())
+            .collect::<Vec<_>>();
+        let key = VariantConfigKey {
+            files,
+            target_platform: args.target_platform,
+            build_platform: args.build_platform(),
+            experimental: args.common.experimental,
+            lenient_skip: args.lenient_skip,
+            environment: environment
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        };
+
+        let stale = self
+            .entries
+            .get(&key)
+            .map_or(true, |(cached_modified, _)| *cached_modified != modified);
+        if stale {
+            let variant_config = crate::get_variant_config(args, Some(environment.clone()))?;
+            self.entries.insert(key.clone(), (modified, variant_config));
+        } else {
+            tracing::info!("Using cached variant config");
+        }
+
+        Ok(&self.entries[&key].1)
+    }
+}
+
+/// The state that is kept in memory between builds.
+struct Daemon {
+    tool_config: Configuration,
+    variant_configs: VariantConfigCache,
+}
+
+impl Daemon {
+    /// Run a single build request.
+    async fn build(&mut self, request: BuildRequest) -> miette::Result<()> {
+        let mut args =
+            BuildOpts::try_parse_from(std::iter::once("build".to_string()).chain(request.args))
+                .into_diagnostic()?;
+        resolve_paths(&mut args, &request.cwd);
+        if args.tui || args.render_only || args.build_order || args.dry_run || args.debug_shell {
+            return Err(miette::miette!(
+                "`--tui`, `--render-only`, `--build-order`, `--dry-run` and `--debug-shell` are not supported in daemon mode"
+            ));
+        }
+
+        // nobody could answer a prompt of the daemon
+        args.common.non_interactive = true;
+        let environment = Arc::new(request.env);
+        let tool_config = Configuration {
+            environment: Some(environment.clone()),
+            // local channels like `-c ./output` are relative to the client
+            channel_config: ChannelConfig::default_with_root_dir(request.cwd.clone()),
+            ..get_tool_config(&args, &self.tool_config.fancy_log_handler)?
+        };
+        let tool_config = if self.same_network(&tool_config) {
+            Configuration {
+                client: self.tool_config.client.clone(),
+                repodata_gateway: self.tool_config.repodata_gateway.clone(),
+                network: self.tool_config.network.clone(),
+                ..tool_config
+            }
+        } else {
+            tracing::info!(
+                "The network settings of the request differ from the ones of the daemon, not using the cached repodata"
+            );
+            tool_config
+        };
+
+        let mut outputs = Vec::new();
+        for recipe_path in get_recipe_paths(&args)? {
+            let variant_config = self.variant_configs.get(&args, &environment)?;
+            outputs.extend(
+                get_build_output_with_variant_config(
+                    &args,
+                    &recipe_path,
+                    &tool_config,
+                    variant_config,
+                )
+                .await?,
+            );
+        }
+
+        sort_build_outputs_topologically(&mut outputs, args.up_to.as_deref())?;
+        run_build_from_args(outputs, tool_config).await
+    }
+
+    /// Whether a request uses the same network settings and caches as the daemon, so
+    /// that the client and the repodata gateway of the daemon can be used for it.
+    fn same_network(&self, tool_config: &Configuration) -> bool {
+        let (daemon, request) = (&self.tool_config.network, &tool_config.network);
+        daemon.proxy == request.proxy
+            && daemon.ca_cert == request.ca_cert
+            && daemon.auth_file == request.auth_file
+            && daemon.mirrors == request.mirrors
+            && daemon.offline == request.offline
+            && self.tool_config.cache_dir == tool_config.cache_dir
+            && tool_config.private_cache_dir.is_none()
+    }
+}
+
+/// Resolves the relative paths of the build arguments against the working directory of
+/// the client. The working directory of the daemon itself is left alone, as it is shared
+/// by everything that runs in the daemon.
+fn resolve_paths(args: &mut BuildOpts, cwd: &Path) {
+    let resolve = |path: &mut PathBuf| {
+        if path.is_relative() {
+            *path = cwd.join(&*path);
+        }
+    };
+    // the output directory defaults to `./output` of the client
+    args.common
+        .output_dir
+        .get_or_insert_with(|| PathBuf::from("output"));
+
+    args.recipe
+        .iter_mut()
+        .chain(&mut args.recipe_dir)
+        .chain(&mut args.variant_config)
+        .chain(&mut args.reuse_lockfiles)
+        .chain(&mut args.stats_file)
+        .chain(&mut args.source_cache_dir)
+        .chain(&mut args.lockfile_dir)
+        .chain(&mut args.common.output_dir)
+        .chain(&mut args.common.auth_file)
+        .chain(&mut args.common.ca_cert)
+        .chain(&mut args.common.cache_dir)
+        .for_each(resolve);
+}
+
+/// Returns the default path of the daemon socket.
+fn default_socket_path() -> miette::Result<PathBuf> {
+    Ok(rattler::default_cache_dir()
+        .map_err(|e| miette::miette!("Could not determine the cache directory: {}", e))?
+        .join("rattler-build-daemon.sock"))
+}
+
+/// Start the daemon or send a build request to it.
+pub async fn daemon_from_args(
+    args: DaemonOpts,
+    fancy_log_handler: LoggingOutputHandler,
+) -> miette::Result<()> {
+    match args.command {
+        DaemonCommand::Start(args) => start(args, fancy_log_handler).await,
+        DaemonCommand::Build(args) => build(args).await,
+    }
+}
+
+/// Start the daemon and handle build requests until the process is stopped.
+async fn start(
+    args: DaemonStartOpts,
+    fancy_log_handler: LoggingOutputHandler,
+) -> miette::Result<()> {
+    let socket = match args.socket {
+        Some(socket) => socket,
+        None => default_socket_path()?,
+    };
+
+    let network = NetworkSettings {
+        proxy: args.proxy,
+        ca_cert: args
+            .ca_cert
+            .map(|path| dunce::canonicalize(path).into_diagnostic())
+            .transpose()?,
+        auth_file: args.auth_file.clone(),
+        // nobody could answer a prompt of the daemon
+        non_interactive: true,
+        ..NetworkSettings::default()
+    };
+    let client = tool_configuration::reqwest_client_from_auth_storage(args.auth_file, &network)
+        .into_diagnostic()?;
+    let mut daemon = Daemon {
+        tool_config: Configuration {
+            fancy_log_handler,
+            repodata_gateway: tool_configuration::repodata_gateway(
+                client.clone(),
+                &network,
+                &tool_configuration::rattler_cache_dir(None),
+            ),
+            client,
+            network,
+            ..Configuration::default()
+        },
+        variant_configs: VariantConfigCache::default(),
+    };
+
+    let listener = bind(&socket)?;
+    tracing::info!("Listening for build requests on {}", socket.display());
+
+    loop {
+        let (stream, _) = listener.accept().await.into_diagnostic()?;
+        if let Err(err) = handle_connection(&mut daemon, stream).await {
+            tracing::error!("Failed to handle build request: {:?}", err);
+        }
+    }
+}
+
+/// Bind the socket, removing a stale socket file of a daemon that is no longer
+/// running.
+fn bind(socket: &Path) -> miette::Result<UnixListener> {
+    if socket.exists() {
+        if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+            return Err(miette::miette!(
+                "A daemon is already listening on {}",
+                socket.display()
+            ));
+        }
+        fs_err::remove_file(socket).into_diagnostic()?;
+    }
+    if let Some(parent) = socket.parent() {
+        fs_err::create_dir_all(parent).into_diagnostic()?;
+    }
+    UnixListener::bind(socket).into_diagnostic()
+}
+
+/// Read a single request from the connection, run the build and send the response.
+async fn handle_connection(daemon: &mut Daemon, stream: UnixStream) -> miette::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader)
+        .read_line(&mut line)
+        .await
+        .into_diagnostic()?;
+    let request: BuildRequest = serde_json::from_str(&line).into_diagnostic()?;
+    tracing::info!("Received build request: {}", request.args.join(" "));
+
+    let response = match daemon.build(request).await {
+        Ok(()) => BuildResponse {
+            success: true,
+            error: None,
+        },
+        Err(err) => {
+            tracing::error!("Build failed: {:?}", err);
+            BuildResponse {
+                success: false,
+                error: Some(format!("{:?}", err)),
+            }
+        }
+    };
+
+    let mut response = serde_json::to_string(&response).into_diagnostic()?;
+    response.push('\n');
+    writer
+        .write_all(response.as_bytes())
+        .await
+        .into_diagnostic()
+}
+
+/// Send a build request to the daemon and wait for the build to finish.
+async fn build(args: DaemonBuildOpts) -> miette::Result<()> {
+    let socket = match args.socket {
+        Some(socket) => socket,
+        None => default_socket_path()?,
+    };
+    let stream = UnixStream::connect(&socket).await.map_err(|e| {
+        miette::miette!(
+            help = "start the daemon with `rattler-build daemon start`",
+            "Could not connect to the daemon at {}: {}",
+            socket.display(),
+            e
+        )
+    })?;
+
+    let request = BuildRequest {
+        cwd: std::env::current_dir().into_diagnostic()?,
+        args: args.args,
+        env: std::env::vars().collect(),
+    };
+    let mut request = serde_json::to_string(&request).into_diagnostic()?;
+    request.push('\n');
+
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(request.as_bytes())
+        .await
+        .into_diagnostic()?;
+
+    let mut line = String::new();
+    BufReader::new(reader)
+        .read_line(&mut line)
+        .await
+        .into_diagnostic()?;
+    let response: BuildResponse = serde_json::from_str(&line)
+        .map_err(|_| miette::miette!("The daemon closed the connection without a response"))?;
+
+    if response.success {
+        tracing::info!("Build finished successfully");
+        Ok(())
+    } else {
+        Err(miette::miette!(
+            "{}",
+            response.error.unwrap_or_else(|| "Build failed".to_string())
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use clap::Parser;
+
+    use super::{resolve_paths, VariantConfigCache};
+    use crate::opt::BuildOpts;
+
+    #[test]
+    fn test_resolve_paths() {
+        let mut args =
+            BuildOpts::try_parse_from(["build", "-r", "recipe", "-m", "/etc/variants.yaml"])
+                .unwrap();
+        resolve_paths(&mut args, Path::new("/home/client"));
+        assert_eq!(args.recipe, vec![PathBuf::from("/home/client/recipe")]);
+        assert_eq!(
+            args.variant_config,
+            vec![PathBuf::from("/etc/variants.yaml")]
+        );
+        assert_eq!(
+            args.common.output_dir,
+            Some(PathBuf::from("/home/client/output"))
+        );
+    }
+
+    #[test]
+    fn test_variant_config_cache() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("variants.yaml");
+        let write = |content: &str, modified: SystemTime| {
+            std::fs::write(&path, content).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+
+        let args = BuildOpts::try_parse_from(["build", "-m", path.to_str().unwrap()]).unwrap();
+        let mut cache = VariantConfigCache::default();
+        let now = SystemTime::now();
+        let environment = Arc::new(HashMap::new());
+
+        write("python:\n  - \"3.11\"\n", now);
+        let python = |cache: &mut VariantConfigCache, environment: &Arc<HashMap<_, _>>| {
+            cache
+                .get(&args, environment)
+                .unwrap()
+                .variants
+                .get("python")
+                .cloned()
+        };
+        assert_eq!(
+            python(&mut cache, &environment),
+            Some(vec!["3.11".to_string()])
+        );
+
+        // the cached config is used as long as the file is not modified
+        write("python:\n  - \"3.12\"\n", now);
+        assert_eq!(
+            python(&mut cache, &environment),
+            Some(vec!["3.11".to_string()])
+        );
+
+        // but not for a client with another environment
+        let other_environment =
+            Arc::new(HashMap::from([("CONDA_PY".to_string(), "312".to_string())]));
+        assert_eq!(
+            python(&mut cache, &other_environment),
+            Some(vec!["3.12".to_string()])
+        );
+
+        write("python:\n  - \"3.12\"\n", now + Duration::from_secs(1));
+        assert_eq!(
+            python(&mut cache, &environment),
+            Some(vec!["3.12".to_string()])
+        );
+    }
+}
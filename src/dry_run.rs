@@ -0,0 +1,225 @@
+//! Resolve the environments of the outputs without fetching sources or running the
+//! build scripts (`rattler-build build --dry-run`).
+
+use miette::IntoDiagnostic;
+use rattler_conda_types::{MatchSpec, PackageName, ParseStrictness, Platform};
+
+use crate::{
+    build::skip_existing,
+    dependency_name,
+    metadata::{log_skipped_outputs, Output},
+    recipe::parser::{Dependency, TestType},
+    render::{resolved_dependencies::ResolveError, solver::create_environment},
+    tool_configuration::Configuration,
+};
+
+/// Resolves the build, host and test environments of the (topologically sorted) outputs
+/// and reports which packages would be built. Nothing is installed, no sources are
+/// fetched and no build scripts are executed. The solves are done for all outputs, even
+/// if some of them fail, and an error is returned at the end if any solve failed.
+///
+/// Environments that contain an earlier output of the dry run (e.g. through
+/// `pin_subpackage`) can not be resolved, as that output is not built. They are reported
+/// as pending instead.
+pub async fn dry_run(outputs: Vec<Output>, tool_config: &Configuration) -> miette::Result<()> {
+    let outputs = skip_existing(outputs, tool_config).await?;
+    let names = outputs
+        .iter()
+        .map(|output| output.name().clone())
+        .collect::<Vec<_>>();
+
+    let mut would_build = Vec::new();
+    let mut pending = Vec::new();
+    let mut failed = Vec::new();
+    for (idx, output) in outputs.into_iter().enumerate() {
+        let identifier = output.identifier().unwrap_or_default();
+        let span = tracing::info_span!("Dry run", output = %identifier);
+        let _enter = span.enter();
+
+        let earlier_outputs = |dependencies: &[Dependency]| {
+            dependencies
+                .iter()
+                .map(dependency_name)
+                .filter(|name| name != output.name() && names[..idx].contains(name))
+                .collect::<Vec<_>>()
+        };
+        let requirements = output.recipe.requirements();
+        let mut build_or_host = earlier_outputs(requirements.build());
+        build_or_host.extend(earlier_outputs(requirements.host()));
+        let run = earlier_outputs(requirements.run());
+
+        if !build_or_host.is_empty() {
+            tracing::warn!(
+                "Not resolving the environments of {}, they contain {} which would be built first",
+                identifier,
+                join_names(&build_or_host)
+            );
+            pending.push(format!(
+                "{} (needs {})",
+                identifier,
+                join_names(&build_or_host)
+            ));
+            continue;
+        }
+
+        let result = match output.resolve_dependencies(tool_config).await {
+            Ok(_) if !run.is_empty() => {
+                tracing::warn!(
+                    "Not resolving the test environments of {}, they contain {} which would be built first",
+                    identifier,
+                    join_names(&run)
+                );
+                pending.push(format!("{} (tests need {})", identifier, join_names(&run)));
+                continue;
+            }
+            Ok(output) => resolve_test_environments(&output, tool_config).await,
+            Err(e) => Err(e).into_diagnostic(),
+        };
+
+        match result {
+            Ok(()) => would_build.push(identifier),
+            Err(e) => {
+                tracing::error!(
+                    "Could not resolve the environments of {}: {:?}",
+                    identifier,
+                    e
+                );
+                failed.push(identifier);
+            }
+        }
+    }
+
+    let span = tracing::info_span!("Dry run summary");
+    let _enter = span.enter();
+    log_skipped_outputs(&tool_config.skipped_outputs.lock().unwrap()).into_diagnostic()?;
+
+    if !would_build.is_empty() {
+        tracing::info!("\nThe following packages would be built:");
+        for identifier in &would_build {
+            tracing::info!("  - {}", identifier);
+        }
+    }
+
+    if !pending.is_empty() {
+        tracing::info!(
+            "\nThe following packages would be built, but their environments could only be resolved after building the outputs they need:"
+        );
+        for identifier in &pending {
+            tracing::info!("  - {}", identifier);
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(miette::miette!(
+            "Could not resolve the environments of:\n - {}",
+            failed.join("\n - ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Joins package names for a message, e.g. `libfoo, libbar`.
+fn join_names(names: &[PackageName]) -> String {
+    names
+        .iter()
+        .map(|name| name.as_normalized())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Resolves the environments of the tests of an output. The package itself is not built
+/// yet, so its run dependencies are used in its place.
+async fn resolve_test_environments(
+    output: &Output,
+    tool_config: &Configuration,
+) -> miette::Result<()> {
+    let Some(finalized_dependencies) = &output.finalized_dependencies else {
+        return Ok(());
+    };
+
+    let build_configuration = &output.build_configuration;
+    let channels = output.channels(tool_config).await.into_diagnostic()?;
+    let test_platform = match build_configuration.target_platform {
+        Platform::NoArch => build_configuration.build_platform,
+        platform => platform,
+    };
+    let run_specs = finalized_dependencies
+        .run
+        .depends
+        .iter()
+        .map(|dep| dep.spec().clone())
+        .collect::<Vec<_>>();
+    let parse_specs = |specs: &[String]| {
+        specs
+            .iter()
+            .map(|s| MatchSpec::from_str(s, ParseStrictness::Lenient))
+            .collect::<Result<Vec<_>, _>>()
+            .into_diagnostic()
+    };
+
+    for test in output.recipe.tests() {
+        let (build_specs, test_specs) = match test {
+            TestType::Python(python_test) => {
+                let mut specs = run_specs.clone();
+                if python_test.pip_check {
+                    specs.push(MatchSpec::from_str("pip", ParseStrictness::Strict).unwrap());
+                }
+                (Vec::new(), specs)
+            }
+            TestType::Perl(_) => {
+                let mut specs = run_specs.clone();
+                specs.push(MatchSpec::from_str("perl", ParseStrictness::Strict).unwrap());
+                (Vec::new(), specs)
+            }
+            TestType::R(_) => {
+                let mut specs = run_specs.clone();
+                specs.push(MatchSpec::from_str("r-base", ParseStrictness::Strict).unwrap());
+                (Vec::new(), specs)
+            }
+            TestType::Command(command_test) => {
+                let mut specs = run_specs.clone();
+                specs.extend(parse_specs(&command_test.requirements.run)?);
+                (parse_specs(&command_test.requirements.build)?, specs)
+            }
+            // these tests need the built package
+            TestType::Downstream(_) | TestType::PackageContents(_) => continue,
+        };
+
+        tracing::info!("\nResolving test environment");
+        if !build_specs.is_empty() {
+            create_environment(
+                &build_specs,
+                &build_configuration.build_platform,
+                &build_configuration.directories.build_prefix,
+                &channels,
+                tool_config,
+            )
+            .await
+            .map_err(ResolveError::from)
+            .into_diagnostic()?;
+        }
+        let records = create_environment(
+            &test_specs,
+            &test_platform,
+            &build_configuration.directories.host_prefix,
+            &channels,
+            tool_config,
+        )
+        .await
+        .map_err(ResolveError::from)
+        .into_diagnostic()?;
+
+        tracing::info!("\nTest environment:");
+        for record in &records {
+            tracing::info!(
+                "  - {} {} {}",
+                record.package_record.name.as_normalized(),
+                record.package_record.version,
+                record.package_record.build
+            );
+        }
+    }
+
+    Ok(())
+}
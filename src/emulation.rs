@@ -0,0 +1,393 @@
+//! Transparent emulation of a foreign architecture with QEMU user-mode emulation.
+//!
+//! With `--emulate`, a package for another architecture of Linux (e.g. `linux-aarch64`
+//! on an `x86_64` machine) is built and tested as if the machine had that architecture:
+//! the build platform is the target platform, so the build environment contains
+//! binaries of the target architecture, and the kernel runs them with the QEMU
+//! interpreter that is registered with `binfmt_misc`.
+//!
+//! If no interpreter is registered for the architecture, a `qemu-<arch>-static` (or
+//! `qemu-<arch>`) from the `PATH` is registered, which requires root (e.g. a privileged
+//! container). The emulation is recorded in the `about.json` of the package.
+
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+use goblin::elf::header;
+use rattler_conda_types::{Arch, Platform};
+use serde::{Deserialize, Serialize};
+
+/// The directory in which `binfmt_misc` is mounted.
+const BINFMT_MISC_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// How a package was built with emulation, recorded in its `about.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Emulation {
+    /// The platform of the machine that ran the emulator
+    pub machine_platform: Platform,
+    /// The name of the `binfmt_misc` handler (e.g. `qemu-aarch64`)
+    pub handler: String,
+    /// The path of the emulator
+    pub interpreter: PathBuf,
+}
+
+/// An error that occurs while setting up the emulation.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum EmulationError {
+    #[error("Emulation is only supported between Linux platforms, not for {target} on {machine}")]
+    UnsupportedPlatform { machine: Platform, target: Platform },
+
+    #[error("`binfmt_misc` is not available in {0}")]
+    #[diagnostic(help(
+        "mount it with `mount -t binfmt_misc binfmt_misc /proc/sys/fs/binfmt_misc`"
+    ))]
+    BinfmtUnavailable(String),
+
+    #[error("`binfmt_misc` is disabled")]
+    #[diagnostic(help("enable it with `echo 1 > /proc/sys/fs/binfmt_misc/status`"))]
+    BinfmtDisabled,
+
+    #[error("No QEMU interpreter is registered for {0} and no `qemu-{0}-static` was found")]
+    #[diagnostic(help(
+        "install `qemu-user-static`, or register the interpreters with `docker run --privileged --rm tonistiigi/binfmt --install all`"
+    ))]
+    NotRegistered(Arch),
+
+    #[error("The interpreter {1} of the `binfmt_misc` handler `{0}` does not exist")]
+    #[diagnostic(help(
+        "register the interpreter with the `F` (fix binary) flag, so that it is loaded when it is registered"
+    ))]
+    InterpreterMissing(String, PathBuf),
+
+    #[error("Could not register {1} with `binfmt_misc` (registering requires root)")]
+    RegistrationFailed(String, PathBuf, #[source] std::io::Error),
+}
+
+/// The architecture of an ELF machine type.
+pub(crate) fn elf_architecture(machine: u16, is_64: bool, little_endian: bool) -> Option<Arch> {
+    match machine {
+        header::EM_X86_64 => Some(Arch::X86_64),
+        header::EM_386 => Some(Arch::X86),
+        header::EM_AARCH64 => Some(Arch::Aarch64),
+        header::EM_ARM => Some(Arch::ArmV7l),
+        header::EM_PPC64 if little_endian => Some(Arch::Ppc64le),
+        header::EM_PPC64 => Some(Arch::Ppc64),
+        header::EM_S390 => Some(Arch::S390X),
+        header::EM_RISCV if is_64 => Some(Arch::Riscv64),
+        header::EM_RISCV => Some(Arch::Riscv32),
+        _ => None,
+    }
+}
+
+/// Whether two architectures are the same (`arm64` and `aarch64` are).
+pub(crate) fn same_architecture(a: Arch, b: Arch) -> bool {
+    let normalize = |arch| match arch {
+        Arch::Arm64 => Arch::Aarch64,
+        arch => arch,
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Whether the machine runs binaries of the platform without emulation by QEMU (e.g. an
+/// `osx-arm64` machine runs `osx-64` binaries with Rosetta 2).
+pub fn can_run_natively(machine: Platform, platform: Platform) -> bool {
+    machine == platform
+        || platform == Platform::NoArch
+        || matches!(
+            (machine, platform),
+            (Platform::OsxArm64, Platform::Osx64)
+                | (Platform::Win64, Platform::Win32)
+                | (Platform::Linux64, Platform::Linux32)
+        )
+}
+
+/// A handler that is registered with `binfmt_misc`.
+#[derive(Debug, PartialEq, Eq)]
+struct BinfmtHandler {
+    name: String,
+    enabled: bool,
+    interpreter: PathBuf,
+    flags: String,
+    /// The architecture of the ELF binaries that the handler matches
+    arch: Option<Arch>,
+}
+
+impl BinfmtHandler {
+    /// Parse a handler from its file in the `binfmt_misc` directory, e.g.:
+    ///
+    /// ```text
+    /// enabled
+    /// interpreter /usr/bin/qemu-aarch64-static
+    /// flags: F
+    /// offset 0
+    /// magic 7f454c460201010000000000000000000200b700
+    /// mask ffffffffffffff00fffffffffffffffffeffffff
+    /// ```
+    fn parse(name: &str, content: &str) -> Self {
+        let mut handler = BinfmtHandler {
+            name: name.to_string(),
+            enabled: false,
+            interpreter: PathBuf::new(),
+            flags: String::new(),
+            arch: None,
+        };
+        let mut offset = 0;
+        let mut magic = Vec::new();
+        for line in content.lines() {
+            match line.split_once([' ', ':']) {
+                _ if line == "enabled" => handler.enabled = true,
+                Some(("interpreter", value)) => handler.interpreter = PathBuf::from(value.trim()),
+                Some(("flags", value)) => handler.flags = value.trim().to_string(),
+                Some(("offset", value)) => offset = value.trim().parse().unwrap_or(0),
+                Some(("magic", value)) => magic = hex::decode(value.trim()).unwrap_or_default(),
+                _ => {}
+            }
+        }
+
+        // the magic of an ELF handler is the start of the ELF header, up to `e_machine`
+        if offset == 0 && magic.len() >= 20 && magic.starts_with(b"\x7fELF") {
+            let little_endian = magic[5] == 1;
+            let machine = if little_endian {
+                u16::from_le_bytes([magic[18], magic[19]])
+            } else {
+                u16::from_be_bytes([magic[18], magic[19]])
+            };
+            handler.arch = elf_architecture(machine, magic[4] == 2, little_endian);
+        }
+        handler
+    }
+}
+
+/// The handlers that are registered with `binfmt_misc`.
+fn registered_handlers(binfmt_dir: &Path) -> Result<Vec<BinfmtHandler>, EmulationError> {
+    let status = fs::read_to_string(binfmt_dir.join("status"))
+        .map_err(|_| EmulationError::BinfmtUnavailable(binfmt_dir.display().to_string()))?;
+    if status.trim() != "enabled" {
+        return Err(EmulationError::BinfmtDisabled);
+    }
+
+    let entries = fs::read_dir(binfmt_dir)
+        .map_err(|_| EmulationError::BinfmtUnavailable(binfmt_dir.display().to_string()))?;
+    let mut handlers = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == "status" || name == "register" {
+            continue;
+        }
+        if let Ok(content) = fs::read_to_string(entry.path()) {
+            handlers.push(BinfmtHandler::parse(&name, &content));
+        }
+    }
+    Ok(handlers)
+}
+
+/// The name of QEMU for an architecture and the magic and mask that match its ELF
+/// binaries (from `qemu-binfmt-conf.sh`).
+fn qemu_target(arch: Arch) -> Option<(&'static str, [u8; 20], [u8; 20])> {
+    const ELF64_LE: [u8; 16] = *b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+    const ELF64_BE: [u8; 16] = *b"\x7fELF\x02\x02\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+    let magic = |header: [u8; 16], machine: [u8; 4]| {
+        let mut magic = [0; 20];
+        magic[..16].copy_from_slice(&header);
+        magic[16..].copy_from_slice(&machine);
+        magic
+    };
+    let target = match arch {
+        Arch::Aarch64 | Arch::Arm64 => (
+            "aarch64",
+            magic(ELF64_LE, *b"\x02\x00\xb7\x00"),
+            *b"\xff\xff\xff\xff\xff\xff\xff\x00\xff\xff\xff\xff\xff\xff\xff\xff\xfe\xff\xff\xff",
+        ),
+        Arch::Ppc64le => (
+            "ppc64le",
+            magic(ELF64_LE, *b"\x02\x00\x15\x00"),
+            *b"\xff\xff\xff\xff\xff\xff\xff\xfc\xff\xff\xff\xff\xff\xff\xff\xff\xfe\xff\xff\x00",
+        ),
+        Arch::S390X => (
+            "s390x",
+            magic(ELF64_BE, *b"\x00\x02\x00\x16"),
+            *b"\xff\xff\xff\xff\xff\xff\xff\xfc\xff\xff\xff\xff\xff\xff\xff\xff\xff\xfe\xff\xff",
+        ),
+        Arch::X86_64 => (
+            "x86_64",
+            magic(ELF64_LE, *b"\x02\x00\x3e\x00"),
+            *b"\xff\xff\xff\xff\xff\xfe\xfe\x00\xff\xff\xff\xff\xff\xff\xff\xff\xfe\xff\xff\xff",
+        ),
+        Arch::Riscv64 => (
+            "riscv64",
+            magic(ELF64_LE, *b"\x02\x00\xf3\x00"),
+            *b"\xff\xff\xff\xff\xff\xff\xff\x00\xff\xff\xff\xff\xff\xff\xff\xff\xfe\xff\xff\xff",
+        ),
+        _ => return None,
+    };
+    Some(target)
+}
+
+/// The line that registers an interpreter with `binfmt_misc`. The `F` flag loads the
+/// interpreter right away, so that it also works in containers and chroots.
+fn registration(name: &str, magic: &[u8], mask: &[u8], interpreter: &Path) -> String {
+    let escape = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|byte| format!("\\x{:02x}", byte))
+            .collect::<String>()
+    };
+    format!(
+        ":{}:M::{}:{}:{}:F",
+        name,
+        escape(magic),
+        escape(mask),
+        interpreter.display()
+    )
+}
+
+/// Register QEMU from the `PATH` as the interpreter for an architecture.
+fn register(binfmt_dir: &Path, arch: Arch) -> Result<BinfmtHandler, EmulationError> {
+    let (name, magic, mask) = qemu_target(arch).ok_or(EmulationError::NotRegistered(arch))?;
+    let interpreter = which::which(format!("qemu-{}-static", name))
+        .or_else(|_| which::which(format!("qemu-{}", name)))
+        .map_err(|_| EmulationError::NotRegistered(arch))?;
+
+    let handler = format!("qemu-{}", name);
+    tracing::info!(
+        "Registering {} for {} with `binfmt_misc`",
+        interpreter.display(),
+        arch
+    );
+    fs::write(
+        binfmt_dir.join("register"),
+        registration(&handler, &magic, &mask, &interpreter),
+    )
+    .map_err(|err| EmulationError::RegistrationFailed(handler.clone(), interpreter.clone(), err))?;
+
+    Ok(BinfmtHandler {
+        name: handler,
+        enabled: true,
+        interpreter,
+        flags: "F".to_string(),
+        arch: Some(arch),
+    })
+}
+
+/// Make sure that binaries of the target platform can be run on the machine, and
+/// return how they are emulated (or `None` if the machine runs them natively).
+pub fn setup(machine: Platform, target: Platform) -> Result<Option<Emulation>, EmulationError> {
+    setup_in(Path::new(BINFMT_MISC_DIR), machine, target)
+}
+
+fn setup_in(
+    binfmt_dir: &Path,
+    machine: Platform,
+    target: Platform,
+) -> Result<Option<Emulation>, EmulationError> {
+    if machine == target {
+        tracing::info!(
+            "{} runs natively on this machine, no emulation needed",
+            target
+        );
+        return Ok(None);
+    }
+    let arch = match target.arch() {
+        Some(arch) if machine.is_linux() && target.is_linux() => arch,
+        _ => return Err(EmulationError::UnsupportedPlatform { machine, target }),
+    };
+
+    let registered = registered_handlers(binfmt_dir)?
+        .into_iter()
+        .find(|handler| {
+            handler.enabled
+                && handler
+                    .arch
+                    .is_some_and(|handler_arch| same_architecture(handler_arch, arch))
+        });
+    let handler = match registered {
+        Some(handler) => {
+            // without the `F` flag, the interpreter is looked up when a binary is run
+            if !handler.flags.contains('F') && !handler.interpreter.exists() {
+                return Err(EmulationError::InterpreterMissing(
+                    handler.name,
+                    handler.interpreter,
+                ));
+            }
+            handler
+        }
+        None => register(binfmt_dir, arch)?,
+    };
+
+    tracing::info!(
+        "Emulating {} with {} (`binfmt_misc` handler `{}`)",
+        target,
+        handler.interpreter.display(),
+        handler.name
+    );
+    Ok(Some(Emulation {
+        machine_platform: machine,
+        handler: handler.name,
+        interpreter: handler.interpreter,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binfmt_handlers() {
+        let binfmt_dir = tempfile::tempdir().unwrap();
+        let dir = binfmt_dir.path();
+        fs::write(dir.join("status"), "enabled\n").unwrap();
+        fs::write(
+            dir.join("qemu-aarch64"),
+            "enabled\ninterpreter /usr/bin/qemu-aarch64-static\nflags: F\noffset 0\n\
+             magic 7f454c460201010000000000000000000200b700\n\
+             mask ffffffffffffff00fffffffffffffffffeffffff\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("qemu-s390x"),
+            "disabled\ninterpreter /usr/bin/qemu-s390x-static\nflags: \noffset 0\n\
+             magic 7f454c4602020100000000000000000000020016\n\
+             mask fffffffffffffffcfffffffffffffffffffeffff\n",
+        )
+        .unwrap();
+
+        let handlers = registered_handlers(dir).unwrap();
+        assert_eq!(handlers.len(), 2);
+        let s390x = handlers
+            .iter()
+            .find(|handler| handler.name == "qemu-s390x")
+            .unwrap();
+        assert_eq!(s390x.arch, Some(Arch::S390X));
+        assert!(!s390x.enabled);
+        assert!(same_architecture(Arch::Arm64, Arch::Aarch64));
+        assert!(can_run_natively(Platform::OsxArm64, Platform::Osx64));
+        assert!(!can_run_natively(Platform::Linux64, Platform::LinuxAarch64));
+
+        let emulation = setup_in(dir, Platform::Linux64, Platform::LinuxAarch64)
+            .unwrap()
+            .unwrap();
+        assert_eq!(emulation.handler, "qemu-aarch64");
+        assert_eq!(
+            emulation.interpreter,
+            PathBuf::from("/usr/bin/qemu-aarch64-static")
+        );
+        assert!(setup_in(dir, Platform::Linux64, Platform::Linux64)
+            .unwrap()
+            .is_none());
+        assert!(setup_in(dir, Platform::Linux64, Platform::Win64).is_err());
+
+        let (name, magic, mask) = qemu_target(Arch::Aarch64).unwrap();
+        assert_eq!(
+            BinfmtHandler::parse(name, &format!("enabled\nmagic {}\n", hex::encode(magic))).arch,
+            Some(Arch::Aarch64)
+        );
+        assert!(registration(
+            "qemu-aarch64",
+            &magic,
+            &mask,
+            Path::new("/usr/bin/qemu-aarch64-static")
+        )
+        .starts_with(":qemu-aarch64:M::\\x7f\\x45\\x4c\\x46\\x02\\x01"));
+    }
+}
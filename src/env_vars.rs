@@ -267,6 +267,22 @@ pub fn vars(output: &Output, build_state: &str) -> HashMap<String, String> {
         vars.extend(force_color_vars());
     }
 
+    // development builds link back to the local sources, the build script can check
+    // this variable to do an editable install. Keep the cargo target directory between
+    // builds so that rust outputs are rebuilt incrementally.
+    if output.build_configuration.dev {
+        insert!(vars, "RATTLER_BUILD_DEV", "1");
+        insert!(
+            vars,
+            "CARGO_TARGET_DIR",
+            directories
+                .output_dir
+                .join("bld")
+                .join(format!("cargo_target_{}", output.name().as_normalized()))
+                .to_string_lossy()
+        );
+    }
+
     // pkg vars
     insert!(vars, "PKG_NAME", output.name().as_normalized());
     insert!(vars, "PKG_VERSION", output.version());
@@ -0,0 +1,334 @@
+//! The `generate-patch` subcommand turns edits in the work directory of a build (e.g. one
+//! prepared with `rattler-build debug`) into patch files for the recipe.
+//!
+//! The sources of the recipe are fetched again into a temporary directory (with the
+//! existing patches applied), and every file that differs from the work directory is
+//! diffed with `git diff --no-index`. The changes of every source end up in a numbered
+//! patch file next to the recipe.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use clap::{Parser, ValueEnum};
+use fs_err as fs;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use miette::IntoDiagnostic;
+
+use crate::{
+    console_utils::LoggingOutputHandler,
+    get_build_output, get_recipe_paths, get_tool_config,
+    metadata::Directories,
+    opt::BuildOpts,
+    recipe::parser::{ScriptPhase, Source},
+    source::fetch_sources,
+    system_tools::{SystemTools, Tool},
+};
+
+/// Directories of version control systems, which are never part of a patch.
+const VCS_DIRS: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Files that `rattler-build debug` and the build script write into the work directory.
+/// The scripts of a phase have the phase in their name, e.g. `conda_build_install.sh`.
+const BUILD_SCRIPT_FILES: [&str; 8] = [
+    "build_env.sh",
+    "build_env.bat",
+    "build_env.ps1",
+    "conda_build.sh",
+    "conda_build.bat",
+    "conda_build.ps1",
+    "conda_build_script.py",
+    "conda_build.log",
+];
+
+/// Whether the file name is one of the [`BUILD_SCRIPT_FILES`], possibly of a phase.
+fn is_build_script_file(name: &str) -> bool {
+    BUILD_SCRIPT_FILES.iter().any(|file| {
+        name == *file
+            || ScriptPhase::value_variants().iter().any(|phase| {
+                let (stem, extension) = file.rsplit_once('.').expect("has an extension");
+                name == format!("{stem}_{phase}.{extension}")
+            })
+    })
+}
+
+/// Options for the `generate-patch` subcommand.
+#[derive(Parser)]
+pub struct GeneratePatchOpts {
+    /// The work directory with the modified sources (as printed by `rattler-build debug`)
+    #[arg(long)]
+    pub work_dir: PathBuf,
+
+    /// The name of the patch, patch files are called `<number>-<name>.patch`
+    #[arg(long, default_value = "changes")]
+    pub name: String,
+
+    /// The name of the output, if the recipe has multiple outputs
+    #[arg(long)]
+    pub output_name: Option<String>,
+
+    /// Glob patterns (relative to the work directory) of files to leave out of the patch,
+    /// e.g. build artifacts
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    /// Build options, used to render the recipe and fetch the pristine sources
+    #[clap(flatten)]
+    pub build: BuildOpts,
+}
+
+/// All files in a directory (relative to it), without the directories of version
+/// control systems and the build scripts.
+fn collect_files(dir: &Path, exclude: &GlobSet) -> miette::Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let walker = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| {
+            !(entry.file_type().is_dir()
+                && VCS_DIRS.iter().any(|vcs_dir| entry.file_name() == *vcs_dir))
+        });
+    for entry in walker {
+        let entry = entry.into_diagnostic()?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(dir)
+            .expect("walkdir yields paths in the directory")
+            .to_path_buf();
+        let is_build_script =
+            relative.components().count() == 1 && is_build_script_file(&relative.to_string_lossy());
+        if !is_build_script && !exclude.is_match(&relative) {
+            files.insert(relative);
+        }
+    }
+    Ok(files)
+}
+
+/// The path as `git` prints it in the headers of a diff.
+fn git_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('\\', "/")
+        .trim_start_matches('/')
+        .to_string()
+}
+
+/// Rewrite the headers of a diff of two files in different directories, so that both
+/// sides use the path of the file in the source (`a/<path>` and `b/<path>`).
+fn rewrite_diff_paths(diff: &str, pristine: &Path, work: &Path, path: &Path) -> String {
+    let path = git_path(path);
+    let mut diff = diff.to_string();
+    for side in [git_path(pristine), git_path(work)] {
+        for prefix in ["a/", "b/"] {
+            diff = diff.replace(&format!("{prefix}{side}"), &format!("{prefix}{path}"));
+        }
+    }
+    diff
+}
+
+/// Diff a file of the pristine sources with the file in the work directory. A missing
+/// file is compared with the null device (a new or deleted file).
+fn diff_file(
+    system_tools: &SystemTools,
+    pristine: &Path,
+    work: &Path,
+    path: &Path,
+) -> miette::Result<String> {
+    let null_device = Path::new(if cfg!(windows) { "NUL" } else { "/dev/null" });
+    let old = if pristine.exists() {
+        pristine
+    } else {
+        null_device
+    };
+    let new = if work.exists() { work } else { null_device };
+
+    let output = system_tools
+        .call(Tool::Git)
+        .into_diagnostic()?
+        .args(["diff", "--no-index", "--binary", "--no-color"])
+        .arg(old)
+        .arg(new)
+        .output()
+        .into_diagnostic()?;
+    // `git diff --no-index` exits with 1 if the files differ
+    if !matches!(output.status.code(), Some(0) | Some(1)) {
+        return Err(miette::miette!(
+            "`git diff` failed for {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(rewrite_diff_paths(
+        &String::from_utf8_lossy(&output.stdout),
+        pristine,
+        work,
+        path,
+    ))
+}
+
+/// The index of the source that a file in the work directory belongs to (the source with
+/// the longest matching `target_directory`), and the path of the file in that source.
+fn source_of<'a>(sources: &[Source], path: &'a Path) -> Option<(usize, &'a Path)> {
+    sources
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, source)| match source.target_directory() {
+            Some(target) => path.strip_prefix(target).ok().map(|rel| (idx, target, rel)),
+            None => Some((idx, Path::new(""), path)),
+        })
+        .max_by_key(|(_, target, _)| target.components().count())
+        .map(|(idx, _, rel)| (idx, rel))
+}
+
+/// The next free number for a patch file in the recipe directory (patch files are
+/// called `0001-name.patch`, `0002-other.patch`, ...).
+fn next_patch_number(recipe_dir: &Path) -> miette::Result<usize> {
+    let mut next = 1;
+    for entry in fs::read_dir(recipe_dir).into_diagnostic()? {
+        let name = entry.into_diagnostic()?.file_name();
+        let name = name.to_string_lossy();
+        if !name.ends_with(".patch") {
+            continue;
+        }
+        if let Some(number) = name
+            .split_once('-')
+            .and_then(|(number, _)| number.parse::<usize>().ok())
+        {
+            next = next.max(number + 1);
+        }
+    }
+    Ok(next)
+}
+
+/// Diff the work directory against freshly fetched sources and write the changes into
+/// patch files next to the recipe.
+pub async fn generate_patch_from_args(
+    args: GeneratePatchOpts,
+    fancy_log_handler: LoggingOutputHandler,
+) -> miette::Result<()> {
+    let work_dir = dunce::canonicalize(&args.work_dir).into_diagnostic()?;
+    let mut exclude = GlobSetBuilder::new();
+    for pattern in &args.exclude {
+        exclude.add(Glob::new(pattern).into_diagnostic()?);
+    }
+    let exclude = exclude.build().into_diagnostic()?;
+
+    let tool_config = get_tool_config(&args.build, &fancy_log_handler)?;
+    let mut outputs = Vec::new();
+    for recipe_path in get_recipe_paths(&args.build)? {
+        outputs.extend(get_build_output(&args.build, &recipe_path, &tool_config).await?);
+    }
+    if let Some(output_name) = &args.output_name {
+        outputs.retain(|output| output.name().as_normalized() == output_name);
+    }
+    let Some(output) = outputs.into_iter().next() else {
+        return Err(miette::miette!("No matching output found in the recipe"));
+    };
+
+    let sources = output.recipe.sources();
+    if sources.is_empty() {
+        return Err(miette::miette!("The recipe has no sources to patch"));
+    }
+
+    // fetch the sources (with the existing patches) into a temporary directory
+    let pristine = tempfile::tempdir().into_diagnostic()?;
+    let directories = Directories {
+        work_dir: pristine.path().to_path_buf(),
+        ..output.build_configuration.directories.clone()
+    };
+    fetch_sources(
+        sources,
+        &directories,
+        output.build_configuration.host_platform,
+        &output.system_tools,
+        &tool_config,
+        false,
+    )
+    .await
+    .into_diagnostic()?;
+
+    let mut files = collect_files(pristine.path(), &exclude)?;
+    files.extend(collect_files(&work_dir, &exclude)?);
+
+    let mut diffs = vec![String::new(); sources.len()];
+    for path in &files {
+        let old = pristine.path().join(path);
+        let new = work_dir.join(path);
+        if old.is_file()
+            && new.is_file()
+            && fs::read(&old).into_diagnostic()? == fs::read(&new).into_diagnostic()?
+        {
+            continue;
+        }
+        let Some((idx, source_path)) = source_of(sources, path) else {
+            tracing::warn!(
+                "Skipping {}, it does not belong to any source",
+                path.display()
+            );
+            continue;
+        };
+        diffs[idx].push_str(&diff_file(&output.system_tools, &old, &new, source_path)?);
+    }
+
+    if diffs.iter().all(String::is_empty) {
+        println!("The work directory does not differ from the sources, no patch was written.");
+        return Ok(());
+    }
+
+    let recipe_dir = &output.build_configuration.directories.recipe_dir;
+    let mut number = next_patch_number(recipe_dir)?;
+    for (idx, diff) in diffs.iter().enumerate() {
+        if diff.is_empty() {
+            continue;
+        }
+        let file_name = format!("{:04}-{}.patch", number, args.name);
+        number += 1;
+        fs::write(recipe_dir.join(&file_name), diff).into_diagnostic()?;
+        println!("Wrote {}", recipe_dir.join(&file_name).display());
+
+        println!("\nAdd the patch to source {} of the recipe:\n", idx);
+        println!("  patches:");
+        for patch in sources[idx].patches() {
+            println!("    - {}", patch.path().display());
+        }
+        println!("    - {}\n", file_name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_script_files() {
+        assert!(is_build_script_file("conda_build.sh"));
+        assert!(is_build_script_file("build_env_configure.bat"));
+        assert!(is_build_script_file("conda_build_script_install.py"));
+        assert!(!is_build_script_file("conda_build_foo.sh"));
+        assert!(!is_build_script_file("build.sh"));
+    }
+
+    #[test]
+    fn test_patch_helpers() {
+        let diff = "diff --git a/tmp/pristine/src/foo.c b/build/work/src/foo.c\n--- a/tmp/pristine/src/foo.c\n+++ b/build/work/src/foo.c\n";
+        assert_eq!(
+            rewrite_diff_paths(
+                diff,
+                Path::new("/tmp/pristine/src/foo.c"),
+                Path::new("/build/work/src/foo.c"),
+                Path::new("src/foo.c")
+            ),
+            "diff --git a/src/foo.c b/src/foo.c\n--- a/src/foo.c\n+++ b/src/foo.c\n"
+        );
+
+        let recipe_dir = tempfile::tempdir().unwrap();
+        assert_eq!(next_patch_number(recipe_dir.path()).unwrap(), 1);
+        fs::write(recipe_dir.path().join("0003-fix.patch"), "").unwrap();
+        fs::write(recipe_dir.path().join("upstream.patch"), "").unwrap();
+        assert_eq!(next_patch_number(recipe_dir.path()).unwrap(), 4);
+    }
+}
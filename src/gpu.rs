@@ -0,0 +1,240 @@
+//! Helpers for variants that are built for a GPU stack (CUDA or ROCm).
+//!
+//! A variant is a CUDA variant if it sets `cuda_compiler_version`, and a ROCm variant if it
+//! sets `rocm_version`. The value `None` marks the CPU variant of a GPU matrix (this is what
+//! conda-forge uses), e.g.:
+//!
+//! ```yaml
+//! cuda_compiler_version:
+//!   - None
+//!   - "12.0"
+//! ```
+
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+use clap::ValueEnum;
+use rattler_conda_types::{
+    GenericVirtualPackage, MatchSpec, PackageName, ParseStrictness, Platform, Version,
+};
+use rattler_virtual_packages::VirtualPackage;
+use serde::{Deserialize, Serialize};
+
+/// The variant key that selects the CUDA version.
+pub const CUDA_VERSION_KEY: &str = "cuda_compiler_version";
+
+/// The variant key that selects the ROCm version.
+pub const ROCM_VERSION_KEY: &str = "rocm_version";
+
+/// The environment variable that mocks the CUDA driver (like in conda), e.g.
+/// `CONDA_OVERRIDE_CUDA=12.4`.
+pub const CUDA_OVERRIDE_ENV: &str = "CONDA_OVERRIDE_CUDA";
+
+/// The GPU stack that a variant is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuVariant {
+    /// A variant without a GPU stack
+    Cpu,
+    /// A variant that is built with CUDA
+    Cuda,
+    /// A variant that is built with ROCm
+    Rocm,
+}
+
+impl std::fmt::Display for GpuVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GpuVariant::Cpu => write!(f, "cpu"),
+            GpuVariant::Cuda => write!(f, "cuda"),
+            GpuVariant::Rocm => write!(f, "rocm"),
+        }
+    }
+}
+
+/// Get the value of a GPU version key, unless it is unset or `None`.
+fn gpu_version<'a>(variant: &'a BTreeMap<String, String>, key: &str) -> Option<&'a str> {
+    variant
+        .get(key)
+        .map(String::as_str)
+        .filter(|version| !version.is_empty() && *version != "None")
+}
+
+impl GpuVariant {
+    /// Determine the GPU stack of a variant.
+    pub fn from_variant(variant: &BTreeMap<String, String>) -> Self {
+        if gpu_version(variant, CUDA_VERSION_KEY).is_some() {
+            GpuVariant::Cuda
+        } else if gpu_version(variant, ROCM_VERSION_KEY).is_some() {
+            GpuVariant::Rocm
+        } else {
+            GpuVariant::Cpu
+        }
+    }
+
+    /// Whether a GPU of this stack is available on the current machine. In a container,
+    /// the GPU has to be passed through (e.g. with `docker run --gpus all`).
+    pub fn is_available(&self) -> bool {
+        match self {
+            GpuVariant::Cpu => true,
+            GpuVariant::Cuda => VirtualPackage::current()
+                .map(|packages| {
+                    packages
+                        .iter()
+                        .any(|package| matches!(package, VirtualPackage::Cuda(_)))
+                })
+                .unwrap_or(false),
+            // the ROCm kernel driver exposes this device
+            GpuVariant::Rocm => Path::new("/dev/kfd").exists(),
+        }
+    }
+}
+
+/// The mocked `__cuda` virtual package of `CONDA_OVERRIDE_CUDA`, if it is set to a
+/// version. Setting it is an explicit opt-in to run the tests of CUDA variants on a
+/// machine without a GPU.
+pub fn cuda_override() -> Option<GenericVirtualPackage> {
+    mock_cuda(&std::env::var(CUDA_OVERRIDE_ENV).ok()?)
+}
+
+/// A `__cuda` virtual package for the given driver version (empty for no driver).
+fn mock_cuda(version: &str) -> Option<GenericVirtualPackage> {
+    let version = Version::from_str(version.trim()).ok()?;
+    Some(GenericVirtualPackage {
+        name: PackageName::new_unchecked("__cuda"),
+        version,
+        build_string: "0".to_string(),
+    })
+}
+
+/// Check that the GPU keys of a variant are consistent and supported on the target platform.
+pub fn validate_variant(
+    variant: &BTreeMap<String, String>,
+    target_platform: Platform,
+) -> Result<(), String> {
+    let cuda = gpu_version(variant, CUDA_VERSION_KEY);
+    let rocm = gpu_version(variant, ROCM_VERSION_KEY);
+
+    if cuda.is_some() && rocm.is_some() {
+        return Err(format!(
+            "the variant sets both `{CUDA_VERSION_KEY}` and `{ROCM_VERSION_KEY}`, set one of them to `None`"
+        ));
+    }
+
+    for (key, version) in [(CUDA_VERSION_KEY, cuda), (ROCM_VERSION_KEY, rocm)] {
+        if let Some(version) = version {
+            if Version::from_str(version).is_err() {
+                return Err(format!("`{key}` is not a valid version: {version}"));
+            }
+        }
+    }
+
+    if cuda.is_some() && target_platform.is_osx() {
+        return Err(format!(
+            "CUDA is not available for {target_platform}, set `{CUDA_VERSION_KEY}` to `None` for this platform"
+        ));
+    }
+    if rocm.is_some() && !target_platform.is_linux() {
+        return Err(format!(
+            "ROCm is only available for linux, set `{ROCM_VERSION_KEY}` to `None` for {target_platform}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// The version of a GPU compiler (`compiler('cuda')` or `compiler('hip')`) that follows
+/// from the GPU stack of the variant.
+pub fn compiler_version(lang: &str, variant: &BTreeMap<String, String>) -> Option<String> {
+    match lang {
+        "hip" => variant.get(ROCM_VERSION_KEY).cloned(),
+        _ => None,
+    }
+}
+
+/// The default package name of a GPU compiler. The CUDA compiler is `nvcc` up to CUDA 11
+/// and `cuda-nvcc` (from the split CUDA packages) starting with CUDA 12.
+pub fn default_compiler(lang: &str, version: Option<&str>) -> Option<String> {
+    match lang {
+        "cuda" => {
+            let major = version?.split('.').next()?.parse::<u32>().ok()?;
+            Some(if major >= 12 { "cuda-nvcc" } else { "nvcc" }.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// The run constraint on the CUDA driver (`__cuda`) for packages of a CUDA variant: the
+/// driver has to support at least the major version that the package was built with.
+pub fn cuda_run_constraint(variant: &BTreeMap<String, String>) -> Option<MatchSpec> {
+    let version = gpu_version(variant, CUDA_VERSION_KEY)?;
+    let major = version.split('.').next()?;
+    MatchSpec::from_str(&format!("__cuda >={major}"), ParseStrictness::Strict).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn gpu_variants() {
+        assert_eq!(
+            GpuVariant::from_variant(&variant(&[(CUDA_VERSION_KEY, "None")])),
+            GpuVariant::Cpu
+        );
+        assert_eq!(
+            GpuVariant::from_variant(&variant(&[(CUDA_VERSION_KEY, "12.0")])),
+            GpuVariant::Cuda
+        );
+        assert_eq!(
+            GpuVariant::from_variant(&variant(&[(ROCM_VERSION_KEY, "6.0")])),
+            GpuVariant::Rocm
+        );
+
+        assert_eq!(default_compiler("cuda", Some("11.8")).unwrap(), "nvcc");
+        assert_eq!(default_compiler("cuda", Some("12.4")).unwrap(), "cuda-nvcc");
+        assert_eq!(
+            cuda_run_constraint(&variant(&[(CUDA_VERSION_KEY, "12.4")]))
+                .unwrap()
+                .to_string(),
+            "__cuda >=12"
+        );
+    }
+
+    #[test]
+    fn validate_gpu_variants() {
+        let both = variant(&[(CUDA_VERSION_KEY, "12.0"), (ROCM_VERSION_KEY, "6.0")]);
+        assert!(validate_variant(&both, Platform::Linux64).is_err());
+
+        let cuda = variant(&[(CUDA_VERSION_KEY, "12.0")]);
+        assert!(validate_variant(&cuda, Platform::Linux64).is_ok());
+        assert!(validate_variant(&cuda, Platform::Win64).is_ok());
+        assert!(validate_variant(&cuda, Platform::OsxArm64).is_err());
+
+        let rocm = variant(&[(ROCM_VERSION_KEY, "6.0")]);
+        assert!(validate_variant(&rocm, Platform::Win64).is_err());
+
+        let cpu = variant(&[(CUDA_VERSION_KEY, "None")]);
+        assert!(validate_variant(&cpu, Platform::OsxArm64).is_ok());
+    }
+
+    #[test]
+    fn mock_gpu_detection() {
+        let mocked = mock_cuda("12.4").unwrap();
+        assert_eq!(mocked.name.as_normalized(), "__cuda");
+        assert_eq!(mocked.version.to_string(), "12.4");
+
+        // the driver constraint of the package is satisfied by the mocked driver
+        let constraint = cuda_run_constraint(&variant(&[(CUDA_VERSION_KEY, "12.4")])).unwrap();
+        assert!(constraint.version.unwrap().matches(&mocked.version));
+
+        // like in conda, an empty override means that there is no driver
+        assert!(mock_cuda("").is_none());
+    }
+}
@@ -123,6 +123,35 @@ impl std::fmt::Display for HashInfo {
     }
 }
 
+/// The prefix of the build string for a variant key (e.g. `py` for `python`).
+fn key_prefix(variant_key: &str) -> Option<&'static str> {
+    match variant_key {
+        "numpy" => Some("np"),
+        "python" => Some("py"),
+        "perl" => Some("pl"),
+        "lua" => Some("lua"),
+        "r" => Some("r"),
+        _ => None,
+    }
+}
+
+/// Which inputs make up the hash of a variant (printed by `render --explain-hash`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HashExplanation {
+    /// The hash with its prefix, e.g. `py311h507f6e9`
+    pub hash: String,
+    /// The variant keys that the prefix is derived from (`noarch: python` packages always
+    /// have the prefix `py`)
+    pub prefix_keys: Vec<String>,
+    /// The variant keys and values that are hashed
+    pub inputs: BTreeMap<String, String>,
+    /// The exact string that is hashed (sha1)
+    pub hash_input: String,
+    /// The keys of the variant config that are not hashed, with the reason
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub excluded: BTreeMap<String, String>,
+}
+
 impl HashInfo {
     fn hash_prefix(variant: &BTreeMap<String, String>, noarch: &NoArchType) -> String {
         if noarch.is_python() {
@@ -132,13 +161,8 @@ impl HashInfo {
         let mut map: HashMap<String, String> = HashMap::new();
 
         for (variant_key, version_spec) in variant.iter() {
-            let prefix = match variant_key.as_str() {
-                "numpy" => "np",
-                "python" => "py",
-                "perl" => "pl",
-                "lua" => "lua",
-                "r" => "r",
-                _ => continue,
+            let Some(prefix) = key_prefix(variant_key) else {
+                continue;
             };
 
             let version_length = match prefix {
@@ -180,6 +204,47 @@ impl HashInfo {
             prefix: Self::hash_prefix(variant, noarch),
         }
     }
+
+    /// Explain the hash of a variant: the keys that are hashed and the keys that the prefix
+    /// is derived from. The keys of the variant config that are not part of the variant
+    /// are listed with a reason (the keys ignored with `build.variant.ignore_keys`, and the
+    /// keys that the recipe does not use).
+    pub fn explain(
+        variant: &BTreeMap<String, String>,
+        noarch: &NoArchType,
+        config_keys: &[String],
+        ignore_keys: &[String],
+    ) -> HashExplanation {
+        let prefix_keys = if noarch.is_python() {
+            vec![]
+        } else {
+            variant
+                .keys()
+                .filter(|key| key_prefix(key).is_some())
+                .cloned()
+                .collect()
+        };
+        let excluded = config_keys
+            .iter()
+            .filter(|key| !variant.contains_key(*key))
+            .map(|key| {
+                let reason = if ignore_keys.contains(key) {
+                    "ignored with `build.variant.ignore_keys`"
+                } else {
+                    "not used by the recipe"
+                };
+                (key.clone(), reason.to_string())
+            })
+            .collect();
+
+        HashExplanation {
+            hash: Self::from_variant(variant, noarch).to_string(),
+            prefix_keys,
+            inputs: variant.clone(),
+            hash_input: HashInput::from_variant(variant).as_str().to_string(),
+            excluded,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -209,4 +274,41 @@ mod tests {
         let build_string_from_output = HashInfo::from_variant(&input, &NoArchType::none());
         assert_eq!(build_string_from_output.to_string(), "py311h507f6e9");
     }
+
+    #[test]
+    fn test_explain_hash() {
+        let mut variant = BTreeMap::new();
+        variant.insert("python".to_string(), "3.11".to_string());
+        variant.insert("target_platform".to_string(), "linux-64".to_string());
+        let config_keys = ["python", "target_platform", "numpy", "openssl"].map(String::from);
+
+        let explanation = HashInfo::explain(
+            &variant,
+            &NoArchType::none(),
+            &config_keys,
+            &["openssl".to_string()],
+        );
+        assert_eq!(
+            explanation.hash,
+            HashInfo::from_variant(&variant, &NoArchType::none()).to_string()
+        );
+        assert!(explanation.hash.starts_with("py311h"));
+        assert_eq!(explanation.prefix_keys, ["python"]);
+        assert_eq!(
+            explanation.hash_input,
+            r#"{"python": "3.11", "target_platform": "linux-64"}"#
+        );
+        assert_eq!(
+            explanation.excluded.get("numpy").map(String::as_str),
+            Some("not used by the recipe")
+        );
+        assert_eq!(
+            explanation.excluded.get("openssl").map(String::as_str),
+            Some("ignored with `build.variant.ignore_keys`")
+        );
+
+        let noarch = HashInfo::explain(&variant, &NoArchType::python(), &[], &[]);
+        assert!(noarch.prefix_keys.is_empty());
+        assert!(noarch.hash.starts_with("pyh"));
+    }
 }
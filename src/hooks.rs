@@ -0,0 +1,244 @@
+//! Post-build hooks: commands and webhooks that run after an output was built and tested
+//! successfully, e.g. to scan, mirror or announce the package.
+//!
+//! Hooks are configured in the `rattler-build.toml` of the project or with
+//! `--post-build-cmd` and `--post-build-webhook`:
+//!
+//! ```toml
+//! [[post_build]]
+//! run = "mirror-package \"$RATTLER_BUILD_ARTIFACT\""
+//!
+//! [[post_build]]
+//! webhook = "https://hooks.example.com/package-built"
+//! outputs = ["libfoo*"]
+//! allow_failure = true
+//! ```
+//!
+//! A command is run through the shell with the path of the package as the last argument.
+//! The package and its metadata are also passed in `RATTLER_BUILD_*` environment variables,
+//! and `RATTLER_BUILD_METADATA` points to a JSON file with the same document that webhooks
+//! receive in the body of a `POST` request.
+
+use std::{ops::Not, path::Path, process::Command};
+
+use globset::Glob;
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::{metadata::Output, tool_configuration};
+
+/// A command or webhook that runs after an output was built successfully.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostBuildHook {
+    /// A shell command to run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+
+    /// A url that the metadata of the package is posted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<Url>,
+
+    /// Globs of the (normalized) names of the outputs to run the hook for, all outputs if
+    /// empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<String>,
+
+    /// Only warn when the hook fails instead of failing the build
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub allow_failure: bool,
+}
+
+impl PostBuildHook {
+    /// A hook that runs a shell command for all outputs.
+    pub fn command(run: impl Into<String>) -> Self {
+        Self {
+            run: Some(run.into()),
+            webhook: None,
+            outputs: Vec::new(),
+            allow_failure: false,
+        }
+    }
+
+    /// A hook that posts to a webhook for all outputs.
+    pub fn webhook(url: Url) -> Self {
+        Self {
+            run: None,
+            webhook: Some(url),
+            outputs: Vec::new(),
+            allow_failure: false,
+        }
+    }
+
+    /// Whether the hook runs for the output with the given name. Fails if the hook does
+    /// not have exactly one of `run` and `webhook` or if a glob is invalid.
+    pub fn applies_to(&self, output: &str) -> miette::Result<bool> {
+        if self.run.is_some() == self.webhook.is_some() {
+            miette::bail!("a post-build hook needs either `run` or `webhook`");
+        }
+        if self.outputs.is_empty() {
+            return Ok(true);
+        }
+        for pattern in &self.outputs {
+            let glob = Glob::new(pattern).map_err(|e| {
+                miette::miette!(
+                    "Invalid output glob `{}` in post-build hook: {}",
+                    pattern,
+                    e
+                )
+            })?;
+            if glob.compile_matcher().is_match(output) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn description(&self) -> String {
+        match (&self.run, &self.webhook) {
+            (Some(run), _) => format!("`{}`", run),
+            (_, Some(webhook)) => webhook.to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// A shell command that runs `cmd` with the path of the artifact appended as the last
+/// argument (and set in `RATTLER_BUILD_ARTIFACT`).
+pub(crate) fn shell_command(cmd: &str, artifact: &Path) -> Command {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command
+            .arg("/C")
+            .arg(format!("{} \"{}\"", cmd, artifact.display()));
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{} \"$@\"", cmd))
+            .arg("sh")
+            .arg(artifact);
+        command
+    };
+    command.env("RATTLER_BUILD_ARTIFACT", artifact);
+    command
+}
+
+/// The metadata of a built package that is passed to the hooks.
+pub fn hook_payload(output: &Output, package: &Path) -> serde_json::Value {
+    let summary = output.build_summary.lock().unwrap();
+    json!({
+        "package": package,
+        "name": output.name().as_normalized(),
+        "version": output.version(),
+        "build_string": output.build_string(),
+        "subdir": output.build_configuration.target_platform.to_string(),
+        "variant": output.build_configuration.variant,
+        "provenance": summary.provenance,
+        "signatures": summary.signatures,
+        "recipe": output.recipe,
+    })
+}
+
+fn run_command(run: &str, output: &Output, package: &Path, metadata: &Path) -> miette::Result<()> {
+    let result = shell_command(run, package)
+        .env("RATTLER_BUILD_METADATA", metadata)
+        .env("RATTLER_BUILD_PACKAGE_NAME", output.name().as_normalized())
+        .env("RATTLER_BUILD_PACKAGE_VERSION", output.version())
+        .env(
+            "RATTLER_BUILD_BUILD_STRING",
+            output.build_string().unwrap_or_default(),
+        )
+        .env(
+            "RATTLER_BUILD_TARGET_PLATFORM",
+            output.build_configuration.target_platform.to_string(),
+        )
+        .output()
+        .into_diagnostic()?;
+
+    for line in String::from_utf8_lossy(&result.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&result.stderr).lines())
+    {
+        tracing::info!("{}", line);
+    }
+    if !result.status.success() {
+        miette::bail!("the command exited with {}", result.status);
+    }
+    Ok(())
+}
+
+async fn post_webhook(
+    webhook: &Url,
+    payload: &serde_json::Value,
+    tool_configuration: &tool_configuration::Configuration,
+) -> miette::Result<()> {
+    tool_configuration
+        .client
+        .post(webhook.clone())
+        .json(payload)
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?;
+    Ok(())
+}
+
+/// Run the post-build hooks of an output for the package that was built.
+pub async fn run_post_build_hooks(
+    output: &Output,
+    package: &Path,
+    tool_configuration: &tool_configuration::Configuration,
+) -> miette::Result<()> {
+    let hooks = &output.build_configuration.post_build;
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let payload = hook_payload(output, package);
+    let metadata_dir = tempfile::tempdir().into_diagnostic()?;
+    let metadata = metadata_dir.path().join("metadata.json");
+    fs_err::write(
+        &metadata,
+        serde_json::to_vec_pretty(&payload).into_diagnostic()?,
+    )
+    .into_diagnostic()?;
+
+    for hook in hooks {
+        tracing::info!("Running post-build hook {}", hook.description());
+        let result = match (&hook.run, &hook.webhook) {
+            (Some(run), _) => run_command(run, output, package, &metadata),
+            (_, Some(webhook)) => post_webhook(webhook, &payload, tool_configuration).await,
+            _ => Ok(()),
+        }
+        .wrap_err_with(|| format!("post-build hook {} failed", hook.description()));
+
+        match result {
+            Err(err) if hook.allow_failure => tracing::warn!("{:?}", err),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostBuildHook;
+
+    #[test]
+    fn test_applies_to() {
+        let hook: PostBuildHook = toml::from_str(
+            "webhook = \"https://hooks.example.com/built\"\noutputs = [\"libfoo*\"]",
+        )
+        .unwrap();
+        assert!(hook.applies_to("libfoo-devel").unwrap());
+        assert!(!hook.applies_to("bar").unwrap());
+        assert!(PostBuildHook::command("true").applies_to("bar").unwrap());
+
+        let invalid: PostBuildHook = toml::from_str("outputs = [\"*\"]").unwrap();
+        assert!(invalid.applies_to("bar").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! The `index` subcommand to turn a directory of packages (e.g. the output directory of
+//! rattler-build) into a channel that can be used by pixi, conda or mamba.
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use fs_err as fs;
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+
+use crate::cache_lock::CacheLock;
+
+/// The zstd compression level of `repodata.json.zst` (the level that conda-index uses).
+pub(crate) const ZSTD_LEVEL: i32 = 16;
+
+/// Options for the `index` command.
+#[derive(Parser)]
+pub struct IndexOpts {
+    /// The directory with the packages, in one subdirectory per platform (e.g.
+    /// `linux-64/` and `noarch/`)
+    #[arg(default_value = "./output")]
+    pub output_dir: PathBuf,
+
+    /// Only index the subdirectory of this platform (and `noarch`)
+    #[arg(long)]
+    pub target_platform: Option<Platform>,
+
+    /// Do not write a zstd compressed `repodata.json.zst` next to every `repodata.json`
+    #[arg(long)]
+    pub no_zstd: bool,
+}
+
+/// Write a zstd compressed copy of the `repodata.json` of a subdirectory.
+fn write_compressed_repodata(subdir: &Path) -> std::io::Result<()> {
+    let repodata = fs::read(subdir.join("repodata.json"))?;
+    let compressed = zstd::stream::encode_all(repodata.as_slice(), ZSTD_LEVEL)?;
+    fs::write(subdir.join("repodata.json.zst"), compressed)
+}
+
+/// The subdirectories whose repodata is written when indexing for `target_platform`:
+/// all of them, or the target platform and `noarch` if it has no repodata yet.
+fn indexed_subdirs(
+    channel_dir: &Path,
+    target_platform: Option<Platform>,
+) -> std::io::Result<Vec<String>> {
+    let mut subdirs = match target_platform {
+        Some(platform) => {
+            let mut subdirs = vec![platform.to_string()];
+            let noarch = Platform::NoArch.as_str();
+            if !channel_dir.join(noarch).join("repodata.json").exists() {
+                subdirs.push(noarch.to_string());
+            }
+            subdirs
+        }
+        None => {
+            let mut subdirs = Vec::new();
+            for entry in fs::read_dir(channel_dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    subdirs.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+            subdirs
+        }
+    };
+    // always lock in the same order, so that two processes cannot wait for each other
+    subdirs.sort();
+    subdirs.dedup();
+    Ok(subdirs)
+}
+
+/// Index a channel directory: write the `repodata.json` (and `repodata.json.zst`) of
+/// every platform subdirectory, or only of `target_platform` and `noarch`. Without
+/// `zstd`, the `repodata.json.zst` of the indexed subdirectories is removed, so that
+/// clients never read an outdated one.
+///
+/// Only the indexed subdirectories are locked, so that outputs for other platforms
+/// that share the channel directory are indexed at the same time. This blocks while
+/// another process indexes the same subdirectory.
+pub fn index_channel(
+    channel_dir: &Path,
+    target_platform: Option<Platform>,
+    zstd: bool,
+) -> std::io::Result<()> {
+    // clients always request `noarch`, so it has to exist even if it is empty
+    fs::create_dir_all(channel_dir.join(Platform::NoArch.as_str()))?;
+    if let Some(platform) = target_platform {
+        fs::create_dir_all(channel_dir.join(platform.as_str()))?;
+    }
+
+    let subdirs = indexed_subdirs(channel_dir, target_platform)?;
+    let _locks = subdirs
+        .iter()
+        .map(|subdir| {
+            CacheLock::acquire(
+                &channel_dir.join(format!(".rattler-build-index-{}.lock", subdir)),
+                &format!("index of {}", subdir),
+            )
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    rattler_index::index(channel_dir, target_platform.as_ref())?;
+
+    for subdir in &subdirs {
+        let subdir = channel_dir.join(subdir);
+        if !subdir.join("repodata.json").is_file() {
+            continue;
+        }
+        if zstd {
+            write_compressed_repodata(&subdir)?;
+        } else if subdir.join("repodata.json.zst").exists() {
+            fs::remove_file(subdir.join("repodata.json.zst"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a package from a channel directory and index its subdirectory again, so that
+/// the package is no longer listed in the repodata (e.g. when it was rejected after it
+/// was written).
+pub fn remove_package(channel_dir: &Path, package: &Path) -> std::io::Result<()> {
+    let subdir = package
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(|subdir| subdir.to_str())
+        .and_then(|subdir| subdir.parse::<Platform>().ok())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not in a platform subdirectory", package.display()),
+            )
+        })?;
+
+    if package.exists() {
+        fs::remove_file(package)?;
+    }
+    index_channel(channel_dir, Some(subdir), true)
+}
+
+/// Index the output directory.
+pub fn index_from_args(args: IndexOpts) -> miette::Result<()> {
+    if !args.output_dir.is_dir() {
+        miette::bail!("{} is not a directory", args.output_dir.display());
+    }
+    index_channel(&args.output_dir, args.target_platform, !args.no_zstd).into_diagnostic()?;
+
+    let channel = dunce::canonicalize(&args.output_dir).into_diagnostic()?;
+    tracing::info!(
+        "Indexed {}, use it as a channel with `-c {}`",
+        args.output_dir.display(),
+        channel.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_compressed_repodata() {
+        let dir = tempfile::tempdir().unwrap();
+        let repodata = r#"{"info":{"subdir":"noarch"},"packages":{},"packages.conda":{}}"#;
+        fs::write(dir.path().join("repodata.json"), repodata).unwrap();
+
+        write_compressed_repodata(dir.path()).unwrap();
+        let compressed = fs::read(dir.path().join("repodata.json.zst")).unwrap();
+        let decompressed = zstd::stream::decode_all(compressed.as_slice()).unwrap();
+        assert_eq!(decompressed, repodata.as_bytes());
+    }
+
+    #[test]
+    fn test_index_channel_zstd() {
+        let dir = tempfile::tempdir().unwrap();
+        let noarch = dir.path().join("noarch");
+
+        index_channel(dir.path(), None, true).unwrap();
+        assert!(noarch.join("repodata.json").is_file());
+        assert!(noarch.join("repodata.json.zst").is_file());
+
+        // without zstd, the outdated compressed repodata is removed
+        index_channel(dir.path(), None, false).unwrap();
+        assert!(noarch.join("repodata.json").is_file());
+        assert!(!noarch.join("repodata.json.zst").exists());
+    }
+
+    #[test]
+    fn test_remove_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let noarch = dir.path().join("noarch");
+        fs::create_dir_all(&noarch).unwrap();
+
+        // a minimal package with only an `info/index.json`
+        let contents = tempfile::tempdir().unwrap();
+        fs::create_dir_all(contents.path().join("info")).unwrap();
+        fs::write(
+            contents.path().join("info/index.json"),
+            r#"{"name":"foo","version":"1.0","build":"0","build_number":0,"subdir":"noarch","depends":[]}"#,
+        )
+        .unwrap();
+        let package = noarch.join("foo-1.0-0.conda");
+        rattler_package_streaming::write::write_conda_package(
+            fs::File::create(&package).unwrap(),
+            contents.path(),
+            &[contents.path().join("info/index.json")],
+            rattler_package_streaming::write::CompressionLevel::Default,
+            None,
+            "foo-1.0-0",
+            None,
+            None,
+        )
+        .unwrap();
+
+        let listed = || {
+            let repodata = fs::read_to_string(noarch.join("repodata.json")).unwrap();
+            repodata.contains("foo-1.0-0.conda")
+        };
+        index_channel(dir.path(), None, true).unwrap();
+        assert!(listed());
+
+        remove_package(dir.path(), &package).unwrap();
+        assert!(!package.exists());
+        assert!(!listed());
+    }
+}
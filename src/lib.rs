@@ -2,27 +2,54 @@
 
 //! rattler-build library.
 
+pub mod apply_patches;
 pub mod build;
+pub mod build_cache;
+pub mod build_stats;
+pub mod cache;
+pub mod cache_lock;
+pub mod changed;
 pub mod console_utils;
+pub mod convert;
+#[cfg(unix)]
+pub mod daemon;
+pub mod dry_run;
+pub mod emulation;
+pub mod generate_patch;
+pub mod gpu;
+pub mod index;
+pub mod lint;
+pub mod lockfile;
+pub mod lsp;
 pub mod metadata;
+pub mod metapackage;
+pub mod mirror;
 pub mod opt;
 pub mod package_test;
 pub mod packaging;
+pub mod project_config;
+pub mod provenance;
 pub mod recipe;
 pub mod render;
+pub mod reporter;
+pub mod runner;
+pub mod sbom;
 pub mod script;
 pub mod selectors;
+pub mod signing;
 pub mod source;
 pub mod system_tools;
 pub mod tool_configuration;
 #[cfg(feature = "tui")]
 pub mod tui;
+pub mod update_checksums;
 pub mod used_variables;
 pub mod utils;
 pub mod variant_config;
 
 mod env_vars;
 pub mod hash;
+pub mod hooks;
 mod linux;
 mod macos;
 mod post_process;
@@ -35,33 +62,55 @@ mod windows;
 use build::skip_existing;
 use dunce::canonicalize;
 use fs_err as fs;
+use futures::{stream::FuturesUnordered, StreamExt};
 use metadata::Output;
 use miette::{IntoDiagnostic, WrapErr};
-use petgraph::{algo::toposort, graph::DiGraph, visit::DfsPostOrder};
-use rattler_conda_types::{package::ArchiveType, Channel, ChannelConfig, Platform};
+use petgraph::{
+    algo::toposort,
+    graph::{DiGraph, NodeIndex},
+    visit::DfsPostOrder,
+};
+use rattler_conda_types::{
+    package::{ArchiveIdentifier, ArchiveType, IndexJson},
+    Channel, ChannelConfig, MatchSpec, PackageName, ParseStrictness, Platform,
+};
+use rattler_networking::Authentication;
 use recipe::parser::Dependency;
+use serde::Serialize;
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     env::current_dir,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
-use tool_configuration::Configuration;
+use tool_configuration::{Configuration, InputRequired, NetworkSettings, TestPolicy};
+use url::Url;
 
 use {
     build::run_build,
     console_utils::LoggingOutputHandler,
-    hash::HashInfo,
+    gpu::GpuVariant,
+    hash::{HashExplanation, HashInfo},
+    hooks::PostBuildHook,
     metadata::{
-        BuildConfiguration, BuildSummary, Directories, PackageIdentifier, PackagingSettings,
+        log_skipped_outputs, BuildConfiguration, BuildSummary, Directories, PackageIdentifier,
+        PackagingSettings, SkippedOutput,
     },
+    mirror::{Mirror, OfflineRequests},
     opt::*,
     package_test::TestConfiguration,
+    project_config::ProjectConfig,
     recipe::{
+        custom_yaml::{Node, ScalarNode, SequenceNodeInternal},
+        error::PartialParsingError,
         parser::{find_outputs_from_src, Recipe},
         ParsingError,
     },
+    render::resolved_dependencies::{FinalizedDependencies, RunExportExplanation},
     selectors::SelectorConfig,
+    signing::SigningConfig,
+    source::extract::ExtractionPolicy,
     system_tools::SystemTools,
     variant_config::{ParseErrors, VariantConfig},
 };
@@ -110,24 +159,212 @@ pub fn get_recipe_path(path: &Path) -> miette::Result<PathBuf> {
     Ok(recipe_path)
 }
 
+/// Returns the paths of the recipes passed with `--recipe` and of all recipes found
+/// in `--recipe-dir`.
+pub fn get_recipe_paths(args: &BuildOpts) -> miette::Result<Vec<PathBuf>> {
+    let mut recipe_paths = Vec::new();
+    for recipe_path in &args.recipe {
+        recipe_paths.push(get_recipe_path(recipe_path)?);
+    }
+    if let Some(recipe_dir) = &args.recipe_dir {
+        for entry in ignore::Walk::new(recipe_dir) {
+            let entry = entry.into_diagnostic()?;
+            if entry.path().is_dir() {
+                if let Ok(recipe_path) = get_recipe_path(entry.path()) {
+                    recipe_paths.push(recipe_path);
+                }
+            }
+        }
+    }
+    Ok(recipe_paths)
+}
+
+/// Returns the network settings for the `--proxy`, `--ca-cert`, `--channel-mirror` and
+/// `--offline` options.
+pub fn network_settings(args: &CommonOpts) -> miette::Result<NetworkSettings> {
+    let channel_config = ChannelConfig::default_with_root_dir(current_dir().into_diagnostic()?);
+    Ok(NetworkSettings {
+        proxy: args.proxy.clone(),
+        // `git` runs in other directories, so the path has to be absolute
+        ca_cert: args
+            .ca_cert
+            .as_ref()
+            .map(|path| {
+                canonicalize(path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("CA certificate {} not found", path.display()))
+            })
+            .transpose()?,
+        auth_file: args.auth_file.clone(),
+        non_interactive: args.non_interactive,
+        mirrors: args
+            .channel_mirror
+            .iter()
+            .map(|mirror| Mirror::parse(mirror, &channel_config))
+            .collect::<Result<_, _>>()
+            .map_err(|e| miette::miette!("Invalid `--channel-mirror`: {}", e))?,
+        offline: args.offline,
+        offline_requests: OfflineRequests::default(),
+    })
+}
+
+/// Discover the project configuration of the recipes (or of the current directory) and
+/// use its `[build]` defaults for the options that are not given on the command line.
+/// The defaults apply to `build`, `render` and `debug`.
+pub fn apply_project_defaults(
+    args: &mut BuildOpts,
+) -> miette::Result<Option<(PathBuf, ProjectConfig)>> {
+    let start = args
+        .recipe_dir
+        .clone()
+        .or_else(|| args.recipe.first().cloned())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let start = canonicalize(&start).unwrap_or(start);
+    let start = if start.is_file() {
+        start.parent().map(Path::to_path_buf).unwrap_or_default()
+    } else {
+        start
+    };
+    let Some((config_path, config)) = ProjectConfig::discover(&start)? else {
+        return Ok(None);
+    };
+    let config_dir = config_path
+        .parent()
+        .expect("the config file is in a directory");
+    let defaults = &config.build;
+
+    if args.channel.is_none() {
+        args.channel = defaults.channels.clone();
+    }
+    if args.common.output_dir.is_none() {
+        args.common.output_dir = defaults.output_dir.as_ref().map(|dir| config_dir.join(dir));
+    }
+    if !defaults.variant_config.is_empty() {
+        args.variant_config = defaults
+            .variant_config
+            .iter()
+            .map(|path| config_dir.join(path))
+            .chain(args.variant_config.drain(..))
+            .collect();
+    }
+    if args.upload_to.is_none() {
+        args.upload_to = defaults
+            .upload_to
+            .as_deref()
+            .map(str::parse::<UploadTo>)
+            .transpose()
+            .map_err(|e| {
+                miette::miette!(
+                    "Invalid `build.upload_to` in {}: {}",
+                    config_path.display(),
+                    e
+                )
+            })?;
+    }
+    Ok(Some((config_path, config)))
+}
+
+/// Run the build in the container of the runner that the project configuration has for
+/// the target platform, if there is one. Returns whether the build was run.
+pub fn build_in_runner(
+    args: &BuildOpts,
+    project_config: Option<&(PathBuf, ProjectConfig)>,
+) -> miette::Result<bool> {
+    let Some((config_path, config)) = project_config else {
+        return Ok(false);
+    };
+    let Some(runner) = config.runner(args.target_platform.as_str()) else {
+        return Ok(false);
+    };
+    let output_dir = args
+        .common
+        .output_dir
+        .clone()
+        .unwrap_or(current_dir().into_diagnostic()?.join("output"));
+    let inputs = runner::HostInputs {
+        paths: args
+            .recipe
+            .iter()
+            .chain(&args.recipe_dir)
+            .chain(&args.variant_config)
+            .cloned()
+            .collect(),
+        upload: args.upload_to.is_some(),
+        auth_file: args.common.auth_file.clone(),
+    };
+    runner::run_in_container(
+        runner,
+        args.target_platform,
+        config_path
+            .parent()
+            .expect("the config file is in a directory"),
+        &output_dir,
+        &inputs,
+    )?;
+    Ok(true)
+}
+
 /// Returns the tool configuration.
 pub fn get_tool_config(
     args: &BuildOpts,
     fancy_log_handler: &LoggingOutputHandler,
 ) -> miette::Result<Configuration> {
-    let client =
-        tool_configuration::reqwest_client_from_auth_storage(args.common.auth_file.clone())
-            .into_diagnostic()?;
+    if args.common.non_interactive && (args.debug_shell || args.tui) {
+        return Err(InputRequired {
+            input: "a terminal".to_string(),
+            reason: "`--debug-shell` and `--tui` can not be used with `--non-interactive`"
+                .to_string(),
+            help: "remove `--debug-shell` / `--tui` or `--non-interactive`".to_string(),
+        }
+        .into());
+    }
+
+    let network = network_settings(&args.common)?;
+    let client = tool_configuration::reqwest_client_from_auth_storage(
+        args.common.auth_file.clone(),
+        &network,
+    )
+    .into_diagnostic()?;
 
     Ok(Configuration {
+        repodata_gateway: tool_configuration::repodata_gateway(
+            client.clone(),
+            &network,
+            &tool_configuration::rattler_cache_dir(args.common.cache_dir.as_deref()),
+        ),
+        cache_dir: args.common.cache_dir.clone(),
         client,
         fancy_log_handler: fancy_log_handler.clone(),
-        no_clean: args.keep_build,
+        no_clean: args.keep_build || args.incremental || args.dev,
         no_test: args.no_test,
+        test_policy: args.test_policy,
+        log_sink: None,
         use_zstd: args.common.use_zstd,
         use_bz2: args.common.use_bz2,
-        render_only: args.render_only,
+        render_only: args.render_only || args.dry_run,
         skip_existing: args.skip_existing,
+        debug_shell: args.debug_shell,
+        incremental: args.incremental,
+        from_phase: args.from_phase,
+        jobs: args.jobs,
+        scan_cmd: args.scan_cmd.clone(),
+        stats_file: args.stats_file.clone(),
+        signing: args.sign.map(|method| SigningConfig {
+            method,
+            key: args.signing_key.clone(),
+        }),
+        extraction_policy: if args.allow_unsafe_extraction {
+            ExtractionPolicy::Permissive
+        } else {
+            ExtractionPolicy::Strict
+        },
+        network,
+        source_cache: args.source_cache_dir.clone(),
+        private_cache_dir: if args.no_shared_cache {
+            Some(Arc::new(tempfile::tempdir().into_diagnostic()?))
+        } else {
+            None
+        },
         ..Configuration::default()
     })
 }
@@ -137,6 +374,259 @@ pub async fn get_build_output(
     args: &BuildOpts,
     recipe_path: &Path,
     tool_config: &Configuration,
+) -> miette::Result<Vec<Output>> {
+    let variant_config = get_variant_config(args, tool_config.environment.clone())?;
+    get_build_output_with_variant_config(args, recipe_path, tool_config, &variant_config).await
+}
+
+/// Returns the selector config that is used to find the variants of a recipe.
+fn variant_selector_config(
+    args: &BuildOpts,
+    environment: Option<Arc<HashMap<String, String>>>,
+) -> SelectorConfig {
+    SelectorConfig {
+        // We ignore noarch here
+        target_platform: args.target_platform,
+        host_platform: args.target_platform,
+        hash: None,
+        build_platform: args.build_platform(),
+        variant: BTreeMap::new(),
+        experimental: args.common.experimental,
+        // allow undefined while finding the variants
+        allow_undefined: true,
+        lenient_skip: args.lenient_skip,
+        subpackages: BTreeMap::new(),
+        environment,
+    }
+}
+
+/// Whether the recipe was extracted from a package (`info/recipe`), which holds the
+/// rendered recipe next to the recipe.
+fn is_extracted_recipe(recipe_path: &Path) -> bool {
+    let recipe_dir = if recipe_path.is_dir() {
+        Some(recipe_path)
+    } else {
+        recipe_path.parent()
+    };
+    recipe_dir.is_some_and(|dir| dir.join("rendered_recipe.yaml").is_file())
+}
+
+/// Returns the variant config files of a build: the variant config of the user (unless
+/// `--no-user-variant-config` is given or a recipe is rebuilt from a package), followed
+/// by the files passed with `-m`. Keys of later files take precedence.
+pub fn variant_config_files(args: &BuildOpts) -> Vec<PathBuf> {
+    let user_config = if args.no_user_variant_config {
+        None
+    } else if args.recipe.iter().any(|path| is_extracted_recipe(path)) {
+        tracing::info!(
+            "Not loading the variant config of the user to rebuild a recipe from a package"
+        );
+        None
+    } else {
+        variant_config::user_variant_config_file()
+    };
+    user_config
+        .into_iter()
+        .chain(args.variant_config.iter().cloned())
+        .collect()
+}
+
+/// Loads the variant config files of the user and the ones passed on the command line.
+/// The `os.environ` lookups of `conda_build_config.yaml` files read from `environment`,
+/// or from the environment of the process if not given.
+pub fn get_variant_config(
+    args: &BuildOpts,
+    environment: Option<Arc<HashMap<String, String>>>,
+) -> miette::Result<VariantConfig> {
+    VariantConfig::from_files(
+        &variant_config_files(args),
+        &variant_selector_config(args, environment),
+    )
+    .into_diagnostic()
+}
+
+/// The build profiles that the recipe requests in `build.profiles` of an output node.
+/// The profiles are read before the recipe is rendered, so they have to be plain names.
+fn recipe_profiles(output: &Node) -> miette::Result<Vec<String>> {
+    let Some(profiles) = output
+        .as_mapping()
+        .and_then(|output| output.get("build"))
+        .and_then(|build| build.as_mapping())
+        .and_then(|build| build.get("profiles"))
+    else {
+        return Ok(vec![]);
+    };
+    let invalid = || {
+        miette::miette!(
+            "`build.profiles` must be a list of profile names (templates and selectors are not supported)"
+        )
+    };
+    let profiles = profiles.as_sequence().ok_or_else(invalid)?;
+    profiles
+        .iter()
+        .map(|profile| match profile {
+            SequenceNodeInternal::Simple(Node::Scalar(name)) if !name.contains("${{") => {
+                Ok(name.as_str().to_string())
+            }
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// The package name of a raw requirement (e.g. `cmake` for `cmake >=3.20`).
+fn raw_requirement_name(requirement: &SequenceNodeInternal) -> Option<&str> {
+    match requirement {
+        SequenceNodeInternal::Simple(Node::Scalar(spec)) => spec.split_whitespace().next(),
+        _ => None,
+    }
+}
+
+/// Append the packages of the build profiles that are requested on the command line
+/// or in the recipe to the build requirements of an output node. This happens before
+/// the variants are discovered, so the packages are part of the variant and the hash.
+fn add_build_profiles(
+    output: &mut Node,
+    cli_profiles: &[String],
+    project_config: Option<&(PathBuf, ProjectConfig)>,
+) -> miette::Result<()> {
+    let profiles = cli_profiles
+        .iter()
+        .cloned()
+        .chain(recipe_profiles(output)?)
+        .collect::<Vec<_>>();
+    if profiles.is_empty() {
+        return Ok(());
+    }
+
+    let Some((config_path, project_config)) = project_config else {
+        return Err(miette::miette!(
+            "The build profiles {} are used, but no `{}` was found in the recipe directory or its parents",
+            profiles.join(", "),
+            project_config::PROJECT_CONFIG_FILE
+        ));
+    };
+
+    let Node::Mapping(output) = output else {
+        return Ok(());
+    };
+    let requirements = output
+        .entry(ScalarNode::from("requirements"))
+        .or_insert_with(|| Node::from(indexmap::IndexMap::new()));
+    if !matches!(requirements, Node::Mapping(_)) {
+        *requirements = Node::from(indexmap::IndexMap::new());
+    }
+    let Node::Mapping(requirements) = requirements else {
+        unreachable!("requirements is a mapping")
+    };
+    let build = requirements
+        .entry(ScalarNode::from("build"))
+        .or_insert_with(|| Node::from(Vec::<SequenceNodeInternal>::new()));
+    if !matches!(build, Node::Sequence(_)) {
+        *build = Node::from(Vec::<SequenceNodeInternal>::new());
+    }
+    let Node::Sequence(build) = build else {
+        unreachable!("build requirements are a sequence")
+    };
+
+    for profile in profiles {
+        let specs = project_config
+            .profile_specs(&profile)
+            .wrap_err_with(|| format!("in {}", config_path.display()))?;
+        for spec in specs {
+            let Some(name) = &spec.name else {
+                continue;
+            };
+            // requirements of the recipe take precedence over the profile
+            let already_required = build
+                .iter()
+                .any(|dep| raw_requirement_name(dep) == Some(name.as_normalized()));
+            if !already_required {
+                build.push(SequenceNodeInternal::Simple(Node::from(spec.to_string())));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The post-build hooks of an output: the ones from the project configuration that apply
+/// to it, followed by the ones from the command line.
+fn post_build_hooks(
+    args: &BuildOpts,
+    recipe: &Recipe,
+    project_config: Option<&(PathBuf, ProjectConfig)>,
+) -> miette::Result<Vec<PostBuildHook>> {
+    let mut hooks = match project_config {
+        Some((config_path, project_config)) => project_config
+            .post_build_hooks(recipe.package().name().as_normalized())
+            .wrap_err_with(|| format!("in {}", config_path.display()))?,
+        None => Vec::new(),
+    };
+    hooks.extend(args.post_build_cmd.iter().map(PostBuildHook::command));
+    hooks.extend(
+        args.post_build_webhook
+            .iter()
+            .cloned()
+            .map(PostBuildHook::webhook),
+    );
+    Ok(hooks)
+}
+
+/// Returns the channel url that the output is uploaded to after the build: the url of
+/// `--upload-to`, or with `--upload-to auto` the `build.upload_to` of the recipe or the
+/// route of the project configuration. The url is checked to be handled by an upload
+/// backend, so a wrong destination is reported before anything is built.
+fn upload_destination(
+    upload_to: Option<&UploadTo>,
+    recipe: &Recipe,
+    project_config: Option<&(PathBuf, ProjectConfig)>,
+) -> miette::Result<Option<Url>> {
+    let name = recipe.package().name().as_normalized();
+    let url = match upload_to {
+        None => return Ok(None),
+        Some(UploadTo::Url(url)) => url.clone(),
+        Some(UploadTo::Auto) => {
+            let from_project = match project_config {
+                Some((config_path, project_config)) => project_config
+                    .upload_destination(name)
+                    .wrap_err_with(|| format!("in {}", config_path.display()))?,
+                None => None,
+            };
+            match recipe.build().upload_to().or(from_project) {
+                Some(url) => url.clone(),
+                None => {
+                    return Err(miette::miette!(
+                        help = format!(
+                        "set `build.upload_to` in the recipe or add an `[upload]` table to `{}`",
+                        project_config::PROJECT_CONFIG_FILE
+                    ),
+                        "No upload destination for the output `{}` (--upload-to auto)",
+                        name
+                    ))
+                }
+            }
+        }
+    };
+
+    if upload::UploadBackendRegistry::with_default_backends()
+        .resolve(&url)
+        .is_none()
+    {
+        return Err(miette::miette!(
+            help = "prefix the scheme with the server type, e.g. `quetz+https://...`",
+            "No upload backend handles {} (the upload destination of `{}`)",
+            url,
+            name
+        ));
+    }
+    Ok(Some(url))
+}
+
+/// Returns the output for the build, using an already loaded variant config.
+pub async fn get_build_output_with_variant_config(
+    args: &BuildOpts,
+    recipe_path: &Path,
+    tool_config: &Configuration,
+    variant_config: &VariantConfig,
 ) -> miette::Result<Vec<Output>> {
     let output_dir = args
         .common
@@ -155,36 +645,108 @@ pub async fn get_build_output(
 
     let recipe_text = fs::read_to_string(recipe_path).into_diagnostic()?;
 
-    if args.target_platform == Platform::NoArch || args.build_platform == Platform::NoArch {
+    // checked before the recipe is parsed, because a recipe for another version of
+    // rattler-build might not parse with this one
+    if let Err(constraint) = Recipe::check_build_tool_constraint_from_src(&recipe_text) {
+        let message = format!(
+            "{} requires rattler-build {}, but this is rattler-build {}",
+            recipe_path.display(),
+            constraint,
+            env!("CARGO_PKG_VERSION")
+        );
+        if !args.ignore_build_tool_constraint {
+            return Err(miette::miette!(
+                help = "install a matching version of rattler-build or pass --ignore-build-tool-constraint",
+                "{}",
+                message
+            ));
+        }
+        tracing::warn!(
+            "{} (ignored because of --ignore-build-tool-constraint)",
+            message
+        );
+    }
+
+    if args.target_platform == Platform::NoArch || args.build_platform() == Platform::NoArch {
         return Err(miette::miette!(
             "target-platform / build-platform cannot be `noarch` - that should be defined in the recipe"
         ));
     }
 
-    let selector_config = SelectorConfig {
-        // We ignore noarch here
-        target_platform: args.target_platform,
-        host_platform: args.target_platform,
-        hash: None,
-        build_platform: args.build_platform,
-        variant: BTreeMap::new(),
-        experimental: args.common.experimental,
-        // allow undefined while finding the variants
-        allow_undefined: true,
+    let selector_config = variant_selector_config(args, tool_config.environment.clone());
+
+    // the emulator is only needed to run the build scripts, not to render the recipe
+    let emulation = if args.emulate && !tool_config.render_only {
+        emulation::setup(Platform::current(), args.target_platform)?
+    } else {
+        None
     };
 
+    let project_config = ProjectConfig::discover(
+        recipe_path
+            .parent()
+            .expect("Could not get parent of recipe"),
+    )?;
+
     let span = tracing::info_span!("Finding outputs from recipe");
 
     let enter = span.enter();
     // First find all outputs from the recipe
-    let outputs = find_outputs_from_src(&recipe_text)?;
+    let mut outputs = find_outputs_from_src(&recipe_text)?;
+    for output in &mut outputs {
+        add_build_profiles(output, &args.profiles, project_config.as_ref())?;
+    }
 
-    let variant_config =
-        VariantConfig::from_files(&args.variant_config, &selector_config).into_diagnostic()?;
+    let mut variant_config = variant_config.clone();
+    if !args.variant_overrides.is_empty() {
+        let pruned = variant_config.apply_overrides(&args.variant_overrides)?;
+        for combination in pruned {
+            tracing::info!(
+                "Pruned variant {} because of --variant",
+                combination
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
 
-    let outputs_and_variants =
+    let mut outputs_and_variants =
         variant_config.find_variants(&outputs, &recipe_text, &selector_config)?;
 
+    for discovered_output in &outputs_and_variants {
+        gpu::validate_variant(
+            &discovered_output.used_vars,
+            selector_config.target_platform,
+        )
+        .map_err(|err| {
+            miette::miette!(
+                "Invalid GPU variant for {}-{}: {}",
+                discovered_output.name,
+                discovered_output.version,
+                err
+            )
+        })?;
+    }
+
+    if !args.gpu_variants.is_empty() {
+        outputs_and_variants.retain(|discovered_output| {
+            let gpu_variant = GpuVariant::from_variant(&discovered_output.used_vars);
+            let keep = args.gpu_variants.contains(&gpu_variant);
+            if !keep {
+                tracing::info!(
+                    "Skipping {} variant of {}-{}-{} because of --gpu-variant",
+                    gpu_variant,
+                    discovered_output.name,
+                    discovered_output.version,
+                    discovered_output.build_string
+                );
+            }
+            keep
+        });
+    }
+
     tracing::info!("Found {} variants\n", outputs_and_variants.len());
     for discovered_output in &outputs_and_variants {
         tracing::info!(
@@ -220,26 +782,79 @@ pub async fn get_build_output(
             build_platform: selector_config.build_platform,
             experimental: args.common.experimental,
             allow_undefined: false,
+            lenient_skip: args.lenient_skip,
+            subpackages: subpackages.clone(),
+            environment: selector_config.environment.clone(),
         };
 
-        let recipe =
-            Recipe::from_node(&discovered_output.node, selector_config).map_err(|err| {
-                let errs: ParseErrors = err
-                    .into_iter()
-                    .map(|err| ParsingError::from_partial(&recipe_text, err))
-                    .collect::<Vec<ParsingError>>()
-                    .into();
-                errs
-            })?;
+        let to_parse_errors = |err: Vec<PartialParsingError>| {
+            let errs: ParseErrors = err
+                .into_iter()
+                .map(|err| ParsingError::from_partial(&recipe_text, err))
+                .collect::<Vec<ParsingError>>()
+                .into();
+            errs
+        };
+        let mut recipe =
+            Recipe::from_node(&discovered_output.node, selector_config).map_err(to_parse_errors)?;
+
+        if args.dev {
+            recipe.build.mark_as_dev();
+        }
 
         if recipe.build().skip() {
-            tracing::info!(
-                "Skipping build for variant: {:#?}",
-                discovered_output.used_vars
-            );
+            if let Some(reason) = recipe.build().skip_reason() {
+                tracing::info!(
+                    "Skipping build for variant because {} is true: {:#?}",
+                    reason,
+                    discovered_output.used_vars
+                );
+                tool_config
+                    .skipped_outputs
+                    .lock()
+                    .unwrap()
+                    .push(SkippedOutput {
+                        name: recipe.package().name().clone(),
+                        version: recipe.package().version().to_owned(),
+                        variant: discovered_output.used_vars.clone(),
+                        reason,
+                    });
+            } else {
+                tracing::info!(
+                    "Skipping build for variant: {:#?}",
+                    discovered_output.used_vars
+                );
+            }
             continue;
         }
 
+        recipe
+            .validate_patches(
+                recipe_path
+                    .parent()
+                    .expect("Could not get parent of recipe"),
+            )
+            .map_err(to_parse_errors)?;
+
+        let upload_to =
+            upload_destination(args.upload_to.as_ref(), &recipe, project_config.as_ref())?;
+        let post_build = post_build_hooks(args, &recipe, project_config.as_ref())?;
+
+        // packages of CUDA variants require a driver that supports their CUDA version
+        if recipe.build().noarch().is_none() {
+            if let Some(constraint) = gpu::cuda_run_constraint(&discovered_output.used_vars) {
+                let constrained = recipe.requirements.run_constraints.iter().any(
+                    |dep| matches!(dep, Dependency::Spec(spec) if spec.name == constraint.name),
+                );
+                if !constrained {
+                    recipe
+                        .requirements
+                        .run_constraints
+                        .push(Dependency::Spec(constraint));
+                }
+            }
+        }
+
         subpackages.insert(
             recipe.package().name().clone(),
             PackageIdentifier {
@@ -265,22 +880,24 @@ pub async fn get_build_output(
             .collect::<Result<Vec<_>, _>>()
             .into_diagnostic()?;
 
-        let timestamp = chrono::Utc::now();
+        let timestamp = metadata::build_timestamp()?;
 
         let output = metadata::Output {
             recipe,
             build_configuration: BuildConfiguration {
                 target_platform: discovered_output.target_platform,
                 host_platform: args.target_platform,
-                build_platform: args.build_platform,
+                build_platform: args.build_platform(),
                 hash,
                 variant: discovered_output.used_vars.clone(),
                 directories: Directories::setup(
                     name.as_normalized(),
                     recipe_path,
                     &output_dir,
-                    args.no_build_id,
-                    &timestamp,
+                    args.no_build_id || args.incremental,
+                    // the current time, so that builds with the same `SOURCE_DATE_EPOCH`
+                    // do not share a build directory
+                    &chrono::Utc::now(),
                 )
                 .into_diagnostic()?,
                 channels,
@@ -293,6 +910,17 @@ pub async fn get_build_output(
                 ),
                 store_recipe: !args.no_include_recipe,
                 force_colors: args.color_build_log && console::colors_enabled(),
+                dev: args.dev,
+                error_overlinking: args.error_overlinking,
+                error_overdepending: args.error_overdepending,
+                upload_to,
+                emulation: emulation.clone(),
+                sbom: args.sbom,
+                provenance: args.provenance,
+                post_build,
+                lockfile_dir: args.lockfile_dir.clone(),
+                reuse_lockfiles: args.reuse_lockfiles.clone(),
+                reproducible: args.reproducible,
             },
             finalized_dependencies: None,
             finalized_sources: None,
@@ -319,24 +947,38 @@ pub async fn run_build_from_args(
     build_output: Vec<Output>,
     tool_config: Configuration,
 ) -> miette::Result<()> {
-    let mut outputs: Vec<metadata::Output> = Vec::new();
+    let build_output = skip_existing(build_output, &tool_config).await?;
 
-    for output in skip_existing(build_output, &tool_config).await? {
-        let output = match run_build(output, &tool_config).await {
-            Ok((output, _archive)) => {
-                output.record_build_end();
-                output
-            }
-            Err(e) => {
-                tracing::error!("Error building package: {}", e);
-                return Err(e);
-            }
-        };
-        outputs.push(output);
+    // the clones share the build summary with the outputs that are built, so the
+    // statistics can also be written if a build fails
+    let stats_outputs = tool_config
+        .stats_file
+        .as_ref()
+        .map(|stats_file| (stats_file, build_output.clone()));
+
+    let outputs = run_builds(build_output, &tool_config).await;
+
+    if let Some((stats_file, stats_outputs)) = stats_outputs {
+        match build_stats::write_stats_file(stats_file, &stats_outputs) {
+            Ok(()) => tracing::info!("Wrote the build statistics to {}", stats_file.display()),
+            Err(e) => tracing::error!("Error writing the build statistics: {}", e),
+        }
     }
+    // in offline mode, list everything that would have been downloaded instead of the
+    // first failed request
+    let outputs = outputs.map_err(|e| match tool_config.network.offline_requests.error() {
+        Some(offline) => offline.into(),
+        None => e,
+    })?;
+
+    upload_built_packages(&outputs, &tool_config).await?;
 
     let span = tracing::info_span!("Build summary");
     let _enter = span.enter();
+    let _ = log_skipped_outputs(&tool_config.skipped_outputs.lock().unwrap()).map_err(|e| {
+        tracing::error!("Error writing skipped outputs: {}", e);
+        e
+    });
     for output in outputs {
         // print summaries for each output
         let _ = output.log_build_summary().map_err(|e| {
@@ -348,14 +990,448 @@ pub async fn run_build_from_args(
     Ok(())
 }
 
+/// Upload the packages of the built outputs to their upload destinations (see
+/// [`BuildConfiguration::upload_to`]). The packages with the same destination are
+/// uploaded together.
+async fn upload_built_packages(
+    outputs: &[Output],
+    tool_config: &Configuration,
+) -> miette::Result<()> {
+    let mut packages_by_destination = BTreeMap::<Url, Vec<PathBuf>>::new();
+    let mut attachments_by_destination = BTreeMap::<Url, Vec<(PathBuf, PathBuf)>>::new();
+    for output in outputs {
+        let Some(url) = &output.build_configuration.upload_to else {
+            continue;
+        };
+        let summary = output.build_summary.lock().unwrap();
+        if let Some(artifact) = &summary.artifact {
+            packages_by_destination
+                .entry(url.clone())
+                .or_default()
+                .push(artifact.clone());
+            let attachments = summary.provenance.iter().chain(&summary.signatures);
+            attachments_by_destination
+                .entry(url.clone())
+                .or_default()
+                .extend(attachments.map(|attachment| (artifact.clone(), attachment.clone())));
+        }
+    }
+    if packages_by_destination.is_empty() {
+        return Ok(());
+    }
+
+    let store = tool_configuration::get_auth_store(tool_config.network.auth_file.clone())
+        .into_diagnostic()?;
+    let registry = upload::UploadBackendRegistry::with_default_backends();
+    let settings = upload::UploadSettings {
+        network: tool_config.network.clone(),
+        ..Default::default()
+    };
+    for (url, package_files) in packages_by_destination {
+        let (backend, target) = registry
+            .resolve(&url)
+            .ok_or_else(|| miette::miette!("No upload backend handles {}", url))?;
+        upload::upload_packages(backend.as_ref(), &store, &target, &package_files, &settings)
+            .await
+            .wrap_err_with(|| format!("failed to upload the packages to {}", url))?;
+
+        let attachments = attachments_by_destination.remove(&url).unwrap_or_default();
+        upload::upload_attachments(backend.as_ref(), &store, &target, &attachments, &settings)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Builds the (topologically sorted) outputs one after the other, or in parallel if
+/// more than one job is allowed.
+async fn run_builds(
+    outputs: Vec<Output>,
+    tool_config: &Configuration,
+) -> miette::Result<Vec<Output>> {
+    if tool_config.jobs.get() > 1 {
+        return run_builds_in_parallel(outputs, tool_config).await;
+    }
+
+    let mut built = Vec::new();
+    for output in outputs {
+        match run_build(output, tool_config).await {
+            Ok((output, _archive)) => {
+                output.record_build_end();
+                built.push(output);
+            }
+            Err(e) => {
+                tracing::error!("Error building package: {}", e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(built)
+}
+
+/// Builds the (topologically sorted) outputs with up to `tool_config.jobs` builds
+/// running at the same time. An output is only started once all the outputs it has
+/// to wait for (see [`build_dependencies`]) are built. After a failure no new builds
+/// are started, the running ones are awaited and the first error is returned.
+async fn run_builds_in_parallel(
+    outputs: Vec<Output>,
+    tool_config: &Configuration,
+) -> miette::Result<Vec<Output>> {
+    let dependencies = build_dependencies(&outputs);
+    let mut pending = outputs.into_iter().map(Some).collect::<Vec<_>>();
+    let mut finished = vec![false; pending.len()];
+    let mut running = FuturesUnordered::new();
+    let mut built = Vec::new();
+    let mut error = None;
+
+    loop {
+        if error.is_none() {
+            for idx in 0..pending.len() {
+                if running.len() >= tool_config.jobs.get() {
+                    break;
+                }
+                if pending[idx].is_none() || !dependencies[idx].iter().all(|&dep| finished[dep]) {
+                    continue;
+                }
+
+                let output = pending[idx].take().expect("checked above");
+                let tool_config = tool_config.clone();
+                let handle = tokio::runtime::Handle::current();
+                // the build futures are not `Send`, so every build is driven on its own
+                // blocking thread
+                running.push(tokio::task::spawn_blocking(move || {
+                    (idx, handle.block_on(run_build(output, &tool_config)))
+                }));
+            }
+        }
+
+        let Some(result) = running.next().await else {
+            break;
+        };
+        match result.into_diagnostic()? {
+            (idx, Ok((output, _archive))) => {
+                output.record_build_end();
+                finished[idx] = true;
+                built.push(output);
+            }
+            (_, Err(e)) => {
+                tracing::error!("Error building package: {}", e);
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(built),
+    }
+}
+
+/// Returns for every output the indices of the outputs that have to be built before it:
+/// the outputs it depends on and earlier outputs that use the same build directory. The
+/// outputs are expected to be sorted topologically.
+fn build_dependencies(outputs: &[Output]) -> Vec<Vec<usize>> {
+    output_dependencies(outputs)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, mut dependencies)| {
+            let build_dir = &outputs[idx].build_configuration.directories.build_dir;
+            dependencies.extend((0..idx).filter(|&other| {
+                &outputs[other].build_configuration.directories.build_dir == build_dir
+            }));
+            dependencies.sort();
+            dependencies.dedup();
+            dependencies
+        })
+        .collect()
+}
+
+/// Returns for every output the indices of the earlier outputs that it requires. The
+/// outputs are expected to be sorted topologically.
+fn output_dependencies(outputs: &[Output]) -> Vec<Vec<usize>> {
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(idx, output)| {
+            let dependency_names = output
+                .recipe
+                .requirements()
+                .all()
+                .map(dependency_name)
+                .filter(|name| name != output.name())
+                .collect::<HashSet<_>>();
+            (0..idx)
+                .filter(|&other| dependency_names.contains(outputs[other].name()))
+                .collect()
+        })
+        .collect()
+}
+
+/// An output in the build order that is printed with `--build-order`.
+#[derive(Debug, Serialize)]
+pub struct BuildOrderEntry {
+    /// The recipe that produces the output
+    pub recipe: PathBuf,
+    /// The identifier of the output (`name-version-build_string`)
+    pub output: String,
+    /// The identifiers of the outputs that have to be built first
+    pub depends_on: Vec<String>,
+}
+
+/// Returns the build order of the topologically sorted outputs.
+pub fn build_order(outputs: &[Output]) -> Vec<BuildOrderEntry> {
+    let identifier = |output: &Output| output.identifier().unwrap_or_default();
+    output_dependencies(outputs)
+        .into_iter()
+        .zip(outputs)
+        .map(|(dependencies, output)| BuildOrderEntry {
+            recipe: output.build_configuration.directories.recipe_path.clone(),
+            output: identifier(output),
+            depends_on: dependencies
+                .into_iter()
+                .map(|idx| identifier(&outputs[idx]))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Returns the name of the package that a dependency refers to.
+pub(crate) fn dependency_name(dep: &Dependency) -> PackageName {
+    match dep {
+        Dependency::Spec(spec) => spec
+            .name
+            .clone()
+            .expect("MatchSpec should always have a name"),
+        Dependency::PinSubpackage(pin) => pin.pin_value().name.clone(),
+        Dependency::PinCompatible(pin) => pin.pin_value().name.clone(),
+    }
+}
+
+/// A fully rendered output as printed by the `render` subcommand.
+#[derive(Debug, Serialize)]
+pub struct RenderedOutput {
+    /// The recipe file the output was rendered from
+    pub recipe_path: PathBuf,
+    /// The target platform the output was rendered for
+    pub target_platform: Platform,
+    /// The variant that was used to render the output
+    pub variant: BTreeMap<String, String>,
+    /// The rendered recipe, with all Jinja expressions and selectors evaluated
+    pub recipe: Recipe,
+    /// The resolved dependencies, only present when rendering with `--with-solve`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finalized_dependencies: Option<FinalizedDependencies>,
+    /// Where the run exports of the environments ended up, only present when rendering
+    /// with `--explain-run-exports`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_exports: Option<Vec<RunExportExplanation>>,
+    /// The inputs of the variant hash, only present when rendering with `--explain-hash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashExplanation>,
+}
+
+impl From<Output> for RenderedOutput {
+    fn from(output: Output) -> Self {
+        Self {
+            recipe_path: output.build_configuration.directories.recipe_path,
+            target_platform: output.build_configuration.target_platform,
+            variant: output.build_configuration.variant,
+            recipe: output.recipe,
+            finalized_dependencies: output.finalized_dependencies,
+            run_exports: None,
+            hash: None,
+        }
+    }
+}
+
+/// An entry printed by the `render` subcommand.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum RenderEntry {
+    /// An output that is built
+    Rendered(Box<RenderedOutput>),
+    /// A variant that is skipped (only printed with `--include-skipped`)
+    Skipped(SkippedOutput),
+}
+
+/// Renders the recipes for all variants and prints the rendered outputs.
+pub async fn render_from_args(
+    args: RenderOpts,
+    fancy_log_handler: LoggingOutputHandler,
+) -> miette::Result<()> {
+    let mut build_args = BuildOpts {
+        render_only: true,
+        with_solve: args.build.with_solve || args.explain_run_exports,
+        ..args.build
+    };
+    apply_project_defaults(&mut build_args)?;
+    if args.show_variant_sources {
+        let mut variant_config = get_variant_config(&build_args, None)?;
+        variant_config.apply_overrides(&build_args.variant_overrides)?;
+        let sources = variant_config.key_sources();
+        let sources = match args.format {
+            RenderFormat::Json => serde_json::to_string_pretty(&sources).into_diagnostic()?,
+            RenderFormat::Yaml => serde_yaml::to_string(&sources).into_diagnostic()?,
+        };
+        println!("{}", sources);
+        return Ok(());
+    }
+
+    let tool_config = get_tool_config(&build_args, &fancy_log_handler)?;
+
+    let mut outputs = Vec::new();
+    for recipe_path in get_recipe_paths(&build_args)? {
+        outputs.extend(get_build_output(&build_args, &recipe_path, &tool_config).await?);
+    }
+    let skipped = tool_config.skipped_outputs.lock().unwrap().clone();
+    log_skipped_outputs(&skipped).into_diagnostic()?;
+
+    let config_keys = if args.explain_hash {
+        get_variant_config(&build_args, None)?
+            .variants
+            .map
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+
+    let mut rendered = outputs
+        .into_iter()
+        .map(|output| {
+            let run_exports = match &output.finalized_dependencies {
+                Some(deps) if args.explain_run_exports => {
+                    Some(deps.explain_run_exports(output.build_configuration.target_platform))
+                }
+                _ => None,
+            };
+            let hash = args.explain_hash.then(|| {
+                HashInfo::explain(
+                    &output.build_configuration.variant,
+                    output.recipe.build().noarch(),
+                    &config_keys,
+                    &output.recipe.build().variant().ignore_keys,
+                )
+            });
+            RenderEntry::Rendered(Box::new(RenderedOutput {
+                run_exports,
+                hash,
+                ..output.into()
+            }))
+        })
+        .collect::<Vec<_>>();
+    if args.include_skipped {
+        rendered.extend(skipped.into_iter().map(RenderEntry::Skipped));
+    }
+    let rendered = match args.format {
+        RenderFormat::Json => serde_json::to_string_pretty(&rendered).into_diagnostic()?,
+        RenderFormat::Yaml => serde_yaml::to_string(&rendered).into_diagnostic()?,
+    };
+    println!("{}", rendered);
+
+    Ok(())
+}
+
+/// Prepares the build environment of a recipe for debugging, without running the
+/// build script, and prints instructions for entering it.
+pub async fn debug_from_args(
+    mut args: DebugOpts,
+    fancy_log_handler: LoggingOutputHandler,
+) -> miette::Result<()> {
+    apply_project_defaults(&mut args.build)?;
+    let build_args = &args.build;
+    let tool_config = get_tool_config(build_args, &fancy_log_handler)?;
+
+    let mut outputs = Vec::new();
+    for recipe_path in get_recipe_paths(build_args)? {
+        outputs.extend(get_build_output(build_args, &recipe_path, &tool_config).await?);
+    }
+
+    if let Some(output_name) = &args.output_name {
+        outputs.retain(|output| output.name().as_normalized() == output_name);
+    }
+
+    let output = match outputs.len() {
+        0 => return Err(miette::miette!("No matching output found in the recipe")),
+        1 => outputs.remove(0),
+        _ => {
+            let identifiers = outputs
+                .iter()
+                .map(|output| output.identifier().unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join("\n - ");
+            return Err(miette::miette!(
+                help = "select a single output with `--output-name` and a single variant with a variant config",
+                "Found multiple outputs to debug:\n - {}",
+                identifiers
+            ));
+        }
+    };
+
+    let (output, build_scripts) = build::prepare_debug_build(output, &tool_config).await?;
+    let directories = &output.build_configuration.directories;
+
+    // enter the environment of the first phase, the build scripts are run in order
+    let file_name =
+        |path: &std::path::Path| path.file_name().unwrap().to_string_lossy().to_string();
+    let build_env = build_scripts
+        .first()
+        .map(|(_, path)| file_name(path).replacen("conda_build", "build_env", 1))
+        .unwrap_or_default();
+    let (enter, run) = if cfg!(windows) {
+        (
+            format!("call {}", build_env),
+            build_scripts
+                .iter()
+                .map(|(_, path)| format!("call {}", file_name(path)))
+                .collect::<Vec<_>>()
+                .join(" && "),
+        )
+    } else {
+        (
+            format!("source {}", build_env),
+            build_scripts
+                .iter()
+                .map(|(_, path)| format!("bash {}", file_name(path)))
+                .collect::<Vec<_>>()
+                .join(" && "),
+        )
+    };
+
+    println!("\nThe build environment is ready for debugging.\n");
+    println!("  work directory: {}", directories.work_dir.display());
+    println!("  host prefix:    {}", directories.host_prefix.display());
+    println!("  build prefix:   {}", directories.build_prefix.display());
+    for (phase, path) in &build_scripts {
+        match phase {
+            Some(phase) => println!("  {:<15} {}", format!("{phase} phase:"), path.display()),
+            None => println!("  build script:   {}", path.display()),
+        }
+    }
+    println!("\nTo enter the build environment, run:\n");
+    println!("  cd {}", directories.work_dir.display());
+    println!("  {}", enter);
+    println!(
+        "\nAfterwards you can run the build script with `{}`, or run its commands one by one.",
+        run
+    );
+    println!(
+        "The environment variables are defined in `{}`, the build directory is kept until you remove it.",
+        build_env
+    );
+
+    Ok(())
+}
+
 /// Runs test.
 pub async fn run_test_from_args(
     args: TestOpts,
     fancy_log_handler: LoggingOutputHandler,
 ) -> miette::Result<()> {
-    let package_file = canonicalize(args.package_file).into_diagnostic()?;
-    let client = tool_configuration::reqwest_client_from_auth_storage(args.common.auth_file)
-        .into_diagnostic()?;
+    let network = network_settings(&args.common)?;
+    let client =
+        tool_configuration::reqwest_client_from_auth_storage(args.common.auth_file, &network)
+            .into_diagnostic()?;
 
     let channel_config = ChannelConfig::default_with_root_dir(
         std::env::current_dir()
@@ -379,27 +1455,138 @@ pub async fn run_test_from_args(
         keep_test_prefix: false,
         channels,
         tool_configuration: Configuration {
+            repodata_gateway: tool_configuration::repodata_gateway(
+                client.clone(),
+                &network,
+                &tool_configuration::rattler_cache_dir(args.common.cache_dir.as_deref()),
+            ),
+            cache_dir: args.common.cache_dir.clone(),
             client,
             fancy_log_handler,
             // duplicate from `keep_test_prefix`?
             no_clean: false,
+            test_policy: args.test_policy,
+            network,
             ..Default::default()
         },
+        skip_downstream_tests: false,
+        lockfile_dir: None,
     };
 
+    let Some(package_file) = args.package_file else {
+        return test_channel_packages(
+            &args.spec,
+            args.subdir.unwrap_or_else(Platform::current),
+            args.all_builds,
+            args.jobs,
+            args.junit.as_deref(),
+            &test_options,
+        )
+        .await;
+    };
+    let package_file = canonicalize(package_file).into_diagnostic()?;
     let package_name = package_file
         .file_name()
         .ok_or_else(|| miette::miette!("Could not get file name from package file"))?
         .to_string_lossy()
         .to_string();
 
+    let classname = ArchiveIdentifier::try_from_path(&package_file)
+        .map(|identifier| identifier.name)
+        .unwrap_or_else(|| package_name.clone());
+    let index_json: IndexJson =
+        rattler_package_streaming::seek::read_package_file(&package_file).into_diagnostic()?;
+    let platform = index_json
+        .subdir
+        .as_deref()
+        .and_then(|subdir| subdir.parse().ok())
+        .unwrap_or(Platform::NoArch);
+    if let Some(entry) = package_test::skipped_entry(
+        package_name.clone(),
+        classname.clone(),
+        platform,
+        args.test_policy,
+    ) {
+        reporter::report_all(
+            &reporter::reporters(args.junit.as_deref()),
+            "rattler-build test",
+            std::slice::from_ref(&entry),
+        )
+        .into_diagnostic()?;
+        return match entry.outcome {
+            reporter::Outcome::Failed(err) => Err(miette::miette!(err)),
+            _ => Ok(()),
+        };
+    }
+
     let span = tracing::info_span!("Running tests for ", recipe = %package_name);
     let _enter = span.enter();
-    package_test::run_test(&package_file, &test_options)
-        .await
+    let start = std::time::Instant::now();
+    let result = package_test::run_test(&package_file, &test_options).await;
+
+    let entry = package_test::report_entry(package_name, classname, start.elapsed(), &result);
+    reporter::report_all(
+        &reporter::reporters(args.junit.as_deref()),
+        "rattler-build test",
+        &[entry],
+    )
+    .into_diagnostic()?;
+
+    result.into_diagnostic()
+}
+
+/// Run the tests of the packages of the channels that match the specs (or of all
+/// packages of the subdir) and report the results.
+async fn test_channel_packages(
+    specs: &[String],
+    subdir: Platform,
+    all_builds: bool,
+    jobs: NonZeroUsize,
+    junit: Option<&Path>,
+    test_options: &TestConfiguration,
+) -> miette::Result<()> {
+    let specs = specs
+        .iter()
+        .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Lenient))
+        .collect::<Result<Vec<_>, _>>()
         .into_diagnostic()?;
+    let records = package_test::find_packages(
+        &test_options.channels,
+        subdir,
+        &specs,
+        all_builds,
+        &test_options.tool_configuration,
+    )
+    .await
+    .into_diagnostic()?;
+    if records.is_empty() {
+        return Err(miette::miette!("No packages found to test"));
+    }
+    tracing::info!("Testing {} packages", records.len());
 
-    Ok(())
+    let results = package_test::test_packages(&records, test_options, jobs).await;
+    reporter::report_all(&reporter::reporters(junit), "rattler-build test", &results)
+        .into_diagnostic()?;
+
+    let failed = results
+        .iter()
+        .filter(|result| result.is_failure())
+        .map(|result| result.name.as_str())
+        .collect::<Vec<_>>();
+    tracing::info!(
+        "{} of {} packages passed their tests",
+        results.len() - failed.len(),
+        results.len()
+    );
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(miette::miette!(
+            "The tests of {} packages failed:\n{}",
+            failed.len(),
+            failed.join("\n")
+        ))
+    }
 }
 
 /// Rebuild.
@@ -418,10 +1605,27 @@ pub async fn rebuild_from_args(
 
     tracing::info!("Extracted recipe to: {:?}", temp_dir);
 
+    // keep a copy of the original package, the rebuilt package may replace it
+    let original_folder = tempfile::tempdir().into_diagnostic()?;
+    let original = if args.verify {
+        let file_name = args
+            .package_file
+            .file_name()
+            .ok_or_else(|| miette::miette!("{} is not a file", args.package_file.display()))?;
+        let original = original_folder.path().join(file_name);
+        fs::create_dir_all(original.parent().expect("has a parent")).into_diagnostic()?;
+        fs::copy(&args.package_file, &original).into_diagnostic()?;
+        Some(original)
+    } else {
+        None
+    };
+
+    // the variant is taken from the rendered recipe, no variant config files are loaded
     let rendered_recipe =
         fs::read_to_string(temp_dir.join("rendered_recipe.yaml")).into_diagnostic()?;
 
     let mut output: metadata::Output = serde_yaml::from_str(&rendered_recipe).into_diagnostic()?;
+    let original_build_dir = output.build_configuration.directories.build_dir.clone();
 
     // set recipe dir to the temp folder
     output.build_configuration.directories.recipe_dir = temp_dir;
@@ -436,16 +1640,27 @@ pub async fn rebuild_from_args(
     output.build_configuration.directories.output_dir =
         canonicalize(output_dir).into_diagnostic()?;
 
-    let client = tool_configuration::reqwest_client_from_auth_storage(args.common.auth_file)
-        .into_diagnostic()?;
+    let network = network_settings(&args.common)?;
+    let client =
+        tool_configuration::reqwest_client_from_auth_storage(args.common.auth_file, &network)
+            .into_diagnostic()?;
 
     let tool_config = tool_configuration::Configuration {
+        repodata_gateway: tool_configuration::repodata_gateway(
+            client.clone(),
+            &network,
+            &tool_configuration::rattler_cache_dir(args.common.cache_dir.as_deref()),
+        ),
+        cache_dir: args.common.cache_dir.clone(),
         client,
         fancy_log_handler,
         no_clean: true,
         no_test: args.no_test,
+        test_policy: TestPolicy::default(),
+        log_sink: None,
         use_zstd: args.common.use_zstd,
         use_bz2: args.common.use_bz2,
+        network,
         ..Configuration::default()
     };
 
@@ -455,7 +1670,52 @@ pub async fn rebuild_from_args(
         .recreate_directories()
         .into_diagnostic()?;
 
-    run_build(output, &tool_config).await?;
+    let (output, rebuilt) = run_build(output, &tool_config).await?;
+
+    if let Some(original) = original {
+        let directories = &output.build_configuration.directories;
+        let mut build_paths = [
+            &original_build_dir,
+            &directories.build_dir,
+            &directories.recipe_dir,
+            &directories.output_dir,
+        ]
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+        build_paths.sort();
+        build_paths.dedup();
+
+        match rebuild::verify(&original, &rebuilt, &build_paths).into_diagnostic()? {
+            rebuild::Verification::Identical => tracing::info!(
+                "{} The rebuilt package is bit-for-bit identical to {}",
+                console::style(console::Emoji("✔", "")).green(),
+                args.package_file.display()
+            ),
+            rebuild::Verification::SameFiles if args.ignore_archive_differences => tracing::info!(
+                "{} The files of the rebuilt package are identical to {}, only the archives differ (e.g. the compression)",
+                console::style(console::Emoji("✔", "")).green(),
+                args.package_file.display()
+            ),
+            rebuild::Verification::SameFiles => miette::bail!(
+                help = "pass `--ignore-archive-differences` to accept packages with identical files",
+                "the rebuilt package {} is not bit-for-bit identical to {}: the files are identical, but the archives differ (e.g. the compression)",
+                rebuilt.display(),
+                args.package_file.display()
+            ),
+            rebuild::Verification::Different(differences) => {
+                for difference in &differences {
+                    tracing::warn!("  - {}", difference);
+                }
+                miette::bail!(
+                    "the rebuilt package {} is not reproducible: it differs from {} in {} places",
+                    rebuilt.display(),
+                    args.package_file.display(),
+                    differences.len()
+                );
+            }
+        }
+    }
 
     Ok(())
 }
@@ -475,60 +1735,111 @@ pub async fn upload_from_args(args: UploadOpts) -> miette::Result<()> {
         }
     }
 
+    let network = network_settings(&args.common)?;
     let store = tool_configuration::get_auth_store(args.common.auth_file).into_diagnostic()?;
 
-    match args.server_type {
-        ServerType::Quetz(quetz_opts) => {
-            upload::upload_package_to_quetz(
-                &store,
-                quetz_opts.api_key,
-                &args.package_files,
-                quetz_opts.url,
-                quetz_opts.channel,
-            )
-            .await?;
-        }
+    let settings = upload::UploadSettings {
+        retries: args.retries,
+        parallelism: args.parallel,
+        chunk_size: args.chunk_size * 1024 * 1024,
+        network,
+    };
+
+    let (backend, target): (Box<dyn upload::UploadBackend>, _) = match args.server_type {
+        ServerType::Quetz(quetz_opts) => (
+            Box::new(upload::Quetz),
+            upload::UploadTarget::new(quetz_opts.url, quetz_opts.channel)
+                .with_credentials(quetz_opts.api_key.map(Authentication::CondaToken)),
+        ),
         ServerType::Artifactory(artifactory_opts) => {
-            upload::upload_package_to_artifactory(
-                &store,
-                artifactory_opts.username,
-                artifactory_opts.password,
-                &args.package_files,
-                artifactory_opts.url,
-                artifactory_opts.channel,
+            let credentials = match (artifactory_opts.username, artifactory_opts.password) {
+                (Some(username), Some(password)) => {
+                    Some(Authentication::BasicHTTP { username, password })
+                }
+                (Some(_), _) | (_, Some(_)) => {
+                    return Err(miette::miette!("A username and password is required for authentication with artifactory, only one was given"));
+                }
+                _ => None,
+            };
+            (
+                Box::new(upload::Artifactory),
+                upload::UploadTarget::new(artifactory_opts.url, artifactory_opts.channel)
+                    .with_credentials(credentials),
             )
-            .await?;
         }
-        ServerType::Prefix(prefix_opts) => {
-            upload::upload_package_to_prefix(
-                &store,
-                prefix_opts.api_key,
-                &args.package_files,
-                prefix_opts.url,
-                prefix_opts.channel,
-            )
-            .await?;
+        ServerType::Prefix(prefix_opts) => (
+            Box::new(upload::Prefix),
+            upload::UploadTarget::new(prefix_opts.url, prefix_opts.channel)
+                .with_credentials(prefix_opts.api_key.map(Authentication::BearerToken)),
+        ),
+        ServerType::Anaconda(anaconda_opts) => (
+            Box::new(upload::AnacondaBackend {
+                labels: anaconda_opts.channel,
+                force: anaconda_opts.force,
+            }),
+            upload::UploadTarget::new(anaconda_opts.url, anaconda_opts.owner)
+                .with_credentials(anaconda_opts.api_key.map(Authentication::CondaToken)),
+        ),
+        ServerType::Oci(oci_opts) => {
+            let credentials = match (oci_opts.username, oci_opts.password) {
+                (Some(username), Some(password)) => {
+                    Some(Authentication::BasicHTTP { username, password })
+                }
+                (Some(_), _) | (_, Some(_)) => {
+                    return Err(miette::miette!("A username and password is required for authentication with an OCI registry, only one was given"));
+                }
+                _ => None,
+            };
+            let target = upload::UploadBackend::resolve_target(&upload::Oci, &oci_opts.url)
+                .ok_or_else(|| {
+                    miette::miette!(
+                        "{} is not an OCI channel url like `oci://ghcr.io/my-org/my-channel`",
+                        oci_opts.url
+                    )
+                })?;
+            (Box::new(upload::Oci), target.with_credentials(credentials))
         }
-        ServerType::Anaconda(anaconda_opts) => {
-            upload::upload_package_to_anaconda(
-                &store,
-                anaconda_opts.api_key,
-                &args.package_files,
-                anaconda_opts.url,
-                anaconda_opts.owner,
-                anaconda_opts.channel,
-                anaconda_opts.force,
-            )
-            .await?;
+        ServerType::S3(s3_opts) => {
+            let backend = upload::S3::new(upload::S3Config {
+                endpoint_url: s3_opts.endpoint_url,
+                region: s3_opts.region,
+                profile: s3_opts.profile,
+                role_arn: s3_opts.role_arn,
+                session_token: s3_opts.session_token,
+                force_path_style: s3_opts.force_path_style,
+                repodata: s3_opts.repodata,
+            });
+            let target = upload::UploadBackend::resolve_target(&backend, &s3_opts.channel)
+                .ok_or_else(|| {
+                    miette::miette!(
+                        "{} is not an S3 channel url like `s3://my-bucket/my-channel`",
+                        s3_opts.channel
+                    )
+                })?;
+            let credentials = s3_opts
+                .access_key_id
+                .zip(s3_opts.secret_access_key)
+                .map(|(username, password)| Authentication::BasicHTTP { username, password });
+            (Box::new(backend), target.with_credentials(credentials))
         }
         ServerType::CondaForge(conda_forge_opts) => {
-            upload::conda_forge::upload_packages_to_conda_forge(
+            return upload::conda_forge::upload_packages_to_conda_forge(
                 conda_forge_opts,
                 &args.package_files,
+                &settings.network,
             )
-            .await?;
+            .await;
         }
-    }
+    };
+
+    upload::upload_packages(
+        backend.as_ref(),
+        &store,
+        &target,
+        &args.package_files,
+        &settings,
+    )
+    .await?;
 
     Ok(())
 }
@@ -539,50 +1850,48 @@ pub fn sort_build_outputs_topologically(
     up_to: Option<&str>,
 ) -> miette::Result<()> {
     let mut graph = DiGraph::<usize, ()>::new();
-    let mut name_to_index = HashMap::new();
+    let mut name_to_indices = HashMap::<_, Vec<_>>::new();
 
-    // Index outputs by their produced names for quick lookup
+    // Index outputs by their produced names for quick lookup, an output can have
+    // multiple variants
     for (idx, output) in outputs.iter().enumerate() {
         let idx = graph.add_node(idx);
-        name_to_index.insert(output.name().clone(), idx);
+        name_to_indices
+            .entry(output.name().clone())
+            .or_default()
+            .push(idx);
     }
 
     // Add edges based on dependencies
-    for output in outputs.iter() {
-        let output_idx = *name_to_index
-            .get(output.name())
-            .expect("We just inserted it");
+    for (output_idx, output) in outputs.iter().enumerate() {
+        let output_idx = NodeIndex::new(output_idx);
         for dep in output.recipe.requirements().all() {
-            let dep_name = match dep {
-                Dependency::Spec(spec) => spec
-                    .name
-                    .clone()
-                    .expect("MatchSpec should always have a name"),
-                Dependency::PinSubpackage(pin) => pin.pin_value().name.clone(),
-                Dependency::PinCompatible(pin) => pin.pin_value().name.clone(),
-            };
-            if let Some(&dep_idx) = name_to_index.get(&dep_name) {
-                // do not point to self (circular dependency) - this can happen with
-                // pin_subpackage in run_exports, for example.
-                if output_idx == dep_idx {
-                    continue;
-                }
+            let dep_name = dependency_name(dep);
+            // do not point to self or other variants of the same output (circular
+            // dependency) - this can happen with pin_subpackage in run_exports, for example.
+            if dep_name == *output.name() {
+                continue;
+            }
+            for &dep_idx in name_to_indices.get(&dep_name).into_iter().flatten() {
                 graph.add_edge(output_idx, dep_idx, ());
             }
         }
     }
 
     let sorted_indices = if let Some(up_to) = up_to {
-        // Find the node index for the "up-to" package
-        let up_to_index = name_to_index.get(up_to).copied().ok_or_else(|| {
+        // Find the node indices for the "up-to" package
+        let up_to_indices = name_to_indices.get(up_to).ok_or_else(|| {
             miette::miette!("The package '{}' was not found in the outputs", up_to)
         })?;
 
-        // Perform a DFS post-order traversal from the "up-to" node to find all dependencies
-        let mut dfs = DfsPostOrder::new(&graph, up_to_index);
+        // Perform a DFS post-order traversal from the "up-to" nodes to find all dependencies
+        let mut dfs = DfsPostOrder::new(&graph, up_to_indices[0]);
         let mut sorted_indices = Vec::new();
-        while let Some(nx) = dfs.next(&graph) {
-            sorted_indices.push(nx);
+        for &start in up_to_indices {
+            dfs.move_to(start);
+            while let Some(nx) = dfs.next(&graph) {
+                sorted_indices.push(nx);
+            }
         }
 
         sorted_indices
@@ -0,0 +1,766 @@
+//! Lint recipes without building them (`rattler-build lint`).
+//!
+//! The linter parses the recipe with the regular recipe parser and reports problems
+//! that would either fail the build (e.g. unknown keys) or are likely mistakes (e.g. a
+//! run dependency without a version constraint). Every diagnostic points at the line and
+//! column in the recipe so that editors can show it inline. With `--online-lints`, the
+//! URLs of the `about` section are also checked to respond.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::Parser;
+use fs_err as fs;
+use marked_yaml::Span;
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    get_recipe_path,
+    recipe::{
+        custom_yaml::{HasSpan, Node, SequenceNodeInternal},
+        error::{ErrorKind, PartialParsingError},
+        jinja::platform_selectors,
+        parser::{find_outputs_from_src, Recipe},
+        Jinja,
+    },
+    reporter::{self, Outcome, ReportEntry},
+    selectors::SelectorConfig,
+    tool_configuration::NetworkSettings,
+    used_variables::referenced_vars_from_template,
+    variant_config::VariantConfig,
+};
+
+/// Output format of the `lint` subcommand.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LintFormat {
+    /// One line per diagnostic, meant to be read by humans
+    #[default]
+    Human,
+    /// A JSON array of diagnostics, meant for editor integrations
+    Json,
+}
+
+/// Options for the `lint` subcommand.
+#[derive(Parser, Clone, Debug)]
+pub struct LintOpts {
+    /// The recipe file or the directory that contains `recipe.yaml`.
+    #[arg(default_value = ".")]
+    pub recipe: PathBuf,
+
+    /// The target platform used to evaluate selectors in the recipe.
+    #[arg(long, default_value_t = Platform::current())]
+    pub target_platform: Platform,
+
+    /// Variant configuration files used to evaluate the recipe.
+    #[arg(short = 'm', long)]
+    pub variant_config: Vec<PathBuf>,
+
+    /// The output format of the diagnostics.
+    #[arg(long, value_enum, default_value_t = LintFormat::Human)]
+    pub format: LintFormat,
+
+    /// Also run the lints that need network access, e.g. check that the URLs of the
+    /// `about` section respond.
+    #[arg(long)]
+    pub online_lints: bool,
+
+    /// Write a JUnit XML report of the diagnostics
+    #[arg(long)]
+    pub junit: Option<PathBuf>,
+}
+
+/// The severity of a lint diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The recipe cannot be built.
+    Error,
+    /// The recipe can be built, but this is likely a mistake.
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A single problem found in a recipe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintDiagnostic {
+    /// The severity of the problem
+    pub severity: Severity,
+    /// A stable identifier of the lint, e.g. `unknown_key`
+    pub code: &'static str,
+    /// A description of the problem
+    pub message: String,
+    /// The line in the recipe (1-based)
+    pub line: usize,
+    /// The column in the recipe (1-based)
+    pub column: usize,
+    /// A suggestion on how to fix the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
+}
+
+impl LintDiagnostic {
+    fn new(severity: Severity, code: &'static str, message: String, span: &Span) -> Self {
+        let (line, column) = span
+            .start()
+            .map(|marker| (marker.line(), marker.column()))
+            .unwrap_or((1, 1));
+        Self {
+            severity,
+            code,
+            message,
+            line,
+            column,
+            help: None,
+        }
+    }
+
+    fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    fn from_partial(err: PartialParsingError) -> Self {
+        let code = match err.kind {
+            ErrorKind::InvalidField(_) => "unknown_key",
+            ErrorKind::UrlParsing(_) => "invalid_url",
+            _ => "parse_error",
+        };
+        let mut diagnostic = Self::new(Severity::Error, code, err.kind.to_string(), &err.span);
+        diagnostic.help = err.help.map(|help| help.into_owned());
+        diagnostic
+    }
+}
+
+/// The `about` fields that have to be URLs of a web page.
+const ABOUT_URL_FIELDS: [&str; 3] = ["homepage", "repository", "documentation"];
+
+/// The time after which a URL of the `about` section is considered unreachable.
+const URL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Lint the source of a recipe. The diagnostics are sorted by their location.
+pub fn lint_recipe(
+    recipe_text: &str,
+    variant_config: &VariantConfig,
+    selector_config: &SelectorConfig,
+) -> Vec<LintDiagnostic> {
+    let (mut diagnostics, _) = lint_outputs(recipe_text, variant_config, selector_config);
+    sort_diagnostics(&mut diagnostics);
+    diagnostics
+}
+
+/// Lint the source of a recipe, including the lints that need network access. The
+/// diagnostics are sorted by their location.
+pub async fn lint_recipe_online(
+    recipe_text: &str,
+    variant_config: &VariantConfig,
+    selector_config: &SelectorConfig,
+    client: &reqwest::Client,
+) -> Vec<LintDiagnostic> {
+    let (mut diagnostics, urls) = lint_outputs(recipe_text, variant_config, selector_config);
+
+    let checks = urls.iter().map(|(url, _)| check_url(client, url));
+    let results = futures::future::join_all(checks).await;
+    for ((url, spans), result) in urls.iter().zip(results) {
+        let Err(err) = result else {
+            continue;
+        };
+        for (field, span) in spans {
+            diagnostics.push(
+                LintDiagnostic::new(
+                    Severity::Warning,
+                    "unreachable_url",
+                    format!("`about.{field}` ({url}) is not reachable: {err}"),
+                    span,
+                )
+                .with_help("update the URL or remove the field"),
+            );
+        }
+    }
+
+    sort_diagnostics(&mut diagnostics);
+    diagnostics
+}
+
+/// Sort the diagnostics by their location. Outputs share the top-level keys of the
+/// recipe, so every problem is reported only once.
+fn sort_diagnostics(diagnostics: &mut Vec<LintDiagnostic>) {
+    diagnostics.sort_by(|a, b| {
+        (a.line, a.column, a.severity, a.code).cmp(&(b.line, b.column, b.severity, b.code))
+    });
+    diagnostics.dedup();
+}
+
+/// The URLs of the `about` sections of a recipe, with the field and the location of
+/// every place they are used.
+type AboutUrls = BTreeMap<Url, Vec<(&'static str, Span)>>;
+
+/// Run the offline lints on all outputs of a recipe. Also returns the URLs of the
+/// `about` sections for the online lints.
+fn lint_outputs(
+    recipe_text: &str,
+    variant_config: &VariantConfig,
+    selector_config: &SelectorConfig,
+) -> (Vec<LintDiagnostic>, AboutUrls) {
+    let mut urls = AboutUrls::new();
+    let outputs = match find_outputs_from_src(recipe_text) {
+        Ok(outputs) => outputs,
+        Err(err) => {
+            let (line, column) = line_and_column(recipe_text, err.span.offset());
+            let diagnostic = LintDiagnostic {
+                severity: Severity::Error,
+                code: "parse_error",
+                message: err.kind.to_string(),
+                line,
+                column,
+                help: err.help.map(|help| help.into_owned()),
+            };
+            return (vec![diagnostic], urls);
+        }
+    };
+
+    // render with the first value of every variant key, this is good enough to find
+    // unknown keys and missing fields
+    let variant = variant_config
+        .variants
+        .iter()
+        .filter_map(|(key, values)| values.first().map(|value| (key.clone(), value.clone())))
+        .collect::<BTreeMap<_, _>>();
+    let selector_config = SelectorConfig {
+        variant,
+        allow_undefined: true,
+        ..selector_config.clone()
+    };
+
+    let mut known_variables = Jinja::new(selector_config.clone())
+        .context()
+        .keys()
+        .cloned()
+        .collect::<HashSet<_>>();
+    known_variables.extend(platform_selectors());
+    known_variables.extend(variant_config.variants.iter().map(|(key, _)| key.clone()));
+
+    let mut diagnostics = Vec::new();
+    for output in &outputs {
+        if let Some(context) = output.as_mapping().and_then(|root| root.get("context")) {
+            if let Some(context) = context.as_mapping() {
+                known_variables.extend(context.keys().map(|key| key.as_str().to_string()));
+            }
+        }
+
+        match Recipe::from_node(output, selector_config.clone()) {
+            Ok(recipe) => {
+                if recipe.about().license.is_none() {
+                    let root = output.as_mapping();
+                    let span = root
+                        .and_then(|root| root.get("about").or_else(|| root.get("package")))
+                        .map(|node| *node.span())
+                        .unwrap_or_else(|| *output.span());
+                    diagnostics.push(
+                        LintDiagnostic::new(
+                            Severity::Warning,
+                            "missing_license",
+                            format!(
+                                "package `{}` does not declare a license",
+                                recipe.package().name().as_normalized()
+                            ),
+                            &span,
+                        )
+                        .with_help("add an SPDX license identifier to `about.license`"),
+                    );
+                }
+                lint_about_urls(output, &recipe, &mut diagnostics, &mut urls);
+            }
+            Err(errs) => diagnostics.extend(errs.into_iter().map(LintDiagnostic::from_partial)),
+        }
+
+        lint_run_dependencies(output, variant_config, &mut diagnostics);
+        lint_skip(output, &mut diagnostics);
+        lint_jinja_variables(output, &known_variables, &mut diagnostics);
+    }
+
+    (diagnostics, urls)
+}
+
+/// Convert a byte offset into a 1-based line and column.
+fn line_and_column(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+/// Get the node at the given path of mapping keys.
+fn get_path<'a>(node: &'a Node, path: &[&str]) -> Option<&'a Node> {
+    path.iter()
+        .try_fold(node, |node, key| node.as_mapping()?.get(*key))
+}
+
+/// Collect all scalars of a sequence, including both branches of `if` selectors.
+fn sequence_scalars<'a>(node: &'a Node, scalars: &mut Vec<&'a Node>) {
+    match node {
+        Node::Scalar(_) => scalars.push(node),
+        Node::Sequence(sequence) => {
+            for item in sequence.iter() {
+                match item {
+                    SequenceNodeInternal::Simple(node) => sequence_scalars(node, scalars),
+                    SequenceNodeInternal::Conditional(selector) => {
+                        sequence_scalars(selector.then(), scalars);
+                        if let Some(otherwise) = selector.otherwise() {
+                            sequence_scalars(otherwise, scalars);
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Warn about run dependencies that do not have a version constraint. Dependencies that
+/// are templated (e.g. `${{ pin_subpackage(...) }}`), virtual packages and packages that
+/// are pinned through the variant configuration are fine.
+fn lint_run_dependencies(
+    output: &Node,
+    variant_config: &VariantConfig,
+    diagnostics: &mut Vec<LintDiagnostic>,
+) {
+    let Some(run) = get_path(output, &["requirements", "run"]) else {
+        return;
+    };
+    let mut scalars = Vec::new();
+    sequence_scalars(run, &mut scalars);
+
+    for node in scalars {
+        let Some(spec) = node.as_scalar() else {
+            continue;
+        };
+        let spec = spec.as_str().trim();
+        if spec.is_empty()
+            || spec.contains("${{")
+            || spec.contains(char::is_whitespace)
+            || spec.contains(['=', '<', '>', '!', '~', '*', '['])
+            || spec.starts_with("__")
+            || variant_config.variants.get(spec).is_some()
+        {
+            continue;
+        }
+        diagnostics.push(
+            LintDiagnostic::new(
+                Severity::Warning,
+                "unpinned_run_dependency",
+                format!("run dependency `{spec}` has no version constraint"),
+                node.span(),
+            )
+            .with_help(format!(
+                "add a version constraint (e.g. `{spec} >=1.0`) or use a run export"
+            )),
+        );
+    }
+}
+
+/// Warn about URLs in the `about` section that are not web pages (e.g. `ftp://` or
+/// `file://` URLs), and collect them for the reachability check. URLs that cannot be
+/// parsed at all are reported by the parser.
+fn lint_about_urls(
+    output: &Node,
+    recipe: &Recipe,
+    diagnostics: &mut Vec<LintDiagnostic>,
+    urls: &mut AboutUrls,
+) {
+    let about = recipe.about();
+    for (field, url) in
+        ABOUT_URL_FIELDS
+            .into_iter()
+            .zip([&about.homepage, &about.repository, &about.documentation])
+    {
+        let Some(url) = url else {
+            continue;
+        };
+        let span = get_path(output, &["about", field])
+            .map(|node| *node.span())
+            .unwrap_or_else(|| *output.span());
+
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            diagnostics.push(
+                LintDiagnostic::new(
+                    Severity::Warning,
+                    "invalid_url",
+                    format!("`about.{field}` is not an http(s) URL: {url}"),
+                    &span,
+                )
+                .with_help("use the `https://` URL of the web page"),
+            );
+            continue;
+        }
+        urls.entry(url.clone()).or_default().push((field, span));
+    }
+}
+
+/// Check that a URL responds with a successful status (after following redirects).
+async fn check_url(client: &reqwest::Client, url: &Url) -> Result<(), String> {
+    let send = |method: reqwest::Method| {
+        client
+            .request(method, url.clone())
+            .timeout(URL_TIMEOUT)
+            .send()
+    };
+
+    let mut response = send(reqwest::Method::HEAD)
+        .await
+        .map_err(|err| err.to_string())?;
+    // not every server implements `HEAD` requests
+    if matches!(
+        response.status(),
+        reqwest::StatusCode::METHOD_NOT_ALLOWED | reqwest::StatusCode::NOT_IMPLEMENTED
+    ) {
+        response = send(reqwest::Method::GET)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("the server responded with {status}"))
+    }
+}
+
+/// Check the `build.skip` expressions: they have to be valid jinja expressions and they
+/// should depend on at least one variable, otherwise they are always (or never) true.
+fn lint_skip(output: &Node, diagnostics: &mut Vec<LintDiagnostic>) {
+    let Some(skip) = get_path(output, &["build", "skip"]) else {
+        return;
+    };
+    let mut scalars = Vec::new();
+    sequence_scalars(skip, &mut scalars);
+
+    for node in scalars {
+        let Some(condition) = node.as_scalar() else {
+            continue;
+        };
+        let condition = condition.as_str();
+        match referenced_vars_from_template(&format!("${{{{ {} }}}}", condition)) {
+            Err(err) => diagnostics.push(
+                LintDiagnostic::new(
+                    Severity::Error,
+                    "suspicious_skip",
+                    format!("skip condition `{condition}` is not a valid expression: {err}"),
+                    node.span(),
+                )
+                .with_help("skip conditions are plain jinja expressions, e.g. `win or py < 39`"),
+            ),
+            Ok(variables) if variables.is_empty() => diagnostics.push(
+                LintDiagnostic::new(
+                    Severity::Warning,
+                    "suspicious_skip",
+                    format!("skip condition `{condition}` does not depend on any variable"),
+                    node.span(),
+                )
+                .with_help("a constant condition either always or never skips the build"),
+            ),
+            Ok(_) => {}
+        }
+    }
+}
+
+/// Warn about variables used in jinja expressions, `if` selectors and skip conditions
+/// that are neither defined in the recipe context, the variant configuration nor by
+/// rattler-build itself.
+fn lint_jinja_variables(
+    output: &Node,
+    known_variables: &HashSet<String>,
+    diagnostics: &mut Vec<LintDiagnostic>,
+) {
+    let mut expressions = Vec::new();
+    collect_expressions(output, &mut expressions);
+    if let Some(skip) = get_path(output, &["build", "skip"]) {
+        let mut scalars = Vec::new();
+        sequence_scalars(skip, &mut scalars);
+        for node in scalars {
+            if let Some(condition) = node.as_scalar() {
+                expressions.push((format!("${{{{ {} }}}}", condition.as_str()), *node.span()));
+            }
+        }
+    }
+
+    for (template, span) in expressions {
+        // parse errors are reported by the parser itself
+        let Ok(variables) = referenced_vars_from_template(&template) else {
+            continue;
+        };
+        let mut undefined = variables
+            .into_iter()
+            .filter(|var| !known_variables.contains(var))
+            .collect::<Vec<_>>();
+        undefined.sort();
+        for var in undefined {
+            diagnostics.push(
+                LintDiagnostic::new(
+                    Severity::Warning,
+                    "undefined_variable",
+                    format!("variable `{var}` is never defined"),
+                    &span,
+                )
+                .with_help(format!(
+                    "define `{var}` in the `context` section or in a variant configuration"
+                )),
+            );
+        }
+    }
+}
+
+/// Collect all templates (scalars with `${{ ... }}` and `if` selectors) of a node.
+fn collect_expressions(node: &Node, expressions: &mut Vec<(String, Span)>) {
+    match node {
+        Node::Mapping(mapping) => {
+            for (key, value) in mapping.iter() {
+                // skip conditions are expressions without `${{ }}`, handled by the caller
+                if key.as_str() != "skip" {
+                    collect_expressions(value, expressions);
+                }
+            }
+        }
+        Node::Sequence(sequence) => {
+            for item in sequence.iter() {
+                match item {
+                    SequenceNodeInternal::Simple(node) => collect_expressions(node, expressions),
+                    SequenceNodeInternal::Conditional(selector) => {
+                        expressions.push((
+                            format!("${{{{ {} }}}}", selector.cond().as_str()),
+                            *selector.cond().span(),
+                        ));
+                        collect_expressions(selector.then(), expressions);
+                        if let Some(otherwise) = selector.otherwise() {
+                            collect_expressions(otherwise, expressions);
+                        }
+                    }
+                }
+            }
+        }
+        Node::Scalar(scalar) if scalar.contains("${{") => {
+            expressions.push((scalar.as_str().to_string(), *scalar.span()));
+        }
+        _ => {}
+    }
+}
+
+/// Print the diagnostics in the requested format.
+fn print_diagnostics(recipe_path: &Path, diagnostics: &[LintDiagnostic], format: LintFormat) {
+    match format {
+        LintFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(diagnostics).expect("diagnostics are serializable")
+        ),
+        LintFormat::Human => {
+            for diagnostic in diagnostics {
+                println!(
+                    "{}:{}:{}: {}[{}]: {}",
+                    recipe_path.display(),
+                    diagnostic.line,
+                    diagnostic.column,
+                    diagnostic.severity,
+                    diagnostic.code,
+                    diagnostic.message
+                );
+                if let Some(help) = &diagnostic.help {
+                    println!("  help: {help}");
+                }
+            }
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.severity == Severity::Error)
+                .count();
+            println!(
+                "{} error(s), {} warning(s)",
+                errors,
+                diagnostics.len() - errors
+            );
+        }
+    }
+}
+
+/// The report entries of the diagnostics (a single passed entry if there are none). The
+/// recipe path is made relative to the current directory, so that CI systems can link
+/// it to the file in the repository.
+fn report_entries(recipe_path: &Path, diagnostics: &[LintDiagnostic]) -> Vec<ReportEntry> {
+    let file = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| recipe_path.strip_prefix(cwd).ok())
+        .unwrap_or(recipe_path)
+        .to_path_buf();
+    let classname = file.display().to_string();
+
+    if diagnostics.is_empty() {
+        return vec![ReportEntry {
+            name: "lint".to_string(),
+            classname,
+            file: Some(file),
+            location: None,
+            duration: Duration::ZERO,
+            outcome: Outcome::Passed,
+        }];
+    }
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let mut message = diagnostic.message.clone();
+            if let Some(help) = &diagnostic.help {
+                message.push_str(&format!("\nhelp: {help}"));
+            }
+            ReportEntry {
+                name: diagnostic.code.to_string(),
+                classname: classname.clone(),
+                file: Some(file.clone()),
+                location: Some((diagnostic.line, diagnostic.column)),
+                duration: Duration::ZERO,
+                outcome: match diagnostic.severity {
+                    Severity::Error => Outcome::Failed(message),
+                    Severity::Warning => Outcome::Warning(message),
+                },
+            }
+        })
+        .collect()
+}
+
+/// The selector config used to evaluate the recipe and the variant configuration.
+pub(crate) fn lint_selector_config(target_platform: Platform) -> SelectorConfig {
+    SelectorConfig {
+        target_platform,
+        host_platform: target_platform,
+        build_platform: Platform::current(),
+        hash: None,
+        variant: BTreeMap::new(),
+        experimental: false,
+        allow_undefined: true,
+        lenient_skip: false,
+        subpackages: BTreeMap::new(),
+        environment: None,
+    }
+}
+
+/// Lint a recipe and print the diagnostics. Returns an error if any diagnostic is an error.
+pub async fn lint_from_args(args: LintOpts) -> miette::Result<()> {
+    let recipe_path = get_recipe_path(&args.recipe)?;
+    let recipe_text = fs::read_to_string(&recipe_path).into_diagnostic()?;
+
+    let selector_config = lint_selector_config(args.target_platform);
+    let variant_config =
+        VariantConfig::from_files(&args.variant_config, &selector_config).into_diagnostic()?;
+
+    let diagnostics = if args.online_lints {
+        let client = NetworkSettings::default().client().into_diagnostic()?;
+        lint_recipe_online(&recipe_text, &variant_config, &selector_config, &client).await
+    } else {
+        lint_recipe(&recipe_text, &variant_config, &selector_config)
+    };
+    print_diagnostics(&recipe_path, &diagnostics, args.format);
+    reporter::report_all(
+        &reporter::reporters(args.junit.as_deref()),
+        "rattler-build lint",
+        &report_entries(&recipe_path, &diagnostics),
+    )
+    .into_diagnostic()?;
+
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return Err(miette::miette!("{} has lint errors", recipe_path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lint_diagnostics() {
+        let recipe = r#"
+context:
+  version: "1.0"
+
+package:
+  name: foo
+  version: ${{ version }}
+
+build:
+  skip:
+    - true
+  unknown_option: 1
+
+requirements:
+  run:
+    - numpy
+    - python >=3.8
+    - ${{ pin_compatible("bar") }}
+    - if: unix and use_fancy
+      then: libfoo
+"#;
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            ..Default::default()
+        };
+        let diagnostics = lint_recipe(recipe, &VariantConfig::default(), &selector_config);
+        let codes = diagnostics
+            .iter()
+            .map(|d| (d.line, d.code))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            vec![
+                (11, "suspicious_skip"),
+                (12, "unknown_key"),
+                (16, "unpinned_run_dependency"),
+                (19, "undefined_variable"),
+                (20, "unpinned_run_dependency"),
+            ]
+        );
+        assert_eq!(
+            diagnostics[3].message,
+            "variable `use_fancy` is never defined"
+        );
+    }
+
+    #[test]
+    fn lint_urls() {
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            ..Default::default()
+        };
+        let lint = |about: &str| {
+            let recipe = format!(
+                "package:\n  name: foo\n  version: \"1.0\"\n\nabout:\n  license: MIT\n{about}"
+            );
+            lint_recipe(&recipe, &VariantConfig::default(), &selector_config)
+                .into_iter()
+                .map(|d| (d.line, d.severity, d.code))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lint("  homepage: https://example.com\n  repository: ftp://example.com/foo\n"),
+            vec![(8, Severity::Warning, "invalid_url")]
+        );
+        assert_eq!(
+            lint("  documentation: example.com/docs\n"),
+            vec![(7, Severity::Error, "invalid_url")]
+        );
+    }
+}
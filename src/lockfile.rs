@@ -0,0 +1,455 @@
+//! Lock files of the build, host and test environments, in the (version 1) format of
+//! `conda-lock`.
+//!
+//! The lock files of the build and host environments are stored in `info/lock/` of every
+//! package, and written to `--lockfile-dir` together with the lock files of the test
+//! environments. The build and host environments can be recreated later without solving
+//! with `--reuse-lockfile`:
+//!
+//! ```yaml
+//! version: 1
+//! metadata:
+//!   content_hash:
+//!     linux-64: 3f9a...
+//!   channels:
+//!   - url: https://conda.anaconda.org/conda-forge/
+//!     used_env_vars: []
+//!   platforms:
+//!   - linux-64
+//!   sources:
+//!   - recipe.yaml
+//!   custom_metadata:
+//!     output: libfoo
+//!     environment: host
+//! package:
+//! - name: zlib
+//!   version: 1.3.1
+//!   manager: conda
+//!   platform: linux-64
+//!   dependencies:
+//!     libgcc-ng: '>=12'
+//!   url: https://conda.anaconda.org/conda-forge/linux-64/zlib-1.3.1-h4ab18f5_1.conda
+//!   hash:
+//!     md5: 57d7dc60e9325e3de37ff8dffd18e814
+//!     sha256: cdd8...
+//!   category: main
+//!   optional: false
+//! ```
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{Channel, MatchSpec, ParseStrictness, Platform, RepoDataRecord};
+use rattler_digest::Sha256;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::tool_configuration;
+
+/// The file name of the lock file of an environment, e.g. `host.conda-lock.yml`.
+pub fn lockfile_name(environment: &str) -> String {
+    format!("{environment}.conda-lock.yml")
+}
+
+/// An error that occurs while reading or writing a lock file.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum LockfileError {
+    #[error("Failed to read or write the lock file {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("Invalid lock file {0}: {1}")]
+    Parse(PathBuf, #[source] serde_yaml::Error),
+
+    #[error("Failed to serialize the lock file: {0}")]
+    Serialize(#[from] serde_yaml::Error),
+
+    #[error("Invalid package {0} in the lock file: {1}")]
+    InvalidPackage(String, String),
+
+    #[error("Could not read the repodata of the locked packages: {0}")]
+    Repodata(#[from] rattler_repodata_gateway::GatewayError),
+
+    #[error("The locked package {0} ({1}) is not in the repodata of its channel")]
+    MissingRecord(String, Url),
+
+    #[error("The sha256 of the locked package {0} does not match the repodata of its channel")]
+    HashMismatch(String),
+}
+
+/// A lock file in the format of `conda-lock` (version 1).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CondaLock {
+    /// The version of the format, always 1
+    pub version: u32,
+    /// The metadata of the lock file
+    pub metadata: LockMetadata,
+    /// The locked packages
+    pub package: Vec<LockedPackage>,
+}
+
+/// The metadata of a lock file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockMetadata {
+    /// A hash of the locked packages per platform
+    pub content_hash: BTreeMap<String, String>,
+    /// The channels of the environment
+    pub channels: Vec<LockChannel>,
+    /// The platforms of the environment
+    pub platforms: Vec<String>,
+    /// The files that the environment was created from
+    pub sources: Vec<String>,
+    /// The output (`output`) and the environment (`environment`) that was locked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_metadata: Option<BTreeMap<String, String>>,
+}
+
+/// A channel of a lock file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockChannel {
+    /// The url of the channel
+    pub url: String,
+    /// The environment variables that are used in the url
+    #[serde(default)]
+    pub used_env_vars: Vec<String>,
+}
+
+/// A locked package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    /// The name of the package
+    pub name: String,
+    /// The version of the package
+    pub version: String,
+    /// The package manager, always `conda`
+    pub manager: String,
+    /// The platform of the environment
+    pub platform: String,
+    /// The dependencies of the package, by name
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+    /// The url of the package file
+    pub url: Url,
+    /// The hashes of the package file
+    pub hash: LockedHash,
+    /// The category of the package, always `main`
+    #[serde(default = "main_category")]
+    pub category: String,
+    /// Whether the package is optional
+    #[serde(default)]
+    pub optional: bool,
+}
+
+fn main_category() -> String {
+    "main".to_string()
+}
+
+/// The hashes of a locked package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedHash {
+    /// The MD5 hash of the package file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    /// The SHA256 hash of the package file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Split a dependency like `libgcc-ng >=12` into its name and its constraint (`*` if it
+/// has none).
+fn split_dependency(dependency: &str) -> (String, String) {
+    match dependency.trim().split_once(char::is_whitespace) {
+        Some((name, constraint)) => (name.to_string(), constraint.trim().to_string()),
+        None => (dependency.trim().to_string(), "*".to_string()),
+    }
+}
+
+impl CondaLock {
+    /// The lock file of an environment of an output, with the channels of the packages.
+    pub fn new(
+        output: &str,
+        environment: &str,
+        platform: Platform,
+        records: &[RepoDataRecord],
+    ) -> Self {
+        let channels = records
+            .iter()
+            .map(|record| record.channel.clone())
+            .collect::<BTreeSet<_>>();
+        let mut urls = records
+            .iter()
+            .map(|record| record.url.as_str())
+            .collect::<Vec<_>>();
+        urls.sort_unstable();
+        let content_hash = format!(
+            "{:x}",
+            rattler_digest::compute_bytes_digest::<Sha256>(urls.join("\n"))
+        );
+
+        let mut package = records
+            .iter()
+            .map(|record| {
+                let package_record = &record.package_record;
+                LockedPackage {
+                    name: package_record.name.as_normalized().to_string(),
+                    version: package_record.version.to_string(),
+                    manager: "conda".to_string(),
+                    platform: platform.to_string(),
+                    dependencies: package_record
+                        .depends
+                        .iter()
+                        .map(|dependency| split_dependency(dependency))
+                        .collect(),
+                    url: record.url.clone(),
+                    hash: LockedHash {
+                        md5: package_record.md5.map(|md5| format!("{md5:x}")),
+                        sha256: package_record.sha256.map(|sha256| format!("{sha256:x}")),
+                    },
+                    category: main_category(),
+                    optional: false,
+                }
+            })
+            .collect::<Vec<_>>();
+        package.sort_by(|a, b| a.name.cmp(&b.name));
+
+        CondaLock {
+            version: 1,
+            metadata: LockMetadata {
+                content_hash: BTreeMap::from([(platform.to_string(), content_hash)]),
+                channels: channels
+                    .into_iter()
+                    .map(|url| LockChannel {
+                        url,
+                        used_env_vars: vec![],
+                    })
+                    .collect(),
+                platforms: vec![platform.to_string()],
+                sources: vec!["recipe.yaml".to_string()],
+                custom_metadata: Some(BTreeMap::from([
+                    ("output".to_string(), output.to_string()),
+                    ("environment".to_string(), environment.to_string()),
+                ])),
+            },
+            package,
+        }
+    }
+
+    /// Read a lock file.
+    pub fn from_path(path: &Path) -> Result<Self, LockfileError> {
+        let content =
+            fs_err::read_to_string(path).map_err(|e| LockfileError::Io(path.to_path_buf(), e))?;
+        serde_yaml::from_str(&content).map_err(|e| LockfileError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Write the lock file.
+    pub fn write(&self, path: &Path) -> Result<(), LockfileError> {
+        let content = serde_yaml::to_string(self)?;
+        if let Some(parent) = path.parent() {
+            fs_err::create_dir_all(parent).map_err(|e| LockfileError::Io(path.to_path_buf(), e))?;
+        }
+        fs_err::write(path, content).map_err(|e| LockfileError::Io(path.to_path_buf(), e))
+    }
+
+    fn custom_metadata(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .custom_metadata
+            .as_ref()?
+            .get(key)
+            .map(String::as_str)
+    }
+
+    /// Whether this is the lock file of the environment of the output. Lock files without
+    /// an `output` apply to all outputs, and lock files without an `environment` are the
+    /// lock files of host environments.
+    pub fn locks(&self, output: &str, environment: &str) -> bool {
+        self.custom_metadata("output")
+            .map_or(true, |locked| locked == output)
+            && self.custom_metadata("environment").unwrap_or("host") == environment
+    }
+
+    /// The repodata records of the locked packages for a platform. The lock file only
+    /// records the urls and hashes of the packages, so the complete records (with the
+    /// build number, the run exports, the constraints, ...) are read from the repodata of
+    /// their channels. That way an environment from a lock file is the same as the solved
+    /// environment it was written from.
+    pub async fn records(
+        &self,
+        platform: Platform,
+        tool_configuration: &tool_configuration::Configuration,
+    ) -> Result<Vec<RepoDataRecord>, LockfileError> {
+        let packages = self
+            .package
+            .iter()
+            .filter(|package| package.manager == "conda" && package.platform == platform.as_str())
+            .collect::<Vec<_>>();
+        if packages.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let channels = packages
+            .iter()
+            .map(|package| package.channel_url())
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        let specs = packages
+            .iter()
+            .map(|package| {
+                MatchSpec::from_str(&package.name, ParseStrictness::Strict)
+                    .map_err(|e| LockfileError::InvalidPackage(package.name.clone(), e.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let repodata = tool_configuration
+            .repodata_gateway
+            .query(
+                channels.into_iter().map(Channel::from_url),
+                [platform, Platform::NoArch],
+                specs,
+            )
+            .recursive(false)
+            .await?;
+        let by_url = repodata
+            .iter()
+            .flat_map(|repodata| repodata.iter())
+            .map(|record| (&record.url, record))
+            .collect::<HashMap<_, _>>();
+
+        packages
+            .iter()
+            .map(|package| {
+                let record = by_url.get(&package.url).ok_or_else(|| {
+                    LockfileError::MissingRecord(package.name.clone(), package.url.clone())
+                })?;
+                if let (Some(locked), Some(actual)) =
+                    (&package.hash.sha256, &record.package_record.sha256)
+                {
+                    if !locked.eq_ignore_ascii_case(&format!("{actual:x}")) {
+                        return Err(LockfileError::HashMismatch(package.name.clone()));
+                    }
+                }
+                Ok((*record).clone())
+            })
+            .collect()
+    }
+}
+
+impl LockedPackage {
+    /// The url of the channel of the package (the url without the subdir and the file
+    /// name).
+    fn channel_url(&self) -> Result<Url, LockfileError> {
+        let mut segments = self
+            .url
+            .path_segments()
+            .map(|segments| segments.collect::<Vec<_>>())
+            .unwrap_or_default();
+        if segments.len() < 2 {
+            return Err(LockfileError::InvalidPackage(
+                self.name.clone(),
+                format!("`{}` is not the url of a package in a channel", self.url),
+            ));
+        }
+        segments.truncate(segments.len() - 2);
+        let mut channel = self.url.clone();
+        channel.set_path(&format!("{}/", segments.join("/")));
+        channel.set_query(None);
+        Ok(channel)
+    }
+}
+
+/// Find the lock file of the environment of an output among the lock files that are
+/// reused (`--reuse-lockfile`).
+pub fn find_reused(
+    lockfiles: &[PathBuf],
+    output: &str,
+    environment: &str,
+) -> Result<Option<(PathBuf, CondaLock)>, LockfileError> {
+    for path in lockfiles {
+        let lock = CondaLock::from_path(path)?;
+        if lock.locks(output, environment) {
+            return Ok(Some((path.clone(), lock)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rattler_conda_types::{PackageName, PackageRecord, VersionWithSource};
+
+    use super::*;
+
+    #[test]
+    fn test_lockfile_roundtrip() {
+        let url = Url::parse(
+            "https://conda.anaconda.org/conda-forge/linux-64/zlib-1.3.1-h4ab18f5_1.conda",
+        )
+        .unwrap();
+        let lock_yaml = r#"
+version: 1
+metadata:
+  content_hash:
+    linux-64: abc
+  channels:
+  - url: https://conda.anaconda.org/conda-forge/
+    used_env_vars: []
+  platforms:
+  - linux-64
+  sources:
+  - recipe.yaml
+  custom_metadata:
+    output: libfoo
+    environment: build
+package:
+- name: zlib
+  version: 1.3.1
+  manager: conda
+  platform: linux-64
+  dependencies:
+    libgcc-ng: '>=12'
+    libzlib: '*'
+  url: https://conda.anaconda.org/conda-forge/linux-64/zlib-1.3.1-h4ab18f5_1.conda
+  hash:
+    md5: 57d7dc60e9325e3de37ff8dffd18e814
+  category: main
+  optional: false
+"#;
+        let lock: CondaLock = serde_yaml::from_str(lock_yaml).unwrap();
+        assert!(lock.locks("libfoo", "build"));
+        assert!(!lock.locks("libfoo", "host"));
+        assert!(!lock.locks("libbar", "build"));
+
+        assert_eq!(lock.package[0].url, url);
+        assert_eq!(
+            lock.package[0].channel_url().unwrap().as_str(),
+            "https://conda.anaconda.org/conda-forge/"
+        );
+
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked("zlib"),
+            VersionWithSource::from_str("1.3.1").unwrap(),
+            "h4ab18f5_1".to_string(),
+        );
+        package_record.subdir = "linux-64".to_string();
+        package_record.depends = vec!["libgcc-ng >=12".to_string(), "libzlib".to_string()];
+        package_record.md5 = rattler_digest::parse_digest_from_hex::<rattler_digest::Md5>(
+            "57d7dc60e9325e3de37ff8dffd18e814",
+        );
+        let records = vec![RepoDataRecord {
+            package_record,
+            file_name: "zlib-1.3.1-h4ab18f5_1.conda".to_string(),
+            url,
+            channel: "https://conda.anaconda.org/conda-forge/".to_string(),
+        }];
+
+        let written = CondaLock::new("libfoo", "build", Platform::Linux64, &records);
+        assert_eq!(written.metadata.channels, lock.metadata.channels);
+        assert_eq!(
+            written.package[0].dependencies,
+            lock.package[0].dependencies
+        );
+        assert_eq!(written.package[0].hash, lock.package[0].hash);
+    }
+}
@@ -0,0 +1,377 @@
+//! A minimal language server for recipes (`rattler-build lsp`).
+//!
+//! The server speaks the Language Server Protocol over stdio and publishes the
+//! diagnostics of [`crate::lint`] for every open recipe. Recipes are re-validated on
+//! every change, and all open recipes are re-validated when a variant configuration
+//! (`variants.yaml` next to the recipe, or one of the files passed with `-m`) is saved.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::{
+    lint::{lint_recipe, lint_selector_config, LintDiagnostic, Severity},
+    variant_config::VariantConfig,
+};
+
+/// Options for the `lsp` subcommand.
+#[derive(Parser, Clone, Debug)]
+pub struct LspOpts {
+    /// The target platform used to evaluate selectors in the recipes.
+    #[arg(long, default_value_t = Platform::current())]
+    pub target_platform: Platform,
+
+    /// Variant configuration files used to evaluate the recipes, in addition to the
+    /// `variants.yaml` next to each recipe.
+    #[arg(short = 'm', long)]
+    pub variant_config: Vec<PathBuf>,
+}
+
+/// The name of the variant configuration file that is picked up next to a recipe.
+const VARIANTS_FILE: &str = "variants.yaml";
+
+/// JSON-RPC error code for requests the server does not implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Read a single message. Returns `None` when the client closed the stream.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write a single message.
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Convert a lint diagnostic to an LSP diagnostic. The range covers the rest of the line.
+fn to_lsp_diagnostic(diagnostic: &LintDiagnostic, text: &str) -> Value {
+    let line = diagnostic.line.saturating_sub(1);
+    let start = diagnostic.column.saturating_sub(1);
+    let end = text
+        .lines()
+        .nth(line)
+        .map(|line| line.trim_end().chars().count())
+        .unwrap_or(start)
+        .max(start);
+    let message = match &diagnostic.help {
+        Some(help) => format!("{}\nhelp: {}", diagnostic.message, help),
+        None => diagnostic.message.clone(),
+    };
+
+    json!({
+        "range": {
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        },
+        "severity": match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        "code": diagnostic.code,
+        "source": "rattler-build",
+        "message": message,
+    })
+}
+
+/// The state of the language server.
+struct Server {
+    opts: LspOpts,
+    /// The text of the open recipes, by URI
+    documents: BTreeMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn new(opts: LspOpts) -> Self {
+        Self {
+            opts,
+            documents: BTreeMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Whether the file is a variant configuration instead of a recipe.
+    fn is_variant_config(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| name == VARIANTS_FILE)
+            || self.opts.variant_config.iter().any(|file| file == path)
+    }
+
+    /// Lint a recipe with the variant configuration that applies to it.
+    fn diagnostics(&self, path: Option<&Path>, text: &str) -> Vec<Value> {
+        let selector_config = lint_selector_config(self.opts.target_platform);
+
+        let mut variant_files = self.opts.variant_config.clone();
+        if let Some(variants) = path
+            .and_then(Path::parent)
+            .map(|dir| dir.join(VARIANTS_FILE))
+            .filter(|variants| variants.is_file())
+        {
+            variant_files.push(variants);
+        }
+
+        match VariantConfig::from_files(&variant_files, &selector_config) {
+            Ok(variant_config) => lint_recipe(text, &variant_config, &selector_config)
+                .iter()
+                .map(|diagnostic| to_lsp_diagnostic(diagnostic, text))
+                .collect(),
+            Err(err) => vec![json!({
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 0 },
+                },
+                "severity": 1,
+                "code": "variant_config",
+                "source": "rattler-build",
+                "message": format!("failed to load the variant configuration: {}", err),
+            })],
+        }
+    }
+
+    fn publish(
+        &self,
+        writer: &mut impl Write,
+        uri: &str,
+        diagnostics: Vec<Value>,
+    ) -> io::Result<()> {
+        write_message(
+            writer,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": uri, "diagnostics": diagnostics },
+            }),
+        )
+    }
+
+    /// Re-validate a single open recipe.
+    fn validate(&self, writer: &mut impl Write, uri: &str) -> io::Result<()> {
+        let Some(text) = self.documents.get(uri) else {
+            return Ok(());
+        };
+        let path = Url::parse(uri).ok().and_then(|url| url.to_file_path().ok());
+        self.publish(writer, uri, self.diagnostics(path.as_deref(), text))
+    }
+
+    /// Re-validate all open recipes, e.g. after a variant configuration changed.
+    fn validate_all(&self, writer: &mut impl Write) -> io::Result<()> {
+        for uri in self.documents.keys() {
+            self.validate(writer, uri)?;
+        }
+        Ok(())
+    }
+
+    /// Handle the change of a document. Returns `true` if the document is a recipe.
+    fn update(&mut self, uri: &str, text: Option<String>) -> bool {
+        let path = Url::parse(uri).ok().and_then(|url| url.to_file_path().ok());
+        if path.is_some_and(|path| self.is_variant_config(&path)) {
+            return false;
+        }
+        if let Some(text) = text {
+            self.documents.insert(uri.to_string(), text);
+        }
+        true
+    }
+
+    /// Handle a single message. Returns `false` when the server should exit.
+    fn handle(&mut self, writer: &mut impl Write, message: Value) -> io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        if let Some(id) = message.get("id") {
+            // the server never sends requests, so responses from the client are ignored
+            if method.is_empty() {
+                return Ok(true);
+            }
+            let response = match method {
+                "initialize" => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "result": {
+                        "capabilities": {
+                            "textDocumentSync": {
+                                "openClose": true,
+                                // the client always sends the full text
+                                "change": 1,
+                                "save": true,
+                            },
+                        },
+                        "serverInfo": {
+                            "name": "rattler-build",
+                            "version": env!("CARGO_PKG_VERSION"),
+                        },
+                    },
+                }),
+                "shutdown" => {
+                    self.shutdown = true;
+                    json!({ "jsonrpc": "2.0", "id": id, "result": null })
+                }
+                _ => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("method `{}` is not supported", method),
+                    },
+                }),
+            };
+            write_message(writer, &response)?;
+            return Ok(true);
+        }
+
+        match method {
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().map(str::to_string);
+                if self.update(uri, text) {
+                    self.validate(writer, uri)?;
+                }
+            }
+            "textDocument/didChange" => {
+                // full document sync, the last change contains the complete text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                    .map(str::to_string);
+                if self.update(uri, text) {
+                    self.validate(writer, uri)?;
+                }
+            }
+            "textDocument/didSave" => {
+                if !self.update(uri, None) {
+                    self.validate_all(writer)?;
+                }
+            }
+            "textDocument/didClose" => {
+                if self.documents.remove(uri).is_some() {
+                    self.publish(writer, uri, Vec::new())?;
+                }
+            }
+            "workspace/didChangeWatchedFiles" => self.validate_all(writer)?,
+            _ => {}
+        }
+        Ok(true)
+    }
+}
+
+/// Run the language server until the client sends `exit` or closes the stream.
+pub fn run_server(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    opts: LspOpts,
+) -> io::Result<()> {
+    let mut server = Server::new(opts);
+    while let Some(message) = read_message(&mut reader)? {
+        if !server.handle(&mut writer, message)? {
+            break;
+        }
+    }
+    if !server.shutdown {
+        tracing::warn!("the language client exited without a shutdown request");
+    }
+    Ok(())
+}
+
+/// Run the language server on stdio.
+pub fn lsp_from_args(opts: LspOpts) -> miette::Result<()> {
+    let stdin = io::stdin();
+    run_server(stdin.lock(), io::stdout().lock(), opts).into_diagnostic()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(message: Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let recipe =
+            "package:\n  name: foo\n  version: \"1.0\"\n\nrequirements:\n  run:\n    - numpy\n";
+        let uri = "file:///tmp/foo/recipe.yaml";
+        let input = [
+            frame(json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} })),
+            frame(json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "yaml", "version": 1, "text": recipe } },
+            })),
+            frame(json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" })),
+            frame(json!({ "jsonrpc": "2.0", "method": "exit" })),
+        ]
+        .concat();
+
+        let opts = LspOpts {
+            target_platform: Platform::Linux64,
+            variant_config: Vec::new(),
+        };
+        let mut output = Vec::new();
+        run_server(io::Cursor::new(input), &mut output, opts).unwrap();
+
+        let mut reader = io::Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["id"], 1);
+        assert_eq!(
+            messages[0]["result"]["capabilities"]["textDocumentSync"]["change"],
+            1
+        );
+
+        let params = &messages[1]["params"];
+        assert_eq!(params["uri"], uri);
+        let codes = params["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["code"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(codes, vec!["missing_license", "unpinned_run_dependency"]);
+        assert_eq!(
+            params["diagnostics"][1]["range"]["start"],
+            json!({ "line": 6, "character": 6 })
+        );
+
+        assert_eq!(messages[2]["id"], 2);
+    }
+}
@@ -9,18 +9,38 @@ use std::{
 use clap::{CommandFactory, Parser};
 use miette::IntoDiagnostic;
 use rattler_build::{
-    console_utils::init_logging,
-    get_build_output, get_recipe_path, get_tool_config,
+    apply_patches::apply_patches_from_args,
+    apply_project_defaults, build_in_runner, build_order,
+    cache::cache_from_args,
+    changed::changed_from_args,
+    console_utils::{init_logging, LogStyle},
+    convert::convert_from_args,
+    debug_from_args,
+    dry_run::dry_run,
+    generate_patch::generate_patch_from_args,
+    get_build_output, get_recipe_path, get_recipe_paths, get_tool_config,
+    index::index_from_args,
+    lint::lint_from_args,
+    lsp::lsp_from_args,
+    metadata::log_skipped_outputs,
+    metapackage::make_metapackage_from_args,
     opt::{App, ShellCompletion, SubCommands},
     rebuild_from_args,
     recipe_generator::generate_recipe,
-    run_build_from_args, run_test_from_args, sort_build_outputs_topologically, upload_from_args,
+    render_from_args, run_build_from_args, run_test_from_args, sort_build_outputs_topologically,
+    update_checksums::{fetch_checksums_from_args, update_checksums_from_args},
+    upload_from_args,
     utils::get_current_timestamp,
 };
 
 #[tokio::main]
 async fn main() -> miette::Result<()> {
     let app = App::parse();
+    if matches!(app.log_style, LogStyle::Json) {
+        // report errors as JSON as well, so that e.g. an `input_required` error can be
+        // handled by the caller
+        miette::set_hook(Box::new(|_| Box::new(miette::JSONReportHandler::new())))?;
+    }
     let log_handler = if !app.is_tui() {
         Some(
             init_logging(
@@ -55,9 +75,15 @@ async fn main() -> miette::Result<()> {
             print_completions(shell, &mut cmd);
             Ok(())
         }
-        Some(SubCommands::Build(build_args)) => {
-            let mut recipe_paths = Vec::new();
-            if !std::io::stdin().is_terminal()
+        Some(SubCommands::Build(mut build_args)) => {
+            let project_config = apply_project_defaults(&mut build_args)?;
+            if build_in_runner(&build_args, project_config.as_ref())? {
+                return Ok(());
+            }
+
+            // in non-interactive mode, stdin may be open without anybody writing to it
+            let recipe_paths = if !std::io::stdin().is_terminal()
+                && !build_args.common.non_interactive
                 && build_args.recipe.len() == 1
                 && get_recipe_path(&build_args.recipe[0]).is_err()
             {
@@ -71,21 +97,15 @@ async fn main() -> miette::Result<()> {
                     &mut File::create(&recipe_path).into_diagnostic()?,
                 )
                 .into_diagnostic()?;
-                recipe_paths.push(get_recipe_path(&recipe_path)?);
+                vec![get_recipe_path(&recipe_path)?]
             } else {
-                for recipe_path in &build_args.recipe {
-                    recipe_paths.push(get_recipe_path(recipe_path)?);
-                }
-                if let Some(recipe_dir) = &build_args.recipe_dir {
-                    for entry in ignore::Walk::new(recipe_dir) {
-                        let entry = entry.into_diagnostic()?;
-                        if entry.path().is_dir() {
-                            if let Ok(recipe_path) = get_recipe_path(entry.path()) {
-                                recipe_paths.push(recipe_path);
-                            }
-                        }
-                    }
-                }
+                get_recipe_paths(&build_args)?
+            };
+
+            if build_args.with_solve && !build_args.render_only {
+                return Err(miette::miette!(
+                    "`--with-solve` can only be used together with `--render-only`"
+                ));
             }
 
             if build_args.tui {
@@ -111,6 +131,8 @@ async fn main() -> miette::Result<()> {
                 }
 
                 if build_args.render_only {
+                    log_skipped_outputs(&tool_config.skipped_outputs.lock().unwrap())
+                        .into_diagnostic()?;
                     println!(
                         "{}",
                         serde_json::to_string_pretty(&outputs).into_diagnostic()?
@@ -119,6 +141,16 @@ async fn main() -> miette::Result<()> {
                 }
 
                 sort_build_outputs_topologically(&mut outputs, build_args.up_to.as_deref())?;
+                if build_args.build_order {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&build_order(&outputs)).into_diagnostic()?
+                    );
+                    return Ok(());
+                }
+                if build_args.dry_run {
+                    return dry_run(outputs, &tool_config).await;
+                }
                 run_build_from_args(outputs, tool_config).await?;
             }
             Ok(())
@@ -133,8 +165,37 @@ async fn main() -> miette::Result<()> {
             )
             .await
         }
+        Some(SubCommands::Debug(debug_args)) => {
+            debug_from_args(debug_args, log_handler.expect("logger is not initialized")).await
+        }
+        Some(SubCommands::GeneratePatch(args)) => {
+            generate_patch_from_args(args, log_handler.expect("logger is not initialized")).await
+        }
+        Some(SubCommands::ApplyPatches(args)) => {
+            apply_patches_from_args(args, log_handler.expect("logger is not initialized")).await
+        }
+        Some(SubCommands::UpdateChecksums(args)) => update_checksums_from_args(args).await,
+        Some(SubCommands::FetchChecksums(args)) => fetch_checksums_from_args(args).await,
+        Some(SubCommands::MakeMetapackage(args)) => make_metapackage_from_args(args),
+        Some(SubCommands::Convert(args)) => convert_from_args(args),
+        Some(SubCommands::Render(render_args)) => {
+            render_from_args(render_args, log_handler.expect("logger is not initialized")).await
+        }
         Some(SubCommands::Upload(upload_args)) => upload_from_args(upload_args).await,
         Some(SubCommands::GenerateRecipe(args)) => generate_recipe(args).await,
+        Some(SubCommands::Changed(args)) => changed_from_args(args),
+        Some(SubCommands::Lint(args)) => lint_from_args(args).await,
+        Some(SubCommands::Lsp(args)) => lsp_from_args(args),
+        #[cfg(unix)]
+        Some(SubCommands::Daemon(args)) => {
+            rattler_build::daemon::daemon_from_args(
+                args,
+                log_handler.expect("logger is not initialized"),
+            )
+            .await
+        }
+        Some(SubCommands::Cache(args)) => cache_from_args(args).await,
+        Some(SubCommands::Index(args)) => index_from_args(args),
         Some(SubCommands::Auth(args)) => rattler::cli::auth::execute(args).await.into_diagnostic(),
         None => {
             _ = App::command().print_long_help();
@@ -17,16 +17,20 @@ use rattler_conda_types::{
     package::{ArchiveType, PathType, PathsEntry, PathsJson},
     Channel, PackageName, Platform, RepoDataRecord,
 };
-use rattler_index::index;
 use rattler_package_streaming::write::CompressionLevel;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 use crate::{
+    build_stats::ResourceStats,
     console_utils::github_integration_enabled,
+    emulation::Emulation,
     hash::HashInfo,
-    recipe::parser::{Recipe, Source},
+    hooks::PostBuildHook,
+    index::index_channel,
+    recipe::parser::{Recipe, SkipReason, Source},
     render::resolved_dependencies::FinalizedDependencies,
+    sbom::SbomFormat,
     system_tools::SystemTools,
 };
 /// A Git revision
@@ -77,6 +81,29 @@ pub struct Directories {
     pub output_dir: PathBuf,
 }
 
+/// The environment variable with the timestamp (in seconds since the epoch) that
+/// reproducible builds use instead of the current time.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// The timestamp of a build: `SOURCE_DATE_EPOCH` if it is set, otherwise the current time.
+pub fn build_timestamp() -> miette::Result<DateTime<Utc>> {
+    let Ok(epoch) = std::env::var(SOURCE_DATE_EPOCH) else {
+        return Ok(Utc::now());
+    };
+    epoch
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .ok_or_else(|| {
+            miette::miette!(
+                "`{}` must be a number of seconds since the epoch, got `{}`",
+                SOURCE_DATE_EPOCH,
+                epoch
+            )
+        })
+}
+
 fn get_build_dir(
     output_dir: &Path,
     name: &str,
@@ -252,6 +279,42 @@ pub struct BuildConfiguration {
     /// Wether to set additional environment variables to force colors in the build script or not
     #[serde(skip_serializing, default = "default_true")]
     pub force_colors: bool,
+    /// Whether this is a development build that refers back to the local sources
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dev: bool,
+    /// Whether overlinking is an error, regardless of the recipe
+    #[serde(skip_serializing, default)]
+    pub error_overlinking: bool,
+    /// Whether overdepending is an error, regardless of the recipe
+    #[serde(skip_serializing, default)]
+    pub error_overdepending: bool,
+    /// The channel url that the package is uploaded to after the build
+    #[serde(skip_serializing, default)]
+    pub upload_to: Option<Url>,
+    /// How the binaries of the target platform are emulated (with `--emulate`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emulation: Option<Emulation>,
+    /// The format of the SBOM that is stored in the package (with `--sbom`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sbom: Option<SbomFormat>,
+    /// Whether to write a provenance statement next to the package (with `--provenance`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub provenance: bool,
+    /// The hooks that run after the package was built and tested
+    #[serde(skip_serializing, default)]
+    pub post_build: Vec<PostBuildHook>,
+    /// The directory that the lock files of the environments are written to (with
+    /// `--lockfile-dir`)
+    #[serde(skip_serializing, default)]
+    pub lockfile_dir: Option<PathBuf>,
+    /// Lock files whose environments are installed instead of solving the requirements
+    /// (with `--reuse-lockfile`)
+    #[serde(skip_serializing, default)]
+    pub reuse_lockfiles: Vec<PathBuf>,
+    /// Whether the strictest normalization for reproducible packages is applied (with
+    /// `--reproducible`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reproducible: bool,
 }
 
 impl BuildConfiguration {
@@ -288,6 +351,120 @@ pub struct BuildSummary {
     pub paths: Option<PathsJson>,
     ///  Whether the build was successful or not
     pub failed: bool,
+    /// The result of scanning the artifact with the `--scan-cmd`
+    pub scan: Option<ScanResult>,
+    /// The resource usage of the build
+    pub build_stats: Option<ResourceStats>,
+    /// The resource usage of the tests and whether they passed
+    pub test_stats: Option<(ResourceStats, bool)>,
+    /// Why the tests were skipped, if they were skipped
+    pub test_skipped: Option<String>,
+    /// The provenance statement that was written next to the artifact
+    pub provenance: Option<PathBuf>,
+    /// The detached signatures of the artifact and its provenance statement
+    pub signatures: Vec<PathBuf>,
+}
+
+/// The result of running the `--scan-cmd` on an artifact
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanResult {
+    /// The command that was run
+    pub command: String,
+    /// Whether the command exited with a zero exit code
+    pub passed: bool,
+    /// The exit code of the command, if it was not terminated by a signal
+    pub exit_code: Option<i32>,
+}
+
+impl fmt::Display for ScanResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.passed, self.exit_code) {
+            (true, _) => write!(f, "passed (`{}`)", self.command),
+            (false, Some(code)) => write!(f, "failed with exit code {} (`{}`)", code, self.command),
+            (false, None) => write!(f, "failed (`{}`)", self.command),
+        }
+    }
+}
+
+/// An output that was not built because one of its `skip` conditions evaluated to true
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedOutput {
+    /// The name of the output
+    pub name: PackageName,
+    /// The version of the output
+    pub version: String,
+    /// The variant that was skipped
+    pub variant: BTreeMap<String, String>,
+    /// The condition that caused the output to be skipped
+    pub reason: SkipReason,
+}
+
+/// Log a table of the skipped outputs and the conditions that caused them to be skipped,
+/// and add it to the GitHub step summary when the integration is enabled.
+pub fn log_skipped_outputs(skipped: &[SkippedOutput]) -> Result<(), std::io::Error> {
+    if skipped.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(comfy_table::presets::UTF8_FULL_CONDENSED)
+        .apply_modifier(comfy_table::modifiers::UTF8_ROUND_CORNERS)
+        .set_header(vec!["Skipped output", "Variant", "Condition", "Location"]);
+    let rows = skipped
+        .iter()
+        .map(|output| {
+            let variant = output
+                .variant
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(", ");
+            vec![
+                format!("{}-{}", output.name.as_normalized(), output.version),
+                variant,
+                if output.reason.variables.is_empty() {
+                    output.reason.condition.clone()
+                } else {
+                    format!(
+                        "{} ({})",
+                        output.reason.condition,
+                        output.reason.variables_str()
+                    )
+                },
+                format!("{}:{}", output.reason.line, output.reason.column),
+            ]
+        })
+        .collect::<Vec<_>>();
+    for row in &rows {
+        table.add_row(row);
+    }
+    tracing::info!("Skipped outputs:\n{}\n", table);
+
+    if let Ok(github_summary) = std::env::var("GITHUB_STEP_SUMMARY") {
+        if !github_integration_enabled() {
+            return Ok(());
+        }
+        let mut summary_file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(github_summary)?;
+
+        writeln!(summary_file, "### Skipped outputs")?;
+        writeln!(summary_file)?;
+        writeln!(summary_file, "| Output | Variant | Condition | Location |")?;
+        writeln!(summary_file, "| --- | --- | --- | --- |")?;
+        for row in &rows {
+            writeln!(
+                summary_file,
+                "| {} | {} | `{}` | {} |",
+                row[0], row[1], row[2], row[3]
+            )?;
+        }
+        writeln!(summary_file)?;
+    }
+
+    Ok(())
 }
 
 /// A output. This is the central element that is passed to the `run_build` function
@@ -328,17 +505,52 @@ impl Output {
         self.recipe.build().string()
     }
 
+    /// Index the output directory for the target platform of this output, like the
+    /// `index` command (including the `repodata.json.zst`). Outputs that are built in
+    /// parallel share the output directory, so only one of them indexes a subdirectory
+    /// at a time; waiting for the others happens off the async runtime.
+    pub async fn index_output_dir(&self) -> Result<(), std::io::Error> {
+        let output_dir = self.build_configuration.directories.output_dir.clone();
+        let target_platform = self.build_configuration.target_platform;
+        tokio::task::spawn_blocking(move || index_channel(&output_dir, Some(target_platform), true))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    }
+
     /// The channels to use when resolving dependencies
-    pub fn reindex_channels(&self) -> Result<Vec<Url>, std::io::Error> {
+    pub async fn reindex_channels(&self) -> Result<Vec<Url>, std::io::Error> {
         let output_dir = &self.build_configuration.directories.output_dir;
 
-        index(output_dir, Some(&self.build_configuration.target_platform))?;
+        self.index_output_dir().await?;
 
         Ok(iter::once(Channel::from_directory(output_dir).base_url)
             .chain(self.build_configuration.channels.iter().cloned())
             .collect())
     }
 
+    /// The channels to use when resolving dependencies. With `--render-only` and
+    /// `--dry-run` nothing is written, so the output directory is not indexed and only
+    /// used as a channel if it was indexed before.
+    pub async fn channels(
+        &self,
+        tool_configuration: &crate::tool_configuration::Configuration,
+    ) -> Result<Vec<Url>, std::io::Error> {
+        if !tool_configuration.render_only {
+            return self.reindex_channels().await;
+        }
+
+        let output_dir = &self.build_configuration.directories.output_dir;
+        let indexed = output_dir
+            .join(Platform::NoArch.as_str())
+            .join("repodata.json")
+            .is_file();
+        Ok(indexed
+            .then(|| Channel::from_directory(output_dir).base_url)
+            .into_iter()
+            .chain(self.build_configuration.channels.iter().cloned())
+            .collect())
+    }
+
     /// retrieve an identifier for this output ({name}-{version}-{build_string})
     pub fn identifier(&self) -> Option<String> {
         Some(format!(
@@ -370,6 +582,36 @@ impl Output {
         summary.paths = Some(paths.clone());
     }
 
+    /// Record the provenance statement of the artifact
+    pub fn record_provenance(&self, provenance: &Path) {
+        self.build_summary.lock().unwrap().provenance = Some(provenance.to_path_buf());
+    }
+
+    /// Record the detached signatures of the artifact
+    pub fn record_signatures(&self, signatures: Vec<PathBuf>) {
+        self.build_summary.lock().unwrap().signatures = signatures;
+    }
+
+    /// Record the result of scanning the artifact
+    pub fn record_scan(&self, scan: ScanResult) {
+        self.build_summary.lock().unwrap().scan = Some(scan);
+    }
+
+    /// Record the resource usage of the build
+    pub fn record_build_stats(&self, stats: ResourceStats) {
+        self.build_summary.lock().unwrap().build_stats = Some(stats);
+    }
+
+    /// Record the resource usage of the tests and whether they passed
+    pub fn record_test_stats(&self, stats: ResourceStats, passed: bool) {
+        self.build_summary.lock().unwrap().test_stats = Some((stats, passed));
+    }
+
+    /// Record that the tests were skipped and why
+    pub fn record_test_skipped(&self, reason: &str) {
+        self.build_summary.lock().unwrap().test_skipped = Some(reason.to_string());
+    }
+
     /// Record the end of the build
     pub fn record_build_end(&self) {
         let mut summary = self.build_summary.lock().unwrap();
@@ -434,6 +676,14 @@ impl Output {
         } else {
             tracing::info!("No artifact was created");
         }
+        match &summary.scan {
+            Some(scan) if scan.passed => tracing::info!("Scan: {}", scan),
+            Some(scan) => tracing::error!("Scan: {}", scan),
+            None => {}
+        }
+        if let Some(reason) = &summary.test_skipped {
+            tracing::warn!("Tests skipped: {}", reason);
+        }
         tracing::info!("{}", self);
 
         if !summary.warnings.is_empty() {
@@ -466,6 +716,13 @@ impl Output {
                 writeln!(summary_file, "**No artifact was created**")?;
             }
 
+            if let Some(scan) = &summary.scan {
+                writeln!(summary_file, "**Scan**: {}", scan)?;
+            }
+            if let Some(reason) = &summary.test_skipped {
+                writeln!(summary_file, "**Tests skipped**: {}", reason)?;
+            }
+
             if let Some(paths) = &summary.paths {
                 if paths.paths.is_empty() {
                     writeln!(summary_file, "Included files: **No files included**")?;
@@ -630,8 +887,8 @@ mod test {
     use chrono::TimeZone;
     use insta::assert_yaml_snapshot;
     use rattler_conda_types::{
-        MatchSpec, NoArchType, PackageName, PackageRecord, ParseStrictness, RepoDataRecord,
-        VersionWithSource,
+        Channel, MatchSpec, NoArchType, PackageName, PackageRecord, ParseStrictness,
+        RepoDataRecord, VersionWithSource,
     };
     use rattler_digest::{parse_digest_from_hex, Md5, Sha256};
     use url::Url;
@@ -734,6 +991,33 @@ mod test {
         assert_yaml_snapshot!(recipe_path, output);
     }
 
+    #[tokio::test]
+    async fn channels_without_indexing() {
+        let test_data_dir =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/rendered_recipes");
+        let recipe = std::fs::read_to_string(test_data_dir.join("rich_recipe.yaml")).unwrap();
+        let mut output: Output = serde_yaml::from_str(&recipe).unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        output.build_configuration.directories.output_dir = tmp.path().to_path_buf();
+        let output_channel = Channel::from_directory(tmp.path()).base_url;
+
+        // a dry run does not index (or create) anything in the output directory
+        let dry_run = crate::tool_configuration::Configuration {
+            render_only: true,
+            ..Default::default()
+        };
+        let channels = output.channels(&dry_run).await.unwrap();
+        assert!(!channels.contains(&output_channel));
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+
+        // a build indexes the output directory, which a later dry run uses as well
+        let channels = output.channels(&Default::default()).await.unwrap();
+        assert_eq!(channels[0], output_channel);
+        assert!(tmp.path().join("noarch/repodata.json").is_file());
+        let channels = output.channels(&dry_run).await.unwrap();
+        assert_eq!(channels[0], output_channel);
+    }
+
     #[test]
     fn read_recipe_with_sources() {
         let test_data_dir =
@@ -0,0 +1,383 @@
+//! Create metapackages (packages without files that only carry dependencies) directly
+//! from the command line or a small spec file, without writing a recipe.
+//!
+//! ```yaml
+//! name: my-stack
+//! version: "1.0"
+//! depends:
+//!   - numpy >=2
+//!   - scipy
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use fs_err as fs;
+use miette::{IntoDiagnostic, WrapErr};
+use rattler_conda_types::{
+    package::{AboutJson, ArchiveType, IndexJson, PackageFile, PathsJson},
+    MatchSpec, NoArchType, PackageName, ParseStrictness, Platform,
+};
+use rattler_package_streaming::write::{
+    write_conda_package, write_tar_bz2_package, CompressionLevel,
+};
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+use crate::{metadata::PackagingSettings, opt::PackageFormatAndCompression};
+
+/// Options for the `make-metapackage` subcommand.
+#[derive(Parser, Debug, Clone)]
+pub struct MetapackageOpts {
+    /// A YAML file with the fields of the metapackage (`name`, `version`, `depends`,
+    /// ...). The options on the command line take precedence.
+    #[arg(long)]
+    pub spec: Option<PathBuf>,
+
+    /// The name of the package
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// The version of the package
+    #[arg(long)]
+    pub version: Option<String>,
+
+    /// The build number of the package
+    #[arg(long)]
+    pub build_number: Option<u64>,
+
+    /// The build string of the package. Defaults to a hash of the dependencies and the
+    /// build number (`h<hash>_<number>`).
+    #[arg(long)]
+    pub build_string: Option<String>,
+
+    /// A run dependency of the package. Can be repeated.
+    #[arg(long = "depends")]
+    pub depends: Vec<String>,
+
+    /// A run constraint of the package. Can be repeated.
+    #[arg(long = "constrains")]
+    pub constrains: Vec<String>,
+
+    /// The license of the package
+    #[arg(long)]
+    pub license: Option<String>,
+
+    /// The summary of the package
+    #[arg(long)]
+    pub summary: Option<String>,
+
+    /// The platform (subdir) of the package. Defaults to a `noarch: generic` package.
+    #[arg(long)]
+    pub platform: Option<Platform>,
+
+    /// The channel directory the package is written to (in the subdirectory of its
+    /// platform)
+    #[arg(long, default_value = "output")]
+    pub output_dir: PathBuf,
+
+    /// The package format, `conda` or `tar-bz2` (optionally with a compression level,
+    /// e.g. `conda:19`)
+    #[arg(long, default_value = "conda")]
+    pub package_format: PackageFormatAndCompression,
+}
+
+/// The fields of a metapackage.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetapackageSpec {
+    /// The name of the package
+    pub name: Option<String>,
+    /// The version of the package. Numbers are accepted as well, but versions like
+    /// `1.10` have to be quoted (YAML reads them as `1.1`).
+    #[serde(default, deserialize_with = "deserialize_version")]
+    pub version: Option<String>,
+    /// The build number of the package
+    pub build_number: Option<u64>,
+    /// The build string of the package
+    pub build_string: Option<String>,
+    /// The run dependencies
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// The run constraints
+    #[serde(default)]
+    pub constrains: Vec<String>,
+    /// The license of the package
+    pub license: Option<String>,
+    /// The summary of the package
+    pub summary: Option<String>,
+    /// The platform (subdir) of the package
+    pub platform: Option<Platform>,
+}
+
+/// Accept unquoted versions like `1.0` that YAML parses as numbers.
+fn deserialize_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let value = Option::<serde_yaml::Value>::deserialize(deserializer)?;
+    match value {
+        None => Ok(None),
+        Some(serde_yaml::Value::String(version)) => Ok(Some(version)),
+        Some(serde_yaml::Value::Number(version)) => Ok(Some(version.to_string())),
+        Some(_) => Err(serde::de::Error::custom("expected a version string")),
+    }
+}
+
+impl MetapackageSpec {
+    /// Read a spec file.
+    pub fn from_path(path: &Path) -> miette::Result<Self> {
+        let content = fs::read_to_string(path).into_diagnostic()?;
+        serde_yaml::from_str(&content)
+            .map_err(|e| miette::miette!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    /// Override the fields of the spec with the ones given on the command line. The
+    /// dependencies on the command line are added to the ones of the spec.
+    fn with_args(mut self, args: &MetapackageOpts) -> Self {
+        self.name = args.name.clone().or(self.name);
+        self.version = args.version.clone().or(self.version);
+        self.build_number = args.build_number.or(self.build_number);
+        self.build_string = args.build_string.clone().or(self.build_string);
+        self.depends.extend(args.depends.iter().cloned());
+        self.constrains.extend(args.constrains.iter().cloned());
+        self.license = args.license.clone().or(self.license);
+        self.summary = args.summary.clone().or(self.summary);
+        self.platform = args.platform.or(self.platform);
+        self
+    }
+
+    /// Create the `index.json` of the metapackage. The dependencies are validated as
+    /// match specs.
+    pub fn index_json(&self) -> miette::Result<IndexJson> {
+        let name = self
+            .name
+            .as_deref()
+            .ok_or_else(|| miette::miette!("The metapackage has no `name`"))?;
+        let name = PackageName::try_from(name).into_diagnostic()?;
+        let version = self
+            .version
+            .as_deref()
+            .ok_or_else(|| miette::miette!("The metapackage has no `version`"))?;
+
+        let parse_specs = |specs: &[String], field: &str| {
+            specs
+                .iter()
+                .map(|spec| {
+                    MatchSpec::from_str(spec, ParseStrictness::Strict)
+                        .map(|spec| spec.to_string())
+                        .map_err(|e| {
+                            miette::miette!("Invalid spec `{}` in `{}`: {}", spec, field, e)
+                        })
+                })
+                .collect::<miette::Result<Vec<_>>>()
+        };
+        let depends = parse_specs(&self.depends, "depends")?;
+        let constrains = parse_specs(&self.constrains, "constrains")?;
+
+        let build_number = self.build_number.unwrap_or(0);
+        let build = self
+            .build_string
+            .clone()
+            .unwrap_or_else(|| default_build_string(&depends, &constrains, build_number));
+
+        let platform = self.platform.unwrap_or(Platform::NoArch);
+        Ok(IndexJson {
+            name,
+            version: version
+                .parse()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid version `{}`", version))?,
+            build,
+            build_number,
+            arch: platform.arch().map(|a| a.to_string()),
+            platform: platform.only_platform().map(|p| p.to_string()),
+            subdir: Some(platform.to_string()),
+            license: self.license.clone(),
+            license_family: None,
+            timestamp: Some(crate::metadata::build_timestamp()?),
+            depends,
+            constrains,
+            noarch: if platform == Platform::NoArch {
+                NoArchType::generic()
+            } else {
+                NoArchType::none()
+            },
+            track_features: Vec::new(),
+            features: None,
+        })
+    }
+
+    /// Create the `about.json` of the metapackage.
+    fn about_json(&self) -> AboutJson {
+        AboutJson {
+            home: Vec::new(),
+            license: self.license.clone(),
+            license_family: None,
+            summary: self.summary.clone(),
+            description: None,
+            doc_url: Vec::new(),
+            dev_url: Vec::new(),
+            source_url: None,
+            channels: Vec::new(),
+        }
+    }
+}
+
+/// The default build string: a hash of the dependencies and the build number, like the
+/// build strings of outputs (`h<hash>_<number>`).
+fn default_build_string(depends: &[String], constrains: &[String], build_number: u64) -> String {
+    let mut hasher = Sha256::new();
+    for spec in depends {
+        hasher.update(spec.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.update(b"--\n");
+    for spec in constrains {
+        hasher.update(spec.as_bytes());
+        hasher.update(b"\n");
+    }
+    let hash = hex::encode(hasher.finalize());
+    format!("h{}_{}", &hash[..7], build_number)
+}
+
+/// Write the metapackage to `<output_dir>/<subdir>/<name>-<version>-<build>.<ext>` and
+/// return its path.
+pub fn write_metapackage(
+    spec: &MetapackageSpec,
+    output_dir: &Path,
+    package_format: &PackageFormatAndCompression,
+) -> miette::Result<PathBuf> {
+    let index_json = spec.index_json()?;
+
+    let tmp = tempfile::tempdir().into_diagnostic()?;
+    let info = tmp.path().join("info");
+    fs::create_dir_all(&info).into_diagnostic()?;
+    let paths_json = PathsJson {
+        paths: Vec::new(),
+        paths_version: 1,
+    };
+    let files = [
+        (
+            IndexJson::package_path(),
+            serde_json::to_string_pretty(&index_json),
+        ),
+        (
+            PathsJson::package_path(),
+            serde_json::to_string_pretty(&paths_json),
+        ),
+        (
+            AboutJson::package_path(),
+            serde_json::to_string_pretty(&spec.about_json()),
+        ),
+    ];
+    let mut paths = Vec::new();
+    for (path, content) in files {
+        let path = tmp.path().join(path);
+        fs::write(&path, content.into_diagnostic()?).into_diagnostic()?;
+        paths.push(path);
+    }
+
+    let identifier = format!(
+        "{}-{}-{}",
+        index_json.name.as_normalized(),
+        index_json.version,
+        index_json.build
+    );
+    let subdir = output_dir.join(index_json.subdir.as_deref().unwrap_or("noarch"));
+    fs::create_dir_all(&subdir).into_diagnostic()?;
+    let archive_type = package_format.archive_type;
+    let out_path = subdir.join(format!("{}{}", identifier, archive_type.extension()));
+
+    let settings =
+        PackagingSettings::from_args(archive_type, package_format.compression_level, None);
+    let file = fs::File::create(&out_path).into_diagnostic()?;
+    let timestamp = index_json.timestamp;
+    match archive_type {
+        ArchiveType::TarBz2 => write_tar_bz2_package(
+            file,
+            tmp.path(),
+            &paths,
+            CompressionLevel::Numeric(settings.compression_level),
+            timestamp.as_ref(),
+            None,
+        ),
+        ArchiveType::Conda => write_conda_package(
+            file,
+            tmp.path(),
+            &paths,
+            CompressionLevel::Numeric(settings.compression_level),
+            settings.compression_threads,
+            &identifier,
+            timestamp.as_ref(),
+            None,
+        ),
+    }
+    .into_diagnostic()
+    .wrap_err_with(|| format!("Failed to write {}", out_path.display()))?;
+
+    Ok(out_path)
+}
+
+/// Create a metapackage from the command line arguments.
+pub fn make_metapackage_from_args(args: MetapackageOpts) -> miette::Result<()> {
+    let spec = match &args.spec {
+        Some(path) => MetapackageSpec::from_path(path)?,
+        None => MetapackageSpec::default(),
+    }
+    .with_args(&args);
+
+    let path = write_metapackage(&spec, &args.output_dir, &args.package_format)?;
+    tracing::info!("Created metapackage {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_write_metapackage() {
+        let spec: MetapackageSpec = serde_yaml::from_str(
+            r#"
+name: my-stack
+version: 1.0
+depends:
+  - numpy >=2
+"#,
+        )
+        .unwrap();
+        let args = MetapackageOpts::parse_from([
+            "make-metapackage",
+            "--depends",
+            "scipy",
+            "--license",
+            "MIT",
+        ]);
+        let spec = spec.with_args(&args);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let package_format = PackageFormatAndCompression::from_str("conda").unwrap();
+        let path = write_metapackage(&spec, output_dir.path(), &package_format).unwrap();
+        assert!(path.starts_with(output_dir.path().join("noarch")));
+
+        let extracted = tempfile::tempdir().unwrap();
+        rattler_package_streaming::fs::extract(&path, extracted.path()).unwrap();
+        let index_json = IndexJson::from_package_directory(extracted.path()).unwrap();
+        assert_eq!(index_json.name.as_normalized(), "my-stack");
+        assert_eq!(index_json.version.to_string(), "1.0");
+        assert_eq!(index_json.depends, vec!["numpy >=2", "scipy"]);
+        assert_eq!(index_json.license.as_deref(), Some("MIT"));
+        assert!(index_json.build.starts_with('h') && index_json.build.ends_with("_0"));
+        assert_eq!(index_json.noarch, NoArchType::generic());
+
+        let spec = MetapackageSpec {
+            name: Some("foo".to_string()),
+            version: Some("1.0".to_string()),
+            depends: vec!["bar >=2,<".to_string()],
+            ..MetapackageSpec::default()
+        };
+        assert!(spec.index_json().is_err());
+    }
+}
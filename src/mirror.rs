@@ -0,0 +1,183 @@
+//! Channel mirrors and the offline mode, for build farms that can not (or may not) reach
+//! the public servers.
+//!
+//! A mirror replaces a url prefix (e.g. the url of the `conda-forge` channel) with the url
+//! of a mirror, for the repodata, the packages and the sources. In offline mode no requests
+//! are made at all: the repodata and the packages have to be in the rattler cache and the
+//! sources in the source cache. The requests that would have been needed are collected and
+//! reported together when the build fails.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use rattler_conda_types::{Channel, ChannelConfig};
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+use url::Url;
+
+/// A url prefix that is replaced by the url of a mirror.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    /// The original url prefix, e.g. `https://conda.anaconda.org/conda-forge/`
+    pub original: Url,
+    /// The url that replaces the prefix, e.g. `https://mirror.example.com/conda-forge/`
+    pub mirror: Url,
+}
+
+/// Add a trailing slash to the path of a url, so that it only is a prefix of the urls
+/// below it.
+fn with_trailing_slash(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url
+}
+
+impl Mirror {
+    /// Parse `<channel>=<mirror url>`, where the channel is a channel name (e.g.
+    /// `conda-forge`) or a url prefix (e.g. `https://github.com/`).
+    pub fn parse(s: &str, channel_config: &ChannelConfig) -> Result<Self, String> {
+        let (original, mirror) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `<channel>=<mirror url>`, got `{s}`"))?;
+        let original = match Url::parse(original) {
+            Ok(url) => url,
+            Err(_) => {
+                Channel::from_str(original, channel_config)
+                    .map_err(|e| format!("invalid channel `{original}`: {e}"))?
+                    .base_url
+            }
+        };
+        let mirror =
+            Url::parse(mirror).map_err(|e| format!("invalid mirror url `{mirror}`: {e}"))?;
+        Ok(Self {
+            original: with_trailing_slash(original),
+            mirror: with_trailing_slash(mirror),
+        })
+    }
+}
+
+/// The url on the mirror with the longest matching prefix, if a mirror matches.
+pub fn mirror_url(mirrors: &[Mirror], url: &Url) -> Option<Url> {
+    let mirror = mirrors
+        .iter()
+        .filter(|mirror| url.as_str().starts_with(mirror.original.as_str()))
+        .max_by_key(|mirror| mirror.original.as_str().len())?;
+    let rest = &url.as_str()[mirror.original.as_str().len()..];
+    Url::parse(&format!("{}{}", mirror.mirror, rest)).ok()
+}
+
+/// The network requests that were refused in offline mode. Clones share the list.
+#[derive(Debug, Clone, Default)]
+pub struct OfflineRequests(Arc<Mutex<BTreeSet<String>>>);
+
+impl OfflineRequests {
+    /// Record a refused request, e.g. `GET https://conda.anaconda.org/...`.
+    pub fn record(&self, request: impl Into<String>) {
+        self.0.lock().unwrap().insert(request.into());
+    }
+
+    /// The error that lists all refused requests, if there were any.
+    pub fn error(&self) -> Option<OfflineError> {
+        let requests = self.0.lock().unwrap();
+        if requests.is_empty() {
+            return None;
+        }
+        Some(OfflineError {
+            requests: requests.iter().cloned().collect(),
+        })
+    }
+}
+
+/// The build needed network access in offline mode.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("network access is disabled (--offline), but the build needed:\n  - {}", .requests.join("\n  - "))]
+#[diagnostic(
+    code(rattler_build::offline),
+    help("run the build once with network access to fill the caches, or use `--channel-mirror` to build from a reachable mirror")
+)]
+pub struct OfflineError {
+    /// The refused requests
+    pub requests: Vec<String>,
+}
+
+/// A middleware that sends the requests to the mirrors, and refuses all requests in
+/// offline mode.
+#[derive(Debug, Clone)]
+pub struct MirrorMiddleware {
+    mirrors: Vec<Mirror>,
+    offline: Option<OfflineRequests>,
+}
+
+impl MirrorMiddleware {
+    /// Create a middleware for the mirrors. In offline mode (with the list that the
+    /// refused requests are recorded in), all requests fail.
+    pub fn new(mirrors: Vec<Mirror>, offline: Option<OfflineRequests>) -> Self {
+        Self { mirrors, offline }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for MirrorMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if let Some(url) = mirror_url(&self.mirrors, req.url()) {
+            tracing::debug!("Using the mirror {} for {}", url, req.url());
+            *req.url_mut() = url;
+        }
+        if let Some(offline) = &self.offline {
+            let request = format!("{} {}", req.method(), req.url());
+            offline.record(request.clone());
+            return Err(reqwest_middleware::Error::Middleware(anyhow::anyhow!(
+                "network access is disabled (--offline): {request}"
+            )));
+        }
+        next.run(req, extensions).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_url() {
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::temp_dir());
+        let mirrors = [
+            Mirror::parse("conda-forge=https://mirror.example.com/cf", &channel_config).unwrap(),
+            Mirror::parse(
+                "https://conda.anaconda.org/conda-forge/label=https://mirror.example.com/labels",
+                &channel_config,
+            )
+            .unwrap(),
+        ];
+        assert_eq!(
+            mirrors[0].original.as_str(),
+            "https://conda.anaconda.org/conda-forge/"
+        );
+
+        let mirrored =
+            |url: &str| mirror_url(&mirrors, &Url::parse(url).unwrap()).map(|url| url.to_string());
+        assert_eq!(
+            mirrored("https://conda.anaconda.org/conda-forge/noarch/repodata.json").as_deref(),
+            Some("https://mirror.example.com/cf/noarch/repodata.json")
+        );
+        assert_eq!(
+            mirrored("https://conda.anaconda.org/conda-forge/label/dev/noarch/repodata.json")
+                .as_deref(),
+            Some("https://mirror.example.com/labels/dev/noarch/repodata.json")
+        );
+        assert_eq!(
+            mirrored("https://conda.anaconda.org/conda-forge-extra/noarch/repodata.json"),
+            None
+        );
+        assert!(Mirror::parse("conda-forge", &channel_config).is_err());
+    }
+}
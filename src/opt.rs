@@ -1,11 +1,27 @@
 //! Command-line options.
 
-use std::{path::PathBuf, str::FromStr};
+use std::{num::NonZeroUsize, path::PathBuf, str::FromStr};
 
 use crate::{
+    apply_patches::ApplyPatchesOpts,
+    cache::CacheOpts,
+    changed::ChangedOpts,
     console_utils::{Color, LogStyle},
+    convert::ConvertOpts,
+    generate_patch::GeneratePatchOpts,
+    gpu::GpuVariant,
+    index::IndexOpts,
+    lint::LintOpts,
+    lsp::LspOpts,
+    metapackage::MetapackageOpts,
+    recipe::parser::ScriptPhase,
     recipe_generator::GenerateRecipeOpts,
-    tool_configuration::SkipExisting,
+    sbom::SbomFormat,
+    signing::SigningMethod,
+    tool_configuration::{SkipExisting, TestPolicy},
+    update_checksums::{FetchChecksumsOpts, UpdateChecksumsOpts},
+    upload::RepodataUpdate,
+    variant_config::VariantOverride,
 };
 use clap::builder::ArgPredicate;
 use clap::{arg, crate_version, Parser};
@@ -26,6 +42,32 @@ pub enum SubCommands {
     /// Rebuild a package
     Rebuild(RebuildOpts),
 
+    /// Prepare the build environment of a recipe without running the build script
+    Debug(DebugOpts),
+
+    /// Turn changes in the work directory of a build into patch files for the recipe
+    GeneratePatch(GeneratePatchOpts),
+
+    /// Fetch the sources of a recipe and apply its patches without building
+    ApplyPatches(ApplyPatchesOpts),
+
+    /// Download the URL sources of a recipe and write their checksums into the recipe
+    UpdateChecksums(UpdateChecksumsOpts),
+
+    /// Download the URL sources of a recipe that have no checksum (or a placeholder like
+    /// `TODO`) and write their checksums into the recipe
+    FetchChecksums(FetchChecksumsOpts),
+
+    /// Create a metapackage (a package without files that only has dependencies)
+    /// without writing a recipe
+    MakeMetapackage(MetapackageOpts),
+
+    /// Convert a conda-build `meta.yaml` into a `recipe.yaml`
+    Convert(ConvertOpts),
+
+    /// Render the recipes for all variants and print them as JSON or YAML
+    Render(RenderOpts),
+
     /// Upload a package
     Upload(UploadOpts),
 
@@ -35,6 +77,26 @@ pub enum SubCommands {
     /// Generate a recipe from PyPI or CRAN
     GenerateRecipe(GenerateRecipeOpts),
 
+    /// List the recipes affected by changes since a git reference
+    Changed(ChangedOpts),
+
+    /// Lint a recipe and report problems with their location in the recipe
+    Lint(LintOpts),
+
+    /// Run a language server that reports recipe diagnostics to editors over stdio
+    Lsp(LspOpts),
+
+    /// Run a build daemon that keeps caches warm between builds, or send it a build
+    #[cfg(unix)]
+    Daemon(crate::daemon::DaemonOpts),
+
+    /// Inspect and clean the package and source caches
+    Cache(CacheOpts),
+
+    /// Write the `repodata.json` of a directory of packages (e.g. the output directory),
+    /// so that it can be used as a channel
+    Index(IndexOpts),
+
     /// Handle authentication to external repositories
     Auth(rattler::cli::auth::Args),
 }
@@ -111,6 +173,42 @@ pub struct CommonOpts {
     /// Path to an auth-file to read authentication information from
     #[clap(long, env = "RATTLER_AUTH_FILE", hide = true)]
     pub auth_file: Option<PathBuf>,
+
+    /// Proxy for all outbound requests (repodata, packages and sources). Defaults to the
+    /// `HTTPS_PROXY` / `HTTP_PROXY` environment variables. Hosts in `NO_PROXY` are not proxied.
+    #[clap(long, env = "RATTLER_BUILD_PROXY")]
+    pub proxy: Option<Url>,
+
+    /// A PEM file with additional CA certificates to trust for all outbound requests, e.g.
+    /// the certificate of a TLS-intercepting proxy
+    #[clap(long, env = "RATTLER_BUILD_CA_CERT")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Never block on a prompt (for credentials, the recipe on stdin or the debug shell).
+    /// If an input is required, fail with an `input_required` error instead.
+    #[clap(long, env = "RATTLER_BUILD_NON_INTERACTIVE")]
+    pub non_interactive: bool,
+
+    /// Use a mirror for a channel (or any url prefix) for repodata, packages and sources,
+    /// e.g. `conda-forge=https://mirror.example.com/conda-forge` (can be repeated)
+    #[clap(
+        long,
+        value_name = "CHANNEL=URL",
+        env = "RATTLER_BUILD_CHANNEL_MIRRORS",
+        value_delimiter = ','
+    )]
+    pub channel_mirror: Vec<String>,
+
+    /// Never access the network: repodata, packages and sources are only taken from the
+    /// caches. If anything is missing, the build fails with a list of what would have been
+    /// downloaded.
+    #[clap(long, env = "RATTLER_BUILD_OFFLINE")]
+    pub offline: bool,
+
+    /// The directory of the package and repodata caches. Defaults to the cache directory of
+    /// rattler, which pixi uses as well
+    #[clap(long, env = "RATTLER_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
 }
 
 /// Container for the CLI package format and compression level
@@ -177,6 +275,28 @@ impl FromStr for PackageFormatAndCompression {
     }
 }
 
+/// Where the built packages are uploaded to (`--upload-to`).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UploadTo {
+    /// Upload every output to the destination of its recipe or the project configuration
+    Auto,
+    /// Upload all outputs to the given channel url
+    Url(Url),
+}
+
+impl FromStr for UploadTo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(UploadTo::Auto);
+        }
+        Url::parse(s)
+            .map(UploadTo::Url)
+            .map_err(|e| format!("expected `auto` or a channel url: {e}"))
+    }
+}
+
 /// Build options.
 #[derive(Parser, Clone)]
 pub struct BuildOpts {
@@ -197,6 +317,11 @@ pub struct BuildOpts {
     #[arg(long)]
     pub up_to: Option<String>,
 
+    /// The number of outputs to build in parallel. Outputs are only built once the
+    /// outputs they depend on are finished.
+    #[arg(long, short = 'j', default_value = "1", conflicts_with = "debug_shell")]
+    pub jobs: NonZeroUsize,
+
     /// The build platform to use for the build (e.g. for building with emulation, or rendering).
     #[arg(long, default_value_t = Platform::current())]
     pub build_platform: Platform,
@@ -210,18 +335,43 @@ pub struct BuildOpts {
     #[arg(short = 'c', long)]
     pub channel: Option<Vec<String>>,
 
-    /// Variant configuration files for the build.
+    /// Variant configuration files for the build. Keys of later files replace the keys of
+    /// earlier files (and of the variant config of the user).
     #[arg(short = 'm', long)]
     pub variant_config: Vec<PathBuf>,
 
+    /// Do not load the variant config of the user
+    /// (`~/.config/rattler-build/variants.yaml`).
+    #[arg(long)]
+    pub no_user_variant_config: bool,
+
+    /// Restrict the variant matrix to the given value of a variant key, e.g.
+    /// `--variant python=3.12`. Can be repeated, also for the same key.
+    #[arg(long = "variant", value_name = "KEY=VALUE")]
+    pub variant_overrides: Vec<VariantOverride>,
+
     /// Render the recipe files without executing the build.
     #[arg(long)]
     pub render_only: bool,
 
-    /// Render the recipe files with solving requirements.
-    #[arg(long, requires("render_only"))]
+    /// Render the recipe files with solving requirements (with `--render-only` or the
+    /// `render` subcommand).
+    #[arg(long)]
     pub with_solve: bool,
 
+    /// Print the order in which the outputs are built (based on the dependencies
+    /// between the outputs of all given recipes) as JSON, without executing the build.
+    #[arg(long, conflicts_with = "render_only")]
+    pub build_order: bool,
+
+    /// Resolve the build, host and test environments of all outputs and report what would
+    /// be installed and built, without fetching sources or running the build scripts.
+    #[arg(
+        long,
+        conflicts_with_all = ["render_only", "build_order", "incremental", "debug_shell", "tui"]
+    )]
+    pub dry_run: bool,
+
     /// Keep intermediate build artifacts after the build.
     #[arg(long)]
     pub keep_build: bool,
@@ -230,6 +380,23 @@ pub struct BuildOpts {
     #[arg(long)]
     pub no_build_id: bool,
 
+    /// Reuse the build and host environments of the previous build of the recipe if
+    /// the requirements did not change, and only run the build script again. Implies
+    /// `--keep-build` and `--no-build-id`.
+    #[arg(long, visible_alias = "keep-build-environments")]
+    pub incremental: bool,
+
+    /// Only run the phases of the build script starting at the given phase, reusing the
+    /// work directory of the previous build. Requires `--incremental`.
+    #[arg(long, requires = "incremental")]
+    pub from_phase: Option<ScriptPhase>,
+
+    /// Build a development package for local testing. Local `path` sources are linked
+    /// instead of copied, `RATTLER_BUILD_DEV` is set for the build script (e.g. to do an
+    /// editable install) and the build string is prefixed with `dev_`. Implies `--keep-build`.
+    #[arg(long)]
+    pub dev: bool,
+
     /// The package format to use for the build. Can be one of `tar-bz2` or `conda`.
     /// You can also add a compression level to the package format, e.g. `tar-bz2:<number>` (from 1 to 9) or `conda:<number>` (from -7 to 22).
     #[arg(long, default_value = "conda")]
@@ -247,6 +414,36 @@ pub struct BuildOpts {
     #[arg(long, default_value = "false")]
     pub no_test: bool,
 
+    /// What to do with the tests of packages for a platform that this machine cannot run
+    /// natively: skip them (`native-only`), run them with QEMU emulation (`emulate`), or
+    /// fail the build (`required`)
+    #[arg(long = "test", value_enum, default_value = "native-only")]
+    pub test_policy: TestPolicy,
+
+    /// A command that is run for every created package, e.g. a virus or vulnerability
+    /// scanner. The path of the package is passed as the last argument (and as
+    /// `RATTLER_BUILD_ARTIFACT`). The build fails if the command exits with a non-zero code.
+    #[arg(long)]
+    pub scan_cmd: Option<String>,
+
+    /// A command that is run after every output was built and tested successfully (can be
+    /// repeated). The path of the package is passed as the last argument, the metadata in
+    /// `RATTLER_BUILD_*` environment variables. Hooks from the `[[post_build]]` tables of
+    /// the project configuration run as well.
+    #[arg(long, value_name = "CMD")]
+    pub post_build_cmd: Vec<String>,
+
+    /// A url that the metadata of every successfully built package is posted to as JSON
+    /// (can be repeated)
+    #[arg(long, value_name = "URL")]
+    pub post_build_webhook: Vec<Url>,
+
+    /// Write the resource usage of the builds and tests, the paths of the packages and
+    /// whether their tests passed to a JSON file (compatible with the `--stats-file` of
+    /// conda-build)
+    #[arg(long)]
+    pub stats_file: Option<PathBuf>,
+
     /// Do not force colors in the output of the build script
     #[arg(long, default_value = "true")]
     pub color_build_log: bool,
@@ -262,6 +459,125 @@ pub struct BuildOpts {
     /// Wether to skip packages that already exist in any channel
     #[arg(long, default_missing_value = "local", default_value = "none", num_args = 0..=1)]
     pub skip_existing: SkipExisting,
+
+    /// Start an interactive shell in the build environment when the build script fails
+    #[arg(long, conflicts_with = "tui")]
+    pub debug_shell: bool,
+
+    /// Extract source archives even if they contain members that escape the
+    /// target directory (absolute paths, `..` components, links pointing outside)
+    /// or device nodes
+    #[arg(long)]
+    pub allow_unsafe_extraction: bool,
+
+    /// Treat `skip` conditions that use variables which are not defined (e.g. missing
+    /// from the variant config) as false instead of failing, like conda-build does
+    #[arg(long)]
+    pub lenient_skip: bool,
+
+    /// Only build the variants for the given GPU stacks (`cpu`, `cuda` or `rocm`). A variant
+    /// is a CUDA or ROCm variant if it sets `cuda_compiler_version` or `rocm_version` to a
+    /// version (and not `None`). Can be repeated.
+    #[arg(long = "gpu-variant")]
+    pub gpu_variants: Vec<GpuVariant>,
+
+    /// Build the recipe even if this version of rattler-build does not satisfy its
+    /// `build_tool_constraint`
+    #[arg(long)]
+    pub ignore_build_tool_constraint: bool,
+
+    /// Fail the build when a binary links against a shared library that is not provided
+    /// by a run dependency (overlinking), regardless of `overlinking_behavior`
+    #[arg(long)]
+    pub error_overlinking: bool,
+
+    /// Fail the build when a run dependency with shared libraries is not linked against
+    /// by any binary (overdepending), regardless of `overdepending_behavior`
+    #[arg(long)]
+    pub error_overdepending: bool,
+
+    /// Upload the packages after all outputs are built, either to a channel url (e.g.
+    /// `https://prefix.dev/my-channel` or `quetz+https://quetz.example.com/api/my-channel`)
+    /// or, with `auto`, to the destination of each output in the recipe
+    /// (`build.upload_to`) or the project configuration (`[upload]`)
+    #[arg(long, value_name = "URL|auto")]
+    pub upload_to: Option<UploadTo>,
+
+    /// Add the packages of the named build profile from the project configuration
+    /// (`rattler-build.toml`) to the build requirements of all outputs. Can be repeated.
+    #[arg(long = "profile")]
+    pub profiles: Vec<String>,
+
+    /// The directory in which downloaded sources and git checkouts are cached. Defaults
+    /// to `src_cache` in the output directory.
+    #[arg(long, env = "RATTLER_BUILD_SOURCE_CACHE_DIR")]
+    pub source_cache_dir: Option<PathBuf>,
+
+    /// Use a package and source cache that is private to this invocation instead of
+    /// the caches that are shared (and locked) between concurrent rattler-build processes
+    #[arg(long)]
+    pub no_shared_cache: bool,
+
+    /// Build and test for another Linux architecture (e.g. `--target-platform
+    /// linux-aarch64` on `x86_64`) by running the binaries of the target platform with
+    /// the QEMU interpreter registered with `binfmt_misc`. The build platform is the
+    /// target platform.
+    #[arg(long, conflicts_with = "build_platform")]
+    pub emulate: bool,
+
+    /// Create a software bill of materials for every package, with the resolved build and
+    /// host dependencies, the run requirements, the sources and the build environment. It
+    /// is stored in `info/` and written next to the package file.
+    #[arg(long, value_enum)]
+    pub sbom: Option<SbomFormat>,
+
+    /// Write an in-toto statement with the SLSA provenance of every package (the recipe
+    /// hash, the variant, the command line, the resolved dependencies and the machine it
+    /// was built on) next to the package file. The statements are uploaded with the
+    /// packages by backends that support it.
+    #[arg(long)]
+    pub provenance: bool,
+
+    /// Write the lock files of the build and host environments (in the format of
+    /// `conda-lock`) of every package to this directory. They are also stored in
+    /// `info/lock/` of every package. The lock files of the test environments are
+    /// only written to this directory.
+    #[arg(long)]
+    pub lockfile_dir: Option<PathBuf>,
+
+    /// Apply the strictest normalization for reproducible packages: the permissions of all
+    /// files are normalized and the timestamps in the `.pyc` files of the package are set
+    /// to the build timestamp (`SOURCE_DATE_EPOCH`, if it is set).
+    #[arg(long)]
+    pub reproducible: bool,
+
+    /// Install the environments of a lock file (written by `--lockfile-dir` or taken from
+    /// `info/lock/` of a package) instead of solving the requirements. Can be repeated for
+    /// the build and host environments and for multiple outputs.
+    #[arg(long = "reuse-lockfile", value_name = "LOCKFILE")]
+    pub reuse_lockfiles: Vec<PathBuf>,
+
+    /// Sign every package (and its provenance statement) with sigstore (`cosign`) or GPG.
+    /// The detached signatures are written next to the package and uploaded with it by
+    /// backends that support it.
+    #[arg(long, value_enum)]
+    pub sign: Option<SigningMethod>,
+
+    /// The key to sign with: a cosign key reference (without it, cosign signs keyless)
+    /// or a GPG key id (without it, the default key is used)
+    #[arg(long, requires = "sign")]
+    pub signing_key: Option<String>,
+}
+
+impl BuildOpts {
+    /// The build platform, which is the target platform when building with `--emulate`.
+    pub fn build_platform(&self) -> Platform {
+        if self.emulate {
+            self.target_platform
+        } else {
+            self.build_platform
+        }
+    }
 }
 
 /// Test options.
@@ -272,8 +588,42 @@ pub struct TestOpts {
     pub channel: Option<Vec<String>>,
 
     /// The package file to test
-    #[arg(short, long)]
-    pub package_file: PathBuf,
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["spec", "subdir"],
+        conflicts_with_all = ["spec", "subdir"]
+    )]
+    pub package_file: Option<PathBuf>,
+
+    /// Test the packages of the channels that match the spec (e.g. `numpy=2.1`), can be
+    /// given multiple times
+    #[arg(long)]
+    pub spec: Vec<String>,
+
+    /// The subdir of the channels to test. Without `--spec`, every package of the
+    /// subdir and `noarch` is tested. Defaults to the current platform.
+    #[arg(long)]
+    pub subdir: Option<Platform>,
+
+    /// Test all builds of every version, instead of only the latest builds (the ones with
+    /// the highest build number)
+    #[arg(long)]
+    pub all_builds: bool,
+
+    /// The number of packages to test in parallel
+    #[arg(long, short = 'j', default_value = "1")]
+    pub jobs: NonZeroUsize,
+
+    /// Write a JUnit XML report of the tested packages
+    #[arg(long)]
+    pub junit: Option<PathBuf>,
+
+    /// What to do with the tests of packages for a platform that this machine cannot run
+    /// natively: skip them (`native-only`), run them with QEMU emulation (`emulate`), or
+    /// fail (`required`)
+    #[arg(long = "test", value_enum, default_value = "native-only")]
+    pub test_policy: TestPolicy,
 
     /// Common options.
     #[clap(flatten)]
@@ -291,11 +641,78 @@ pub struct RebuildOpts {
     #[arg(long, default_value = "false")]
     pub no_test: bool,
 
+    /// Compare the rebuilt package with the original bit-for-bit, and report the
+    /// differences with their likely sources of non-determinism (timestamps, build paths,
+    /// archive ordering). Fails if the packages differ.
+    #[arg(long)]
+    pub verify: bool,
+
+    /// With `--verify`, accept a rebuilt package whose files are identical to the ones of
+    /// the original package, even if the archives differ (e.g. the compression)
+    #[arg(long, requires = "verify")]
+    pub ignore_archive_differences: bool,
+
     /// Common options.
     #[clap(flatten)]
     pub common: CommonOpts,
 }
 
+/// Debug options.
+#[derive(Parser)]
+pub struct DebugOpts {
+    /// The name of the output to prepare, if the recipe has multiple outputs
+    #[arg(long)]
+    pub output_name: Option<String>,
+
+    /// Build options.
+    #[clap(flatten)]
+    pub build: BuildOpts,
+}
+
+/// The format in which the `render` subcommand prints the rendered recipes.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderFormat {
+    /// Print the rendered recipes as JSON
+    #[default]
+    Json,
+    /// Print the rendered recipes as YAML
+    Yaml,
+}
+
+/// Render options.
+#[derive(Parser)]
+pub struct RenderOpts {
+    /// The format to print the rendered recipes in
+    #[arg(long, default_value = "json")]
+    pub format: RenderFormat,
+
+    /// Also print the variants that were skipped, together with the skip condition that
+    /// matched and the values of the variables it uses
+    #[arg(long)]
+    pub include_skipped: bool,
+
+    /// Print the merged variant config with the file that set every key, instead of
+    /// the rendered recipes
+    #[arg(long)]
+    pub show_variant_sources: bool,
+
+    /// Resolve the environments (like `--with-solve`) and add an explanation of the run
+    /// exports to every output: the package that exported each run dependency and
+    /// constraint, and the run exports removed by `ignore_run_exports`
+    #[arg(long)]
+    pub explain_run_exports: bool,
+
+    /// Add an explanation of the variant hash to every output: the variant keys and values
+    /// that are hashed, the keys the prefix (e.g. `py311`) is derived from, and the keys of
+    /// the variant config that are not hashed
+    #[arg(long)]
+    pub explain_hash: bool,
+
+    /// Build options.
+    #[clap(flatten)]
+    pub build: BuildOpts,
+}
+
 /// Upload options.
 #[derive(Parser, Debug)]
 pub struct UploadOpts {
@@ -307,6 +724,19 @@ pub struct UploadOpts {
     #[clap(subcommand)]
     pub server_type: ServerType,
 
+    /// How often a failed upload of a package is retried
+    #[arg(long, global = true, default_value = "3")]
+    pub retries: u32,
+
+    /// How many packages are uploaded at the same time
+    #[arg(long, global = true, default_value = "1")]
+    pub parallel: usize,
+
+    /// The size of the chunks (in MiB) that large packages are uploaded in, for servers
+    /// that support chunked uploads (S3 and OCI registries)
+    #[arg(long, global = true, default_value = "64")]
+    pub chunk_size: u64,
+
     /// Common options.
     #[clap(flatten)]
     pub common: CommonOpts,
@@ -320,6 +750,8 @@ pub enum ServerType {
     Artifactory(ArtifactoryOpts),
     Prefix(PrefixOpts),
     Anaconda(AnacondaOpts),
+    Oci(OciOpts),
+    S3(S3Opts),
     #[clap(hide = true)]
     CondaForge(CondaForgeOpts),
 }
@@ -413,6 +845,69 @@ pub struct AnacondaOpts {
     pub force: bool,
 }
 
+/// Options for uploading to an OCI registry (e.g. ghcr.io).
+/// Authentication is used from the docker configuration or the keychain / auth-file.
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct OciOpts {
+    /// The URL of the channel in the registry (e.g. `oci://ghcr.io/my-org/my-channel`)
+    #[arg(short, long, env = "OCI_CHANNEL_URL")]
+    pub url: Url,
+
+    /// The username for the registry
+    #[arg(short = 'r', long, env = "OCI_USERNAME")]
+    pub username: Option<String>,
+
+    /// The password or token for the registry
+    #[arg(short, long, env = "OCI_PASSWORD")]
+    pub password: Option<String>,
+}
+
+/// Options for uploading to an S3 bucket (or an S3 compatible object store).
+/// Authentication is used from the `AWS_*` environment variables, the AWS profile or the
+/// keychain / auth-file.
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct S3Opts {
+    /// The URL of the channel in the bucket (e.g. `s3://my-bucket/my-channel`)
+    #[arg(short, long, env = "S3_CHANNEL")]
+    pub channel: Url,
+
+    /// The endpoint of an S3 compatible object store (e.g. `http://localhost:9000` for MinIO)
+    #[arg(long, env = "S3_ENDPOINT_URL")]
+    pub endpoint_url: Option<Url>,
+
+    /// The region of the bucket
+    #[arg(long, env = "S3_REGION")]
+    pub region: Option<String>,
+
+    /// The AWS profile to read the settings and credentials from
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// The ARN of a role to assume before uploading
+    #[arg(long)]
+    pub role_arn: Option<String>,
+
+    /// Address the bucket in the path instead of the host name
+    #[arg(long)]
+    pub force_path_style: bool,
+
+    /// The access key id
+    #[arg(long, env = "S3_ACCESS_KEY_ID", requires = "secret_access_key")]
+    pub access_key_id: Option<String>,
+
+    /// The secret access key
+    #[arg(long, env = "S3_SECRET_ACCESS_KEY", requires = "access_key_id")]
+    pub secret_access_key: Option<String>,
+
+    /// The session token of temporary credentials
+    #[arg(long, env = "S3_SESSION_TOKEN")]
+    pub session_token: Option<String>,
+
+    /// How the repodata of the channel is updated after a package is uploaded
+    #[arg(long, value_enum, default_value_t = RepodataUpdate::Patch)]
+    pub repodata: RepodataUpdate,
+}
+
 /// Options for uploading to conda-forge
 #[derive(Clone, Debug, PartialEq, Parser)]
 pub struct CondaForgeOpts {
@@ -0,0 +1,297 @@
+//! Re-run the tests that are embedded in packages of a channel, e.g. to validate a
+//! channel after infrastructure changes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use futures::{stream, StreamExt};
+use rattler_conda_types::{Channel, MatchSpec, PackageName, Platform, RepoData, RepoDataRecord};
+use tracing::Instrument;
+use url::Url;
+
+use super::{
+    run_test::{download, fetch_package, run_test},
+    TestConfiguration, TestError,
+};
+use crate::{
+    reporter::{Outcome, ReportEntry},
+    tool_configuration::{self, TestPolicy},
+};
+
+/// Find the packages to test in the channels: the packages of `subdir` and `noarch` that
+/// match one of the specs, or all of them without specs. Unless `all_builds` is set, only
+/// the latest builds of every version are returned (see [`latest_builds`]).
+pub async fn find_packages(
+    channels: &[Url],
+    subdir: Platform,
+    specs: &[MatchSpec],
+    all_builds: bool,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<Vec<RepoDataRecord>, TestError> {
+    let specs = if specs.is_empty() {
+        package_names(channels, subdir, tool_configuration)
+            .await?
+            .into_iter()
+            .map(|name| MatchSpec {
+                name: Some(name),
+                ..Default::default()
+            })
+            .collect()
+    } else {
+        specs.to_vec()
+    };
+
+    let repodata = tool_configuration
+        .repodata_gateway
+        .query(
+            channels.iter().map(|url| Channel::from_url(url.clone())),
+            [subdir, Platform::NoArch],
+            specs.clone(),
+        )
+        .recursive(false)
+        .await?;
+    let records = repodata
+        .iter()
+        .flat_map(|repodata| repodata.iter())
+        .filter(|record| {
+            specs
+                .iter()
+                .any(|spec| spec.matches(&record.package_record))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    Ok(if all_builds {
+        records
+    } else {
+        latest_builds(records)
+    })
+}
+
+/// The names of all packages of `subdir` and `noarch` in the channels, to query them
+/// with the gateway.
+async fn package_names(
+    channels: &[Url],
+    subdir: Platform,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<HashSet<PackageName>, TestError> {
+    let mut names = HashSet::new();
+    for url in channels {
+        let channel = Channel::from_url(url.clone());
+        for platform in [subdir, Platform::NoArch] {
+            let repodata_url = channel
+                .platform_url(platform)
+                .join("repodata.json")
+                .expect("the platform url is a directory");
+            tracing::info!("Fetching {}", repodata_url);
+            let bytes = download(&repodata_url, tool_configuration).await?;
+            let repodata: RepoData = serde_json::from_slice(&bytes)
+                .map_err(|err| TestError::RepodataParse(repodata_url.clone(), err))?;
+            names.extend(
+                repodata
+                    .packages
+                    .into_values()
+                    .chain(repodata.conda_packages.into_values())
+                    .map(|record| record.name),
+            );
+        }
+    }
+    Ok(names)
+}
+
+/// The latest builds of every version of a package in every subdir: the records with the
+/// highest build number. The builds of the other variants (e.g. for other Python
+/// versions) with the same build number are kept.
+fn latest_builds(records: Vec<RepoDataRecord>) -> Vec<RepoDataRecord> {
+    let key = |record: &RepoDataRecord| {
+        (
+            record.package_record.name.clone(),
+            record.package_record.version.to_string(),
+            record.package_record.subdir.clone(),
+        )
+    };
+    let mut latest = HashMap::new();
+    for record in &records {
+        let build_number = latest
+            .entry(key(record))
+            .or_insert(record.package_record.build_number);
+        *build_number = (*build_number).max(record.package_record.build_number);
+    }
+    records
+        .into_iter()
+        .filter(|record| latest[&key(record)] == record.package_record.build_number)
+        .collect()
+}
+
+/// The error with its causes, one per line.
+fn error_chain(err: &TestError) -> String {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        message.push_str(&format!("\ncaused by: {}", err));
+        source = err.source();
+    }
+    message
+}
+
+/// The report entry of the tests of a package.
+pub fn report_entry(
+    name: String,
+    classname: String,
+    duration: Duration,
+    result: &Result<(), TestError>,
+) -> ReportEntry {
+    ReportEntry {
+        name,
+        classname,
+        file: None,
+        location: None,
+        duration,
+        outcome: match result {
+            Ok(()) => Outcome::Passed,
+            Err(err) => Outcome::Failed(error_chain(err)),
+        },
+    }
+}
+
+/// The report entry of a package for a platform whose tests are skipped (or not allowed)
+/// by the test policy, or `None` if the tests can run.
+pub fn skipped_entry(
+    name: String,
+    classname: String,
+    platform: Platform,
+    test_policy: TestPolicy,
+) -> Option<ReportEntry> {
+    let outcome = match test_policy.skipped_reason(Platform::current(), platform) {
+        Ok(None) => return None,
+        Ok(Some(reason)) => {
+            tracing::warn!("Skipping the tests of {}: {}", name, reason);
+            Outcome::Warning(format!("the tests were skipped: {}", reason))
+        }
+        Err(err) => {
+            tracing::error!("{} failed: {}", name, err);
+            Outcome::Failed(err.to_string())
+        }
+    };
+    Some(ReportEntry {
+        name,
+        classname,
+        file: None,
+        location: None,
+        duration: Duration::ZERO,
+        outcome,
+    })
+}
+
+/// Download a package and run its tests, unless the test policy skips them.
+async fn test_package(record: &RepoDataRecord, config: &TestConfiguration) -> ReportEntry {
+    let platform = record
+        .package_record
+        .subdir
+        .parse()
+        .unwrap_or(Platform::NoArch);
+    if let Some(entry) = skipped_entry(
+        record.file_name.clone(),
+        record.package_record.name.as_normalized().to_string(),
+        platform,
+        config.tool_configuration.test_policy,
+    ) {
+        return entry;
+    }
+
+    let start = Instant::now();
+    let result = async {
+        let dir = tempfile::tempdir()?;
+        let package_file = fetch_package(record, dir.path(), config).await?;
+        let test_config = TestConfiguration {
+            test_prefix: dir.path().join("test"),
+            target_platform: None,
+            ..config.clone()
+        };
+        run_test(&package_file, &test_config).await
+    }
+    .instrument(tracing::info_span!("Running tests for", package = %record.file_name))
+    .await;
+
+    match &result {
+        Ok(()) => tracing::info!("{} passed", record.file_name),
+        Err(err) => tracing::error!("{} failed: {}", record.file_name, err),
+    }
+    report_entry(
+        record.file_name.clone(),
+        record.package_record.name.as_normalized().to_string(),
+        start.elapsed(),
+        &result,
+    )
+}
+
+/// Run the tests of the packages, `jobs` packages at a time. Every package is tested,
+/// also when the tests of another package fail.
+pub async fn test_packages(
+    records: &[RepoDataRecord],
+    config: &TestConfiguration,
+    jobs: NonZeroUsize,
+) -> Vec<ReportEntry> {
+    stream::iter(records)
+        .map(|record| test_package(record, config))
+        .buffer_unordered(jobs.get())
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rattler_conda_types::{PackageName, PackageRecord, RepoDataRecord, VersionWithSource};
+    use url::Url;
+
+    use super::latest_builds;
+
+    fn record(name: &str, version: &str, build: &str, build_number: u64) -> RepoDataRecord {
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            VersionWithSource::from_str(version).unwrap(),
+            build.to_string(),
+        );
+        package_record.build_number = build_number;
+        package_record.subdir = "linux-64".to_string();
+        let file_name = format!("{name}-{version}-{build}.conda");
+        RepoDataRecord {
+            package_record,
+            url: Url::parse("https://conda.anaconda.org/my-channel/linux-64/")
+                .unwrap()
+                .join(&file_name)
+                .unwrap(),
+            file_name,
+            channel: "https://conda.anaconda.org/my-channel/".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_latest_builds() {
+        let records = vec![
+            record("foo", "1.0", "py311_0", 0),
+            record("foo", "1.0", "py311_1", 1),
+            record("foo", "1.0", "py312_1", 1),
+            record("foo", "2.0", "py312_0", 0),
+            record("bar", "1.0", "h1234567_3", 3),
+        ];
+        let latest = latest_builds(records)
+            .into_iter()
+            .map(|record| record.file_name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            latest,
+            [
+                "foo-1.0-py311_1.conda",
+                "foo-1.0-py312_1.conda",
+                "foo-2.0-py312_0.conda",
+                "bar-1.0-h1234567_3.conda",
+            ]
+        );
+    }
+}
@@ -1,9 +1,16 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use crate::package_test::TestError;
 use crate::recipe::parser::PackageContentsTest;
 use globset::{Glob, GlobBuilder, GlobSet};
-use rattler_conda_types::{package::PathsJson, Arch, Platform};
+use rattler_conda_types::{
+    package::{ArchiveType, PathType, PathsJson},
+    Arch, Platform,
+};
 
 fn build_glob(glob: String) -> Result<Glob, globset::Error> {
     tracing::debug!("Building glob: {}", glob);
@@ -24,6 +31,52 @@ fn display_success(matches: &[&PathBuf], glob: &str, section: &str) {
     }
 }
 
+/// Returns true if the contents look binary (like `git`, a NUL byte in the first 8000
+/// bytes).
+fn is_binary(reader: impl Read) -> std::io::Result<bool> {
+    let mut head = Vec::with_capacity(8000);
+    reader.take(8000).read_to_end(&mut head)?;
+    Ok(head.contains(&0))
+}
+
+/// Determine which of the files of a tar archive are binary.
+fn binary_files_in_archive<R: Read>(
+    mut archive: tar::Archive<R>,
+    files: &HashSet<&PathBuf>,
+) -> std::io::Result<HashMap<PathBuf, bool>> {
+    let mut result = HashMap::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.into_owned();
+        if files.contains(&path) {
+            result.insert(path, is_binary(entry)?);
+        }
+    }
+    Ok(result)
+}
+
+/// Determine which of the files of a package are binary, by reading them from the archive.
+fn binary_files(
+    package: &Path,
+    files: &HashSet<&PathBuf>,
+) -> std::io::Result<HashMap<PathBuf, bool>> {
+    let to_io_error = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+    let file = fs_err::File::open(package)?;
+    match ArchiveType::try_from(package) {
+        Some(ArchiveType::TarBz2) => {
+            binary_files_in_archive(rattler_package_streaming::read::stream_tar_bz2(file), files)
+        }
+        Some(ArchiveType::Conda) => binary_files_in_archive(
+            rattler_package_streaming::seek::stream_conda_content(file).map_err(to_io_error)?,
+            files,
+        ),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a package archive", package.display()),
+        )),
+    }
+}
+
 impl PackageContentsTest {
     /// Retrieve the include globs as a vector of (glob, GlobSet) tuples
     pub fn include_as_globs(
@@ -213,12 +266,18 @@ impl PackageContentsTest {
         Ok(result)
     }
 
-    /// Run the package content test
-    pub fn run_test(&self, paths: &PathsJson, target_platform: &Platform) -> Result<(), TestError> {
+    /// Run the package content test against the `paths.json` of the package. The
+    /// `text` and `binary` checks read the files from the package archive.
+    pub fn run_test(
+        &self,
+        paths_json: &PathsJson,
+        package: &Path,
+        target_platform: &Platform,
+    ) -> Result<(), TestError> {
         let span = tracing::info_span!("Package content test");
         let _enter = span.enter();
 
-        let paths = paths
+        let paths = paths_json
             .paths
             .iter()
             .map(|p| &p.relative_path)
@@ -242,7 +301,7 @@ impl PackageContentsTest {
 
         let mut collected_issues = Vec::new();
 
-        for glob in include_globs {
+        for glob in &include_globs {
             let matches = match_glob(&glob.1, &paths);
 
             if !matches.is_empty() {
@@ -254,7 +313,7 @@ impl PackageContentsTest {
             }
         }
 
-        for glob in bin_globs {
+        for glob in &bin_globs {
             let matches = match_glob(&glob.1, &paths);
 
             if !matches.is_empty() {
@@ -266,7 +325,7 @@ impl PackageContentsTest {
             }
         }
 
-        for glob in lib_globs {
+        for glob in &lib_globs {
             let matches = match_glob(&glob.1, &paths);
 
             if !matches.is_empty() {
@@ -278,7 +337,7 @@ impl PackageContentsTest {
             }
         }
 
-        for glob in site_package_globs {
+        for glob in &site_package_globs {
             let matches = match_glob(&glob.1, &paths);
 
             if !matches.is_empty() {
@@ -290,7 +349,7 @@ impl PackageContentsTest {
             }
         }
 
-        for glob in file_globs {
+        for glob in &file_globs {
             let matches = match_glob(&glob.1, &paths);
 
             if !matches.is_empty() {
@@ -302,6 +361,101 @@ impl PackageContentsTest {
             }
         }
 
+        for glob in self.not_exists.globs() {
+            let matcher = glob.compile_matcher();
+            for path in paths.iter().filter(|path| matcher.is_match(path)) {
+                collected_issues.push(format!(
+                    "File {} matches the not_exists glob: {}",
+                    path.display(),
+                    glob.glob()
+                ));
+            }
+        }
+
+        for (glob, max_size) in &self.max_size {
+            let matcher = build_glob(glob.clone())?.compile_matcher();
+            for entry in paths_json
+                .paths
+                .iter()
+                .filter(|entry| matcher.is_match(&entry.relative_path))
+            {
+                let size = entry.size_in_bytes.unwrap_or_default();
+                if size > *max_size {
+                    collected_issues.push(format!(
+                        "File {} is {} bytes, more than the max_size of {} bytes for: {}",
+                        entry.relative_path.display(),
+                        size,
+                        max_size,
+                        glob
+                    ));
+                }
+            }
+        }
+
+        if !self.text.is_empty() || !self.binary.is_empty() {
+            // symlinks are not checked, only the files they point to
+            let checked = paths_json
+                .paths
+                .iter()
+                .filter(|entry| entry.path_type == PathType::HardLink)
+                .map(|entry| &entry.relative_path)
+                .filter(|path| self.text.is_match(path) || self.binary.is_match(path))
+                .collect::<HashSet<_>>();
+            let binary = binary_files(package, &checked)?;
+            for path in &checked {
+                let Some(&is_binary) = binary.get(*path) else {
+                    collected_issues.push(format!(
+                        "File {} is missing from the package archive, so it can not be checked to be a text or binary file",
+                        path.display()
+                    ));
+                    continue;
+                };
+                if self.text.is_match(path) && is_binary {
+                    collected_issues.push(format!(
+                        "File {} is binary, but should be a text file",
+                        path.display()
+                    ));
+                }
+                if self.binary.is_match(path) && !is_binary {
+                    collected_issues.push(format!(
+                        "File {} is a text file, but should be binary",
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        if self.strict {
+            let declared = [
+                &include_globs,
+                &bin_globs,
+                &lib_globs,
+                &site_package_globs,
+                &file_globs,
+            ];
+            let undeclared = paths
+                .iter()
+                .filter(|path| {
+                    !declared
+                        .iter()
+                        .flat_map(|globs| globs.iter())
+                        .any(|(_, globset)| globset.is_match(path))
+                })
+                .collect::<Vec<_>>();
+            for path in undeclared.iter().take(10) {
+                collected_issues.push(format!(
+                    "File {} is not matched by any glob (strict)",
+                    path.display()
+                ));
+            }
+            if undeclared.len() > 10 {
+                collected_issues.push(format!(
+                    "... and {} more files that are not matched by any glob (strict)",
+                    undeclared.len() - 10
+                ));
+            }
+        }
+
         if !collected_issues.is_empty() {
             tracing::error!("Package content test failed:");
             for issue in &collected_issues {
@@ -326,9 +480,12 @@ mod tests {
     use std::path::Path;
 
     use super::PackageContentsTest;
-    use crate::recipe::parser::GlobVec;
+    use crate::{package_test::TestError, recipe::parser::GlobVec};
     use globset::GlobSet;
-    use rattler_conda_types::Platform;
+    use rattler_conda_types::{
+        package::{PathType, PathsEntry, PathsJson},
+        Platform,
+    };
     use serde::Deserialize;
 
     #[derive(Debug)]
@@ -483,6 +640,96 @@ mod tests {
         evaluate_test_case(test_case).unwrap();
     }
 
+    #[test]
+    fn test_is_binary() {
+        assert!(super::is_binary(&b"\x7fELF\x02\x01\x01\0\0"[..]).unwrap());
+        assert!(!super::is_binary("#!/bin/sh\necho hello\n".as_bytes()).unwrap());
+    }
+
+    fn paths_json(files: &[(&str, u64)]) -> PathsJson {
+        PathsJson {
+            paths: files
+                .iter()
+                .map(|(path, size)| PathsEntry {
+                    sha256: None,
+                    relative_path: (*path).into(),
+                    path_type: PathType::HardLink,
+                    prefix_placeholder: None,
+                    no_link: false,
+                    size_in_bytes: Some(*size),
+                })
+                .collect(),
+            paths_version: 1,
+        }
+    }
+
+    /// Run the test and return the issues. The package archive is only read for the
+    /// `text` and `binary` checks, so it does not need to exist.
+    fn run_content_test(test: &PackageContentsTest, paths_json: &PathsJson) -> Option<String> {
+        match test.run_test(paths_json, Path::new("missing.conda"), &Platform::Linux64) {
+            Ok(()) => None,
+            Err(TestError::PackageContentTestFailed(issues)) => Some(issues),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_strict() {
+        let paths_json = paths_json(&[("include/foo.h", 10), ("share/foo/data.txt", 10)]);
+        let test = PackageContentsTest {
+            include: GlobVec::from_vec(vec!["foo.h"]),
+            strict: true,
+            ..Default::default()
+        };
+        let issues = run_content_test(&test, &paths_json).unwrap();
+        assert!(issues.contains("share/foo/data.txt is not matched by any glob"));
+        assert!(!issues.contains("include/foo.h"));
+
+        let test = PackageContentsTest {
+            include: GlobVec::from_vec(vec!["foo.h"]),
+            files: GlobVec::from_vec(vec!["share/foo/**"]),
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(run_content_test(&test, &paths_json), None);
+    }
+
+    #[test]
+    fn test_not_exists() {
+        let paths_json = paths_json(&[("lib/libfoo.so", 10), ("lib/libfoo.a", 10)]);
+        let test = PackageContentsTest {
+            not_exists: GlobVec::from_vec(vec!["lib/*.a"]),
+            ..Default::default()
+        };
+        let issues = run_content_test(&test, &paths_json).unwrap();
+        assert!(issues.contains("File lib/libfoo.a matches the not_exists glob: lib/*.a"));
+        assert!(!issues.contains("libfoo.so"));
+
+        let test = PackageContentsTest {
+            not_exists: GlobVec::from_vec(vec!["share/**"]),
+            ..Default::default()
+        };
+        assert_eq!(run_content_test(&test, &paths_json), None);
+    }
+
+    #[test]
+    fn test_max_size() {
+        let paths_json = paths_json(&[("lib/libfoo.so", 2000), ("lib/libbar.so", 500)]);
+        let test = PackageContentsTest {
+            max_size: [("lib/*.so".to_string(), 1000)].into_iter().collect(),
+            ..Default::default()
+        };
+        let issues = run_content_test(&test, &paths_json).unwrap();
+        assert!(issues.contains("File lib/libfoo.so is 2000 bytes"));
+        assert!(!issues.contains("libbar.so"));
+
+        let test = PackageContentsTest {
+            max_size: [("lib/*.so".to_string(), 2000)].into_iter().collect(),
+            ..Default::default()
+        };
+        assert_eq!(run_content_test(&test, &paths_json), None);
+    }
+
     #[test]
     fn test_file_globs() {
         let test_case = load_test_case(Path::new("test_files.yaml"));
@@ -1,7 +1,9 @@
 //! Tests that are run as part of the package build process.
+mod channel;
 mod content_test;
 mod run_test;
 mod serialize_test;
 
+pub use channel::{find_packages, report_entry, skipped_entry, test_packages};
 pub use run_test::{run_test, TestConfiguration, TestError};
 pub(crate) use serialize_test::write_test_files;
@@ -6,29 +6,43 @@
 //! * `commands` - run a list of commands and check their exit code
 //! * `imports` - import a list of modules and check if they can be imported
 //! * `files` - check if a list of files exist
+//!
+//! All test scripts get the build-time constants of the package as environment variables
+//! (`RATTLER_TEST_*`), so that they can branch on the variant.
 
 use fs_err as fs;
 use rattler_conda_types::package::IndexJson;
 use rattler_conda_types::{Channel, ParseStrictness};
 use std::fmt::Write as fmt_write;
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
 };
 
 use dunce::canonicalize;
+use futures::future::BoxFuture;
 use rattler::package_cache::CacheKey;
-use rattler_conda_types::{package::ArchiveIdentifier, MatchSpec, Platform};
+use rattler_conda_types::{package::ArchiveIdentifier, MatchSpec, Platform, RepoDataRecord};
 use rattler_index::index;
 use rattler_shell::activation::ActivationError;
 use url::Url;
 
 use crate::env_vars;
-use crate::recipe::parser::{Script, ScriptContent};
+use crate::lockfile::{lockfile_name, CondaLock};
+use crate::recipe::parser::{InheritEnvironment, Script, ScriptContent};
+use crate::script::LogSink;
 use crate::source::copy_dir::CopyDir;
 use crate::{
-    recipe::parser::{CommandsTestRequirements, PythonTest},
-    render::solver::create_environment,
+    recipe::parser::{
+        CommandsTestExpectations, CommandsTestRequirements, DownstreamTest, PerlTest, PythonTest,
+        RTest,
+    },
+    render::{
+        solver::{create_environment, solve_environment},
+        unsolvable::explain_unsolvable,
+    },
     tool_configuration,
 };
 
@@ -50,6 +64,9 @@ pub enum TestError {
     #[error("failed to run test")]
     TestFailed,
 
+    #[error("test expectations failed:\n{0}")]
+    ExpectationsFailed(String),
+
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 
@@ -79,6 +96,21 @@ pub enum TestError {
 
     #[error("could not determine target platform from package file (no index.json?)")]
     CouldNotDetermineTargetPlatform,
+
+    #[error("failed to download {0}")]
+    DownloadFailed(Url, #[source] reqwest_middleware::Error),
+
+    #[error("the tests of the downstream package {0} failed")]
+    DownstreamTestFailed(String, #[source] Box<TestError>),
+
+    #[error("the downstream package {0} cannot be installed together with {1}: {2}")]
+    DownstreamUnsolvable(String, String, anyhow::Error),
+
+    #[error("failed to query the repodata")]
+    RepodataQuery(#[from] rattler_repodata_gateway::GatewayError),
+
+    #[error("failed to parse the repodata of {0}")]
+    RepodataParse(Url, #[source] serde_json::Error),
 }
 
 #[derive(Debug)]
@@ -88,11 +120,18 @@ enum Tests {
 }
 
 impl Tests {
-    async fn run(&self, environment: &Path, cwd: &Path) -> Result<(), TestError> {
+    async fn run(
+        &self,
+        environment: &Path,
+        cwd: &Path,
+        test_env: &HashMap<String, String>,
+        log_sinks: &[Arc<dyn LogSink>],
+    ) -> Result<(), TestError> {
         tracing::info!("Testing commands:");
 
         let mut env_vars = env_vars::os_vars(environment, &Platform::current());
         env_vars.retain(|key, _| key != "PATH");
+        env_vars.extend(test_env_vars(test_env, environment));
         env_vars.insert(
             "PREFIX".to_string(),
             environment.to_string_lossy().to_string(),
@@ -115,7 +154,16 @@ impl Tests {
                 })?;
 
                 script
-                    .run_script(env_vars, tmp_dir.path(), cwd, environment, None)
+                    .run_script(
+                        env_vars,
+                        tmp_dir.path(),
+                        cwd,
+                        environment,
+                        None,
+                        &InheritEnvironment::all(),
+                        None,
+                        log_sinks,
+                    )
                     .await
                     .map_err(|_| TestError::TestFailed)?;
             }
@@ -127,7 +175,16 @@ impl Tests {
                 };
 
                 script
-                    .run_script(env_vars, tmp_dir.path(), cwd, environment, None)
+                    .run_script(
+                        env_vars,
+                        tmp_dir.path(),
+                        cwd,
+                        environment,
+                        None,
+                        &InheritEnvironment::all(),
+                        None,
+                        log_sinks,
+                    )
                     .await
                     .map_err(|_| TestError::TestFailed)?;
             }
@@ -136,6 +193,86 @@ impl Tests {
     }
 }
 
+/// The build-time constants of a package that are exposed to its tests, so that test
+/// scripts can branch on the variant without re-parsing files:
+///
+/// * `RATTLER_TEST_<KEY>` for every variant key of the package (e.g. `RATTLER_TEST_PYTHON`)
+/// * `RATTLER_TEST_TARGET_PLATFORM`, `RATTLER_TEST_PKG_NAME`, `RATTLER_TEST_PKG_VERSION` and
+///   `RATTLER_TEST_PKG_BUILD_STRING`
+///
+/// The variant is read from `info/hash_input.json` of the extracted package. Only
+/// strings, numbers and booleans are exported, other values (and a malformed file) are
+/// skipped.
+fn package_test_env(
+    package_folder: &Path,
+    pkg: &ArchiveIdentifier,
+    target_platform: Platform,
+) -> Result<HashMap<String, String>, TestError> {
+    let hash_input = package_folder.join("info/hash_input.json");
+    let variant = if hash_input.exists() {
+        match serde_json::from_str(&fs::read_to_string(&hash_input)?) {
+            Ok(serde_json::Value::Object(variant)) => variant,
+            Ok(_) => {
+                tracing::debug!("{} is not a JSON object, skipping it", hash_input.display());
+                Default::default()
+            }
+            Err(e) => {
+                tracing::debug!("Could not parse {}: {}", hash_input.display(), e);
+                Default::default()
+            }
+        }
+    } else {
+        Default::default()
+    };
+
+    let mut env = HashMap::new();
+    for (key, value) in variant {
+        let value = match value {
+            serde_json::Value::String(value) => value,
+            serde_json::Value::Number(value) => value.to_string(),
+            serde_json::Value::Bool(value) => value.to_string(),
+            value => {
+                tracing::debug!(
+                    "Not exporting the variant key `{}` to the tests, `{}` is not a scalar",
+                    key,
+                    value
+                );
+                continue;
+            }
+        };
+        env.insert(test_env_key(&key), value);
+    }
+    env.insert(test_env_key("target_platform"), target_platform.to_string());
+    env.insert(test_env_key("pkg_name"), pkg.name.clone());
+    env.insert(test_env_key("pkg_version"), pkg.version.clone());
+    env.insert(test_env_key("pkg_build_string"), pkg.build_string.clone());
+    Ok(env)
+}
+
+/// The name of the environment variable for a build-time constant, e.g.
+/// `RATTLER_TEST_CUDA_COMPILER_VERSION` for `cuda_compiler_version`.
+fn test_env_key(key: &str) -> String {
+    let key = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("RATTLER_TEST_{key}")
+}
+
+/// The environment variables of a test script: the build-time constants of the package
+/// and `RATTLER_TEST_PREFIX`, the prefix of the test environment.
+fn test_env_vars(test_env: &HashMap<String, String>, prefix: &Path) -> HashMap<String, String> {
+    let mut env_vars = test_env.clone();
+    env_vars.insert(test_env_key("prefix"), prefix.to_string_lossy().to_string());
+    env_vars
+}
+
 async fn legacy_tests_from_folder(pkg: &Path) -> Result<(PathBuf, Vec<Tests>), std::io::Error> {
     let mut tests = Vec::new();
 
@@ -181,6 +318,54 @@ pub struct TestConfiguration {
     pub channels: Vec<Url>,
     /// The tool configuration
     pub tool_configuration: tool_configuration::Configuration,
+    /// Skip the `downstream` tests. This is set when the tests of a downstream package
+    /// are run, so that downstream tests do not recurse.
+    pub skip_downstream_tests: bool,
+    /// The directory that the lock files of the test environments are written to
+    /// (`--lockfile-dir`)
+    pub lockfile_dir: Option<PathBuf>,
+}
+
+/// Create a test environment and write its lock file to the lock file directory of the
+/// test configuration, as `<package>.<environment>.conda-lock.yml` (e.g.
+/// `foo-1.0-h1234567_0.test-0.conda-lock.yml` for the first test).
+async fn create_test_environment(
+    pkg: &ArchiveIdentifier,
+    environment: &str,
+    specs: &[MatchSpec],
+    platform: &Platform,
+    prefix: &Path,
+    config: &TestConfiguration,
+) -> anyhow::Result<Vec<RepoDataRecord>> {
+    let records = create_environment(
+        specs,
+        platform,
+        prefix,
+        &config.channels,
+        &config.tool_configuration,
+    )
+    .await?;
+
+    if let Some(lockfile_dir) = &config.lockfile_dir {
+        let path = lockfile_dir.join(format!(
+            "{}-{}-{}.{}",
+            pkg.name,
+            pkg.version,
+            pkg.build_string,
+            lockfile_name(environment)
+        ));
+        CondaLock::new(&pkg.name, environment, *platform, &records).write(&path)?;
+    }
+    Ok(records)
+}
+
+/// The name of the environment of a test in a lock file, e.g. `test-0` for the tests in
+/// `info/tests/0`.
+fn test_environment_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(index) => format!("test-{}", index.to_string_lossy()),
+        None => "test".to_string(),
+    }
 }
 
 /// Run a test for a single package
@@ -238,13 +423,17 @@ pub async fn run_test(package_file: &Path, config: &TestConfiguration) -> Result
     // index the temporary channel
     index(tmp_repo.path(), Some(&target_platform))?;
 
-    let cache_dir = rattler::default_cache_dir()?;
+    let cache_dir = config.tool_configuration.package_cache_dir();
+    let cache_lock = config
+        .tool_configuration
+        .lock_cache(&cache_dir, "package cache")
+        .await?;
 
     let pkg = ArchiveIdentifier::try_from_path(package_file).ok_or(TestError::TestFailed)?;
 
     // if the package is already in the cache, remove it. TODO make this based on SHA256 instead!
     let cache_key = CacheKey::from(pkg.clone());
-    let package_folder = cache_dir.join("pkgs").join(cache_key.to_string());
+    let package_folder = cache_dir.join(cache_key.to_string());
 
     if package_folder.exists() {
         tracing::info!("Removing previously cached package {:?}", &package_folder);
@@ -276,6 +465,10 @@ pub async fn run_test(package_file: &Path, config: &TestConfiguration) -> Result
         tracing::error!("Failed to extract package: {:?}", e);
         TestError::TestFailed
     })?;
+    // the test environments are installed from the same cache, which locks it again
+    drop(cache_lock);
+
+    let test_env = package_test_env(&package_folder, &pkg, target_platform)?;
 
     // extract package in place
     if package_folder.join("info/test").exists() {
@@ -301,12 +494,13 @@ pub async fn run_test(package_file: &Path, config: &TestConfiguration) -> Result
         .map_err(|e| TestError::MatchSpecParse(e.to_string()))?;
         dependencies.push(match_spec);
 
-        create_environment(
+        create_test_environment(
+            &pkg,
+            "test-legacy",
             &dependencies,
             &platform,
             &prefix,
-            &config.channels,
-            &config.tool_configuration,
+            &config,
         )
         .await
         .map_err(TestError::TestEnvironmentSetup)?;
@@ -315,7 +509,13 @@ pub async fn run_test(package_file: &Path, config: &TestConfiguration) -> Result
         let (test_folder, tests) = legacy_tests_from_folder(&package_folder).await?;
 
         for test in tests {
-            test.run(&prefix, &test_folder).await?;
+            test.run(
+                &prefix,
+                &test_folder,
+                &test_env,
+                config.tool_configuration.log_sink.as_slice(),
+            )
+            .await?;
         }
 
         tracing::info!(
@@ -332,7 +532,7 @@ pub async fn run_test(package_file: &Path, config: &TestConfiguration) -> Result
         // for each enumerated test, we load and run it
         while let Some(entry) = read_dir.next_entry().await? {
             tracing::info!("test {:?}", entry.path());
-            run_individual_test(&pkg, &entry.path(), &prefix, &config).await?;
+            run_individual_test(&pkg, &entry.path(), &prefix, &config, &test_env).await?;
         }
 
         tracing::info!(
@@ -351,6 +551,7 @@ async fn run_python_test(
     path: &Path,
     prefix: &Path,
     config: &TestConfiguration,
+    test_env: &HashMap<String, String>,
 ) -> Result<(), TestError> {
     let test_file = path.join("python_test.json");
     let test: PythonTest = serde_json::from_reader(fs::File::open(test_file)?)?;
@@ -364,12 +565,13 @@ async fn run_python_test(
         dependencies.push(MatchSpec::from_str("pip", ParseStrictness::Strict).unwrap());
     }
 
-    create_environment(
+    create_test_environment(
+        pkg,
+        &test_environment_name(path),
         &dependencies,
         &Platform::current(),
         prefix,
-        &config.channels,
-        &config.tool_configuration,
+        config,
     )
     .await
     .map_err(TestError::TestEnvironmentSetup)?;
@@ -387,7 +589,16 @@ async fn run_python_test(
 
     let tmp_dir = tempfile::tempdir()?;
     script
-        .run_script(Default::default(), tmp_dir.path(), path, prefix, None)
+        .run_script(
+            test_env_vars(test_env, prefix),
+            tmp_dir.path(),
+            path,
+            prefix,
+            None,
+            &InheritEnvironment::all(),
+            None,
+            config.tool_configuration.log_sink.as_slice(),
+        )
         .await
         .map_err(|_| TestError::TestFailed)?;
 
@@ -402,7 +613,16 @@ async fn run_python_test(
             ..Script::default()
         };
         script
-            .run_script(Default::default(), path, path, prefix, None)
+            .run_script(
+                test_env_vars(test_env, prefix),
+                path,
+                path,
+                prefix,
+                None,
+                &InheritEnvironment::all(),
+                None,
+                config.tool_configuration.log_sink.as_slice(),
+            )
             .await
             .map_err(|_| TestError::TestFailed)?;
 
@@ -415,11 +635,118 @@ async fn run_python_test(
     Ok(())
 }
 
+/// Install the package and the interpreter (e.g. `perl`) in a test environment and
+/// run a command that loads the modules of the package.
+async fn run_load_test(
+    pkg: &ArchiveIdentifier,
+    path: &Path,
+    prefix: &Path,
+    config: &TestConfiguration,
+    test_env: &HashMap<String, String>,
+    interpreter: &str,
+    command: String,
+) -> Result<(), TestError> {
+    let match_spec = MatchSpec::from_str(
+        format!("{}={}={}", pkg.name, pkg.version, pkg.build_string).as_str(),
+        ParseStrictness::Lenient,
+    )?;
+    let dependencies = vec![
+        match_spec,
+        MatchSpec::from_str(interpreter, ParseStrictness::Strict)?,
+    ];
+
+    create_test_environment(
+        pkg,
+        &test_environment_name(path),
+        &dependencies,
+        &Platform::current(),
+        prefix,
+        config,
+    )
+    .await
+    .map_err(TestError::TestEnvironmentSetup)?;
+
+    let script = Script {
+        content: ScriptContent::Command(command),
+        ..Script::default()
+    };
+
+    let tmp_dir = tempfile::tempdir()?;
+    script
+        .run_script(
+            test_env_vars(test_env, prefix),
+            tmp_dir.path(),
+            path,
+            prefix,
+            None,
+            &InheritEnvironment::all(),
+            None,
+            config.tool_configuration.log_sink.as_slice(),
+        )
+        .await
+        .map_err(|_| TestError::TestFailed)?;
+
+    Ok(())
+}
+
+async fn run_perl_test(
+    pkg: &ArchiveIdentifier,
+    path: &Path,
+    prefix: &Path,
+    config: &TestConfiguration,
+    test_env: &HashMap<String, String>,
+) -> Result<(), TestError> {
+    let test_file = path.join("perl_test.json");
+    let test: PerlTest = serde_json::from_reader(fs::File::open(test_file)?)?;
+
+    let uses = test
+        .uses
+        .iter()
+        .map(|module| format!("use {};", module))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let command = format!("perl -e \"{}\"", uses);
+    run_load_test(pkg, path, prefix, config, test_env, "perl", command).await?;
+
+    tracing::info!(
+        "{} perl uses test passed!",
+        console::style(console::Emoji("✔", "")).green()
+    );
+    Ok(())
+}
+
+async fn run_r_test(
+    pkg: &ArchiveIdentifier,
+    path: &Path,
+    prefix: &Path,
+    config: &TestConfiguration,
+    test_env: &HashMap<String, String>,
+) -> Result<(), TestError> {
+    let test_file = path.join("r_test.json");
+    let test: RTest = serde_json::from_reader(fs::File::open(test_file)?)?;
+
+    let libraries = test
+        .libraries
+        .iter()
+        .map(|library| format!("library({});", library))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let command = format!("Rscript -e \"{}\"", libraries);
+    run_load_test(pkg, path, prefix, config, test_env, "r-base", command).await?;
+
+    tracing::info!(
+        "{} r libraries test passed!",
+        console::style(console::Emoji("✔", "")).green()
+    );
+    Ok(())
+}
+
 async fn run_shell_test(
     pkg: &ArchiveIdentifier,
     path: &Path,
     prefix: &Path,
     config: &TestConfiguration,
+    test_env: &HashMap<String, String>,
 ) -> Result<(), TestError> {
     let deps = if path.join("test_time_dependencies.json").exists() {
         let test_dep_json = path.join("test_time_dependencies.json");
@@ -438,15 +765,22 @@ async fn run_shell_test(
             .map(|s| MatchSpec::from_str(s, ParseStrictness::Lenient))
             .collect::<Result<Vec<_>, _>>()?;
 
-        create_environment(
+        let origins = build_dependencies
+            .iter()
+            .map(|spec| (spec.clone(), "tests.requirements.build".to_string()))
+            .collect::<Vec<_>>();
+        create_test_environment(
+            pkg,
+            &format!("{}-build", test_environment_name(path)),
             &build_dependencies,
             &platform,
             &build_prefix,
-            &config.channels,
-            &config.tool_configuration,
+            config,
         )
         .await
-        .map_err(TestError::TestEnvironmentSetup)?;
+        .map_err(|e| {
+            TestError::TestEnvironmentSetup(explain_unsolvable(e, "test build", &origins))
+        })?;
         Some(build_prefix)
     } else {
         None
@@ -466,23 +800,44 @@ async fn run_shell_test(
 
     let platform = config.target_platform.unwrap_or_else(Platform::current);
 
+    let origins = dependencies
+        .iter()
+        .enumerate()
+        .map(|(index, spec)| {
+            let origin = if index + 1 == dependencies.len() {
+                "the package under test"
+            } else {
+                "tests.requirements.run"
+            };
+            (spec.clone(), origin.to_string())
+        })
+        .collect::<Vec<_>>();
+
     let run_env = prefix.join("run");
-    create_environment(
+    create_test_environment(
+        pkg,
+        &test_environment_name(path),
         &dependencies,
         &platform,
         &run_env,
-        &config.channels,
-        &config.tool_configuration,
+        config,
     )
     .await
-    .map_err(TestError::TestEnvironmentSetup)?;
+    .map_err(|e| TestError::TestEnvironmentSetup(explain_unsolvable(e, "test", &origins)))?;
 
     let mut env_vars = env_vars::os_vars(prefix, &Platform::current());
     env_vars.retain(|key, _| key != "PATH");
+    env_vars.extend(test_env_vars(test_env, &run_env));
     env_vars.insert("PREFIX".to_string(), run_env.to_string_lossy().to_string());
 
+    let env_file = path.join("test_env.json");
     let script = Script {
         content: ScriptContent::Path(PathBuf::from("run_test")),
+        env: if env_file.exists() {
+            serde_json::from_str(&fs::read_to_string(env_file)?)?
+        } else {
+            Default::default()
+        },
         ..Default::default()
     };
 
@@ -496,11 +851,262 @@ async fn run_shell_test(
     })?;
 
     tracing::info!("Testing commands:");
-    script
-        .run_script(env_vars, tmp_dir.path(), path, &run_env, build_env.as_ref())
+    let expect_file = path.join("expect.json");
+    if expect_file.exists() {
+        let expect: CommandsTestExpectations =
+            serde_json::from_str(&fs::read_to_string(expect_file)?)?;
+        let output = script
+            .run_script_with_output(
+                env_vars,
+                tmp_dir.path(),
+                path,
+                &run_env,
+                build_env.as_ref(),
+                &InheritEnvironment::all(),
+                None,
+                config.tool_configuration.log_sink.as_slice(),
+            )
+            .await?;
+        expect.check(&output)?;
+    } else {
+        script
+            .run_script(
+                env_vars,
+                tmp_dir.path(),
+                path,
+                &run_env,
+                build_env.as_ref(),
+                &InheritEnvironment::all(),
+                None,
+                config.tool_configuration.log_sink.as_slice(),
+            )
+            .await
+            .map_err(|_| TestError::TestFailed)?;
+    }
+
+    Ok(())
+}
+
+impl CommandsTestExpectations {
+    /// Check the exit code and the output of a script test against the expectations.
+    fn check(&self, output: &std::process::Output) -> Result<(), TestError> {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        let mut failures = Vec::new();
+        match output.status.code() {
+            Some(code) if code == self.exit_code => {}
+            Some(code) => failures.push(format!(
+                "expected exit code {}, got {}",
+                self.exit_code, code
+            )),
+            None => failures.push("the script was terminated by a signal".to_string()),
+        }
+        for (stream, content, contains, not_contains, matches) in [
+            (
+                "stdout",
+                &stdout,
+                &self.stdout_contains,
+                &self.stdout_not_contains,
+                &self.stdout_matches,
+            ),
+            (
+                "stderr",
+                &stderr,
+                &self.stderr_contains,
+                &self.stderr_not_contains,
+                &self.stderr_matches,
+            ),
+        ] {
+            let mut stream_failures = Vec::new();
+            for expected in contains {
+                if !content.contains(expected.as_str()) {
+                    stream_failures.push(format!("expected {stream} to contain {expected:?}"));
+                }
+            }
+            for unexpected in not_contains {
+                if content.contains(unexpected.as_str()) {
+                    stream_failures
+                        .push(format!("expected {stream} not to contain {unexpected:?}"));
+                }
+            }
+            for pattern in matches {
+                match regex::Regex::new(pattern) {
+                    Ok(regex) if regex.is_match(content) => {}
+                    Ok(_) => {
+                        stream_failures.push(format!("expected {stream} to match /{pattern}/"))
+                    }
+                    Err(err) => stream_failures.push(format!(
+                        "invalid regular expression /{pattern}/ for {stream}: {err}"
+                    )),
+                }
+            }
+            // show the actual output once, below the assertions on it
+            if !stream_failures.is_empty() {
+                let actual = if content.trim().is_empty() {
+                    "   (empty)".to_string()
+                } else {
+                    content
+                        .lines()
+                        .map(|line| format!("   | {line}"))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+                stream_failures.push(format!("actual {stream}:\n{actual}"));
+            }
+            failures.extend(stream_failures);
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(TestError::ExpectationsFailed(
+                failures
+                    .iter()
+                    .map(|failure| format!(" - {failure}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ))
+        }
+    }
+}
+
+/// Download a file, or read it if it is a local (`file://`) url.
+pub(crate) async fn download(
+    url: &Url,
+    config: &tool_configuration::Configuration,
+) -> Result<Vec<u8>, TestError> {
+    if let Ok(path) = url.to_file_path() {
+        return Ok(fs::read(path)?);
+    }
+
+    let download = async {
+        let response = config
+            .client
+            .get(url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok::<_, reqwest_middleware::Error>(response.bytes().await?)
+    };
+    let bytes = download
         .await
-        .map_err(|_| TestError::TestFailed)?;
+        .map_err(|err| TestError::DownloadFailed(url.clone(), err))?;
+    Ok(bytes.to_vec())
+}
+
+/// Download a package (or copy it from a local channel) into a directory.
+pub(crate) async fn fetch_package(
+    record: &RepoDataRecord,
+    dir: &Path,
+    config: &TestConfiguration,
+) -> Result<PathBuf, TestError> {
+    let path = dir.join(&record.file_name);
+    fs::write(
+        &path,
+        download(&record.url, &config.tool_configuration).await?,
+    )?;
+    Ok(path)
+}
+
+/// Boxed, because the tests of a downstream package are run from within `run_test`.
+fn run_test_boxed<'a>(
+    package_file: &'a Path,
+    config: &'a TestConfiguration,
+) -> BoxFuture<'a, Result<(), TestError>> {
+    Box::pin(run_test(package_file, config))
+}
+
+/// Run the tests of a downstream package against the package. If the downstream package
+/// cannot be installed together with the package (e.g. because it requires an older
+/// version), the test is skipped.
+async fn run_downstream_test(
+    pkg: &ArchiveIdentifier,
+    path: &Path,
+    prefix: &Path,
+    config: &TestConfiguration,
+) -> Result<(), TestError> {
+    let test_file = path.join("downstream_test.json");
+    let test: DownstreamTest = serde_json::from_reader(fs::File::open(test_file)?)?;
+
+    if config.skip_downstream_tests {
+        tracing::info!(
+            "Skipping the downstream test of {} in the tests of a downstream package",
+            test.downstream
+        );
+        return Ok(());
+    }
+
+    let downstream_spec = MatchSpec::from_str(&test.downstream, ParseStrictness::Lenient)?;
+    let Some(downstream_name) = downstream_spec.name.clone() else {
+        return Err(TestError::MatchSpecParse(format!(
+            "the downstream package `{}` has no name",
+            test.downstream
+        )));
+    };
+    // pin the exact version and build, `name=version=build` would also match versions
+    // that start with the version
+    let match_spec = MatchSpec::from_str(
+        format!("{} =={} {}", pkg.name, pkg.version, pkg.build_string).as_str(),
+        ParseStrictness::Strict,
+    )?;
 
+    let platform = match config.target_platform {
+        Some(Platform::NoArch) | None => Platform::current(),
+        Some(platform) => platform,
+    };
+
+    let records = solve_environment(
+        &[match_spec, downstream_spec],
+        &platform,
+        &config.channels,
+        &config.tool_configuration,
+    )
+    .await
+    .map_err(|err| {
+        TestError::DownstreamUnsolvable(
+            test.downstream.clone(),
+            format!("{}-{}-{}", pkg.name, pkg.version, pkg.build_string),
+            err,
+        )
+    })?;
+    let Some(downstream) = records
+        .iter()
+        .find(|record| record.package_record.name == downstream_name)
+    else {
+        return Err(TestError::MatchSpecParse(format!(
+            "the downstream package `{}` is not part of the solved environment",
+            test.downstream
+        )));
+    };
+
+    tracing::info!(
+        "Running the tests of the downstream package {}",
+        downstream.file_name
+    );
+    let download_dir = tempfile::tempdir()?;
+    let package_file = fetch_package(downstream, download_dir.path(), config).await?;
+    let downstream_config = TestConfiguration {
+        test_prefix: prefix.join("downstream"),
+        target_platform: None,
+        keep_test_prefix: false,
+        skip_downstream_tests: true,
+        ..config.clone()
+    };
+    run_test_boxed(&package_file, &downstream_config)
+        .await
+        .map_err(|err| {
+            TestError::DownstreamTestFailed(
+                downstream.package_record.name.as_normalized().to_string(),
+                Box::new(err),
+            )
+        })?;
+
+    tracing::info!(
+        "{} downstream test of {} passed!",
+        console::style(console::Emoji("✔", "")).green(),
+        test.downstream
+    );
     Ok(())
 }
 
@@ -509,12 +1115,19 @@ async fn run_individual_test(
     path: &Path,
     prefix: &Path,
     config: &TestConfiguration,
+    test_env: &HashMap<String, String>,
 ) -> Result<(), TestError> {
     if path.join("python_test.json").exists() {
-        run_python_test(pkg, path, prefix, config).await?;
+        run_python_test(pkg, path, prefix, config, test_env).await?;
+    } else if path.join("perl_test.json").exists() {
+        run_perl_test(pkg, path, prefix, config, test_env).await?;
+    } else if path.join("r_test.json").exists() {
+        run_r_test(pkg, path, prefix, config, test_env).await?;
+    } else if path.join("downstream_test.json").exists() {
+        run_downstream_test(pkg, path, prefix, config).await?;
     } else if path.join("run_test.sh").exists() || path.join("run_test.bat").exists() {
         // run shell test
-        run_shell_test(pkg, path, prefix, config).await?;
+        run_shell_test(pkg, path, prefix, config, test_env).await?;
     } else {
         // no test found
     }
@@ -526,3 +1139,82 @@ async fn run_individual_test(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_test_env() {
+        let package_folder = tempfile::tempdir().unwrap();
+        fs::create_dir_all(package_folder.path().join("info")).unwrap();
+        fs::write(
+            package_folder.path().join("info/hash_input.json"),
+            r#"{"python": "3.11", "cuda_compiler_version": "None", "target_platform": "linux-64"}"#,
+        )
+        .unwrap();
+        let pkg = ArchiveIdentifier::try_from_filename("foo-1.0-py311_0.conda").unwrap();
+
+        let env = package_test_env(package_folder.path(), &pkg, Platform::Linux64).unwrap();
+        assert_eq!(env["RATTLER_TEST_PYTHON"], "3.11");
+        assert_eq!(env["RATTLER_TEST_CUDA_COMPILER_VERSION"], "None");
+        assert_eq!(env["RATTLER_TEST_TARGET_PLATFORM"], "linux-64");
+        assert_eq!(env["RATTLER_TEST_PKG_VERSION"], "1.0");
+        assert_eq!(env["RATTLER_TEST_PKG_BUILD_STRING"], "py311_0");
+
+        // only scalar values are exported
+        fs::write(
+            package_folder.path().join("info/hash_input.json"),
+            r#"{"python": "3.11", "numpy": 2, "debug": true, "zip_keys": [["a", "b"]], "none": null}"#,
+        )
+        .unwrap();
+        let env = package_test_env(package_folder.path(), &pkg, Platform::Linux64).unwrap();
+        assert_eq!(env["RATTLER_TEST_NUMPY"], "2");
+        assert_eq!(env["RATTLER_TEST_DEBUG"], "true");
+        assert!(!env.contains_key("RATTLER_TEST_ZIP_KEYS"));
+        assert!(!env.contains_key("RATTLER_TEST_NONE"));
+
+        // a malformed file is skipped
+        fs::write(
+            package_folder.path().join("info/hash_input.json"),
+            "not json",
+        )
+        .unwrap();
+        let env = package_test_env(package_folder.path(), &pkg, Platform::Linux64).unwrap();
+        assert!(!env.contains_key("RATTLER_TEST_PYTHON"));
+        assert_eq!(env["RATTLER_TEST_PKG_NAME"], "foo");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_commands_test_expectations() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let output = |code: i32, stdout: &str, stderr: &str| std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        let expect = CommandsTestExpectations {
+            exit_code: 2,
+            stdout_contains: vec!["usage:".to_string()],
+            stderr_not_contains: vec!["panicked".to_string()],
+            stdout_matches: vec![r"^usage: \w+ \[OPTIONS\]".to_string()],
+            ..Default::default()
+        };
+
+        assert!(expect
+            .check(&output(2, "usage: foo [OPTIONS]\n", ""))
+            .is_ok());
+
+        let err = expect
+            .check(&output(0, "foo 1.0\n", "thread 'main' panicked\n"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("expected exit code 2, got 0"));
+        assert!(err.contains("expected stdout to contain \"usage:\""));
+        assert!(err.contains("expected stderr not to contain \"panicked\""));
+        assert!(err.contains("expected stdout to match /^usage: \\w+ \\[OPTIONS\\]/"));
+        assert!(err.contains("actual stdout:\n   | foo 1.0"));
+    }
+}
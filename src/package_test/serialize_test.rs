@@ -9,7 +9,7 @@ use std::{
 use crate::{
     metadata::Output,
     packaging::PackagingError,
-    recipe::parser::{CommandsTest, DownstreamTest, PythonTest, TestType},
+    recipe::parser::{CommandsTest, DownstreamTest, PerlTest, PythonTest, RTest, TestType},
 };
 
 impl DownstreamTest {
@@ -44,6 +44,15 @@ impl CommandsTest {
 
         for cf in command_files {
             let mut file = File::create(&cf)?;
+            // the commands are not echoed, so that the output only contains the output
+            // of the commands that the expectations are checked against
+            if self.expect.is_some() {
+                if cf.extension().is_some_and(|ext| ext == "bat") {
+                    writeln!(file, "@echo off\n")?;
+                } else {
+                    writeln!(file, "{{ set +x; }} 2>/dev/null\n")?;
+                }
+            }
             for el in &self.script {
                 writeln!(file, "{}\n", el)?;
             }
@@ -58,6 +67,18 @@ impl CommandsTest {
             test_files.push(test_file);
         }
 
+        if !self.env.is_empty() {
+            let env_file = folder.join("test_env.json");
+            serde_json::to_writer(&File::create(&env_file)?, &self.env)?;
+            test_files.push(env_file);
+        }
+
+        if let Some(expect) = &self.expect {
+            let expect_file = folder.join("expect.json");
+            serde_json::to_writer(&File::create(&expect_file)?, expect)?;
+            test_files.push(expect_file);
+        }
+
         if !self.files.recipe.is_empty() {
             let globs = &self.files.recipe;
             let copy_dir = crate::source::copy_dir::CopyDir::new(
@@ -97,6 +118,24 @@ impl PythonTest {
     }
 }
 
+impl PerlTest {
+    fn write_to_folder(&self, folder: &Path) -> Result<Vec<PathBuf>, PackagingError> {
+        fs::create_dir_all(folder)?;
+        let path = folder.join("perl_test.json");
+        serde_json::to_writer(&File::create(&path)?, self)?;
+        Ok(vec![path])
+    }
+}
+
+impl RTest {
+    fn write_to_folder(&self, folder: &Path) -> Result<Vec<PathBuf>, PackagingError> {
+        fs::create_dir_all(folder)?;
+        let path = folder.join("r_test.json");
+        serde_json::to_writer(&File::create(&path)?, self)?;
+        Ok(vec![path])
+    }
+}
+
 /// Write out the test files for the final package
 pub(crate) fn write_test_files(
     output: &Output,
@@ -108,6 +147,8 @@ pub(crate) fn write_test_files(
         let folder = tmp_dir_path.join(format!("info/tests/{}", idx));
         let files = match test {
             TestType::Python(python_test) => python_test.write_to_folder(&folder)?,
+            TestType::Perl(perl_test) => perl_test.write_to_folder(&folder)?,
+            TestType::R(r_test) => r_test.write_to_folder(&folder)?,
             TestType::Command(command_test) => command_test.write_to_folder(&folder, output)?,
             TestType::Downstream(downstream_test) => downstream_test.write_to_folder(&folder)?,
             TestType::PackageContents(_) => Vec::new(),
@@ -1,6 +1,7 @@
 //! This module contains the functions to package a conda package from a given output.
 use fs_err as fs;
 use fs_err::File;
+use indicatif::HumanBytes;
 use rattler_conda_types::Platform;
 use std::collections::HashSet;
 use std::io::Write;
@@ -18,6 +19,7 @@ mod metadata;
 pub use file_finder::{Files, TempFiles};
 pub use metadata::create_prefix_placeholder;
 
+use crate::lockfile::{lockfile_name, CondaLock};
 use crate::metadata::Output;
 use crate::package_test::write_test_files;
 use crate::{post_process, tool_configuration};
@@ -72,6 +74,18 @@ pub enum PackagingError {
 
     #[error("No license files were copied")]
     LicensesNotFound,
+
+    #[error("`python.abi3` is set, but these extension modules are not built against the stable ABI: {0:?}")]
+    Abi3Violation(Vec<PathBuf>),
+
+    #[error("files with the setuid or setgid bit are not allowed (add them to `build.file_permissions.allow_setuid` to keep them): {0:?}")]
+    SetuidNotAllowed(Vec<PathBuf>),
+
+    #[error(transparent)]
+    Lockfile(#[from] crate::lockfile::LockfileError),
+
+    #[error("absolute paths of the build machine leaked into files that are not registered for prefix replacement (set `build.prefix_detection.allow_leaked_prefixes` to ignore them):\n  {}", .0.join("\n  "))]
+    LeakedPrefix(Vec<String>),
 }
 
 /// This function copies the license files to the info/licenses folder.
@@ -201,7 +215,13 @@ pub fn package_conda(
         return Err(PackagingError::DependenciesNotFinalized);
     }
 
-    let mut tmp = files.to_temp_folder(output)?;
+    let log_handler = &tool_configuration.fancy_log_handler;
+    let progress_bar = log_handler.add_counting_progress_bar("Copying files", 0);
+    let mut tmp = files.to_temp_folder(output, &progress_bar)?;
+    log_handler.finish_progress_bar(
+        &progress_bar,
+        format!("{} files", progress_bar.length().unwrap_or_default()),
+    );
 
     tracing::info!("Copying done!");
 
@@ -209,15 +229,26 @@ pub fn package_conda(
 
     tmp.add_files(post_process::python::python(&tmp, output)?);
 
+    post_process::reproducible::normalize_pyc_timestamps(&tmp, output)?;
+
     post_process::regex_replacements::regex_post_process(&tmp, output)?;
 
+    post_process::permissions::file_permissions(&tmp, output)?;
+
     tracing::info!("Post-processing done!");
 
     let info_folder = tmp.temp_dir.path().join("info");
 
     tracing::info!("Writing metadata for package");
 
-    tmp.add_files(output.write_metadata(&tmp)?);
+    let progress_bar = log_handler.add_counting_progress_bar("Hashing files", 0);
+    tmp.add_files(output.write_metadata(&tmp, &progress_bar)?);
+    log_handler.finish_progress_bar(
+        &progress_bar,
+        format!("{} files", progress_bar.length().unwrap_or_default()),
+    );
+
+    post_process::prefix_leaks::check_prefix_leaks(&tmp, output)?;
 
     // TODO move things below also to metadata.rs
     tracing::info!("Copying license files");
@@ -231,6 +262,18 @@ pub fn package_conda(
         tmp.add_files(recipe_files);
     }
 
+    if let Some(format) = output.build_configuration.sbom {
+        tracing::info!("Writing SBOM");
+        let sbom_path = info_folder.join(format.file_name());
+        let sbom = crate::sbom::sbom(output, format)?;
+        fs::write(&sbom_path, serde_json::to_vec_pretty(&sbom)?)?;
+        tmp.add_files(vec![sbom_path]);
+    }
+
+    tracing::info!("Writing lock files");
+    let lockfiles = write_lockfiles(output, &info_folder)?;
+    tmp.add_files(lockfiles);
+
     tracing::info!("Writing test files");
     let test_files = write_test_files(output, tmp.temp_dir.path())?;
     tmp.add_files(test_files);
@@ -239,7 +282,7 @@ pub fn package_conda(
     // create any entry points or link.json for noarch packages
     if output.recipe.build().noarch().is_python() {
         let link_json = File::create(info_folder.join("link.json"))?;
-        serde_json::to_writer_pretty(link_json, &output.link_json()?)?;
+        serde_json::to_writer_pretty(link_json, &output.link_json(&tmp)?)?;
         tmp.add_files(vec![info_folder.join("link.json")]);
     }
 
@@ -292,11 +335,12 @@ pub fn package_conda(
 
     tracing::info!("Compressing archive...");
 
-    let progress_bar = tool_configuration.fancy_log_handler.add_progress_bar(
+    let progress_bar = log_handler.add_progress_bar(
         indicatif::ProgressBar::new(0)
             .with_prefix("Compressing ")
-            .with_style(tool_configuration.fancy_log_handler.default_bytes_style()),
+            .with_style(log_handler.determinate_bytes_style()),
     );
+    progress_bar.enable_steady_tick(std::time::Duration::from_millis(100));
 
     match packaging_settings.archive_type {
         ArchiveType::TarBz2 => {
@@ -306,7 +350,9 @@ pub fn package_conda(
                 &tmp.files.iter().cloned().collect::<Vec<_>>(),
                 CompressionLevel::Numeric(packaging_settings.compression_level),
                 Some(&output.build_configuration.timestamp),
-                Some(Box::new(ProgressBar { progress_bar })),
+                Some(Box::new(ProgressBar {
+                    progress_bar: progress_bar.clone(),
+                })),
             )?;
         }
         ArchiveType::Conda => {
@@ -318,17 +364,73 @@ pub fn package_conda(
                 packaging_settings.compression_threads,
                 &identifier,
                 Some(&output.build_configuration.timestamp),
-                Some(Box::new(ProgressBar { progress_bar })),
+                Some(Box::new(ProgressBar {
+                    progress_bar: progress_bar.clone(),
+                })),
             )?;
         }
     }
+    log_handler.finish_progress_bar(
+        &progress_bar,
+        format!(
+            "{} compressed to {}",
+            HumanBytes(progress_bar.length().unwrap_or_default()),
+            HumanBytes(fs::metadata(&out_path)?.len())
+        ),
+    );
 
     tracing::info!("Archive written to {:?}", out_path);
 
+    if let Some(format) = output.build_configuration.sbom {
+        let sbom_path = output_folder.join(format!("{}.{}", identifier, format.file_name()));
+        fs::copy(info_folder.join(format.file_name()), &sbom_path)?;
+        tracing::info!("SBOM written to {:?}", sbom_path);
+    }
+
+    if let Some(lockfile_dir) = &output.build_configuration.lockfile_dir {
+        fs::create_dir_all(lockfile_dir)?;
+        for environment in ["build", "host"] {
+            let lockfile = info_folder.join("lock").join(lockfile_name(environment));
+            if lockfile.exists() {
+                let path =
+                    lockfile_dir.join(format!("{}.{}", identifier, lockfile_name(environment)));
+                fs::copy(&lockfile, &path)?;
+                tracing::info!(
+                    "Lock file of the {} environment written to {:?}",
+                    environment,
+                    path
+                );
+            }
+        }
+    }
+
     let paths_json = PathsJson::from_path(info_folder.join("paths.json"))?;
     Ok((out_path, paths_json))
 }
 
+/// Write the lock files of the build and host environments to `info/lock/`.
+fn write_lockfiles(output: &Output, info_folder: &Path) -> Result<Vec<PathBuf>, PackagingError> {
+    let Some(dependencies) = &output.finalized_dependencies else {
+        return Ok(Vec::new());
+    };
+    let name = output.name().as_normalized();
+    let configuration = &output.build_configuration;
+
+    let mut files = Vec::new();
+    for (environment, resolved, platform) in [
+        ("build", &dependencies.build, configuration.build_platform),
+        ("host", &dependencies.host, configuration.host_platform),
+    ] {
+        let Some(resolved) = resolved else {
+            continue;
+        };
+        let path = info_folder.join("lock").join(lockfile_name(environment));
+        CondaLock::new(name, environment, platform, &resolved.resolved).write(&path)?;
+        files.push(path);
+    }
+    Ok(files)
+}
+
 /// When building package for noarch, we don't create another build-platform folder
 /// together with noarch but conda-build does
 /// because of this we have a failure in conda-smithy CI so we also *mimic* this behaviour
@@ -356,11 +458,21 @@ impl Output {
     ) -> Result<(PathBuf, PathsJson), PackagingError> {
         let span = tracing::info_span!("Packaging new files");
         let _enter = span.enter();
-        let files_after = Files::from_prefix(
+        let mut files_after = Files::from_prefix(
             &self.build_configuration.directories.host_prefix,
             self.recipe.build().always_include_files(),
         )?;
 
+        // outputs that share a cache build only package the files they select
+        let selected_files = self.recipe.build().files();
+        if !selected_files.is_empty() {
+            let prefix = files_after.prefix.clone();
+            files_after.new_files.retain(|file| {
+                file.strip_prefix(&prefix)
+                    .is_ok_and(|path| selected_files.is_match(path))
+            });
+        }
+
         package_conda(self, tool_configuration, &files_after)
     }
 }
@@ -1,7 +1,7 @@
 use content_inspector::ContentType;
 use fs_err as fs;
 use globset::GlobSet;
-use rattler_conda_types::PrefixRecord;
+use rattler_conda_types::{package::EntryPoint, PrefixRecord};
 use std::{
     collections::{HashMap, HashSet},
     io::{self, Read},
@@ -10,7 +10,7 @@ use std::{
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
-use crate::metadata::Output;
+use crate::{metadata::Output, post_process::python::python_entry_points};
 
 use super::{file_mapper, PackagingError};
 
@@ -34,6 +34,9 @@ pub struct TempFiles {
     pub temp_dir: tempfile::TempDir,
     /// The prefix which is encoded in the files (the long placeholder for the actual prefix, e.g. /home/user/bld_placeholder...)
     pub encoded_prefix: PathBuf,
+    /// The python entry points of the package (from the recipe and the `.dist-info` of
+    /// `noarch: python` and Windows packages)
+    pub entry_points: Vec<EntryPoint>,
     /// The content type of the files
     content_type_map: HashMap<PathBuf, Option<ContentType>>,
 }
@@ -115,17 +118,27 @@ impl Files {
     }
 
     /// Copy the new files to a temporary directory and return the temporary directory and the files that were copied.
-    pub fn to_temp_folder(&self, output: &Output) -> Result<TempFiles, PackagingError> {
+    /// The progress bar is advanced for every file that is copied.
+    pub fn to_temp_folder(
+        &self,
+        output: &Output,
+        progress: &indicatif::ProgressBar,
+    ) -> Result<TempFiles, PackagingError> {
         let temp_dir = TempDir::with_prefix(output.name().as_normalized())?;
         let mut files = HashSet::new();
         let mut content_type_map = HashMap::new();
+        let entry_points = python_entry_points(output, &self.new_files);
+        progress.set_length(self.new_files.len() as u64);
         for f in &self.new_files {
+            progress.inc(1);
             // temporary measure to remove pyc files that are not supposed to be there
             if file_mapper::filter_pyc(f, &self.new_files) {
                 continue;
             }
 
-            if let Some(dest_file) = output.write_to_dest(f, &self.prefix, temp_dir.path())? {
+            if let Some(dest_file) =
+                output.write_to_dest(f, &self.prefix, temp_dir.path(), &entry_points)?
+            {
                 content_type_map.insert(dest_file.clone(), content_type(f)?);
                 files.insert(dest_file);
             }
@@ -135,6 +148,7 @@ impl Files {
             files,
             temp_dir,
             encoded_prefix: self.prefix.clone(),
+            entry_points,
             content_type_map,
         })
     }
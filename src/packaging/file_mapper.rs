@@ -4,8 +4,10 @@ use crate::metadata::Output;
 use fs_err as fs;
 #[cfg(target_family = "unix")]
 use fs_err::os::unix::fs::symlink;
+use rattler_conda_types::package::EntryPoint;
 use std::{
     collections::HashSet,
+    io::{BufRead, BufReader, Read},
     path::{Component, Path, PathBuf},
 };
 
@@ -81,6 +83,26 @@ pub fn filter_file(relative_path: &Path) -> bool {
     false
 }
 
+/// Returns true if the file is a regular file that starts with a python shebang.
+fn is_python_script(path: &Path) -> bool {
+    if path.is_symlink() || !path.is_file() {
+        return false;
+    }
+    let Ok(file) = fs::File::open(path) else {
+        return false;
+    };
+    let mut first_line = Vec::new();
+    if BufReader::new(file)
+        .take(512)
+        .read_until(b'\n', &mut first_line)
+        .is_err()
+    {
+        return false;
+    }
+    let first_line = String::from_utf8_lossy(&first_line);
+    first_line.starts_with("#!") && first_line.contains("python")
+}
+
 impl Output {
     /// This function copies the given file to the destination folder and
     /// transforms it on the way if needed.
@@ -90,17 +112,19 @@ impl Output {
     ///  `.pyc` files are skipped.
     /// * For `noarch: python` packages, furthermore `bin` is replaced with `python-scripts`, and
     ///   `Scripts` is replaced with `python-scripts` (on Windows only). All other files are included
-    ///   as-is.
+    ///   as-is. The launchers of the `entry_points` are skipped (they are recreated on install).
+    /// * For Windows packages, python scripts without an extension in `Scripts` are renamed to
+    ///   `<name>-script.py` (an `.exe` launcher is added to them in post-processing).
     /// * Absolute symlinks are made relative so that they are easily relocatable.
     pub fn write_to_dest(
         &self,
         path: &Path,
         prefix: &Path,
         dest_folder: &Path,
+        entry_points: &[EntryPoint],
     ) -> Result<Option<PathBuf>, PackagingError> {
         let target_platform = &self.build_configuration.target_platform;
         let noarch_type = self.recipe.build().noarch();
-        let entry_points = &self.recipe.build().python().entry_points;
 
         let path_rel = path.strip_prefix(prefix)?;
 
@@ -203,6 +227,15 @@ impl Output {
                 // keep everything else as-is
                 dest_path = dest_folder.join(path_rel);
             }
+        } else if target_platform.is_windows()
+            && path_rel.starts_with("Scripts")
+            && path_rel.extension().is_none()
+            && is_python_script(path)
+        {
+            if let Some(name) = path_rel.file_name() {
+                dest_path = dest_folder
+                    .join(path_rel.with_file_name(format!("{}-script.py", name.to_string_lossy())));
+            }
         }
 
         match dest_path.parent() {
@@ -212,7 +212,7 @@ impl Output {
     pub fn about_json(&self) -> AboutJson {
         let recipe = &self.recipe;
 
-        let about_json = AboutJson {
+        let mut about_json = AboutJson {
             home: recipe
                 .about()
                 .homepage
@@ -245,6 +245,16 @@ impl Output {
                 .collect(),
         };
 
+        // tombstone packages carry the deprecation message
+        if let Some(deprecated) = recipe.deprecated() {
+            let message = deprecated.message(recipe.package().name());
+            about_json.summary = Some(format!("DEPRECATED: {message}"));
+            about_json.description = Some(match about_json.description {
+                Some(description) => format!("{message}\n\n{description}"),
+                None => message,
+            });
+        }
+
         about_json
     }
 
@@ -264,7 +274,7 @@ impl Output {
         // Track features are exclusively used to down-prioritize packages
         // Each feature contributes "1 point" to the down-priorization. So we add a feature for each
         // down-priorization level.
-        let track_features = self
+        let mut track_features = self
             .recipe
             .build()
             .variant()
@@ -277,6 +287,13 @@ impl Output {
                 track_features
             })
             .unwrap_or_default();
+        // the track features of the recipe are added on top
+        for feature in recipe.build().track_features() {
+            if !track_features.contains(feature) {
+                track_features.push(feature.clone());
+            }
+        }
+        let features = recipe.build().features();
 
         Ok(IndexJson {
             name: self.name().clone(),
@@ -308,15 +325,14 @@ impl Output {
                 .collect(),
             noarch: *recipe.build().noarch(),
             track_features,
-            features: None,
+            features: (!features.is_empty()).then(|| features.join(" ")),
         })
     }
 
     /// This function creates a link.json file for the given output.
-    pub fn link_json(&self) -> Result<LinkJson, PackagingError> {
-        let entry_points = &self.recipe.build().python().entry_points;
+    pub fn link_json(&self, temp_files: &TempFiles) -> Result<LinkJson, PackagingError> {
         let noarch_links = PythonEntryPoints {
-            entry_points: entry_points.clone(),
+            entry_points: temp_files.entry_points.clone(),
         };
 
         let link_json = LinkJson {
@@ -330,7 +346,12 @@ impl Output {
     /// Create a `paths.json` file structure for the given paths.
     /// Paths should be given as absolute paths under the `path_prefix` directory.
     /// This function will also determine if the file is binary or text, and if it contains the prefix.
-    pub fn paths_json(&self, temp_files: &TempFiles) -> Result<PathsJson, PackagingError> {
+    /// The progress bar is advanced for every file that is hashed.
+    pub fn paths_json(
+        &self,
+        temp_files: &TempFiles,
+        progress: &indicatif::ProgressBar,
+    ) -> Result<PathsJson, PackagingError> {
         let always_copy_files = self.recipe.build().always_copy_files();
 
         let mut paths_json = PathsJson {
@@ -343,7 +364,9 @@ impl Output {
             .iter()
             .sorted_by(|(k1, _), (k2, _)| k1.cmp(k2));
 
+        progress.set_length(temp_files.content_type_map().len() as u64);
         for (p, content_type) in sorted {
+            progress.inc(1);
             let meta = fs::symlink_metadata(p)?;
 
             let relative_path = p.strip_prefix(temp_files.temp_dir.path())?.to_path_buf();
@@ -421,10 +444,12 @@ impl Output {
         Ok(paths_json)
     }
 
-    /// Create the metadata for the given output and place it in the temporary directory
+    /// Create the metadata for the given output and place it in the temporary directory.
+    /// The progress bar tracks the files that are hashed for the `paths.json`.
     pub fn write_metadata(
         &self,
         temp_files: &TempFiles,
+        progress: &indicatif::ProgressBar,
     ) -> Result<HashSet<PathBuf>, PackagingError> {
         let mut new_files = HashSet::new();
         let root_dir = temp_files.temp_dir.path();
@@ -433,7 +458,7 @@ impl Output {
 
         let paths_json_path = root_dir.join(PathsJson::package_path());
         let paths_json = File::create(&paths_json_path)?;
-        serde_json::to_writer_pretty(paths_json, &self.paths_json(temp_files)?)?;
+        serde_json::to_writer_pretty(paths_json, &self.paths_json(temp_files, progress)?)?;
         new_files.insert(paths_json_path);
 
         let index_json_path = root_dir.join(IndexJson::package_path());
@@ -447,9 +472,21 @@ impl Output {
 
         let about_json_path = root_dir.join(AboutJson::package_path());
         let about_json = File::create(&about_json_path)?;
-        serde_json::to_writer_pretty(about_json, &self.about_json())?;
+        let mut about = serde_json::to_value(self.about_json())?;
+        if let Some(emulation) = &self.build_configuration.emulation {
+            about["emulation"] = serde_json::to_value(emulation)?;
+        }
+        serde_json::to_writer_pretty(about_json, &about)?;
         new_files.insert(about_json_path);
 
+        let file_permissions = self.recipe.build().file_permissions();
+        if !file_permissions.is_default() {
+            let file_permissions_path = info_folder.join("file_permissions.json");
+            let file_permissions_json = File::create(&file_permissions_path)?;
+            serde_json::to_writer_pretty(file_permissions_json, file_permissions)?;
+            new_files.insert(file_permissions_path);
+        }
+
         if let Some(run_exports) = self.run_exports_json()? {
             let run_exports_path = root_dir.join(RunExportsJson::package_path());
             let run_exports_json = File::create(&run_exports_path)?;
@@ -4,8 +4,13 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::post_process::{package_nature::PackageNature, relink};
+use crate::post_process::{
+    package_nature::PackageNature,
+    relink,
+    sysroot::{binary_architecture, Sysroot},
+};
 use crate::{
+    emulation::same_architecture,
     metadata::Output,
     post_process::{package_nature::PrefixInfo, relink::RelinkError},
 };
@@ -22,16 +27,48 @@ pub enum LinkingCheckError {
     #[error("Shared object error: {0}")]
     SharedObject(String),
 
-    #[error("Overlinking against: {package} (file: {file:?})")]
-    Overlinking { package: PathBuf, file: PathBuf },
-
-    #[error("Overdepending against: {package}")]
-    Overdepending { package: PathBuf },
+    #[error(
+        "Overlinking against shared libraries that are not provided by a run dependency:{}",
+        format_overlinking(.0)
+    )]
+    Overlinking(Vec<(PathBuf, PathBuf)>),
+
+    #[error(
+        "Overdepending against run dependencies that are never linked against: {}",
+        .0.join(", ")
+    )]
+    Overdepending(Vec<String>),
+
+    #[error(
+        "Binaries for the wrong architecture in a package for {target_platform}:{}\n\
+         Add intentional foreign binaries to `dynamic_linking.foreign_architecture_allowlist`",
+        format_wrong_architecture(.files)
+    )]
+    WrongArchitecture {
+        target_platform: String,
+        files: Vec<(PathBuf, String)>,
+    },
 
     #[error("failed to build glob from pattern")]
     GlobError(#[from] globset::Error),
 }
 
+/// List the libraries and the files that link against them, one per line.
+fn format_overlinking(overlinked: &[(PathBuf, PathBuf)]) -> String {
+    overlinked
+        .iter()
+        .map(|(lib, file)| format!("\n - {} (linked by {})", lib.display(), file.display()))
+        .collect()
+}
+
+/// List the files and their architectures, one per line.
+fn format_wrong_architecture(files: &[(PathBuf, String)]) -> String {
+    files
+        .iter()
+        .map(|(file, arch)| format!("\n - {} ({})", file.display(), arch))
+        .collect()
+}
+
 #[derive(Debug)]
 struct PackageFile {
     pub file: PathBuf,
@@ -207,13 +244,62 @@ fn find_system_libs(output: &Output) -> Result<GlobSet, globset::Error> {
     system_libs.build()
 }
 
+/// Check that the binaries of a cross-compiled package are built for the target platform
+/// (and not, by accident, for the build platform).
+fn check_architectures(
+    output: &Output,
+    new_files: &HashSet<PathBuf>,
+    tmp_prefix: &Path,
+) -> Result<(), LinkingCheckError> {
+    let target_platform = output.build_configuration.target_platform;
+    let Some(target_arch) = target_platform.arch() else {
+        return Ok(());
+    };
+
+    let dynamic_linking = output.recipe.build().dynamic_linking();
+    let mut files = new_files
+        .iter()
+        .filter_map(|file| {
+            let arch = binary_architecture(file)?;
+            let file = file.strip_prefix(tmp_prefix).unwrap_or(file);
+            (!same_architecture(arch, target_arch)
+                && !dynamic_linking.is_foreign_architecture_allowed(file))
+            .then(|| (file.to_path_buf(), arch.to_string()))
+        })
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    files.sort();
+    Err(LinkingCheckError::WrongArchitecture {
+        target_platform: target_platform.to_string(),
+        files,
+    })
+}
+
 pub fn perform_linking_checks(
     output: &Output,
     new_files: &HashSet<PathBuf>,
     tmp_prefix: &Path,
 ) -> Result<(), LinkingCheckError> {
     let dynamic_linking = output.recipe.build().dynamic_linking();
+    let error_on_overdepending =
+        dynamic_linking.error_on_overdepending() || output.build_configuration.error_overdepending;
     let system_libs = find_system_libs(output)?;
+    // when cross-compiling, libraries outside of the prefix are looked up in the sysroot
+    // of the target platform instead of on the build machine
+    let cross_compilation = output.build_configuration.cross_compilation();
+    let sysroot = Sysroot::from_output(output);
+    if cross_compilation {
+        if sysroot.is_empty() {
+            tracing::warn!(
+                "No sysroot for {} found in the build or host environment, libraries outside of the prefix are not resolved",
+                output.build_configuration.target_platform
+            );
+        }
+        check_architectures(output, new_files, tmp_prefix)?;
+    }
 
     let prefix_info = PrefixInfo::from_prefix(output.prefix())?;
 
@@ -231,7 +317,17 @@ pub fn perform_linking_checks(
             Ok(relinker) => {
                 let mut file_dsos = Vec::new();
 
-                let resolved_libraries = relinker.resolve_libraries(tmp_prefix, host_prefix);
+                let mut resolved_libraries = relinker.resolve_libraries(tmp_prefix, host_prefix);
+                if cross_compilation {
+                    for (lib, resolved) in resolved_libraries.iter_mut() {
+                        let on_build_machine = resolved.as_ref().is_some_and(|path| {
+                            !path.starts_with(host_prefix) && !path.starts_with(tmp_prefix)
+                        });
+                        if on_build_machine {
+                            *resolved = sysroot.resolve(lib);
+                        }
+                    }
+                }
                 for (lib, resolved) in &resolved_libraries {
                     // filter out @self on macOS
                     if target_platform.is_osx() && lib.to_str() == Some("self") {
@@ -273,6 +369,7 @@ pub fn perform_linking_checks(
     tracing::trace!("Package files: {package_files:#?}");
 
     let mut linked_packages = Vec::new();
+    let mut overlinked = Vec::new();
     for package in package_files.iter() {
         let mut link_info = PackageLinkInfo {
             file: package.file.clone(),
@@ -298,7 +395,7 @@ pub fn perform_linking_checks(
             }
 
             // Check if the library is one of the system libraries (i.e. comes from sysroot).
-            if system_libs.is_match(lib) {
+            if system_libs.is_match(lib) || sysroot.contains(lib) {
                 link_info.linked_packages.push(LinkedPackage {
                     name: lib.to_path_buf(),
                     link_origin: LinkOrigin::System,
@@ -316,31 +413,17 @@ pub fn perform_linking_checks(
             }
 
             // Check if we allow overlinking.
-            if dynamic_linking
-                .missing_dso_allowlist()
-                .map(|v| v.is_match(lib))
-                .unwrap_or(false)
-            {
+            if dynamic_linking.is_missing_dso_allowed(&package.file, lib) {
                 tracing::info!(
                     "{lib:?} is missing in run dependencies for {:?}, \
                     yet it is included in the allow list. Skipping...",
                     package.file
                 );
-            // Error on overlinking.
-            } else if dynamic_linking.error_on_overlinking() {
-                link_info.linked_packages.push(LinkedPackage {
-                    name: lib.to_path_buf(),
-                    link_origin: LinkOrigin::NotFound,
-                });
-                linked_packages.push(link_info);
-                linked_packages.iter().for_each(|linked_package| {
-                    tracing::info!("\n{linked_package}");
-                });
-
-                return Err(LinkingCheckError::Overlinking {
-                    package: lib.to_path_buf(),
-                    file: package.file.clone(),
-                });
+            // Error on overlinking (after all files are checked).
+            } else if output.build_configuration.error_overlinking
+                || dynamic_linking.error_on_overlinking_for(&package.file)
+            {
+                overlinked.push((lib.to_path_buf(), package.file.clone()));
             } else {
                 let warn_str = format!("Overlinking against {lib:?} for {:?}", package.file);
                 tracing::warn!(warn_str);
@@ -359,7 +442,12 @@ pub fn perform_linking_checks(
         tracing::info!("{linked_package}");
     });
 
+    if !overlinked.is_empty() {
+        return Err(LinkingCheckError::Overlinking(overlinked));
+    }
+
     // If there are any unused run dependencies then it is "overdepending".
+    let mut overdepending = Vec::new();
     for run_dependency in resolved_run_dependencies.iter() {
         if !package_files
             .iter()
@@ -372,15 +460,18 @@ pub fn perform_linking_checks(
             })
             .any(|libraries| libraries.contains(run_dependency))
         {
-            if dynamic_linking.error_on_overdepending() {
-                return Err(LinkingCheckError::Overdepending {
-                    package: PathBuf::from(run_dependency),
-                });
+            if error_on_overdepending {
+                overdepending.push(run_dependency.clone());
+                continue;
             }
             tracing::warn!("Overdepending against {run_dependency}");
             output.record_warning(&format!("Overdepending against {run_dependency}"));
         }
     }
 
+    if !overdepending.is_empty() {
+        return Err(LinkingCheckError::Overdepending(overdepending));
+    }
+
     Ok(())
 }
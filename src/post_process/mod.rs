@@ -1,5 +1,9 @@
 pub mod checks;
 pub mod package_nature;
+pub mod permissions;
+pub mod prefix_leaks;
 pub mod python;
 pub mod regex_replacements;
 pub mod relink;
+pub mod reproducible;
+pub mod sysroot;
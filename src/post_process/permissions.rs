@@ -0,0 +1,97 @@
+//! Normalize the permissions of the files in the package and reject files with
+//! setuid / setgid bits that are not explicitly allowed.
+
+use std::path::PathBuf;
+
+use crate::{
+    metadata::Output,
+    packaging::{PackagingError, TempFiles},
+};
+
+/// The setuid and setgid bits of a file mode.
+#[cfg(any(unix, test))]
+const SETUID_SETGID: u32 = 0o6000;
+
+/// Returns the normalized mode for a file: `0755` for executables and `0644` for all
+/// other files. The setuid / setgid bits are only kept if `keep_setuid` is true.
+#[cfg(any(unix, test))]
+fn normalized_mode(mode: u32, keep_setuid: bool) -> u32 {
+    let base = if mode & 0o111 != 0 { 0o755 } else { 0o644 };
+    if keep_setuid {
+        base | (mode & SETUID_SETGID)
+    } else {
+        base
+    }
+}
+
+/// Apply the `build.file_permissions` settings of the recipe to the files in the
+/// temporary directory.
+#[cfg(unix)]
+pub fn file_permissions(temp_files: &TempFiles, output: &Output) -> Result<(), PackagingError> {
+    use fs_err as fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let settings = output.recipe.build().file_permissions();
+    let base_path = temp_files.temp_dir.path();
+
+    let mut not_allowed: Vec<PathBuf> = Vec::new();
+    for file in &temp_files.files {
+        let metadata = fs::symlink_metadata(file)?;
+        if metadata.is_symlink() || metadata.is_dir() {
+            continue;
+        }
+
+        let mode = metadata.permissions().mode() & 0o7777;
+        let relative_path = file.strip_prefix(base_path)?;
+        let has_setuid = mode & SETUID_SETGID != 0;
+        let allowed = has_setuid && settings.allow_setuid.is_match(relative_path);
+
+        if has_setuid && !allowed {
+            not_allowed.push(relative_path.to_path_buf());
+            continue;
+        }
+
+        if settings.normalize || output.build_configuration.reproducible {
+            let new_mode = normalized_mode(mode, allowed);
+            if new_mode != mode {
+                tracing::debug!(
+                    "Normalizing permissions of {} from {:o} to {:o}",
+                    relative_path.display(),
+                    mode,
+                    new_mode
+                );
+                fs::set_permissions(file, std::fs::Permissions::from_mode(new_mode))?;
+            }
+        }
+    }
+
+    if !not_allowed.is_empty() {
+        not_allowed.sort();
+        return Err(PackagingError::SetuidNotAllowed(not_allowed));
+    }
+
+    Ok(())
+}
+
+/// File modes are not tracked on Windows, so there is nothing to do.
+#[cfg(not(unix))]
+pub fn file_permissions(_temp_files: &TempFiles, _output: &Output) -> Result<(), PackagingError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::normalized_mode;
+
+    #[test]
+    fn test_normalized_mode() {
+        assert_eq!(normalized_mode(0o777, false), 0o755);
+        assert_eq!(normalized_mode(0o775, false), 0o755);
+        assert_eq!(normalized_mode(0o700, false), 0o755);
+        assert_eq!(normalized_mode(0o666, false), 0o644);
+        assert_eq!(normalized_mode(0o600, false), 0o644);
+        assert_eq!(normalized_mode(0o4755, false), 0o755);
+        assert_eq!(normalized_mode(0o4775, true), 0o4755);
+        assert_eq!(normalized_mode(0o2755, true), 0o2755);
+    }
+}
@@ -0,0 +1,191 @@
+//! Find absolute paths of the build machine that leaked into the files of the package.
+//!
+//! The host prefix is replaced with the install prefix when a package is installed,
+//! but only in files that are registered for prefix replacement (the files with a
+//! `prefix_placeholder` in `paths.json`). Paths into the build prefix are never
+//! replaced, and a host prefix in a file that is not registered stays as it is. Both
+//! make the package non-relocatable, so they fail the build unless
+//! `build.prefix_detection.allow_leaked_prefixes` is set.
+//!
+//! Paths into the work directory are mostly the source paths in debug information and
+//! assertion messages, which do not need to exist at runtime. They are only reported
+//! as a warning.
+
+use std::path::Path;
+
+use content_inspector::ContentType;
+use rattler_conda_types::package::{PackageFile, PathsJson};
+
+use crate::{
+    metadata::Output,
+    packaging::{PackagingError, TempFiles},
+    utils::to_forward_slash_lossy,
+};
+
+/// The byte patterns of a path as it can appear in a file of the target platform.
+fn path_patterns(path: &Path, is_windows: bool) -> Vec<Vec<u8>> {
+    let mut patterns = vec![path.to_string_lossy().as_bytes().to_vec()];
+    if is_windows {
+        let forward_slash = to_forward_slash_lossy(path).as_bytes().to_vec();
+        if !patterns.contains(&forward_slash) {
+            patterns.push(forward_slash);
+        }
+    }
+    patterns
+}
+
+/// Returns the names of the prefixes that are contained in the data.
+fn find_leaks<'a>(data: &[u8], prefixes: &[(&'a str, Vec<Vec<u8>>)]) -> Vec<&'a str> {
+    prefixes
+        .iter()
+        .filter(|(_, patterns)| {
+            patterns
+                .iter()
+                .any(|pattern| memchr::memmem::find(data, pattern).is_some())
+        })
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Check the files of the package for leaked build prefixes. Must be called after the
+/// `paths.json` was written.
+pub fn check_prefix_leaks(temp_files: &TempFiles, output: &Output) -> Result<(), PackagingError> {
+    let prefix_detection = output.recipe.build().prefix_detection();
+    if prefix_detection.allow_leaked_prefixes {
+        return Ok(());
+    }
+
+    let base_path = temp_files.temp_dir.path();
+    let target_platform = output.build_configuration.target_platform;
+    let is_windows = target_platform.is_windows();
+    let directories = &output.build_configuration.directories;
+
+    let never_replaced = [(
+        "build prefix",
+        path_patterns(&directories.build_prefix, is_windows),
+    )];
+    let work_dir = [(
+        "work directory",
+        path_patterns(&directories.work_dir, is_windows),
+    )];
+    let host_prefix = [(
+        "host prefix",
+        path_patterns(&temp_files.encoded_prefix, is_windows),
+    )];
+
+    let paths_json = PathsJson::from_package_directory(base_path)?;
+    let mut leaks = Vec::new();
+    let mut work_dir_leaks = Vec::new();
+    for entry in &paths_json.paths {
+        let path = base_path.join(&entry.relative_path);
+        let metadata = fs_err::symlink_metadata(&path)?;
+        if !metadata.is_file() || metadata.len() == 0 {
+            continue;
+        }
+        let file = fs_err::File::open(&path)?;
+        // SAFETY: the file is only read while the map exists
+        let data = unsafe { memmap2::Mmap::map(&file) }?;
+
+        if !find_leaks(&data, &work_dir).is_empty() {
+            work_dir_leaks.push(entry.relative_path.display().to_string());
+        }
+
+        let mut found = find_leaks(&data, &never_replaced);
+        if entry.prefix_placeholder.is_none()
+            && !is_expected_unregistered(&entry.relative_path, temp_files, output)
+        {
+            found.extend(find_leaks(&data, &host_prefix));
+        }
+
+        if !found.is_empty() {
+            leaks.push(format!(
+                "{} ({})",
+                entry.relative_path.display(),
+                found.join(", ")
+            ));
+        }
+    }
+
+    if !work_dir_leaks.is_empty() {
+        work_dir_leaks.sort();
+        tracing::warn!(
+            "Files of the package contain paths into the work directory (e.g. in debug information):\n  {}",
+            work_dir_leaks.join("\n  ")
+        );
+    }
+
+    if !leaks.is_empty() {
+        leaks.sort();
+        return Err(PackagingError::LeakedPrefix(leaks));
+    }
+
+    Ok(())
+}
+
+/// Returns true if the file was deliberately left out of prefix replacement: Python
+/// bytecode, files that are ignored by the recipe and binary files when binary prefix
+/// replacement is disabled (or not supported, on Windows).
+fn is_expected_unregistered(relative_path: &Path, temp_files: &TempFiles, output: &Output) -> bool {
+    let prefix_detection = output.recipe.build().prefix_detection();
+
+    if relative_path
+        .extension()
+        .is_some_and(|ext| ext == "pyc" || ext == "pyo")
+    {
+        return true;
+    }
+
+    if prefix_detection.ignore.is_match(relative_path) {
+        return true;
+    }
+
+    let is_binary = prefix_detection
+        .force_file_type
+        .binary
+        .is_match(relative_path)
+        || !temp_files
+            .content_type_map()
+            .get(&temp_files.temp_dir.path().join(relative_path))
+            .copied()
+            .flatten()
+            .is_some_and(|content_type| {
+                matches!(content_type, ContentType::UTF_8 | ContentType::UTF_8_BOM)
+            });
+
+    is_binary
+        && (prefix_detection.ignore_binary_files
+            || output.build_configuration.target_platform.is_windows())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{find_leaks, path_patterns};
+
+    #[test]
+    fn test_find_leaks() {
+        let build_prefix = Path::new("/home/user/bld/build_env");
+        let work_dir = Path::new("/home/user/bld/work");
+        let prefixes = [
+            ("build prefix", path_patterns(build_prefix, false)),
+            ("work directory", path_patterns(work_dir, false)),
+        ];
+
+        let data = b"\x7fELF\0\0/home/user/bld/build_env/lib\0/usr/lib\0";
+        assert_eq!(find_leaks(data, &prefixes), vec!["build prefix"]);
+
+        let data = b"#include \"/home/user/bld/work/src/foo.h\"";
+        assert_eq!(find_leaks(data, &prefixes), vec!["work directory"]);
+
+        assert!(find_leaks(b"/home/user/bld/host_env/lib", &prefixes).is_empty());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_path_patterns_windows() {
+        let patterns = path_patterns(Path::new("C:\\bld\\work"), true);
+        assert_eq!(patterns.len(), 2);
+        assert!(patterns.contains(&b"C:/bld/work".to_vec()));
+    }
+}
@@ -4,14 +4,17 @@
 //!   - Fixing up the shebangs in scripts
 //!   - Compiling `.py` files to `.pyc` files
 //!   - Replacing the contents of `.dist-info/INSTALLER` files with "conda"
+//!   - Verifying that extension modules of `abi3` packages use the stable ABI
+//!   - Creating entry points and `.exe` launchers for python scripts on Windows
 use fs_err as fs;
 use globset::GlobSet;
 use rattler::install::{get_windows_launcher, python_entry_point_template, PythonInfo};
-use rattler_conda_types::Platform;
+use rattler_conda_types::{package::EntryPoint, Platform};
 use std::collections::HashSet;
 use std::io::{self, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
 
 use crate::metadata::Output;
 use crate::packaging::{PackagingError, TempFiles};
@@ -121,6 +124,20 @@ pub fn compile_pyc(
     if !pyc_files_to_compile.is_empty() {
         tracing::info!("Compiling {} .py files to .pyc", pyc_files_to_compile.len());
 
+        // the `.pyc` files record the modification time of the source file. The files of
+        // the package are installed with the build timestamp as modification time, so
+        // that is the time that makes the `.pyc` files valid (and reproducible).
+        let timestamp = std::time::SystemTime::from(build_config.timestamp);
+        for f in &pyc_files_to_compile {
+            let set_modified = std::fs::File::options()
+                .write(true)
+                .open(f)
+                .and_then(|file| file.set_modified(timestamp));
+            if let Err(e) = set_modified {
+                tracing::debug!("Could not set the modification time of {:?}: {}", f, e);
+            }
+        }
+
         for f in &pyc_files_to_compile {
             let command = Command::new(&python_interpreter)
                 .args(["-Wi", "-m", "py_compile"])
@@ -172,7 +189,19 @@ pub fn python(temp_files: &TempFiles, output: &Output) -> Result<HashSet<PathBuf
         )?);
 
         // create entry points if it is not a noarch package
-        result.extend(create_entry_points(output, temp_files.temp_dir.path())?);
+        result.extend(create_entry_points(
+            output,
+            &temp_files.entry_points,
+            temp_files.temp_dir.path(),
+        )?);
+
+        if output.target_platform().is_windows() {
+            result.extend(create_script_launchers(output, &temp_files.files)?);
+        }
+    }
+
+    if output.recipe.build().python().abi3 {
+        check_abi3_extensions(&temp_files.files, temp_files.temp_dir.path())?;
     }
 
     let metadata_glob = globset::Glob::new("**/*.dist-info/METADATA")?.compile_matcher();
@@ -206,6 +235,42 @@ pub fn python(temp_files: &TempFiles, output: &Output) -> Result<HashSet<PathBuf
     Ok(result)
 }
 
+/// Returns true if the file name of an extension module is tagged for a single python version
+/// (e.g. `foo.cpython-311-x86_64-linux-gnu.so` or `foo.cp311-win_amd64.pyd`).
+fn is_version_specific_extension(file_name: &str) -> bool {
+    let re = regex::Regex::new(r"\.(cpython-\d+|cp\d+|pypy\d+)[^.]*\.(so|pyd)$")
+        .expect("Should never fail to compile the regex");
+    re.is_match(file_name)
+}
+
+/// Make sure that a package marked as `abi3` does not contain extension modules that were
+/// built for a specific python version.
+fn check_abi3_extensions(paths: &HashSet<PathBuf>, base_path: &Path) -> Result<(), PackagingError> {
+    let mut offending = paths
+        .iter()
+        .filter(|p| {
+            p.file_name()
+                .map(|f| is_version_specific_extension(&f.to_string_lossy()))
+                .unwrap_or(false)
+        })
+        .map(|p| p.strip_prefix(base_path).unwrap_or(p).to_path_buf())
+        .collect::<Vec<_>>();
+
+    if offending.is_empty() {
+        return Ok(());
+    }
+
+    offending.sort();
+    for path in &offending {
+        tracing::error!(
+            "Extension module is not built against the stable ABI: {}",
+            path.display()
+        );
+    }
+
+    Err(PackagingError::Abi3Violation(offending))
+}
+
 fn python_in_prefix(prefix: &Path, use_python_app_entrypoint: bool) -> String {
     if use_python_app_entrypoint {
         format!(
@@ -294,12 +359,118 @@ fn fix_shebang(
     Ok(())
 }
 
-/// Create the python entry point script for the recipe. Overwrites any existing entry points.
+/// Parse the `console_scripts` of an `entry_points.txt` file of a `.dist-info` folder.
+fn parse_console_scripts(content: &str) -> Vec<EntryPoint> {
+    let mut in_console_scripts = false;
+    let mut result = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_console_scripts = line == "[console_scripts]";
+            continue;
+        }
+        if !in_console_scripts || line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // strip the extras, e.g. `foo = foo.cli:main [cli]`
+        let spec = line.split_once('[').map_or(line, |(spec, _)| spec).trim();
+        match EntryPoint::from_str(spec) {
+            Ok(entry_point) => result.push(entry_point),
+            Err(e) => tracing::warn!("Ignoring invalid console script `{}`: {}", line, e),
+        }
+    }
+    result
+}
+
+/// The python entry points of the package. These are the entry points of the recipe and,
+/// with `python.dist_info_entry_points` for `noarch: python` and Windows packages, the
+/// `console_scripts` that the installed python packages declare in their
+/// `.dist-info/entry_points.txt`. The launchers that pip generated for those only work in
+/// the build prefix, so they are replaced by entry points that conda clients (for noarch
+/// packages) or we (for Windows packages) create.
+pub(crate) fn python_entry_points(output: &Output, files: &HashSet<PathBuf>) -> Vec<EntryPoint> {
+    let python = output.recipe.build().python();
+    let mut entry_points = python.entry_points.clone();
+    if !python.dist_info_entry_points
+        || (!output.recipe.build().noarch().is_python() && !output.target_platform().is_windows())
+    {
+        return entry_points;
+    }
+
+    let glob = globset::Glob::new("**/*.dist-info/entry_points.txt")
+        .expect("Should never fail to compile the glob")
+        .compile_matcher();
+    let mut entry_points_files = files
+        .iter()
+        .filter(|p| glob.is_match(p))
+        .collect::<Vec<_>>();
+    entry_points_files.sort();
+
+    for path in entry_points_files {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::warn!("Could not read {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        for entry_point in parse_console_scripts(&content) {
+            if entry_points
+                .iter()
+                .any(|ep| ep.command == entry_point.command)
+            {
+                continue;
+            }
+            tracing::info!(
+                "Adding entry point `{}` from {}",
+                entry_point.command,
+                path.display()
+            );
+            entry_points.push(entry_point);
+        }
+    }
+
+    entry_points
+}
+
+/// Create `.exe` launchers for the python scripts in `Scripts/` of a Windows package (the
+/// files that were renamed to `<name>-script.py` when they were copied into the package).
+fn create_script_launchers(
+    output: &Output,
+    files: &HashSet<PathBuf>,
+) -> Result<Vec<PathBuf>, PackagingError> {
+    let mut new_files = Vec::new();
+    for path in files {
+        let in_scripts = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .map_or(false, |p| p == "Scripts");
+        let Some(command) = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .and_then(|f| f.strip_suffix("-script.py"))
+        else {
+            continue;
+        };
+        let exe_path = path.with_file_name(format!("{}.exe", command));
+        if !in_scripts || files.contains(&exe_path) || exe_path.exists() {
+            continue;
+        }
+
+        let mut exe = fs::File::create(&exe_path)?;
+        exe.write_all(get_windows_launcher(output.target_platform()))?;
+        new_files.push(exe_path);
+    }
+    Ok(new_files)
+}
+
+/// Create the python entry point scripts. Overwrites any existing entry points.
 pub(crate) fn create_entry_points(
     output: &Output,
+    entry_points: &[EntryPoint],
     tmp_dir_path: &Path,
 ) -> Result<Vec<PathBuf>, PackagingError> {
-    if output.recipe.build().python().entry_points.is_empty() {
+    if entry_points.is_empty() {
         return Ok(Vec::new());
     }
 
@@ -313,7 +484,7 @@ pub(crate) fn create_entry_points(
 
     let python_version = python_record.package_record.version.clone();
 
-    for ep in &output.recipe.build().python().entry_points {
+    for ep in entry_points {
         let script = python_entry_point_template(
             &output.prefix().to_string_lossy(),
             output.target_platform().is_windows(),
@@ -399,6 +570,45 @@ mod tests {
         assert_eq!(new_shebang, (false, "#!/some/path/to/ruby".to_string()));
     }
 
+    #[test]
+    fn test_parse_console_scripts() {
+        let content = r#"
+[console_scripts]
+foo = foo.cli:main
+foo-extra = foo.cli:extra [cli]
+
+[gui_scripts]
+foo-gui = foo.gui:main
+"#;
+        let entry_points = parse_console_scripts(content);
+        assert_eq!(
+            entry_points
+                .iter()
+                .map(|ep| ep.command.as_str())
+                .collect::<Vec<_>>(),
+            vec!["foo", "foo-extra"]
+        );
+        assert_eq!(entry_points[1].module, "foo.cli");
+        assert_eq!(entry_points[1].function, "extra");
+    }
+
+    #[test]
+    fn test_version_specific_extension() {
+        assert!(is_version_specific_extension(
+            "foo.cpython-311-x86_64-linux-gnu.so"
+        ));
+        assert!(is_version_specific_extension("foo.cpython-38-darwin.so"));
+        assert!(is_version_specific_extension("foo.cp312-win_amd64.pyd"));
+        assert!(is_version_specific_extension(
+            "foo.pypy39-pp73-x86_64-linux-gnu.so"
+        ));
+
+        assert!(!is_version_specific_extension("foo.abi3.so"));
+        assert!(!is_version_specific_extension("foo.pyd"));
+        assert!(!is_version_specific_extension("libfoo.so"));
+        assert!(!is_version_specific_extension("foo.cpython-311.pyc"));
+    }
+
     #[test]
     fn test_replace_shebang_in_file() {
         let tempdir = tempfile::tempdir().unwrap();
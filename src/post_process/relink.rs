@@ -157,7 +157,6 @@ pub fn relink(temp_files: &TempFiles, output: &Output) -> Result<(), RelinkError
     }
 
     let rpaths = dynamic_linking.rpaths();
-    let rpath_allowlist = dynamic_linking.rpath_allowlist();
 
     let tmp_prefix = temp_files.temp_dir.path();
     let encoded_prefix = &temp_files.encoded_prefix;
@@ -182,11 +181,13 @@ pub fn relink(temp_files: &TempFiles, output: &Output) -> Result<(), RelinkError
         }
         if is_valid_file(target_platform, p)? {
             let relinker = get_relinker(target_platform, p)?;
+            let rpath_allowlist =
+                dynamic_linking.rpath_allowlist_for(p.strip_prefix(tmp_prefix).unwrap_or(p));
             relinker.relink(
                 tmp_prefix,
                 encoded_prefix,
                 &rpaths,
-                rpath_allowlist,
+                rpath_allowlist.globset(),
                 &system_tools,
             )?;
             binaries.insert(p.clone());
@@ -0,0 +1,104 @@
+//! The strictest normalization for reproducible packages (`--reproducible`): the
+//! timestamps in the headers of `.pyc` files are set to the build timestamp.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use fs_err as fs;
+
+use crate::{
+    metadata::Output,
+    packaging::{PackagingError, TempFiles},
+};
+
+/// The magic number of the first Python version (3.7) with the flags field in the
+/// `.pyc` header (PEP 552).
+const PEP_552_MAGIC: u16 = 3390;
+
+/// Set the source modification time in a `.pyc` header to the timestamp. Returns the
+/// changed header, or `None` if the header has no timestamp (hash-based `.pyc` files),
+/// is not a `.pyc` header, or already has the timestamp.
+fn normalized_pyc_header(header: &[u8], timestamp: u32) -> Option<Vec<u8>> {
+    if header.len() < 12 || header[2..4] != *b"\r\n" {
+        return None;
+    }
+    let magic = u16::from_le_bytes([header[0], header[1]]);
+    let offset = if magic >= PEP_552_MAGIC {
+        if header.len() < 16 {
+            return None;
+        }
+        let flags = u32::from_le_bytes(header[4..8].try_into().expect("4 bytes"));
+        if flags != 0 {
+            return None;
+        }
+        8
+    } else {
+        4
+    };
+    let timestamp = timestamp.to_le_bytes();
+    if header[offset..offset + 4] == timestamp {
+        return None;
+    }
+    let mut header = header.to_vec();
+    header[offset..offset + 4].copy_from_slice(&timestamp);
+    Some(header)
+}
+
+/// Set the timestamps of the timestamp-based `.pyc` files in the package (e.g. the ones
+/// that `pip` compiled in the build script) to the build timestamp. The files of the
+/// package have the build timestamp as modification time when they are installed, so
+/// the `.pyc` files stay valid.
+pub fn normalize_pyc_timestamps(
+    temp_files: &TempFiles,
+    output: &Output,
+) -> Result<(), PackagingError> {
+    if !output.build_configuration.reproducible {
+        return Ok(());
+    }
+    let timestamp = output.build_configuration.timestamp.timestamp() as u32;
+
+    for file in &temp_files.files {
+        if file.extension().map_or(true, |ext| ext != "pyc") || file.is_symlink() {
+            continue;
+        }
+        let mut pyc = fs::OpenOptions::new().read(true).write(true).open(file)?;
+        let mut header = Vec::with_capacity(16);
+        pyc.by_ref().take(16).read_to_end(&mut header)?;
+        if let Some(header) = normalized_pyc_header(&header, timestamp) {
+            tracing::debug!("Normalizing the timestamp of {}", file.display());
+            pyc.seek(SeekFrom::Start(0))?;
+            pyc.write_all(&header)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalized_pyc_header() {
+        // Python 3.11 (magic 3495), timestamp-based
+        let mut header = vec![0xa7, 0x0d, b'\r', b'\n', 0, 0, 0, 0];
+        header.extend(1_700_000_000u32.to_le_bytes());
+        header.extend(1234u32.to_le_bytes());
+        let normalized = normalized_pyc_header(&header, 1_600_000_000).unwrap();
+        assert_eq!(normalized[8..12], 1_600_000_000u32.to_le_bytes());
+        assert_eq!(normalized[12..16], header[12..16]);
+        assert!(normalized_pyc_header(&normalized, 1_600_000_000).is_none());
+
+        // hash-based (checked) .pyc files have no timestamp
+        let mut checked = header.clone();
+        checked[4] = 0b11;
+        assert!(normalized_pyc_header(&checked, 1_600_000_000).is_none());
+
+        // Python 3.6 (magic 3379) has no flags field
+        let mut old = vec![0x33, 0x0d, b'\r', b'\n'];
+        old.extend(1_700_000_000u32.to_le_bytes());
+        old.extend(1234u32.to_le_bytes());
+        let normalized = normalized_pyc_header(&old, 1_600_000_000).unwrap();
+        assert_eq!(normalized[4..8], 1_600_000_000u32.to_le_bytes());
+
+        assert!(normalized_pyc_header(b"not a pyc file!!", 1_600_000_000).is_none());
+    }
+}
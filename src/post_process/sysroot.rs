@@ -0,0 +1,196 @@
+//! The sysroot of the target platform, used by the linking checks of cross-compiled
+//! packages.
+//!
+//! When the build platform differs from the target platform, the libraries that a
+//! binary links against must not be looked up on the build machine (e.g. in `/usr/lib`)
+//! but in the sysroot of the cross toolchain: the files of the `sysroot_<platform>`
+//! package on Linux and the `CONDA_BUILD_SYSROOT` on macOS.
+
+use std::path::{Component, Path, PathBuf};
+
+use rattler_conda_types::{Arch, PrefixRecord};
+
+use crate::{emulation::elf_architecture, metadata::Output};
+
+/// The directories of the sysroot of the target platform.
+#[derive(Debug, Default)]
+pub struct Sysroot {
+    /// The root directories (that contain e.g. `usr/lib`)
+    roots: Vec<PathBuf>,
+    /// The directories that contain shared libraries
+    library_dirs: Vec<PathBuf>,
+}
+
+impl Sysroot {
+    /// Find the sysroot of the target platform of the output in its build and host
+    /// prefixes.
+    pub fn from_output(output: &Output) -> Self {
+        let mut sysroot = Self::default();
+        let target_platform = output.build_configuration.target_platform;
+
+        if target_platform.is_osx() {
+            if let Some(root) = output
+                .build_configuration
+                .variant
+                .get("CONDA_BUILD_SYSROOT")
+            {
+                sysroot.roots.push(PathBuf::from(root));
+            }
+            return sysroot;
+        }
+
+        let Some(dependencies) = output.finalized_dependencies.as_ref() else {
+            return sysroot;
+        };
+        let directories = &output.build_configuration.directories;
+        let environments = [
+            (dependencies.build.as_ref(), &directories.build_prefix),
+            (dependencies.host.as_ref(), &directories.host_prefix),
+        ];
+        for (environment, prefix) in environments {
+            let Some(environment) = environment else {
+                continue;
+            };
+            for record in environment.resolved.iter().filter(|record| {
+                record
+                    .package_record
+                    .name
+                    .as_normalized()
+                    .starts_with(&format!("sysroot_{}", target_platform))
+            }) {
+                let record_path = prefix.join("conda-meta").join(format!(
+                    "{}-{}-{}.json",
+                    record.package_record.name.as_normalized(),
+                    record.package_record.version,
+                    record.package_record.build
+                ));
+                let Ok(prefix_record) = PrefixRecord::from_path(&record_path) else {
+                    tracing::warn!("Could not read {}", record_path.display());
+                    continue;
+                };
+                sysroot.add_files(prefix, &prefix_record.files);
+            }
+        }
+        sysroot
+    }
+
+    /// Add the files of a sysroot package that is installed in the prefix.
+    fn add_files(&mut self, prefix: &Path, files: &[PathBuf]) {
+        for file in files {
+            let is_library = file
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().contains(".so"));
+            if !is_library {
+                continue;
+            }
+            if let Some(dir) = file.parent().map(|dir| prefix.join(dir)) {
+                if !self.library_dirs.contains(&dir) {
+                    self.library_dirs.push(dir);
+                }
+            }
+            // e.g. `x86_64-conda-linux-gnu/sysroot/lib64/libc.so.6`
+            let root = file
+                .components()
+                .position(|component| component.as_os_str() == "sysroot")
+                .map(|idx| prefix.join(file.components().take(idx + 1).collect::<PathBuf>()));
+            if let Some(root) = root {
+                if !self.roots.contains(&root) {
+                    self.roots.push(root);
+                }
+            }
+        }
+    }
+
+    /// Returns true if no sysroot was found.
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty() && self.library_dirs.is_empty()
+    }
+
+    /// Resolve a library in the sysroot. An absolute path is looked up relative to the
+    /// roots (on macOS also as a `.tbd` stub), a file name in the library directories.
+    pub fn resolve(&self, library: &Path) -> Option<PathBuf> {
+        if library.is_absolute() {
+            let relative = library
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect::<PathBuf>();
+            self.roots.iter().find_map(|root| {
+                let candidate = root.join(&relative);
+                [candidate.clone(), candidate.with_extension("tbd")]
+                    .into_iter()
+                    .find(|candidate| candidate.exists())
+            })
+        } else {
+            self.library_dirs
+                .iter()
+                .map(|dir| dir.join(library))
+                .find(|candidate| candidate.exists())
+        }
+    }
+
+    /// Returns true if the (resolved) library is part of the sysroot.
+    pub fn contains(&self, library: &Path) -> bool {
+        self.roots.iter().any(|root| library.starts_with(root))
+            || self.library_dirs.iter().any(|dir| library.starts_with(dir))
+            || self.resolve(library).is_some()
+    }
+}
+
+/// Returns the architecture of an ELF or (thin) Mach-O binary, or `None` if the file is
+/// no such binary or the architecture is unknown.
+pub fn binary_architecture(path: &Path) -> Option<Arch> {
+    use goblin::mach::constants::cputype;
+
+    let file = std::fs::File::open(path).ok()?;
+    // SAFETY: the file is only read while the map exists
+    let data = unsafe { memmap2::Mmap::map(&file) }.ok()?;
+    match goblin::Object::parse(&data).ok()? {
+        goblin::Object::Elf(elf) => {
+            elf_architecture(elf.header.e_machine, elf.is_64, elf.little_endian)
+        }
+        goblin::Object::Mach(goblin::mach::Mach::Binary(macho)) => match macho.header.cputype {
+            cputype::CPU_TYPE_X86_64 => Some(Arch::X86_64),
+            cputype::CPU_TYPE_ARM64 => Some(Arch::Arm64),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysroot_resolve() {
+        let prefix = tempfile::tempdir().unwrap();
+        let files = [
+            PathBuf::from("aarch64-conda-linux-gnu/sysroot/lib64/libc.so.6"),
+            PathBuf::from("aarch64-conda-linux-gnu/sysroot/usr/lib64/libm.so"),
+            PathBuf::from("aarch64-conda-linux-gnu/sysroot/usr/include/stdio.h"),
+        ];
+        for file in &files {
+            let path = prefix.path().join(file);
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            fs_err::write(&path, "").unwrap();
+        }
+
+        let mut sysroot = Sysroot::default();
+        sysroot.add_files(prefix.path(), &files);
+        assert_eq!(sysroot.roots.len(), 1);
+        assert_eq!(sysroot.library_dirs.len(), 2);
+
+        let root = prefix.path().join("aarch64-conda-linux-gnu/sysroot");
+        assert_eq!(
+            sysroot.resolve(Path::new("libc.so.6")),
+            Some(root.join("lib64/libc.so.6"))
+        );
+        assert_eq!(
+            sysroot.resolve(Path::new("/usr/lib64/libm.so")),
+            Some(root.join("usr/lib64/libm.so"))
+        );
+        assert!(sysroot.resolve(Path::new("libfoo.so")).is_none());
+        assert!(sysroot.contains(&root.join("lib64/libc.so.6")));
+        assert!(!sysroot.contains(Path::new("/usr/lib/libfoo.so")));
+    }
+}
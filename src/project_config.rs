@@ -0,0 +1,303 @@
+//! The project configuration (`rattler-build.toml`) that is shared by all recipes in a
+//! directory tree.
+//!
+//! ```toml
+//! [build]
+//! channels = ["conda-forge", "https://prefix.dev/my-channel"]
+//! output_dir = "build/output"
+//! variant_config = ["variants.yaml"]
+//!
+//! [runner.linux-aarch64]
+//! image = "ghcr.io/my-org/rattler-build:latest"
+//!
+//! [profile]
+//! cpp = ["cmake", "ninja", "ccache"]
+//!
+//! [upload]
+//! default = "https://prefix.dev/my-channel"
+//!
+//! [[upload.routes]]
+//! outputs = ["*-internal"]
+//! to = "quetz+https://quetz.example.com/api/private"
+//!
+//! [[post_build]]
+//! webhook = "https://hooks.example.com/package-built"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use globset::Glob;
+use miette::IntoDiagnostic;
+use rattler_conda_types::{MatchSpec, ParseStrictness};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{hooks::PostBuildHook, runner::RunnerConfig};
+
+/// The file name of the project configuration.
+pub const PROJECT_CONFIG_FILE: &str = "rattler-build.toml";
+
+/// The project configuration.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Defaults for the options of `rattler-build build`.
+    #[serde(default)]
+    pub build: BuildDefaults,
+
+    /// The containers that the builds run in, by target platform.
+    #[serde(default)]
+    pub runner: BTreeMap<String, RunnerConfig>,
+
+    /// Named lists of build requirements ("build profiles") that recipes and the
+    /// `--profile` option can refer to.
+    #[serde(default)]
+    pub profile: BTreeMap<String, Vec<String>>,
+
+    /// Where the outputs are uploaded to with `--upload-to auto`.
+    #[serde(default)]
+    pub upload: UploadConfig,
+
+    /// Commands and webhooks that run after an output was built successfully.
+    #[serde(default)]
+    pub post_build: Vec<PostBuildHook>,
+}
+
+/// Defaults for the options of `rattler-build build`. Options that are given on the
+/// command line take precedence. Relative paths are relative to the directory of the
+/// configuration file.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildDefaults {
+    /// The channels (`--channel`)
+    pub channels: Option<Vec<String>>,
+
+    /// The output directory (`--output-dir`)
+    pub output_dir: Option<PathBuf>,
+
+    /// Variant configuration files that are loaded before the ones of `--variant-config`
+    #[serde(default)]
+    pub variant_config: Vec<PathBuf>,
+
+    /// Where the packages are uploaded to (`--upload-to`), a channel url or `auto`
+    pub upload_to: Option<String>,
+}
+
+/// The upload destinations of the outputs.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadConfig {
+    /// The channel url for the outputs that no route matches
+    pub default: Option<Url>,
+
+    /// Channel urls for the outputs with matching names. The first matching route wins.
+    #[serde(default)]
+    pub routes: Vec<UploadRoute>,
+}
+
+/// The channel url for the outputs whose names match one of the globs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadRoute {
+    /// Globs of the (normalized) output names
+    pub outputs: Vec<String>,
+    /// The channel url that the outputs are uploaded to
+    pub to: Url,
+}
+
+impl ProjectConfig {
+    /// Parse the project configuration from a file.
+    pub fn from_path(path: &Path) -> miette::Result<Self> {
+        let content = fs::read_to_string(path).into_diagnostic()?;
+        toml::from_str(&content)
+            .map_err(|e| miette::miette!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    /// Find the project configuration in `start` or the closest of its parent
+    /// directories and parse it.
+    pub fn discover(start: &Path) -> miette::Result<Option<(PathBuf, Self)>> {
+        for dir in start.ancestors() {
+            let path = dir.join(PROJECT_CONFIG_FILE);
+            if path.is_file() {
+                let config = Self::from_path(&path)?;
+                return Ok(Some((path, config)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the match specs of the build profile with the given name.
+    pub fn profile_specs(&self, name: &str) -> miette::Result<Vec<MatchSpec>> {
+        let Some(specs) = self.profile.get(name) else {
+            let available = self.profile.keys().cloned().collect::<Vec<_>>();
+            return Err(miette::miette!(
+                help = if available.is_empty() {
+                    format!("define it in the `[profile]` table of `{PROJECT_CONFIG_FILE}`")
+                } else {
+                    format!("available profiles: {}", available.join(", "))
+                },
+                "The build profile `{}` is not defined",
+                name
+            ));
+        };
+
+        specs
+            .iter()
+            .map(|spec| {
+                MatchSpec::from_str(spec, ParseStrictness::Strict).map_err(|e| {
+                    miette::miette!(
+                        "Invalid match spec `{}` in build profile `{}`: {}",
+                        spec,
+                        name,
+                        e
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Get the channel url that the output with the given name is uploaded to: the one
+    /// of the first matching route, or the default.
+    pub fn upload_destination(&self, output: &str) -> miette::Result<Option<&Url>> {
+        for route in &self.upload.routes {
+            for pattern in &route.outputs {
+                let glob = Glob::new(pattern).map_err(|e| {
+                    miette::miette!("Invalid output glob `{}` in upload route: {}", pattern, e)
+                })?;
+                if glob.compile_matcher().is_match(output) {
+                    return Ok(Some(&route.to));
+                }
+            }
+        }
+        Ok(self.upload.default.as_ref())
+    }
+
+    /// Get the runner for the target platform, unless this process already runs in a
+    /// runner.
+    pub fn runner(&self, target_platform: &str) -> Option<&RunnerConfig> {
+        if crate::runner::in_runner() {
+            return None;
+        }
+        self.runner.get(target_platform)
+    }
+
+    /// Get the post-build hooks that run for the output with the given name.
+    pub fn post_build_hooks(&self, output: &str) -> miette::Result<Vec<PostBuildHook>> {
+        let mut hooks = Vec::new();
+        for hook in &self.post_build {
+            if hook.applies_to(output)? {
+                hooks.push(hook.clone());
+            }
+        }
+        Ok(hooks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProjectConfig, PROJECT_CONFIG_FILE};
+
+    #[test]
+    fn discover_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let recipe_dir = dir.path().join("recipes").join("foo");
+        fs_err::create_dir_all(&recipe_dir).unwrap();
+        fs_err::write(
+            dir.path().join(PROJECT_CONFIG_FILE),
+            "[profile]\ncpp = [\"cmake >=3.20\", \"ninja\", \"ccache\"]\n",
+        )
+        .unwrap();
+
+        let (path, config) = ProjectConfig::discover(&recipe_dir).unwrap().unwrap();
+        assert_eq!(path, dir.path().join(PROJECT_CONFIG_FILE));
+
+        let specs = config.profile_specs("cpp").unwrap();
+        assert_eq!(
+            specs.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            vec!["cmake >=3.20", "ninja", "ccache"]
+        );
+        assert!(config.profile_specs("rust").is_err());
+    }
+
+    #[test]
+    fn build_defaults() {
+        let config: ProjectConfig = toml::from_str(
+            r#"
+[build]
+channels = ["conda-forge", "https://prefix.dev/my-channel"]
+output_dir = "build/output"
+variant_config = ["variants.yaml"]
+
+[runner.linux-aarch64]
+image = "ghcr.io/my-org/rattler-build:latest"
+engine = "podman"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.build.channels.as_deref(),
+            Some(
+                &[
+                    "conda-forge".to_string(),
+                    "https://prefix.dev/my-channel".to_string()
+                ][..]
+            )
+        );
+        assert_eq!(
+            config.build.output_dir.as_deref(),
+            Some("build/output".as_ref())
+        );
+        assert_eq!(
+            config.build.variant_config,
+            ["variants.yaml"].map(std::path::PathBuf::from)
+        );
+        assert_eq!(
+            config.runner["linux-aarch64"].image,
+            "ghcr.io/my-org/rattler-build:latest"
+        );
+        assert!(config.runner("linux-64").is_none());
+    }
+
+    #[test]
+    fn upload_routes() {
+        let config: ProjectConfig = toml::from_str(
+            r#"
+[upload]
+default = "https://prefix.dev/public"
+
+[[upload.routes]]
+outputs = ["*-internal", "secret"]
+to = "quetz+https://quetz.example.com/api/private"
+"#,
+        )
+        .unwrap();
+
+        let destination = |name| {
+            config
+                .upload_destination(name)
+                .unwrap()
+                .map(|url| url.as_str())
+        };
+        assert_eq!(
+            destination("libfoo-internal"),
+            Some("quetz+https://quetz.example.com/api/private")
+        );
+        assert_eq!(
+            destination("secret"),
+            Some("quetz+https://quetz.example.com/api/private")
+        );
+        assert_eq!(destination("libfoo"), Some("https://prefix.dev/public"));
+        assert!(config.post_build_hooks("libfoo").unwrap().is_empty());
+        assert_eq!(
+            ProjectConfig::default()
+                .upload_destination("libfoo")
+                .unwrap(),
+            None
+        );
+    }
+}
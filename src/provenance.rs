@@ -0,0 +1,281 @@
+//! Provenance attestations for built packages.
+//!
+//! With `--provenance`, an [in-toto statement](https://github.com/in-toto/attestation)
+//! with a [SLSA v1 provenance](https://slsa.dev/spec/v1.0/provenance) predicate is written
+//! next to every package (`<name>-<version>-<build>.intoto.json`). It records the hash of
+//! the recipe, the rendered variant, the command line, the resolved sources and
+//! dependencies and the machine (the host, a container and its image, or an emulator)
+//! that the package was built on.
+//!
+//! The statement is not signed, it can be signed with e.g. `cosign attest-blob`. When the
+//! packages are uploaded with `--upload-to`, the statements are uploaded next to them by
+//! the backends that support it.
+
+use std::path::{Path, PathBuf};
+
+use rattler_conda_types::RepoDataRecord;
+use rattler_digest::{compute_bytes_digest, compute_file_digest};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{metadata::Output, recipe::parser::Source};
+
+/// The build type of the provenance predicate.
+const BUILD_TYPE: &str = "https://github.com/prefix-dev/rattler-build/provenance/v1";
+
+/// The environment variable that can be set to the image (with digest) of the container
+/// that the build runs in, e.g. by the CI system.
+pub const RUNNER_IMAGE_ENV: &str = "RATTLER_BUILD_RUNNER_IMAGE";
+
+/// What kind of machine the build runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunnerKind {
+    /// Directly on the host
+    Host,
+    /// In a Docker container
+    Docker,
+    /// In a Podman container
+    Podman,
+    /// In another kind of container (e.g. Kubernetes or systemd-nspawn)
+    Container,
+}
+
+/// The machine that the build runs on.
+#[derive(Debug, Clone, Serialize)]
+pub struct Runner {
+    /// The kind of machine
+    pub kind: RunnerKind,
+    /// The image of the container, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Read a `key="value"` entry of the `/run/.containerenv` file of Podman.
+fn containerenv_value(content: &str, key: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let (k, value) = line.split_once('=')?;
+        (k.trim() == key).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+impl Runner {
+    /// Detect the machine that this process runs on.
+    pub fn detect() -> Self {
+        let image_from_env = std::env::var(RUNNER_IMAGE_ENV)
+            .ok()
+            .filter(|image| !image.is_empty());
+
+        let (kind, image) = if Path::new("/.dockerenv").exists() {
+            (RunnerKind::Docker, None)
+        } else if let Ok(containerenv) = fs_err::read_to_string("/run/.containerenv") {
+            // The `imageid` of the file is the id of the local image, not the digest of
+            // the manifest in the registry, so the image is recorded as it was pulled
+            let image = containerenv_value(&containerenv, "image");
+            (RunnerKind::Podman, image.filter(|image| !image.is_empty()))
+        } else if std::env::var_os("container").is_some()
+            || fs_err::read_to_string("/proc/1/cgroup").is_ok_and(|cgroup| {
+                ["docker", "containerd", "kubepods", "libpod"]
+                    .iter()
+                    .any(|name| cgroup.contains(name))
+            })
+        {
+            (RunnerKind::Container, None)
+        } else {
+            (RunnerKind::Host, None)
+        };
+
+        Self {
+            kind,
+            image: image_from_env.or(image),
+        }
+    }
+}
+
+fn record_descriptor(record: &RepoDataRecord, environment: &str) -> Value {
+    let mut digest = serde_json::Map::new();
+    if let Some(sha256) = &record.package_record.sha256 {
+        digest.insert("sha256".to_string(), json!(format!("{:x}", sha256)));
+    }
+    if let Some(md5) = &record.package_record.md5 {
+        digest.insert("md5".to_string(), json!(format!("{:x}", md5)));
+    }
+    json!({
+        "uri": record.url.to_string(),
+        "name": record.file_name,
+        "digest": digest,
+        "annotations": { "environment": environment, "channel": record.channel },
+    })
+}
+
+fn source_descriptor(source: &Source) -> Value {
+    match source {
+        Source::Url(url) => {
+            let mut digest = serde_json::Map::new();
+            if let Some(sha256) = url.sha256() {
+                digest.insert("sha256".to_string(), json!(format!("{:x}", sha256)));
+            }
+            if let Some(md5) = url.md5() {
+                digest.insert("md5".to_string(), json!(format!("{:x}", md5)));
+            }
+            json!({
+                "uri": url.urls().first().map(|url| url.to_string()),
+                "digest": digest,
+            })
+        }
+        Source::Git(git) => json!({
+            "uri": format!("git+{}", git.url()),
+            "digest": { "gitCommit": git.rev().to_string() },
+        }),
+        Source::Hg(hg) => json!({
+            "uri": format!("hg+{}", hg.url()),
+            "annotations": { "revision": hg.rev() },
+        }),
+        Source::Svn(svn) => json!({
+            "uri": format!("svn+{}", svn.url()),
+            "annotations": { "revision": svn.rev() },
+        }),
+        Source::Oci(oci) => {
+            let reference = oci.reference();
+            let mut descriptor = json!({ "uri": format!("oci://{}", reference) });
+            if let Some(digest) = &reference.digest {
+                descriptor["digest"] = json!({ "sha256": format!("{:x}", digest) });
+            }
+            descriptor
+        }
+        Source::Path(path) => json!({
+            "uri": format!("file://{}", path.path().display()),
+        }),
+    }
+}
+
+/// The GitHub Actions run that the build runs in, if any.
+fn invocation_id() -> Option<String> {
+    let server = std::env::var("GITHUB_SERVER_URL").ok()?;
+    let repository = std::env::var("GITHUB_REPOSITORY").ok()?;
+    let run_id = std::env::var("GITHUB_RUN_ID").ok()?;
+    let attempt = std::env::var("GITHUB_RUN_ATTEMPT").unwrap_or_else(|_| "1".to_string());
+    Some(format!(
+        "{}/{}/actions/runs/{}/attempts/{}",
+        server, repository, run_id, attempt
+    ))
+}
+
+/// Create the in-toto statement with the SLSA provenance of a package.
+pub fn statement(output: &Output, package_file: &Path) -> std::io::Result<Value> {
+    let configuration = &output.build_configuration;
+    let package_digest = compute_file_digest::<sha2::Sha256>(package_file)?;
+    let recipe_digest =
+        compute_file_digest::<sha2::Sha256>(&configuration.directories.recipe_path)?;
+    let rendered_recipe = serde_yaml::to_string(&output.recipe)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    let rendered_digest = compute_bytes_digest::<sha2::Sha256>(rendered_recipe.as_bytes());
+
+    let mut resolved_dependencies = output
+        .finalized_sources
+        .as_deref()
+        .unwrap_or_else(|| output.recipe.sources())
+        .iter()
+        .map(source_descriptor)
+        .collect::<Vec<_>>();
+    if let Some(dependencies) = &output.finalized_dependencies {
+        for (environment, name) in [
+            (dependencies.build.as_ref(), "build"),
+            (dependencies.host.as_ref(), "host"),
+        ] {
+            if let Some(environment) = environment {
+                resolved_dependencies.extend(
+                    environment
+                        .resolved
+                        .iter()
+                        .map(|record| record_descriptor(record, name)),
+                );
+            }
+        }
+    }
+
+    let summary = output.build_summary.lock().unwrap();
+    let mut metadata = json!({
+        "startedOn": summary.build_start.map(|start| start.to_rfc3339()),
+        "finishedOn": chrono::Utc::now().to_rfc3339(),
+    });
+    drop(summary);
+    if let Some(invocation_id) = invocation_id() {
+        metadata["invocationId"] = json!(invocation_id);
+    }
+
+    Ok(json!({
+        "_type": "https://in-toto.io/Statement/v1",
+        "subject": [{
+            "name": package_file.file_name().map(|name| name.to_string_lossy()),
+            "digest": { "sha256": format!("{:x}", package_digest) },
+        }],
+        "predicateType": "https://slsa.dev/provenance/v1",
+        "predicate": {
+            "buildDefinition": {
+                "buildType": BUILD_TYPE,
+                "externalParameters": {
+                    "recipe": {
+                        "path": configuration.directories.recipe_path,
+                        "digest": { "sha256": format!("{:x}", recipe_digest) },
+                    },
+                    "variant": configuration.variant,
+                    "targetPlatform": configuration.target_platform.to_string(),
+                    "channels": configuration.channels,
+                    "commandLine": std::env::args().collect::<Vec<_>>(),
+                },
+                "internalParameters": {
+                    "buildPlatform": configuration.build_platform.to_string(),
+                    "hostPlatform": configuration.host_platform.to_string(),
+                    "renderedRecipe": {
+                        "digest": { "sha256": format!("{:x}", rendered_digest) },
+                    },
+                },
+                "resolvedDependencies": resolved_dependencies,
+            },
+            "runDetails": {
+                "builder": {
+                    "id": format!(
+                        "https://github.com/prefix-dev/rattler-build@{}",
+                        env!("CARGO_PKG_VERSION")
+                    ),
+                    "version": { "rattler-build": env!("CARGO_PKG_VERSION") },
+                    "runner": Runner::detect(),
+                    "emulation": configuration.emulation,
+                },
+                "metadata": metadata,
+            },
+        },
+    }))
+}
+
+/// Write the provenance statement of a package next to the package file and return the
+/// path of the statement.
+pub fn write_statement(output: &Output, package_file: &Path) -> std::io::Result<PathBuf> {
+    let identifier = output.identifier().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::Other, "the build string is not set")
+    })?;
+    let path = package_file.with_file_name(format!("{}.intoto.json", identifier));
+    let statement = statement(output, package_file)?;
+    fs_err::write(&path, serde_json::to_vec_pretty(&statement)?)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::containerenv_value;
+
+    #[test]
+    fn test_containerenv_value() {
+        let content = "engine=\"podman-4.9.3\"\nname=\"build\"\nimage=\"ghcr.io/org/image:latest\"\nimageid=\"4f0a\"\n";
+        assert_eq!(
+            containerenv_value(content, "image").as_deref(),
+            Some("ghcr.io/org/image:latest")
+        );
+        assert_eq!(
+            containerenv_value(content, "imageid").as_deref(),
+            Some("4f0a")
+        );
+        assert_eq!(containerenv_value(content, "id"), None);
+    }
+}
@@ -1,8 +1,15 @@
-//! The rebuild module contains rebuild helper functions.
+//! The rebuild module contains rebuild helper functions, and the comparison of a rebuilt
+//! package with the original (`rattler-build rebuild --verify`).
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use rattler_conda_types::package::ArchiveType;
+use sha2::{Digest, Sha256};
 
 /// Extracts a folder from a tar.bz2 archive.
 fn folder_from_tar_bz2(
@@ -62,18 +69,383 @@ fn folder_from_conda(
     Ok(())
 }
 
-/// Extracts a recipe from a package archive to a destination folder.
-pub fn extract_recipe(package: &Path, dest_folder: &Path) -> Result<(), std::io::Error> {
+/// Extracts a folder of `info/` from a package archive to a destination folder.
+fn extract_info_folder(
+    package: &Path,
+    folder: &str,
+    dest_folder: &Path,
+) -> Result<(), std::io::Error> {
     let archive_type = ArchiveType::try_from(package).ok_or_else(|| {
         std::io::Error::new(
             std::io::ErrorKind::NotFound,
             "package does not point to valid archive",
         )
     })?;
-    let path = PathBuf::from("info/recipe");
+    let path = PathBuf::from("info").join(folder);
     match archive_type {
         ArchiveType::TarBz2 => folder_from_tar_bz2(package, &path, dest_folder)?,
         ArchiveType::Conda => folder_from_conda(package, &path, dest_folder)?,
     };
     Ok(())
 }
+
+/// Extracts a recipe from a package archive to a destination folder.
+pub fn extract_recipe(package: &Path, dest_folder: &Path) -> Result<(), std::io::Error> {
+    extract_info_folder(package, "recipe", dest_folder)
+}
+
+/// A file in a package archive, as far as it matters for a bit-for-bit comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// The path of the file in the package
+    pub path: String,
+    /// The modification time in the tar header
+    pub mtime: u64,
+    /// The permissions in the tar header
+    pub mode: u32,
+    /// The sha256 of the contents (and of the target of a link)
+    pub sha256: String,
+    /// The first of the build paths that the contents contain
+    pub build_path: Option<String>,
+}
+
+/// Whether `haystack` contains `needle`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    !needle.is_empty()
+        && haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+}
+
+/// Read the entries of a tar archive, in the order of the archive.
+fn entries_of_archive<R: Read>(
+    mut archive: tar::Archive<R>,
+    build_paths: &[String],
+    entries: &mut Vec<ArchiveEntry>,
+) -> std::io::Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let mtime = entry.header().mtime()?;
+        let mode = entry.header().mode()?;
+        let link = entry
+            .link_name()?
+            .map(|link| link.to_string_lossy().to_string());
+
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        let mut hasher = Sha256::new();
+        hasher.update(&contents);
+        if let Some(link) = &link {
+            hasher.update(link.as_bytes());
+        }
+        let build_path = build_paths
+            .iter()
+            .find(|build_path| {
+                contains(&contents, build_path.as_bytes())
+                    || link
+                        .as_ref()
+                        .is_some_and(|link| link.contains(build_path.as_str()))
+            })
+            .cloned();
+
+        entries.push(ArchiveEntry {
+            path,
+            mtime,
+            mode,
+            sha256: format!("{:x}", hasher.finalize()),
+            build_path,
+        });
+    }
+    Ok(())
+}
+
+/// Read the entries of a package archive (for `.conda` files, the entries of `info/`
+/// followed by the entries of the package contents). The contents are checked for the
+/// given build paths.
+pub fn archive_entries(
+    package: &Path,
+    build_paths: &[String],
+) -> std::io::Result<Vec<ArchiveEntry>> {
+    let to_io_error = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+    let mut entries = Vec::new();
+    match ArchiveType::try_from(package) {
+        Some(ArchiveType::TarBz2) => entries_of_archive(
+            rattler_package_streaming::read::stream_tar_bz2(fs_err::File::open(package)?),
+            build_paths,
+            &mut entries,
+        )?,
+        Some(ArchiveType::Conda) => {
+            entries_of_archive(
+                rattler_package_streaming::seek::stream_conda_info(fs_err::File::open(package)?)
+                    .map_err(to_io_error)?,
+                build_paths,
+                &mut entries,
+            )?;
+            entries_of_archive(
+                rattler_package_streaming::seek::stream_conda_content(fs_err::File::open(package)?)
+                    .map_err(to_io_error)?,
+                build_paths,
+                &mut entries,
+            )?;
+        }
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not a package archive", package.display()),
+            ))
+        }
+    }
+    Ok(entries)
+}
+
+/// A difference between the original and the rebuilt package, with its likely source of
+/// non-determinism.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// A file is only in one of the packages
+    OnlyIn {
+        /// The path of the file
+        path: String,
+        /// Whether the file is only in the original package (otherwise only in the rebuilt
+        /// package)
+        original: bool,
+    },
+    /// The contents of a file differ
+    Contents {
+        /// The path of the file
+        path: String,
+        /// The build path that one of the versions contains, if any
+        build_path: Option<String>,
+    },
+    /// The modification times of a file differ
+    Timestamp {
+        /// The path of the file
+        path: String,
+        /// The modification time in the original package
+        original: u64,
+        /// The modification time in the rebuilt package
+        rebuilt: u64,
+    },
+    /// The permissions of a file differ
+    Permissions {
+        /// The path of the file
+        path: String,
+        /// The permissions in the original package
+        original: u32,
+        /// The permissions in the rebuilt package
+        rebuilt: u32,
+    },
+    /// The files are stored in a different order in the archives
+    Order,
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::OnlyIn { path, original } => {
+                let package = if *original { "original" } else { "rebuilt" };
+                write!(f, "{path}: only in the {package} package")
+            }
+            Difference::Contents {
+                path,
+                build_path: Some(build_path),
+            } => write!(
+                f,
+                "{path}: contents differ (contains the build path {build_path})"
+            ),
+            Difference::Contents {
+                path,
+                build_path: None,
+            } => write!(f, "{path}: contents differ"),
+            Difference::Timestamp {
+                path,
+                original,
+                rebuilt,
+            } => write!(
+                f,
+                "{path}: modification time differs (timestamp {original} vs. {rebuilt})"
+            ),
+            Difference::Permissions {
+                path,
+                original,
+                rebuilt,
+            } => write!(
+                f,
+                "{path}: permissions differ ({original:o} vs. {rebuilt:o})"
+            ),
+            Difference::Order => write!(
+                f,
+                "the files are stored in a different order (archive ordering)"
+            ),
+        }
+    }
+}
+
+/// Compare the entries of the original and the rebuilt package.
+pub fn compare_entries(original: &[ArchiveEntry], rebuilt: &[ArchiveEntry]) -> Vec<Difference> {
+    let rebuilt_by_path = rebuilt
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect::<HashMap<_, _>>();
+    let original_by_path = original
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect::<HashMap<_, _>>();
+
+    let mut differences = Vec::new();
+    for entry in original {
+        let Some(other) = rebuilt_by_path.get(entry.path.as_str()) else {
+            differences.push(Difference::OnlyIn {
+                path: entry.path.clone(),
+                original: true,
+            });
+            continue;
+        };
+        if entry.sha256 != other.sha256 {
+            differences.push(Difference::Contents {
+                path: entry.path.clone(),
+                build_path: entry
+                    .build_path
+                    .clone()
+                    .or_else(|| other.build_path.clone()),
+            });
+        }
+        if entry.mtime != other.mtime {
+            differences.push(Difference::Timestamp {
+                path: entry.path.clone(),
+                original: entry.mtime,
+                rebuilt: other.mtime,
+            });
+        }
+        if entry.mode != other.mode {
+            differences.push(Difference::Permissions {
+                path: entry.path.clone(),
+                original: entry.mode,
+                rebuilt: other.mode,
+            });
+        }
+    }
+    for entry in rebuilt {
+        if !original_by_path.contains_key(entry.path.as_str()) {
+            differences.push(Difference::OnlyIn {
+                path: entry.path.clone(),
+                original: false,
+            });
+        }
+    }
+
+    // the order of the files that are in both packages
+    let original_order = original
+        .iter()
+        .filter(|entry| rebuilt_by_path.contains_key(entry.path.as_str()))
+        .map(|entry| entry.path.as_str());
+    let rebuilt_order = rebuilt
+        .iter()
+        .filter(|entry| original_by_path.contains_key(entry.path.as_str()))
+        .map(|entry| entry.path.as_str());
+    if !original_order.eq(rebuilt_order) {
+        differences.push(Difference::Order);
+    }
+
+    differences
+}
+
+/// The result of comparing a rebuilt package with the original.
+#[derive(Debug)]
+pub enum Verification {
+    /// The package files are bit-for-bit identical
+    Identical,
+    /// The files in the packages are identical, but the archives are not (e.g. because
+    /// of the compression)
+    SameFiles,
+    /// The packages differ
+    Different(Vec<Difference>),
+}
+
+/// Compare the rebuilt package with the original package. The build paths (e.g. the build
+/// and recipe directories of both builds) are reported when the differing files contain
+/// them.
+pub fn verify(
+    original: &Path,
+    rebuilt: &Path,
+    build_paths: &[String],
+) -> std::io::Result<Verification> {
+    let hash = |path: &Path| -> std::io::Result<_> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut fs_err::File::open(path)?, &mut hasher)?;
+        Ok(hasher.finalize())
+    };
+    if hash(original)? == hash(rebuilt)? {
+        return Ok(Verification::Identical);
+    }
+
+    let differences = compare_entries(
+        &archive_entries(original, build_paths)?,
+        &archive_entries(rebuilt, build_paths)?,
+    );
+    if differences.is_empty() {
+        Ok(Verification::SameFiles)
+    } else {
+        Ok(Verification::Different(differences))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, sha256: &str, mtime: u64) -> ArchiveEntry {
+        ArchiveEntry {
+            path: path.to_string(),
+            mtime,
+            mode: 0o644,
+            sha256: sha256.to_string(),
+            build_path: None,
+        }
+    }
+
+    #[test]
+    fn test_compare_entries() {
+        let original = [
+            entry("info/index.json", "a", 100),
+            entry("bin/foo", "b", 100),
+            entry("lib/libfoo.so", "c", 100),
+            entry("share/old.txt", "d", 100),
+        ];
+        let mut libfoo = entry("lib/libfoo.so", "e", 100);
+        libfoo.build_path = Some("/tmp/bld/work".to_string());
+        let rebuilt = [
+            entry("info/index.json", "a", 100),
+            libfoo,
+            entry("bin/foo", "b", 200),
+            entry("share/new.txt", "d", 100),
+        ];
+
+        let differences = compare_entries(&original, &rebuilt);
+        assert_eq!(
+            differences,
+            [
+                Difference::Timestamp {
+                    path: "bin/foo".to_string(),
+                    original: 100,
+                    rebuilt: 200
+                },
+                Difference::Contents {
+                    path: "lib/libfoo.so".to_string(),
+                    build_path: Some("/tmp/bld/work".to_string())
+                },
+                Difference::OnlyIn {
+                    path: "share/old.txt".to_string(),
+                    original: true
+                },
+                Difference::OnlyIn {
+                    path: "share/new.txt".to_string(),
+                    original: false
+                },
+                Difference::Order,
+            ]
+        );
+        assert!(compare_entries(&original, &original).is_empty());
+    }
+}
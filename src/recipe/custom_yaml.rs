@@ -971,7 +971,12 @@ impl TryConvertNode<bool> for RenderedScalarNode {
 
 impl TryConvertNode<u64> for RenderedNode {
     fn try_convert(&self, name: &str) -> Result<u64, Vec<PartialParsingError>> {
-        self.as_scalar()
+        // a value that is selected with if/then renders to a sequence with a single item
+        let scalar = match self.as_sequence().map(|sequence| sequence.as_slice()) {
+            Some([item]) => item.as_scalar(),
+            _ => self.as_scalar(),
+        };
+        scalar
             .ok_or_else(|| {
                 _partialerror!(
                     *self.span(),
@@ -2,15 +2,20 @@
 
 use std::process::Command;
 use std::sync::Arc;
-use std::{collections::BTreeMap, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+};
 
-use minijinja::value::{Kwargs, Object};
+use minijinja::value::{Kwargs, Object, ValueKind};
 use minijinja::{Environment, Value};
 use rattler_conda_types::{PackageName, ParseStrictness, Platform, Version, VersionSpec};
 
+use crate::gpu;
 use crate::render::pin::PinArgs;
 pub use crate::render::pin::{Pin, PinExpression};
 pub use crate::selectors::SelectorConfig;
+use crate::used_variables::used_vars_from_expression;
 
 use super::parser::{Dependency, PinCompatible, PinSubpackage};
 
@@ -19,14 +24,23 @@ use super::parser::{Dependency, PinCompatible, PinSubpackage};
 pub struct Jinja<'a> {
     env: Environment<'a>,
     context: BTreeMap<String, Value>,
+    allow_undefined: bool,
+    lenient_skip: bool,
 }
 
 impl<'a> Jinja<'a> {
     /// Create a new Jinja instance with the given selector configuration.
     pub fn new(config: SelectorConfig) -> Self {
         let env = set_jinja(&config);
+        let allow_undefined = config.allow_undefined;
+        let lenient_skip = config.lenient_skip;
         let context = config.into_context();
-        Self { env, context }
+        Self {
+            env,
+            context,
+            allow_undefined,
+            lenient_skip,
+        }
     }
 
     /// Get a reference to the miniJinja environment.
@@ -67,6 +81,87 @@ impl<'a> Jinja<'a> {
         let expr = self.env.compile_expression(&expr)?;
         expr.eval(self.context())
     }
+
+    /// Returns the variables that are used in an expression but are not defined in the
+    /// context. Platform and architecture selectors (e.g. `win` or `aarch64`) count as
+    /// defined because they are only added to the context on the matching platform.
+    ///
+    /// Always empty if undefined variables are allowed (while searching for the variants).
+    pub fn undefined_variables(&self, str: &str) -> Result<Vec<String>, minijinja::Error> {
+        if self.allow_undefined {
+            return Ok(Vec::new());
+        }
+
+        let selectors = platform_selectors();
+
+        let expr = self.render_str(str)?;
+        let mut undefined = used_vars_from_expression(&expr)?
+            .into_iter()
+            .filter(|var| !self.context.contains_key(var) && !selectors.contains(var))
+            .collect::<Vec<_>>();
+        undefined.sort();
+        Ok(undefined)
+    }
+
+    /// Whether skip conditions that use undefined variables evaluate to false instead
+    /// of raising an error.
+    pub fn lenient_skip(&self) -> bool {
+        self.lenient_skip
+    }
+
+    /// Returns the defined variable whose name is closest to the given (undefined)
+    /// variable, if any is close enough to be a likely typo.
+    pub fn suggest_variable(&self, name: &str) -> Option<&str> {
+        let max_distance = (name.len() / 3).max(1);
+        self.context
+            .iter()
+            .filter(|(_, value)| value.kind() != ValueKind::Map)
+            .map(|(key, _)| {
+                let distance = if key.eq_ignore_ascii_case(name) {
+                    0
+                } else {
+                    strsim::levenshtein(&key.to_lowercase(), &name.to_lowercase())
+                };
+                (distance, key)
+            })
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, key)| key.as_str())
+    }
+
+    /// Returns the values of the variables that are used in an expression and are
+    /// defined in the context (e.g. the variant values that a skip condition depends on).
+    /// Platform and architecture selectors (e.g. `osx` or `unix`) are not included.
+    pub fn used_values(&self, str: &str) -> Result<BTreeMap<String, String>, minijinja::Error> {
+        let selectors = platform_selectors();
+
+        let expr = self.render_str(str)?;
+        Ok(used_vars_from_expression(&expr)?
+            .into_iter()
+            .filter(|var| var != "unix" && !selectors.contains(var))
+            .filter_map(|var| {
+                let value = self.context.get(&var)?;
+                matches!(
+                    value.kind(),
+                    ValueKind::String | ValueKind::Bool | ValueKind::Number
+                )
+                .then(|| (var, value.to_string()))
+            })
+            .collect())
+    }
+}
+
+/// The platform and architecture selectors (e.g. `win` or `aarch64`) of all platforms.
+pub(crate) fn platform_selectors() -> HashSet<String> {
+    Platform::all()
+        .flat_map(|platform| {
+            platform
+                .only_platform()
+                .map(str::to_string)
+                .into_iter()
+                .chain(platform.arch().map(|arch| arch.to_string()))
+        })
+        .collect()
 }
 
 impl Default for Jinja<'_> {
@@ -74,6 +169,8 @@ impl Default for Jinja<'_> {
         Self {
             env: set_jinja(&SelectorConfig::default()),
             context: BTreeMap::new(),
+            allow_undefined: false,
+            lenient_skip: false,
         }
     }
 }
@@ -197,6 +294,8 @@ fn compiler_stdlib_eval(
 ) -> Result<String, minijinja::Error> {
     let variant_key = format!("{lang}_{prefix}");
     let variant_key_version = format!("{lang}_{prefix}_version");
+    let variant_key_template = format!("{lang}_{prefix}_template");
+    let global_key_template = format!("{prefix}_template");
 
     let default_fn = if prefix == "compiler" {
         default_compiler
@@ -204,16 +303,47 @@ fn compiler_stdlib_eval(
         |_: Platform, _: &str| None
     };
 
+    // GPU compilers default to the version of the GPU stack of the variant
+    let version = variant.get(&variant_key_version).cloned().or_else(|| {
+        (prefix == "compiler")
+            .then(|| gpu::compiler_version(lang, variant))
+            .flatten()
+    });
+    if version.as_deref() == Some("None") {
+        return Err(minijinja::Error::new(
+            minijinja::ErrorKind::InvalidOperation,
+            format!(
+                "The {lang} {prefix} is used in a variant that sets `{variant_key_version}` to `None`.\nGuard it with a selector, e.g. `if: {variant_key_version} != \"None\"`.",
+            ),
+        ));
+    }
+
     let res = if let Some(name) = variant
         .get(&variant_key)
         .cloned()
         .or_else(|| default_fn(platform, lang))
-    {
+        .or_else(|| {
+            (prefix == "compiler")
+                .then(|| gpu::default_compiler(lang, version.as_deref()))
+                .flatten()
+        }) {
+        // the template controls how the package name is derived from the
+        // configured name, e.g. `{name}_{target_platform}` (the default)
+        let name = variant
+            .get(&variant_key_template)
+            .or_else(|| variant.get(&global_key_template))
+            .map(|template| {
+                template
+                    .replace("{name}", &name)
+                    .replace("{target_platform}", &platform.to_string())
+            })
+            .unwrap_or_else(|| format!("{name}_{platform}"));
+
         // check if we also have a compiler version
-        if let Some(version) = variant.get(&variant_key_version) {
-            Some(format!("{name}_{platform} {version}"))
+        if let Some(version) = version {
+            Some(format!("{name} {version}"))
         } else {
-            Some(format!("{name}_{platform}"))
+            Some(name)
         }
     } else {
         None
@@ -238,6 +368,7 @@ fn set_jinja(config: &SelectorConfig) -> minijinja::Environment<'static> {
         variant,
         experimental,
         allow_undefined,
+        subpackages,
         ..
     } = config.clone();
 
@@ -256,7 +387,12 @@ fn set_jinja(config: &SelectorConfig) -> minijinja::Environment<'static> {
     })
     .expect("is tested to be correct");
 
-    env.add_function("cmp", |a: &Value, spec: &str| {
+    let cmp = |a: &Value, spec: &str| -> Result<bool, minijinja::Error> {
+        if a.is_none() {
+            // a variable that is explicitly `none` (e.g. an undefined variable in a skip
+            // condition) matches nothing
+            return Ok(false);
+        }
         if let Some(variant) = a.as_str() {
             // check if version matches spec
             let (version, _) = variant.split_once(' ').unwrap_or((variant, ""));
@@ -281,7 +417,10 @@ fn set_jinja(config: &SelectorConfig) -> minijinja::Environment<'static> {
             // if a is undefined, we are currently searching for all variants and thus return true
             Ok(true)
         }
-    });
+    };
+    env.add_function("cmp", cmp);
+    // `match` is the name that conda-build uses for the same function
+    env.add_function("match", cmp);
 
     let variant_clone = variant.clone();
     env.add_function("cdt", move |package_name: String| {
@@ -346,6 +485,31 @@ fn set_jinja(config: &SelectorConfig) -> minijinja::Environment<'static> {
         jinja_pin_function(name, kwargs, "__PIN_COMPATIBLE")
     });
 
+    let subpackages = Arc::new(subpackages);
+    env.add_function("outputs", move |name: String| {
+        let package_name = PackageName::from_str(&name).map_err(|e| {
+            minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                format!("`{name}` is not a valid package name: {e}"),
+            )
+        })?;
+        match subpackages.get(&package_name) {
+            Some(identifier) => Ok(Value::from_serializable(identifier)),
+            // while searching for the variants, the other outputs might not be rendered yet
+            None if allow_undefined => Ok(Value::from_serializable(&BTreeMap::from([
+                ("name", name.as_str()),
+                ("version", "undefined"),
+                ("build_string", "undefined"),
+            ]))),
+            None => Err(minijinja::Error::new(
+                minijinja::ErrorKind::UndefinedError,
+                format!(
+                    "output `{name}` is not rendered before this output (only outputs that are built earlier can be referenced)"
+                ),
+            )),
+        }
+    });
+
     env.add_filter("version_to_buildstring", |s: String| {
         // we first split the string by whitespace and take the first part
         let s = s.split_whitespace().next().unwrap_or(&s);
@@ -584,8 +748,23 @@ impl Object for Git {
     }
 }
 
+/// The `env` object of the recipe. Reads the given environment variables, or the ones
+/// of the process if there are none.
 #[derive(Debug)]
-pub(crate) struct Env;
+pub(crate) struct Env(pub(crate) Option<Arc<HashMap<String, String>>>);
+
+impl Env {
+    fn var(&self, key: &str) -> Result<String, std::env::VarError> {
+        match &self.0 {
+            Some(environment) => environment
+                .get(key)
+                .cloned()
+                .ok_or(std::env::VarError::NotPresent),
+            None => std::env::var(key),
+        }
+    }
+}
+
 impl std::fmt::Display for Env {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("Env")
@@ -624,7 +803,7 @@ impl Object for Env {
                         "`get` requires a string argument",
                     ));
                 };
-                match std::env::var(key) {
+                match self.var(key) {
                     Ok(r) => Ok(Value::from(r)),
                     Err(e) => Err(minijinja::Error::new(
                         minijinja::ErrorKind::InvalidOperation,
@@ -664,7 +843,7 @@ impl Object for Env {
                         "`get_default` requires string arguments",
                     ));
                 };
-                let ret = std::env::var(key).unwrap_or_else(|_| default.to_string());
+                let ret = self.var(key).unwrap_or_else(|_| default.to_string());
                 Ok(Value::from(ret))
             }
             "exists" => {
@@ -687,7 +866,7 @@ impl Object for Env {
                         "`exists` requires a string argument",
                     ));
                 };
-                Ok(Value::from(std::env::var(key).is_ok()))
+                Ok(Value::from(self.var(key).is_ok()))
             }
             name => Err(minijinja::Error::new(
                 minijinja::ErrorKind::UnknownMethod,
@@ -881,6 +1060,26 @@ mod tests {
         assert!(jinja.eval("${{ true if win }}").expect("test 1").is_true());
     }
 
+    #[test]
+    fn test_used_values() {
+        let options = SelectorConfig {
+            target_platform: Platform::OsxArm64,
+            host_platform: Platform::OsxArm64,
+            build_platform: Platform::OsxArm64,
+            variant: BTreeMap::from([("python".to_string(), "3.12".to_string())]),
+            ..Default::default()
+        };
+        let jinja = Jinja::new(options);
+
+        let values = jinja
+            .used_values("osx and arm64 and unix and python == '3.12'")
+            .unwrap();
+        assert_eq!(
+            values,
+            BTreeMap::from([("python".to_string(), "3.12".to_string())])
+        );
+    }
+
     #[test]
     fn eval_cdt_x86_64() {
         let variant = BTreeMap::new();
@@ -1023,6 +1222,56 @@ mod tests {
         assert!(!jinja.eval("cmp(python, '>3.5,<3.7')").expect("test 6").is_true());
     }
 
+    #[test]
+    fn eval_match() {
+        let variant = BTreeMap::from_iter(vec![("python".to_string(), "3.9".to_string())]);
+
+        let options = SelectorConfig {
+            target_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            variant,
+            ..Default::default()
+        };
+        let jinja = Jinja::new(options);
+
+        assert!(jinja.eval("match(python, '<3.10')").unwrap().is_true());
+        assert!(!jinja.eval("match(python, '>=3.10')").unwrap().is_true());
+    }
+
+    #[test]
+    fn undefined_variables() {
+        let variant = BTreeMap::from_iter(vec![("python".to_string(), "3.9".to_string())]);
+
+        let options = SelectorConfig {
+            target_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            variant,
+            ..Default::default()
+        };
+        let jinja = Jinja::new(options.clone());
+
+        // platform and architecture selectors of other platforms are not undefined
+        assert!(jinja
+            .undefined_variables("osx and arm64 and match(python, '<3.10')")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            jinja
+                .undefined_variables("cuda_compiler_version == '11.8' or python == mpi")
+                .unwrap(),
+            vec!["cuda_compiler_version".to_string(), "mpi".to_string()]
+        );
+
+        let jinja = Jinja::new(SelectorConfig {
+            allow_undefined: true,
+            ..options
+        });
+        assert!(jinja
+            .undefined_variables("cuda_compiler_version == '11.8'")
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     #[rustfmt::skip]
     fn eval_complicated_cmp() {
@@ -1045,6 +1294,80 @@ mod tests {
         assert!(!jinja.eval("cmp(python, '>3.5,<3.7')").expect("test 6").is_true());
     }
 
+    #[test]
+    fn eval_compiler_template() {
+        let variant = BTreeMap::from_iter(vec![
+            ("c_compiler".to_string(), "mygcc".to_string()),
+            ("c_compiler_version".to_string(), "12".to_string()),
+            ("c_stdlib".to_string(), "sysroot".to_string()),
+            ("compiler_template".to_string(), "{name}".to_string()),
+            (
+                "c_stdlib_template".to_string(),
+                "{name}-{target_platform}".to_string(),
+            ),
+        ]);
+
+        let options = SelectorConfig {
+            target_platform: Platform::LinuxAarch64,
+            build_platform: Platform::Linux64,
+            variant,
+            ..Default::default()
+        };
+        let jinja = Jinja::new(options);
+
+        assert_eq!(
+            jinja.eval("compiler('c')").expect("test 1").to_string(),
+            "mygcc 12"
+        );
+        // the global template also applies to the default compilers
+        assert_eq!(
+            jinja.eval("compiler('cxx')").expect("test 2").to_string(),
+            "gxx"
+        );
+        assert_eq!(
+            jinja.eval("stdlib('c')").expect("test 3").to_string(),
+            "sysroot-linux-aarch64"
+        );
+    }
+
+    #[test]
+    fn eval_gpu_compilers() {
+        let config = |pairs: &[(&str, &str)]| SelectorConfig {
+            target_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            variant: pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+
+        let jinja = Jinja::new(config(&[("cuda_compiler_version", "11.8")]));
+        assert_eq!(
+            jinja.eval("compiler('cuda')").unwrap().to_string(),
+            "nvcc_linux-64 11.8"
+        );
+
+        let jinja = Jinja::new(config(&[("cuda_compiler_version", "12.4")]));
+        assert_eq!(
+            jinja.eval("compiler('cuda')").unwrap().to_string(),
+            "cuda-nvcc_linux-64 12.4"
+        );
+
+        // the CPU variant of a CUDA matrix
+        let jinja = Jinja::new(config(&[("cuda_compiler_version", "None")]));
+        assert!(jinja.eval("compiler('cuda')").is_err());
+
+        let jinja = Jinja::new(config(&[
+            ("hip_compiler", "hipcc"),
+            ("rocm_version", "6.0"),
+        ]));
+        assert_eq!(
+            jinja.eval("compiler('hip')").unwrap().to_string(),
+            "hipcc_linux-64 6.0"
+        );
+    }
+
     fn with_env((key, value): (impl AsRef<str>, impl AsRef<str>), f: impl Fn()) {
         if let Ok(old_value) = std::env::var(key.as_ref()) {
             std::env::set_var(key.as_ref(), value.as_ref());
@@ -1087,6 +1410,37 @@ mod tests {
         assert_eq!(ps("lower_bound='1.2.3'"), "{\"pin_subpackage\":{\"name\":\"foo\",\"min_pin\":\"x.x.x.x.x.x\",\"max_pin\":\"x\",\"lower_bound\":\"1.2.3\"}}");
     }
 
+    #[test]
+    fn eval_outputs() {
+        let name = rattler_conda_types::PackageName::new_unchecked("libfoo");
+        let options = SelectorConfig {
+            subpackages: [(
+                name.clone(),
+                crate::metadata::PackageIdentifier {
+                    name,
+                    version: "1.2.3".to_string(),
+                    build_string: "h1234_0".to_string(),
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+
+        let jinja = Jinja::new(options);
+        assert_eq!(
+            jinja.eval("outputs('libfoo').version").unwrap().to_string(),
+            "1.2.3"
+        );
+        assert_eq!(
+            jinja
+                .eval("outputs('libfoo').build_string")
+                .unwrap()
+                .to_string(),
+            "h1234_0"
+        );
+        assert!(jinja.eval("outputs('libbar').version").is_err());
+    }
+
     #[test]
     fn eval_env() {
         let options = SelectorConfig {
@@ -1129,4 +1483,28 @@ mod tests {
                 .is_true());
         });
     }
+
+    #[test]
+    fn eval_env_given_environment() {
+        let options = SelectorConfig {
+            environment: Some(Arc::new(HashMap::from([(
+                "CLIENT_ONLY_VAR".to_string(),
+                "client".to_string(),
+            )]))),
+            ..Default::default()
+        };
+        let jinja = Jinja::new(options);
+
+        with_env(("RANDOM_JINJA_ENV_VAR", "false"), || {
+            assert_eq!(
+                jinja.eval("env.get('CLIENT_ONLY_VAR')").unwrap().as_str(),
+                Some("client")
+            );
+            // the environment of the process is not used
+            assert!(!jinja
+                .eval("env.exists('RANDOM_JINJA_ENV_VAR')")
+                .unwrap()
+                .is_true());
+        });
+    }
 }
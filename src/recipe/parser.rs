@@ -2,15 +2,16 @@
 //!
 //! This phase parses YAML and [`SelectorConfig`] into a [`Recipe`], where
 //! if-selectors are handled and any jinja string is processed, resulting in a rendered recipe.
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::BTreeSet, path::Path, str::FromStr};
 
 use minijinja::Value;
+use rattler_conda_types::{NoArchType, ParseStrictness, Version, VersionSpec};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     _partialerror,
     recipe::{
-        custom_yaml::{HasSpan, RenderedMappingNode, ScalarNode, TryConvertNode},
+        custom_yaml::{HasSpan, RenderedMappingNode, RenderedNode, ScalarNode, TryConvertNode},
         error::{ErrorKind, ParsingError, PartialParsingError},
         jinja::Jinja,
         Render,
@@ -20,10 +21,13 @@ use crate::{
 
 mod about;
 mod build;
+mod cache;
+mod deprecated;
 mod glob_vec;
 mod helper;
 mod output;
 mod package;
+mod pins;
 mod regex;
 mod requirements;
 mod script;
@@ -33,7 +37,12 @@ mod test;
 
 pub use self::{
     about::About,
-    build::{Build, DynamicLinking, PrefixDetection},
+    build::{
+        Build, DynamicLinking, FilePermissions, InheritEnvironment, InheritMode, LinkingOverride,
+        PrefixDetection, DEFAULT_INHERITED_ENV,
+    },
+    cache::{Cache, CacheBuild},
+    deprecated::Deprecated,
     glob_vec::GlobVec,
     output::find_outputs_from_src,
     package::{OutputPackage, Package},
@@ -42,11 +51,15 @@ pub use self::{
         Dependency, IgnoreRunExports, Language, PinCompatible, PinSubpackage, Requirements,
         RunExports,
     },
-    script::{Script, ScriptContent},
-    source::{GitRev, GitSource, GitUrl, PathSource, Source, UrlSource},
+    script::{Script, ScriptContent, ScriptPhase, ScriptShell},
+    skip::SkipReason,
+    source::{
+        GitRev, GitSource, GitSubmodules, GitUrl, HgSource, OciReference, OciSource, PathSource,
+        Source, SourcePatch, SvnSource, UrlSource,
+    },
     test::{
-        CommandsTest, CommandsTestFiles, CommandsTestRequirements, DownstreamTest,
-        PackageContentsTest, PythonTest, TestType,
+        CommandsTest, CommandsTestExpectations, CommandsTestFiles, CommandsTestRequirements,
+        DownstreamTest, PackageContentsTest, PerlTest, PythonTest, RTest, TestType,
     },
 };
 
@@ -57,6 +70,14 @@ use super::custom_yaml::Node;
 pub struct Recipe {
     /// The schema version of this recipe YAML file
     pub schema_version: u64,
+    /// The versions of rattler-build that can build this recipe
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_build_tool_constraint",
+        deserialize_with = "deserialize_build_tool_constraint"
+    )]
+    pub build_tool_constraint: Option<VersionSpec>,
     /// The package information
     pub package: Package,
     /// The information about where to obtain the sources
@@ -72,6 +93,12 @@ pub struct Recipe {
     /// The information about the package
     #[serde(default, skip_serializing_if = "About::is_default")]
     pub about: About,
+    /// Marks the package as deprecated (an empty tombstone package)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<Deprecated>,
+    /// The build that is shared by the outputs of a multi-output recipe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<Cache>,
 }
 
 pub(crate) trait CollectErrors<K, V>: Iterator<Item = Result<K, V>> + Sized {
@@ -116,6 +143,40 @@ pub(crate) trait FlattenErrors<K, V>: Iterator<Item = Result<K, Vec<V>>> + Sized
 
 impl<T, K, V> FlattenErrors<K, V> for T where T: Iterator<Item = Result<K, Vec<V>>> + Sized {}
 
+/// Whether this version of rattler-build satisfies a build tool constraint.
+fn matches_build_tool(constraint: &VersionSpec) -> bool {
+    let version = Version::from_str(env!("CARGO_PKG_VERSION"))
+        .expect("the version of rattler-build is a valid version");
+    constraint.matches(&version)
+}
+
+/// Serialize the build tool constraint as a version spec string.
+fn serialize_build_tool_constraint<S>(
+    constraint: &Option<VersionSpec>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match constraint {
+        Some(spec) => serializer.serialize_str(&spec.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Deserialize the build tool constraint from a version spec string.
+fn deserialize_build_tool_constraint<'de, D>(
+    deserializer: D,
+) -> Result<Option<VersionSpec>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|spec| VersionSpec::from_str(&spec, ParseStrictness::Strict))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
 impl Recipe {
     /// Build a recipe from a YAML string.
     pub fn from_yaml(yaml: &str, jinja_opt: SelectorConfig) -> Result<Self, Vec<ParsingError>> {
@@ -144,12 +205,21 @@ impl Recipe {
         Ok(recipe)
     }
 
-    /// Create recipes from a YAML [`Node`] structure.
-    pub fn from_node(
+    /// Render a recipe [`Node`] with the `context` of the recipe, without converting it
+    /// into a [`Recipe`]. The spans of the rendered nodes point into the recipe text.
+    pub fn render_node(
         root_node: &Node,
         jinja_opt: SelectorConfig,
-    ) -> Result<Self, Vec<PartialParsingError>> {
-        let hash = jinja_opt.hash.clone();
+    ) -> Result<RenderedMappingNode, Vec<PartialParsingError>> {
+        Self::render_with_context(root_node, jinja_opt).map(|(_, rendered_node)| rendered_node)
+    }
+
+    /// Add the `context` of the recipe to a new Jinja environment and render the root
+    /// node with it.
+    fn render_with_context(
+        root_node: &Node,
+        jinja_opt: SelectorConfig,
+    ) -> Result<(Jinja, RenderedMappingNode), Vec<PartialParsingError>> {
         let mut jinja = Jinja::new(jinja_opt);
 
         let root_node = root_node.as_mapping().ok_or_else(|| {
@@ -194,15 +264,54 @@ impl Recipe {
                 .flatten_errors()?;
         }
 
+        // the build number is available as `build_number` (e.g. for the build string),
+        // unless the context defines it
+        if !jinja.context().contains_key("build_number") {
+            let number = root_node
+                .get("build")
+                .and_then(|build| build.as_mapping())
+                .and_then(|build| build.get("number"));
+            // the build number may use an if/then selector, which renders to a sequence
+            let number: u64 = match number {
+                Some(number) => {
+                    let rendered: RenderedNode = number.render(&jinja, "build.number")?;
+                    if rendered.is_null() {
+                        0
+                    } else {
+                        rendered.try_convert("build.number")?
+                    }
+                }
+                None => 0,
+            };
+            jinja
+                .context_mut()
+                .insert("build_number".to_string(), Value::from(number));
+        }
+
         let rendered_node: RenderedMappingNode = root_node.render(&jinja, "ROOT")?;
+        Ok((jinja, rendered_node))
+    }
+
+    /// Create recipes from a YAML [`Node`] structure.
+    pub fn from_node(
+        root_node: &Node,
+        jinja_opt: SelectorConfig,
+    ) -> Result<Self, Vec<PartialParsingError>> {
+        let hash = jinja_opt.hash.clone();
+        let target_platform = jinja_opt.target_platform;
+        let (jinja, rendered_node) = Self::render_with_context(root_node, jinja_opt)?;
 
         let mut schema_version = 1;
+        let mut build_tool_constraint = None;
         let mut package = None;
         let mut build = Build::default();
         let mut source = Vec::new();
         let mut requirements = Requirements::default();
         let mut tests = Vec::default();
         let mut about = About::default();
+        let mut deprecated: Option<Deprecated> = None;
+        let mut deprecated_span = None;
+        let mut cache = None;
 
         rendered_node
             .iter()
@@ -210,6 +319,21 @@ impl Recipe {
                 let key_str = key.as_str();
                 match key_str {
                     "schema_version" => schema_version = value.try_convert(key_str)?,
+                    "build_tool_constraint" => {
+                        let spec: String = value.try_convert(key_str)?;
+                        build_tool_constraint = Some(
+                            VersionSpec::from_str(&spec, ParseStrictness::Strict).map_err(
+                                |err| {
+                                    vec![_partialerror!(
+                                        *value.span(),
+                                        ErrorKind::Other,
+                                        label = format!("invalid version constraint: {}", err),
+                                        help = "use a version spec like `>=0.40,<0.45`"
+                                    )]
+                                },
+                            )?,
+                        );
+                    }
                     "package" => package = Some(value.try_convert(key_str)?),
                     "recipe" => {
                         return Err(vec![_partialerror!(
@@ -219,11 +343,19 @@ impl Recipe {
                             "The recipe field is only allowed in conjunction with multiple outputs"
                     )])
                     }
-                    "source" => source = value.try_convert(key_str)?,
+                    "source" => {
+                        source = source::select_platform_values(value, target_platform)?
+                            .try_convert(key_str)?
+                    }
                     "build" => build = value.try_convert(key_str)?,
                     "requirements" => requirements = value.try_convert(key_str)?,
                     "tests" => tests = value.try_convert(key_str)?,
                     "about" => about = value.try_convert(key_str)?,
+                    "deprecated" => {
+                        deprecated = Some(value.try_convert(key_str)?);
+                        deprecated_span = Some(*key.span());
+                    }
+                    "cache" => cache = Some(value.try_convert(key_str)?),
                     "context" => {}
                     "extra" => {}
                     invalid_key => {
@@ -247,6 +379,32 @@ impl Recipe {
         // evaluate the skip conditions
         build.skip = build.skip.with_eval(&jinja)?;
 
+        if let (Some(deprecated), Some(span)) = (&deprecated, deprecated_span) {
+            if !source.is_empty() || !build.script.is_default() {
+                return Err(vec![_partialerror!(
+                    span,
+                    ErrorKind::Other,
+                    label = "a deprecated package is an empty tombstone package",
+                    help = "remove the `source` and the build script of the deprecated package"
+                )]);
+            }
+
+            // the tombstone is the same on every platform
+            if build.noarch.is_none() {
+                build.noarch = NoArchType::generic();
+            }
+
+            if let Some(successor) = &deprecated.successor {
+                let already_required = requirements.run.iter().any(|dep| match dep {
+                    Dependency::Spec(spec) => spec.name == successor.name,
+                    _ => false,
+                });
+                if !already_required {
+                    requirements.run.push(Dependency::Spec(successor.clone()));
+                }
+            }
+        }
+
         if schema_version != 1 {
             tracing::warn!("Unknown schema version: {}. rattler-build {} is only known to parse schema version 1.",
                 schema_version, env!("CARGO_PKG_VERSION"));
@@ -254,6 +412,7 @@ impl Recipe {
 
         let recipe = Recipe {
             schema_version,
+            build_tool_constraint,
             package: package.ok_or_else(|| {
                 vec![_partialerror!(
                     *root_node.span(),
@@ -267,11 +426,49 @@ impl Recipe {
             requirements,
             tests,
             about,
+            deprecated,
+            cache,
         };
 
         Ok(recipe)
     }
 
+    /// Get the versions of rattler-build that can build this recipe, if constrained.
+    pub const fn build_tool_constraint(&self) -> Option<&VersionSpec> {
+        self.build_tool_constraint.as_ref()
+    }
+
+    /// Check that this version of rattler-build satisfies the `build_tool_constraint`
+    /// of the recipe. Returns the constraint if it is not satisfied.
+    pub fn check_build_tool_constraint(&self) -> Result<(), &VersionSpec> {
+        match &self.build_tool_constraint {
+            Some(constraint) if !matches_build_tool(constraint) => Err(constraint),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the `build_tool_constraint` of a recipe before it is parsed, so that a
+    /// recipe for another version of rattler-build (which might not parse with this one)
+    /// fails with a clear message. Returns the constraint if it is not satisfied. A
+    /// recipe that is not valid YAML or has an invalid constraint is left to the parser.
+    pub fn check_build_tool_constraint_from_src(src: &str) -> Result<(), VersionSpec> {
+        let Ok(serde_yaml::Value::Mapping(root)) = serde_yaml::from_str(src) else {
+            return Ok(());
+        };
+        let Some(Ok(constraint)) = root
+            .get("build_tool_constraint")
+            .and_then(serde_yaml::Value::as_str)
+            .map(|spec| VersionSpec::from_str(spec, ParseStrictness::Strict))
+        else {
+            return Ok(());
+        };
+        if matches_build_tool(&constraint) {
+            Ok(())
+        } else {
+            Err(constraint)
+        }
+    }
+
     /// Get the package information.
     pub const fn package(&self) -> &Package {
         &self.package
@@ -282,6 +479,36 @@ impl Recipe {
         self.source.as_slice()
     }
 
+    /// Check that the patch files of all sources exist and contain a diff, so that a
+    /// broken patch is reported when the recipe is rendered instead of after fetching
+    /// the sources.
+    pub fn validate_patches(&self, recipe_dir: &Path) -> Result<(), Vec<PartialParsingError>> {
+        let errors = self
+            .source
+            .iter()
+            .flat_map(|source| source.patches())
+            .filter_map(|patch| patch.validate(recipe_dir).err())
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check the `pin_subpackage` and `pin_compatible` expressions in the requirements of
+    /// an output [`Node`] against the `outputs` of the recipe (given by their normalized
+    /// names), so that a pin of an unknown package is reported with its location in the
+    /// recipe instead of when the dependencies are resolved.
+    pub fn validate_pins(
+        root_node: &Node,
+        jinja_opt: SelectorConfig,
+        outputs: &BTreeSet<String>,
+    ) -> Result<(), Vec<PartialParsingError>> {
+        let rendered_node = Self::render_node(root_node, jinja_opt)?;
+        pins::validate_pins(&rendered_node, outputs)
+    }
+
     /// Get the build information.
     pub const fn build(&self) -> &Build {
         &self.build
@@ -301,10 +528,40 @@ impl Recipe {
     pub const fn about(&self) -> &About {
         &self.about
     }
+
+    /// Get the deprecation information, if the package is deprecated.
+    pub const fn deprecated(&self) -> Option<&Deprecated> {
+        self.deprecated.as_ref()
+    }
+
+    /// Get the build that is shared by the outputs, if any.
+    pub const fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// The recipe that runs the `cache` build: the sources and package information of
+    /// this recipe with the script and requirements of the cache.
+    pub fn cache_recipe(&self) -> Option<Recipe> {
+        let cache = self.cache.as_ref()?;
+        Some(Recipe {
+            build: Build {
+                script: cache.build.script.clone(),
+                files: GlobVec::default(),
+                ..self.build.clone()
+            },
+            requirements: cache.requirements.clone(),
+            tests: Vec::new(),
+            deprecated: None,
+            cache: None,
+            ..self.clone()
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
     use insta::{assert_snapshot, assert_yaml_snapshot};
     use rattler_conda_types::Platform;
 
@@ -316,9 +573,11 @@ mod tests {
     fn parsing_unix() {
         let recipe = include_str!("../../test-data/recipes/test-parsing/xtensor.yaml");
 
+        // the recipe skips with the undefined conda-build selector `vc14`
         let selector_config_unix = SelectorConfig {
             target_platform: Platform::Linux64,
             host_platform: Platform::Linux64,
+            lenient_skip: true,
             ..SelectorConfig::default()
         };
 
@@ -334,6 +593,7 @@ mod tests {
         let selector_config_win = SelectorConfig {
             target_platform: Platform::Win64,
             host_platform: Platform::Win64,
+            lenient_skip: true,
             ..SelectorConfig::default()
         };
 
@@ -342,6 +602,172 @@ mod tests {
         insta::assert_debug_snapshot!("recipe_windows", win_recipe.unwrap());
     }
 
+    #[test]
+    fn skip_reason() {
+        let raw_recipe = r#"
+package:
+  name: zlib
+  version: 0.1.0
+
+build:
+  skip:
+    - win
+    - linux
+    - osx
+"#;
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config).unwrap();
+        assert!(recipe.build().skip());
+        assert_eq!(
+            recipe.build().skip_reason(),
+            Some(SkipReason {
+                condition: "linux".to_string(),
+                line: 9,
+                column: 7,
+                variables: BTreeMap::from_iter([("linux".to_string(), "linux".to_string())]),
+            })
+        );
+
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            ..SelectorConfig::default()
+        };
+        let recipe =
+            Recipe::from_yaml(&raw_recipe.replace("- linux\n", ""), selector_config).unwrap();
+        assert!(!recipe.build().skip());
+        assert_eq!(recipe.build().skip_reason(), None);
+    }
+
+    #[test]
+    fn deprecated_package() {
+        let raw_recipe = r#"
+package:
+  name: old-name
+  version: 2.0.0
+
+deprecated:
+  successor: new-name >=2
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, SelectorConfig::default()).unwrap();
+        let deprecated = recipe.deprecated().unwrap();
+        assert_eq!(
+            deprecated.message(recipe.package().name()),
+            "`old-name` is deprecated, use `new-name` instead"
+        );
+        assert!(recipe.build().noarch().is_generic());
+        assert_eq!(
+            recipe
+                .requirements()
+                .run
+                .iter()
+                .map(|dep| match dep {
+                    Dependency::Spec(spec) => spec.to_string(),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>(),
+            vec!["new-name >=2"]
+        );
+
+        let with_source = format!("{raw_recipe}\nsource:\n  path: ./src\n");
+        assert!(Recipe::from_yaml(&with_source, SelectorConfig::default()).is_err());
+    }
+
+    #[test]
+    fn skip_with_variant() {
+        let raw_recipe = r#"
+package:
+  name: zlib
+  version: 0.1.0
+
+build:
+  skip:
+    - osx and arm64 and cuda_compiler_version == "11.8"
+    - match(python, "<3.10")
+"#;
+        let variant = BTreeMap::from_iter([
+            ("cuda_compiler_version".to_string(), "11.8".to_string()),
+            ("python".to_string(), "3.12".to_string()),
+        ]);
+        let selector_config = SelectorConfig {
+            target_platform: Platform::OsxArm64,
+            host_platform: Platform::OsxArm64,
+            variant: variant.clone(),
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config).unwrap();
+        let reason = recipe.build().skip_reason().unwrap();
+        assert_eq!(
+            reason.condition,
+            r#"osx and arm64 and cuda_compiler_version == "11.8""#
+        );
+        assert_eq!(reason.variables_str(), "cuda_compiler_version=11.8");
+
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            variant,
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config).unwrap();
+        assert!(!recipe.build().skip());
+
+        // the variant keys used in the skip conditions have to be defined
+        let selector_config = SelectorConfig {
+            target_platform: Platform::OsxArm64,
+            host_platform: Platform::OsxArm64,
+            ..SelectorConfig::default()
+        };
+        let err: ParseErrors = Recipe::from_yaml(raw_recipe, selector_config)
+            .unwrap_err()
+            .into();
+        assert!(format!("{:?}", err).contains("cuda_compiler_version"));
+    }
+
+    #[test]
+    fn skip_with_undefined_variable() {
+        let raw_recipe = r#"
+package:
+  name: zlib
+  version: 0.1.0
+
+build:
+  skip:
+    - match(pyhton, "<3.10")
+"#;
+        let variant = BTreeMap::from_iter([("python".to_string(), "3.8".to_string())]);
+        let selector_config = SelectorConfig {
+            variant: variant.clone(),
+            ..SelectorConfig::default()
+        };
+        let err: ParseErrors = Recipe::from_yaml(raw_recipe, selector_config)
+            .unwrap_err()
+            .into();
+        assert!(format!("{:?}", err).contains("did you mean `python` instead of `pyhton`?"));
+
+        // with `lenient_skip`, the undefined variable evaluates as false with a warning
+        let selector_config = SelectorConfig {
+            variant: variant.clone(),
+            lenient_skip: true,
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config).unwrap();
+        assert!(!recipe.build().skip());
+
+        let raw_recipe = raw_recipe.replace(r#"match(pyhton, "<3.10")"#, "not pyhton");
+        let selector_config = SelectorConfig {
+            variant,
+            lenient_skip: true,
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(&raw_recipe, selector_config).unwrap();
+        assert!(recipe.build().skip());
+    }
+
     #[test]
     fn bad_skip_single_output() {
         let raw_recipe = include_str!("../../test-data/recipes/test-parsing/recipe_bad_skip.yaml");
@@ -463,4 +889,323 @@ mod tests {
         let recipe = Recipe::from_yaml(recipe, selector_config).unwrap();
         assert_snapshot!(serde_yaml::to_string(&recipe).unwrap());
     }
+
+    #[test]
+    fn track_features() {
+        let raw_recipe = r#"
+package:
+  name: blas_mutex
+  version: 1.0.0
+
+build:
+  track_features:
+    - if: linux
+      then: blas_mkl
+  features:
+    - blas_mkl
+"#;
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config).unwrap();
+        assert_eq!(recipe.build().track_features(), ["blas_mkl"]);
+        assert_eq!(recipe.build().features(), ["blas_mkl"]);
+
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Osx64,
+            host_platform: Platform::Osx64,
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config).unwrap();
+        assert!(recipe.build().track_features().is_empty());
+    }
+
+    #[test]
+    fn cache_build() {
+        let raw_recipe = r#"
+recipe:
+  name: libfoo-split
+  version: 1.0.0
+
+cache:
+  requirements:
+    build:
+      - cmake
+  build:
+    script:
+      - cmake --install .
+
+outputs:
+  - package:
+      name: libfoo
+    build:
+      files:
+        - lib/**
+  - package:
+      name: libfoo-dev
+    build:
+      files:
+        - include/**
+"#;
+        let outputs = find_outputs_from_src(raw_recipe).unwrap();
+        assert_eq!(outputs.len(), 2);
+        for output in outputs {
+            let recipe = Recipe::from_node(&output, SelectorConfig::default()).unwrap();
+            let cache = recipe.cache().unwrap();
+            assert_eq!(cache.requirements.build.len(), 1);
+            assert!(!recipe.build().files().is_empty());
+
+            let cache_recipe = recipe.cache_recipe().unwrap();
+            assert_eq!(cache_recipe.package().name(), recipe.package().name());
+            assert!(cache_recipe.build().files().is_empty());
+            assert!(cache_recipe.cache().is_none());
+            assert!(matches!(
+                cache_recipe.build().script().contents(),
+                ScriptContent::Commands(commands) if commands == &["cmake --install ."]
+            ));
+        }
+
+        let raw_recipe = r#"
+package:
+  name: libfoo
+  version: 1.0.0
+
+cache:
+  requirements:
+    run:
+      - zlib
+"#;
+        assert!(Recipe::from_yaml(raw_recipe, SelectorConfig::default()).is_err());
+    }
+
+    #[test]
+    fn build_tool_constraint() {
+        let raw_recipe = r#"
+build_tool_constraint: ">=0.1"
+package:
+  name: foo
+  version: 1.0.0
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, SelectorConfig::default()).unwrap();
+        assert_eq!(recipe.build_tool_constraint().unwrap().to_string(), ">=0.1");
+        assert!(recipe.check_build_tool_constraint().is_ok());
+
+        let raw_recipe = r#"
+build_tool_constraint: "<0.1"
+package:
+  name: foo
+  version: 1.0.0
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, SelectorConfig::default()).unwrap();
+        assert!(recipe.check_build_tool_constraint().is_err());
+
+        // the constraint is checked even if the rest of the recipe does not parse
+        let raw_recipe = r#"
+build_tool_constraint: "<0.1"
+package:
+  name: foo
+  version: 1.0.0
+some_future_section:
+  enabled: true
+"#;
+        assert!(Recipe::from_yaml(raw_recipe, SelectorConfig::default()).is_err());
+        assert_eq!(
+            Recipe::check_build_tool_constraint_from_src(raw_recipe)
+                .unwrap_err()
+                .to_string(),
+            "<0.1"
+        );
+        assert!(Recipe::check_build_tool_constraint_from_src("package: [").is_ok());
+
+        let raw_recipe = r#"
+build_tool_constraint: ">=0.40,<"
+package:
+  name: foo
+  version: 1.0.0
+"#;
+        assert!(Recipe::from_yaml(raw_recipe, SelectorConfig::default()).is_err());
+    }
+
+    #[test]
+    fn inherit_environment() {
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, SelectorConfig::default()).unwrap();
+        let inherit_environment = recipe.build().inherit_environment();
+        assert_eq!(inherit_environment.mode, InheritMode::Allowlist);
+        assert!(inherit_environment.inherits("PATH"));
+        assert!(inherit_environment.inherits("LC_ALL"));
+        assert!(!inherit_environment.inherits("CFLAGS"));
+
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+build:
+  inherit_environment:
+    allow: [CFLAGS, MY_*]
+    deny: [LC_*]
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, SelectorConfig::default()).unwrap();
+        let inherit_environment = recipe.build().inherit_environment();
+        assert!(inherit_environment.inherits("CFLAGS"));
+        assert!(inherit_environment.inherits("MY_VAR"));
+        assert!(!inherit_environment.inherits("LC_ALL"));
+
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+build:
+  inherit_environment: none
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, SelectorConfig::default()).unwrap();
+        assert!(!recipe.build().inherit_environment().inherits("PATH"));
+        assert_eq!(
+            recipe.build().inherit_environment().inherited_vars(None),
+            Some(HashMap::new())
+        );
+        assert!(InheritEnvironment::all().inherited_vars(None).is_none());
+        // a given environment (e.g. of a daemon client) is always passed explicitly
+        let environment = HashMap::from([("PATH".to_string(), "/usr/bin".to_string())]);
+        assert_eq!(
+            InheritEnvironment::all().inherited_vars(Some(&environment)),
+            Some(environment)
+        );
+
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+build:
+  inherit_environment: some
+"#;
+        assert!(Recipe::from_yaml(raw_recipe, SelectorConfig::default()).is_err());
+    }
+
+    #[test]
+    fn dynamic_linking_overrides() {
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+build:
+  dynamic_linking:
+    missing_dso_allowlist:
+      - "**/libc.so*"
+    overlinking_behavior: error
+    foreign_architecture_allowlist:
+      - share/firmware/*.elf
+    overrides:
+      - files:
+          - lib/plugins/*.so
+        missing_dso_allowlist:
+          - "**/libGL.so*"
+        rpath_allowlist:
+          - /opt/vendor/lib
+      - files:
+          - bin/legacy
+        overlinking_behavior: ignore
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, SelectorConfig::default()).unwrap();
+        let dynamic_linking = recipe.build().dynamic_linking();
+        assert_eq!(dynamic_linking.overrides().len(), 2);
+
+        let plugin = Path::new("lib/plugins/foo.so");
+        let libgl = Path::new("/usr/lib/libGL.so.1");
+        assert!(dynamic_linking.is_missing_dso_allowed(plugin, libgl));
+        assert!(!dynamic_linking.is_missing_dso_allowed(Path::new("bin/foo"), libgl));
+        assert!(dynamic_linking
+            .is_missing_dso_allowed(Path::new("bin/foo"), Path::new("/lib/libc.so.6")));
+        assert!(dynamic_linking
+            .rpath_allowlist_for(plugin)
+            .is_match(Path::new("/opt/vendor/lib")));
+        assert!(dynamic_linking
+            .rpath_allowlist_for(Path::new("bin/foo"))
+            .is_empty());
+        assert!(dynamic_linking.error_on_overlinking_for(plugin));
+        assert!(!dynamic_linking.error_on_overlinking_for(Path::new("bin/legacy")));
+        assert!(
+            dynamic_linking.is_foreign_architecture_allowed(Path::new("share/firmware/device.elf"))
+        );
+        assert!(!dynamic_linking.is_foreign_architecture_allowed(Path::new("bin/legacy")));
+
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+build:
+  dynamic_linking:
+    overrides:
+      - missing_dso_allowlist:
+          - "**/libGL.so*"
+"#;
+        assert!(Recipe::from_yaml(raw_recipe, SelectorConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_templated_build_string() {
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+build:
+  number: 3
+  string: py${{ python | version_to_buildstring }}_${{ hash }}_${{ build_number }}
+"#;
+        let selector_config = SelectorConfig {
+            hash: Some(crate::hash::HashInfo {
+                hash: "1234567".to_string(),
+                prefix: "py311".to_string(),
+            }),
+            variant: BTreeMap::from([("python".to_string(), "3.11".to_string())]),
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config.clone()).unwrap();
+        assert_eq!(recipe.build().string(), Some("py311_1234567_3"));
+
+        // the build number is an integer, also if it is selected with if/then
+        let raw_recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+build:
+  number:
+    - if: unix
+      then: 3
+      else: 4
+  string: h${{ hash }}_${{ build_number + 1 }}
+"#;
+        let recipe = Recipe::from_yaml(
+            raw_recipe,
+            SelectorConfig {
+                target_platform: Platform::Linux64,
+                host_platform: Platform::Linux64,
+                ..selector_config.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(recipe.build().number(), 3);
+        assert_eq!(recipe.build().string(), Some("h1234567_4"));
+
+        // a `build_number` in the context takes precedence
+        let raw_recipe = r#"
+context:
+  build_number: 5
+package:
+  name: foo
+  version: 1.0.0
+build:
+  number: ${{ build_number }}
+  string: custom_${{ build_number }}
+"#;
+        let recipe = Recipe::from_yaml(raw_recipe, selector_config).unwrap();
+        assert_eq!(recipe.build().number(), 5);
+        assert_eq!(recipe.build().string(), Some("custom_5"));
+    }
 }
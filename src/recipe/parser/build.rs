@@ -1,14 +1,15 @@
-use std::str::FromStr;
+use std::{collections::HashMap, path::Path, str::FromStr};
 
 use globset::GlobSet;
 use rattler_conda_types::{package::EntryPoint, NoArchType};
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use super::glob_vec::{AllOrGlobVec, GlobVec};
 use super::{Dependency, FlattenErrors, SerializableRegex};
 use crate::recipe::custom_yaml::RenderedSequenceNode;
 use crate::recipe::parser::script::Script;
-use crate::recipe::parser::skip::Skip;
+use crate::recipe::parser::skip::{Skip, SkipReason};
 
 use crate::validate_keys;
 use crate::{
@@ -97,6 +98,10 @@ pub struct Build {
     /// Setting to control wether to always include a file (even if it is already present in the host env)
     #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
     pub(super) always_include_files: GlobVec,
+    /// Only the new files that match one of these globs are packaged (all new files if empty).
+    /// Outputs that share a `cache` build use this to select their part of the files.
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub(super) files: GlobVec,
     /// Merge the build and host envs
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub(super) merge_build_and_host_envs: bool,
@@ -107,6 +112,27 @@ pub struct Build {
     pub(super) prefix_detection: PrefixDetection,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub(super) post_process: Vec<PostProcess>,
+    /// Settings for the permissions of the files in the package
+    #[serde(default, skip_serializing_if = "FilePermissions::is_default")]
+    pub(super) file_permissions: FilePermissions,
+    /// Build profiles from the project configuration whose packages are added to the
+    /// build requirements
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) profiles: Vec<String>,
+    /// Track features of the package. Every track feature lowers the priority of the
+    /// package in the solver (e.g. for the less preferred variants of a mutex package).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) track_features: Vec<String>,
+    /// The (legacy) features that the package provides. Packages that depend on a
+    /// feature are preferred if the feature is tracked in the environment.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) features: Vec<String>,
+    /// The environment variables of rattler-build that the build script inherits
+    #[serde(default, skip_serializing_if = "InheritEnvironment::is_default")]
+    pub(super) inherit_environment: InheritEnvironment,
+    /// The channel url that the package is uploaded to with `--upload-to auto`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) upload_to: Option<Url>,
 }
 
 /// Post process operations for regex based replacements
@@ -138,11 +164,40 @@ impl Build {
         self.string.as_deref()
     }
 
+    /// Mark the build as a development build by prefixing the build string with `dev_`.
+    pub fn mark_as_dev(&mut self) {
+        if let Some(string) = self.string.as_mut() {
+            if !string.starts_with("dev_") {
+                *string = format!("dev_{}", string);
+            }
+        }
+    }
+
+    /// Get the names of the build profiles that are added to the build requirements.
+    pub fn profiles(&self) -> &[String] {
+        &self.profiles
+    }
+
+    /// Get the track features of the package.
+    pub fn track_features(&self) -> &[String] {
+        &self.track_features
+    }
+
+    /// Get the features of the package.
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
     /// Get the skip conditions.
     pub fn skip(&self) -> bool {
         self.skip.eval()
     }
 
+    /// Get the skip condition that evaluated to true, if the build is skipped.
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        self.skip.reason()
+    }
+
     /// Get the build script.
     pub fn script(&self) -> &Script {
         &self.script
@@ -173,6 +228,11 @@ impl Build {
         self.always_include_files.globset()
     }
 
+    /// Get the globs that select the files of the package.
+    pub const fn files(&self) -> &GlobVec {
+        &self.files
+    }
+
     /// Get the prefix detection settings.
     pub const fn prefix_detection(&self) -> &PrefixDetection {
         &self.prefix_detection
@@ -182,6 +242,21 @@ impl Build {
     pub const fn post_process(&self) -> &Vec<PostProcess> {
         &self.post_process
     }
+
+    /// Get the file permission settings.
+    pub const fn file_permissions(&self) -> &FilePermissions {
+        &self.file_permissions
+    }
+
+    /// Get the environment variables that the build script inherits.
+    pub const fn inherit_environment(&self) -> &InheritEnvironment {
+        &self.inherit_environment
+    }
+
+    /// Get the channel url that the package is uploaded to with `--upload-to auto`.
+    pub const fn upload_to(&self) -> Option<&Url> {
+        self.upload_to.as_ref()
+    }
 }
 
 impl TryConvertNode<Build> for RenderedNode {
@@ -208,16 +283,228 @@ impl TryConvertNode<Build> for RenderedMappingNode {
             dynamic_linking,
             always_copy_files,
             always_include_files,
+            files,
             merge_build_and_host_envs,
             variant,
             prefix_detection,
-            post_process
+            post_process,
+            file_permissions,
+            profiles,
+            track_features,
+            features,
+            inherit_environment,
+            upload_to
         }
 
         Ok(build)
     }
 }
 
+/// The environment variables that are always inherited in the `allowlist` mode: the
+/// ones needed to find programs, temporary directories, certificates and proxies, and
+/// the system variables that Windows programs (e.g. the compiler activation) rely on.
+pub const DEFAULT_INHERITED_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TERM",
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "TZ",
+    "TMPDIR",
+    "TMP",
+    "TEMP",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "REQUESTS_CA_BUNDLE",
+    "CURL_CA_BUNDLE",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "ALL_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "no_proxy",
+    "all_proxy",
+    "CI",
+    "DEVELOPER_DIR",
+    "SYSTEMROOT",
+    "SYSTEMDRIVE",
+    "WINDIR",
+    "COMSPEC",
+    "PATHEXT",
+    "USERNAME",
+    "USERPROFILE",
+    "HOMEDRIVE",
+    "HOMEPATH",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "PROGRAMDATA",
+    "PROGRAMFILES",
+    "PROGRAMFILES(X86)",
+    "PROGRAMW6432",
+    "COMMONPROGRAMFILES",
+    "COMMONPROGRAMFILES(X86)",
+    "NUMBER_OF_PROCESSORS",
+    "PROCESSOR_ARCHITECTURE",
+    "PROCESSOR_IDENTIFIER",
+    "OS",
+    "COMPUTERNAME",
+];
+
+/// Which of the environment variables of rattler-build are visible to the build script.
+/// The variables that rattler-build sets for the build (e.g. `PREFIX`) and the `env`
+/// and `secrets` of the script are always set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InheritMode {
+    /// Inherit no environment variables
+    None,
+    /// Inherit the variables of [`DEFAULT_INHERITED_ENV`] and the `allow` list
+    #[default]
+    Allowlist,
+    /// Inherit all environment variables (except for the `deny` list)
+    All,
+}
+
+/// The environment variables of rattler-build that the build script inherits. Given as
+/// a mode (`none`, `allowlist` or `all`) or as a mapping with the `mode` and the
+/// `allow` and `deny` lists. The lists contain variable names, a trailing `*` matches
+/// all variables with that prefix.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InheritEnvironment {
+    /// Which variables are inherited
+    #[serde(default)]
+    pub mode: InheritMode,
+    /// Additional variables that are inherited in the `allowlist` mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Variables that are never inherited
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+/// Whether a variable name matches a name or a prefix pattern (`LC_*`). Names are
+/// compared case-insensitively on Windows.
+fn env_name_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = if cfg!(windows) {
+        (pattern.to_uppercase(), name.to_uppercase())
+    } else {
+        (pattern.to_string(), name.to_string())
+    };
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+impl InheritEnvironment {
+    /// Inherit all environment variables (the behavior for scripts that are not build
+    /// scripts, e.g. tests).
+    pub fn all() -> Self {
+        Self {
+            mode: InheritMode::All,
+            ..Self::default()
+        }
+    }
+
+    /// Returns true if this is the default (`allowlist` without additional variables).
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Whether the build script inherits the environment variable with the given name.
+    pub fn inherits(&self, name: &str) -> bool {
+        if self
+            .deny
+            .iter()
+            .any(|pattern| env_name_matches(pattern, name))
+        {
+            return false;
+        }
+        match self.mode {
+            InheritMode::None => false,
+            InheritMode::All => true,
+            InheritMode::Allowlist => DEFAULT_INHERITED_ENV
+                .iter()
+                .copied()
+                .chain(self.allow.iter().map(String::as_str))
+                .any(|pattern| env_name_matches(pattern, name)),
+        }
+    }
+
+    /// The inherited variables of `environment` (the environment of the process if not
+    /// given), or `None` if the whole environment of the process is inherited.
+    pub fn inherited_vars(
+        &self,
+        environment: Option<&HashMap<String, String>>,
+    ) -> Option<HashMap<String, String>> {
+        match environment {
+            Some(environment) => Some(
+                environment
+                    .iter()
+                    .filter(|(name, _)| self.inherits(name))
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect(),
+            ),
+            None if self.mode == InheritMode::All && self.deny.is_empty() => None,
+            None => Some(
+                std::env::vars()
+                    .filter(|(name, _)| self.inherits(name))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl TryConvertNode<InheritEnvironment> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<InheritEnvironment, Vec<PartialParsingError>> {
+        match self {
+            RenderedNode::Scalar(scalar) => Ok(InheritEnvironment {
+                mode: scalar.try_convert(name)?,
+                ..InheritEnvironment::default()
+            }),
+            RenderedNode::Mapping(mapping) => {
+                let mut inherit_environment = InheritEnvironment::default();
+                validate_keys!(inherit_environment, mapping.iter(), mode, allow, deny);
+                Ok(inherit_environment)
+            }
+            _ => Err(vec![_partialerror!(
+                *self.span(),
+                ErrorKind::Other,
+                label = format!("expected a mode or a mapping for `{name}`")
+            )]),
+        }
+    }
+}
+
+impl TryConvertNode<InheritMode> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<InheritMode, Vec<PartialParsingError>> {
+        self.as_scalar()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedScalar)])
+            .and_then(|s| s.try_convert(name))
+    }
+}
+
+impl TryConvertNode<InheritMode> for RenderedScalarNode {
+    fn try_convert(&self, name: &str) -> Result<InheritMode, Vec<PartialParsingError>> {
+        match self.as_str() {
+            "none" => Ok(InheritMode::None),
+            "allowlist" => Ok(InheritMode::Allowlist),
+            "all" => Ok(InheritMode::All),
+            _ => Err(vec![_partialerror!(
+                *self.span(),
+                ErrorKind::Other,
+                label = format!("invalid value for `{name}`"),
+                help = "valid options are `none`, `allowlist` or `all`"
+            )]),
+        }
+    }
+}
+
 /// Settings for shared libraries and executables.
 #[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct DynamicLinking {
@@ -233,12 +520,37 @@ pub struct DynamicLinking {
     /// Allow runpath / rpath to point to these locations outside of the environment.
     #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
     pub(super) rpath_allowlist: GlobVec,
+    /// Binaries (relative to the prefix) that may be built for another architecture
+    /// than the target platform.
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub(super) foreign_architecture_allowlist: GlobVec,
     /// What to do when detecting overdepending.
     #[serde(default, skip_serializing_if = "LinkingCheckBehavior::is_default")]
     pub(super) overdepending_behavior: LinkingCheckBehavior,
     /// What to do when detecting overlinking.
     #[serde(default, skip_serializing_if = "LinkingCheckBehavior::is_default")]
     pub(super) overlinking_behavior: LinkingCheckBehavior,
+    /// Settings for the binaries that match a glob.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) overrides: Vec<LinkingOverride>,
+}
+
+/// Linking settings for the binaries that match one of the `files` globs (relative to
+/// the prefix). The allow lists extend the ones of `dynamic_linking`, the overlinking
+/// behavior replaces it.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct LinkingOverride {
+    /// The binaries that the settings apply to
+    pub(super) files: GlobVec,
+    /// Additional libraries that these binaries may link against
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub(super) missing_dso_allowlist: GlobVec,
+    /// Additional rpaths outside of the environment for these binaries
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub(super) rpath_allowlist: GlobVec,
+    /// What to do when detecting overlinking in these binaries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) overlinking_behavior: Option<LinkingCheckBehavior>,
 }
 
 impl DynamicLinking {
@@ -271,6 +583,12 @@ impl DynamicLinking {
         self.rpath_allowlist.globset()
     }
 
+    /// Whether the binary (relative to the prefix) may be built for another architecture
+    /// than the target platform.
+    pub fn is_foreign_architecture_allowed(&self, file: &Path) -> bool {
+        self.foreign_architecture_allowlist.is_match(file)
+    }
+
     /// Get the overdepending behavior.
     pub fn error_on_overdepending(&self) -> bool {
         self.overdepending_behavior == LinkingCheckBehavior::Error
@@ -280,6 +598,48 @@ impl DynamicLinking {
     pub fn error_on_overlinking(&self) -> bool {
         self.overlinking_behavior == LinkingCheckBehavior::Error
     }
+
+    /// Get the overrides for the binaries that match a glob.
+    pub fn overrides(&self) -> &[LinkingOverride] {
+        &self.overrides
+    }
+
+    /// The overrides that apply to a file (relative to the prefix).
+    fn overrides_for<'a>(&'a self, file: &'a Path) -> impl Iterator<Item = &'a LinkingOverride> {
+        self.overrides
+            .iter()
+            .filter(move |linking_override| linking_override.files.is_match(file))
+    }
+
+    /// Whether the file (relative to the prefix) may link against the library even
+    /// though no run dependency provides it.
+    pub fn is_missing_dso_allowed(&self, file: &Path, lib: &Path) -> bool {
+        self.missing_dso_allowlist.is_match(lib)
+            || self
+                .overrides_for(file)
+                .any(|linking_override| linking_override.missing_dso_allowlist.is_match(lib))
+    }
+
+    /// Get the rpath allow list for a file (relative to the prefix), including the
+    /// globs of the overrides that match the file.
+    pub fn rpath_allowlist_for(&self, file: &Path) -> GlobVec {
+        GlobVec::union(
+            std::iter::once(&self.rpath_allowlist).chain(
+                self.overrides_for(file)
+                    .map(|linking_override| &linking_override.rpath_allowlist),
+            ),
+        )
+    }
+
+    /// Whether overlinking in the file (relative to the prefix) is an error. The last
+    /// override that matches the file and sets a behavior wins.
+    pub fn error_on_overlinking_for(&self, file: &Path) -> bool {
+        self.overrides_for(file)
+            .filter_map(|linking_override| linking_override.overlinking_behavior.as_ref())
+            .last()
+            .unwrap_or(&self.overlinking_behavior)
+            == &LinkingCheckBehavior::Error
+    }
 }
 
 /// What to do during linking checks.
@@ -340,14 +700,58 @@ impl TryConvertNode<DynamicLinking> for RenderedMappingNode {
             binary_relocation,
             missing_dso_allowlist,
             rpath_allowlist,
+            foreign_architecture_allowlist,
             overdepending_behavior,
-            overlinking_behavior
+            overlinking_behavior,
+            overrides
         );
 
         Ok(dynamic_linking)
     }
 }
 
+impl TryConvertNode<Vec<LinkingOverride>> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<Vec<LinkingOverride>, Vec<PartialParsingError>> {
+        self.as_sequence()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedSequence)])
+            .and_then(|sequence| {
+                sequence
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, node)| node.try_convert(&format!("{name}[{idx}]")))
+                    .collect()
+            })
+    }
+}
+
+impl TryConvertNode<LinkingOverride> for RenderedNode {
+    fn try_convert(&self, _name: &str) -> Result<LinkingOverride, Vec<PartialParsingError>> {
+        let mapping = self
+            .as_mapping()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedMapping)])?;
+
+        let mut linking_override = LinkingOverride::default();
+        validate_keys!(
+            linking_override,
+            mapping.iter(),
+            files,
+            missing_dso_allowlist,
+            rpath_allowlist,
+            overlinking_behavior
+        );
+
+        if linking_override.files.is_empty() {
+            return Err(vec![_partialerror!(
+                *self.span(),
+                ErrorKind::MissingField("files".into()),
+                help = "add the globs of the binaries that the override applies to"
+            )]);
+        }
+
+        Ok(linking_override)
+    }
+}
+
 impl TryConvertNode<Vec<PostProcess>> for RenderedNode {
     fn try_convert(&self, name: &str) -> Result<Vec<PostProcess>, Vec<PartialParsingError>> {
         self.as_sequence()
@@ -409,12 +813,27 @@ pub struct Python {
     /// This is only relevant for macOS.
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub use_python_app_entrypoint: bool,
+
+    /// Add the `console_scripts` of the `.dist-info/entry_points.txt` files of the package
+    /// to the entry points (only for `noarch: python` and Windows packages), replacing the
+    /// launchers that were generated for the build prefix.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dist_info_entry_points: bool,
+
+    /// Build the extension modules against the stable ABI (limited API) so that a single
+    /// package works with every Python version starting from the lowest one in the variant
+    /// config. Only one variant is built and the package depends on `python >=<lowest>`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub abi3: bool,
 }
 
 impl Python {
     /// Returns true if this is the default python configuration.
     pub fn is_default(&self) -> bool {
-        self.entry_points.is_empty() && self.skip_pyc_compilation.is_empty()
+        self.entry_points.is_empty()
+            && self.skip_pyc_compilation.is_empty()
+            && !self.dist_info_entry_points
+            && !self.abi3
     }
 }
 
@@ -434,7 +853,9 @@ impl TryConvertNode<Python> for RenderedMappingNode {
             self.iter(),
             entry_points,
             skip_pyc_compilation,
-            use_python_app_entrypoint
+            use_python_app_entrypoint,
+            dist_info_entry_points,
+            abi3
         );
         Ok(python)
     }
@@ -567,6 +988,11 @@ pub struct PrefixDetection {
     /// This option defaults to false on Unix
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub ignore_binary_files: bool,
+
+    /// Do not fail the build when absolute paths of the build machine (build prefix,
+    /// work directory or an unregistered host prefix) are found in the packaged files
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_leaked_prefixes: bool,
 }
 
 impl Default for PrefixDetection {
@@ -575,6 +1001,7 @@ impl Default for PrefixDetection {
             force_file_type: ForceFileType::default(),
             ignore: AllOrGlobVec::All(false),
             ignore_binary_files: false,
+            allow_leaked_prefixes: false,
         }
     }
 }
@@ -602,12 +1029,50 @@ impl TryConvertNode<PrefixDetection> for RenderedMappingNode {
             self.iter(),
             force_file_type,
             ignore,
-            ignore_binary_files
+            ignore_binary_files,
+            allow_leaked_prefixes
         );
         Ok(prefix_detection)
     }
 }
 
+/// Settings for the permissions of the files in the package.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePermissions {
+    /// Normalize the file modes: executables get `0755` and all other files `0644`, which
+    /// removes the group and world write bits (ignored on Windows)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub normalize: bool,
+
+    /// Files that are allowed to have the setuid or setgid bit set. Any other file with
+    /// these bits fails the build.
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub allow_setuid: GlobVec,
+}
+
+impl FilePermissions {
+    /// Returns true if this is the default file permission configuration.
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl TryConvertNode<FilePermissions> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<FilePermissions, Vec<PartialParsingError>> {
+        self.as_mapping()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedMapping)])
+            .and_then(|m| m.try_convert(name))
+    }
+}
+
+impl TryConvertNode<FilePermissions> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<FilePermissions, Vec<PartialParsingError>> {
+        let mut file_permissions = FilePermissions::default();
+        validate_keys!(file_permissions, self.iter(), normalize, allow_setuid);
+        Ok(file_permissions)
+    }
+}
+
 impl TryConvertNode<ForceFileType> for RenderedNode {
     fn try_convert(&self, name: &str) -> Result<ForceFileType, Vec<PartialParsingError>> {
         self.as_mapping()
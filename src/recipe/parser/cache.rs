@@ -0,0 +1,90 @@
+//! The `cache` section of a multi-output recipe, a build that is shared by all outputs.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    _partialerror,
+    recipe::{
+        custom_yaml::{HasSpan, RenderedMappingNode, RenderedNode, TryConvertNode},
+        error::{ErrorKind, PartialParsingError},
+    },
+    validate_keys,
+};
+
+use super::{FlattenErrors, Requirements, Script};
+
+/// A build that runs once before the outputs are built. The files that the build
+/// installs into the host prefix are cached and copied into the host prefix of every
+/// output, which then packages its part of them (selected with `build.files`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cache {
+    /// The build script of the cache
+    #[serde(default)]
+    pub build: CacheBuild,
+    /// The build and host requirements of the cache build
+    #[serde(default)]
+    pub requirements: Requirements,
+}
+
+/// The `build` section of the cache, which only contains the script.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheBuild {
+    /// The script that builds the files of the cache
+    #[serde(default, skip_serializing_if = "Script::is_default")]
+    pub script: Script,
+}
+
+impl TryConvertNode<Cache> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<Cache, Vec<PartialParsingError>> {
+        self.as_mapping()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedMapping,)])
+            .and_then(|m| m.try_convert(name))
+    }
+}
+
+impl TryConvertNode<Cache> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<Cache, Vec<PartialParsingError>> {
+        let mut cache = Cache::default();
+
+        validate_keys!(cache, self.iter(), build, requirements);
+
+        // the cache is not a package, so it cannot have run requirements
+        let requirements = &cache.requirements;
+        if !requirements.run.is_empty()
+            || !requirements.run_constraints.is_empty()
+            || !requirements.run_exports.is_empty()
+        {
+            let span = self
+                .iter()
+                .find(|(key, _)| key.as_str() == "requirements")
+                .map(|(key, _)| *key.span())
+                .unwrap_or(*self.span());
+            return Err(vec![_partialerror!(
+                span,
+                ErrorKind::Other,
+                label = "the cache can only have `build` and `host` requirements",
+                help = "move the run requirements to the outputs"
+            )]);
+        }
+
+        Ok(cache)
+    }
+}
+
+impl TryConvertNode<CacheBuild> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<CacheBuild, Vec<PartialParsingError>> {
+        self.as_mapping()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedMapping,)])
+            .and_then(|m| m.try_convert(name))
+    }
+}
+
+impl TryConvertNode<CacheBuild> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<CacheBuild, Vec<PartialParsingError>> {
+        let mut build = CacheBuild::default();
+
+        validate_keys!(build, self.iter(), script);
+
+        Ok(build)
+    }
+}
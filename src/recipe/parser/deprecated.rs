@@ -0,0 +1,91 @@
+//! The `deprecated` section of a recipe, which turns the package into a tombstone.
+
+use rattler_conda_types::{MatchSpec, PackageName, ParseStrictness};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    _partialerror,
+    recipe::{
+        custom_yaml::{HasSpan, RenderedMappingNode, RenderedNode, TryConvertNode},
+        error::{ErrorKind, PartialParsingError},
+    },
+    validate_keys,
+};
+
+use super::FlattenErrors;
+
+/// Marks a package as deprecated. A deprecated package is an empty "tombstone"
+/// package: it has no sources and no build script, and only carries the metadata
+/// that points users to its successor.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Deprecated {
+    /// The package that replaces the deprecated package. It is added as a run
+    /// dependency, so that installing the old name pulls in the new one.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_successor",
+        deserialize_with = "deserialize_successor"
+    )]
+    pub successor: Option<MatchSpec>,
+    /// The deprecation message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Deprecated {
+    /// The deprecation message, or a default message that names the successor.
+    pub fn message(&self, name: &PackageName) -> String {
+        if let Some(message) = &self.message {
+            return message.clone();
+        }
+        match self.successor.as_ref().and_then(|spec| spec.name.as_ref()) {
+            Some(successor) => format!(
+                "`{}` is deprecated, use `{}` instead",
+                name.as_normalized(),
+                successor.as_normalized()
+            ),
+            None => format!("`{}` is deprecated", name.as_normalized()),
+        }
+    }
+}
+
+/// Serialize the successor as a match spec string.
+fn serialize_successor<S>(successor: &Option<MatchSpec>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match successor {
+        Some(spec) => serializer.serialize_str(&spec.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Deserialize the successor from a match spec string.
+fn deserialize_successor<'de, D>(deserializer: D) -> Result<Option<MatchSpec>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|spec| MatchSpec::from_str(&spec, ParseStrictness::Strict))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+impl TryConvertNode<Deprecated> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<Deprecated, Vec<PartialParsingError>> {
+        self.as_mapping()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedMapping,)])
+            .and_then(|m| m.try_convert(name))
+    }
+}
+
+impl TryConvertNode<Deprecated> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<Deprecated, Vec<PartialParsingError>> {
+        let mut deprecated = Deprecated::default();
+
+        validate_keys!(deprecated, self.iter(), successor, message);
+
+        Ok(deprecated)
+    }
+}
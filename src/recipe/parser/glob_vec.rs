@@ -94,6 +94,25 @@ impl GlobVec {
         }
     }
 
+    /// Returns a globvec with the globs of all given globvecs
+    pub fn union<'a>(glob_vecs: impl IntoIterator<Item = &'a GlobVec>) -> Self {
+        let globs = glob_vecs
+            .into_iter()
+            .flat_map(|glob_vec| glob_vec.0.iter().cloned())
+            .collect::<Vec<_>>();
+        if globs.is_empty() {
+            return Self(globs, None);
+        }
+
+        let mut globset_builder = globset::GlobSetBuilder::new();
+        for glob in globs.iter() {
+            globset_builder.add(glob.clone());
+        }
+        // the globs were valid on their own, so building the set does not fail
+        let globset = globset_builder.build().ok();
+        Self(globs, globset)
+    }
+
     /// Only used for testing
     #[cfg(test)]
     pub fn from_vec(vec: Vec<&str>) -> Self {
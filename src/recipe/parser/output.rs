@@ -14,8 +14,16 @@ use crate::{
 };
 
 static DEEP_MERGE_KEYS: [&str; 4] = ["package", "about", "extra", "build"];
-static ALLOWED_KEYS_MULTI_OUTPUTS: [&str; 7] = [
-    "context", "recipe", "source", "build", "outputs", "about", "extra",
+static ALLOWED_KEYS_MULTI_OUTPUTS: [&str; 9] = [
+    "context",
+    "recipe",
+    "source",
+    "build",
+    "outputs",
+    "about",
+    "extra",
+    "cache",
+    "build_tool_constraint",
 ];
 
 /// Retrieve all outputs from the recipe source (YAML)
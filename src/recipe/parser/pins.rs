@@ -0,0 +1,169 @@
+//! A semantic analysis pass over the rendered requirements of an output that checks
+//! the `pin_subpackage` and `pin_compatible` expressions.
+//!
+//! Without this pass, a `pin_subpackage` of a package that is not an output of the
+//! recipe, or a `pin_compatible` of a package that is not installed in the build or
+//! host environment, only fails when the dependencies are resolved. Here, the errors
+//! point at the expression in the recipe.
+
+use std::collections::BTreeSet;
+
+use rattler_conda_types::PackageName;
+
+use crate::{
+    _partialerror,
+    recipe::{
+        custom_yaml::{
+            HasSpan, RenderedMappingNode, RenderedNode, RenderedScalarNode, TryConvertNode,
+        },
+        error::{ErrorKind, PartialParsingError},
+    },
+};
+
+use super::Dependency;
+
+/// The sections of the requirements that can contain pin expressions.
+const SECTIONS: [&str; 5] = ["build", "host", "run", "run_constraints", "run_exports"];
+
+/// Collect the scalar nodes of a (possibly nested) requirements section.
+fn collect_scalars<'a>(node: &'a RenderedNode, scalars: &mut Vec<&'a RenderedScalarNode>) {
+    match node {
+        RenderedNode::Scalar(scalar) => scalars.push(scalar),
+        RenderedNode::Sequence(sequence) => sequence
+            .iter()
+            .for_each(|node| collect_scalars(node, scalars)),
+        RenderedNode::Mapping(mapping) => mapping
+            .values()
+            .for_each(|node| collect_scalars(node, scalars)),
+        RenderedNode::Null(_) => {}
+    }
+}
+
+/// Check the pin expressions in the `requirements` of a rendered output:
+///
+/// - the package of a `pin_subpackage` must be one of the `outputs` of the recipe
+///   (given by their normalized names),
+/// - the package of a `pin_compatible` must be a build or host requirement.
+pub(crate) fn validate_pins(
+    root: &RenderedMappingNode,
+    outputs: &BTreeSet<String>,
+) -> Result<(), Vec<PartialParsingError>> {
+    let Some(requirements) = root.get("requirements").and_then(RenderedNode::as_mapping) else {
+        return Ok(());
+    };
+
+    // invalid dependencies are reported when the recipe is parsed, so they are skipped here
+    let mut dependencies = Vec::new();
+    for section in SECTIONS {
+        let mut scalars = Vec::new();
+        if let Some(node) = requirements.get(section) {
+            collect_scalars(node, &mut scalars);
+        }
+        dependencies.extend(scalars.into_iter().filter_map(|scalar| {
+            let dependency: Dependency = scalar.try_convert(section).ok()?;
+            Some((section, scalar, dependency))
+        }));
+    }
+
+    let build_time_packages = dependencies
+        .iter()
+        .filter(|(section, _, _)| matches!(*section, "build" | "host"))
+        .filter_map(|(_, _, dependency)| match dependency {
+            Dependency::Spec(spec) => spec.name.clone(),
+            Dependency::PinSubpackage(pin) => Some(pin.pin_value().name.clone()),
+            Dependency::PinCompatible(_) => None,
+        })
+        .collect::<BTreeSet<PackageName>>();
+
+    let mut errors = Vec::new();
+    for (_, scalar, dependency) in &dependencies {
+        match dependency {
+            Dependency::PinSubpackage(pin) => {
+                let name = pin.pin_value().name.as_normalized();
+                if !outputs.contains(name) {
+                    errors.push(_partialerror!(
+                        *scalar.span(),
+                        ErrorKind::Other,
+                        label = format!("`{}` is not an output of this recipe", name),
+                        help = format!(
+                            "`pin_subpackage` pins to another output of the recipe, the outputs are: {}",
+                            outputs.iter().cloned().collect::<Vec<_>>().join(", ")
+                        )
+                    ));
+                }
+            }
+            Dependency::PinCompatible(pin) => {
+                let name = &pin.pin_value().name;
+                if !build_time_packages.contains(name) {
+                    errors.push(_partialerror!(
+                        *scalar.span(),
+                        ErrorKind::Other,
+                        label = format!(
+                            "`{}` is not a build or host requirement",
+                            name.as_normalized()
+                        ),
+                        help = format!(
+                            "`pin_compatible` pins to the version of a package in the host environment, add `{}` to `requirements.host`",
+                            name.as_normalized()
+                        )
+                    ));
+                }
+            }
+            Dependency::Spec(_) => {}
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        recipe::{custom_yaml::Node, Recipe},
+        selectors::SelectorConfig,
+    };
+
+    fn validate(recipe: &str, outputs: &[&str]) -> Result<(), Vec<PartialParsingError>> {
+        let node = Node::parse_yaml(0, recipe).unwrap();
+        let root = Recipe::render_node(&node, SelectorConfig::default()).unwrap();
+        let outputs = outputs.iter().map(|name| name.to_string()).collect();
+        validate_pins(&root, &outputs)
+    }
+
+    #[test]
+    fn test_validate_pins() {
+        let recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+requirements:
+  host:
+    - numpy >=1.26
+  run:
+    - ${{ pin_compatible('numpy') }}
+    - ${{ pin_subpackage('libfoo', exact=True) }}
+  run_exports:
+    weak:
+      - ${{ pin_subpackage('foo') }}
+"#;
+        assert!(validate(recipe, &["foo", "libfoo"]).is_ok());
+
+        let errors = validate(recipe, &["foo"]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+
+        let recipe = r#"
+package:
+  name: foo
+  version: 1.0.0
+requirements:
+  run:
+    - ${{ pin_compatible('numpy') }}
+"#;
+        assert_eq!(validate(recipe, &["foo"]).unwrap_err().len(), 1);
+    }
+}
@@ -7,13 +7,15 @@ use crate::{
     recipe::error::{ErrorKind, PartialParsingError},
 };
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::{borrow::Cow, collections::BTreeMap, path::PathBuf};
+use std::{borrow::Cow, collections::BTreeMap, path::PathBuf, str::FromStr};
 
 /// Defines the script to run to build the package.
 #[derive(Debug, Default, Clone)]
 pub struct Script {
     /// The interpreter to use for the script.
     pub interpreter: Option<String>,
+    /// The shell that runs the script. Defaults to `bash` on Unix and `cmd` on Windows.
+    pub shell: Option<ScriptShell>,
     /// Environment variables to set in the build environment.
     pub env: BTreeMap<String, String>,
     /// Environment variables to leak into the build environment from the host system that
@@ -22,6 +24,123 @@ pub struct Script {
     pub secrets: Vec<String>,
     /// The contents of the script, either a path or a list of commands.
     pub content: ScriptContent,
+    /// The maximum number of seconds the script is allowed to run.
+    pub timeout: Option<u64>,
+    /// Named phases of the script that are executed one after another (in the order
+    /// `configure`, `build`, `install`, `check`). The settings of the script itself
+    /// are inherited by every phase.
+    pub phases: BTreeMap<ScriptPhase, Script>,
+}
+
+/// The name of a phase of a [`Script`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptPhase {
+    /// Configure the sources, e.g. run `cmake` or `./configure`.
+    Configure,
+    /// Compile the sources.
+    Build,
+    /// Install the build results into the prefix.
+    Install,
+    /// Run checks against the installed files.
+    Check,
+}
+
+impl ScriptPhase {
+    /// The name of the phase as used in the recipe.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ScriptPhase::Configure => "configure",
+            ScriptPhase::Build => "build",
+            ScriptPhase::Install => "install",
+            ScriptPhase::Check => "check",
+        }
+    }
+}
+
+/// The shell that runs a [`Script`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptShell {
+    /// GNU bash
+    Bash,
+    /// The Z shell
+    Zsh,
+    /// The Debian Almquist shell, a plain POSIX shell
+    Dash,
+    /// `cmd.exe` on Windows
+    Cmd,
+    /// Windows PowerShell
+    Powershell,
+    /// PowerShell (Core)
+    Pwsh,
+}
+
+impl ScriptShell {
+    /// The name of the shell as used in the recipe.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ScriptShell::Bash => "bash",
+            ScriptShell::Zsh => "zsh",
+            ScriptShell::Dash => "dash",
+            ScriptShell::Cmd => "cmd",
+            ScriptShell::Powershell => "powershell",
+            ScriptShell::Pwsh => "pwsh",
+        }
+    }
+
+    /// The extension of the script files of the shell.
+    pub const fn script_extension(&self) -> &'static str {
+        match self {
+            ScriptShell::Bash | ScriptShell::Zsh | ScriptShell::Dash => "sh",
+            ScriptShell::Cmd => "bat",
+            ScriptShell::Powershell | ScriptShell::Pwsh => "ps1",
+        }
+    }
+}
+
+impl std::fmt::Display for ScriptShell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ScriptShell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(ScriptShell::Bash),
+            "zsh" => Ok(ScriptShell::Zsh),
+            "dash" => Ok(ScriptShell::Dash),
+            "cmd" => Ok(ScriptShell::Cmd),
+            "powershell" => Ok(ScriptShell::Powershell),
+            "pwsh" => Ok(ScriptShell::Pwsh),
+            _ => Err(format!("unknown shell `{s}`")),
+        }
+    }
+}
+
+impl std::fmt::Display for ScriptPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ScriptPhase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "configure" => Ok(ScriptPhase::Configure),
+            "build" => Ok(ScriptPhase::Build),
+            "install" => Ok(ScriptPhase::Install),
+            "check" => Ok(ScriptPhase::Check),
+            _ => Err(format!("unknown script phase `{s}`")),
+        }
+    }
 }
 
 impl Serialize for Script {
@@ -45,26 +164,38 @@ impl Serialize for Script {
             Object {
                 #[serde(skip_serializing_if = "Option::is_none")]
                 interpreter: Option<&'a String>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                shell: Option<ScriptShell>,
                 #[serde(skip_serializing_if = "BTreeMap::is_empty")]
                 env: &'a BTreeMap<String, String>,
                 #[serde(skip_serializing_if = "Vec::is_empty")]
                 secrets: &'a Vec<String>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                timeout: Option<u64>,
+                #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+                phases: &'a BTreeMap<ScriptPhase, Script>,
                 #[serde(skip_serializing_if = "Option::is_none", flatten)]
                 content: Option<RawScriptContent<'a>>,
             },
         }
 
+        let only_content = self.interpreter.is_none()
+            && self.shell.is_none()
+            && self.env.is_empty()
+            && self.secrets.is_empty()
+            && self.timeout.is_none()
+            && self.phases.is_empty();
+
         let raw_script = match &self.content {
             ScriptContent::CommandOrPath(content) => RawScript::CommandOrPath(content),
-            ScriptContent::Commands(content)
-                if self.interpreter.is_none() && self.env.is_empty() && self.secrets.is_empty() =>
-            {
-                RawScript::Commands(content)
-            }
+            ScriptContent::Commands(content) if only_content => RawScript::Commands(content),
             _ => RawScript::Object {
                 interpreter: self.interpreter.as_ref(),
+                shell: self.shell,
                 env: &self.env,
                 secrets: &self.secrets,
+                timeout: self.timeout,
+                phases: &self.phases,
                 content: match &self.content {
                     ScriptContent::Command(content) => Some(RawScriptContent::Command { content }),
                     ScriptContent::Commands(content) => {
@@ -103,9 +234,15 @@ impl<'de> Deserialize<'de> for Script {
                 #[serde(default)]
                 interpreter: Option<String>,
                 #[serde(default)]
+                shell: Option<ScriptShell>,
+                #[serde(default)]
                 env: BTreeMap<String, String>,
                 #[serde(default)]
                 secrets: Vec<String>,
+                #[serde(default)]
+                timeout: Option<u64>,
+                #[serde(default)]
+                phases: BTreeMap<ScriptPhase, Script>,
                 content: Option<RawScriptContent>,
             },
         }
@@ -116,13 +253,19 @@ impl<'de> Deserialize<'de> for Script {
             RawScript::Commands(commands) => ScriptContent::Commands(commands).into(),
             RawScript::Object {
                 interpreter,
+                shell,
                 env,
                 secrets,
+                timeout,
+                phases,
                 content,
             } => Self {
                 interpreter,
+                shell,
                 env,
                 secrets,
+                timeout,
+                phases,
                 content: match content {
                     Some(RawScriptContent::Command { content }) => ScriptContent::Command(content),
                     Some(RawScriptContent::Commands { content }) => {
@@ -142,6 +285,11 @@ impl Script {
         self.interpreter.as_deref()
     }
 
+    /// Returns the shell that runs the script, if one was selected
+    pub fn shell(&self) -> Option<ScriptShell> {
+        self.shell
+    }
+
     /// Returns the script contents
     pub fn contents(&self) -> &ScriptContent {
         &self.content
@@ -163,13 +311,71 @@ impl Script {
         self.secrets.as_slice()
     }
 
+    /// Returns the maximum duration the script is allowed to run.
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout.map(std::time::Duration::from_secs)
+    }
+
+    /// Returns the named phases of the script.
+    pub fn phases(&self) -> &BTreeMap<ScriptPhase, Script> {
+        &self.phases
+    }
+
+    /// Returns the scripts that should be executed, in order. If the script has no
+    /// phases this is the script itself. Otherwise, every phase inherits the
+    /// interpreter, shell, environment variables, secrets and timeout of this script unless
+    /// it overrides them. A phase never falls back to the default `build.sh` or
+    /// `build.bat` of the recipe.
+    pub fn resolved_phases(&self) -> Vec<(Option<ScriptPhase>, Script)> {
+        if self.phases.is_empty() {
+            return vec![(None, self.clone())];
+        }
+
+        self.phases
+            .iter()
+            .map(|(phase, script)| {
+                let mut env = self.env.clone();
+                env.extend(script.env.clone());
+                let mut secrets = self.secrets.clone();
+                secrets.extend(
+                    script
+                        .secrets
+                        .iter()
+                        .filter(|s| !self.secrets.contains(s))
+                        .cloned(),
+                );
+                (
+                    Some(*phase),
+                    Script {
+                        interpreter: script
+                            .interpreter
+                            .clone()
+                            .or_else(|| self.interpreter.clone()),
+                        shell: script.shell.or(self.shell),
+                        env,
+                        secrets,
+                        content: match &script.content {
+                            ScriptContent::Default => ScriptContent::Commands(Vec::new()),
+                            content => content.clone(),
+                        },
+                        timeout: script.timeout.or(self.timeout),
+                        phases: BTreeMap::new(),
+                    },
+                )
+            })
+            .collect()
+    }
+
     /// Returns true if the script references the default build script and has no additional
     /// configuration.
     pub fn is_default(&self) -> bool {
         self.content.is_default()
             && self.interpreter.is_none()
+            && self.shell.is_none()
             && self.env.is_empty()
             && self.secrets.is_empty()
+            && self.timeout.is_none()
+            && self.phases.is_empty()
     }
 }
 
@@ -177,9 +383,12 @@ impl From<ScriptContent> for Script {
     fn from(value: ScriptContent) -> Self {
         Self {
             interpreter: None,
+            shell: None,
             env: Default::default(),
             secrets: Default::default(),
             content: value,
+            timeout: None,
+            phases: BTreeMap::new(),
         }
     }
 }
@@ -224,7 +433,14 @@ impl TryConvertNode<Script> for RenderedMappingNode {
         let invalid = self.keys().find(|k| {
             !matches!(
                 k.as_str(),
-                "env" | "secrets" | "interpreter" | "content" | "file"
+                "env"
+                    | "secrets"
+                    | "interpreter"
+                    | "shell"
+                    | "content"
+                    | "file"
+                    | "timeout"
+                    | "phases"
             )
         });
 
@@ -232,7 +448,7 @@ impl TryConvertNode<Script> for RenderedMappingNode {
             return Err(vec![_partialerror!(
                 *invalid.span(),
                 ErrorKind::InvalidField(invalid.to_string().into()),
-                help = format!("valid keys for {name} are `env`, `secrets`, `interpreter`, `content` or `file`")
+                help = format!("valid keys for {name} are `env`, `secrets`, `interpreter`, `shell`, `content`, `file`, `timeout` or `phases`")
             )]);
         }
 
@@ -254,10 +470,43 @@ impl TryConvertNode<Script> for RenderedMappingNode {
             .transpose()?
             .unwrap_or_default();
 
+        let shell = self
+            .get("shell")
+            .map(|node| {
+                let shell: String = node.try_convert("shell")?;
+                ScriptShell::from_str(&shell).map_err(|err| {
+                    vec![_partialerror!(
+                        *node.span(),
+                        ErrorKind::Other,
+                        label = err,
+                        help = "valid shells are `bash`, `zsh`, `dash`, `cmd`, `powershell` and `pwsh`"
+                    )]
+                })
+            })
+            .transpose()?;
+
+        let timeout = self
+            .get("timeout")
+            .map(|node| node.try_convert("timeout"))
+            .transpose()?;
+
+        let phases = match self.get("phases") {
+            Some(phases) => parse_phases(phases)?,
+            None => BTreeMap::new(),
+        };
+
         let file = self.get("file");
 
         let content = self.get("content");
 
+        if let (Some(node), false) = (file.or(content), phases.is_empty()) {
+            return Err(vec![_partialerror!(
+                *node.span(),
+                ErrorKind::InvalidField(if file.is_some() { "file" } else { "content" }.into()),
+                help = "cannot specify both `phases` and the script `content` or `file`"
+            )]);
+        }
+
         let content = match (file, content) {
             (Some(file), Some(content)) => {
                 let (last_node, last_node_name) =
@@ -286,11 +535,55 @@ impl TryConvertNode<Script> for RenderedMappingNode {
             env,
             secrets,
             interpreter,
+            shell,
             content,
+            timeout,
+            phases,
         })
     }
 }
 
+/// Parse the `phases` of a script. Every phase is a script on its own, but phases
+/// cannot be nested.
+fn parse_phases(
+    node: &RenderedNode,
+) -> Result<BTreeMap<ScriptPhase, Script>, Vec<PartialParsingError>> {
+    let mapping = node
+        .as_mapping()
+        .ok_or_else(|| vec![_partialerror!(*node.span(), ErrorKind::ExpectedMapping)])?;
+
+    let mut phases = BTreeMap::new();
+    for (key, value) in mapping.iter() {
+        let phase = ScriptPhase::from_str(key.as_str()).map_err(|_| {
+            vec![_partialerror!(
+                *key.span(),
+                ErrorKind::InvalidField(key.to_string().into()),
+                help = "valid script phases are `configure`, `build`, `install` and `check`"
+            )]
+        })?;
+
+        if let Some(nested) = value.as_mapping().and_then(|m| m.get("phases")) {
+            return Err(vec![_partialerror!(
+                *nested.span(),
+                ErrorKind::InvalidField("phases".into()),
+                help = "script phases cannot be nested"
+            )]);
+        }
+
+        let script: Script = value.try_convert(key.as_str())?;
+        if script.contents().is_default() {
+            return Err(vec![_partialerror!(
+                *value.span(),
+                ErrorKind::MissingField("content".into()),
+                help = format!("the `{phase}` phase needs a `content` or `file`")
+            )]);
+        }
+        phases.insert(phase, script);
+    }
+
+    Ok(phases)
+}
+
 /// Describes the contents of the script as defined in [`Script`].
 #[derive(Debug, Clone, Default)]
 pub enum ScriptContent {
@@ -317,3 +610,144 @@ impl ScriptContent {
         matches!(self, Self::Default)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Script, ScriptContent, ScriptPhase, ScriptShell};
+    use crate::recipe::custom_yaml::{RenderedNode, TryConvertNode};
+
+    #[test]
+    fn test_parsing_phases() {
+        let script_section = r#"
+        script:
+          env:
+            FOO: bar
+          timeout: 60
+          phases:
+            install: make install
+            configure:
+              interpreter: bash
+              content:
+                - cmake ..
+              env:
+                BAZ: qux
+            build:
+              content: make
+              timeout: 3600
+        "#;
+
+        let yaml_root = RenderedNode::parse_yaml(0, script_section)
+            .map_err(|err| vec![err])
+            .unwrap();
+        let script_node = yaml_root.as_mapping().unwrap().get("script").unwrap();
+        let script: Script = script_node.try_convert("script").unwrap();
+
+        let phases = script.resolved_phases();
+        let names = phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                Some(ScriptPhase::Configure),
+                Some(ScriptPhase::Build),
+                Some(ScriptPhase::Install)
+            ]
+        );
+
+        let (_, configure) = &phases[0];
+        assert_eq!(configure.interpreter(), Some("bash"));
+        assert_eq!(configure.env().get("FOO").map(String::as_str), Some("bar"));
+        assert_eq!(configure.env().get("BAZ").map(String::as_str), Some("qux"));
+        assert_eq!(configure.timeout, Some(60));
+
+        let (_, build) = &phases[1];
+        assert!(matches!(build.contents(), ScriptContent::Command(c) if c == "make"));
+        assert_eq!(build.timeout, Some(3600));
+
+        // round trip through serde
+        let yaml_serde = serde_yaml::to_string(&script).unwrap();
+        let script: Script = serde_yaml::from_str(&yaml_serde).unwrap();
+        assert_eq!(script.phases().len(), 3);
+        assert_eq!(script.timeout, Some(60));
+    }
+
+    #[test]
+    fn test_phases_conflict_with_content() {
+        let script_section = r#"
+        script:
+          content: make
+          phases:
+            install: make install
+        "#;
+
+        let yaml_root = RenderedNode::parse_yaml(0, script_section)
+            .map_err(|err| vec![err])
+            .unwrap();
+        let script_node = yaml_root.as_mapping().unwrap().get("script").unwrap();
+        let script: Result<Script, _> = script_node.try_convert("script");
+        assert!(script.is_err());
+    }
+
+    #[test]
+    fn test_phase_without_content() {
+        let script_section = r#"
+        script:
+          phases:
+            configure:
+              env:
+                FOO: bar
+            install: make install
+        "#;
+
+        let yaml_root = RenderedNode::parse_yaml(0, script_section)
+            .map_err(|err| vec![err])
+            .unwrap();
+        let script_node = yaml_root.as_mapping().unwrap().get("script").unwrap();
+        let script: Result<Script, _> = script_node.try_convert("script");
+        assert!(script.is_err());
+
+        // a phase that was deserialized without content runs nothing instead of `build.sh`
+        let script: Script =
+            serde_yaml::from_str("phases:\n  configure:\n    env:\n      FOO: bar\n").unwrap();
+        let phases = script.resolved_phases();
+        assert!(matches!(
+            phases[0].1.contents(),
+            ScriptContent::Commands(commands) if commands.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_parsing_shell() {
+        let script_section = r#"
+        script:
+          shell: zsh
+          phases:
+            configure: ./configure
+            build:
+              shell: dash
+              content: make
+        "#;
+
+        let yaml_root = RenderedNode::parse_yaml(0, script_section)
+            .map_err(|err| vec![err])
+            .unwrap();
+        let script_node = yaml_root.as_mapping().unwrap().get("script").unwrap();
+        let script: Script = script_node.try_convert("script").unwrap();
+        assert_eq!(script.shell(), Some(ScriptShell::Zsh));
+
+        let phases = script.resolved_phases();
+        assert_eq!(phases[0].1.shell(), Some(ScriptShell::Zsh));
+        assert_eq!(phases[1].1.shell(), Some(ScriptShell::Dash));
+
+        // round trip through serde
+        let yaml_serde = serde_yaml::to_string(&script).unwrap();
+        let script: Script = serde_yaml::from_str(&yaml_serde).unwrap();
+        assert_eq!(script.shell(), Some(ScriptShell::Zsh));
+
+        let yaml_root = RenderedNode::parse_yaml(0, "script:\n  shell: fish\n  content: make")
+            .map_err(|err| vec![err])
+            .unwrap();
+        let script_node = yaml_root.as_mapping().unwrap().get("script").unwrap();
+        let script: Result<Script, _> = script_node.try_convert("script");
+        assert!(script.is_err());
+    }
+}
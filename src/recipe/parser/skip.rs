@@ -1,4 +1,7 @@
+use std::collections::BTreeMap;
+
 use marked_yaml::Span;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     _partialerror,
@@ -10,7 +13,55 @@ use crate::{
 };
 
 #[derive(Default, Debug, Clone)]
-pub struct Skip(Vec<(String, Span)>, Option<bool>);
+pub struct Skip {
+    /// The conditions and their location in the recipe
+    conditions: Vec<(String, Span)>,
+    /// The result of the evaluation, `None` if the conditions were not evaluated yet
+    value: Option<bool>,
+    /// The index of the condition that evaluated to true
+    triggered: Option<usize>,
+    /// The values of the variables used by the condition that evaluated to true
+    variables: BTreeMap<String, String>,
+}
+
+/// The condition that caused an output to be skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkipReason {
+    /// The text of the condition
+    pub condition: String,
+    /// The line of the condition in the recipe (1-based)
+    pub line: usize,
+    /// The column of the condition in the recipe (1-based)
+    pub column: usize,
+    /// The values of the variables that the condition uses
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+}
+
+impl SkipReason {
+    /// The values of the variables used by the condition, e.g. `python=3.8, win=true`.
+    pub fn variables_str(&self) -> String {
+        self.variables
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` (line {}, column {})",
+            self.condition, self.line, self.column
+        )?;
+        if !self.variables.is_empty() {
+            write!(f, " with {}", self.variables_str())?;
+        }
+        Ok(())
+    }
+}
 
 impl TryConvertNode<Vec<(String, Span)>> for RenderedSequenceNode {
     fn try_convert(&self, name: &str) -> Result<Vec<(String, Span)>, Vec<PartialParsingError>> {
@@ -48,35 +99,105 @@ impl TryConvertNode<Skip> for RenderedNode {
             RenderedNode::Null(_) => vec![],
         };
 
-        Ok(Skip(conditions, None))
+        Ok(Skip {
+            conditions,
+            value: None,
+            triggered: None,
+            variables: BTreeMap::new(),
+        })
     }
 }
 
 impl Skip {
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.conditions.is_empty()
     }
 
     pub fn with_eval(self, jinja: &Jinja) -> Result<Self, Vec<PartialParsingError>> {
-        for condition in &self.0 {
-            match jinja.eval(&condition.0) {
-                Ok(res) => {
-                    if res.is_true() {
-                        return Ok(Skip(self.0, Some(true)));
-                    }
-                }
-                Err(e) => {
+        for (idx, condition) in self.conditions.iter().enumerate() {
+            // undefined variables would silently evaluate to false
+            let undefined = jinja
+                .undefined_variables(&condition.0)
+                .map_err(|e| vec![_partialerror!(condition.1, ErrorKind::JinjaRendering(e))])?;
+            let mut lenient = None;
+            if !undefined.is_empty() {
+                let suggestions = undefined
+                    .iter()
+                    .filter_map(|var| {
+                        jinja.suggest_variable(var).map(|suggestion| {
+                            format!("did you mean `{suggestion}` instead of `{var}`? ")
+                        })
+                    })
+                    .collect::<String>();
+                if !jinja.lenient_skip() {
                     return Err(vec![_partialerror!(
                         condition.1,
-                        ErrorKind::JinjaRendering(e),
-                    )])
+                        ErrorKind::JinjaRendering(minijinja::Error::new(
+                            minijinja::ErrorKind::UndefinedError,
+                            format!(
+                                "undefined variable(s) in skip condition: {}",
+                                undefined.join(", ")
+                            ),
+                        )),
+                        help = format!(
+                            "{suggestions}add the variable(s) to the variant config or the `context` of the recipe, or pass `--lenient-skip` to treat them as false"
+                        )
+                    )]);
                 }
+
+                tracing::warn!(
+                    "skip condition `{}` uses undefined variable(s) {}, they evaluate as false. {}",
+                    condition.0,
+                    undefined.join(", "),
+                    suggestions
+                );
+                // `none` is falsy, and `match(var, ...)` is false for it (while an
+                // undefined variable matches every spec when searching for the variants)
+                let mut with_none = jinja.clone();
+                with_none.extend(
+                    undefined
+                        .iter()
+                        .map(|var| (var.clone(), minijinja::Value::from(()))),
+                );
+                lenient = Some(with_none);
+            }
+            let jinja = lenient.as_ref().unwrap_or(jinja);
+
+            let res = jinja
+                .eval(&condition.0)
+                .map_err(|e| vec![_partialerror!(condition.1, ErrorKind::JinjaRendering(e))])?;
+
+            if res.is_true() {
+                let variables = jinja
+                    .used_values(&condition.0)
+                    .map_err(|e| vec![_partialerror!(condition.1, ErrorKind::JinjaRendering(e))])?;
+                return Ok(Skip {
+                    value: Some(true),
+                    triggered: Some(idx),
+                    variables,
+                    ..self
+                });
             }
         }
-        Ok(Skip(self.0, Some(false)))
+        Ok(Skip {
+            value: Some(false),
+            ..self
+        })
     }
 
     pub fn eval(&self) -> bool {
-        self.1.unwrap_or(true)
+        self.value.unwrap_or(true)
+    }
+
+    /// The condition that evaluated to true, if the output is skipped.
+    pub fn reason(&self) -> Option<SkipReason> {
+        let (condition, span) = &self.conditions[self.triggered?];
+        let start = span.start();
+        Some(SkipReason {
+            condition: condition.clone(),
+            line: start.map_or(0, |m| m.line()),
+            column: start.map_or(0, |m| m.column()),
+            variables: self.variables.clone(),
+        })
     }
 }
@@ -1,7 +1,13 @@
 //! Parse the source section of a recipe
 
-use std::{fmt, path::PathBuf, str::FromStr};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use marked_yaml::Span;
+use rattler_conda_types::Platform;
 use rattler_digest::{serde::SerializableHash, Md5, Md5Hash, Sha256, Sha256Hash};
 use serde::{Deserialize, Serialize};
 use serde_with::{formats::PreferOne, serde_as, OneOrMany};
@@ -11,7 +17,8 @@ use crate::{
     _partialerror,
     recipe::{
         custom_yaml::{
-            HasSpan, RenderedMappingNode, RenderedNode, RenderedScalarNode, TryConvertNode,
+            HasSpan, RenderedMappingNode, RenderedNode, RenderedScalarNode, RenderedSequenceNode,
+            TryConvertNode,
         },
         error::{ErrorKind, PartialParsingError},
     },
@@ -29,15 +36,24 @@ pub enum Source {
     Url(UrlSource),
     /// Path source pointing to a local file or directory to retrieve the source from
     Path(PathSource),
+    /// OCI source pointing to an image or artifact in a container registry
+    Oci(OciSource),
+    /// Mercurial source pointing to a Mercurial repository
+    Hg(HgSource),
+    /// Subversion source pointing to a Subversion repository
+    Svn(SvnSource),
 }
 
 impl Source {
     /// Get the patches.
-    pub fn patches(&self) -> &[PathBuf] {
+    pub fn patches(&self) -> &[SourcePatch] {
         match self {
             Self::Git(git) => git.patches(),
             Self::Url(url) => url.patches(),
             Self::Path(path) => path.patches(),
+            Self::Oci(oci) => oci.patches(),
+            Self::Hg(hg) => hg.patches(),
+            Self::Svn(svn) => svn.patches(),
         }
     }
 
@@ -47,10 +63,249 @@ impl Source {
             Self::Git(git) => git.target_directory(),
             Self::Url(url) => url.target_directory(),
             Self::Path(path) => path.target_directory(),
+            Self::Oci(oci) => oci.target_directory(),
+            Self::Hg(hg) => hg.target_directory(),
+            Self::Svn(svn) => svn.target_directory(),
+        }
+    }
+
+    /// The same source without its patches.
+    pub fn without_patches(&self) -> Self {
+        let mut source = self.clone();
+        match &mut source {
+            Self::Git(git) => git.patches.clear(),
+            Self::Url(url) => url.patches.clear(),
+            Self::Path(path) => path.patches.clear(),
+            Self::Oci(oci) => oci.patches.clear(),
+            Self::Hg(hg) => hg.patches.clear(),
+            Self::Svn(svn) => svn.patches.clear(),
+        }
+        source
+    }
+}
+
+/// A patch that is applied to a source after it is fetched. In the recipe, a patch is
+/// either the path of the patch file (relative to the recipe), or a mapping with the path
+/// and options for applying it:
+///
+/// ```yaml
+/// patches:
+///   - fix-build.patch
+///   - path: upstream.patch
+///     strip: 1
+///     fuzz: 0
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "RawSourcePatch", into = "RawSourcePatch")]
+pub struct SourcePatch {
+    path: PathBuf,
+    strip: Option<usize>,
+    fuzz: Option<usize>,
+    /// The location of the patch in the recipe, used for error messages
+    span: Option<Span>,
+}
+
+/// The serialized form of a [`SourcePatch`]: a plain path if no options are set.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawSourcePatch {
+    Path(PathBuf),
+    Options {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        strip: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fuzz: Option<usize>,
+    },
+}
+
+impl From<RawSourcePatch> for SourcePatch {
+    fn from(raw: RawSourcePatch) -> Self {
+        match raw {
+            RawSourcePatch::Path(path) => SourcePatch::from(path),
+            RawSourcePatch::Options { path, strip, fuzz } => SourcePatch {
+                path,
+                strip,
+                fuzz,
+                span: None,
+            },
+        }
+    }
+}
+
+impl From<SourcePatch> for RawSourcePatch {
+    fn from(patch: SourcePatch) -> Self {
+        if patch.strip.is_none() && patch.fuzz.is_none() {
+            RawSourcePatch::Path(patch.path)
+        } else {
+            RawSourcePatch::Options {
+                path: patch.path,
+                strip: patch.strip,
+                fuzz: patch.fuzz,
+            }
+        }
+    }
+}
+
+impl From<PathBuf> for SourcePatch {
+    fn from(path: PathBuf) -> Self {
+        SourcePatch {
+            path,
+            strip: None,
+            fuzz: None,
+            span: None,
+        }
+    }
+}
+
+impl PartialEq for SourcePatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.strip == other.strip && self.fuzz == other.fuzz
+    }
+}
+
+impl SourcePatch {
+    /// The path of the patch file, relative to the recipe directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of leading path components to strip from the file names in the patch
+    /// (`-p` of `patch`). If unset, the strip level is guessed from the work directory.
+    pub const fn strip(&self) -> Option<usize> {
+        self.strip
+    }
+
+    /// The maximum fuzz factor (`-F` of `patch`). If unset, the default of `patch` is used.
+    pub const fn fuzz(&self) -> Option<usize> {
+        self.fuzz
+    }
+
+    /// A description of the patch for error messages, with its location in the recipe.
+    pub fn describe(&self) -> String {
+        match self.span.as_ref().and_then(|span| span.start()) {
+            Some(start) => format!(
+                "`{}` (recipe line {}, column {})",
+                self.path.display(),
+                start.line(),
+                start.column()
+            ),
+            None => format!("`{}`", self.path.display()),
+        }
+    }
+
+    /// Check that the patch file exists and looks like a patch, without applying it.
+    pub fn validate(&self, recipe_dir: &Path) -> Result<(), PartialParsingError> {
+        let span = self.span.unwrap_or_else(Span::new_blank);
+        let invalid = |label: String, help: &'static str| {
+            _partialerror!(
+                span,
+                ErrorKind::InvalidValue((
+                    "patches".to_string(),
+                    self.path.display().to_string().into()
+                )),
+                label = label,
+                help = help
+            )
+        };
+
+        let text = fs_err::read_to_string(recipe_dir.join(&self.path)).map_err(|err| {
+            invalid(
+                format!("could not read the patch file: {err}"),
+                "patch paths are relative to the recipe directory",
+            )
+        })?;
+
+        // git patches can rename files or contain binary diffs without a `---` header
+        let is_diff = text.lines().any(|line| {
+            line.starts_with("--- ")
+                || line.starts_with("diff --git ")
+                || line.starts_with("Index: ")
+        });
+        if !is_diff {
+            return Err(invalid(
+                "the file does not contain a unified diff".to_string(),
+                "create patches with `git diff` or `diff -u`",
+            ));
+        }
+        if let Err(err) = patch::Patch::from_multiple(&text) {
+            tracing::warn!("Could not parse patch {}: {}", self.describe(), err);
+        }
+        Ok(())
+    }
+}
+
+impl TryConvertNode<SourcePatch> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<SourcePatch, Vec<PartialParsingError>> {
+        match self {
+            RenderedNode::Scalar(scalar) => scalar.try_convert(name),
+            RenderedNode::Mapping(mapping) => mapping.try_convert(name),
+            _ => Err(vec![_partialerror!(
+                *self.span(),
+                ErrorKind::Other,
+                label = format!("expected a path or a mapping for `{name}`")
+            )]),
         }
     }
 }
 
+impl TryConvertNode<SourcePatch> for RenderedScalarNode {
+    fn try_convert(&self, name: &str) -> Result<SourcePatch, Vec<PartialParsingError>> {
+        let path: PathBuf = self.try_convert(name)?;
+        Ok(SourcePatch {
+            span: Some(*self.span()),
+            ..SourcePatch::from(path)
+        })
+    }
+}
+
+impl TryConvertNode<SourcePatch> for RenderedMappingNode {
+    fn try_convert(&self, name: &str) -> Result<SourcePatch, Vec<PartialParsingError>> {
+        let mut path = None;
+        let mut strip = None;
+        let mut fuzz = None;
+
+        self.iter()
+            .map(|(key, value)| {
+                match key.as_str() {
+                    "path" => path = Some(value.try_convert("path")?),
+                    "strip" => {
+                        let level: u64 = value.try_convert("strip")?;
+                        strip = Some(level as usize);
+                    }
+                    "fuzz" => {
+                        let factor: u64 = value.try_convert("fuzz")?;
+                        fuzz = Some(factor as usize);
+                    }
+                    invalid_key => {
+                        return Err(vec![_partialerror!(
+                            *key.span(),
+                            ErrorKind::InvalidField(invalid_key.to_string().into()),
+                            help = "valid fields for a patch are `path`, `strip` and `fuzz`"
+                        )])
+                    }
+                }
+                Ok(())
+            })
+            .flatten_errors()?;
+
+        let Some(path) = path else {
+            return Err(vec![_partialerror!(
+                *self.span(),
+                ErrorKind::MissingField("path".into()),
+                label = format!("the `path` of the patch file is required in `{name}`")
+            )]);
+        };
+
+        Ok(SourcePatch {
+            path,
+            strip,
+            fuzz,
+            span: Some(*self.span()),
+        })
+    }
+}
+
 impl TryConvertNode<Vec<Source>> for RenderedNode {
     fn try_convert(&self, _name: &str) -> Result<Vec<Source>, Vec<PartialParsingError>> {
         let mut sources = Vec::new();
@@ -67,12 +322,21 @@ impl TryConvertNode<Vec<Source>> for RenderedNode {
                 } else if map.contains_key("path") {
                     let path_src = map.try_convert("source")?;
                     sources.push(Source::Path(path_src));
+                } else if map.contains_key("oci") {
+                    let oci_src = map.try_convert("source")?;
+                    sources.push(Source::Oci(oci_src));
+                } else if map.contains_key("hg") {
+                    let hg_src = map.try_convert("source")?;
+                    sources.push(Source::Hg(hg_src));
+                } else if map.contains_key("svn") {
+                    let svn_src = map.try_convert("source")?;
+                    sources.push(Source::Svn(svn_src));
                 } else {
                     return Err(vec![_partialerror!(
                         *self.span(),
                         ErrorKind::Other,
-                        label = "unknown source type (no `url`, `path` or `git` found)",
-                        help = "are you missing `url`, `path` or `git`?"
+                        label = "unknown source type (no `url`, `path`, `git`, `hg`, `svn` or `oci` found)",
+                        help = "are you missing `url`, `path`, `git`, `hg`, `svn` or `oci`?"
                     )]);
                 }
             }
@@ -96,6 +360,94 @@ impl TryConvertNode<Vec<Source>> for RenderedNode {
     }
 }
 
+/// The fields of a URL source that can be specified per target platform.
+const PER_PLATFORM_FIELDS: [&str; 3] = ["url", "sha256", "md5"];
+
+/// Resolve per-platform values in the URL sources of a rendered `source` node.
+///
+/// The `url`, `sha256` and `md5` fields of a URL source can be given as a mapping
+/// from platform to value. This function replaces these mappings with the value for
+/// the given platform, so that one logical source can point to different files:
+///
+/// ```yaml
+/// source:
+///   url:
+///     linux-64: https://example.com/sdk-linux-64.tar.gz
+///     win-64: https://example.com/sdk-win-64.zip
+///   sha256:
+///     linux-64: 2c8a6d5b...
+///     win-64: 83b1f6c2...
+/// ```
+pub(super) fn select_platform_values(
+    node: &RenderedNode,
+    platform: Platform,
+) -> Result<RenderedNode, Vec<PartialParsingError>> {
+    match node {
+        RenderedNode::Sequence(seq) => {
+            let mut items = Vec::with_capacity(seq.len());
+            let mut errs = Vec::new();
+            for item in seq.iter() {
+                match select_platform_values(item, platform) {
+                    Ok(item) => items.push(item),
+                    Err(e) => errs.extend(e),
+                }
+            }
+            if !errs.is_empty() {
+                return Err(errs);
+            }
+            Ok(RenderedSequenceNode::new(*seq.span(), items).into())
+        }
+        RenderedNode::Mapping(map) if map.contains_key("url") => {
+            let mut map = map.clone();
+            for field in PER_PLATFORM_FIELDS {
+                let selected = match map.get(field) {
+                    Some(RenderedNode::Mapping(per_platform)) => {
+                        select_for_platform(field, per_platform, platform)?
+                    }
+                    _ => continue,
+                };
+                map.insert(field.into(), selected);
+            }
+            Ok(map.into())
+        }
+        _ => Ok(node.clone()),
+    }
+}
+
+/// Select the value for the given platform from a `platform -> value` mapping.
+fn select_for_platform(
+    field: &str,
+    per_platform: &RenderedMappingNode,
+    platform: Platform,
+) -> Result<RenderedNode, Vec<PartialParsingError>> {
+    let mut selected = None;
+    for (key, value) in per_platform.iter() {
+        let key_platform = Platform::from_str(key.as_str()).map_err(|_| {
+            vec![_partialerror!(
+                *key.span(),
+                ErrorKind::InvalidValue((field.to_string(), key.as_str().to_string().into())),
+                help = format!("keys of a per-platform `{field}` must be platforms like `linux-64` or `win-64`")
+            )]
+        })?;
+        if key_platform == platform {
+            selected = Some(value.clone());
+        }
+    }
+
+    selected.ok_or_else(|| {
+        let available = per_platform
+            .keys()
+            .map(|k| format!("`{}`", k.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        vec![_partialerror!(
+            *per_platform.span(),
+            ErrorKind::MissingField(format!("{field} for {platform}").into()),
+            help = format!("the source only provides a `{field}` for: {available}")
+        )]
+    })
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// A git revision (branch, tag or commit)
 pub enum GitRev {
@@ -166,6 +518,88 @@ where
     GitRev::from_str(&s).map_err(serde::de::Error::custom)
 }
 
+/// Which submodules of a git repository are checked out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GitSubmodules {
+    /// Do not check out submodules (`submodules: false`)
+    None,
+    /// Check out the submodules of the repository, but not their submodules
+    /// (`submodules: true`)
+    TopLevel,
+    /// Check out all submodules recursively (`submodules: recursive`)
+    #[default]
+    Recursive,
+}
+
+impl GitSubmodules {
+    /// Returns true if all submodules are checked out recursively (the default).
+    pub fn is_recursive(&self) -> bool {
+        matches!(self, Self::Recursive)
+    }
+}
+
+impl Serialize for GitSubmodules {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::None => serializer.serialize_bool(false),
+            Self::TopLevel => serializer.serialize_bool(true),
+            Self::Recursive => serializer.serialize_str("recursive"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for GitSubmodules {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            String(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bool(false) => Ok(Self::None),
+            Raw::Bool(true) => Ok(Self::TopLevel),
+            Raw::String(s) if s == "recursive" => Ok(Self::Recursive),
+            Raw::String(s) => Err(serde::de::Error::custom(format!(
+                "invalid value for `submodules`: `{s}`, expected `true`, `false` or `recursive`"
+            ))),
+        }
+    }
+}
+
+impl TryConvertNode<GitSubmodules> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<GitSubmodules, Vec<PartialParsingError>> {
+        let scalar = self.as_scalar().ok_or_else(|| {
+            vec![_partialerror!(
+                *self.span(),
+                ErrorKind::ExpectedScalar,
+                label = format!("expected a scalar value for `{name}`")
+            )]
+        })?;
+
+        if scalar.as_str() == "recursive" {
+            return Ok(GitSubmodules::Recursive);
+        }
+        match scalar.as_bool() {
+            Some(true) => Ok(GitSubmodules::TopLevel),
+            Some(false) => Ok(GitSubmodules::None),
+            None => Err(vec![_partialerror!(
+                *scalar.span(),
+                ErrorKind::Other,
+                label = format!("invalid value for `{name}`"),
+                help = "`submodules` must be `true`, `false` or `recursive`"
+            )]),
+        }
+    }
+}
+
 /// Git source information.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GitSource {
@@ -185,13 +619,16 @@ pub struct GitSource {
     pub depth: Option<i32>,
     /// Optionally patches to apply to the source code
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    pub patches: Vec<PathBuf>,
+    pub patches: Vec<SourcePatch>,
     /// Optionally a folder name under the `work` directory to place the source code
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub target_directory: Option<PathBuf>,
     /// Optionally request the lfs pull in git source
     #[serde(default, skip_serializing_if = "should_not_serialize_lfs")]
     pub lfs: bool,
+    /// Which submodules to check out, defaults to all submodules (recursively)
+    #[serde(default, skip_serializing_if = "GitSubmodules::is_recursive")]
+    pub submodules: GitSubmodules,
 }
 
 /// A helper method to skip serializing the lfs flag if it is false.
@@ -205,7 +642,7 @@ impl GitSource {
         url: GitUrl,
         rev: GitRev,
         depth: Option<i32>,
-        patches: Vec<PathBuf>,
+        patches: Vec<SourcePatch>,
         target_directory: Option<PathBuf>,
         lfs: bool,
     ) -> Self {
@@ -216,6 +653,7 @@ impl GitSource {
             patches,
             target_directory,
             lfs,
+            submodules: GitSubmodules::default(),
         }
     }
 
@@ -235,7 +673,7 @@ impl GitSource {
     }
 
     /// Get the patches.
-    pub fn patches(&self) -> &[PathBuf] {
+    pub fn patches(&self) -> &[SourcePatch] {
         self.patches.as_slice()
     }
 
@@ -248,6 +686,11 @@ impl GitSource {
     pub const fn lfs(&self) -> bool {
         self.lfs
     }
+
+    /// Get which submodules are checked out.
+    pub const fn submodules(&self) -> GitSubmodules {
+        self.submodules
+    }
 }
 
 impl TryConvertNode<GitSource> for RenderedMappingNode {
@@ -258,6 +701,7 @@ impl TryConvertNode<GitSource> for RenderedMappingNode {
         let mut patches = Vec::new();
         let mut target_directory = None;
         let mut lfs = false;
+        let mut submodules = GitSubmodules::default();
 
         self.iter().map(|(k, v)| {
             match k.as_str() {
@@ -316,11 +760,14 @@ impl TryConvertNode<GitSource> for RenderedMappingNode {
                 "lfs" => {
                     lfs = v.try_convert("lfs")?;
                 }
+                "submodules" => {
+                    submodules = v.try_convert("submodules")?;
+                }
                 _ => {
                     return Err(vec![_partialerror!(
                         *k.span(),
                         ErrorKind::InvalidField(k.as_str().to_owned().into()),
-                        help = "valid fields for git `source` are `git`, `rev`, `tag`, `branch`, `depth`, `patches`, `lfs` and `target_directory`"
+                        help = "valid fields for git `source` are `git`, `rev`, `tag`, `branch`, `depth`, `patches`, `lfs`, `submodules` and `target_directory`"
                     )])
                 }
             }
@@ -338,11 +785,13 @@ impl TryConvertNode<GitSource> for RenderedMappingNode {
         // Use HEAD as default rev
         let rev = rev.unwrap_or_default();
 
-        if !rev.is_head() && depth.is_some() {
+        // a shallow clone can only be made of a branch or tag, an arbitrary commit might
+        // not be reachable within the given depth
+        if matches!(rev, GitRev::Commit(_)) && depth.is_some_and(|depth| depth != -1) {
             return Err(vec![_partialerror!(
                 *self.span(),
                 ErrorKind::Other,
-                help = "git `source` with a `tag`, `branch` or `rev` cannot have a `depth`"
+                help = "git `source` with a `rev` cannot have a `depth`, use a `tag` or `branch` instead"
             )]);
         }
 
@@ -353,6 +802,7 @@ impl TryConvertNode<GitSource> for RenderedMappingNode {
             patches,
             target_directory,
             lfs,
+            submodules,
         })
     }
 }
@@ -403,7 +853,7 @@ pub struct UrlSource {
     file_name: Option<String>,
     /// Patches to apply to the source code
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    patches: Vec<PathBuf>,
+    patches: Vec<SourcePatch>,
     /// Optionally a folder name under the `work` directory to place the source code
     #[serde(skip_serializing_if = "Option::is_none")]
     target_directory: Option<PathBuf>,
@@ -426,7 +876,7 @@ impl UrlSource {
     }
 
     /// Get the patches of the URL source.
-    pub fn patches(&self) -> &[PathBuf] {
+    pub fn patches(&self) -> &[SourcePatch] {
         self.patches.as_slice()
     }
 
@@ -489,7 +939,7 @@ impl TryConvertNode<UrlSource> for RenderedMappingNode {
             return Err(vec![_partialerror!(
                 *self.span(),
                 ErrorKind::MissingField("sha256 or md5".into()),
-                help = "URL `source` must have a `sha256` or `md5` checksum field"
+                help = "URL `source` must have a `sha256` or `md5` checksum field (run `rattler-build update-checksums` to add it)"
             )]);
         }
 
@@ -521,7 +971,7 @@ pub struct PathSource {
     pub md5: Option<Md5Hash>,
     /// Patches to apply to the source code
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    pub patches: Vec<PathBuf>,
+    pub patches: Vec<SourcePatch>,
     /// Optionally a folder name under the `work` directory to place the source code
     #[serde(skip_serializing_if = "Option::is_none")]
     pub target_directory: Option<PathBuf>,
@@ -545,7 +995,7 @@ impl PathSource {
     }
 
     /// Get the patches.
-    pub fn patches(&self) -> &[PathBuf] {
+    pub fn patches(&self) -> &[SourcePatch] {
         self.patches.as_slice()
     }
 
@@ -631,9 +1081,620 @@ impl TryConvertNode<PathSource> for RenderedMappingNode {
     }
 }
 
+/// Mercurial source information.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HgSource {
+    /// Url (or path) of the Mercurial repository
+    #[serde(rename = "hg")]
+    pub url: String,
+    /// Optionally a revision (changeset, tag or branch) to check out, defaults to `tip`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// Patches to apply to the source code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<SourcePatch>,
+    /// Optionally a folder name under the `work` directory to place the source code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_directory: Option<PathBuf>,
+}
+
+impl HgSource {
+    /// Get the url of the repository.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get the revision to check out.
+    pub fn rev(&self) -> &str {
+        self.rev.as_deref().unwrap_or("tip")
+    }
+
+    /// Get the patches.
+    pub fn patches(&self) -> &[SourcePatch] {
+        self.patches.as_slice()
+    }
+
+    /// Get the target_directory.
+    pub const fn target_directory(&self) -> Option<&PathBuf> {
+        self.target_directory.as_ref()
+    }
+}
+
+impl TryConvertNode<HgSource> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<HgSource, Vec<PartialParsingError>> {
+        let mut url = None;
+        let mut rev = None;
+        let mut patches = Vec::new();
+        let mut target_directory = None;
+
+        self.iter().map(|(key, value)| {
+            match key.as_str() {
+                "hg" => url = Some(value.try_convert("hg")?),
+                "rev" => rev = value.try_convert("rev")?,
+                "patches" => patches = value.try_convert("patches")?,
+                "target_directory" => target_directory = value.try_convert("target_directory")?,
+                invalid_key => {
+                    return Err(vec![_partialerror!(
+                        *key.span(),
+                        ErrorKind::InvalidField(invalid_key.to_string().into()),
+                        help = "valid fields for hg `source` are `hg`, `rev`, `patches` and `target_directory`"
+                    )])
+                }
+            }
+            Ok(())
+        }).flatten_errors()?;
+
+        let url = url.ok_or_else(|| {
+            vec![_partialerror!(
+                *self.span(),
+                ErrorKind::MissingField("hg".into()),
+                help = "hg `source` must have an `hg` field"
+            )]
+        })?;
+
+        Ok(HgSource {
+            url,
+            rev,
+            patches,
+            target_directory,
+        })
+    }
+}
+
+/// Subversion source information.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SvnSource {
+    /// Url of the Subversion repository
+    #[serde(rename = "svn")]
+    pub url: String,
+    /// Optionally a revision number to check out, defaults to `HEAD`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// Whether to skip the externals of the repository, defaults to `false`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_externals: bool,
+    /// Patches to apply to the source code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<SourcePatch>,
+    /// Optionally a folder name under the `work` directory to place the source code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_directory: Option<PathBuf>,
+}
+
+impl SvnSource {
+    /// Get the url of the repository.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get the revision to check out.
+    pub fn rev(&self) -> &str {
+        self.rev.as_deref().unwrap_or("HEAD")
+    }
+
+    /// Whether the externals of the repository are skipped.
+    pub const fn ignore_externals(&self) -> bool {
+        self.ignore_externals
+    }
+
+    /// Get the patches.
+    pub fn patches(&self) -> &[SourcePatch] {
+        self.patches.as_slice()
+    }
+
+    /// Get the target_directory.
+    pub const fn target_directory(&self) -> Option<&PathBuf> {
+        self.target_directory.as_ref()
+    }
+}
+
+impl TryConvertNode<SvnSource> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<SvnSource, Vec<PartialParsingError>> {
+        let mut url = None;
+        let mut rev = None;
+        let mut ignore_externals = false;
+        let mut patches = Vec::new();
+        let mut target_directory = None;
+
+        self.iter().map(|(key, value)| {
+            match key.as_str() {
+                "svn" => url = Some(value.try_convert("svn")?),
+                "rev" => {
+                    let rev_str: RenderedScalarNode = value.try_convert("rev")?;
+                    if rev_str.as_str() != "HEAD" && rev_str.as_str().parse::<u64>().is_err() {
+                        return Err(vec![_partialerror!(
+                            *rev_str.span(),
+                            ErrorKind::InvalidValue(("rev".to_string(), rev_str.as_str().to_string().into())),
+                            help = "the `rev` of a svn `source` must be a revision number or `HEAD`"
+                        )]);
+                    }
+                    rev = Some(rev_str.as_str().to_string());
+                }
+                "ignore_externals" => ignore_externals = value.try_convert("ignore_externals")?,
+                "patches" => patches = value.try_convert("patches")?,
+                "target_directory" => target_directory = value.try_convert("target_directory")?,
+                invalid_key => {
+                    return Err(vec![_partialerror!(
+                        *key.span(),
+                        ErrorKind::InvalidField(invalid_key.to_string().into()),
+                        help = "valid fields for svn `source` are `svn`, `rev`, `ignore_externals`, `patches` and `target_directory`"
+                    )])
+                }
+            }
+            Ok(())
+        }).flatten_errors()?;
+
+        let url = url.ok_or_else(|| {
+            vec![_partialerror!(
+                *self.span(),
+                ErrorKind::MissingField("svn".into()),
+                help = "svn `source` must have an `svn` field"
+            )]
+        })?;
+
+        Ok(SvnSource {
+            url,
+            rev,
+            ignore_externals,
+            patches,
+            target_directory,
+        })
+    }
+}
+
+/// The registry of image references without a registry.
+const DEFAULT_OCI_REGISTRY: &str = "docker.io";
+
+/// A reference to an image or artifact in an OCI registry, e.g.
+/// `ghcr.io/org/image:tag` or `ghcr.io/org/image@sha256:...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciReference {
+    /// The registry host (with an optional port), e.g. `ghcr.io`
+    pub registry: String,
+    /// The repository in the registry, e.g. `org/image`
+    pub repository: String,
+    /// The tag, if any
+    pub tag: Option<String>,
+    /// The digest of the manifest, if the reference is pinned
+    pub digest: Option<Sha256Hash>,
+}
+
+impl OciReference {
+    /// The tag or digest to request the manifest for. Defaults to the `latest` tag.
+    pub fn manifest_reference(&self) -> String {
+        match (&self.digest, &self.tag) {
+            (Some(digest), _) => format!("sha256:{digest:x}"),
+            (None, Some(tag)) => tag.clone(),
+            (None, None) => "latest".to_string(),
+        }
+    }
+
+    /// The same reference, pinned to the given manifest digest.
+    pub fn with_digest(&self, digest: Sha256Hash) -> Self {
+        Self {
+            digest: Some(digest),
+            ..self.clone()
+        }
+    }
+}
+
+impl FromStr for OciReference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, digest) = match s.rsplit_once('@') {
+            Some((name, digest)) => {
+                let hex = digest.strip_prefix("sha256:").ok_or_else(|| {
+                    format!("unsupported digest `{digest}`, expected `sha256:...`")
+                })?;
+                let digest = rattler_digest::parse_digest_from_hex::<Sha256>(hex)
+                    .ok_or_else(|| format!("invalid digest `{digest}`"))?;
+                (name, Some(digest))
+            }
+            None => (s, None),
+        };
+
+        // a tag follows the last `:` after the last `/` (a `:` before that is a port)
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((repo, tag)) if !tag.contains('/') => (repo, Some(tag.to_string())),
+            _ => (name, None),
+        };
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, rest))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), rest.to_string())
+            }
+            _ => (DEFAULT_OCI_REGISTRY.to_string(), name.to_string()),
+        };
+        // official images on Docker Hub live in the `library` namespace
+        let repository = if registry == DEFAULT_OCI_REGISTRY && !repository.contains('/') {
+            format!("library/{repository}")
+        } else {
+            repository
+        };
+
+        if repository.is_empty()
+            || !repository.split('/').all(|part| {
+                !part.is_empty()
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+            })
+        {
+            return Err(format!("invalid repository name `{repository}`"));
+        }
+
+        Ok(Self {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+}
+
+impl fmt::Display for OciReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@sha256:{digest:x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for OciReference {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for OciReference {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        OciReference::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A source that is pulled from an OCI registry. The layers of an image (or the blobs of
+/// an artifact) are unpacked into the work directory. In the rendered recipe the
+/// reference is pinned to the digest of the manifest that was pulled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OciSource {
+    /// The image or artifact reference
+    #[serde(rename = "oci")]
+    pub reference: OciReference,
+    /// Patches to apply to the source code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<SourcePatch>,
+    /// Optionally a folder name under the `work` directory to place the source code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_directory: Option<PathBuf>,
+}
+
+impl OciSource {
+    /// Get the reference.
+    pub const fn reference(&self) -> &OciReference {
+        &self.reference
+    }
+
+    /// Get the patches.
+    pub fn patches(&self) -> &[SourcePatch] {
+        self.patches.as_slice()
+    }
+
+    /// Get the target_directory.
+    pub const fn target_directory(&self) -> Option<&PathBuf> {
+        self.target_directory.as_ref()
+    }
+}
+
+impl TryConvertNode<OciSource> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<OciSource, Vec<PartialParsingError>> {
+        let mut reference = None;
+        let mut patches = Vec::new();
+        let mut target_directory = None;
+
+        self.iter().map(|(key, value)| {
+            match key.as_str() {
+                "oci" => {
+                    let reference_str: RenderedScalarNode = value.try_convert("oci")?;
+                    let parsed = OciReference::from_str(reference_str.as_str()).map_err(|e| {
+                        vec![_partialerror!(
+                            *reference_str.span(),
+                            ErrorKind::InvalidValue(("oci".to_string(), reference_str.as_str().to_string().into())),
+                            help = e
+                        )]
+                    })?;
+                    reference = Some(parsed);
+                }
+                "patches" => patches = value.try_convert("patches")?,
+                "target_directory" => target_directory = value.try_convert("target_directory")?,
+                invalid_key => {
+                    return Err(vec![_partialerror!(
+                        *key.span(),
+                        ErrorKind::InvalidField(invalid_key.to_string().into()),
+                        help = "valid fields for oci `source` are `oci`, `patches` and `target_directory`"
+                    )])
+                }
+            }
+            Ok(())
+        }).flatten_errors()?;
+
+        let reference = reference.ok_or_else(|| {
+            vec![_partialerror!(
+                *self.span(),
+                ErrorKind::MissingField("oci".into()),
+                help = "oci `source` must have an `oci` field"
+            )]
+        })?;
+
+        Ok(OciSource {
+            reference,
+            patches,
+            target_directory,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{recipe::Recipe, selectors::SelectorConfig};
+
+    const PER_PLATFORM_RECIPE: &str = r#"
+    package:
+        name: test
+        version: 0.0.1
+
+    source:
+      - url:
+          linux-64: https://example.com/sdk-linux-64.tar.gz
+          win-64: https://example.com/sdk-win-64.zip
+        sha256:
+          linux-64: 1111111111111111111111111111111111111111111111111111111111111111
+          win-64: 2222222222222222222222222222222222222222222222222222222222222222
+    "#;
+
+    fn url_source_for(platform: Platform) -> UrlSource {
+        let selector_config = SelectorConfig {
+            target_platform: platform,
+            host_platform: platform,
+            ..SelectorConfig::default()
+        };
+        let recipe = Recipe::from_yaml(PER_PLATFORM_RECIPE, selector_config).unwrap();
+        match &recipe.sources()[0] {
+            Source::Url(url) => url.clone(),
+            _ => panic!("expected a url source"),
+        }
+    }
+
+    #[test]
+    fn test_per_platform_url_source() {
+        let linux = url_source_for(Platform::Linux64);
+        assert_eq!(
+            linux.urls()[0].as_str(),
+            "https://example.com/sdk-linux-64.tar.gz"
+        );
+        assert_eq!(format!("{:x}", linux.sha256().unwrap()), "1".repeat(64));
+
+        let win = url_source_for(Platform::Win64);
+        assert_eq!(win.urls()[0].as_str(), "https://example.com/sdk-win-64.zip");
+        assert_eq!(format!("{:x}", win.sha256().unwrap()), "2".repeat(64));
+    }
+
+    #[test]
+    fn test_per_platform_url_source_missing_platform() {
+        let selector_config = SelectorConfig {
+            target_platform: Platform::OsxArm64,
+            host_platform: Platform::OsxArm64,
+            ..SelectorConfig::default()
+        };
+        let err = Recipe::from_yaml(PER_PLATFORM_RECIPE, selector_config).unwrap_err();
+        assert!(err[0].to_string().contains("url for osx-arm64"));
+    }
+
+    #[test]
+    fn test_git_source_options() {
+        let recipe = r#"
+        package:
+            name: test
+            version: 0.0.1
+
+        source:
+          - git: https://github.com/prefix-dev/rattler-build.git
+            tag: v0.1.0
+            depth: 1
+            submodules: false
+            lfs: true
+          - git: https://github.com/prefix-dev/rattler-build.git
+            branch: main
+            submodules: true
+          - git: https://github.com/prefix-dev/rattler-build.git
+        "#;
+        let recipe = Recipe::from_yaml(recipe, SelectorConfig::default()).unwrap();
+        let git = |i: usize| match &recipe.sources()[i] {
+            Source::Git(git) => git.clone(),
+            _ => panic!("expected a git source"),
+        };
+
+        assert_eq!(git(0).depth(), Some(1));
+        assert_eq!(git(0).submodules(), GitSubmodules::None);
+        assert!(git(0).lfs());
+        assert_eq!(git(1).submodules(), GitSubmodules::TopLevel);
+        assert_eq!(git(2).submodules(), GitSubmodules::Recursive);
+
+        let yaml = serde_yaml::to_string(&git(0)).unwrap();
+        assert!(yaml.contains("submodules: false"));
+        let parsed: GitSource = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, git(0));
+
+        let commit_with_depth = r#"
+        package:
+            name: test
+            version: 0.0.1
+
+        source:
+          git: https://github.com/prefix-dev/rattler-build.git
+          rev: 50a1f7ed6c168eb0815d424cba2df62790f168f0
+          depth: 1
+        "#;
+        assert!(Recipe::from_yaml(commit_with_depth, SelectorConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_hg_and_svn_sources() {
+        let recipe = r#"
+        package:
+            name: test
+            version: 0.0.1
+
+        source:
+          - hg: https://hg.example.org/project
+            rev: v1.0
+          - svn: https://svn.code.sf.net/p/project/code/trunk
+            rev: 1234
+            ignore_externals: true
+            target_directory: svn
+        "#;
+        let recipe = Recipe::from_yaml(recipe, SelectorConfig::default()).unwrap();
+        match &recipe.sources()[0] {
+            Source::Hg(hg) => {
+                assert_eq!(hg.url(), "https://hg.example.org/project");
+                assert_eq!(hg.rev(), "v1.0");
+            }
+            _ => panic!("expected a hg source"),
+        }
+        match &recipe.sources()[1] {
+            Source::Svn(svn) => {
+                assert_eq!(svn.rev(), "1234");
+                assert!(svn.ignore_externals());
+                assert_eq!(svn.target_directory(), Some(&PathBuf::from("svn")));
+            }
+            _ => panic!("expected a svn source"),
+        }
+
+        let invalid_rev = r#"
+        package:
+            name: test
+            version: 0.0.1
+
+        source:
+          svn: https://svn.code.sf.net/p/project/code/trunk
+          rev: trunk
+        "#;
+        assert!(Recipe::from_yaml(invalid_rev, SelectorConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_oci_reference() {
+        let reference: OciReference = "ghcr.io/org/sdk:1.2".parse().unwrap();
+        assert_eq!(reference.registry, "ghcr.io");
+        assert_eq!(reference.repository, "org/sdk");
+        assert_eq!(reference.manifest_reference(), "1.2");
+
+        let reference: OciReference = "localhost:5000/sdk".parse().unwrap();
+        assert_eq!(reference.registry, "localhost:5000");
+        assert_eq!(reference.repository, "sdk");
+        assert_eq!(reference.manifest_reference(), "latest");
+
+        let reference: OciReference = "ubuntu:22.04".parse().unwrap();
+        assert_eq!(reference.to_string(), "docker.io/library/ubuntu:22.04");
+
+        let digest = "a".repeat(64);
+        let reference: OciReference = format!("ghcr.io/org/sdk:1.2@sha256:{digest}")
+            .parse()
+            .unwrap();
+        assert_eq!(reference.tag.as_deref(), Some("1.2"));
+        assert_eq!(reference.manifest_reference(), format!("sha256:{digest}"));
+        assert_eq!(
+            reference.to_string(),
+            format!("ghcr.io/org/sdk:1.2@sha256:{digest}")
+        );
+
+        assert!("ghcr.io/Org/SDK".parse::<OciReference>().is_err());
+        assert!("ghcr.io/org/sdk@sha256:1234"
+            .parse::<OciReference>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_source_patches() {
+        let recipe = r#"
+        package:
+            name: test
+            version: 0.0.1
+
+        source:
+          path: ./src
+          patches:
+            - fix.patch
+            - path: upstream.patch
+              strip: 2
+              fuzz: 0
+        "#;
+        let recipe = Recipe::from_yaml(recipe, SelectorConfig::default()).unwrap();
+        let patches = recipe.sources()[0].patches();
+        assert_eq!(patches[0].path(), Path::new("fix.patch"));
+        assert_eq!(patches[0].strip(), None);
+        assert_eq!(patches[1].strip(), Some(2));
+        assert_eq!(patches[1].fuzz(), Some(0));
+        assert!(patches[1].describe().contains("recipe line 10"));
+
+        let yaml = serde_yaml::to_string(patches).unwrap();
+        assert_eq!(
+            yaml,
+            "- fix.patch\n- path: upstream.patch\n  strip: 2\n  fuzz: 0\n"
+        );
+        let parsed: Vec<SourcePatch> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, patches);
+
+        let recipe_dir = tempfile::tempdir().unwrap();
+        fs_err::write(
+            recipe_dir.path().join("fix.patch"),
+            "--- a/foo.c\n+++ b/foo.c\n@@ -1 +1 @@\n-foo\n+bar\n",
+        )
+        .unwrap();
+        fs_err::write(recipe_dir.path().join("upstream.patch"), "not a patch\n").unwrap();
+        let errors = recipe.validate_patches(recipe_dir.path()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .label
+            .as_deref()
+            .unwrap()
+            .contains("does not contain a unified diff"));
+    }
 
     #[test]
     fn test_git_serialization() {
@@ -644,6 +1705,7 @@ mod tests {
             patches: Vec::new(),
             target_directory: None,
             lfs: false,
+            submodules: GitSubmodules::default(),
         };
 
         let yaml = serde_yaml::to_string(&git).unwrap();
@@ -664,6 +1726,7 @@ mod tests {
             patches: Vec::new(),
             target_directory: None,
             lfs: false,
+            submodules: GitSubmodules::default(),
         };
 
         let yaml = serde_yaml::to_string(&git).unwrap();
@@ -1,5 +1,7 @@
 //! Test parser module.
 
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -40,6 +42,37 @@ pub struct CommandsTestFiles {
     pub recipe: Vec<String>,
 }
 
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+/// Assertions on the exit code and the output of a script test. They are checked by
+/// the test runner, so they work the same with every shell.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommandsTestExpectations {
+    /// The expected exit code of the script (defaults to 0)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub exit_code: i32,
+    /// Strings that must appear in the standard output
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stdout_contains: Vec<String>,
+    /// Strings that must not appear in the standard output
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stdout_not_contains: Vec<String>,
+    /// Strings that must appear in the standard error
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stderr_contains: Vec<String>,
+    /// Strings that must not appear in the standard error
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stderr_not_contains: Vec<String>,
+    /// Regular expressions that must match the standard output
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stdout_matches: Vec<String>,
+    /// Regular expressions that must match the standard error
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stderr_matches: Vec<String>,
+}
+
 /// A test that executes a script in a freshly created environment
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CommandsTest {
@@ -55,6 +88,13 @@ pub struct CommandsTest {
     /// Extra files to include in the test
     #[serde(default, skip_serializing_if = "CommandsTestFiles::is_empty")]
     pub files: CommandsTestFiles,
+    /// Environment variables to set when running the script
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Assertions on the exit code and the output of the script. The `returns` key of
+    /// the test is a shorthand for `expect.exit_code`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<CommandsTestExpectations>,
 }
 
 impl CommandsTestRequirements {
@@ -98,6 +138,20 @@ impl Default for PythonTest {
     }
 }
 
+/// A Perl test that checks if the modules can be loaded with `use`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PerlTest {
+    /// List of modules to load
+    pub uses: Vec<String>,
+}
+
+/// An R test that checks if the libraries can be loaded with `library()`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RTest {
+    /// List of libraries to load
+    pub libraries: Vec<String>,
+}
+
 /// A test that runs the tests of a downstream package.
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DownstreamTest {
@@ -111,6 +165,10 @@ pub struct DownstreamTest {
 pub enum TestType {
     /// A Python test.
     Python(PythonTest),
+    /// A Perl test.
+    Perl(PerlTest),
+    /// An R test.
+    R(RTest),
     /// A test that executes multiple commands in a freshly created environment
     Command(CommandsTest),
     /// A test that runs the tests of a downstream package
@@ -138,6 +196,47 @@ pub struct PackageContentsTest {
     /// check if include path contains the file, direct or glob?
     #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
     pub include: GlobVec,
+    /// globs that no file of the package may match
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub not_exists: GlobVec,
+    /// the files that match these globs must be text files
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub text: GlobVec,
+    /// the files that match these globs must be binary files
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub binary: GlobVec,
+    /// the maximum size in bytes of every file that matches the glob
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_size: BTreeMap<String, u64>,
+    /// fail if the package contains files that are not matched by any of the
+    /// `files`, `site_packages`, `bin`, `lib` or `include` globs
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub strict: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Parse a file size like `512`, `10 KB`, `1.5MiB` or `2 GB` into bytes.
+pub(crate) fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" | "k" => 1000,
+        "mb" | "m" => 1000_u64.pow(2),
+        "gb" | "g" => 1000_u64.pow(3),
+        "kib" => 1024,
+        "mib" => 1024_u64.pow(2),
+        "gib" => 1024_u64.pow(3),
+        _ => return None,
+    };
+    Some((number * factor as f64).round() as u64)
 }
 
 impl TryConvertNode<Vec<TestType>> for RenderedNode {
@@ -202,7 +301,15 @@ impl TryConvertNode<TestType> for RenderedMappingNode {
                     let imports = as_mapping(value, key_str)?.try_convert(key_str)?;
                     test = TestType::Python(imports);
                 }
-                "script" | "requirements" | "files"  => {
+                "perl" => {
+                    let uses = as_mapping(value, key_str)?.try_convert(key_str)?;
+                    test = TestType::Perl(uses);
+                }
+                "r" => {
+                    let libraries = as_mapping(value, key_str)?.try_convert(key_str)?;
+                    test = TestType::R(libraries);
+                }
+                "script" | "requirements" | "files" | "env" | "expect" | "returns" => {
                     let commands = self.try_convert(key_str)?;
                     test = TestType::Command(commands);
                 }
@@ -217,7 +324,7 @@ impl TryConvertNode<TestType> for RenderedMappingNode {
                 invalid => Err(vec![_partialerror!(
                     *key.span(),
                     ErrorKind::InvalidField(invalid.to_string().into()),
-                    help = format!("expected fields for {name} is one of `python`, `script`, `downstream`, `package_contents`")
+                    help = format!("expected fields for {name} is one of `python`, `perl`, `r`, `script`, `downstream`, `package_contents`")
                 )])?
             }
             Ok(())
@@ -249,6 +356,50 @@ impl TryConvertNode<PythonTest> for RenderedMappingNode {
     }
 }
 
+///////////////////////////
+/// Perl Test           ///
+///////////////////////////
+
+impl TryConvertNode<PerlTest> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<PerlTest, Vec<PartialParsingError>> {
+        let mut perl_test = PerlTest::default();
+
+        validate_keys!(perl_test, self.iter(), uses);
+
+        if perl_test.uses.is_empty() {
+            Err(vec![_partialerror!(
+                *self.span(),
+                ErrorKind::MissingField("uses".into()),
+                help = "expected field `uses` in perl test to be a list of modules"
+            )])?;
+        }
+
+        Ok(perl_test)
+    }
+}
+
+///////////////////////////
+/// R Test              ///
+///////////////////////////
+
+impl TryConvertNode<RTest> for RenderedMappingNode {
+    fn try_convert(&self, _name: &str) -> Result<RTest, Vec<PartialParsingError>> {
+        let mut r_test = RTest::default();
+
+        validate_keys!(r_test, self.iter(), libraries);
+
+        if r_test.libraries.is_empty() {
+            Err(vec![_partialerror!(
+                *self.span(),
+                ErrorKind::MissingField("libraries".into()),
+                help = "expected field `libraries` in r test to be a list of libraries"
+            )])?;
+        }
+
+        Ok(r_test)
+    }
+}
+
 ///////////////////////////
 /// Downstream Test     ///
 ///////////////////////////
@@ -303,11 +454,86 @@ impl TryConvertNode<CommandsTestFiles> for RenderedMappingNode {
     }
 }
 
+impl TryConvertNode<CommandsTestExpectations> for RenderedNode {
+    fn try_convert(
+        &self,
+        name: &str,
+    ) -> Result<CommandsTestExpectations, Vec<PartialParsingError>> {
+        self.as_mapping()
+            .ok_or_else(|| vec![_partialerror!(*self.span(), ErrorKind::ExpectedMapping,)])
+            .and_then(|m| m.try_convert(name))
+    }
+}
+
+impl TryConvertNode<CommandsTestExpectations> for RenderedMappingNode {
+    fn try_convert(
+        &self,
+        _name: &str,
+    ) -> Result<CommandsTestExpectations, Vec<PartialParsingError>> {
+        let mut expect = CommandsTestExpectations::default();
+        validate_keys!(
+            expect,
+            self.iter(),
+            exit_code,
+            stdout_contains,
+            stdout_not_contains,
+            stderr_contains,
+            stderr_not_contains,
+            stdout_matches,
+            stderr_matches
+        );
+
+        // report invalid regular expressions at the pattern in the recipe
+        let mut errors = Vec::new();
+        for key in ["stdout_matches", "stderr_matches"] {
+            let Some(patterns) = self.get(key).and_then(RenderedNode::as_sequence) else {
+                continue;
+            };
+            for pattern in patterns.iter().filter_map(RenderedNode::as_scalar) {
+                if let Err(err) = regex::Regex::new(pattern.as_str()) {
+                    errors.push(_partialerror!(
+                        *pattern.span(),
+                        ErrorKind::Other,
+                        label = format!("invalid regular expression: {}", err)
+                    ));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(expect)
+    }
+}
+
 impl TryConvertNode<CommandsTest> for RenderedMappingNode {
     fn try_convert(&self, _name: &str) -> Result<CommandsTest, Vec<PartialParsingError>> {
         let mut commands_test = CommandsTest::default();
 
-        validate_keys!(commands_test, self.iter(), script, requirements, files);
+        validate_keys!(
+            commands_test,
+            self.iter().filter(|(key, _)| key.as_str() != "returns"),
+            script,
+            requirements,
+            files,
+            env,
+            expect
+        );
+
+        if let Some(returns) = self.get("returns") {
+            let exit_code: i32 = returns.try_convert("returns")?;
+            let expect = commands_test.expect.get_or_insert_with(Default::default);
+            if expect.exit_code != 0 && expect.exit_code != exit_code {
+                Err(vec![_partialerror!(
+                    *returns.span(),
+                    ErrorKind::Other,
+                    label = "conflicts with `expect.exit_code`",
+                    help = "`returns` is a shorthand for `expect.exit_code`, use only one of them"
+                )])?;
+            }
+            expect.exit_code = exit_code;
+        }
 
         if commands_test.script.is_empty() {
             Err(vec![_partialerror!(
@@ -343,13 +569,51 @@ impl TryConvertNode<PackageContentsTest> for RenderedMappingNode {
         let mut package_contents = PackageContentsTest::default();
         validate_keys!(
             package_contents,
-            self.iter(),
+            self.iter().filter(|(key, _)| key.as_str() != "max_size"),
             files,
             site_packages,
             lib,
             bin,
-            include
+            include,
+            not_exists,
+            text,
+            binary,
+            strict
         );
+
+        // the sizes are given in a human readable form, e.g. `10 MB`
+        if let Some(max_size) = self.get("max_size") {
+            let max_size = max_size.as_mapping().ok_or_else(|| {
+                vec![_partialerror!(
+                    *max_size.span(),
+                    ErrorKind::ExpectedMapping,
+                    help = "expected a mapping from globs to sizes, e.g. `lib/*.so: 10 MB`"
+                )]
+            })?;
+            for (glob, size) in max_size.iter() {
+                if let Err(err) = globset::Glob::new(glob.as_str()) {
+                    Err(vec![_partialerror!(
+                        *glob.span(),
+                        ErrorKind::GlobParsing(err),
+                    )])?;
+                }
+                let bytes = size
+                    .as_scalar()
+                    .and_then(|size| parse_size(size.as_str()))
+                    .ok_or_else(|| {
+                        vec![_partialerror!(
+                            *size.span(),
+                            ErrorKind::Other,
+                            label = "invalid size",
+                            help = "expected a size like `512`, `10 KB` or `1.5 MiB`"
+                        )]
+                    })?;
+                package_contents
+                    .max_size
+                    .insert(glob.as_str().to_string(), bytes);
+            }
+        }
+
         Ok(package_contents)
     }
 }
@@ -393,4 +657,48 @@ mod test {
             _ => panic!("expected python test"),
         }
     }
+
+    #[test]
+    fn test_parse_size() {
+        use super::parse_size;
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("10 KB"), Some(10_000));
+        assert_eq!(parse_size("1.5MiB"), Some(1_572_864));
+        assert_eq!(parse_size("2 gb"), Some(2_000_000_000));
+        assert_eq!(parse_size("ten MB"), None);
+        assert_eq!(parse_size("10 parsecs"), None);
+    }
+
+    #[test]
+    fn test_parsing_perl_and_r() {
+        let test_section = r#"
+        tests:
+          - perl:
+              uses:
+                - Test::More
+          - r:
+              libraries:
+                - ggplot2
+          - r: {}
+        "#;
+
+        let yaml_root = RenderedNode::parse_yaml(0, test_section)
+            .map_err(|err| vec![err])
+            .unwrap();
+        let tests_node = yaml_root.as_mapping().unwrap().get("tests").unwrap();
+        let sequence = tests_node.as_sequence().unwrap();
+
+        let perl: TestType = sequence[0].try_convert("tests").unwrap();
+        match perl {
+            TestType::Perl(perl_test) => assert_eq!(perl_test.uses, vec!["Test::More"]),
+            _ => panic!("expected perl test"),
+        }
+        let r: TestType = sequence[1].try_convert("tests").unwrap();
+        match r {
+            TestType::R(r_test) => assert_eq!(r_test.libraries, vec!["ggplot2"]),
+            _ => panic!("expected r test"),
+        }
+        let empty: Result<TestType, _> = sequence[2].try_convert("tests");
+        assert!(empty.is_err());
+    }
 }
@@ -0,0 +1,275 @@
+//! Generating recipes for Rust binaries from the crates.io API.
+
+use std::path::Path;
+
+use itertools::Itertools;
+use miette::{Context, IntoDiagnostic};
+use serde::Deserialize;
+
+use crate::{
+    recipe_generator::{
+        license,
+        serialize::{self, SourceElement},
+    },
+    tool_configuration::NetworkSettings,
+};
+
+/// The response of `https://crates.io/api/v1/crates/<name>`.
+#[derive(Deserialize, Debug)]
+struct CrateResponse {
+    #[serde(rename = "crate")]
+    krate: CrateInfo,
+    versions: Vec<CrateVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CrateInfo {
+    name: String,
+    max_version: String,
+    max_stable_version: Option<String>,
+    description: Option<String>,
+    homepage: Option<String>,
+    repository: Option<String>,
+    documentation: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CrateVersion {
+    num: String,
+    license: Option<String>,
+    /// The SHA256 of the `.crate` file
+    checksum: String,
+    yanked: bool,
+    #[serde(default)]
+    bin_names: Vec<String>,
+}
+
+/// The response of `https://crates.io/api/v1/crates/<name>/<version>/dependencies`.
+#[derive(Deserialize, Debug)]
+struct DependenciesResponse {
+    dependencies: Vec<CrateDependency>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CrateDependency {
+    crate_id: String,
+    kind: String,
+    optional: bool,
+}
+
+/// Crates that link against a system library, and the conda package of the library.
+const SYS_CRATES: [(&str, &str); 13] = [
+    ("openssl-sys", "openssl"),
+    ("libz-sys", "zlib"),
+    ("libgit2-sys", "libgit2"),
+    ("libssh2-sys", "libssh2"),
+    ("curl-sys", "libcurl"),
+    ("bzip2-sys", "bzip2"),
+    ("zstd-sys", "zstd"),
+    ("lzma-sys", "xz"),
+    ("libsqlite3-sys", "libsqlite"),
+    ("libxml2-sys", "libxml2"),
+    ("pq-sys", "libpq"),
+    ("hdf5-sys", "hdf5"),
+    ("libarchive3-sys", "libarchive"),
+];
+
+/// The conda package of the system library that a crate links against.
+fn sys_crate_package(crate_id: &str) -> Option<&'static str> {
+    SYS_CRATES
+        .iter()
+        .find(|(krate, _)| *krate == crate_id)
+        .map(|(_, package)| *package)
+}
+
+/// Old crates use `/` instead of ` OR ` in the license expression.
+fn map_license(license: &str) -> String {
+    license
+        .split('/')
+        .map(str::trim)
+        .filter(|license| !license.is_empty())
+        .join(" OR ")
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+) -> miette::Result<Option<T>> {
+    let response = client.get(url).send().await.into_diagnostic()?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let value = response
+        .error_for_status()
+        .into_diagnostic()?
+        .json::<T>()
+        .await
+        .into_diagnostic()
+        .wrap_err("failed to parse the crates.io metadata")?;
+    Ok(Some(value))
+}
+
+async fn download_crate(client: &reqwest::Client, url: &str, dest: &Path) -> miette::Result<()> {
+    let bytes = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .into_diagnostic()?
+        .bytes()
+        .await
+        .into_diagnostic()?;
+    fs_err::write(dest, bytes).into_diagnostic()
+}
+
+pub async fn generate_cargo_recipe(package: &str, fetch_license: bool) -> miette::Result<()> {
+    eprintln!("Generating Rust recipe for {}", package);
+    let client = NetworkSettings::default().client().into_diagnostic()?;
+    let response: CrateResponse = fetch_json(
+        &client,
+        &format!("https://crates.io/api/v1/crates/{}", package),
+    )
+    .await?
+    .ok_or_else(|| miette::miette!("Crate {} not found on crates.io", package))?;
+    let info = response.krate;
+
+    let version_num = info
+        .max_stable_version
+        .as_ref()
+        .unwrap_or(&info.max_version);
+    let version = response
+        .versions
+        .iter()
+        .find(|version| &version.num == version_num && !version.yanked)
+        .ok_or_else(|| miette::miette!("No release of {} found on crates.io", package))?;
+    if version.bin_names.is_empty() {
+        return Err(miette::miette!(
+            "Crate {} has no binaries, only crates with binaries can be packaged",
+            package
+        ));
+    }
+
+    let mut recipe = serialize::Recipe::default();
+    recipe
+        .context
+        .insert("version".to_string(), version.num.clone());
+    recipe.package.name = info.name.to_lowercase().replace('_', "-");
+    recipe.package.version = "${{ version }}".to_string();
+
+    let crate_url = format!(
+        "https://static.crates.io/crates/{0}/{0}-{1}.crate",
+        info.name, version.num
+    );
+    recipe.source.push(SourceElement {
+        url: crate_url.replace(&version.num, "${{ version }}"),
+        sha256: Some(version.checksum.clone()),
+        md5: None,
+    });
+
+    recipe.build.number = 0;
+    recipe.build.script = [
+        "cargo-bundle-licenses --format yaml --output $SRC_DIR/THIRDPARTY.yml",
+        "cargo install --locked --no-track --bins --root $PREFIX --path .",
+    ]
+    .join("\n");
+
+    recipe.requirements.build = vec![
+        "${{ compiler('rust') }}".to_string(),
+        "${{ compiler('c') }}".to_string(),
+        "${{ stdlib('c') }}".to_string(),
+        "cargo-bundle-licenses".to_string(),
+    ];
+
+    let dependencies: DependenciesResponse = fetch_json(
+        &client,
+        &format!(
+            "https://crates.io/api/v1/crates/{}/{}/dependencies",
+            info.name, version.num
+        ),
+    )
+    .await?
+    .ok_or_else(|| miette::miette!("No dependencies found for {}", package))?;
+    for dependency in dependencies
+        .dependencies
+        .iter()
+        .filter(|dependency| dependency.kind != "dev" && !dependency.optional)
+    {
+        if dependency.crate_id == "cmake" {
+            recipe.requirements.build.push("cmake".to_string());
+        } else if dependency.crate_id == "pkg-config" {
+            recipe.requirements.build.push("pkg-config".to_string());
+        } else if let Some(package) = sys_crate_package(&dependency.crate_id) {
+            recipe.requirements.host.push(package.to_string());
+        }
+    }
+    recipe.requirements.build = recipe.requirements.build.into_iter().unique().collect();
+    recipe.requirements.host = recipe.requirements.host.into_iter().unique().collect();
+
+    recipe.tests.push(serialize::Test {
+        script: version
+            .bin_names
+            .iter()
+            .map(|bin| format!("{} --help", bin))
+            .collect(),
+        ..Default::default()
+    });
+
+    recipe.about.homepage = info.homepage.clone();
+    recipe.about.repository = info.repository.clone();
+    recipe.about.documentation = info.documentation.clone();
+    recipe.about.summary = info.description.as_ref().map(|d| d.trim().to_string());
+    recipe.about.license = version.license.as_deref().map(map_license);
+
+    // the license files are read from the `.crate` (a gzipped tarball)
+    let tempdir = tempfile::tempdir().into_diagnostic()?;
+    let crate_path = tempdir
+        .path()
+        .join(format!("{}-{}.tar.gz", info.name, version.num));
+    download_crate(&client, &crate_url, &crate_path)
+        .await
+        .wrap_err("failed to download the crate")?;
+    let mut license_files = license::sdist_license_files(&crate_path)?;
+    if license_files.is_empty() && fetch_license {
+        let urls = [&info.repository, &info.homepage]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if let Some(upstream) = license::fetch_github_license(
+            &client,
+            &urls,
+            &version.num,
+            recipe.about.license.as_deref(),
+        )
+        .await?
+        {
+            recipe.source.push(upstream.source);
+            license_files.push(upstream.file_name);
+            recipe.about.license_comments = upstream.comments;
+        } else {
+            eprintln!("No GitHub repository found to fetch the license file from");
+        }
+    } else if license_files.is_empty() {
+        eprintln!("The crate does not contain a license file, use `--fetch-license` to fetch it from GitHub");
+    }
+    // the licenses of the dependencies are collected by `cargo-bundle-licenses`
+    license_files.push("THIRDPARTY.yml".to_string());
+    recipe.about.license_file = license_files;
+
+    print!("{}", recipe);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cargo_helpers() {
+        assert_eq!(map_license("MIT/Apache-2.0"), "MIT OR Apache-2.0");
+        assert_eq!(map_license("MIT OR Apache-2.0"), "MIT OR Apache-2.0");
+        assert_eq!(sys_crate_package("openssl-sys"), Some("openssl"));
+        assert_eq!(sys_crate_package("serde"), None);
+    }
+}
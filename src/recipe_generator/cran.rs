@@ -2,7 +2,13 @@ use itertools::Itertools;
 use miette::IntoDiagnostic;
 use serde::{Deserialize, Serialize};
 
-use crate::recipe_generator::serialize::{self, SourceElement};
+use crate::{
+    recipe_generator::{
+        license,
+        serialize::{self, SourceElement},
+    },
+    tool_configuration::NetworkSettings,
+};
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PackageInfo {
@@ -15,6 +21,8 @@ pub struct PackageInfo {
     pub License: String,
     pub URL: String,
     pub NeedsCompilation: String,
+    #[serde(default)]
+    pub SystemRequirements: Option<String>,
     pub Packaged: Packaged,
     pub Repository: String,
     #[serde(rename = "Date/Publication")]
@@ -95,6 +103,72 @@ fn map_license(license: &str) -> String {
     res
 }
 
+/// Known system requirements (matched case-insensitively) and their conda packages.
+const SYSTEM_REQUIREMENTS: [(&str, &str); 22] = [
+    ("gnu make", "make"),
+    ("libxml2", "libxml2"),
+    ("openssl", "openssl"),
+    ("libcurl", "libcurl"),
+    ("zlib", "zlib"),
+    ("gdal", "libgdal"),
+    ("geos", "geos"),
+    ("proj", "proj"),
+    ("gmp", "gmp"),
+    ("mpfr", "mpfr"),
+    ("glpk", "glpk"),
+    ("fftw", "fftw"),
+    ("gsl", "gsl"),
+    ("hdf5", "hdf5"),
+    ("netcdf", "libnetcdf"),
+    ("libpng", "libpng"),
+    ("jpeg", "libjpeg-turbo"),
+    ("cairo", "cairo"),
+    ("freetype", "freetype"),
+    ("sqlite", "libsqlite"),
+    ("pandoc", "pandoc"),
+    ("udunits", "udunits2"),
+];
+
+/// The requirements from the `SystemRequirements` field: the conda packages and
+/// compilers that are known, and the entries that could not be mapped.
+#[derive(Debug, Default, PartialEq)]
+struct SystemRequirements {
+    build: Vec<String>,
+    host: Vec<String>,
+    unknown: Vec<String>,
+}
+
+fn map_system_requirements(system_requirements: &str) -> SystemRequirements {
+    let mut result = SystemRequirements::default();
+    for requirement in system_requirements
+        .split([',', ';', '\n'])
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+    {
+        let lowercase = requirement.to_lowercase();
+        if lowercase.starts_with("c++") || lowercase.starts_with("c99") {
+            // the C++ standard is provided by the compiler
+            continue;
+        } else if lowercase.contains("fortran") {
+            result.build.push("${{ compiler('fortran') }}".to_string());
+        } else if lowercase.contains("cargo") || lowercase.contains("rustc") {
+            result.build.push("${{ compiler('rust') }}".to_string());
+        } else if let Some((_, package)) = SYSTEM_REQUIREMENTS
+            .iter()
+            .find(|(name, _)| lowercase.contains(name))
+        {
+            if *package == "make" || *package == "pandoc" {
+                result.build.push(package.to_string());
+            } else {
+                result.host.push(package.to_string());
+            }
+        } else {
+            result.unknown.push(requirement.to_string());
+        }
+    }
+    result
+}
+
 fn format_r_package(package: &str, version: Option<&String>) -> String {
     let mut res = format!("r-{}", package.to_lowercase());
     if let Some(version) = version {
@@ -105,7 +179,7 @@ fn format_r_package(package: &str, version: Option<&String>) -> String {
     res
 }
 
-pub async fn generate_r_recipe(package: &str) -> miette::Result<()> {
+pub async fn generate_r_recipe(package: &str, fetch_license: bool) -> miette::Result<()> {
     eprintln!("Generating R recipe for {}", package);
     let package_info = reqwest::get(&format!(
         "https://cran.r-universe.dev/api/packages/{}",
@@ -151,17 +225,6 @@ pub async fn generate_r_recipe(package: &str) -> miette::Result<()> {
                 .run
                 .push(format_r_package(&dep.package, dep.version.as_ref()));
         }
-        if dep.role == "LinkingTo" {
-            recipe
-                .requirements
-                .build
-                .push("${{ compiler('c') }}".to_string());
-            recipe
-                .requirements
-                .build
-                .push("${{ compiler('cxx') }}".to_string());
-            recipe.requirements.build.push("make".to_string());
-        }
         if dep.role == "Suggests" {
             recipe.requirements.run.push(format!(
                 "SUGGEST {}",
@@ -170,6 +233,36 @@ pub async fn generate_r_recipe(package: &str) -> miette::Result<()> {
         }
     }
 
+    // packages with compiled code (or `LinkingTo` headers) need the compilers
+    let needs_compilation = package_info.NeedsCompilation == "yes"
+        || package_info
+            ._dependencies
+            .iter()
+            .any(|dep| dep.role == "LinkingTo");
+    if needs_compilation {
+        recipe.requirements.build.extend(
+            [
+                "${{ compiler('c') }}",
+                "${{ compiler('cxx') }}",
+                "${{ stdlib('c') }}",
+                "make",
+            ]
+            .map(String::from),
+        );
+    }
+
+    if let Some(system_requirements) = &package_info.SystemRequirements {
+        let mapped = map_system_requirements(system_requirements);
+        recipe.requirements.build.extend(mapped.build);
+        recipe.requirements.host.extend(mapped.host);
+        recipe.requirements.host.extend(
+            mapped
+                .unknown
+                .into_iter()
+                .map(|requirement| format!("SYSREQ {}", requirement)),
+        );
+    }
+
     // make requirements unique
     recipe.requirements.host = recipe.requirements.host.into_iter().unique().collect();
     recipe.requirements.build = recipe.requirements.build.into_iter().unique().collect();
@@ -184,14 +277,45 @@ pub async fn generate_r_recipe(package: &str) -> miette::Result<()> {
         recipe.about.documentation = Some(package_info._pkgdocs.clone());
     }
 
-    // ??
-    // recipe.about.license_file = Some("LICENSE".to_string());
+    // packages with a custom license ship it as `file LICENSE`, the standard licenses
+    // (e.g. GPL-3) are not part of the package
+    if let Some(file) = ["LICENSE", "LICENCE"]
+        .into_iter()
+        .find(|file| package_info.License.contains(&format!("file {file}")))
+    {
+        recipe.about.license_file = vec![file.to_string()];
+    } else if fetch_license {
+        let client = NetworkSettings::default().client().into_diagnostic()?;
+        let urls = std::iter::once(package_info._upstream.as_str())
+            .chain(package_info.URL.split([',', ' ', '\n']))
+            .filter(|url| !url.is_empty())
+            .collect::<Vec<_>>();
+        if let Some(upstream) = license::fetch_github_license(
+            &client,
+            &urls,
+            &package_info.Version,
+            recipe.about.license.as_deref(),
+        )
+        .await?
+        {
+            recipe.source.push(upstream.source);
+            recipe.about.license_file = vec![upstream.file_name];
+            recipe.about.license_comments = upstream.comments;
+        } else {
+            eprintln!("No GitHub repository found to fetch the license file from");
+        }
+    }
 
     let recipe = format!("{}", recipe);
 
     let mut final_recipe = String::new();
     for line in recipe.lines() {
-        if line.contains("SUGGEST") {
+        if line.contains("SYSREQ") {
+            final_recipe.push_str(&format!(
+                "{}  # system requirement, map to a conda package\n",
+                line.replace(" - SYSREQ ", " # - ")
+            ));
+        } else if line.contains("SUGGEST") {
             final_recipe.push_str(&format!(
                 "{}  # suggested\n",
                 line.replace(" - SUGGEST", " # - ")
@@ -205,3 +329,23 @@ pub async fn generate_r_recipe(package: &str) -> miette::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_system_requirements() {
+        let mapped = map_system_requirements("GNU make, C++17, libxml2 (>= 2.6.3), foo-lib");
+        assert_eq!(
+            mapped,
+            SystemRequirements {
+                build: vec!["make".to_string()],
+                host: vec!["libxml2".to_string()],
+                unknown: vec!["foo-lib".to_string()],
+            }
+        );
+        let mapped = map_system_requirements("Cargo (Rust's package manager), rustc");
+        assert_eq!(mapped.build, vec!["${{ compiler('rust') }}"; 2]);
+    }
+}
@@ -0,0 +1,241 @@
+//! Finding the license files for generated recipes. Source distributions (especially
+//! PyPI sdists) often do not contain the license file, in which case it can be fetched
+//! from the upstream GitHub repository at the tag of the release.
+
+use std::path::Path;
+
+use miette::{Context, IntoDiagnostic};
+use rattler_digest::compute_bytes_digest;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::recipe_generator::serialize::SourceElement;
+
+/// The file name prefixes of license files, compared case-insensitively.
+const LICENSE_FILE_PREFIXES: [&str; 4] = ["license", "licence", "copying", "copyright"];
+
+/// Whether a file name looks like a license file (e.g. `LICENSE`, `LICENSE.txt`,
+/// `COPYING.md` or `LICENSE-MIT`).
+fn is_license_file(name: &str) -> bool {
+    let name = name.to_lowercase();
+    LICENSE_FILE_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// The license files in the top-level directory of a source distribution (a `.tar.gz`
+/// or `.zip` archive with a single top-level directory).
+pub fn sdist_license_files(path: &Path) -> miette::Result<Vec<String>> {
+    let file = fs_err::File::open(path).into_diagnostic()?;
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let paths = if file_name.ends_with(".zip") {
+        let archive = zip::ZipArchive::new(file).into_diagnostic()?;
+        archive.file_names().map(str::to_string).collect::<Vec<_>>()
+    } else {
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        let mut paths = Vec::new();
+        for entry in archive.entries().into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            paths.push(
+                entry
+                    .path()
+                    .into_diagnostic()?
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+        paths
+    };
+
+    let mut license_files = paths
+        .iter()
+        .filter_map(|path| {
+            let mut components = path.trim_end_matches('/').split('/');
+            let _top_level = components.next()?;
+            let name = components.next()?;
+            (components.next().is_none() && is_license_file(name)).then(|| name.to_string())
+        })
+        .collect::<Vec<_>>();
+    license_files.sort();
+    license_files.dedup();
+    Ok(license_files)
+}
+
+/// The owner and name of a GitHub repository from a URL of the repository (or of a page
+/// in the repository).
+fn github_repository(url: &str) -> Option<(String, String)> {
+    let url = url::Url::parse(url.trim()).ok()?;
+    if !matches!(url.host_str()?, "github.com" | "www.github.com") {
+        return None;
+    }
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+    let owner = segments.next()?;
+    let repo = segments.next()?.trim_end_matches(".git");
+    Some((owner.to_string(), repo.to_string()))
+}
+
+/// The response of the GitHub API for the license of a repository.
+#[derive(Deserialize)]
+struct GithubLicenseFile {
+    path: String,
+    download_url: String,
+    license: Option<GithubLicense>,
+}
+
+#[derive(Deserialize)]
+struct GithubLicense {
+    spdx_id: Option<String>,
+}
+
+/// A license file that was found in the upstream repository.
+pub struct UpstreamLicense {
+    /// The source that downloads the license file into the work directory
+    pub source: SourceElement,
+    /// The name of the license file in the work directory
+    pub file_name: String,
+    /// Everything that should be double-checked by the recipe author
+    pub comments: Vec<String>,
+}
+
+/// Request the license file of a repository at the given ref (or the default branch).
+async fn github_license_file(
+    client: &reqwest::Client,
+    owner: &str,
+    repo: &str,
+    git_ref: Option<&str>,
+) -> miette::Result<Option<GithubLicenseFile>> {
+    let mut request = client.get(format!(
+        "https://api.github.com/repos/{owner}/{repo}/license"
+    ));
+    if let Some(git_ref) = git_ref {
+        request = request.query(&[("ref", git_ref)]);
+    }
+    let response = request
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await
+        .into_diagnostic()?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let file = response
+        .error_for_status()
+        .into_diagnostic()?
+        .json::<GithubLicenseFile>()
+        .await
+        .into_diagnostic()?;
+    Ok(Some(file))
+}
+
+/// Fetch the license file from the first GitHub repository among the given URLs, at the
+/// tag of the version (`v1.2.3` or `1.2.3`). Falls back to the default branch if there
+/// is no such tag. `license` is the license of the package metadata, a mismatch with
+/// the license that GitHub detects is noted in the comments.
+pub async fn fetch_github_license(
+    client: &reqwest::Client,
+    urls: &[&str],
+    version: &str,
+    license: Option<&str>,
+) -> miette::Result<Option<UpstreamLicense>> {
+    let Some((owner, repo)) = urls.iter().find_map(|url| github_repository(url)) else {
+        return Ok(None);
+    };
+    eprintln!("Fetching the license file from github.com/{owner}/{repo}");
+
+    let mut comments = Vec::new();
+    let tags = [format!("v{version}"), version.to_string()];
+    let mut file = None;
+    for tag in &tags {
+        file = github_license_file(client, &owner, &repo, Some(tag)).await?;
+        if file.is_some() {
+            break;
+        }
+    }
+    let file = match file {
+        Some(file) => file,
+        None => {
+            let Some(file) = github_license_file(client, &owner, &repo, None).await? else {
+                return Ok(None);
+            };
+            comments.push(format!(
+                "no tag `{}` or `{}` found in github.com/{owner}/{repo}, the license file is from the default branch",
+                tags[0], tags[1]
+            ));
+            file
+        }
+    };
+
+    let bytes = client
+        .get(&file.download_url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .into_diagnostic()
+        .wrap_err("failed to download the license file")?
+        .bytes()
+        .await
+        .into_diagnostic()?;
+    let sha256 = format!("{:x}", compute_bytes_digest::<Sha256>(&bytes));
+
+    match file.license.and_then(|license| license.spdx_id) {
+        Some(spdx_id) if spdx_id != "NOASSERTION" => {
+            if let Some(license) = license {
+                if !license.to_lowercase().contains(&spdx_id.to_lowercase()) {
+                    comments.push(format!(
+                        "GitHub detects `{}` as {spdx_id}, but the package metadata says `{license}`",
+                        file.path
+                    ));
+                }
+            }
+        }
+        _ => comments.push(format!(
+            "GitHub could not detect the license of `{}`, check it manually",
+            file.path
+        )),
+    }
+
+    let file_name = file
+        .path
+        .rsplit('/')
+        .next()
+        .unwrap_or(&file.path)
+        .to_string();
+
+    Ok(Some(UpstreamLicense {
+        source: SourceElement {
+            url: file.download_url,
+            sha256: Some(sha256),
+            md5: None,
+        },
+        file_name,
+        comments,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_license_helpers() {
+        assert!(is_license_file("LICENSE"));
+        assert!(is_license_file("License.txt"));
+        assert!(is_license_file("COPYING.md"));
+        assert!(!is_license_file("README.md"));
+
+        assert_eq!(
+            github_repository("https://github.com/prefix-dev/rattler-build.git"),
+            Some(("prefix-dev".to_string(), "rattler-build".to_string()))
+        );
+        assert_eq!(
+            github_repository("https://github.com/prefix-dev/rattler-build/issues"),
+            Some(("prefix-dev".to_string(), "rattler-build".to_string()))
+        );
+        assert_eq!(github_repository("https://gitlab.com/foo/bar"), None);
+        assert_eq!(github_repository("https://github.com/foo"), None);
+    }
+}
@@ -1,11 +1,14 @@
-//! Module for generating recipes for Python (PyPI) or R (CRAN) packages
+//! Module for generating recipes for Python (PyPI), R (CRAN) or Rust (crates.io) packages
 use clap::Parser;
 
+mod cargo;
 mod cran;
 
+mod license;
 mod pypi;
 mod serialize;
 
+use cargo::generate_cargo_recipe;
 use cran::generate_r_recipe;
 
 use self::pypi::generate_pypi_recipe;
@@ -17,6 +20,8 @@ pub enum Source {
     Pypi,
     /// Generate a recipe for an R package from CRAN
     Cran,
+    /// Generate a recipe for a Rust binary from crates.io
+    Cargo,
 }
 
 /// Options for generating a recipe
@@ -27,13 +32,19 @@ pub struct GenerateRecipeOpts {
     pub source: Source,
     /// Name of the package to generate
     pub package: String,
+
+    /// Fetch the license file from the upstream GitHub repository (at the tag of the
+    /// version) if the source distribution does not contain one
+    #[arg(long)]
+    pub fetch_license: bool,
 }
 
 /// Generate a recipe for a package
 pub async fn generate_recipe(args: GenerateRecipeOpts) -> miette::Result<()> {
     match args.source {
-        Source::Pypi => generate_pypi_recipe(&args.package).await?,
-        Source::Cran => generate_r_recipe(&args.package).await?,
+        Source::Pypi => generate_pypi_recipe(&args.package, args.fetch_license).await?,
+        Source::Cran => generate_r_recipe(&args.package, args.fetch_license).await?,
+        Source::Cargo => generate_cargo_recipe(&args.package, args.fetch_license).await?,
     }
 
     Ok(())
@@ -1,21 +1,20 @@
+//! Generating recipes for Python packages from the metadata of the PyPI JSON API and
+//! the contents of the source distribution.
+
 use async_once_cell::OnceCell;
+use indexmap::IndexMap;
+use itertools::Itertools;
 use miette::{IntoDiagnostic, WrapErr};
-use rattler_installs_packages::python_env::Pep508EnvMakers;
-use rattler_installs_packages::resolve::solve_options::ResolveOptions;
-use rattler_installs_packages::types::{ArtifactFromSource, Requirement, VersionOrUrl};
-use rattler_installs_packages::wheel_builder::WheelBuilder;
-use rattler_installs_packages::{
-    artifacts::SDist,
-    index::{ArtifactRequest, PackageDb, PackageSources},
-    types::{ArtifactName, NormalizedPackageName},
-};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
 use std::path::Path;
-use std::sync::Arc;
-use std::{collections::HashMap, str::FromStr};
 use tokio::io::AsyncWriteExt;
 
-use crate::recipe_generator::serialize;
+use crate::{
+    recipe_generator::{license, serialize},
+    tool_configuration::NetworkSettings,
+};
 
 #[derive(Deserialize)]
 struct CondaPyPiNameMapping {
@@ -23,7 +22,8 @@ struct CondaPyPiNameMapping {
     pypi_name: String,
 }
 
-/// Downloads and caches the conda-forge conda-to-pypi name mapping.
+/// Downloads and caches the conda-forge conda-to-pypi name mapping, keyed by the
+/// (normalized) PyPI name.
 pub async fn conda_pypi_name_mapping() -> miette::Result<&'static HashMap<String, String>> {
     static MAPPING: OnceCell<HashMap<String, String>> = OnceCell::new();
     MAPPING.get_or_try_init(async {
@@ -37,35 +37,135 @@ pub async fn conda_pypi_name_mapping() -> miette::Result<&'static HashMap<String
             .context("failed to parse pypi name mapping")?;
         let mapping_by_name: HashMap<_, _> = mapping
             .into_iter()
-            .map(|m| (m.conda_name, m.pypi_name))
+            .map(|m| (normalize_name(&m.pypi_name), m.conda_name))
             .collect();
         Ok(mapping_by_name)
     }).await
 }
 
-async fn download_sdist(url: &url::Url, dest: &Path) -> miette::Result<()> {
-    let response = reqwest::get(url.clone()).await.into_diagnostic()?;
+/// The response of the PyPI JSON API for a release.
+#[derive(Deserialize)]
+struct PypiRelease {
+    info: PypiInfo,
+    urls: Vec<PypiUrl>,
+}
 
-    let mut file = tokio::fs::File::create(&dest).await.into_diagnostic()?;
+#[derive(Deserialize)]
+struct PypiInfo {
+    name: String,
+    version: String,
+    summary: Option<String>,
+    home_page: Option<String>,
+    license: Option<String>,
+    license_expression: Option<String>,
+    requires_python: Option<String>,
+    requires_dist: Option<Vec<String>>,
+    project_urls: Option<HashMap<String, String>>,
+}
 
-    let bytes = response.bytes().await.into_diagnostic()?;
-    file.write_all(&bytes).await.into_diagnostic()?;
+#[derive(Deserialize)]
+struct PypiUrl {
+    packagetype: String,
+    filename: String,
+    url: String,
+    digests: PypiDigests,
+}
 
-    Ok(())
+#[derive(Deserialize)]
+struct PypiDigests {
+    sha256: String,
+}
+
+/// The parts of `pyproject.toml` that are used for the recipe.
+#[derive(Default, Deserialize)]
+struct PyProjectToml {
+    #[serde(rename = "build-system")]
+    build_system: Option<BuildSystem>,
+    project: Option<Project>,
+}
+
+#[derive(Deserialize)]
+struct BuildSystem {
+    #[serde(default)]
+    requires: Vec<String>,
+    #[serde(rename = "build-backend")]
+    build_backend: Option<String>,
 }
 
-async fn pypi_requirement(req: &Requirement) -> miette::Result<String> {
-    let mut res = req.name.clone().to_lowercase();
+#[derive(Deserialize)]
+struct Project {
+    #[serde(default)]
+    scripts: IndexMap<String, String>,
+}
 
-    // check if the name is in the conda-forge pypi name mapping
+/// Normalize a PyPI package name (PEP 503).
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase().replace(['_', '.'], "-")
+}
+
+/// A requirement of `requires-dist` or `build-system.requires`.
+#[derive(Debug, PartialEq)]
+struct PythonRequirement {
+    name: String,
+    version: Option<String>,
+    marker: Option<String>,
+}
+
+impl PythonRequirement {
+    /// Parse a PEP 508 requirement, e.g. `requests[socks] (>=2.0) ; python_version < "3.8"`.
+    fn parse(requirement: &str) -> Option<Self> {
+        let (spec, marker) = match requirement.split_once(';') {
+            Some((spec, marker)) => (spec, Some(marker.trim().to_string())),
+            None => (requirement, None),
+        };
+        let spec = spec.trim();
+        let name_end = spec
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+            .unwrap_or(spec.len());
+        let name = &spec[..name_end];
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut rest = spec[name_end..].trim_start();
+        // extras of the dependency are not available in conda
+        if rest.starts_with('[') {
+            rest = rest.split_once(']').map_or("", |(_, rest)| rest);
+        }
+        // direct references (`name @ url`) cannot be expressed as a conda dependency
+        let version = if rest.trim_start().starts_with('@') {
+            String::new()
+        } else {
+            rest.chars()
+                .filter(|c| !c.is_whitespace() && !matches!(c, '(' | ')'))
+                .collect::<String>()
+        };
+
+        Some(Self {
+            name: name.to_string(),
+            version: (!version.is_empty()).then_some(version),
+            marker: marker.filter(|marker| !marker.is_empty()),
+        })
+    }
+
+    /// Whether the requirement is only needed for an optional extra of the package.
+    fn is_extra(&self) -> bool {
+        self.marker
+            .as_deref()
+            .is_some_and(|marker| marker.contains("extra"))
+    }
+}
+
+/// The conda dependency for a Python requirement. Requirements with environment markers
+/// are marked so that they are commented out in the recipe.
+async fn pypi_requirement(req: &PythonRequirement) -> miette::Result<String> {
     let mapping = conda_pypi_name_mapping()
         .await
         .wrap_err("failed to get conda-pypi name mapping")?;
-    if let Some(conda_name) = mapping.get(&req.name) {
-        res = conda_name.clone();
-    }
+    let name = normalize_name(&req.name);
+    let mut res = mapping.get(&name).cloned().unwrap_or(name);
 
-    if let Some(VersionOrUrl::VersionSpecifier(version)) = &req.version_or_url {
+    if let Some(version) = &req.version {
         res.push_str(&format!(" {}", version));
     }
 
@@ -75,140 +175,362 @@ async fn pypi_requirement(req: &Requirement) -> miette::Result<String> {
     Ok(res)
 }
 
-pub async fn generate_pypi_recipe(package: &str) -> miette::Result<()> {
-    let client = reqwest::Client::new();
-    let client_with_middlewares = reqwest_middleware::ClientBuilder::new(client).build();
-    let package_sources =
-        PackageSources::from(url::Url::parse("https://pypi.org/simple/").unwrap());
-    let tempdir = tempfile::tempdir().into_diagnostic()?;
-    let artifact_request = ArtifactRequest::FromIndex(NormalizedPackageName::from_str(package)?);
-    let package_db = Arc::new(PackageDb::new(
-        package_sources,
-        client_with_middlewares,
-        tempdir.path(),
-    )?);
-    let artifacts = package_db.available_artifacts(artifact_request).await?;
-
-    // find first artifact or bail out
-    let first_artifact = artifacts
-        .into_iter()
-        .next()
-        .ok_or_else(|| miette::miette!("No package artifacts found for {}", package))?;
-
-    let source_dist = first_artifact
-        .1
-        .iter()
-        .find(|artifact| matches!(artifact.filename, ArtifactName::SDist(_)))
-        .ok_or_else(|| miette::miette!("No source distribution found for {}", package))?;
+/// The build backend of a package, detected from `build-system.build-backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildBackend {
+    Setuptools,
+    Hatchling,
+    Flit,
+    Poetry,
+    Pdm,
+    Maturin,
+    ScikitBuildCore,
+    MesonPython,
+    Other,
+}
 
-    let env_markers = Arc::new(Pep508EnvMakers::from_env().await.unwrap().0);
-    let wheel_builder = WheelBuilder::new(
-        package_db.clone(),
-        env_markers,
-        None,
-        ResolveOptions::default(),
-    )
-    .unwrap();
+impl BuildBackend {
+    /// Detect the build backend. Packages without `build-backend` use setuptools.
+    fn detect(build_backend: Option<&str>) -> Self {
+        let Some(backend) = build_backend else {
+            return Self::Setuptools;
+        };
+        match backend.split(['.', ':']).next().unwrap_or_default() {
+            "setuptools" => Self::Setuptools,
+            "hatchling" => Self::Hatchling,
+            "flit_core" => Self::Flit,
+            "poetry" => Self::Poetry,
+            "pdm" => Self::Pdm,
+            "maturin" => Self::Maturin,
+            "scikit_build_core" => Self::ScikitBuildCore,
+            "mesonpy" => Self::MesonPython,
+            _ => Self::Other,
+        }
+    }
 
-    let metadata = package_db
-        .get_metadata(first_artifact.1, Some(&wheel_builder))
-        .await?
-        .ok_or_else(|| miette::miette!("No metadata found for {}", package))?;
+    /// The conda package of the backend, for packages that do not list their build
+    /// requirements.
+    fn package(&self) -> Option<&'static str> {
+        match self {
+            Self::Setuptools => Some("setuptools"),
+            Self::Hatchling => Some("hatchling"),
+            Self::Flit => Some("flit-core"),
+            Self::Poetry => Some("poetry-core"),
+            Self::Pdm => Some("pdm-backend"),
+            Self::Maturin => Some("maturin"),
+            Self::ScikitBuildCore => Some("scikit-build-core"),
+            Self::MesonPython => Some("meson-python"),
+            Self::Other => None,
+        }
+    }
 
-    let mut recipe = serialize::Recipe::default();
-    recipe.package.name = metadata.1.name.as_str().to_string();
-    recipe.package.version = metadata.1.version.to_string();
+    /// The build requirements (compilers and build tools) of backends that build
+    /// extension modules.
+    fn build_requirements(&self) -> &'static [&'static str] {
+        match self {
+            Self::Maturin => &[
+                "${{ compiler('c') }}",
+                "${{ stdlib('c') }}",
+                "${{ compiler('rust') }}",
+            ],
+            Self::ScikitBuildCore => &[
+                "${{ compiler('c') }}",
+                "${{ stdlib('c') }}",
+                "${{ compiler('cxx') }}",
+                "cmake",
+                "ninja",
+            ],
+            Self::MesonPython => &[
+                "${{ compiler('c') }}",
+                "${{ stdlib('c') }}",
+                "${{ compiler('cxx') }}",
+                "meson",
+                "ninja",
+            ],
+            _ => &[],
+        }
+    }
+}
 
-    let hash_string = source_dist
-        .hashes
-        .as_ref()
-        .and_then(|h| h.sha256)
-        .map(|h| format!("{:x}", h));
+/// The contents of a source distribution that are needed for the recipe.
+#[derive(Default)]
+struct SDistContents {
+    /// The paths of all files, without the top-level directory
+    files: Vec<String>,
+    pyproject_toml: Option<String>,
+    setup_py: Option<String>,
+}
 
-    let url = source_dist.url.to_string();
-    let url = url.split_once('#').map(|(url, _)| url).unwrap_or(&url);
+impl SDistContents {
+    /// Read a `.tar.gz` or `.zip` source distribution.
+    fn read(path: &Path) -> miette::Result<Self> {
+        let mut contents = Self::default();
+        let mut add = |path: &str, reader: &mut dyn Read| -> miette::Result<()> {
+            let Some((_, path)) = path.split_once('/') else {
+                return Ok(());
+            };
+            if path.is_empty() || path.ends_with('/') {
+                return Ok(());
+            }
+            let text = match path {
+                "pyproject.toml" => &mut contents.pyproject_toml,
+                "setup.py" => &mut contents.setup_py,
+                _ => {
+                    contents.files.push(path.to_string());
+                    return Ok(());
+                }
+            };
+            let mut content = String::new();
+            reader.read_to_string(&mut content).into_diagnostic()?;
+            *text = Some(content);
+            contents.files.push(path.to_string());
+            Ok(())
+        };
+
+        let file = fs_err::File::open(path).into_diagnostic()?;
+        if path.extension().is_some_and(|ext| ext == "zip") {
+            let mut archive = zip::ZipArchive::new(file).into_diagnostic()?;
+            for idx in 0..archive.len() {
+                let mut entry = archive.by_index(idx).into_diagnostic()?;
+                let name = entry.name().to_string();
+                add(&name, &mut entry)?;
+            }
+        } else {
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+            for entry in archive.entries().into_diagnostic()? {
+                let mut entry = entry.into_diagnostic()?;
+                let name = entry
+                    .path()
+                    .into_diagnostic()?
+                    .to_string_lossy()
+                    .to_string();
+                add(&name, &mut entry)?;
+            }
+        }
+        Ok(contents)
+    }
 
-    recipe.source.push(serialize::SourceElement {
-        url: url.to_string(),
-        sha256: hash_string,
-        md5: None,
-    });
+    /// Whether the package builds extension modules with setuptools (C or Cython).
+    fn has_setuptools_extensions(&self) -> bool {
+        self.setup_py
+            .as_deref()
+            .is_some_and(|setup_py| setup_py.contains("ext_modules"))
+            || self.files.iter().any(|file| file.ends_with(".pyx"))
+    }
 
-    // find package build time deps...
-    let tempdir = tempfile::tempdir().into_diagnostic()?;
+    /// The top-level modules of the package, i.e. the packages in the root or in `src/`
+    /// (without tests and documentation).
+    fn top_level_modules(&self) -> Vec<String> {
+        const IGNORED: [&str; 6] = ["test", "tests", "testing", "docs", "doc", "examples"];
+        self.files
+            .iter()
+            .filter_map(|file| {
+                let file = file.strip_prefix("src/").unwrap_or(file);
+                let (module, rest) = file.split_once('/')?;
+                (rest == "__init__.py" && !IGNORED.contains(&module)).then(|| module.to_string())
+            })
+            .sorted()
+            .dedup()
+            .collect()
+    }
+}
+
+async fn download_sdist(url: &url::Url, dest: &Path) -> miette::Result<()> {
+    let response = reqwest::get(url.clone()).await.into_diagnostic()?;
+
+    let mut file = tokio::fs::File::create(&dest).await.into_diagnostic()?;
+
+    let bytes = response.bytes().await.into_diagnostic()?;
+    file.write_all(&bytes).await.into_diagnostic()?;
+
+    Ok(())
+}
 
-    // downlaod the sdist
-    let filename = source_dist.url.to_string();
-    let filename = filename.split('/').last().unwrap();
-    // split off everything after the #
-    let filename = filename
-        .split_once('#')
-        .map(|(fname, _)| fname)
-        .unwrap_or(filename);
-
-    let sdist_path = tempdir.path().join(filename);
-    download_sdist(&source_dist.url, &sdist_path)
+/// Fetch the metadata of the latest release of a package from the PyPI JSON API.
+async fn fetch_pypi_release(
+    client: &reqwest::Client,
+    package: &str,
+) -> miette::Result<PypiRelease> {
+    let response = client
+        .get(format!("https://pypi.org/pypi/{}/json", package))
+        .send()
         .await
-        .wrap_err("failed to download sdist")?;
+        .into_diagnostic()?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(miette::miette!("Package {} not found on PyPI", package));
+    }
+    response
+        .error_for_status()
+        .into_diagnostic()?
+        .json::<PypiRelease>()
+        .await
+        .into_diagnostic()
+        .wrap_err("failed to parse the PyPI metadata")
+}
 
-    // get the metadata
-    let wheel_metadata = metadata.1;
+pub async fn generate_pypi_recipe(package: &str, fetch_license: bool) -> miette::Result<()> {
+    let client = NetworkSettings::default().client().into_diagnostic()?;
+    let release = fetch_pypi_release(&client, package).await?;
+    let info = release.info;
 
-    let sdist = SDist::from_path(
-        &sdist_path,
-        &NormalizedPackageName::from(wheel_metadata.name),
-    )
-    .unwrap();
+    let source_dist = release
+        .urls
+        .iter()
+        .find(|url| url.packagetype == "sdist")
+        .ok_or_else(|| miette::miette!("No source distribution found for {}", package))?;
 
-    let pyproject_toml = sdist.read_pyproject_toml().ok();
-    let (_, mut pkg_info) = sdist.read_package_info().into_diagnostic()?;
+    let mut recipe = serialize::Recipe::default();
+    let name = normalize_name(&info.name);
+    recipe
+        .context
+        .insert("version".to_string(), info.version.clone());
+    recipe.package.name = name.clone();
+    recipe.package.version = "${{ version }}".to_string();
+
+    // the `packages/source` url redirects to the sdist, and can be templated with the version
+    recipe.source.push(serialize::SourceElement {
+        url: format!(
+            "https://pypi.org/packages/source/{}/{}/{}",
+            info.name.chars().next().unwrap_or_default(),
+            info.name,
+            source_dist
+                .filename
+                .replace(&info.version, "${{ version }}")
+        ),
+        sha256: Some(source_dist.digests.sha256.clone()),
+        md5: None,
+    });
 
-    if let Some(pyproject_toml) = pyproject_toml {
-        if let Some(build_system) = pyproject_toml.build_system {
-            for req in build_system.requires {
-                recipe.requirements.host.push(pypi_requirement(&req).await?);
-            }
+    // the build system and the modules are read from the sdist
+    let tempdir = tempfile::tempdir().into_diagnostic()?;
+    let sdist_path = tempdir.path().join(&source_dist.filename);
+    let sdist_url = url::Url::parse(&source_dist.url).into_diagnostic()?;
+    download_sdist(&sdist_url, &sdist_path)
+        .await
+        .wrap_err("failed to download sdist")?;
+    let sdist = SDistContents::read(&sdist_path)?;
+    let pyproject_toml = match &sdist.pyproject_toml {
+        Some(content) => toml::from_str::<PyProjectToml>(content)
+            .into_diagnostic()
+            .wrap_err("failed to parse pyproject.toml")?,
+        None => PyProjectToml::default(),
+    };
+
+    let build_system = pyproject_toml.build_system.as_ref();
+    let backend = BuildBackend::detect(build_system.and_then(|b| b.build_backend.as_deref()));
+    let build_requirements = match backend {
+        BuildBackend::Setuptools if sdist.has_setuptools_extensions() => {
+            &["${{ compiler('c') }}", "${{ stdlib('c') }}"][..]
         }
-
-        if let Some(project) = pyproject_toml.project {
-            if let Some(scripts) = project.scripts {
-                recipe.build.python.entry_points = scripts
-                    .iter()
-                    .map(|(k, v)| format!("{} = {}", k, v))
-                    .collect();
-            }
-            // recipe.about.license_file = project.license_files.map(|p| p.join(", "));
-            if let Some(urls) = project.urls {
-                recipe.about.repository = urls.get("Source Code").map(|s| s.to_string());
-                recipe.about.documentation = urls.get("Documentation").map(|s| s.to_string());
+        _ => backend.build_requirements(),
+    };
+    let noarch = build_requirements.is_empty();
+    recipe.requirements.build = build_requirements.iter().map(|s| s.to_string()).collect();
+
+    // python is pinned by the variant for packages with extension modules
+    let python = match (&info.requires_python, noarch) {
+        (Some(requires_python), true) if !requires_python.is_empty() => {
+            format!("python {}", requires_python.replace(' ', ""))
+        }
+        _ => "python".to_string(),
+    };
+    recipe.requirements.host.push(python.clone());
+    recipe.requirements.host.push("pip".to_string());
+    match build_system.filter(|b| !b.requires.is_empty()) {
+        Some(build_system) => {
+            for req in build_system
+                .requires
+                .iter()
+                .filter_map(|req| PythonRequirement::parse(req))
+            {
+                recipe.requirements.host.push(pypi_requirement(&req).await?);
             }
         }
-    }
-
-    if let Some(python_req) = wheel_metadata.requires_python.as_ref() {
-        recipe
+        None => recipe
             .requirements
             .host
-            .push(format!("python {}", python_req));
-        recipe
-            .requirements
-            .run
-            .push(format!("python {}", python_req));
-    } else {
-        recipe.requirements.host.push("python".to_string());
+            .extend(backend.package().map(str::to_string)),
     }
-    recipe.requirements.host.push("pip".to_string());
 
-    for pkg in wheel_metadata.requires_dist {
-        recipe.requirements.run.push(pypi_requirement(&pkg).await?);
+    recipe.requirements.run.push(python);
+    for req in info
+        .requires_dist
+        .iter()
+        .flatten()
+        .filter_map(|req| PythonRequirement::parse(req))
+        .filter(|req| !req.is_extra())
+    {
+        recipe.requirements.run.push(pypi_requirement(&req).await?);
     }
 
-    recipe.build.script = "python -m pip install .".to_string();
+    recipe.build.number = 0;
+    recipe.build.noarch = noarch.then(|| "python".to_string());
+    recipe.build.script = "python -m pip install . -vv --no-deps --no-build-isolation".to_string();
+    if let Some(project) = &pyproject_toml.project {
+        recipe.build.python.entry_points = project
+            .scripts
+            .iter()
+            .map(|(k, v)| format!("{} = {}", k, v))
+            .collect();
+    }
 
-    recipe.about.summary = pkg_info.parsed.take("Summary").ok();
-    recipe.about.description = pkg_info.parsed.take("Description").ok();
-    recipe.about.homepage = pkg_info.parsed.take("Home-page").ok();
-    recipe.about.license = pkg_info.parsed.take("License").ok();
+    let mut imports = sdist.top_level_modules();
+    if imports.is_empty() {
+        imports.push(name.replace('-', "_"));
+    }
+    recipe.tests.push(serialize::Test {
+        python: Some(serialize::PythonTest {
+            imports,
+            pip_check: true,
+        }),
+        ..Default::default()
+    });
+
+    let project_urls = info.project_urls.unwrap_or_default();
+    let project_url = |keys: &[&str]| {
+        project_urls
+            .iter()
+            .find(|(key, _)| keys.contains(&key.to_lowercase().as_str()))
+            .map(|(_, url)| url.clone())
+    };
+    recipe.about.homepage = info
+        .home_page
+        .filter(|url| !url.is_empty())
+        .or_else(|| project_url(&["homepage", "home"]));
+    recipe.about.repository = project_url(&["source", "source code", "repository", "code"]);
+    recipe.about.documentation = project_url(&["documentation", "docs"]);
+    recipe.about.summary = info.summary.filter(|summary| !summary.is_empty());
+    // the `license` field sometimes contains the full license text
+    recipe.about.license = info.license_expression.or(info
+        .license
+        .filter(|license| !license.is_empty() && !license.contains('\n')));
+
+    recipe.about.license_file = license::sdist_license_files(&sdist_path)?;
+    if recipe.about.license_file.is_empty() {
+        if fetch_license {
+            let urls = [&recipe.about.repository, &recipe.about.homepage]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if let Some(upstream) = license::fetch_github_license(
+                &client,
+                &urls,
+                &info.version,
+                recipe.about.license.as_deref(),
+            )
+            .await?
+            {
+                recipe.source.push(upstream.source);
+                recipe.about.license_file = vec![upstream.file_name];
+                recipe.about.license_comments = upstream.comments;
+            } else {
+                eprintln!("No GitHub repository found to fetch the license file from");
+            }
+        } else {
+            eprintln!("The sdist does not contain a license file, use `--fetch-license` to fetch it from GitHub");
+        }
+    }
 
     let string = format!("{}", recipe);
 
@@ -228,3 +550,78 @@ pub async fn generate_pypi_recipe(package: &str) -> miette::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requirement() {
+        assert_eq!(
+            PythonRequirement::parse("requests (>=2.0) ; extra == \"socks\"").unwrap(),
+            PythonRequirement {
+                name: "requests".to_string(),
+                version: Some(">=2.0".to_string()),
+                marker: Some("extra == \"socks\"".to_string()),
+            }
+        );
+        assert!(
+            PythonRequirement::parse("requests (>=2.0) ; extra == \"socks\"")
+                .unwrap()
+                .is_extra()
+        );
+
+        let req = PythonRequirement::parse("urllib3[brotli]<3,>=1.21.1").unwrap();
+        assert_eq!(req.name, "urllib3");
+        assert_eq!(req.version.as_deref(), Some("<3,>=1.21.1"));
+        assert_eq!(req.marker, None);
+
+        let req = PythonRequirement::parse("tomli; python_version < \"3.11\"").unwrap();
+        assert_eq!(req.version, None);
+        assert_eq!(req.marker.as_deref(), Some("python_version < \"3.11\""));
+        assert!(!req.is_extra());
+
+        let req = PythonRequirement::parse("foo @ https://example.com/foo.zip").unwrap();
+        assert_eq!(req.version, None);
+        assert_eq!(normalize_name("Typing_Extensions"), "typing-extensions");
+    }
+
+    #[test]
+    fn test_build_backend() {
+        assert_eq!(BuildBackend::detect(None), BuildBackend::Setuptools);
+        assert_eq!(
+            BuildBackend::detect(Some("setuptools.build_meta:__legacy__")),
+            BuildBackend::Setuptools
+        );
+        assert_eq!(
+            BuildBackend::detect(Some("hatchling.build")),
+            BuildBackend::Hatchling
+        );
+        assert_eq!(
+            BuildBackend::detect(Some("poetry.core.masonry.api")),
+            BuildBackend::Poetry
+        );
+        assert_eq!(BuildBackend::detect(Some("maturin")), BuildBackend::Maturin);
+        assert!(BuildBackend::Hatchling.build_requirements().is_empty());
+        assert!(!BuildBackend::Maturin.build_requirements().is_empty());
+    }
+
+    #[test]
+    fn test_top_level_modules() {
+        let sdist = SDistContents {
+            files: [
+                "src/foo/__init__.py",
+                "src/foo/bar/__init__.py",
+                "tests/__init__.py",
+                "setup.py",
+                "baz/__init__.py",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            ..Default::default()
+        };
+        assert_eq!(sdist.top_level_modules(), vec!["baz", "foo"]);
+        assert!(!sdist.has_setuptools_extensions());
+    }
+}
@@ -14,7 +14,11 @@ pub struct SourceElement {
 
 #[derive(Default, Debug, Serialize)]
 pub struct Build {
+    pub number: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noarch: Option<String>,
     pub script: String,
+    #[serde(skip_serializing_if = "Python::is_empty")]
     pub python: Python,
 }
 
@@ -24,6 +28,26 @@ pub struct Python {
     pub entry_points: Vec<String>,
 }
 
+impl Python {
+    fn is_empty(&self) -> bool {
+        self.entry_points.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PythonTest {
+    pub imports: Vec<String>,
+    pub pip_check: bool,
+}
+
+#[derive(Default, Debug, Serialize)]
+pub struct Test {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub python: Option<PythonTest>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub script: Vec<String>,
+}
+
 #[derive(Default, Debug, Serialize)]
 pub struct About {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -34,8 +58,11 @@ pub struct About {
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub license: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub license_file: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub license_file: Vec<String>,
+    /// Notes about the license file, printed as comments above `license_file`
+    #[serde(skip)]
+    pub license_comments: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub repository: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -50,11 +77,14 @@ pub struct Package {
 
 #[derive(Default, Debug, Serialize)]
 pub struct Recipe {
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
     pub context: IndexMap<String, String>,
     pub package: Package,
     pub source: Vec<SourceElement>,
     pub build: Build,
     pub requirements: Requirements,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<Test>,
     pub about: About,
 }
 
@@ -79,6 +109,11 @@ impl fmt::Display for Recipe {
                 writeln!(f)?;
             }
             first_line = false;
+            if line.starts_with("  license_file:") {
+                for comment in &self.about.license_comments {
+                    writeln!(f, "  # {}", comment)?;
+                }
+            }
             writeln!(f, "{}", line)?;
         }
         Ok(())
@@ -4,3 +4,4 @@
 pub mod pin;
 pub mod resolved_dependencies;
 pub mod solver;
+pub mod unsolvable;
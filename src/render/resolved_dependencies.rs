@@ -8,6 +8,7 @@ use std::{
 
 use crate::{
     metadata::{BuildConfiguration, Output},
+    packaging::Files,
     tool_configuration,
 };
 use indicatif::HumanBytes;
@@ -21,8 +22,9 @@ use rattler_conda_types::{version_spec::ParseVersionSpecError, PackageRecord};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use super::{pin::PinError, solver::create_environment};
-use crate::recipe::parser::Dependency;
+use super::{pin::PinError, solver::create_environment, unsolvable::explain_unsolvable};
+use crate::lockfile::{find_reused, LockfileError};
+use crate::recipe::parser::{Dependency, ScriptPhase};
 use crate::render::pin::PinArgs;
 use crate::render::solver::install_packages;
 use serde_with::{serde_as, DisplayFromStr};
@@ -188,6 +190,27 @@ impl DependencyInfo {
         }
     }
 
+    /// Where the dependency comes from, for the requirements of the given environment
+    /// (e.g. `requirements.host` or ``run_export of `zlib` from the build environment``).
+    pub fn origin(&self, env: &str) -> String {
+        match self {
+            DependencyInfo::Variant(spec) => {
+                format!("requirements.{env}, variant `{}`", spec.variant)
+            }
+            DependencyInfo::PinSubpackage(spec) => {
+                format!("pin_subpackage('{}') in requirements.{env}", spec.name)
+            }
+            DependencyInfo::PinCompatible(spec) => {
+                format!("pin_compatible('{}') in requirements.{env}", spec.name)
+            }
+            DependencyInfo::RunExport(spec) => format!(
+                "run_export of `{}` from the {} environment",
+                spec.source_package, spec.from
+            ),
+            DependencyInfo::Source(_) => format!("requirements.{env}"),
+        }
+    }
+
     pub fn as_variant(&self) -> Option<&VariantDependency> {
         match self {
             DependencyInfo::Variant(spec) => Some(spec),
@@ -229,6 +252,10 @@ pub struct FinalizedRunDependencies {
     pub depends: Vec<DependencyInfo>,
     pub constrains: Vec<DependencyInfo>,
     pub run_exports: Option<RunExportsJson>,
+    /// The run exports of the build and host environments that were removed by
+    /// `ignore_run_exports`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignored_run_exports: Vec<RunExportExplanation>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -372,8 +399,102 @@ pub struct FinalizedDependencies {
     pub run: FinalizedRunDependencies,
 }
 
+/// Where a run export of the build or host environment ended up in the run requirements
+/// of an output (printed by `rattler-build render --explain-run-exports`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunExportExplanation {
+    /// The exported spec, not set if all run exports of the package are ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spec: Option<String>,
+    /// The package whose `run_exports` contain the spec
+    pub package: String,
+    /// The environment (`build` or `host`) the package is installed in
+    pub env: String,
+    /// The kind of run export (`strong`, `weak`, `noarch`, `strong_constrains` or
+    /// `weak_constrains`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Where the spec was added (`depends` or `constrains`), not set if it was ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added_to: Option<String>,
+    /// The `ignore_run_exports` field that removed the spec (`by_name` or `from_package`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignored_by: Option<String>,
+}
+
+impl FinalizedDependencies {
+    /// Explain the run exports of the build and host environments: which package
+    /// exported every run dependency and constraint, and which run exports were removed
+    /// by `ignore_run_exports`.
+    pub fn explain_run_exports(&self, target_platform: Platform) -> Vec<RunExportExplanation> {
+        let mut explanations = Vec::new();
+        for (added_to, dependencies) in [
+            ("depends", &self.run.depends),
+            ("constrains", &self.run.constrains),
+        ] {
+            for dependency in dependencies
+                .iter()
+                .filter_map(DependencyInfo::as_run_export)
+            {
+                explanations.push(RunExportExplanation {
+                    spec: Some(dependency.spec.to_string()),
+                    package: dependency.source_package.clone(),
+                    env: dependency.from.clone(),
+                    kind: self.run_export_kind(dependency, added_to, target_platform),
+                    added_to: Some(added_to.to_string()),
+                    ignored_by: None,
+                });
+            }
+        }
+        explanations.extend(self.run.ignored_run_exports.iter().cloned());
+        explanations
+    }
+
+    /// The kind of run export (e.g. `weak`) that a run dependency or constraint came from.
+    fn run_export_kind(
+        &self,
+        dependency: &RunExportDependency,
+        added_to: &str,
+        target_platform: Platform,
+    ) -> Option<String> {
+        let resolved = match dependency.from.as_str() {
+            "build" => self.build.as_ref(),
+            "host" => self.host.as_ref(),
+            _ => None,
+        }?;
+        let (_, rex) = resolved
+            .run_exports
+            .iter()
+            .find(|(name, _)| name.as_normalized() == dependency.source_package)?;
+        let kinds: Vec<(&str, &[String])> = match (added_to, target_platform) {
+            ("depends", Platform::NoArch) => vec![("noarch", rex.noarch.as_slice())],
+            ("depends", _) => vec![
+                ("strong", rex.strong.as_slice()),
+                ("weak", rex.weak.as_slice()),
+            ],
+            _ => vec![
+                ("strong_constrains", rex.strong_constrains.as_slice()),
+                ("weak_constrains", rex.weak_constrains.as_slice()),
+            ],
+        };
+        let spec = dependency.spec.to_string();
+        kinds
+            .iter()
+            .find(|(_, specs)| {
+                specs.iter().any(|s| {
+                    MatchSpec::from_str(s, ParseStrictness::Strict)
+                        .is_ok_and(|s| s.to_string() == spec)
+                })
+            })
+            .map(|(kind, _)| kind.to_string())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ResolveError {
+    #[error("Could not reuse the lock file: {0}")]
+    LockfileError(#[from] LockfileError),
+
     #[error("Failed to get finalized dependencies")]
     FinalizedDependencyNotFound,
 
@@ -406,6 +527,9 @@ pub enum ResolveError {
 
     #[error("Could not reindex channels: {0}")]
     RefreshChannelError(std::io::Error),
+
+    #[error("Could not prepare the environments for an incremental build: {0}")]
+    IncrementalBuildError(std::io::Error),
 }
 
 /// Apply a variant to a dependency list and resolve all pin_subpackage and compiler
@@ -553,6 +677,155 @@ pub async fn install_environments(
     Ok(())
 }
 
+/// The file in the build directory that records the environments of an incremental build.
+const INCREMENTAL_STATE_FILE: &str = "incremental_environments.json";
+
+/// The environments of a previous build, together with a hash of the inputs that
+/// they were resolved from.
+#[derive(Serialize, Deserialize)]
+struct IncrementalState {
+    key: String,
+    finalized_dependencies: FinalizedDependencies,
+}
+
+/// Hash everything that goes into resolving the build and host environments.
+fn environments_key(output: &Output, channels: &[Url]) -> String {
+    let build_configuration = &output.build_configuration;
+    let input = serde_json::json!({
+        "requirements": output.recipe.requirements(),
+        "merge_build_and_host_envs": output.recipe.build().merge_build_and_host_envs(),
+        "variant": build_configuration.variant,
+        "subpackages": build_configuration.subpackages,
+        "channels": channels,
+        "build_platform": build_configuration.build_platform,
+        "host_platform": build_configuration.host_platform,
+        "target_platform": build_configuration.target_platform,
+    });
+    format!(
+        "{:x}",
+        rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(input.to_string())
+    )
+}
+
+/// Load the environments of the previous build if they were resolved from the same
+/// inputs and are still installed.
+fn load_incremental_state(output: &Output, key: &str) -> Option<FinalizedDependencies> {
+    let directories = &output.build_configuration.directories;
+    let state = fs::read_to_string(directories.build_dir.join(INCREMENTAL_STATE_FILE)).ok()?;
+    let state: IncrementalState = serde_json::from_str(&state).ok()?;
+    if state.key != key {
+        tracing::info!("The requirements changed, resolving the environments again");
+        return None;
+    }
+
+    let dependencies = &state.finalized_dependencies;
+    let installed = |deps: &Option<ResolvedDependencies>, prefix: &Path| {
+        deps.is_none() || prefix.join("conda-meta").exists()
+    };
+    if !installed(&dependencies.build, &directories.build_prefix)
+        || !installed(&dependencies.host, &directories.host_prefix)
+    {
+        return None;
+    }
+
+    Some(state.finalized_dependencies)
+}
+
+/// Record the resolved environments for the next incremental build.
+fn save_incremental_state(
+    output: &Output,
+    key: &str,
+    finalized_dependencies: &FinalizedDependencies,
+) -> Result<(), std::io::Error> {
+    let state = IncrementalState {
+        key: key.to_string(),
+        finalized_dependencies: finalized_dependencies.clone(),
+    };
+    fs::write(
+        output
+            .build_configuration
+            .directories
+            .build_dir
+            .join(INCREMENTAL_STATE_FILE),
+        serde_json::to_string(&state)?,
+    )
+}
+
+/// Remove the files that a previous build installed into the prefix, i.e. all files
+/// that do not belong to an installed package.
+fn remove_untracked_files(prefix: &Path) -> Result<(), std::io::Error> {
+    let files = Files::from_prefix(prefix, None)?;
+    let mut untracked = files
+        .new_files
+        .into_iter()
+        .filter(|path| path != prefix)
+        .collect::<Vec<_>>();
+    if untracked.is_empty() {
+        return Ok(());
+    }
+    tracing::info!(
+        "Removing {} files of the previous build from {}",
+        untracked.len(),
+        prefix.display()
+    );
+
+    // remove the deepest paths first so that directories are empty when we get to them
+    untracked.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+    for path in untracked {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if metadata.is_dir() {
+            // directories that still contain files of installed packages are kept
+            let _ = fs::remove_dir(&path);
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Solve and install an environment of the output, or install the packages of the lock
+/// file that is reused for it (`--reuse-lockfile`). The origins of the specs explain
+/// the conflicts if the environment can not be solved.
+async fn create_or_reuse_environment(
+    output: &Output,
+    environment: &str,
+    origins: &[(MatchSpec, String)],
+    platform: &Platform,
+    prefix: &Path,
+    channels: &[Url],
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<Vec<RepoDataRecord>, ResolveError> {
+    let reused = find_reused(
+        &output.build_configuration.reuse_lockfiles,
+        output.name().as_normalized(),
+        environment,
+    )?;
+    if let Some((path, lockfile)) = reused {
+        tracing::info!(
+            "Installing the {} environment from {} instead of solving it",
+            environment,
+            path.display()
+        );
+        let records = lockfile.records(*platform, tool_configuration).await?;
+        if !tool_configuration.render_only {
+            install_packages(&records, platform, prefix, tool_configuration).await?;
+        }
+        return Ok(records);
+    }
+
+    let specs = origins
+        .iter()
+        .map(|(spec, _)| spec.clone())
+        .collect::<Vec<_>>();
+    create_environment(&specs, platform, prefix, channels, tool_configuration)
+        .await
+        .map_err(|e| explain_unsolvable(e, environment, origins).into())
+}
+
 /// This function resolves the dependencies of a recipe.
 /// To do this, we have to run a couple of steps:
 ///
@@ -562,6 +835,43 @@ pub async fn install_environments(
 /// 4. Download the packages
 /// 5. Extract the run exports from the downloaded packages (for the next environment)
 #[allow(clippy::for_kv_map)]
+/// The packages of an environment whose run exports are not collected because of
+/// `ignore_run_exports.from_package`.
+fn ignored_from_package(
+    output: &Output,
+    env: &str,
+    resolved: Option<&ResolvedDependencies>,
+) -> Vec<RunExportExplanation> {
+    let Some(resolved) = resolved else {
+        return Vec::new();
+    };
+    let from_package = output
+        .recipe
+        .requirements()
+        .ignore_run_exports()
+        .from_package();
+    resolved
+        .resolved
+        .iter()
+        .map(|record| &record.package_record.name)
+        .filter(|name| {
+            from_package.contains(name)
+                && resolved
+                    .specs
+                    .iter()
+                    .any(|spec| spec.spec().name.as_ref() == Some(name))
+        })
+        .map(|name| RunExportExplanation {
+            spec: None,
+            package: name.as_normalized().to_string(),
+            env: env.to_string(),
+            kind: None,
+            added_to: None,
+            ignored_by: Some("from_package".to_string()),
+        })
+        .collect()
+}
+
 async fn resolve_dependencies(
     output: &Output,
     channels: &[Url],
@@ -569,8 +879,7 @@ async fn resolve_dependencies(
 ) -> Result<FinalizedDependencies, ResolveError> {
     let merge_build_host = output.recipe.build().merge_build_and_host_envs();
 
-    let cache_dir = rattler::default_cache_dir().expect("Could not get default cache dir");
-    let pkgs_dir = cache_dir.join("pkgs");
+    let pkgs_dir = tool_configuration.package_cache_dir();
 
     let reqs = &output.recipe.requirements();
     let mut compatibility_specs = HashMap::new();
@@ -583,16 +892,21 @@ async fn resolve_dependencies(
         )?;
 
         let match_specs = specs.iter().map(|s| s.spec().clone()).collect::<Vec<_>>();
+        let origins = specs
+            .iter()
+            .map(|s| (s.spec().clone(), s.origin("build")))
+            .collect::<Vec<_>>();
 
-        let env = create_environment(
-            &match_specs,
+        let env = create_or_reuse_environment(
+            output,
+            "build",
+            &origins,
             &output.build_configuration.build_platform,
             &output.build_configuration.directories.build_prefix,
             channels,
             tool_configuration,
         )
-        .await
-        .map_err(ResolveError::from)?;
+        .await?;
 
         let run_exports = collect_run_exports_from_env(&env, &pkgs_dir, |rec| {
             let res = match_specs
@@ -631,9 +945,15 @@ async fn resolve_dependencies(
         &compatibility_specs,
     )?;
 
+    // the run exports that are removed by `ignore_run_exports`, for
+    // `render --explain-run-exports`
+    let mut ignored_run_exports = ignored_from_package(output, "build", build_env.as_ref());
+
     let clone_specs = |name: &PackageName,
                        env: &str,
-                       specs: &[String]|
+                       kind: &str,
+                       specs: &[String],
+                       ignored: &mut Vec<RunExportExplanation>|
      -> Result<Vec<DependencyInfo>, ResolveError> {
         let mut cloned = Vec::new();
         for spec in specs {
@@ -652,6 +972,14 @@ async fn resolve_dependencies(
                 .map(in_ignore_run_exports)
                 .unwrap_or_default()
             {
+                ignored.push(RunExportExplanation {
+                    spec: Some(spec.to_string()),
+                    package: name.as_normalized().to_string(),
+                    env: env.to_string(),
+                    kind: Some(kind.to_string()),
+                    added_to: None,
+                    ignored_by: Some("by_name".to_string()),
+                });
                 continue;
             }
 
@@ -668,11 +996,22 @@ async fn resolve_dependencies(
     // add the run exports of the build environment
     if let Some(build_env) = &build_env {
         for (name, rex) in &build_env.run_exports {
-            specs.extend(clone_specs(name, "build", &rex.strong)?);
+            // these are recorded when they are added to the run requirements below
+            specs.extend(clone_specs(
+                name,
+                "build",
+                "strong",
+                &rex.strong,
+                &mut Vec::new(),
+            )?);
         }
     }
 
     let mut match_specs = specs.iter().map(|s| s.spec().clone()).collect::<Vec<_>>();
+    let mut origins = specs
+        .iter()
+        .map(|s| (s.spec().clone(), s.origin("host")))
+        .collect::<Vec<_>>();
     if merge_build_host {
         // add the reqs of build to host
         let specs = apply_variant(
@@ -681,18 +1020,22 @@ async fn resolve_dependencies(
             &compatibility_specs,
         )?;
         match_specs.extend(specs.iter().map(|s| s.spec().clone()));
+        origins.extend(specs.iter().map(|s| (s.spec().clone(), s.origin("build"))));
     }
 
+    let abi3 = output.recipe.build().python().abi3;
+
     let host_env = if !match_specs.is_empty() {
-        let env = create_environment(
-            &match_specs,
+        let env = create_or_reuse_environment(
+            output,
+            "host",
+            &origins,
             &output.build_configuration.host_platform,
             &output.build_configuration.directories.host_prefix,
             channels,
             tool_configuration,
         )
-        .await
-        .map_err(ResolveError::from)?;
+        .await?;
 
         let run_exports = collect_run_exports_from_env(&env, &pkgs_dir, |rec| {
             let res = match_specs
@@ -705,7 +1048,10 @@ async fn resolve_dependencies(
                 .ignore_run_exports()
                 .from_package();
 
-            res && !ignore_run_exports_from.contains(&rec.package_record.name)
+            // abi3 packages are not tied to the ABI of the python they were built against
+            let ignore_python = abi3 && rec.package_record.name.as_normalized() == "python";
+
+            res && !ignore_python && !ignore_run_exports_from.contains(&rec.package_record.name)
         })
         .map_err(ResolveError::CouldNotCollectRunExports)?;
 
@@ -724,6 +1070,8 @@ async fn resolve_dependencies(
         None
     };
 
+    ignored_run_exports.extend(ignored_from_package(output, "host", host_env.as_ref()));
+
     let depends = apply_variant(&reqs.run, &output.build_configuration, &compatibility_specs)?;
 
     let constrains = apply_variant(
@@ -762,6 +1110,7 @@ async fn resolve_dependencies(
         depends,
         constrains,
         run_exports,
+        ignored_run_exports: Vec::new(),
     };
 
     // Propagate run exports from host env to run env
@@ -769,25 +1118,45 @@ async fn resolve_dependencies(
         match output.build_configuration.target_platform {
             Platform::NoArch => {
                 for (name, rex) in &host_env.run_exports {
-                    run_specs
-                        .depends
-                        .extend(clone_specs(name, "host", &rex.noarch)?);
+                    run_specs.depends.extend(clone_specs(
+                        name,
+                        "host",
+                        "noarch",
+                        &rex.noarch,
+                        &mut ignored_run_exports,
+                    )?);
                 }
             }
             _ => {
                 for (name, rex) in &host_env.run_exports {
-                    run_specs
-                        .depends
-                        .extend(clone_specs(name, "host", &rex.strong)?);
-                    run_specs
-                        .depends
-                        .extend(clone_specs(name, "host", &rex.weak)?);
-                    run_specs
-                        .constrains
-                        .extend(clone_specs(name, "host", &rex.strong_constrains)?);
-                    run_specs
-                        .constrains
-                        .extend(clone_specs(name, "host", &rex.weak_constrains)?);
+                    run_specs.depends.extend(clone_specs(
+                        name,
+                        "host",
+                        "strong",
+                        &rex.strong,
+                        &mut ignored_run_exports,
+                    )?);
+                    run_specs.depends.extend(clone_specs(
+                        name,
+                        "host",
+                        "weak",
+                        &rex.weak,
+                        &mut ignored_run_exports,
+                    )?);
+                    run_specs.constrains.extend(clone_specs(
+                        name,
+                        "host",
+                        "strong_constrains",
+                        &rex.strong_constrains,
+                        &mut ignored_run_exports,
+                    )?);
+                    run_specs.constrains.extend(clone_specs(
+                        name,
+                        "host",
+                        "weak_constrains",
+                        &rex.weak_constrains,
+                        &mut ignored_run_exports,
+                    )?);
                 }
             }
         }
@@ -799,19 +1168,57 @@ async fn resolve_dependencies(
             Platform::NoArch => {}
             _ => {
                 for (name, rex) in &build_env.run_exports {
-                    run_specs
-                        .depends
-                        .extend(clone_specs(name, "build", &rex.strong)?);
+                    run_specs.depends.extend(clone_specs(
+                        name,
+                        "build",
+                        "strong",
+                        &rex.strong,
+                        &mut ignored_run_exports,
+                    )?);
                     run_specs.constrains.extend(clone_specs(
                         name,
                         "build",
+                        "strong_constrains",
                         &rex.strong_constrains,
+                        &mut ignored_run_exports,
                     )?);
                 }
             }
         }
     }
 
+    // abi3 packages work with any python that is at least as new as the one they were
+    // built against, so replace the exact python variant pin with a lower bound
+    if abi3 {
+        run_specs.depends.retain(|dep| match dep {
+            DependencyInfo::Variant(variant) => variant.variant != "python",
+            _ => true,
+        });
+        match compatibility_specs
+            .get(&PackageName::new_unchecked("python"))
+            .and_then(|python| python.version.as_major_minor())
+        {
+            Some((major, minor)) => {
+                let spec = MatchSpec::from_str(
+                    &format!("python >={major}.{minor}"),
+                    ParseStrictness::Strict,
+                )?;
+                run_specs.depends.push(
+                    VariantDependency {
+                        variant: "python".to_string(),
+                        spec,
+                    }
+                    .into(),
+                );
+            }
+            None => tracing::warn!(
+                "`python.abi3` is set but python is not part of the host environment"
+            ),
+        }
+    }
+
+    run_specs.ignored_run_exports = ignored_run_exports;
+
     // log a table of the rendered run dependencies
     if run_specs.depends.is_empty() && run_specs.constrains.is_empty() {
         tracing::info!("\nFinalized run dependencies: this output has no run dependencies");
@@ -844,10 +1251,44 @@ impl Output {
             self.clone()
         } else {
             let channels = self
-                .reindex_channels()
+                .channels(tool_configuration)
+                .await
                 .map_err(ResolveError::RefreshChannelError)?;
-            let finalized_dependencies =
-                resolve_dependencies(&self, &channels, tool_configuration).await?;
+
+            // In incremental mode, the environments of the previous build are reused if
+            // they were resolved from the same inputs. The files installed by the previous
+            // build are kept if the install phase is not run again.
+            let incremental_key = if tool_configuration.incremental {
+                if !tool_configuration
+                    .from_phase
+                    .is_some_and(|phase| phase > ScriptPhase::Install)
+                {
+                    remove_untracked_files(&self.build_configuration.directories.host_prefix)
+                        .map_err(ResolveError::IncrementalBuildError)?;
+                }
+                Some(environments_key(&self, &channels))
+            } else {
+                None
+            };
+            let reused = incremental_key
+                .as_deref()
+                .and_then(|key| load_incremental_state(&self, key));
+
+            let finalized_dependencies = match reused {
+                Some(finalized_dependencies) => {
+                    tracing::info!("Reusing the environments of the previous build");
+                    finalized_dependencies
+                }
+                None => {
+                    let finalized_dependencies =
+                        resolve_dependencies(&self, &channels, tool_configuration).await?;
+                    if let Some(key) = &incremental_key {
+                        save_incremental_state(&self, key, &finalized_dependencies)
+                            .map_err(ResolveError::IncrementalBuildError)?;
+                    }
+                    finalized_dependencies
+                }
+            };
 
             // The output with the resolved dependencies
             Output {
@@ -910,4 +1351,61 @@ mod tests {
         assert!(matches!(dep_info[2], DependencyInfo::PinSubpackage(_)));
         assert!(matches!(dep_info[3], DependencyInfo::PinCompatible(_)));
     }
+
+    #[test]
+    fn test_explain_run_exports() {
+        let host = ResolvedDependencies {
+            specs: Vec::new(),
+            resolved: Vec::new(),
+            run_exports: HashMap::from([(
+                PackageName::new_unchecked("libzlib"),
+                RunExportsJson {
+                    strong: Vec::new(),
+                    weak: vec!["libzlib >=1.3".to_string()],
+                    noarch: Vec::new(),
+                    strong_constrains: vec!["zlib-ng <3".to_string()],
+                    weak_constrains: Vec::new(),
+                },
+            )]),
+        };
+        let ignored = RunExportExplanation {
+            spec: Some("zlib-ng <3".to_string()),
+            package: "libzlib".to_string(),
+            env: "host".to_string(),
+            kind: Some("strong_constrains".to_string()),
+            added_to: None,
+            ignored_by: Some("by_name".to_string()),
+        };
+        let deps = FinalizedDependencies {
+            build: None,
+            host: Some(host),
+            run: FinalizedRunDependencies {
+                depends: vec![
+                    RunExportDependency {
+                        spec: MatchSpec::from_str("libzlib >=1.3", ParseStrictness::Strict)
+                            .unwrap(),
+                        from: "host".to_string(),
+                        source_package: "libzlib".to_string(),
+                    }
+                    .into(),
+                    SourceDependency {
+                        spec: MatchSpec::from_str("python", ParseStrictness::Strict).unwrap(),
+                    }
+                    .into(),
+                ],
+                constrains: Vec::new(),
+                run_exports: None,
+                ignored_run_exports: vec![ignored],
+            },
+        };
+
+        // the explanation follows the run requirements that were actually finalized
+        let explanations = deps.explain_run_exports(Platform::Linux64);
+        assert_eq!(explanations.len(), 2);
+        assert_eq!(explanations[0].spec.as_deref(), Some("libzlib >=1.3"));
+        assert_eq!(explanations[0].kind.as_deref(), Some("weak"));
+        assert_eq!(explanations[0].added_to.as_deref(), Some("depends"));
+        assert_eq!(explanations[1].ignored_by.as_deref(), Some("by_name"));
+        assert!(explanations[1].added_to.is_none());
+    }
 }
@@ -2,9 +2,11 @@ use comfy_table::Table;
 
 use indicatif::ProgressStyle;
 use indicatif::{HumanBytes, ProgressBar};
-use rattler::install::{DefaultProgressFormatter, IndicatifReporter, Installer};
+use rattler::{
+    install::{DefaultProgressFormatter, IndicatifReporter, Installer},
+    package_cache::PackageCache,
+};
 use rattler_conda_types::{Channel, GenericVirtualPackage, MatchSpec, Platform, RepoDataRecord};
-use rattler_repodata_gateway::Gateway;
 use rattler_solve::{resolvo::Solver, SolverImpl, SolverTask};
 use url::Url;
 
@@ -54,6 +56,31 @@ pub async fn create_environment(
     target_prefix: &Path,
     channels: &[Url],
     tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<Vec<RepoDataRecord>> {
+    let required_packages =
+        solve_environment(specs, target_platform, channels, tool_configuration).await?;
+
+    if !tool_configuration.render_only {
+        install_packages(
+            &required_packages,
+            target_platform,
+            target_prefix,
+            tool_configuration,
+        )
+        .await?;
+    } else {
+        tracing::info!("skipping installation when --render-only or --dry-run is used");
+    }
+
+    Ok(required_packages)
+}
+
+/// Resolve the specs to the packages of an environment without installing them.
+pub async fn solve_environment(
+    specs: &[MatchSpec],
+    target_platform: &Platform,
+    channels: &[Url],
+    tool_configuration: &tool_configuration::Configuration,
 ) -> anyhow::Result<Vec<RepoDataRecord>> {
     // Parse the specs from the command line. We do this explicitly instead of allow clap to deal
     // with this because we need to parse the `channel_config` when parsing matchspecs.
@@ -77,6 +104,7 @@ pub async fn create_environment(
     // Determine virtual packages of the system. These packages define the capabilities of the
     // system. Some packages depend on these virtual packages to indicate compatibility with the
     // hardware of the system.
+    let mock_virtual_packages = tool_configuration.mock_virtual_packages.clone();
     let virtual_packages = tool_configuration.fancy_log_handler.wrap_in_progress(
         "determining virtual packages",
         move || {
@@ -84,6 +112,12 @@ pub async fn create_environment(
                 vpkgs
                     .iter()
                     .map(|vpkg| GenericVirtualPackage::from(vpkg.clone()))
+                    .filter(|vpkg| {
+                        !mock_virtual_packages
+                            .iter()
+                            .any(|mocked| mocked.name == vpkg.name)
+                    })
+                    .chain(mock_virtual_packages)
                     .collect::<Vec<_>>()
             })
         },
@@ -104,18 +138,6 @@ pub async fn create_environment(
         .fancy_log_handler
         .wrap_in_progress("solving", move || Solver.solve(solver_task))?;
 
-    if !tool_configuration.render_only {
-        install_packages(
-            &required_packages,
-            target_platform,
-            target_prefix,
-            tool_configuration,
-        )
-        .await?;
-    } else {
-        tracing::info!("skipping installation when --render-only is used");
-    }
-
     Ok(required_packages)
 }
 
@@ -214,20 +236,19 @@ pub async fn load_repodatas(
     specs: &[MatchSpec],
     tool_configuration: &tool_configuration::Configuration,
 ) -> anyhow::Result<Vec<rattler_repodata_gateway::RepoData>> {
-    let cache_dir = rattler::default_cache_dir()?;
-    let download_client = tool_configuration.client.clone();
-
-    // Get the package names from the matchspecs so we can only load the package records that we need.
-    let gateway = Gateway::builder()
-        .with_cache_dir(cache_dir.join("repodata"))
-        .with_client(download_client.clone())
-        .finish();
+    // Use the shared gateway so that repodata is only fetched once per process
+    let gateway = &tool_configuration.repodata_gateway;
 
     let channels = channels
         .iter()
         .map(|url| Channel::from_url(url.clone()))
         .collect::<Vec<_>>();
 
+    // local channels (e.g. the output directory) change between builds, always read them again
+    for channel in channels.iter().filter(|c| c.base_url.scheme() == "file") {
+        gateway.clear_repodata_cache(channel, Default::default());
+    }
+
     let pb =
         ProgressBar::new(50).with_style(tool_configuration.fancy_log_handler.default_bytes_style());
 
@@ -270,6 +291,43 @@ pub async fn load_repodatas(
     Ok(result)
 }
 
+/// The file in the `conda-meta` directory of a prefix that records the hash of the
+/// environment that rattler-build installed into it.
+const ENVIRONMENT_HASH_FILE: &str = "rattler-build-environment";
+
+/// A hash of a solved environment (the platform and the urls of all packages).
+fn environment_hash(packages: &[RepoDataRecord], target_platform: &Platform) -> String {
+    let mut urls = packages
+        .iter()
+        .map(|record| record.url.as_str())
+        .collect::<Vec<_>>();
+    urls.sort_unstable();
+    let input = format!("{}\n{}", target_platform, urls.join("\n"));
+    format!(
+        "{:x}",
+        rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(input)
+    )
+}
+
+/// Remove a prefix (e.g. of a build that is resumed with `--keep-build`) that contains
+/// a different environment than the one that is about to be installed. Installing on
+/// top of it would leave the packages of the old environment behind.
+fn remove_stale_prefix(prefix: &Path, hash: &str) -> std::io::Result<()> {
+    let conda_meta = prefix.join("conda-meta");
+    if !conda_meta.exists() {
+        return Ok(());
+    }
+    let previous = fs_err::read_to_string(conda_meta.join(ENVIRONMENT_HASH_FILE)).ok();
+    if previous.as_deref().map(str::trim) == Some(hash) {
+        return Ok(());
+    }
+    tracing::warn!(
+        "The environment in {} does not match the resolved requirements anymore, recreating it",
+        prefix.display()
+    );
+    fs_err::remove_dir_all(prefix)
+}
+
 pub async fn install_packages(
     required_packages: &Vec<RepoDataRecord>,
     target_platform: &Platform,
@@ -280,9 +338,17 @@ pub async fn install_packages(
 
     print_as_table(required_packages);
 
+    let hash = environment_hash(required_packages, target_platform);
+    remove_stale_prefix(target_prefix, &hash)?;
+
     if !required_packages.is_empty() {
+        let package_cache_dir = tool_configuration.package_cache_dir();
+        let _lock = tool_configuration
+            .lock_cache(&package_cache_dir, "package cache")
+            .await?;
         Installer::new()
             .with_download_client(tool_configuration.client.clone())
+            .with_package_cache(PackageCache::new(package_cache_dir))
             .with_target_platform(*target_platform)
             .with_installed_packages(installed_packages)
             .with_execute_link_scripts(true)
@@ -303,6 +369,10 @@ pub async fn install_packages(
             )
             .install(&target_prefix, required_packages.clone())
             .await?;
+        fs_err::write(
+            target_prefix.join("conda-meta").join(ENVIRONMENT_HASH_FILE),
+            &hash,
+        )?;
 
         tracing::info!(
             "{} Successfully updated the environment",
@@ -317,3 +387,31 @@ pub async fn install_packages(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_stale_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("host_env");
+
+        // a prefix without environment is left alone
+        fs_err::create_dir_all(prefix.join("bin")).unwrap();
+        remove_stale_prefix(&prefix, "abc").unwrap();
+        assert!(prefix.exists());
+
+        fs_err::create_dir_all(prefix.join("conda-meta")).unwrap();
+        fs_err::write(
+            prefix.join("conda-meta").join(ENVIRONMENT_HASH_FILE),
+            "abc\n",
+        )
+        .unwrap();
+        remove_stale_prefix(&prefix, "abc").unwrap();
+        assert!(prefix.exists());
+
+        remove_stale_prefix(&prefix, "def").unwrap();
+        assert!(!prefix.exists());
+    }
+}
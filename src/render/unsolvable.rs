@@ -0,0 +1,126 @@
+//! Explain why an environment could not be solved: the conflict tree of the solver with
+//! the origin of every requested package (the recipe requirement, variant or run export
+//! that introduced it).
+
+use std::fmt::Write;
+
+use rattler_conda_types::MatchSpec;
+use rattler_solve::SolveError;
+
+/// An environment that could not be solved, with the annotated conflict tree.
+#[derive(Debug, thiserror::Error)]
+#[error("Could not solve the {environment} environment:\n\n{tree}\n{requested}")]
+pub struct UnsolvableEnvironment {
+    /// The environment, e.g. `host` or `test`
+    pub environment: String,
+    /// The conflict tree of the solver with the origins of the requested packages
+    pub tree: String,
+    /// The list of the requested packages and their origins
+    pub requested: String,
+}
+
+/// The package name at the start of a line of the conflict tree (after the tree
+/// characters), e.g. `foo` for `├─ foo >=2 cannot be installed because ...`.
+fn root_name(line: &str) -> Option<(&str, &str)> {
+    let rest = line
+        .strip_prefix("├─ ")
+        .or_else(|| line.strip_prefix("└─ "))?;
+    let name = rest.split_whitespace().next()?;
+    Some((name.trim_end_matches(','), rest))
+}
+
+/// Annotate the root entries of the conflict tree (the requested packages) with the
+/// origins of their specs.
+fn annotate_tree(message: &str, origins: &[(MatchSpec, String)]) -> String {
+    let mut tree = String::new();
+    for line in message.lines() {
+        tree.push_str(line);
+        if let Some((name, rest)) = root_name(line) {
+            let matching = origins
+                .iter()
+                .filter(|(spec, _)| spec.name.as_ref().map(|n| n.as_normalized()) == Some(name))
+                .collect::<Vec<_>>();
+            // prefer the spec that is printed, if the same package is requested twice
+            let exact = matching
+                .iter()
+                .filter(|(spec, _)| rest.starts_with(&spec.to_string()))
+                .collect::<Vec<_>>();
+            let annotations = if exact.is_empty() {
+                matching
+                    .iter()
+                    .map(|(_, origin)| origin.as_str())
+                    .collect::<Vec<_>>()
+            } else {
+                exact.iter().map(|(_, origin)| origin.as_str()).collect()
+            };
+            if !annotations.is_empty() {
+                let _ = write!(tree, "  ← {}", annotations.join(", "));
+            }
+        }
+        tree.push('\n');
+    }
+    tree
+}
+
+/// Turn an unsolvable solver error into an [`UnsolvableEnvironment`] that explains where
+/// the requested packages come from. Other errors are returned unchanged.
+pub fn explain_unsolvable(
+    error: anyhow::Error,
+    environment: &str,
+    origins: &[(MatchSpec, String)],
+) -> anyhow::Error {
+    let Some(SolveError::Unsolvable(messages)) = error.downcast_ref::<SolveError>() else {
+        return error;
+    };
+
+    let tree = messages
+        .iter()
+        .map(|message| annotate_tree(message, origins))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut requested = String::from("The requested packages:\n");
+    for (spec, origin) in origins {
+        let _ = writeln!(requested, "  - {} ({})", spec, origin);
+    }
+
+    UnsolvableEnvironment {
+        environment: environment.to_string(),
+        tree,
+        requested,
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use rattler_conda_types::ParseStrictness;
+
+    use super::*;
+
+    fn spec(s: &str) -> MatchSpec {
+        MatchSpec::from_str(s, ParseStrictness::Strict).unwrap()
+    }
+
+    #[test]
+    fn test_annotate_tree() {
+        let message = "The following packages are incompatible\n\
+                       ├─ libfoo >=2 can be installed with any of the following options:\n\
+                       │  └─ libfoo 2.0 would require\n\
+                       │     └─ zlib >=1.3, which can be installed with any of the following options:\n\
+                       │        └─ zlib 1.3\n\
+                       └─ zlib 1.2.* cannot be installed because there are no viable options:\n   \
+                          └─ zlib 1.2.13 is excluded because of the constraint";
+        let origins = [
+            (spec("libfoo >=2"), "requirements.host".to_string()),
+            (
+                spec("zlib 1.2.*"),
+                "run_export of `libbar` from the build environment".to_string(),
+            ),
+        ];
+        let tree = annotate_tree(message, &origins);
+        let lines = tree.lines().collect::<Vec<_>>();
+        assert!(lines[1].ends_with("options:  ← requirements.host"));
+        assert!(!lines[3].contains('←'));
+        assert!(lines[5].ends_with("← run_export of `libbar` from the build environment"));
+    }
+}
@@ -0,0 +1,290 @@
+//! Report the results of package tests and recipe lints to CI systems.
+//!
+//! Every result is an [`ReportEntry`]. The entries are handed to all enabled
+//! [`Reporter`]s: a JUnit XML report (`--junit <file>`), which most CI systems can
+//! display, and annotations for GitHub Actions, which show failures inline on the
+//! pull request (enabled with `RATTLER_BUILD_ENABLE_GITHUB_INTEGRATION=true`).
+
+use std::{
+    fmt::Write as _,
+    io::Write as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::console_utils::github_integration_enabled;
+
+/// The outcome of a test or lint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The test passed
+    Passed,
+    /// The test passed, but with a warning
+    Warning(String),
+    /// The test failed with the error
+    Failed(String),
+}
+
+/// The result of a single test or lint.
+#[derive(Debug, Clone)]
+pub struct ReportEntry {
+    /// The name of the test case, e.g. the file name of the package
+    pub name: String,
+    /// The group of the test case, e.g. the name of the package or the recipe
+    pub classname: String,
+    /// The file that the result refers to, e.g. the recipe of a lint
+    pub file: Option<PathBuf>,
+    /// The line and column in the file (1-based)
+    pub location: Option<(usize, usize)>,
+    /// How long the test took
+    pub duration: Duration,
+    /// The outcome of the test
+    pub outcome: Outcome,
+}
+
+impl ReportEntry {
+    /// Returns true if the test failed.
+    pub fn is_failure(&self) -> bool {
+        matches!(self.outcome, Outcome::Failed(_))
+    }
+}
+
+/// Receives the results of a run (e.g. all packages tested by `rattler-build test`).
+pub trait Reporter {
+    /// Report the entries of a test suite.
+    fn report(&self, suite: &str, entries: &[ReportEntry]) -> std::io::Result<()>;
+}
+
+/// Writes a JUnit XML report with a single test suite.
+pub struct JUnitReporter {
+    /// The file to write the report to
+    pub path: PathBuf,
+}
+
+impl Reporter for JUnitReporter {
+    fn report(&self, suite: &str, entries: &[ReportEntry]) -> std::io::Result<()> {
+        fs_err::write(&self.path, render_junit(suite, entries))
+    }
+}
+
+/// Prints GitHub Actions workflow commands (`::error file=...::message`) for failures
+/// and warnings to stderr, so that they do not mix with the output of the command (e.g.
+/// the JSON output of `lint`).
+pub struct GitHubAnnotationsReporter;
+
+impl Reporter for GitHubAnnotationsReporter {
+    fn report(&self, _suite: &str, entries: &[ReportEntry]) -> std::io::Result<()> {
+        let mut stderr = std::io::stderr().lock();
+        for entry in entries {
+            if let Some(annotation) = github_annotation(entry) {
+                writeln!(stderr, "{}", annotation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The reporters for a run: a JUnit report if a path is given, and GitHub annotations
+/// if the GitHub integration is enabled.
+pub fn reporters(junit: Option<&Path>) -> Vec<Box<dyn Reporter>> {
+    let mut reporters: Vec<Box<dyn Reporter>> = Vec::new();
+    if let Some(path) = junit {
+        reporters.push(Box::new(JUnitReporter {
+            path: path.to_path_buf(),
+        }));
+    }
+    if github_integration_enabled() {
+        reporters.push(Box::new(GitHubAnnotationsReporter));
+    }
+    reporters
+}
+
+/// Hand the entries to all reporters.
+pub fn report_all(
+    reporters: &[Box<dyn Reporter>],
+    suite: &str,
+    entries: &[ReportEntry],
+) -> std::io::Result<()> {
+    for reporter in reporters {
+        reporter.report(suite, entries)?;
+    }
+    Ok(())
+}
+
+/// Escape a string for use in XML text and attributes.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // other control characters are not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render the entries as a JUnit XML document with a single test suite.
+pub fn render_junit(suite: &str, entries: &[ReportEntry]) -> String {
+    let failures = entries.iter().filter(|entry| entry.is_failure()).count();
+    let total: Duration = entries.iter().map(|entry| entry.duration).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    // writing to a string cannot fail
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        entries.len(),
+        failures,
+        total.as_secs_f64()
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        escape_xml(suite),
+        entries.len(),
+        failures,
+        total.as_secs_f64()
+    );
+    for entry in entries {
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape_xml(&entry.name),
+            escape_xml(&entry.classname),
+            entry.duration.as_secs_f64()
+        );
+        if let Some(file) = &entry.file {
+            let _ = write!(xml, " file=\"{}\"", escape_xml(&file.to_string_lossy()));
+        }
+        if let Some((line, _)) = entry.location {
+            let _ = write!(xml, " line=\"{}\"", line);
+        }
+        match &entry.outcome {
+            Outcome::Passed => {
+                let _ = writeln!(xml, "/>");
+            }
+            Outcome::Warning(warning) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <system-out>{}</system-out>\n    </testcase>",
+                    escape_xml(warning)
+                );
+            }
+            Outcome::Failed(failure) => {
+                let message = failure.lines().next().unwrap_or_default();
+                let _ = writeln!(
+                    xml,
+                    ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                    escape_xml(message),
+                    escape_xml(failure)
+                );
+            }
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Escape the message of a GitHub workflow command.
+fn escape_github_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a property (e.g. `file` or `title`) of a GitHub workflow command.
+fn escape_github_property(text: &str) -> String {
+    escape_github_data(text)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+/// The GitHub workflow command for a failed test or a warning.
+fn github_annotation(entry: &ReportEntry) -> Option<String> {
+    let (command, message) = match &entry.outcome {
+        Outcome::Passed => return None,
+        Outcome::Warning(message) => ("warning", message),
+        Outcome::Failed(message) => ("error", message),
+    };
+
+    let mut properties = Vec::new();
+    if let Some(file) = &entry.file {
+        properties.push(format!(
+            "file={}",
+            escape_github_property(&file.to_string_lossy())
+        ));
+    }
+    if let Some((line, column)) = entry.location {
+        properties.push(format!("line={}", line));
+        properties.push(format!("col={}", column));
+    }
+    properties.push(format!("title={}", escape_github_property(&entry.name)));
+
+    Some(format!(
+        "::{} {}::{}",
+        command,
+        properties.join(","),
+        escape_github_data(message)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, outcome: Outcome) -> ReportEntry {
+        ReportEntry {
+            name: name.to_string(),
+            classname: "foo".to_string(),
+            file: None,
+            location: None,
+            duration: Duration::from_millis(250),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_render_junit() {
+        let entries = [
+            entry("foo-1.0-h123_0.conda", Outcome::Passed),
+            entry(
+                "foo-1.0-h456_0.conda",
+                Outcome::Failed("expected stdout to contain \"<ok>\"\nmore".to_string()),
+            ),
+        ];
+
+        let xml = render_junit("rattler-build test", &entries);
+        assert!(xml.contains("<testsuites tests=\"2\" failures=\"1\" time=\"0.500\">"));
+        assert!(xml.contains(
+            "<testcase name=\"foo-1.0-h123_0.conda\" classname=\"foo\" time=\"0.250\"/>"
+        ));
+        assert!(
+            xml.contains("<failure message=\"expected stdout to contain &quot;&lt;ok&gt;&quot;\">")
+        );
+        assert!(xml.ends_with("</testsuites>\n"));
+    }
+
+    #[test]
+    fn test_github_annotation() {
+        assert_eq!(github_annotation(&entry("ok", Outcome::Passed)), None);
+
+        let lint = ReportEntry {
+            file: Some(PathBuf::from("recipe/recipe.yaml")),
+            location: Some((12, 5)),
+            ..entry(
+                "unknown_key",
+                Outcome::Failed("unknown key: foo\n50% sure".to_string()),
+            )
+        };
+        assert_eq!(
+            github_annotation(&lint).unwrap(),
+            "::error file=recipe/recipe.yaml,line=12,col=5,title=unknown_key::unknown key: foo%0A50%25 sure"
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Run `rattler-build build` in a container, for the target platforms that have a runner
+//! in the project configuration:
+//!
+//! ```toml
+//! [runner.linux-aarch64]
+//! image = "ghcr.io/my-org/rattler-build-arm:latest"
+//! engine = "podman"
+//! ```
+//!
+//! The command line is run again with the `rattler-build` of the image. The project
+//! directory, the working directory, the output directory and the directories of the
+//! recipes and variant config files are mounted at the same paths, so that the paths on the
+//! command line and in the project configuration stay valid. The variant config of the user
+//! and, for uploads, the credentials file and the credentials in the environment are
+//! forwarded as well.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+use serde::Deserialize;
+
+use crate::{provenance::RUNNER_IMAGE_ENV, variant_config::user_variant_config_file};
+
+/// The environment variable that is set in the container, so that the build is not
+/// dispatched to a container again.
+pub const IN_RUNNER_ENV: &str = "RATTLER_BUILD_IN_RUNNER";
+
+/// The program that runs the containers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerEngine {
+    /// `docker`
+    #[default]
+    Docker,
+    /// `podman`
+    Podman,
+}
+
+impl ContainerEngine {
+    fn program(&self) -> &'static str {
+        match self {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Podman => "podman",
+        }
+    }
+}
+
+/// The container that the builds for a target platform run in.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunnerConfig {
+    /// The image, which must contain `rattler-build`
+    pub image: String,
+    /// The program that runs the container
+    #[serde(default)]
+    pub engine: ContainerEngine,
+    /// Additional arguments for `<engine> run` (e.g. `["--gpus", "all"]`)
+    #[serde(default)]
+    pub run_args: Vec<String>,
+}
+
+/// The environment variables with upload credentials that are forwarded to the container.
+const UPLOAD_ENV_VARS: [&str; 6] = [
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "AWS_ENDPOINT_URL",
+];
+
+/// What the build in the container needs from the host, besides the project, working and
+/// output directories.
+#[derive(Debug, Clone, Default)]
+pub struct HostInputs {
+    /// The files and directories of the command line (recipes, variant configs). Their
+    /// directories are mounted.
+    pub paths: Vec<PathBuf>,
+    /// Whether the packages are uploaded, so that the credentials are forwarded
+    pub upload: bool,
+    /// The credentials file (`--auth-file`)
+    pub auth_file: Option<PathBuf>,
+}
+
+/// An environment variable of the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerEnv {
+    /// The variable with a value
+    Set(String, OsString),
+    /// The variable with the value of the host, which does not end up on the command line
+    Forward(String),
+}
+
+/// Whether this process runs in a runner container.
+pub fn in_runner() -> bool {
+    std::env::var_os(IN_RUNNER_ENV).is_some()
+}
+
+/// The platform of the container engine for a target platform (e.g. `linux/arm64`).
+fn container_platform(platform: Platform) -> Option<&'static str> {
+    match platform {
+        Platform::Linux64 => Some("linux/amd64"),
+        Platform::LinuxAarch64 => Some("linux/arm64"),
+        Platform::LinuxPpc64le => Some("linux/ppc64le"),
+        Platform::LinuxS390X => Some("linux/s390x"),
+        _ => None,
+    }
+}
+
+/// The directories to mount, without the ones that are inside another mounted directory.
+fn mounts(directories: &[&Path]) -> Vec<PathBuf> {
+    let mut mounts: Vec<PathBuf> = Vec::new();
+    let mut directories = directories.to_vec();
+    directories.sort_by_key(|dir| dir.components().count());
+    for dir in directories {
+        if !mounts.iter().any(|mount| dir.starts_with(mount)) {
+            mounts.push(dir.to_path_buf());
+        }
+    }
+    mounts
+}
+
+/// The command that runs `rattler-build` with the given arguments in the container.
+pub fn container_command(
+    runner: &RunnerConfig,
+    target_platform: Platform,
+    directories: &[&Path],
+    working_dir: &Path,
+    env: &[ContainerEnv],
+    args: &[OsString],
+) -> Command {
+    let mut command = Command::new(runner.engine.program());
+    command.args(["run", "--rm", "--interactive"]);
+    if let Some(platform) = container_platform(target_platform) {
+        command.args(["--platform", platform]);
+    }
+    for mount in mounts(directories) {
+        let mut volume = mount.clone().into_os_string();
+        volume.push(":");
+        volume.push(&mount);
+        command.arg("--volume").arg(volume);
+    }
+    command.arg("--workdir").arg(working_dir);
+    command.args(["--env", &format!("{}=1", IN_RUNNER_ENV)]);
+    command.args(["--env", &format!("{}={}", RUNNER_IMAGE_ENV, runner.image)]);
+    for var in env {
+        match var {
+            ContainerEnv::Set(name, value) => {
+                let mut var = OsString::from(format!("{}=", name));
+                var.push(value);
+                command.arg("--env").arg(var);
+            }
+            ContainerEnv::Forward(name) => {
+                command.args(["--env", name]);
+            }
+        }
+    }
+    command.args(&runner.run_args);
+    command.arg(&runner.image).arg("rattler-build").args(args);
+    command
+}
+
+/// The directory to mount for a file or directory of the command line, if it exists.
+fn host_directory(path: &Path) -> Option<PathBuf> {
+    let path = dunce::canonicalize(path).ok()?;
+    if path.is_file() {
+        path.parent().map(Path::to_path_buf)
+    } else {
+        Some(path)
+    }
+}
+
+/// The credentials file that is used for uploads: `--auth-file`, or the file that is
+/// used besides the keyring (`~/.rattler/credentials.json`).
+fn credentials_file(auth_file: Option<&Path>) -> Option<PathBuf> {
+    match auth_file {
+        Some(path) => dunce::canonicalize(path).ok(),
+        None => dirs::home_dir()
+            .map(|home| home.join(".rattler").join("credentials.json"))
+            .filter(|path| path.is_file()),
+    }
+}
+
+/// The directories to mount and the environment variables to set for the inputs of the
+/// build. The credentials in the keyring are not available in the container, so an upload
+/// without a credentials file or credentials in the environment is refused.
+fn host_mounts(inputs: &HostInputs) -> miette::Result<(Vec<PathBuf>, Vec<ContainerEnv>)> {
+    let mut directories = inputs
+        .paths
+        .iter()
+        .filter_map(|path| host_directory(path))
+        .collect::<Vec<_>>();
+    let mut env = Vec::new();
+
+    if let Some(variant_config) = user_variant_config_file() {
+        // `<config dir>/rattler-build/variants.yaml`
+        let rattler_build_dir = variant_config.parent().expect("the file is in a directory");
+        let config_dir = rattler_build_dir
+            .parent()
+            .expect("the file is in a directory");
+        directories.push(rattler_build_dir.to_path_buf());
+        env.push(ContainerEnv::Set(
+            "XDG_CONFIG_HOME".to_string(),
+            config_dir.as_os_str().to_owned(),
+        ));
+    }
+
+    if inputs.upload {
+        let credentials_file = credentials_file(inputs.auth_file.as_deref());
+        let forwarded = UPLOAD_ENV_VARS
+            .iter()
+            .filter(|name| std::env::var_os(name).is_some())
+            .map(|name| ContainerEnv::Forward(name.to_string()))
+            .collect::<Vec<_>>();
+        if credentials_file.is_none() && forwarded.is_empty() {
+            return Err(miette::miette!(
+                help = "pass `--auth-file` (or set `RATTLER_AUTH_FILE`) to use a credentials file",
+                "the credentials in the keyring are not available in the runner container"
+            ));
+        }
+        if let Some(path) = credentials_file {
+            directories.push(
+                path.parent()
+                    .expect("the file is in a directory")
+                    .to_path_buf(),
+            );
+            env.push(ContainerEnv::Set(
+                "RATTLER_AUTH_FILE".to_string(),
+                path.into_os_string(),
+            ));
+        }
+        env.extend(forwarded);
+    }
+    Ok((directories, env))
+}
+
+/// Run the current command line in the container of the runner. The project, working
+/// and output directories and the inputs of the host are mounted.
+pub fn run_in_container(
+    runner: &RunnerConfig,
+    target_platform: Platform,
+    project_dir: &Path,
+    output_dir: &Path,
+    inputs: &HostInputs,
+) -> miette::Result<()> {
+    let working_dir = std::env::current_dir().into_diagnostic()?;
+    fs_err::create_dir_all(output_dir).into_diagnostic()?;
+    let output_dir = dunce::canonicalize(output_dir).into_diagnostic()?;
+    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+
+    let (host_directories, env) = host_mounts(inputs)?;
+    let mut directories = vec![project_dir, &working_dir, &output_dir];
+    directories.extend(host_directories.iter().map(PathBuf::as_path));
+    let mut command = container_command(
+        runner,
+        target_platform,
+        &directories,
+        &working_dir,
+        &env,
+        &args,
+    );
+    tracing::info!(
+        "Building for {} in the container {} ({})",
+        target_platform,
+        runner.image,
+        runner.engine.program()
+    );
+    let status = command.status().map_err(|e| {
+        miette::miette!(
+            help = "install it, or remove the runner from the project configuration",
+            "failed to run `{}`: {}",
+            runner.engine.program(),
+            e
+        )
+    })?;
+    if !status.success() {
+        miette::bail!(
+            "the build in the container {} failed ({})",
+            runner.image,
+            status
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let recipe = dir.path().join("recipe.yaml");
+        fs_err::write(&recipe, "").unwrap();
+        let dir = dunce::canonicalize(dir.path()).unwrap();
+        assert_eq!(host_directory(&recipe), Some(dir.clone()));
+        assert_eq!(host_directory(&dir), Some(dir.clone()));
+        assert_eq!(host_directory(&dir.join("missing.yaml")), None);
+    }
+
+    #[test]
+    fn test_container_command() {
+        let runner: RunnerConfig =
+            toml::from_str("image = \"ghcr.io/org/builder:1\"\nrun_args = [\"--gpus\", \"all\"]")
+                .unwrap();
+        let command = container_command(
+            &runner,
+            Platform::LinuxAarch64,
+            &[
+                Path::new("/repo/output"),
+                Path::new("/repo"),
+                Path::new("/repo/recipes"),
+                Path::new("/home/user/variants"),
+            ],
+            Path::new("/repo/recipes"),
+            &[
+                ContainerEnv::Set(
+                    "RATTLER_AUTH_FILE".to_string(),
+                    "/home/user/auth.json".into(),
+                ),
+                ContainerEnv::Forward("AWS_SECRET_ACCESS_KEY".to_string()),
+            ],
+            &[
+                "build".into(),
+                "--target-platform".into(),
+                "linux-aarch64".into(),
+            ],
+        );
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(command.get_program(), "docker");
+        assert_eq!(
+            args,
+            [
+                "run",
+                "--rm",
+                "--interactive",
+                "--platform",
+                "linux/arm64",
+                "--volume",
+                "/repo:/repo",
+                "--volume",
+                "/home/user/variants:/home/user/variants",
+                "--workdir",
+                "/repo/recipes",
+                "--env",
+                "RATTLER_BUILD_IN_RUNNER=1",
+                "--env",
+                "RATTLER_BUILD_RUNNER_IMAGE=ghcr.io/org/builder:1",
+                "--env",
+                "RATTLER_AUTH_FILE=/home/user/auth.json",
+                "--env",
+                "AWS_SECRET_ACCESS_KEY",
+                "--gpus",
+                "all",
+                "ghcr.io/org/builder:1",
+                "rattler-build",
+                "build",
+                "--target-platform",
+                "linux-aarch64"
+            ]
+        );
+    }
+}
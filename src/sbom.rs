@@ -0,0 +1,589 @@
+//! Software bill of materials (SBOM) documents for built packages.
+//!
+//! With `--sbom spdx` or `--sbom cyclonedx`, every package gets an SBOM that records the
+//! resolved packages of the build and host environments (with their channels, urls and
+//! hashes), the run requirements, the sources of the recipe and the environment the
+//! package was built in. The document is stored in the package (`info/sbom.spdx.json` or
+//! `info/sbom.cdx.json`) and written next to the package file.
+
+use clap::ValueEnum;
+use rattler_conda_types::RepoDataRecord;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    metadata::Output, packaging::PackagingError, recipe::parser::Source,
+    render::resolved_dependencies::DependencyInfo,
+};
+
+/// The format of the SBOM document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    /// SPDX 2.3 (JSON)
+    Spdx,
+    /// CycloneDX 1.5 (JSON)
+    #[value(name = "cyclonedx")]
+    CycloneDx,
+}
+
+impl SbomFormat {
+    /// The file name of the document, in `info/` and as suffix of the package name.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            SbomFormat::Spdx => "sbom.spdx.json",
+            SbomFormat::CycloneDx => "sbom.cdx.json",
+        }
+    }
+}
+
+/// What a component of the SBOM is to the package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Relationship {
+    /// A package of the build environment
+    Build,
+    /// A package of the host environment
+    Host,
+    /// A run requirement (a spec, resolved when the package is installed)
+    Run,
+    /// A source of the recipe
+    Source,
+}
+
+impl Relationship {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Relationship::Build => "build",
+            Relationship::Host => "host",
+            Relationship::Run => "run",
+            Relationship::Source => "source",
+        }
+    }
+}
+
+/// A package or source that the package was built from or depends on.
+#[derive(Debug, Clone)]
+struct Component {
+    relationship: Relationship,
+    /// A reference that is unique within the document
+    reference: String,
+    name: String,
+    version: Option<String>,
+    purl: Option<String>,
+    /// Where the component was downloaded from
+    location: Option<String>,
+    /// The channel of a package
+    channel: Option<String>,
+    sha256: Option<String>,
+    md5: Option<String>,
+    /// The match spec of a run requirement or the revision of a source
+    comment: Option<String>,
+}
+
+/// The package url of a conda package.
+fn conda_purl(
+    name: &str,
+    version: &str,
+    build: &str,
+    subdir: &str,
+    channel: Option<&str>,
+) -> String {
+    let mut purl = format!("pkg:conda/{}@{}?build={}", name, version, build);
+    if let Some(channel) = channel {
+        purl.push_str("&channel=");
+        purl.extend(url::form_urlencoded::byte_serialize(channel.as_bytes()));
+    }
+    purl.push_str(&format!("&subdir={}", subdir));
+    purl
+}
+
+fn record_component(record: &RepoDataRecord, relationship: Relationship) -> Component {
+    let package = &record.package_record;
+    Component {
+        relationship,
+        reference: format!("{}/{}", relationship.as_str(), record.file_name),
+        name: package.name.as_normalized().to_string(),
+        version: Some(package.version.to_string()),
+        purl: Some(conda_purl(
+            package.name.as_normalized(),
+            &package.version.to_string(),
+            &package.build,
+            &package.subdir,
+            Some(&record.channel),
+        )),
+        location: Some(record.url.to_string()),
+        channel: Some(record.channel.clone()),
+        sha256: package.sha256.map(|hash| format!("{:x}", hash)),
+        md5: package.md5.map(|hash| format!("{:x}", hash)),
+        comment: None,
+    }
+}
+
+fn run_component(dependency: &DependencyInfo, index: usize) -> Component {
+    let spec = dependency.spec();
+    let name = spec
+        .name
+        .as_ref()
+        .map(|name| name.as_normalized().to_string())
+        .unwrap_or_else(|| spec.to_string());
+    Component {
+        relationship: Relationship::Run,
+        reference: format!("run/{}-{}", index, name),
+        purl: Some(format!("pkg:conda/{}", name)),
+        name,
+        version: spec.version.as_ref().map(|version| version.to_string()),
+        location: None,
+        channel: None,
+        sha256: None,
+        md5: None,
+        comment: Some(spec.to_string()),
+    }
+}
+
+fn source_component(source: &Source, index: usize) -> Component {
+    let (name, location, comment, sha256, md5) = match source {
+        Source::Url(url) => {
+            let location = url.urls().first().map(|url| url.to_string());
+            let name = url
+                .urls()
+                .first()
+                .and_then(|url| url.path_segments()?.last().map(str::to_string))
+                .unwrap_or_else(|| format!("source-{}", index));
+            (
+                name,
+                location,
+                None,
+                url.sha256().map(|hash| format!("{:x}", hash)),
+                url.md5().map(|hash| format!("{:x}", hash)),
+            )
+        }
+        Source::Git(git) => (
+            git.url().to_string(),
+            Some(format!("git+{}", git.url())),
+            Some(format!("rev: {}", git.rev().to_string())),
+            None,
+            None,
+        ),
+        Source::Hg(hg) => (
+            hg.url().to_string(),
+            Some(format!("hg+{}", hg.url())),
+            Some(format!("rev: {}", hg.rev())),
+            None,
+            None,
+        ),
+        Source::Svn(svn) => (
+            svn.url().to_string(),
+            Some(format!("svn+{}", svn.url())),
+            Some(format!("rev: {}", svn.rev())),
+            None,
+            None,
+        ),
+        Source::Oci(oci) => (
+            oci.reference().to_string(),
+            Some(format!("oci://{}", oci.reference())),
+            None,
+            oci.reference()
+                .digest
+                .as_ref()
+                .map(|hash| format!("{:x}", hash)),
+            None,
+        ),
+        Source::Path(path) => (
+            path.path().display().to_string(),
+            None,
+            Some("local path".to_string()),
+            None,
+            None,
+        ),
+    };
+    Component {
+        relationship: Relationship::Source,
+        reference: format!("source/{}", index),
+        name,
+        version: None,
+        purl: None,
+        location,
+        channel: None,
+        sha256,
+        md5,
+        comment,
+    }
+}
+
+/// Collect the components of the SBOM of an output.
+fn components(output: &Output) -> Result<Vec<Component>, PackagingError> {
+    let dependencies = output
+        .finalized_dependencies
+        .as_ref()
+        .ok_or(PackagingError::DependenciesNotFinalized)?;
+
+    let mut components = Vec::new();
+    let environments = [
+        (dependencies.build.as_ref(), Relationship::Build),
+        (dependencies.host.as_ref(), Relationship::Host),
+    ];
+    for (environment, relationship) in environments {
+        if let Some(environment) = environment {
+            components.extend(
+                environment
+                    .resolved
+                    .iter()
+                    .map(|record| record_component(record, relationship)),
+            );
+        }
+    }
+    components.extend(
+        dependencies
+            .run
+            .depends
+            .iter()
+            .enumerate()
+            .map(|(index, dependency)| run_component(dependency, index)),
+    );
+    let sources = output
+        .finalized_sources
+        .as_deref()
+        .unwrap_or_else(|| output.recipe.sources());
+    components.extend(
+        sources
+            .iter()
+            .enumerate()
+            .map(|(index, source)| source_component(source, index)),
+    );
+    Ok(components)
+}
+
+/// The build environment as key / value pairs.
+fn build_environment(output: &Output) -> Vec<(String, String)> {
+    let configuration = &output.build_configuration;
+    let mut environment = vec![
+        (
+            "rattler-build:version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        (
+            "rattler-build:build_platform".to_string(),
+            configuration.build_platform.to_string(),
+        ),
+        (
+            "rattler-build:host_platform".to_string(),
+            configuration.host_platform.to_string(),
+        ),
+        (
+            "rattler-build:target_platform".to_string(),
+            configuration.target_platform.to_string(),
+        ),
+    ];
+    environment.extend(
+        configuration
+            .channels
+            .iter()
+            .map(|channel| ("rattler-build:channel".to_string(), channel.to_string())),
+    );
+    environment.extend(
+        configuration
+            .variant
+            .iter()
+            .map(|(key, value)| (format!("rattler-build:variant:{}", key), value.clone())),
+    );
+    environment
+}
+
+/// Create the SBOM document of an output in the given format.
+pub fn sbom(output: &Output, format: SbomFormat) -> Result<Value, PackagingError> {
+    let identifier = output
+        .identifier()
+        .ok_or(PackagingError::BuildStringNotSet)?;
+    let components = components(output)?;
+    Ok(match format {
+        SbomFormat::Spdx => spdx(output, &identifier, &components),
+        SbomFormat::CycloneDx => cyclonedx(output, &identifier, &components),
+    })
+}
+
+/// The package url of the output.
+fn output_purl(output: &Output) -> String {
+    conda_purl(
+        output.name().as_normalized(),
+        output.version(),
+        output.build_string().unwrap_or_default(),
+        &output.build_configuration.target_platform.to_string(),
+        output
+            .build_configuration
+            .upload_to
+            .as_ref()
+            .map(|url| url.as_str()),
+    )
+}
+
+/// An SPDX identifier may only contain letters, numbers, `.` and `-`.
+fn spdx_id(reference: &str) -> String {
+    let id: String = reference
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-{}", id)
+}
+
+fn spdx(output: &Output, identifier: &str, components: &[Component]) -> Value {
+    let created = output
+        .build_configuration
+        .timestamp
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string();
+    let environment = build_environment(output)
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut packages = vec![json!({
+        "SPDXID": "SPDXRef-Package",
+        "name": output.name().as_normalized(),
+        "versionInfo": output.version(),
+        "downloadLocation": "NOASSERTION",
+        "filesAnalyzed": false,
+        "licenseConcluded": "NOASSERTION",
+        "licenseDeclared": output
+            .recipe
+            .about()
+            .license
+            .as_ref()
+            .map(|license| license.to_string())
+            .unwrap_or_else(|| "NOASSERTION".to_string()),
+        "copyrightText": "NOASSERTION",
+        "externalRefs": [{
+            "referenceCategory": "PACKAGE-MANAGER",
+            "referenceType": "purl",
+            "referenceLocator": output_purl(output),
+        }],
+        "primaryPackagePurpose": "LIBRARY",
+    })];
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": "SPDXRef-Package",
+    })];
+
+    for component in components {
+        let id = spdx_id(&component.reference);
+        let mut package = json!({
+            "SPDXID": id,
+            "name": component.name,
+            "downloadLocation": component.location.as_deref().unwrap_or("NOASSERTION"),
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": "NOASSERTION",
+            "copyrightText": "NOASSERTION",
+        });
+        if let Some(version) = &component.version {
+            package["versionInfo"] = json!(version);
+        }
+        if let Some(channel) = &component.channel {
+            package["supplier"] = json!(format!("Organization: {}", channel));
+        }
+        let checksums = [("SHA256", &component.sha256), ("MD5", &component.md5)]
+            .into_iter()
+            .filter_map(|(algorithm, value)| {
+                value
+                    .as_ref()
+                    .map(|value| json!({ "algorithm": algorithm, "checksumValue": value }))
+            })
+            .collect::<Vec<_>>();
+        if !checksums.is_empty() {
+            package["checksums"] = json!(checksums);
+        }
+        if let Some(purl) = &component.purl {
+            package["externalRefs"] = json!([{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl,
+            }]);
+        }
+        let comment = match (&component.comment, component.relationship) {
+            (Some(comment), _) => Some(comment.clone()),
+            (None, Relationship::Build) => Some("build environment".to_string()),
+            (None, Relationship::Host) => Some("host environment".to_string()),
+            _ => None,
+        };
+        if let Some(comment) = comment {
+            package["comment"] = json!(comment);
+        }
+        if component.relationship == Relationship::Source {
+            package["primaryPackagePurpose"] = json!("SOURCE");
+        }
+        packages.push(package);
+
+        let (element, relationship_type, related) = match component.relationship {
+            Relationship::Build | Relationship::Host => {
+                (id.as_str(), "BUILD_DEPENDENCY_OF", "SPDXRef-Package")
+            }
+            Relationship::Run => (id.as_str(), "RUNTIME_DEPENDENCY_OF", "SPDXRef-Package"),
+            Relationship::Source => ("SPDXRef-Package", "GENERATED_FROM", id.as_str()),
+        };
+        relationships.push(json!({
+            "spdxElementId": element,
+            "relationshipType": relationship_type,
+            "relatedSpdxElement": related,
+        }));
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": identifier,
+        "documentNamespace": format!(
+            "https://github.com/prefix-dev/rattler-build/spdx/{}-{}",
+            identifier,
+            output.build_configuration.timestamp.timestamp()
+        ),
+        "creationInfo": {
+            "created": created,
+            "creators": [format!("Tool: rattler-build-{}", env!("CARGO_PKG_VERSION"))],
+            "comment": environment,
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+fn cyclonedx(output: &Output, identifier: &str, components: &[Component]) -> Value {
+    let properties = |pairs: Vec<(String, String)>| {
+        pairs
+            .into_iter()
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect::<Vec<_>>()
+    };
+
+    let mut main_component = json!({
+        "type": "library",
+        "bom-ref": identifier,
+        "name": output.name().as_normalized(),
+        "version": output.version(),
+        "purl": output_purl(output),
+    });
+    if let Some(license) = &output.recipe.about().license {
+        main_component["licenses"] = json!([{ "expression": license.to_string() }]);
+    }
+
+    let mut bom_components = Vec::new();
+    let mut depends_on = Vec::new();
+    for component in components {
+        let is_source = component.relationship == Relationship::Source;
+        let component_type = if is_source { "file" } else { "library" };
+        // only the run requirements end up in the environment of the package
+        let scope = if component.relationship == Relationship::Run {
+            "required"
+        } else {
+            "excluded"
+        };
+        let mut bom_component = json!({
+            "type": component_type,
+            "bom-ref": component.reference,
+            "name": component.name,
+            "scope": scope,
+        });
+        if let Some(version) = &component.version {
+            bom_component["version"] = json!(version);
+        }
+        if let Some(purl) = &component.purl {
+            bom_component["purl"] = json!(purl);
+        }
+        let hashes = [("SHA-256", &component.sha256), ("MD5", &component.md5)]
+            .into_iter()
+            .filter_map(|(alg, value)| {
+                value
+                    .as_ref()
+                    .map(|value| json!({ "alg": alg, "content": value }))
+            })
+            .collect::<Vec<_>>();
+        if !hashes.is_empty() {
+            bom_component["hashes"] = json!(hashes);
+        }
+        if let Some(location) = &component.location {
+            let reference_type = match (is_source, location.split_once('+')) {
+                (true, Some(("git" | "hg" | "svn", _))) => "vcs",
+                (true, _) => "source-distribution",
+                (false, _) => "distribution",
+            };
+            bom_component["externalReferences"] =
+                json!([{ "type": reference_type, "url": location }]);
+        }
+        let mut component_properties = vec![(
+            "rattler-build:relationship".to_string(),
+            component.relationship.as_str().to_string(),
+        )];
+        if let Some(channel) = &component.channel {
+            component_properties.push(("rattler-build:channel".to_string(), channel.clone()));
+        }
+        if let Some(comment) = &component.comment {
+            let name = if component.relationship == Relationship::Run {
+                "rattler-build:spec"
+            } else {
+                "rattler-build:comment"
+            };
+            component_properties.push((name.to_string(), comment.clone()));
+        }
+        bom_component["properties"] = json!(properties(component_properties));
+        bom_components.push(bom_component);
+        depends_on.push(component.reference.clone());
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": output
+                .build_configuration
+                .timestamp
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "rattler-build",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": main_component,
+            "properties": properties(build_environment(output)),
+        },
+        "components": bom_components,
+        "dependencies": [{ "ref": identifier, "dependsOn": depends_on }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conda_purl() {
+        assert_eq!(
+            conda_purl(
+                "zlib",
+                "1.3.1",
+                "hb9d3cd8_2",
+                "linux-64",
+                Some("https://conda.anaconda.org/conda-forge/")
+            ),
+            "pkg:conda/zlib@1.3.1?build=hb9d3cd8_2&channel=https%3A%2F%2Fconda.anaconda.org%2Fconda-forge%2F&subdir=linux-64"
+        );
+    }
+
+    #[test]
+    fn test_spdx_id() {
+        assert_eq!(
+            spdx_id("host/python-3.12.0-h123_0_cpython.conda"),
+            "SPDXRef-host-python-3.12.0-h123-0-cpython.conda"
+        );
+    }
+}
@@ -9,30 +9,116 @@ use rattler_shell::{
 use std::{
     borrow::Cow,
     collections::HashMap,
-    io::ErrorKind,
+    fmt,
+    io::{ErrorKind, IsTerminal, Write},
     path::{Path, PathBuf},
     process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 use tokio::io::AsyncBufReadExt as _;
 
 use crate::{
+    console_utils::LoggingOutputHandler,
     env_vars::{self},
     metadata::Output,
-    recipe::parser::{Script, ScriptContent},
+    recipe::parser::{InheritEnvironment, Script, ScriptContent, ScriptPhase, ScriptShell},
 };
 
 const BASH_PREAMBLE: &str = r#"
 ## Start of bash preamble
 if [ -z ${CONDA_BUILD+x} ]; then
-    source ((script_path))
+    . ((script_path))
 fi
 # enable debug mode for the rest of the script
 set -x
 ## End of preamble
 "#;
 
+/// The stream that a line of script output was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputStream::Stdout => write!(f, "stdout"),
+            OutputStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// A line of output of a script (with the prefixes replaced).
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    /// The stream that the line was written to
+    pub stream: OutputStream,
+    /// When the line was read
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The line, without the line ending
+    pub line: String,
+}
+
+/// Receives the output of the build and test scripts line by line. Besides the sinks
+/// of rattler-build (the log and `conda_build.log`), library users can pass their own
+/// sink with [`crate::tool_configuration::Configuration::log_sink`], e.g. a closure that
+/// sends the lines to a channel:
+///
+/// ```rust,ignore
+/// let (sender, receiver) = std::sync::mpsc::channel();
+/// let sink = move |line: &LogLine| {
+///     let _ = sender.send(line.clone());
+/// };
+/// ```
+pub trait LogSink: Send + Sync {
+    /// Called for every line of output
+    fn log_line(&self, line: &LogLine);
+}
+
+impl<F: Fn(&LogLine) + Send + Sync> LogSink for F {
+    fn log_line(&self, line: &LogLine) {
+        self(line)
+    }
+}
+
+/// Writes the output to the log of rattler-build.
+pub struct TracingSink;
+
+impl LogSink for TracingSink {
+    fn log_line(&self, line: &LogLine) {
+        tracing::info!("{}", line.line);
+    }
+}
+
+/// Writes the output to a file, e.g. the `conda_build.log` in the work directory.
+pub struct FileSink {
+    file: Mutex<fs_err::File>,
+}
+
+impl FileSink {
+    /// Create (or truncate) the file.
+    pub fn create(path: &Path) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            file: Mutex::new(fs_err::File::create(path)?),
+        })
+    }
+}
+
+impl LogSink for FileSink {
+    fn log_line(&self, line: &LogLine) {
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = writeln!(file, "{}", line.line) {
+            tracing::warn!("Could not write to the log file: {}", err);
+        }
+    }
+}
+
 const DEBUG_HELP : &str  = "To debug the build, run it manually in the work directory (execute the `./conda_build.sh` or `conda_build.bat` script)";
 
+#[derive(Clone)]
 pub struct ExecutionArgs {
     pub script: String,
     pub env_vars: IndexMap<String, String>,
@@ -44,6 +130,18 @@ pub struct ExecutionArgs {
     pub run_prefix: PathBuf,
 
     pub work_dir: PathBuf,
+
+    pub timeout: Option<Duration>,
+
+    /// The phase of the script, which is added to the names of the written scripts
+    pub phase: Option<ScriptPhase>,
+
+    /// The environment variables of rattler-build that the script inherits, or `None`
+    /// to inherit the whole environment.
+    pub inherited_env: Option<HashMap<String, String>>,
+
+    /// The sinks that receive the output of the script
+    pub log_sinks: Vec<Arc<dyn LogSink>>,
 }
 
 impl ExecutionArgs {
@@ -69,20 +167,38 @@ impl ExecutionArgs {
 
         replacements
     }
+
+    /// The paths of the script that sets up the build environment and of the build
+    /// script with the given extension, e.g. `build_env.sh` and `conda_build.sh`. The
+    /// phase is added to the names, e.g. `conda_build_configure.sh`.
+    fn script_paths(&self, extension: &str) -> (PathBuf, PathBuf) {
+        let suffix = self
+            .phase
+            .map(|phase| format!("_{phase}"))
+            .unwrap_or_default();
+        (
+            self.work_dir.join(format!("build_env{suffix}.{extension}")),
+            self.work_dir
+                .join(format!("conda_build{suffix}.{extension}")),
+        )
+    }
 }
 
 trait Interpreter {
-    fn get_script<T: Shell + Copy + 'static>(
+    fn get_script<T: Shell + Clone + 'static>(
         &self,
         args: &ExecutionArgs,
         shell_type: T,
     ) -> Result<String, ActivationError> {
-        let mut shell_script = shell::ShellScript::new(shell_type, Platform::current());
+        let mut shell_script = shell::ShellScript::new(shell_type.clone(), Platform::current());
         for (k, v) in args.env_vars.iter() {
             shell_script.set_env_var(k, v)?;
         }
-        let host_prefix_activator =
-            Activator::from_path(&args.run_prefix, shell_type, args.execution_platform)?;
+        let host_prefix_activator = Activator::from_path(
+            &args.run_prefix,
+            shell_type.clone(),
+            args.execution_platform,
+        )?;
 
         let current_path = std::env::var("PATH")
             .ok()
@@ -117,17 +233,29 @@ trait Interpreter {
         Ok(shell_script.contents()?)
     }
 
-    async fn run(&self, args: ExecutionArgs) -> Result<(), std::io::Error>;
+    /// Write the activation and build scripts to the work directory and return the
+    /// path of the build script.
+    async fn write_scripts(&self, args: &ExecutionArgs) -> Result<PathBuf, std::io::Error>;
+
+    /// Run the script and return its exit status and output (with the prefixes replaced).
+    async fn execute(&self, args: ExecutionArgs) -> Result<std::process::Output, std::io::Error>;
 }
 
-struct BashInterpreter;
+/// Runs scripts with a POSIX shell (`bash`, `zsh` or `dash`).
+struct PosixShellInterpreter {
+    shell: ScriptShell,
+    executable: PathBuf,
+}
 
-impl Interpreter for BashInterpreter {
-    async fn run(&self, args: ExecutionArgs) -> Result<(), std::io::Error> {
-        let script = self.get_script(&args, shell::Bash).unwrap();
+impl Interpreter for PosixShellInterpreter {
+    async fn write_scripts(&self, args: &ExecutionArgs) -> Result<PathBuf, std::io::Error> {
+        // the activation scripts for bash are POSIX compatible, so they also work with dash
+        let script = match self.shell {
+            ScriptShell::Zsh => self.get_script(args, shell::Zsh).unwrap(),
+            _ => self.get_script(args, shell::Bash).unwrap(),
+        };
 
-        let build_env_path = args.work_dir.join("build_env.sh");
-        let build_script_path = args.work_dir.join("conda_build.sh");
+        let (build_env_path, build_script_path) = args.script_paths("sh");
 
         tokio::fs::write(&build_env_path, script).await?;
 
@@ -135,29 +263,25 @@ impl Interpreter for BashInterpreter {
         let script = format!("{}\n{}", preamble, args.script);
         tokio::fs::write(&build_script_path, script).await?;
 
+        Ok(build_script_path)
+    }
+
+    async fn execute(&self, args: ExecutionArgs) -> Result<std::process::Output, std::io::Error> {
+        let build_script_path = self.write_scripts(&args).await?;
+
         let build_script_path_str = build_script_path.to_string_lossy().to_string();
-        let cmd_args = ["bash", "-e", &build_script_path_str];
+        let executable = self.executable.to_string_lossy().to_string();
+        let cmd_args = [executable.as_str(), "-e", &build_script_path_str];
 
-        let output = run_process_with_replacements(
+        run_process_with_replacements(
             &cmd_args,
             &args.work_dir,
             &args.replacements("$((var))"),
+            args.timeout,
+            args.inherited_env.as_ref(),
+            &args.log_sinks,
         )
-        .await?;
-
-        if !output.status.success() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Script failed with status {:?}.\nWork directory: {:?}\n{}",
-                    output.status.code(),
-                    args.work_dir,
-                    DEBUG_HELP
-                ),
-            ));
-        }
-
-        Ok(())
+        .await
     }
 }
 
@@ -168,14 +292,15 @@ IF "%CONDA_BUILD%" == "" (
 )
 "#;
 
-struct CmdExeInterpreter;
+struct CmdExeInterpreter {
+    executable: PathBuf,
+}
 
 impl Interpreter for CmdExeInterpreter {
-    async fn run(&self, args: ExecutionArgs) -> Result<(), std::io::Error> {
-        let script = self.get_script(&args, shell::CmdExe).unwrap();
+    async fn write_scripts(&self, args: &ExecutionArgs) -> Result<PathBuf, std::io::Error> {
+        let script = self.get_script(args, shell::CmdExe).unwrap();
 
-        let build_env_path = args.work_dir.join("build_env.bat");
-        let build_script_path = args.work_dir.join("conda_build.bat");
+        let (build_env_path, build_script_path) = args.script_paths("bat");
 
         tokio::fs::write(&build_env_path, script).await?;
 
@@ -190,57 +315,180 @@ impl Interpreter for CmdExeInterpreter {
         )
         .await?;
 
+        Ok(build_script_path)
+    }
+
+    async fn execute(&self, args: ExecutionArgs) -> Result<std::process::Output, std::io::Error> {
+        let build_script_path = self.write_scripts(&args).await?;
+
         let build_script_path_str = build_script_path.to_string_lossy().to_string();
-        let cmd_args = ["cmd.exe", "/d", "/c", &build_script_path_str];
+        let executable = self.executable.to_string_lossy().to_string();
+        let cmd_args = [executable.as_str(), "/d", "/c", &build_script_path_str];
 
-        let output = run_process_with_replacements(
+        run_process_with_replacements(
             &cmd_args,
             &args.work_dir,
             &args.replacements("%((var))%"),
+            args.timeout,
+            args.inherited_env.as_ref(),
+            &args.log_sinks,
         )
-        .await?;
+        .await
+    }
+}
 
-        if !output.status.success() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Script failed with status {:?}.\nWork directory: {:?}\n{}",
-                    output.status.code(),
-                    args.work_dir,
-                    DEBUG_HELP
-                ),
-            ));
+const POWERSHELL_PREAMBLE: &str = r#"
+if (-not $env:CONDA_BUILD) {
+    . "((script_path))"
+}
+"#;
+
+/// Makes `pwsh` stop at the first failing command (including native commands that exit
+/// with a non-zero code). Windows PowerShell turns the stderr output of native commands
+/// into errors, so this is not set there.
+const PWSH_STRICT_PREAMBLE: &str = r#"
+$ErrorActionPreference = "Stop"
+$PSNativeCommandUseErrorActionPreference = $true
+"#;
+
+/// Runs scripts with Windows PowerShell or PowerShell (Core).
+struct PowerShellInterpreter {
+    executable: PathBuf,
+    /// Whether to stop at the first error (only with `pwsh`)
+    strict: bool,
+}
+
+impl Interpreter for PowerShellInterpreter {
+    async fn write_scripts(&self, args: &ExecutionArgs) -> Result<PathBuf, std::io::Error> {
+        let script = self.get_script(args, shell::PowerShell::default()).unwrap();
+
+        let (build_env_path, build_script_path) = args.script_paths("ps1");
+
+        tokio::fs::write(&build_env_path, script).await?;
+
+        let build_script = format!(
+            "{}{}\n{}",
+            if self.strict {
+                PWSH_STRICT_PREAMBLE
+            } else {
+                ""
+            },
+            POWERSHELL_PREAMBLE.replace("((script_path))", &build_env_path.to_string_lossy()),
+            args.script
+        );
+        tokio::fs::write(&build_script_path, build_script).await?;
+
+        Ok(build_script_path)
+    }
+
+    async fn execute(&self, args: ExecutionArgs) -> Result<std::process::Output, std::io::Error> {
+        let build_script_path = self.write_scripts(&args).await?;
+
+        let build_script_path_str = build_script_path.to_string_lossy().to_string();
+        let executable = self.executable.to_string_lossy().to_string();
+        let cmd_args = [
+            executable.as_str(),
+            "-NoLogo",
+            "-NoProfile",
+            "-NonInteractive",
+            "-ExecutionPolicy",
+            "Bypass",
+            "-File",
+            &build_script_path_str,
+        ];
+
+        run_process_with_replacements(
+            &cmd_args,
+            &args.work_dir,
+            &args.replacements("$env:((var))"),
+            args.timeout,
+            args.inherited_env.as_ref(),
+            &args.log_sinks,
+        )
+        .await
+    }
+}
+
+/// The interpreter of a shell, which runs the activation and the build script.
+enum ShellInterpreter {
+    Posix(PosixShellInterpreter),
+    CmdExe(CmdExeInterpreter),
+    PowerShell(PowerShellInterpreter),
+}
+
+impl ShellInterpreter {
+    /// The interpreter for the given shell, running the given executable.
+    fn new(shell: ScriptShell, executable: PathBuf) -> Self {
+        match shell {
+            ScriptShell::Bash | ScriptShell::Zsh | ScriptShell::Dash => {
+                ShellInterpreter::Posix(PosixShellInterpreter { shell, executable })
+            }
+            ScriptShell::Cmd => ShellInterpreter::CmdExe(CmdExeInterpreter { executable }),
+            ScriptShell::Powershell | ScriptShell::Pwsh => {
+                ShellInterpreter::PowerShell(PowerShellInterpreter {
+                    executable,
+                    strict: shell == ScriptShell::Pwsh,
+                })
+            }
         }
+    }
 
-        Ok(())
+    async fn write_scripts(&self, args: &ExecutionArgs) -> Result<PathBuf, std::io::Error> {
+        match self {
+            ShellInterpreter::Posix(interpreter) => interpreter.write_scripts(args).await,
+            ShellInterpreter::CmdExe(interpreter) => interpreter.write_scripts(args).await,
+            ShellInterpreter::PowerShell(interpreter) => interpreter.write_scripts(args).await,
+        }
+    }
+
+    async fn execute(&self, args: ExecutionArgs) -> Result<std::process::Output, std::io::Error> {
+        match self {
+            ShellInterpreter::Posix(interpreter) => interpreter.execute(args).await,
+            ShellInterpreter::CmdExe(interpreter) => interpreter.execute(args).await,
+            ShellInterpreter::PowerShell(interpreter) => interpreter.execute(args).await,
+        }
     }
 }
 
-struct PythonInterpreter;
+struct PythonInterpreter {
+    shell: ShellInterpreter,
+}
 
-// python interpreter calls either bash or cmd.exe interpreter for activation and then runs python script
-impl Interpreter for PythonInterpreter {
-    async fn run(&self, args: ExecutionArgs) -> Result<(), std::io::Error> {
-        let py_script = args.work_dir.join("conda_build_script.py");
-        tokio::fs::write(&py_script, args.script).await?;
+// python interpreter calls the shell interpreter for activation and then runs python script
+impl PythonInterpreter {
+    /// Write the python script and return the arguments to run it with the shell
+    async fn shell_args(&self, args: &ExecutionArgs) -> Result<ExecutionArgs, std::io::Error> {
+        let py_script = match args.phase {
+            Some(phase) => args.work_dir.join(format!("conda_build_script_{phase}.py")),
+            None => args.work_dir.join("conda_build_script.py"),
+        };
+        tokio::fs::write(&py_script, &args.script).await?;
 
-        let args = ExecutionArgs {
+        Ok(ExecutionArgs {
             script: format!("python {:?}", py_script),
-            ..args
-        };
+            ..args.clone()
+        })
+    }
+}
 
-        if cfg!(windows) {
-            CmdExeInterpreter.run(args).await
-        } else {
-            BashInterpreter.run(args).await
-        }
+impl Interpreter for PythonInterpreter {
+    async fn write_scripts(&self, args: &ExecutionArgs) -> Result<PathBuf, std::io::Error> {
+        let args = self.shell_args(args).await?;
+        self.shell.write_scripts(&args).await
+    }
+
+    async fn execute(&self, args: ExecutionArgs) -> Result<std::process::Output, std::io::Error> {
+        let args = self.shell_args(&args).await?;
+        self.shell.execute(args).await
     }
 }
 
 impl Script {
-    fn get_contents(&self, recipe_dir: &Path) -> Result<String, std::io::Error> {
-        let default_extension = if cfg!(windows) { "bat" } else { "sh" };
-
+    fn get_contents(
+        &self,
+        recipe_dir: &Path,
+        default_extension: &str,
+    ) -> Result<String, std::io::Error> {
         let script_content = match self.contents() {
             // No script was specified, so we try to read the default script. If the file cannot be
             // found we return an empty string.
@@ -281,19 +529,20 @@ impl Script {
             // contents of the string. Try to read the file as a script but fall back to using the string
             // as the contents itself if the file is missing.
             ScriptContent::CommandOrPath(path) => {
-                let content =
-                    if !path.contains('\n') && (path.ends_with(".bat") || path.ends_with(".sh")) {
-                        let recipe_file = recipe_dir.join(Path::new(path));
-                        match std::fs::read_to_string(recipe_file) {
-                            Err(err) if err.kind() == ErrorKind::NotFound => None,
-                            Err(e) => {
-                                return Err(e);
-                            }
-                            Ok(content) => Some(content),
+                let content = if !path.contains('\n')
+                    && (path.ends_with(".bat") || path.ends_with(".sh") || path.ends_with(".ps1"))
+                {
+                    let recipe_file = recipe_dir.join(Path::new(path));
+                    match std::fs::read_to_string(recipe_file) {
+                        Err(err) if err.kind() == ErrorKind::NotFound => None,
+                        Err(e) => {
+                            return Err(e);
                         }
-                    } else {
-                        None
-                    };
+                        Ok(content) => Some(content),
+                    }
+                } else {
+                    None
+                };
                 match content {
                     Some(content) => content,
                     None => path.to_owned(),
@@ -306,19 +555,19 @@ impl Script {
         Ok(script_content)
     }
 
-    pub async fn run_script(
+    fn execution_args(
         &self,
         env_vars: HashMap<String, String>,
         work_dir: &Path,
         recipe_dir: &Path,
         run_prefix: &Path,
         build_prefix: Option<&PathBuf>,
-    ) -> Result<(), std::io::Error> {
-        let interpreter = self
-            .interpreter()
-            .unwrap_or(if cfg!(windows) { "cmd" } else { "bash" });
-
-        let contents = self.get_contents(recipe_dir)?;
+        shell: ScriptShell,
+        inherit_environment: &InheritEnvironment,
+        environment: Option<&HashMap<String, String>>,
+        log_sinks: &[Arc<dyn LogSink>],
+    ) -> Result<ExecutionArgs, std::io::Error> {
+        let contents = self.get_contents(recipe_dir, shell.script_extension())?;
 
         let secrets = self
             .secrets()
@@ -326,7 +575,11 @@ impl Script {
             .filter_map(|k| {
                 let secret = k.to_string();
 
-                if let Ok(value) = std::env::var(&secret) {
+                let value = match environment {
+                    Some(environment) => environment.get(&secret).cloned(),
+                    None => std::env::var(&secret).ok(),
+                };
+                if let Some(value) = value {
                     Some((secret, value))
                 } else {
                     tracing::warn!("Secret {} not found in environment", secret);
@@ -340,7 +593,7 @@ impl Script {
             .chain(self.env().clone().into_iter())
             .collect::<IndexMap<String, String>>();
 
-        let exec_args = ExecutionArgs {
+        Ok(ExecutionArgs {
             script: contents,
             env_vars,
             secrets,
@@ -348,56 +601,397 @@ impl Script {
             run_prefix: run_prefix.to_owned(),
             execution_platform: Platform::current(),
             work_dir: work_dir.to_owned(),
-        };
+            timeout: self.timeout(),
+            phase: None,
+            inherited_env: inherit_environment.inherited_vars(environment),
+            log_sinks: std::iter::once(Arc::new(TracingSink) as Arc<dyn LogSink>)
+                .chain(log_sinks.iter().cloned())
+                .collect(),
+        })
+    }
 
-        match interpreter {
-            "bash" => BashInterpreter.run(exec_args).await?,
-            "cmd" => CmdExeInterpreter.run(exec_args).await?,
-            "python" => PythonInterpreter.run(exec_args).await?,
-            _ => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Unsupported interpreter: {}", interpreter),
-                ))
-            }
+    /// The shell that runs the script: the selected `shell`, or the one that matches the
+    /// interpreter (`bash` on Unix and `cmd` on Windows by default).
+    fn resolved_shell(&self) -> ScriptShell {
+        match (self.shell(), self.interpreter()) {
+            (Some(shell), _) => shell,
+            (None, Some("bash")) => ScriptShell::Bash,
+            (None, Some("cmd")) => ScriptShell::Cmd,
+            (None, _) if cfg!(windows) => ScriptShell::Cmd,
+            (None, _) => ScriptShell::Bash,
+        }
+    }
+
+    /// Returns the interpreter of the shell that runs the script. A shell that was
+    /// selected in the recipe is looked up in the build (or run) prefix first and then on
+    /// the `PATH`. Otherwise `bash` or `cmd.exe` is started from the `PATH`.
+    fn shell_interpreter(
+        &self,
+        shell: ScriptShell,
+        run_prefix: &Path,
+        build_prefix: Option<&PathBuf>,
+    ) -> Result<ShellInterpreter, std::io::Error> {
+        let executable = match (self.shell(), shell) {
+            (Some(_), _) => find_shell(shell, run_prefix, build_prefix)?,
+            (None, ScriptShell::Cmd) => PathBuf::from("cmd.exe"),
+            (None, _) => PathBuf::from("bash"),
         };
+        Ok(ShellInterpreter::new(shell, executable))
+    }
+
+    fn unsupported_interpreter(interpreter: &str) -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("Unsupported interpreter: {}", interpreter),
+        )
+    }
+
+    /// Run the script and fail if the script fails. The script only inherits the
+    /// variables of `environment` (the environment of rattler-build if not given) that
+    /// are selected by `inherit_environment`. Its output goes to the log and to the
+    /// `log_sinks`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn run_script(
+        &self,
+        env_vars: HashMap<String, String>,
+        work_dir: &Path,
+        recipe_dir: &Path,
+        run_prefix: &Path,
+        build_prefix: Option<&PathBuf>,
+        inherit_environment: &InheritEnvironment,
+        environment: Option<&HashMap<String, String>>,
+        log_sinks: &[Arc<dyn LogSink>],
+    ) -> Result<(), std::io::Error> {
+        let output = self
+            .run_script_with_output(
+                env_vars,
+                work_dir,
+                recipe_dir,
+                run_prefix,
+                build_prefix,
+                inherit_environment,
+                environment,
+                log_sinks,
+            )
+            .await?;
+
+        if !output.status.success() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "Script failed with status {:?}.\nWork directory: {:?}\n{}",
+                    output.status.code(),
+                    work_dir,
+                    DEBUG_HELP
+                ),
+            ));
+        }
 
         Ok(())
     }
+
+    /// Run the script and return its exit status and output, without failing if the
+    /// script fails. The prefixes in the output are replaced with `$PREFIX` and
+    /// `$BUILD_PREFIX` (`%PREFIX%` and `%BUILD_PREFIX%` on Windows).
+    #[allow(clippy::too_many_arguments)]
+    pub async fn run_script_with_output(
+        &self,
+        env_vars: HashMap<String, String>,
+        work_dir: &Path,
+        recipe_dir: &Path,
+        run_prefix: &Path,
+        build_prefix: Option<&PathBuf>,
+        inherit_environment: &InheritEnvironment,
+        environment: Option<&HashMap<String, String>>,
+        log_sinks: &[Arc<dyn LogSink>],
+    ) -> Result<std::process::Output, std::io::Error> {
+        let interpreter = self
+            .interpreter()
+            .unwrap_or(if cfg!(windows) { "cmd" } else { "bash" });
+        let shell = self.resolved_shell();
+
+        let exec_args = self.execution_args(
+            env_vars,
+            work_dir,
+            recipe_dir,
+            run_prefix,
+            build_prefix,
+            shell,
+            inherit_environment,
+            environment,
+            log_sinks,
+        )?;
+        let shell = self.shell_interpreter(shell, run_prefix, build_prefix)?;
+
+        match interpreter {
+            "bash" | "cmd" => shell.execute(exec_args).await,
+            "python" => PythonInterpreter { shell }.execute(exec_args).await,
+            _ => Err(Self::unsupported_interpreter(interpreter)),
+        }
+    }
+
+    /// Write the activation and build scripts to the work directory without running
+    /// them. The scripts of a phase are named after it, e.g. `conda_build_install.sh`.
+    /// Returns the path of the build script.
+    pub async fn write_script(
+        &self,
+        env_vars: HashMap<String, String>,
+        work_dir: &Path,
+        recipe_dir: &Path,
+        run_prefix: &Path,
+        build_prefix: Option<&PathBuf>,
+        phase: Option<ScriptPhase>,
+    ) -> Result<PathBuf, std::io::Error> {
+        let interpreter = self
+            .interpreter()
+            .unwrap_or(if cfg!(windows) { "cmd" } else { "bash" });
+        let shell = self.resolved_shell();
+
+        let mut exec_args = self.execution_args(
+            env_vars,
+            work_dir,
+            recipe_dir,
+            run_prefix,
+            build_prefix,
+            shell,
+            &InheritEnvironment::all(),
+            None,
+            &[],
+        )?;
+        exec_args.phase = phase;
+        let shell = self.shell_interpreter(shell, run_prefix, build_prefix)?;
+
+        match interpreter {
+            "bash" | "cmd" => shell.write_scripts(&exec_args).await,
+            "python" => PythonInterpreter { shell }.write_scripts(&exec_args).await,
+            _ => Err(Self::unsupported_interpreter(interpreter)),
+        }
+    }
 }
 
 impl Output {
-    pub async fn run_build_script(&self) -> Result<(), std::io::Error> {
-        let span = tracing::info_span!("Running build script");
-        let _enter = span.enter();
-
+    /// The environment variables that are set for the build script.
+    fn build_env_vars(&self) -> HashMap<String, String> {
         let host_prefix = self.build_configuration.directories.host_prefix.clone();
         let target_platform = self.build_configuration.target_platform;
         let mut env_vars = env_vars::vars(self, "BUILD");
         env_vars.extend(env_vars::os_vars(&host_prefix, &target_platform));
+        env_vars
+    }
 
-        self.recipe
-            .build()
-            .script()
-            .run_script(
-                env_vars,
-                &self.build_configuration.directories.work_dir,
-                &self.build_configuration.directories.recipe_dir,
-                &self.build_configuration.directories.host_prefix,
-                Some(&self.build_configuration.directories.build_prefix),
-            )
-            .await?;
+    /// Run the build script. If the script is split into phases, the phases are run
+    /// one after another, starting at `from_phase` if given. The output of the script is
+    /// written to the log, to `conda_build.log` in the work directory and to the
+    /// `log_sink`, if given. The script inherits its variables from `environment`, or
+    /// from the environment of rattler-build if not given.
+    pub async fn run_build_script(
+        &self,
+        from_phase: Option<ScriptPhase>,
+        log_sink: Option<&Arc<dyn LogSink>>,
+        environment: Option<&HashMap<String, String>>,
+    ) -> Result<(), std::io::Error> {
+        let span = tracing::info_span!("Running build script");
+        let _enter = span.enter();
+
+        let work_dir = &self.build_configuration.directories.work_dir;
+        let mut log_sinks: Vec<Arc<dyn LogSink>> = vec![Arc::new(FileSink::create(
+            &work_dir.join("conda_build.log"),
+        )?)];
+        log_sinks.extend(log_sink.cloned());
+
+        let script = self.recipe.build().script();
+        if let Some(from_phase) = from_phase {
+            if !script.phases().contains_key(&from_phase) {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The build script has no `{}` phase", from_phase),
+                ));
+            }
+        }
+
+        for (phase, script) in script.resolved_phases() {
+            if let (Some(phase), Some(from_phase)) = (phase, from_phase) {
+                if phase < from_phase {
+                    tracing::info!("Skipping the `{}` phase", phase);
+                    continue;
+                }
+            }
+
+            let span = phase.map(|phase| tracing::info_span!("Running phase", %phase));
+            let _enter = span.as_ref().map(|span| span.enter());
+
+            script
+                .run_script(
+                    self.build_env_vars(),
+                    &self.build_configuration.directories.work_dir,
+                    &self.build_configuration.directories.recipe_dir,
+                    &self.build_configuration.directories.host_prefix,
+                    Some(&self.build_configuration.directories.build_prefix),
+                    self.recipe.build().inherit_environment(),
+                    environment,
+                    &log_sinks,
+                )
+                .await?;
+        }
 
         Ok(())
     }
+
+    /// Write the build script and the script that sets up the build environment to
+    /// the work directory, without running them. If the script is split into phases,
+    /// the scripts of every phase are written. Returns the phases with the paths of their
+    /// build scripts, in the order they are run.
+    pub async fn write_build_script(
+        &self,
+    ) -> Result<Vec<(Option<ScriptPhase>, PathBuf)>, std::io::Error> {
+        let mut build_scripts = Vec::new();
+        for (phase, script) in self.recipe.build().script().resolved_phases() {
+            let path = script
+                .write_script(
+                    self.build_env_vars(),
+                    &self.build_configuration.directories.work_dir,
+                    &self.build_configuration.directories.recipe_dir,
+                    &self.build_configuration.directories.host_prefix,
+                    Some(&self.build_configuration.directories.build_prefix),
+                    phase,
+                )
+                .await?;
+            build_scripts.push((phase, path));
+        }
+        Ok(build_scripts)
+    }
+}
+
+impl Output {
+    /// Start an interactive shell in the work directory with the same environment as
+    /// the build script (the `build_env.sh` or `build_env.bat` file that was written
+    /// when running the build script). Returns when the user exits the shell.
+    pub async fn run_debug_shell(
+        &self,
+        log_handler: &LoggingOutputHandler,
+    ) -> Result<(), std::io::Error> {
+        let work_dir = &self.build_configuration.directories.work_dir;
+
+        if !std::io::stdin().is_terminal() {
+            tracing::warn!("Not starting a debug shell because stdin is not a terminal");
+            return Ok(());
+        }
+
+        let mut command = if cfg!(windows) {
+            let mut command = std::process::Command::new("cmd.exe");
+            command.arg("/k").arg(work_dir.join("build_env.bat"));
+            command
+        } else {
+            let mut command = std::process::Command::new("bash");
+            command
+                .arg("--rcfile")
+                .arg(work_dir.join("build_env.sh"))
+                .arg("-i")
+                .env("PS1", "(rattler-build debug) \\w $ ");
+            command
+        };
+        command.current_dir(work_dir).env("CONDA_BUILD", "1");
+
+        tracing::info!(
+            "Starting a debug shell in {} (exit the shell to continue)",
+            work_dir.display()
+        );
+
+        // the shell is interactive, so it blocks until the user exits it
+        let multi_progress = log_handler.multi_progress().clone();
+        let status =
+            tokio::task::spawn_blocking(move || multi_progress.suspend(|| command.status()))
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))??;
+        tracing::info!("Debug shell exited with status {:?}", status.code());
+
+        Ok(())
+    }
+}
+
+/// Find the executable of a shell that was selected in the recipe: first in the build
+/// prefix, then in the run prefix and finally on the `PATH`.
+fn find_shell(
+    shell: ScriptShell,
+    run_prefix: &Path,
+    build_prefix: Option<&PathBuf>,
+) -> Result<PathBuf, std::io::Error> {
+    let executable = if cfg!(windows) {
+        format!("{}.exe", shell.as_str())
+    } else {
+        shell.as_str().to_string()
+    };
+
+    let in_prefix = build_prefix
+        .map(PathBuf::as_path)
+        .into_iter()
+        .chain([run_prefix])
+        .flat_map(|prefix| {
+            if cfg!(windows) {
+                vec![
+                    prefix.join("Library").join("bin"),
+                    prefix.join("Scripts"),
+                    prefix.to_path_buf(),
+                ]
+            } else {
+                vec![prefix.join("bin")]
+            }
+        })
+        .map(|dir| dir.join(&executable))
+        .find(|path| path.is_file());
+    if let Some(path) = in_prefix {
+        return Ok(path);
+    }
+
+    which::which(shell.as_str()).map_err(|_| {
+        std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "The shell `{}` of the script was not found in the build environment or on the PATH. \
+                 Add it to the `build` requirements of the recipe or install it on the system.",
+                shell
+            ),
+        )
+    })
+}
+
+/// Kill a process together with all processes it started (the process group on Unix,
+/// the process tree on Windows) and wait for it to exit.
+async fn kill_process_tree(child: &mut tokio::process::Child) -> Result<(), std::io::Error> {
+    if let Some(pid) = child.id() {
+        #[cfg(unix)]
+        // SAFETY: `killpg` has no memory safety requirements, the process group was
+        // created for the script when it was spawned
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+
+        #[cfg(windows)]
+        {
+            let _ = tokio::process::Command::new("taskkill")
+                .args(["/F", "/T", "/PID", &pid.to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await;
+        }
+    }
+
+    child.kill().await
 }
 
 /// Spawns a process and replaces the given strings in the output with the given replacements.
 /// This is used to replace the host prefix with $PREFIX and the build prefix with $BUILD_PREFIX
+/// If a timeout is given, the process is killed once it runs longer than the timeout.
+/// If inherited environment variables are given, the process only gets these instead of
+/// the whole environment.
 async fn run_process_with_replacements(
     args: &[&str],
     cwd: &Path,
     replacements: &HashMap<String, String>,
+    timeout: Option<Duration>,
+    inherited_env: Option<&HashMap<String, String>>,
+    log_sinks: &[Arc<dyn LogSink>],
 ) -> Result<std::process::Output, std::io::Error> {
     let mut command = tokio::process::Command::new(args[0]);
     command
@@ -407,6 +1001,17 @@ async fn run_process_with_replacements(
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
+    if let Some(inherited_env) = inherited_env {
+        command.env_clear().envs(inherited_env);
+    }
+
+    // run the script in its own process group, so that all processes it started can be
+    // killed when it times out
+    #[cfg(unix)]
+    if timeout.is_some() {
+        command.process_group(0);
+    }
+
     let mut child = command.spawn()?;
 
     let stdout = child.stdout.take().expect("Failed to take stdout");
@@ -418,10 +1023,32 @@ async fn run_process_with_replacements(
     let mut stdout_log = String::new();
     let mut stderr_log = String::new();
     let mut closed = (false, false);
+    let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    let timed_out = || {
+        std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!(
+                "Script timed out after {} seconds",
+                timeout.unwrap_or_default().as_secs()
+            ),
+        )
+    };
     loop {
+        if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+            kill_process_tree(&mut child).await?;
+            return Err(timed_out());
+        }
+
+        let sleep = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
         let (line, is_stderr) = tokio::select! {
             line = stdout_lines.next_line() => (line, false),
             line = stderr_lines.next_line() => (line, true),
+            _ = sleep => continue,
             else => break,
         };
 
@@ -431,15 +1058,24 @@ async fn run_process_with_replacements(
                     .iter()
                     .fold(line, |acc, (from, to)| acc.replace(from, to));
 
-                if is_stderr {
+                let stream = if is_stderr {
                     stderr_log.push_str(&filtered_line);
                     stderr_log.push('\n');
+                    OutputStream::Stderr
                 } else {
                     stdout_log.push_str(&filtered_line);
                     stdout_log.push('\n');
-                }
+                    OutputStream::Stdout
+                };
 
-                tracing::info!("{}", filtered_line);
+                let log_line = LogLine {
+                    stream,
+                    timestamp: chrono::Utc::now(),
+                    line: filtered_line,
+                };
+                for sink in log_sinks {
+                    sink.log_line(&log_line);
+                }
             }
             Ok(None) if !is_stderr => closed.0 = true,
             Ok(None) if is_stderr => closed.1 = true,
@@ -455,7 +1091,17 @@ async fn run_process_with_replacements(
         }
     }
 
-    let status = child.wait().await?;
+    // the script can keep running after it closed its output
+    let status = match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, child.wait()).await {
+            Ok(status) => status?,
+            Err(_) => {
+                kill_process_tree(&mut child).await?;
+                return Err(timed_out());
+            }
+        },
+        None => child.wait().await?,
+    };
 
     Ok(std::process::Output {
         status,
@@ -463,3 +1109,64 @@ async fn run_process_with_replacements(
         stderr: stderr_log.into_bytes(),
     })
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::run_process_with_replacements;
+
+    #[tokio::test]
+    async fn test_timeout_kills_process_group() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pid_file = tmp.path().join("pid");
+        let script = format!("sleep 60 & echo $! > {}; wait", pid_file.display());
+
+        let result = run_process_with_replacements(
+            &["bash", "-c", &script],
+            tmp.path(),
+            &HashMap::new(),
+            Some(Duration::from_secs(1)),
+            None,
+            &[],
+        )
+        .await;
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+
+        // the background process of the script was killed as well (it may stay around as
+        // a zombie until it is reaped)
+        let pid = fs_err::read_to_string(&pid_file).unwrap();
+        let stat = std::path::PathBuf::from(format!("/proc/{}/stat", pid.trim()));
+        let mut alive = true;
+        for _ in 0..50 {
+            let state = std::fs::read_to_string(&stat).ok().and_then(|stat| {
+                stat.rsplit_once(") ")
+                    .and_then(|(_, rest)| rest.chars().next())
+            });
+            if !matches!(state, Some(state) if state != 'Z') {
+                alive = false;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(
+            !alive,
+            "the background process of the script is still running"
+        );
+    }
+    #[tokio::test]
+    async fn test_timeout_after_output_is_closed() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let result = run_process_with_replacements(
+            &["bash", "-c", "exec >&- 2>&-; sleep 60"],
+            tmp.path(),
+            &HashMap::new(),
+            Some(Duration::from_secs(1)),
+            None,
+            &[],
+        )
+        .await;
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -1,11 +1,14 @@
 //! Contains the selector config, which is used to render the recipe.
 
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
-use crate::{hash::HashInfo, recipe::jinja::Env, recipe::jinja::Git};
+use crate::{hash::HashInfo, metadata::PackageIdentifier, recipe::jinja::Env, recipe::jinja::Git};
 
 use minijinja::value::Value;
-use rattler_conda_types::Platform;
+use rattler_conda_types::{PackageName, Platform};
 
 /// The selector config is used to render the recipe.
 #[derive(Clone, Debug)]
@@ -24,6 +27,15 @@ pub struct SelectorConfig {
     pub experimental: bool,
     /// Allow undefined variables
     pub allow_undefined: bool,
+    /// Treat skip conditions that use undefined variables as false (like conda-build)
+    /// instead of raising an error
+    pub lenient_skip: bool,
+    /// The outputs of the recipe that were rendered before the current one (available
+    /// through the `outputs("name")` function)
+    pub subpackages: BTreeMap<PackageName, PackageIdentifier>,
+    /// The environment variables for `env.get` and the `os.environ` lookups of
+    /// `conda_build_config.yaml` files. Defaults to the environment of the process.
+    pub environment: Option<Arc<HashMap<String, String>>>,
 }
 
 impl SelectorConfig {
@@ -61,7 +73,10 @@ impl SelectorConfig {
             context.insert("hash".to_string(), Value::from_safe_string(hash.hash));
         }
 
-        context.insert("env".to_string(), Value::from_object(Env));
+        context.insert(
+            "env".to_string(),
+            Value::from_object(Env(self.environment.clone())),
+        );
         context.insert(
             "git".to_string(),
             Value::from_object(Git {
@@ -101,6 +116,9 @@ impl Default for SelectorConfig {
             variant: Default::default(),
             experimental: false,
             allow_undefined: false,
+            lenient_skip: false,
+            subpackages: BTreeMap::new(),
+            environment: None,
         }
     }
 }
@@ -0,0 +1,184 @@
+//! Sign the created packages with sigstore (cosign) or GPG.
+//!
+//! Signing runs after a package is created and before it is tested and uploaded. The
+//! signatures are detached and written next to the package:
+//!
+//! - sigstore: `<package>.sigstore.json`, a bundle with the signature, the certificate
+//!   of the (keyless) identity and the transparency log entry, created with
+//!   `cosign sign-blob`
+//! - GPG: `<package>.asc`, an ASCII armored detached signature created with
+//!   `gpg --detach-sign`
+//!
+//! When the packages are uploaded with `--upload-to`, the signatures are uploaded next to
+//! them by the backends that support it.
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use tokio::process::Command;
+
+/// How packages are signed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SigningMethod {
+    /// Sign with `cosign`, keyless (with the OIDC identity of the CI system or the user)
+    /// or with the key given in `--signing-key`
+    Sigstore,
+    /// Sign with `gpg`, with the default key or the key given in `--signing-key`
+    Gpg,
+}
+
+/// The settings for signing packages.
+#[derive(Debug, Clone)]
+pub struct SigningConfig {
+    /// The signing method
+    pub method: SigningMethod,
+    /// The key to sign with: a cosign key reference (e.g. `cosign.key` or
+    /// `awskms://...`) or a GPG key id. Without a key, cosign signs keyless and GPG
+    /// uses the default key.
+    pub key: Option<String>,
+}
+
+/// An error that occurs while signing a package.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum SigningError {
+    #[error("`{0}` was not found, it is required to sign packages")]
+    #[diagnostic(help("install `{0}` or choose another `--sign` method"))]
+    ToolNotFound(String),
+
+    #[error("failed to run `{0}`")]
+    Io(String, #[source] std::io::Error),
+
+    #[error("signing {path} with `{tool}` failed ({status}):\n{stderr}")]
+    Failed {
+        tool: String,
+        path: PathBuf,
+        status: std::process::ExitStatus,
+        stderr: String,
+    },
+}
+
+impl SigningConfig {
+    /// The path of the detached signature of a file.
+    pub fn signature_path(&self, path: &Path) -> PathBuf {
+        let extension = match self.method {
+            SigningMethod::Sigstore => "sigstore.json",
+            SigningMethod::Gpg => "asc",
+        };
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(extension);
+        path.with_file_name(file_name)
+    }
+
+    /// The command that signs the file and writes the signature to `signature`.
+    fn command(&self, program: &Path, path: &Path, signature: &Path) -> Command {
+        let mut command = Command::new(program);
+        match self.method {
+            SigningMethod::Sigstore => {
+                command.args(["sign-blob", "--yes"]);
+                if let Some(key) = &self.key {
+                    command.arg("--key").arg(key);
+                }
+                command.arg("--bundle").arg(signature).arg(path);
+            }
+            SigningMethod::Gpg => {
+                command.args(["--batch", "--yes", "--armor", "--detach-sign"]);
+                if let Some(key) = &self.key {
+                    command.arg("--local-user").arg(key);
+                }
+                command.arg("--output").arg(signature).arg(path);
+            }
+        }
+        command
+    }
+
+    /// The name of the program that is used to sign.
+    fn tool(&self) -> &'static str {
+        match self.method {
+            SigningMethod::Sigstore => "cosign",
+            SigningMethod::Gpg => "gpg",
+        }
+    }
+
+    /// Sign a file and return the path of the detached signature.
+    pub async fn sign(&self, path: &Path) -> Result<PathBuf, SigningError> {
+        let tool = self.tool();
+        let program = which::which(tool).map_err(|_| SigningError::ToolNotFound(tool.into()))?;
+        let signature = self.signature_path(path);
+
+        tracing::info!("Signing {} with {}", path.display(), tool);
+        let output = self
+            .command(&program, path, &signature)
+            .output()
+            .await
+            .map_err(|err| SigningError::Io(tool.to_string(), err))?;
+        if !output.status.success() {
+            return Err(SigningError::Failed {
+                tool: tool.to_string(),
+                path: path.to_path_buf(),
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_signing_commands() {
+        let package = Path::new("output/linux-64/foo-1.0-h123_0.conda");
+
+        let sigstore = SigningConfig {
+            method: SigningMethod::Sigstore,
+            key: None,
+        };
+        let signature = sigstore.signature_path(package);
+        assert_eq!(
+            signature,
+            Path::new("output/linux-64/foo-1.0-h123_0.conda.sigstore.json")
+        );
+        assert_eq!(
+            args(&sigstore.command(Path::new("cosign"), package, &signature)),
+            [
+                "sign-blob",
+                "--yes",
+                "--bundle",
+                "output/linux-64/foo-1.0-h123_0.conda.sigstore.json",
+                "output/linux-64/foo-1.0-h123_0.conda"
+            ]
+        );
+
+        let gpg = SigningConfig {
+            method: SigningMethod::Gpg,
+            key: Some("ABCDEF".to_string()),
+        };
+        let signature = gpg.signature_path(package);
+        assert_eq!(
+            args(&gpg.command(Path::new("gpg"), package, &signature)),
+            [
+                "--batch",
+                "--yes",
+                "--armor",
+                "--detach-sign",
+                "--local-user",
+                "ABCDEF",
+                "--output",
+                "output/linux-64/foo-1.0-h123_0.conda.asc",
+                "output/linux-64/foo-1.0-h123_0.conda"
+            ]
+        );
+    }
+}
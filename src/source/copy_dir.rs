@@ -288,6 +288,52 @@ impl<'a> CopyDir<'a> {
     }
 }
 
+/// Links the top-level entries of `from_path` into `to_path` instead of copying them, so
+/// that the build refers back to the original source directory (used for `--dev` builds).
+/// Entries ignored by the `.gitignore` file are skipped if `use_gitignore` is set.
+///
+/// Returns the paths of the created links.
+pub(crate) fn link_dir_entries(
+    from_path: &Path,
+    to_path: &Path,
+    use_gitignore: bool,
+) -> Result<Vec<PathBuf>, SourceError> {
+    create_dir_all(to_path)?;
+
+    let mut linked_paths = Vec::new();
+    for entry in WalkBuilder::new(from_path)
+        .git_global(false)
+        .git_ignore(use_gitignore)
+        .hidden(false)
+        .max_depth(Some(1))
+        .build()
+    {
+        let entry = entry?;
+        if entry.depth() == 0 {
+            continue;
+        }
+
+        let dest_path = to_path.join(entry.file_name());
+        if dest_path.symlink_metadata().is_ok() {
+            tracing::warn!("File already exists! Skipping link: {:?}", dest_path);
+            continue;
+        }
+
+        #[cfg(unix)]
+        fs_err::os::unix::fs::symlink(entry.path(), &dest_path)?;
+        #[cfg(windows)]
+        if entry.path().is_dir() {
+            std::os::windows::fs::symlink_dir(entry.path(), &dest_path)?;
+        } else {
+            std::os::windows::fs::symlink_file(entry.path(), &dest_path)?;
+        }
+
+        linked_paths.push(dest_path);
+    }
+
+    Ok(linked_paths)
+}
+
 /// Recursively creates directories and keeps an in-memory cache of the directories that have been
 /// created before. This speeds up creation of large amounts of directories significantly because
 /// there are fewer IO operations.
@@ -468,6 +514,36 @@ impl Match {
 mod test {
     use std::{collections::HashSet, fs, fs::File};
 
+    #[cfg(unix)]
+    #[test]
+    fn test_link_dir_entries() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let dir = tmp_dir.path().join("src");
+
+        // test.txt
+        // test_dir/test.md
+        fs_err::create_dir_all(dir.join("test_dir")).unwrap();
+        std::fs::write(dir.join("test.txt"), "test").unwrap();
+        std::fs::write(dir.join("test_dir").join("test.md"), "test").unwrap();
+
+        let dest_dir = tmp_dir.path().join("work");
+        let linked = super::link_dir_entries(&dir, &dest_dir, false).unwrap();
+        assert_eq!(linked.len(), 2);
+
+        for name in ["test.txt", "test_dir"] {
+            let path = dest_dir.join(name);
+            assert!(path.is_symlink());
+            assert_eq!(fs::read_link(&path).unwrap(), dir.join(name));
+        }
+
+        // changes in the source directory are visible through the links
+        std::fs::write(dir.join("test_dir").join("test.md"), "changed").unwrap();
+        assert_eq!(
+            fs::read_to_string(dest_dir.join("test_dir").join("test.md")).unwrap(),
+            "changed"
+        );
+    }
+
     #[test]
     fn test_copy_dir() {
         let tmp_dir = tempfile::TempDir::new().unwrap();
@@ -527,6 +603,25 @@ mod test {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn copy_checkout_without_metadata() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(tmp_dir.path().join(".hg/store")).unwrap();
+        File::create(tmp_dir.path().join(".hg/store/data.i")).unwrap();
+        File::create(tmp_dir.path().join(".hgignore")).unwrap();
+        File::create(tmp_dir.path().join("setup.py")).unwrap();
+
+        let dest_dir = tempfile::TempDir::new().unwrap();
+        let copy_dir = super::CopyDir::new(tmp_dir.path(), dest_dir.path())
+            .with_exclude_glob(".hg/**")
+            .use_gitignore(false)
+            .run()
+            .unwrap();
+        assert_eq!(copy_dir.copied_paths().len(), 2);
+        assert!(dest_dir.path().join(".hgignore").exists());
+        assert!(!dest_dir.path().join(".hg").exists());
+    }
+
     #[test]
     fn copy_a_bunch_of_files() {
         let tmp_dir = tempfile::TempDir::new().unwrap();
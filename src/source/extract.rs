@@ -1,12 +1,92 @@
 //! Helpers to extract archives
-use std::{ffi::OsStr, io::BufRead, path::Path};
+use std::{
+    ffi::OsStr,
+    io::{BufRead, Read},
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+};
 
-use crate::console_utils::LoggingOutputHandler;
+use crate::{
+    console_utils::LoggingOutputHandler,
+    system_tools::{SystemTools, Tool},
+};
 
 use fs_err as fs;
 use fs_err::File;
 
 use super::SourceError;
+
+/// How strictly archive members are checked before they are extracted.
+///
+/// Upstream archives are untrusted input. With the strict policy (the default) the
+/// extraction fails if a member would end up outside of the target directory (absolute
+/// paths, `..` components, or hard- and symlinks pointing outside), or if the archive
+/// contains device nodes or fifos. The permissive policy falls back to the defaults of
+/// the underlying libraries and tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExtractionPolicy {
+    /// Reject archives with members that escape the target directory
+    #[default]
+    Strict,
+    /// Use the defaults of the underlying extraction libraries
+    Permissive,
+}
+
+/// The archive formats that are extracted into the work directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A (compressed) tarball
+    Tar,
+    /// A zip archive
+    Zip,
+    /// A 7-Zip archive (extracted with the `7z` tool)
+    SevenZip,
+    /// A lz4 compressed file or tarball (decompressed with the `lz4` tool)
+    Lz4,
+    /// A Debian package, the `data.tar.*` payload is extracted
+    Deb,
+    /// A RPM package, the cpio payload is extracted
+    Rpm,
+}
+
+impl ArchiveFormat {
+    /// Determine the archive format from the file name. Returns `None` if the file
+    /// is not a known archive and should be copied as is.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_string_lossy();
+        if is_tarball(&file_name) {
+            Some(Self::Tar)
+        } else {
+            match path.extension().and_then(OsStr::to_str) {
+                Some("zip") => Some(Self::Zip),
+                Some("7z") => Some(Self::SevenZip),
+                Some("lz4" | "tlz4") => Some(Self::Lz4),
+                Some("deb") => Some(Self::Deb),
+                Some("rpm") => Some(Self::Rpm),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Extract an archive of the given format to the target directory.
+pub(crate) fn extract_archive(
+    format: ArchiveFormat,
+    archive: &Path,
+    target_directory: &Path,
+    system_tools: &SystemTools,
+    log_handler: &LoggingOutputHandler,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
+    match format {
+        ArchiveFormat::Tar => extract_tar(archive, target_directory, log_handler, policy),
+        ArchiveFormat::Zip => extract_zip(archive, target_directory, log_handler, policy),
+        ArchiveFormat::SevenZip => extract_7z(archive, target_directory, system_tools, policy),
+        ArchiveFormat::Lz4 => extract_lz4(archive, target_directory, system_tools, policy),
+        ArchiveFormat::Deb => extract_deb(archive, target_directory, log_handler, policy),
+        ArchiveFormat::Rpm => extract_rpm(archive, target_directory, log_handler, policy),
+    }
+}
 /// Handle Compression formats internally
 enum TarCompression<'a> {
     PlainTar(Box<dyn BufRead + 'a>),
@@ -26,6 +106,8 @@ pub fn is_tarball(file_name: &str) -> bool {
         ".tar.gz",
         ".tgz",
         ".taz",
+        // Rust crates are gzipped tarballs
+        ".crate",
         // Bzip2
         ".tar.bz2",
         ".tbz",
@@ -59,7 +141,9 @@ fn ext_to_compression<'a>(ext: Option<&OsStr>, file: Box<dyn BufRead + 'a>) -> T
         .and_then(|s| s.rsplit_once('.'))
         .map(|(_, s)| s)
     {
-        Some("gz" | "tgz" | "taz") => TarCompression::Gzip(flate2::read::GzDecoder::new(file)),
+        Some("gz" | "tgz" | "taz" | "crate") => {
+            TarCompression::Gzip(flate2::read::GzDecoder::new(file))
+        }
         Some("bz2" | "tbz" | "tbz2" | "tz2") => {
             TarCompression::Bzip2(bzip2::read::BzDecoder::new(file))
         }
@@ -89,7 +173,13 @@ impl<'a> std::io::Read for TarCompression<'a> {
     }
 }
 /// Moves the directory content from src to dest after stripping root dir, if present.
-fn move_extracted_dir(src: &Path, dest: &Path) -> Result<(), SourceError> {
+/// With the strict policy the symlinks are verified after stripping, as a symlink to the
+/// root dir (e.g. `pkg/link -> ..`) would point outside of dest afterwards.
+fn move_extracted_dir(
+    src: &Path,
+    dest: &Path,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
     let mut entries = fs::read_dir(src)?;
     let src_dir = match entries.next().transpose()? {
         // ensure if only single directory in entries(root dir)
@@ -99,6 +189,10 @@ fn move_extracted_dir(src: &Path, dest: &Path) -> Result<(), SourceError> {
         _ => src.to_path_buf(),
     };
 
+    if policy == ExtractionPolicy::Strict {
+        verify_symlinks(&src_dir)?;
+    }
+
     for entry in fs::read_dir(src_dir)? {
         let entry = entry?;
         let destination = dest.join(entry.file_name());
@@ -113,6 +207,7 @@ pub(crate) fn extract_tar(
     archive: impl AsRef<Path>,
     target_directory: impl AsRef<Path>,
     log_handler: &LoggingOutputHandler,
+    policy: ExtractionPolicy,
 ) -> Result<(), SourceError> {
     let archive = archive.as_ref();
     let target_directory = target_directory.as_ref();
@@ -134,16 +229,87 @@ pub(crate) fn extract_tar(
     ));
 
     let tmp_extraction_dir = tempfile::Builder::new().tempdir_in(target_directory)?;
-    archive
-        .unpack(&tmp_extraction_dir)
-        .map_err(|e| SourceError::TarExtractionError(e.to_string()))?;
+    unpack_tar(&mut archive, tmp_extraction_dir.path(), policy, false)?;
 
-    move_extracted_dir(tmp_extraction_dir.path(), target_directory)?;
+    move_extracted_dir(tmp_extraction_dir.path(), target_directory, policy)?;
     progress_bar.finish_with_message("Extracted...");
 
     Ok(())
 }
 
+/// Unpacks a tar archive into the given directory. With the strict policy every
+/// member is checked before it is written. A `rooted` archive is laid out like the root
+/// of a file system (e.g. the payload of a Debian package), so its absolute symlink
+/// targets are rewritten relative to the directory.
+fn unpack_tar<R: Read>(
+    archive: &mut tar::Archive<R>,
+    directory: &Path,
+    policy: ExtractionPolicy,
+    rooted: bool,
+) -> Result<(), SourceError> {
+    let tar_error = |e: std::io::Error| SourceError::TarExtractionError(e.to_string());
+
+    if policy == ExtractionPolicy::Permissive {
+        return archive.unpack(directory).map_err(tar_error);
+    }
+
+    // like `tar::Archive::unpack`, directories are unpacked last so that read-only
+    // directories do not prevent the extraction of their content
+    let mut directories = Vec::new();
+    let mut symlinks = Vec::new();
+    for entry in archive.entries().map_err(tar_error)? {
+        let mut entry = entry.map_err(tar_error)?;
+        let name = entry.path().map_err(tar_error)?.to_path_buf();
+        let path = strict_member_path(&name)?;
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_block_special()
+            || entry_type.is_character_special()
+            || entry_type.is_fifo()
+        {
+            return Err(unsafe_member(&name, "is a device node or fifo"));
+        }
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .map_err(tar_error)?
+                .ok_or_else(|| unsafe_member(&name, "is a link without a target"))?
+                .to_path_buf();
+            if entry_type.is_hard_link() {
+                // hard link targets are relative to the root of the archive
+                strict_member_path(&target)?;
+            } else if rooted && target.has_root() {
+                let target = rebase_link_target(&path, &target);
+                if !link_stays_inside(&path, &target) {
+                    return Err(unsafe_member(
+                        &name,
+                        &format!("links to `{}` outside of the archive", target.display()),
+                    ));
+                }
+                symlinks.push((directory.join(&path), target.to_string_lossy().into_owned()));
+                continue;
+            } else if !link_stays_inside(&path, &target) {
+                return Err(unsafe_member(
+                    &name,
+                    &format!("links to `{}` outside of the archive", target.display()),
+                ));
+            }
+        }
+
+        if entry_type.is_dir() {
+            directories.push(entry);
+        } else {
+            entry.unpack_in(directory).map_err(tar_error)?;
+        }
+    }
+    for mut entry in directories {
+        entry.unpack_in(directory).map_err(tar_error)?;
+    }
+    create_symlinks(symlinks)?;
+
+    verify_symlinks(directory)
+}
+
 /// Extracts a zip archive to the specified target directory
 /// currently this doesn't support bzip2 and zstd.
 ///
@@ -152,6 +318,7 @@ pub(crate) fn extract_zip(
     archive: impl AsRef<Path>,
     target_directory: impl AsRef<Path>,
     log_handler: &LoggingOutputHandler,
+    policy: ExtractionPolicy,
 ) -> Result<(), SourceError> {
     let archive = archive.as_ref();
     let target_directory = target_directory.as_ref();
@@ -170,23 +337,1013 @@ pub(crate) fn extract_zip(
     .map_err(|e| SourceError::InvalidZip(e.to_string()))?;
 
     let tmp_extraction_dir = tempfile::Builder::new().tempdir_in(target_directory)?;
-    archive
-        .extract(&tmp_extraction_dir)
-        .map_err(|e| SourceError::ZipExtractionError(e.to_string()))?;
+    match policy {
+        ExtractionPolicy::Strict => unpack_zip(&mut archive, tmp_extraction_dir.path())?,
+        ExtractionPolicy::Permissive => archive
+            .extract(&tmp_extraction_dir)
+            .map_err(|e| SourceError::ZipExtractionError(e.to_string()))?,
+    }
+
+    move_extracted_dir(tmp_extraction_dir.path(), target_directory, policy)?;
+    progress_bar.finish_with_message("Extracted...");
+
+    Ok(())
+}
+
+/// Unpacks a zip archive into the given directory, checking every member before it
+/// is written.
+fn unpack_zip<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    directory: &Path,
+) -> Result<(), SourceError> {
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+    let zip_error = |e: zip::result::ZipError| SourceError::ZipExtractionError(e.to_string());
+
+    let mut symlinks = Vec::new();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(zip_error)?;
+        let name = PathBuf::from(file.name());
+        let path = strict_member_path(&name)?;
+        let destination = directory.join(&path);
+
+        if file.is_dir() {
+            fs::create_dir_all(&destination)?;
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mode = file.unix_mode();
+        if mode.map_or(false, |mode| mode & S_IFMT == S_IFLNK) {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            if !link_stays_inside(&path, Path::new(&target)) {
+                return Err(unsafe_member(
+                    &name,
+                    &format!("links to `{target}` outside of the archive"),
+                ));
+            }
+            symlinks.push((destination, target));
+            continue;
+        }
+
+        let mut output = File::create(&destination)?;
+        std::io::copy(&mut file, &mut output)?;
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&destination, std::fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+
+    // symlinks are created last so that no file is written through them
+    create_symlinks(symlinks)?;
+    verify_symlinks(directory)
+}
+
+/// Extracts a 7-Zip archive to the specified target directory using the `7z` tool.
+pub(crate) fn extract_7z(
+    archive: &Path,
+    target_directory: &Path,
+    system_tools: &SystemTools,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
+    if !archive.exists() {
+        return Err(SourceError::FileNotFound(archive.to_path_buf()));
+    }
+
+    let tmp_extraction_dir = tempfile::Builder::new().tempdir_in(target_directory)?;
+    let output = system_tools
+        .call(Tool::SevenZip)
+        .map_err(|e| SourceError::ArchiveExtractionError("7z".to_string(), e.to_string()))?
+        .arg("x")
+        .arg("-y")
+        .arg(format!("-o{}", tmp_extraction_dir.path().display()))
+        .arg(archive)
+        .stdout(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(SourceError::ArchiveExtractionError(
+            "7z".to_string(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    // `7z` refuses absolute and `..` paths itself, only the symlinks need checking (which
+    // happens when moving the extracted files)
+    move_extracted_dir(tmp_extraction_dir.path(), target_directory, policy)
+}
+
+/// Decompresses a lz4 compressed file using the `lz4` tool. Compressed tarballs
+/// (`.tar.lz4` and `.tlz4`) are extracted, other files are decompressed into the
+/// target directory with the `.lz4` extension removed.
+pub(crate) fn extract_lz4(
+    archive: &Path,
+    target_directory: &Path,
+    system_tools: &SystemTools,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
+    if !archive.exists() {
+        return Err(SourceError::FileNotFound(archive.to_path_buf()));
+    }
+
+    let file_name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut child = system_tools
+        .call(Tool::Lz4)
+        .map_err(|e| SourceError::ArchiveExtractionError("lz4".to_string(), e.to_string()))?
+        .args(["-d", "-c"])
+        .arg(archive)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let result = if file_name.ends_with(".tar.lz4") || file_name.ends_with(".tlz4") {
+        let tmp_extraction_dir = tempfile::Builder::new().tempdir_in(target_directory)?;
+        unpack_tar(
+            &mut tar::Archive::new(stdout),
+            tmp_extraction_dir.path(),
+            policy,
+            false,
+        )
+        .and_then(|_| move_extracted_dir(tmp_extraction_dir.path(), target_directory, policy))
+    } else {
+        let mut stdout = stdout;
+        let destination = target_directory.join(file_name.trim_end_matches(".lz4"));
+        File::create(&destination)
+            .and_then(|mut file| std::io::copy(&mut stdout, &mut file))
+            .map(|_| ())
+            .map_err(SourceError::from)
+    };
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(SourceError::ArchiveExtractionError(
+            "lz4".to_string(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    result
+}
+
+/// Extracts the `data.tar.*` payload of a Debian package to the specified target
+/// directory. The `control.tar.*` member with the package scripts is ignored.
+pub(crate) fn extract_deb(
+    archive: &Path,
+    target_directory: &Path,
+    log_handler: &LoggingOutputHandler,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
+    const AR_MAGIC: &[u8] = b"!<arch>\n";
+    let invalid = |msg: &str| SourceError::ArchiveExtractionError("deb".to_string(), msg.into());
+
+    let len = archive.metadata().map(|m| m.len()).unwrap_or(1);
+    let progress_bar = log_handler.add_progress_bar(
+        indicatif::ProgressBar::new(len)
+            .with_prefix("Extracting deb")
+            .with_style(log_handler.default_bytes_style()),
+    );
+
+    let file = File::open(archive).map_err(|_| SourceError::FileNotFound(archive.to_path_buf()))?;
+    let mut reader = std::io::BufReader::new(progress_bar.wrap_read(file));
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != AR_MAGIC {
+        return Err(invalid("not an ar archive"));
+    }
+
+    // walk the ar members until we find the data tarball
+    loop {
+        let mut header = [0u8; 60];
+        if reader.read_exact(&mut header).is_err() {
+            return Err(invalid("no `data.tar` member found"));
+        }
+        let name = String::from_utf8_lossy(&header[0..16])
+            .trim_end()
+            .trim_end_matches('/')
+            .to_string();
+        let size: u64 = String::from_utf8_lossy(&header[48..58])
+            .trim()
+            .parse()
+            .map_err(|_| invalid("invalid member size"))?;
+
+        if name.starts_with("data.tar") {
+            let payload = std::io::BufReader::new((&mut reader).take(size));
+            let mut tar = tar::Archive::new(ext_to_compression(
+                Some(OsStr::new(&name)),
+                Box::new(payload),
+            ));
+            let tmp_extraction_dir = tempfile::Builder::new().tempdir_in(target_directory)?;
+            unpack_tar(&mut tar, tmp_extraction_dir.path(), policy, true)?;
+            // do not strip the root directory, the payload is laid out like the
+            // root of the file system
+            merge_dir(tmp_extraction_dir.path(), target_directory)?;
+            progress_bar.finish_with_message("Extracted...");
+            return Ok(());
+        }
+
+        // members are aligned to two bytes
+        std::io::copy(
+            &mut (&mut reader).take(size + size % 2),
+            &mut std::io::sink(),
+        )?;
+    }
+}
+
+/// Extracts the cpio payload of a RPM package to the specified target directory.
+pub(crate) fn extract_rpm(
+    archive: &Path,
+    target_directory: &Path,
+    log_handler: &LoggingOutputHandler,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
+    const LEAD_MAGIC: &[u8] = &[0xed, 0xab, 0xee, 0xdb];
+    const HEADER_MAGIC: &[u8] = &[0x8e, 0xad, 0xe8, 0x01];
+    let invalid = |msg: &str| SourceError::ArchiveExtractionError("rpm".to_string(), msg.into());
+
+    let len = archive.metadata().map(|m| m.len()).unwrap_or(1);
+    let progress_bar = log_handler.add_progress_bar(
+        indicatif::ProgressBar::new(len)
+            .with_prefix("Extracting rpm")
+            .with_style(log_handler.default_bytes_style()),
+    );
+
+    let file = File::open(archive).map_err(|_| SourceError::FileNotFound(archive.to_path_buf()))?;
+    let mut reader = std::io::BufReader::new(progress_bar.wrap_read(file));
+
+    let mut lead = [0u8; 96];
+    reader.read_exact(&mut lead)?;
+    if &lead[0..4] != LEAD_MAGIC {
+        return Err(invalid("not a rpm package"));
+    }
+
+    // skip the signature header (padded to 8 bytes) and the main header
+    let mut offset = lead.len() as u64;
+    for padded in [true, false] {
+        let mut header = [0u8; 16];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != HEADER_MAGIC {
+            return Err(invalid("invalid header magic"));
+        }
+        let index_count = u32::from_be_bytes(header[8..12].try_into().unwrap()) as u64;
+        let data_size = u32::from_be_bytes(header[12..16].try_into().unwrap()) as u64;
+        let mut skip = index_count * 16 + data_size;
+        offset += header.len() as u64 + skip;
+        if padded && offset % 8 != 0 {
+            skip += 8 - offset % 8;
+            offset += 8 - offset % 8;
+        }
+        std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink())?;
+    }
+
+    // detect the payload compression from its magic bytes
+    let magic = reader.fill_buf()?.get(..6).unwrap_or_default().to_vec();
+    let payload: Box<dyn Read + '_> = if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(xz2::read::XzDecoder::new(reader))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::stream::read::Decoder::new(reader)?)
+    } else if magic.starts_with(b"BZh") {
+        Box::new(bzip2::read::BzDecoder::new(reader))
+    } else if magic.starts_with(b"07070") {
+        Box::new(reader)
+    } else {
+        return Err(invalid("unsupported payload compression"));
+    };
+
+    let tmp_extraction_dir = tempfile::Builder::new().tempdir_in(target_directory)?;
+    extract_cpio(payload, tmp_extraction_dir.path(), policy)?;
+    merge_dir(tmp_extraction_dir.path(), target_directory)?;
+    progress_bar.finish_with_message("Extracted...");
 
-    move_extracted_dir(tmp_extraction_dir.path(), target_directory)?;
+    Ok(())
+}
+
+/// Extracts a layer of an OCI image on top of the target directory, which holds the
+/// previous layers. The compression follows from the media type of the layer. Whiteout
+/// files (`.wh.<name>` and `.wh..wh..opq`) remove files of the previous layers.
+pub(crate) fn extract_oci_layer(
+    layer: &Path,
+    media_type: &str,
+    target_directory: &Path,
+    log_handler: &LoggingOutputHandler,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
+    let len = layer.metadata().map(|m| m.len()).unwrap_or(1);
+    let progress_bar = log_handler.add_progress_bar(
+        indicatif::ProgressBar::new(len)
+            .with_prefix("Extracting layer")
+            .with_style(log_handler.default_bytes_style()),
+    );
+
+    let file = File::open(layer).map_err(|_| SourceError::FileNotFound(layer.to_path_buf()))?;
+    let reader: Box<dyn BufRead> = Box::new(std::io::BufReader::new(progress_bar.wrap_read(file)));
+    let decoder = if media_type.ends_with("gzip") {
+        TarCompression::Gzip(flate2::read::GzDecoder::new(reader))
+    } else if media_type.ends_with("zstd") {
+        TarCompression::Zstd(zstd::stream::read::Decoder::new(reader)?)
+    } else {
+        TarCompression::PlainTar(reader)
+    };
+    let mut archive = tar::Archive::new(decoder);
+
+    // the layer is unpacked next to the target directory, as an opaque whiteout in the
+    // root of the layer clears the whole target directory
+    let parent = target_directory.parent().unwrap_or(target_directory);
+    let tmp_extraction_dir = tempfile::Builder::new().tempdir_in(parent)?;
+    unpack_tar(&mut archive, tmp_extraction_dir.path(), policy, false)?;
+
+    merge_layer(tmp_extraction_dir.path(), target_directory)?;
+    // the symlinks of a layer may resolve through the files of the previous layers
+    if policy == ExtractionPolicy::Strict {
+        verify_symlinks(target_directory)?;
+    }
     progress_bar.finish_with_message("Extracted...");
 
     Ok(())
 }
 
+/// Moves the content of an unpacked layer into the target directory and applies its
+/// whiteout files. An opaque whiteout clears the directory it sits in.
+pub(crate) fn merge_layer(layer: &Path, target: &Path) -> Result<(), SourceError> {
+    const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+    fs::create_dir_all(target)?;
+    if layer.join(OPAQUE_WHITEOUT).exists() {
+        for entry in fs::read_dir(target)? {
+            remove_path(&entry?.path())?;
+        }
+    }
+
+    for entry in fs::read_dir(layer)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name == OPAQUE_WHITEOUT {
+            continue;
+        }
+        if let Some(removed) = name.strip_prefix(".wh.") {
+            let removed = target.join(removed);
+            if removed.symlink_metadata().is_ok() {
+                remove_path(&removed)?;
+            }
+            continue;
+        }
+
+        let destination = target.join(entry.file_name());
+        let existing = destination.symlink_metadata();
+        if entry.file_type()?.is_dir() {
+            // directories are merged, so that their whiteout files are applied as well
+            if existing.is_ok_and(|metadata| !metadata.is_dir()) {
+                remove_path(&destination)?;
+            }
+            merge_layer(&entry.path(), &destination)?;
+            fs::set_permissions(&destination, entry.metadata()?.permissions())?;
+        } else {
+            if existing.is_ok() {
+                remove_path(&destination)?;
+            }
+            fs::rename(entry.path(), &destination)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the content of `source` into `target`. Directories that exist in both are
+/// merged, other existing entries of the target directory are replaced.
+fn merge_dir(source: &Path, target: &Path) -> Result<(), SourceError> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let destination = target.join(entry.file_name());
+        let existing = destination.symlink_metadata();
+        if entry.file_type()?.is_dir() && existing.as_ref().is_ok_and(|m| m.is_dir()) {
+            merge_dir(&entry.path(), &destination)?;
+        } else {
+            if existing.is_ok() {
+                remove_path(&destination)?;
+            }
+            fs::rename(entry.path(), &destination)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes a file, symlink or directory.
+fn remove_path(path: &Path) -> Result<(), SourceError> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// The error for an archive member that is rejected by the strict extraction policy.
+fn unsafe_member(name: &Path, reason: &str) -> SourceError {
+    SourceError::UnsafeArchiveMember(name.display().to_string(), reason.to_string())
+}
+
+/// Turn the path of an archive member into a relative path. Absolute paths and paths
+/// with `..` components are rejected.
+fn strict_member_path(name: &Path) -> Result<PathBuf, SourceError> {
+    let mut path = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(unsafe_member(name, "has an absolute path"))
+            }
+            Component::ParentDir => {
+                return Err(unsafe_member(name, "escapes the target directory"))
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Turn the path of an archive member into a relative path by stripping the root.
+/// Returns `None` for paths with `..` components, which are skipped.
+fn permissive_member_path(name: &Path) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Checks (lexically) that a symlink at the relative path `member` that points to
+/// `target` stays inside the directory the archive is extracted to.
+fn link_stays_inside(member: &Path, target: &Path) -> bool {
+    let mut depth = member.components().count().saturating_sub(1);
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Rewrites the absolute `target` of a symlink at the relative path `member` of an
+/// archive that is laid out like the root of a file system into a relative target,
+/// e.g. `/usr/lib/libfoo.so.1` for `usr/lib/libfoo.so` becomes `../../usr/lib/libfoo.so.1`.
+fn rebase_link_target(member: &Path, target: &Path) -> PathBuf {
+    let depth = member.components().count().saturating_sub(1);
+    let mut relative: PathBuf = std::iter::repeat(Component::ParentDir)
+        .take(depth)
+        .collect();
+    relative.extend(
+        target
+            .components()
+            .filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_))),
+    );
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+/// Resolves all symlinks below `directory` and makes sure that none of them points
+/// outside of it. This catches escapes through chains of symlinks that each look
+/// harmless on their own. Dangling symlinks are allowed.
+fn verify_symlinks(directory: &Path) -> Result<(), SourceError> {
+    let root = dunce::canonicalize(directory)?;
+    for entry in walkdir::WalkDir::new(directory) {
+        let entry = entry?;
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        if let Ok(resolved) = dunce::canonicalize(entry.path()) {
+            if !resolved.starts_with(&root) {
+                let name = entry.path().strip_prefix(directory)?;
+                return Err(unsafe_member(
+                    name,
+                    &format!(
+                        "resolves to `{}` outside of the archive",
+                        resolved.display()
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates the given symlinks (path and target).
+fn create_symlinks(symlinks: Vec<(PathBuf, String)>) -> Result<(), SourceError> {
+    for (path, target) in symlinks {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        #[cfg(unix)]
+        fs_err::os::unix::fs::symlink(&target, &path)?;
+        #[cfg(not(unix))]
+        tracing::warn!(
+            "Skipping symlink {} -> {} (not supported on this platform)",
+            path.display(),
+            target
+        );
+    }
+    Ok(())
+}
+
+/// Extracts a cpio archive in the `newc` format (as used by RPM payloads).
+///
+/// Only directories, regular files and symlinks are extracted. Symlinks are created
+/// last so that no file is written through a symlink of the same archive. The payload
+/// is laid out like the root of a file system, so absolute symlink targets are
+/// rewritten relative to the target directory.
+fn extract_cpio(
+    mut reader: impl Read,
+    target_directory: &Path,
+    policy: ExtractionPolicy,
+) -> Result<(), SourceError> {
+    const S_IFMT: u32 = 0o170000;
+    const S_IFDIR: u32 = 0o040000;
+    const S_IFREG: u32 = 0o100000;
+    const S_IFLNK: u32 = 0o120000;
+    let invalid = |msg: &str| SourceError::ArchiveExtractionError("cpio".to_string(), msg.into());
+
+    /// Skip the padding that aligns the stream to 4 bytes.
+    fn skip_padding(reader: &mut impl Read, len: u64) -> std::io::Result<()> {
+        let padding = (4 - len % 4) % 4;
+        std::io::copy(&mut reader.take(padding), &mut std::io::sink())?;
+        Ok(())
+    }
+
+    let mut symlinks = Vec::new();
+    loop {
+        let mut header = [0u8; 110];
+        reader.read_exact(&mut header)?;
+        if &header[0..6] != b"070701" && &header[0..6] != b"070702" {
+            return Err(invalid("invalid header magic"));
+        }
+        let field = |index: usize| -> Result<u32, SourceError> {
+            let start = 6 + index * 8;
+            std::str::from_utf8(&header[start..start + 8])
+                .ok()
+                .and_then(|s| u32::from_str_radix(s, 16).ok())
+                .ok_or_else(|| invalid("invalid header field"))
+        };
+        let mode = field(1)?;
+        let file_size = field(6)? as u64;
+        let name_size = field(11)? as u64;
+
+        let mut name = vec![0u8; name_size as usize];
+        reader.read_exact(&mut name)?;
+        skip_padding(&mut reader, header.len() as u64 + name_size)?;
+        let name = String::from_utf8_lossy(&name)
+            .trim_end_matches('\0')
+            .to_string();
+
+        if name == "TRAILER!!!" {
+            break;
+        }
+
+        let relative = match policy {
+            ExtractionPolicy::Strict => Some(strict_member_path(Path::new(&name))?),
+            ExtractionPolicy::Permissive => permissive_member_path(Path::new(&name)),
+        };
+        let mut data = (&mut reader).take(file_size);
+        let Some(relative) = relative else {
+            tracing::warn!(
+                "Skipping cpio member outside of the target directory: {}",
+                name
+            );
+            std::io::copy(&mut data, &mut std::io::sink())?;
+            skip_padding(&mut reader, file_size)?;
+            continue;
+        };
+        let path = target_directory.join(&relative);
+        match mode & S_IFMT {
+            S_IFDIR => fs::create_dir_all(&path)?,
+            S_IFREG => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut file = File::create(&path)?;
+                std::io::copy(&mut data, &mut file)?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    fs::set_permissions(&path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+                }
+            }
+            S_IFLNK => {
+                let mut target = String::new();
+                data.read_to_string(&mut target)?;
+                if Path::new(&target).has_root() {
+                    target = rebase_link_target(&relative, Path::new(&target))
+                        .to_string_lossy()
+                        .into_owned();
+                }
+                if policy == ExtractionPolicy::Strict
+                    && !link_stays_inside(&relative, Path::new(&target))
+                {
+                    return Err(unsafe_member(
+                        Path::new(&name),
+                        &format!("links to `{target}` outside of the archive"),
+                    ));
+                }
+                symlinks.push((path, target));
+            }
+            _ if policy == ExtractionPolicy::Strict => {
+                return Err(unsafe_member(
+                    Path::new(&name),
+                    "is a device node, fifo or socket",
+                ))
+            }
+            _ => tracing::warn!("Skipping unsupported cpio member: {}", name),
+        }
+        // consume whatever is left of the member data
+        std::io::copy(&mut data, &mut std::io::sink())?;
+        skip_padding(&mut reader, file_size)?;
+    }
+
+    create_symlinks(symlinks)?;
+    if policy == ExtractionPolicy::Strict {
+        verify_symlinks(target_directory)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::{fs::File, io::Write};
 
+    use std::path::Path;
+
     use crate::{console_utils::LoggingOutputHandler, source::SourceError};
 
-    use super::extract_zip;
+    use super::{
+        extract_deb, extract_oci_layer, extract_rpm, extract_tar, extract_zip, link_stays_inside,
+        permissive_member_path, rebase_link_target, strict_member_path, ArchiveFormat,
+        ExtractionPolicy,
+    };
+
+    #[test]
+    fn test_archive_format_from_path() {
+        let cases = [
+            ("foo.tar.gz", Some(ArchiveFormat::Tar)),
+            ("foo.tar.zst", Some(ArchiveFormat::Tar)),
+            ("foo.zip", Some(ArchiveFormat::Zip)),
+            ("foo.7z", Some(ArchiveFormat::SevenZip)),
+            ("foo.tar.lz4", Some(ArchiveFormat::Lz4)),
+            ("foo.lz4", Some(ArchiveFormat::Lz4)),
+            ("foo_1.0_amd64.deb", Some(ArchiveFormat::Deb)),
+            ("foo-1.0.x86_64.rpm", Some(ArchiveFormat::Rpm)),
+            ("foo.txt", None),
+        ];
+        for (file_name, expected) in cases {
+            assert_eq!(ArchiveFormat::from_path(Path::new(file_name)), expected);
+        }
+    }
+
+    #[test]
+    fn test_member_path() {
+        assert_eq!(
+            strict_member_path(Path::new("./usr/bin/foo")).unwrap(),
+            Path::new("usr/bin/foo")
+        );
+        assert!(strict_member_path(Path::new("/usr/bin/foo")).is_err());
+        assert!(strict_member_path(Path::new("usr/../../etc/passwd")).is_err());
+
+        assert_eq!(
+            permissive_member_path(Path::new("/usr/bin/foo")).unwrap(),
+            Path::new("usr/bin/foo")
+        );
+        assert!(permissive_member_path(Path::new("usr/../../etc/passwd")).is_none());
+    }
+
+    #[test]
+    fn test_link_stays_inside() {
+        let member = Path::new("pkg/lib/libfoo.so");
+        assert!(link_stays_inside(member, Path::new("libfoo.so.1")));
+        assert!(link_stays_inside(member, Path::new("../../pkg/README")));
+        assert!(!link_stays_inside(member, Path::new("../../../etc/passwd")));
+        assert!(!link_stays_inside(member, Path::new("/etc/passwd")));
+    }
+
+    #[test]
+    fn test_rebase_link_target() {
+        let member = Path::new("usr/lib/libfoo.so");
+        assert_eq!(
+            rebase_link_target(member, Path::new("/usr/lib/libfoo.so.1")),
+            Path::new("../../usr/lib/libfoo.so.1")
+        );
+        assert_eq!(
+            rebase_link_target(Path::new("root"), Path::new("/")),
+            Path::new(".")
+        );
+        // links that escape the root of the payload are still caught
+        let escaping = rebase_link_target(member, Path::new("/../etc/passwd"));
+        assert!(!link_stays_inside(member, &escaping));
+    }
+
+    /// Writes a tarball with a single member and returns its path.
+    fn write_tar(
+        dir: &Path,
+        name: &str,
+        entry_type: tar::EntryType,
+        link_name: Option<&str>,
+    ) -> std::path::PathBuf {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(if entry_type.is_file() { 5 } else { 0 });
+        header.set_mode(0o644);
+        if let Some(link_name) = link_name {
+            header.set_link_name(link_name).unwrap();
+        }
+        // bypass the path validation of `tar::Builder::append_data`
+        let path = &mut header.as_old_mut().name;
+        path[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_cksum();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let data: &[u8] = if entry_type.is_file() { b"hello" } else { &[] };
+        builder.append(&header, data).unwrap();
+
+        let file_path = dir.join("test.tar");
+        std::fs::write(&file_path, builder.into_inner().unwrap()).unwrap();
+        file_path
+    }
+
+    #[test]
+    fn test_extract_tar_rejects_unsafe_members() {
+        let cases = [
+            ("../evil.txt", tar::EntryType::Regular, None),
+            ("/tmp/evil.txt", tar::EntryType::Regular, None),
+            (
+                "pkg/link",
+                tar::EntryType::Symlink,
+                Some("../../etc/passwd"),
+            ),
+            ("pkg/link", tar::EntryType::Symlink, Some("/etc/passwd")),
+            ("pkg/link", tar::EntryType::Link, Some("../etc/passwd")),
+            ("pkg/dev", tar::EntryType::Char, None),
+        ];
+
+        for (name, entry_type, link_name) in cases {
+            let tempdir = tempfile::tempdir().unwrap();
+            let archive = write_tar(tempdir.path(), name, entry_type, link_name);
+            let target = tempdir.path().join("out");
+            std::fs::create_dir(&target).unwrap();
+
+            let res = extract_tar(
+                &archive,
+                &target,
+                &LoggingOutputHandler::default(),
+                ExtractionPolicy::Strict,
+            );
+            assert!(
+                matches!(res, Err(SourceError::UnsafeArchiveMember(..))),
+                "{name} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_tar_allows_inner_symlink() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let archive = write_tar(
+            tempdir.path(),
+            "pkg/lib/libfoo.so",
+            tar::EntryType::Symlink,
+            Some("../README"),
+        );
+        let target = tempdir.path().join("out");
+        std::fs::create_dir(&target).unwrap();
+
+        extract_tar(
+            &archive,
+            &target,
+            &LoggingOutputHandler::default(),
+            ExtractionPolicy::Strict,
+        )
+        .unwrap();
+        assert!(target.join("lib/libfoo.so").symlink_metadata().is_ok());
+    }
+
+    #[test]
+    fn test_extract_tar_rejects_symlink_to_stripped_root() {
+        // `pkg/link -> ..` stays inside the archive, but points outside of the target
+        // directory once the `pkg` root directory is stripped
+        let tempdir = tempfile::tempdir().unwrap();
+        let archive = write_tar(
+            tempdir.path(),
+            "pkg/link",
+            tar::EntryType::Symlink,
+            Some(".."),
+        );
+        let target = tempdir.path().join("out");
+        std::fs::create_dir(&target).unwrap();
+
+        let res = extract_tar(
+            &archive,
+            &target,
+            &LoggingOutputHandler::default(),
+            ExtractionPolicy::Strict,
+        );
+        assert!(matches!(res, Err(SourceError::UnsafeArchiveMember(..))));
+        assert!(target.join("link").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_extract_oci_layers() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let layer = |name: &str, entries: &[&str]| {
+            let mut builder = tar::Builder::new(Vec::new());
+            for entry in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(2);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, entry, &b"ok"[..]).unwrap();
+            }
+            let path = tempdir.path().join(name);
+            std::fs::write(&path, builder.into_inner().unwrap()).unwrap();
+            path
+        };
+        let base = layer("base", &["usr/bin/tool", "usr/share/doc", "etc/config"]);
+        let update = layer(
+            "update",
+            &["usr/bin/.wh.tool", "usr/lib/libfoo.so", "etc/.wh..wh..opq"],
+        );
+
+        let target = tempdir.path().join("work");
+        std::fs::create_dir(&target).unwrap();
+        for layer in [base, update] {
+            extract_oci_layer(
+                &layer,
+                "application/vnd.oci.image.layer.v1.tar",
+                &target,
+                &LoggingOutputHandler::default(),
+                ExtractionPolicy::Strict,
+            )
+            .unwrap();
+        }
+
+        assert!(!target.join("usr/bin/tool").exists());
+        assert!(target.join("usr/share/doc").exists());
+        assert!(target.join("usr/lib/libfoo.so").exists());
+        assert!(target.join("etc").is_dir());
+        assert!(!target.join("etc/config").exists());
+        assert!(!target.join("etc/.wh..wh..opq").exists());
+    }
+
+    #[test]
+    fn test_extract_deb() {
+        let mut data_tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        data_tar
+            .append_data(&mut header, "./usr/share/hello.txt", &b"hello"[..])
+            .unwrap();
+        // deb payloads are rooted at `/`, so absolute symlinks are common
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        data_tar
+            .append_link(&mut header, "./usr/bin/hello.txt", "/usr/share/hello.txt")
+            .unwrap();
+        let data_tar = data_tar.into_inner().unwrap();
+
+        let mut deb = b"!<arch>\n".to_vec();
+        for (name, content) in [
+            ("debian-binary", &b"2.0\n"[..]),
+            ("control.tar", &[0u8; 3][..]),
+            ("data.tar", &data_tar[..]),
+        ] {
+            deb.extend(
+                format!(
+                    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                    name,
+                    0,
+                    0,
+                    0,
+                    644,
+                    content.len()
+                )
+                .as_bytes(),
+            );
+            deb.extend(content);
+            if content.len() % 2 == 1 {
+                deb.push(b'\n');
+            }
+        }
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let file_path = tempdir.path().join("test.deb");
+        std::fs::write(&file_path, deb).unwrap();
+        let target = tempdir.path().join("out");
+        std::fs::create_dir(&target).unwrap();
+
+        extract_deb(
+            &file_path,
+            &target,
+            &LoggingOutputHandler::default(),
+            ExtractionPolicy::Strict,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target.join("usr/share/hello.txt")).unwrap(),
+            "hello"
+        );
+        #[cfg(unix)]
+        {
+            assert_eq!(
+                std::fs::read_link(target.join("usr/bin/hello.txt")).unwrap(),
+                Path::new("../../usr/share/hello.txt")
+            );
+            assert_eq!(
+                std::fs::read_to_string(target.join("usr/bin/hello.txt")).unwrap(),
+                "hello"
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_rpm() {
+        fn cpio_entry(cpio: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+            let name_size = name.len() + 1;
+            cpio.extend(
+                format!(
+                    "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+                    0, mode, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, name_size, 0
+                )
+                .as_bytes(),
+            );
+            cpio.extend(name.as_bytes());
+            cpio.push(0);
+            cpio.resize(cpio.len() + (4 - (110 + name_size) % 4) % 4, 0);
+            cpio.extend(data);
+            cpio.resize(cpio.len() + (4 - data.len() % 4) % 4, 0);
+        }
+
+        // lead, an empty signature header and an empty main header
+        let mut rpm = vec![0xed, 0xab, 0xee, 0xdb];
+        rpm.resize(96, 0);
+        for _ in 0..2 {
+            rpm.extend([0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0]);
+            rpm.extend([0u8; 8]);
+        }
+        cpio_entry(&mut rpm, "./opt/vendor", 0o040755, &[]);
+        cpio_entry(&mut rpm, "./opt/vendor/lib.so", 0o100644, b"binary");
+        cpio_entry(
+            &mut rpm,
+            "./opt/vendor/lib.so.1",
+            0o120777,
+            b"/opt/vendor/lib.so",
+        );
+        cpio_entry(&mut rpm, "TRAILER!!!", 0, &[]);
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let file_path = tempdir.path().join("test.rpm");
+        std::fs::write(&file_path, rpm).unwrap();
+        let target = tempdir.path().join("out");
+        // the payload is merged into existing directories of the target
+        std::fs::create_dir_all(target.join("opt/other")).unwrap();
+
+        extract_rpm(
+            &file_path,
+            &target,
+            &LoggingOutputHandler::default(),
+            ExtractionPolicy::Strict,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target.join("opt/vendor/lib.so")).unwrap(),
+            "binary"
+        );
+        assert!(target.join("opt/other").is_dir());
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(target.join("opt/vendor/lib.so.1")).unwrap(),
+            Path::new("../../opt/vendor/lib.so")
+        );
+    }
 
     #[test]
     fn test_extract_zip() {
@@ -212,7 +1369,12 @@ mod test {
         _ = file.write_all(HELLOW_ZIP_FILE);
 
         let fancy_log = LoggingOutputHandler::from_multi_progress(multi_progress);
-        let res = extract_zip(file_path, tempdir.path(), &fancy_log);
+        let res = extract_zip(
+            file_path,
+            tempdir.path(),
+            &fancy_log,
+            ExtractionPolicy::Strict,
+        );
         assert!(term.contents().trim().starts_with(
             "Extracting zip       [00:00:00] [━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━]"
         ));
@@ -227,7 +1389,7 @@ mod test {
     fn test_extract_fail() {
         let fancy_log = LoggingOutputHandler::default();
         let tempdir = tempfile::tempdir().unwrap();
-        let res = extract_zip("", tempdir.path(), &fancy_log);
+        let res = extract_zip("", tempdir.path(), &fancy_log, ExtractionPolicy::Strict);
         assert!(matches!(res.err(), Some(SourceError::FileNotFound(_))));
     }
 
@@ -237,7 +1399,7 @@ mod test {
         let tempdir = tempfile::tempdir().unwrap();
         let file = tempdir.path().join("test.zip");
         _ = File::create(&file);
-        let res = extract_zip(file, tempdir.path(), &fancy_log);
+        let res = extract_zip(file, tempdir.path(), &fancy_log, ExtractionPolicy::Strict);
         assert!(matches!(res.err(), Some(SourceError::InvalidZip(_))));
     }
 }
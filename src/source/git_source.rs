@@ -6,10 +6,15 @@ use std::{
     process::Command,
 };
 
+use base64::{engine::general_purpose, Engine};
+use rattler_networking::Authentication;
+use url::Url;
+
 use crate::system_tools::{SystemTools, Tool};
 use crate::{
-    recipe::parser::{GitSource, GitUrl},
+    recipe::parser::{GitRev, GitSource, GitSubmodules, GitUrl},
     system_tools::ToolError,
+    tool_configuration::{get_auth_store, NetworkSettings},
 };
 
 use super::SourceError;
@@ -17,10 +22,12 @@ use super::SourceError;
 /// Fetch the given repository using the host `git` executable.
 pub fn fetch_repo(
     system_tools: &SystemTools,
+    git_env: &[(&'static str, String)],
     repo_path: &Path,
     url: &str,
-    rev: &str,
+    source: &GitSource,
 ) -> Result<(), SourceError> {
+    let rev = source.rev().to_string();
     tracing::info!(
         "Fetching repository from {} at {} into {}",
         url,
@@ -33,9 +40,18 @@ pub fn fetch_repo(
     }
 
     let mut command = git_command(system_tools, "fetch")?;
+    command.envs(git_env.iter().cloned()).current_dir(repo_path);
+    if let Some(depth) = shallow_depth(source) {
+        command.args(["--depth", depth.to_string().as_str()]);
+    }
+    match source.rev() {
+        // update the local branch or tag as well, so that it can be checked out below
+        GitRev::Branch(_) | GitRev::Tag(_) => {
+            command.args(["--update-head-ok", url, format!("+{rev}:{rev}").as_str()])
+        }
+        GitRev::Commit(_) | GitRev::Head => command.args([url, rev.as_str()]),
+    };
     let output = command
-        .args([url, rev])
-        .current_dir(repo_path)
         .output()
         .map_err(|_err| SourceError::ValidationFailed)?;
 
@@ -66,7 +82,7 @@ pub fn fetch_repo(
     }
 
     let output = git_command(system_tools, "checkout")?
-        .args([rev])
+        .args([rev.as_str()])
         .current_dir(repo_path)
         .output()
         .map_err(|_err| SourceError::ValidationFailed)?;
@@ -76,21 +92,125 @@ pub fn fetch_repo(
         return Err(SourceError::GitErrorStr("failed to checkout FETCH_HEAD"));
     }
 
-    // Update submodules
-    let output = git_command(system_tools, "submodule")?
-        .args(["update", "--init", "--recursive"])
-        .current_dir(repo_path)
-        .output()?;
+    update_submodules(system_tools, git_env, repo_path, source)?;
+
+    tracing::debug!("Repository fetched successfully!");
+    Ok(())
+}
+
+/// Check out the revision of the source in the cached repository without fetching it. Fails
+/// (and records the fetch that would have been needed) if the repository or the revision is
+/// not in the cache.
+fn checkout_offline(
+    network: &NetworkSettings,
+    repo_path: &Path,
+    url: &str,
+    source: &GitSource,
+) -> Result<(), SourceError> {
+    let rev = source.rev().to_string();
+    let checked_out = repo_path.exists()
+        && Command::new("git")
+            .current_dir(repo_path)
+            .args(["checkout", "--force", rev.as_str()])
+            .output()
+            .map_err(|err| SourceError::GitError(format!("failed to run `git checkout`: {err}")))?
+            .status
+            .success();
+    if !checked_out {
+        network.refuse_offline(format!("git fetch {url} {rev}"));
+        return Err(SourceError::Offline(format!("{url} at {rev}")));
+    }
+    tracing::info!("Using the cached repository of {} (offline)", url);
+    Ok(())
+}
+
+/// The depth of a shallow clone, or `None` to fetch the entire history (`depth: -1`).
+fn shallow_depth(source: &GitSource) -> Option<i32> {
+    source.depth().filter(|depth| *depth > 0)
+}
+
+/// Check out the submodules of the repository as requested by the source.
+fn update_submodules(
+    system_tools: &SystemTools,
+    git_env: &[(&'static str, String)],
+    repo_path: &Path,
+    source: &GitSource,
+) -> Result<(), SourceError> {
+    let mut command = git_command(system_tools, "submodule")?;
+    command
+        .envs(git_env.iter().cloned())
+        .args(["update", "--init"])
+        .current_dir(repo_path);
+    match source.submodules() {
+        GitSubmodules::None => return Ok(()),
+        GitSubmodules::TopLevel => {}
+        GitSubmodules::Recursive => {
+            command.arg("--recursive");
+        }
+    }
+    if let Some(depth) = shallow_depth(source) {
+        command.args(["--depth", depth.to_string().as_str()]);
+    }
 
+    let output = command.output()?;
     if !output.status.success() {
         tracing::debug!("Submodule update failed!");
         return Err(SourceError::GitErrorStr("failed to update submodules"));
     }
-
-    tracing::debug!("Repository fetched successfully!");
     Ok(())
 }
 
+/// The environment variables that pass the credentials for `url` from the authentication
+/// storage to `git`, as an `Authorization` header for requests to that host.
+fn git_auth_env(
+    network: &NetworkSettings,
+    url: &Url,
+) -> Result<Vec<(&'static str, String)>, SourceError> {
+    let Some(host) = url.host_str() else {
+        return Ok(Vec::new());
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return Ok(Vec::new());
+    }
+
+    let storage = get_auth_store(network.auth_file.clone()).map_err(|e| {
+        SourceError::GitError(format!("failed to read the authentication storage: {e}"))
+    })?;
+    let header = match storage.get_by_url(url.clone()) {
+        Ok((_, Some(Authentication::BearerToken(token)))) => format!("Bearer {token}"),
+        Ok((_, Some(Authentication::BasicHTTP { username, password }))) => format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode(format!("{username}:{password}"))
+        ),
+        Ok((_, Some(_))) => {
+            tracing::warn!(
+                "The credentials for {} can not be used for git, only bearer tokens and basic \
+                 http authentication are supported",
+                url
+            );
+            return Ok(Vec::new());
+        }
+        Ok((_, None)) => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(SourceError::GitError(format!(
+                "failed to get the credentials for {url}: {e}"
+            )))
+        }
+    };
+
+    // scope the header to the host, so that it is not sent to other servers (e.g. those of
+    // submodules or the lfs storage)
+    let scope = match url.port() {
+        Some(port) => format!("{}://{}:{}/", url.scheme(), host, port),
+        None => format!("{}://{}/", url.scheme(), host),
+    };
+    Ok(vec![
+        ("GIT_CONFIG_COUNT", "1".to_string()),
+        ("GIT_CONFIG_KEY_0", format!("http.{scope}.extraHeader")),
+        ("GIT_CONFIG_VALUE_0", format!("Authorization: {header}")),
+    ])
+}
+
 /// Create a `git` command with the given subcommand.
 fn git_command(system_tools: &SystemTools, sub_cmd: &str) -> Result<Command, ToolError> {
     let mut command = system_tools.call(Tool::Git)?;
@@ -110,12 +230,13 @@ fn git_command(system_tools: &SystemTools, sub_cmd: &str) -> Result<Command, Too
 /// Fetch the git repository specified by the given source and place it in the cache directory.
 pub fn git_src(
     system_tools: &SystemTools,
+    network: &NetworkSettings,
     source: &GitSource,
     cache_dir: &Path,
     recipe_dir: &Path,
 ) -> Result<(PathBuf, String), SourceError> {
     // depth == -1, fetches the entire git history
-    if !source.rev().is_head() && (source.depth().is_some() && source.depth() != Some(-1)) {
+    if matches!(source.rev(), GitRev::Commit(_)) && shallow_depth(source).is_some() {
         return Err(SourceError::GitErrorStr(
             "use of `depth` with `rev` is invalid, they are mutually exclusive",
         ));
@@ -159,20 +280,37 @@ pub fn git_src(
 
     let rev = source.rev().to_string();
 
+    let mut git_env = network.git_env();
+    if let GitUrl::Url(url) = source.url() {
+        git_env.extend(git_auth_env(network, &network.mirror_url(url))?);
+    }
+
     // Initialize or clone the repository depending on the source's git_url.
     match &source.url() {
         GitUrl::Url(_) | GitUrl::Ssh(_) => {
             let url = match &source.url() {
-                GitUrl::Url(url) => url.to_string(),
+                GitUrl::Url(url) => network.mirror_url(url).to_string(),
                 GitUrl::Ssh(url) => url.to_string(),
                 _ => unreachable!(),
             };
-            // If the cache_path exists, initialize the repo and fetch the specified revision.
-            if !cache_path.exists() {
+            if network.offline {
+                checkout_offline(network, &cache_path, &url, source)?;
+            } else if !cache_path.exists() {
+                // clone the repository into the cache, the revision is fetched below
                 let mut command = git_command(system_tools, "clone")?;
                 command
-                    .args(["--progress", "-n", source.url().to_string().as_str()])
-                    .arg(cache_path.as_os_str());
+                    .envs(git_env.iter().cloned())
+                    .args(["--progress", "-n"]);
+                if let Some(depth) = shallow_depth(source) {
+                    command.args(["--depth", depth.to_string().as_str()]);
+                    match source.rev() {
+                        GitRev::Branch(name) | GitRev::Tag(name) => {
+                            command.args(["--branch", name.as_str()]);
+                        }
+                        GitRev::Commit(_) | GitRev::Head => {}
+                    }
+                }
+                command.arg(url.as_str()).arg(cache_path.as_os_str());
 
                 let output = command
                     .output()
@@ -186,8 +324,10 @@ pub fn git_src(
                 }
             }
 
-            assert!(cache_path.exists());
-            fetch_repo(system_tools, &cache_path, &url.to_string(), &rev)?;
+            if !network.offline {
+                assert!(cache_path.exists());
+                fetch_repo(system_tools, &git_env, &cache_path, &url, source)?;
+            }
         }
         GitUrl::Path(path) => {
             if cache_path.exists() {
@@ -206,15 +346,13 @@ pub fn git_src(
             let path = path.to_string_lossy();
             let mut command = git_command(system_tools, "clone")?;
 
+            if let Some(depth) = shallow_depth(source) {
+                command.args(["--depth", depth.to_string().as_str()]);
+            }
             command
-                .arg("--recursive")
                 .arg(format!("file://{}/.git", path).as_str())
                 .arg(cache_path.as_os_str());
 
-            if let Some(depth) = source.depth() {
-                command.args(["--depth", depth.to_string().as_str()]);
-            }
-
             let output = command
                 .output()
                 .map_err(|_| SourceError::ValidationFailed)?;
@@ -225,6 +363,8 @@ pub fn git_src(
                     "failed to execute clone from file",
                 ));
             }
+
+            update_submodules(system_tools, &git_env, &cache_path, source)?;
         }
     }
 
@@ -248,7 +388,7 @@ pub fn git_src(
 
     // only do lfs pull if a requirement!
     if source.lfs() {
-        git_lfs_pull(&ref_git)?;
+        git_lfs_pull(&git_env, &cache_path, &ref_git)?;
     }
 
     tracing::info!(
@@ -260,10 +400,14 @@ pub fn git_src(
     Ok((cache_path, ref_git))
 }
 
-fn git_lfs_pull(git_ref: &str) -> Result<(), SourceError> {
+fn git_lfs_pull(
+    git_env: &[(&'static str, String)],
+    repo_path: &Path,
+    git_ref: &str,
+) -> Result<(), SourceError> {
     // verify git-lfs is installed
     let mut command = Command::new("git");
-    command.args(["lfs", "ls-files"]);
+    command.args(["lfs", "ls-files"]).current_dir(repo_path);
     let output = command
         .output()
         .map_err(|_| SourceError::GitErrorStr("failed to execute command"))?;
@@ -275,7 +419,10 @@ fn git_lfs_pull(git_ref: &str) -> Result<(), SourceError> {
 
     // git lfs fetch
     let mut command = Command::new("git");
-    command.args(["lfs", "fetch", "origin", git_ref]);
+    command
+        .envs(git_env.iter().cloned())
+        .args(["lfs", "fetch", "origin", git_ref])
+        .current_dir(repo_path);
     let output = command
         .output()
         .map_err(|_| SourceError::GitErrorStr("failed to execute command"))?;
@@ -285,7 +432,7 @@ fn git_lfs_pull(git_ref: &str) -> Result<(), SourceError> {
 
     // git lfs checkout
     let mut command = Command::new("git");
-    command.args(["lfs", "checkout"]);
+    command.args(["lfs", "checkout"]).current_dir(repo_path);
     let output = command
         .output()
         .map_err(|_| SourceError::GitErrorStr("failed to execute command"))?;
@@ -375,6 +522,7 @@ mod tests {
         for (source, repo_name) in cases {
             let res = git_src(
                 &system_tools,
+                &Default::default(),
                 &source,
                 cache_dir.as_ref(),
                 // TODO: this test assumes current dir is the root folder of the project which may
@@ -1,30 +1,30 @@
 //! Module for fetching sources and applying patches
 
-use std::{
-    ffi::OsStr,
-    path::{PathBuf, StripPrefixError},
-};
+use std::path::{Path, PathBuf, StripPrefixError};
 
 use crate::{
     metadata::{Directories, Output},
-    recipe::parser::{GitRev, GitSource, Source},
+    recipe::parser::{GitRev, GitSource, HgSource, Source, SourcePatch, SvnSource},
     source::{
         checksum::Checksum,
-        extract::{extract_tar, extract_zip, is_tarball},
+        extract::{extract_archive, ArchiveFormat},
     },
     system_tools::ToolError,
     tool_configuration,
 };
 
 use fs_err as fs;
+use rattler_conda_types::Platform;
 
 use crate::system_tools::SystemTools;
 pub mod checksum;
 pub mod copy_dir;
 pub mod extract;
 pub mod git_source;
+pub mod oci_source;
 pub mod patch;
 pub mod url_source;
+pub mod vcs_source;
 
 #[allow(missing_docs)]
 #[derive(Debug, thiserror::Error)]
@@ -59,8 +59,8 @@ pub enum SourceError {
     #[error("Patch file not found: {0}")]
     PatchNotFound(PathBuf),
 
-    #[error("Failed to apply patch: {0}")]
-    PatchFailed(String),
+    #[error("Failed to apply patch {0} {1}")]
+    PatchFailed(String, String),
 
     #[error("Failed to extract archive: {0}")]
     TarExtractionError(String),
@@ -71,6 +71,12 @@ pub enum SourceError {
     #[error("Failed to read from zip: {0}")]
     InvalidZip(String),
 
+    #[error("Failed to extract {0} archive: {1}")]
+    ArchiveExtractionError(String, String),
+
+    #[error("Refusing to extract archive member `{0}`: it {1} (use `--allow-unsafe-extraction` to extract it anyway)")]
+    UnsafeArchiveMember(String, String),
+
     #[error("Failed to run git command: {0}")]
     GitError(String),
 
@@ -94,14 +100,30 @@ pub enum SourceError {
 
     #[error("Failed to find git executable: {0}")]
     GitNotFound(#[from] ToolError),
+
+    #[error("Failed to pull {0}: {1}")]
+    OciError(String, String),
+
+    #[error("Failed to run hg command: {0}")]
+    HgError(String),
+
+    #[error("Failed to run svn command: {0}")]
+    SvnError(String),
+
+    #[error("{0} is not in the source cache and network access is disabled (--offline)")]
+    Offline(String),
 }
 
-/// Fetches all sources in a list of sources and applies specified patches
+/// Fetches all sources in a list of sources and applies specified patches. If
+/// `link_path_sources` is set, local directories are linked into the work directory
+/// instead of copied. The platform selects the image of multi-platform OCI sources.
 pub async fn fetch_sources(
     sources: &[Source],
     directories: &Directories,
+    platform: Platform,
     system_tools: &SystemTools,
     tool_configuration: &tool_configuration::Configuration,
+    link_path_sources: bool,
 ) -> Result<Vec<Source>, SourceError> {
     if sources.is_empty() {
         tracing::info!("No sources to fetch");
@@ -111,8 +133,11 @@ pub async fn fetch_sources(
     // Figure out the directories we need
     let work_dir = &directories.work_dir;
     let recipe_dir = &directories.recipe_dir;
-    let cache_src = directories.output_dir.join("src_cache");
+    let cache_src = tool_configuration.source_cache_dir(&directories.output_dir);
     fs::create_dir_all(&cache_src)?;
+    let _lock = tool_configuration
+        .lock_cache(&cache_src, "source cache")
+        .await?;
 
     let mut rendered_sources = Vec::new();
 
@@ -120,34 +145,30 @@ pub async fn fetch_sources(
         match &src {
             Source::Git(src) => {
                 tracing::info!("Fetching source from git repo: {}", src.url());
-                let result = git_source::git_src(system_tools, src, &cache_src, recipe_dir)?;
-                let dest_dir = if let Some(target_directory) = src.target_directory() {
-                    work_dir.join(target_directory)
-                } else {
-                    work_dir.to_path_buf()
-                };
+                let result = git_source::git_src(
+                    system_tools,
+                    &tool_configuration.network,
+                    src,
+                    &cache_src,
+                    recipe_dir,
+                )?;
 
                 rendered_sources.push(Source::Git(GitSource {
                     rev: GitRev::Commit(result.1),
                     ..src.clone()
                 }));
 
-                let copy_result = tool_configuration.fancy_log_handler.wrap_in_progress(
-                    "copying source into isolated environment",
-                    || {
-                        copy_dir::CopyDir::new(&result.0, &dest_dir)
-                            .use_gitignore(false)
-                            .run()
-                    },
+                // `.git` is kept, build scripts use it to derive the version (e.g. with
+                // `git describe` or setuptools-scm)
+                copy_checkout(
+                    &result.0,
+                    None,
+                    src.target_directory(),
+                    src.patches(),
+                    directories,
+                    system_tools,
+                    tool_configuration,
                 )?;
-                tracing::info!(
-                    "Copied {} files into isolated environment",
-                    copy_result.copied_paths().len()
-                );
-
-                if !src.patches().is_empty() {
-                    patch::apply_patches(system_tools, src.patches(), &dest_dir, recipe_dir)?;
-                }
             }
             Source::Url(src) => {
                 let first_url = src.urls().first().expect("we should have at least one URL");
@@ -168,17 +189,16 @@ pub async fn fetch_sources(
                     fs::create_dir_all(&dest_dir)?;
                 }
 
-                if is_tarball(
-                    res.file_name()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .as_ref(),
-                ) {
-                    extract_tar(&res, &dest_dir, &tool_configuration.fancy_log_handler)?;
+                if let Some(format) = ArchiveFormat::from_path(&res) {
+                    extract_archive(
+                        format,
+                        &res,
+                        &dest_dir,
+                        system_tools,
+                        &tool_configuration.fancy_log_handler,
+                        tool_configuration.extraction_policy,
+                    )?;
                     tracing::info!("Extracted to {:?}", dest_dir);
-                } else if res.extension() == Some(OsStr::new("zip")) {
-                    extract_zip(&res, &dest_dir, &tool_configuration.fancy_log_handler)?;
-                    tracing::info!("Extracted zip to {:?}", dest_dir);
                 } else {
                     if let Some(file_name) = src.file_name() {
                         dest_dir = dest_dir.join(file_name);
@@ -215,7 +235,24 @@ pub async fn fetch_sources(
                 }
 
                 // check if the source path is a directory
-                if src_path.is_dir() {
+                // patches would modify the linked source directory, so copy those sources
+                let link_dir = link_path_sources && src.patches().is_empty();
+                if link_path_sources && !link_dir && src_path.is_dir() {
+                    tracing::warn!(
+                        "Copying {:?} instead of linking it because it has patches",
+                        src_path
+                    );
+                }
+
+                if src_path.is_dir() && link_dir {
+                    let linked =
+                        copy_dir::link_dir_entries(&src_path, &dest_dir, src.use_gitignore())?;
+                    tracing::info!(
+                        "Linked {} entries of {:?} into the work directory",
+                        linked.len(),
+                        src_path
+                    );
+                } else if src_path.is_dir() {
                     let copy_result = tool_configuration.fancy_log_handler.wrap_in_progress(
                         "copying source into isolated environment",
                         || {
@@ -228,18 +265,21 @@ pub async fn fetch_sources(
                         "Copied {} files into isolated environment",
                         copy_result.copied_paths().len()
                     );
-                } else if is_tarball(
-                    src_path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .as_ref(),
-                ) {
-                    extract_tar(&src_path, &dest_dir, &tool_configuration.fancy_log_handler)?;
+                } else if let Some(format) = ArchiveFormat::from_path(&src_path) {
+                    if let Some(checksum) = Checksum::from_path_source(src) {
+                        if !checksum.validate(&src_path) {
+                            return Err(SourceError::ValidationFailed);
+                        }
+                    }
+                    extract_archive(
+                        format,
+                        &src_path,
+                        &dest_dir,
+                        system_tools,
+                        &tool_configuration.fancy_log_handler,
+                        tool_configuration.extraction_policy,
+                    )?;
                     tracing::info!("Extracted to {:?}", dest_dir);
-                } else if src_path.extension() == Some(OsStr::new("zip")) {
-                    extract_zip(&src_path, &dest_dir, &tool_configuration.fancy_log_handler)?;
-                    tracing::info!("Extracted zip to {:?}", dest_dir);
                 } else if let Some(file_name) = src
                     .file_name()
                     .cloned()
@@ -266,11 +306,130 @@ pub async fn fetch_sources(
 
                 rendered_sources.push(Source::Path(src.clone()));
             }
+            Source::Hg(src) => {
+                if tool_configuration
+                    .network
+                    .refuse_offline(format!("hg pull {}", src.url()))
+                {
+                    return Err(SourceError::Offline(src.url().to_string()));
+                }
+                tracing::info!("Fetching source from hg repo: {}", src.url());
+                let (checkout, node) = vcs_source::hg_src(system_tools, src, &cache_src)?;
+
+                rendered_sources.push(Source::Hg(HgSource {
+                    rev: Some(node),
+                    ..src.clone()
+                }));
+
+                copy_checkout(
+                    &checkout,
+                    Some(".hg"),
+                    src.target_directory(),
+                    src.patches(),
+                    directories,
+                    system_tools,
+                    tool_configuration,
+                )?;
+            }
+            Source::Svn(src) => {
+                if tool_configuration
+                    .network
+                    .refuse_offline(format!("svn checkout {}", src.url()))
+                {
+                    return Err(SourceError::Offline(src.url().to_string()));
+                }
+                tracing::info!("Fetching source from svn repo: {}", src.url());
+                let (checkout, revision) = vcs_source::svn_src(system_tools, src, &cache_src)?;
+
+                rendered_sources.push(Source::Svn(SvnSource {
+                    rev: Some(revision),
+                    ..src.clone()
+                }));
+
+                copy_checkout(
+                    &checkout,
+                    Some(".svn"),
+                    src.target_directory(),
+                    src.patches(),
+                    directories,
+                    system_tools,
+                    tool_configuration,
+                )?;
+            }
+            Source::Oci(src) => {
+                if tool_configuration
+                    .network
+                    .refuse_offline(format!("oci pull {}", src.reference()))
+                {
+                    return Err(SourceError::Offline(src.reference().to_string()));
+                }
+                tracing::info!("Fetching source from OCI registry: {}", src.reference());
+                let dest_dir = if let Some(target_directory) = src.target_directory() {
+                    work_dir.join(target_directory)
+                } else {
+                    work_dir.to_path_buf()
+                };
+                fs::create_dir_all(&dest_dir)?;
+
+                let rendered = oci_source::oci_src(
+                    src,
+                    &cache_src,
+                    &dest_dir,
+                    platform,
+                    system_tools,
+                    tool_configuration,
+                )
+                .await?;
+
+                if !src.patches().is_empty() {
+                    patch::apply_patches(system_tools, src.patches(), &dest_dir, recipe_dir)?;
+                }
+
+                rendered_sources.push(Source::Oci(rendered));
+            }
         }
     }
     Ok(rendered_sources)
 }
 
+/// Copies the checkout of a version control system into the work directory (or its
+/// `target_directory`) and applies the patches. The metadata directory of the version
+/// control system (e.g. `.hg`) is left out, if given.
+fn copy_checkout(
+    checkout: &Path,
+    metadata_dir: Option<&str>,
+    target_directory: Option<&PathBuf>,
+    patches: &[SourcePatch],
+    directories: &Directories,
+    system_tools: &SystemTools,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<(), SourceError> {
+    let dest_dir = match target_directory {
+        Some(target_directory) => directories.work_dir.join(target_directory),
+        None => directories.work_dir.clone(),
+    };
+    let exclude = metadata_dir.map(|dir| format!("{dir}/**"));
+
+    let copy_result = tool_configuration.fancy_log_handler.wrap_in_progress(
+        "copying source into isolated environment",
+        || {
+            copy_dir::CopyDir::new(checkout, &dest_dir)
+                .use_gitignore(false)
+                .with_exclude_globs(exclude.as_deref())
+                .run()
+        },
+    )?;
+    tracing::info!(
+        "Copied {} files into isolated environment",
+        copy_result.copied_paths().len()
+    );
+
+    if !patches.is_empty() {
+        patch::apply_patches(system_tools, patches, &dest_dir, &directories.recipe_dir)?;
+    }
+    Ok(())
+}
+
 impl Output {
     /// Fetches the sources for the given output and returns a new output with the finalized sources attached
     pub async fn fetch_sources(
@@ -284,8 +443,10 @@ impl Output {
             fetch_sources(
                 finalized_sources,
                 &self.build_configuration.directories,
+                self.build_configuration.host_platform,
                 &self.system_tools,
                 tool_configuration,
+                self.build_configuration.dev,
             )
             .await?;
 
@@ -294,8 +455,10 @@ impl Output {
             let rendered_sources = fetch_sources(
                 self.recipe.sources(),
                 &self.build_configuration.directories,
+                self.build_configuration.host_platform,
                 &self.system_tools,
                 tool_configuration,
+                self.build_configuration.dev,
             )
             .await?;
 
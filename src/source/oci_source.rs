@@ -0,0 +1,512 @@
+//! Pull the layers of an image or the blobs of an artifact from an OCI registry.
+//!
+//! Manifests and blobs are stored in the source cache by their digest, so a source that
+//! is pinned to a digest can be fetched again without network access.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rattler_conda_types::Platform;
+use rattler_digest::{compute_bytes_digest, Sha256, Sha256Hash};
+use rattler_networking::Authentication;
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::{
+    recipe::parser::{OciReference, OciSource},
+    source::{
+        checksum::Checksum,
+        extract::{extract_archive, extract_oci_layer, merge_layer, ArchiveFormat},
+    },
+    system_tools::SystemTools,
+    tool_configuration::{self, get_auth_store},
+};
+
+use super::SourceError;
+
+/// The manifest media types that are accepted from the registry.
+const MANIFEST_MEDIA_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// The annotation with the file name of an artifact blob.
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// A reference to a manifest or blob.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    platform: Option<DescriptorPlatform>,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+/// The platform of a manifest in an image index.
+#[derive(Debug, Deserialize)]
+struct DescriptorPlatform {
+    architecture: String,
+    os: String,
+}
+
+/// An image manifest (with `layers`) or an image index (with `manifests`).
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+/// The response of the token endpoint of a registry.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+fn oci_error(reference: &OciReference, msg: impl Into<String>) -> SourceError {
+    SourceError::OciError(reference.to_string(), msg.into())
+}
+
+/// Parse a `sha256:<hex>` digest.
+fn parse_digest(reference: &OciReference, digest: &str) -> Result<Sha256Hash, SourceError> {
+    digest
+        .strip_prefix("sha256:")
+        .and_then(rattler_digest::parse_digest_from_hex::<Sha256>)
+        .ok_or_else(|| oci_error(reference, format!("unsupported digest `{digest}`")))
+}
+
+/// The path of a manifest or blob in the cache.
+fn cache_path(cache_dir: &Path, digest: &Sha256Hash) -> PathBuf {
+    cache_dir.join(format!("sha256-{digest:x}"))
+}
+
+/// Find a manifest or blob with the given digest in the cache.
+fn find_in_cache(cache_dir: &Path, digest: &Sha256Hash) -> Option<PathBuf> {
+    let path = cache_path(cache_dir, digest);
+    (path.is_file() && Checksum::Sha256(*digest).validate(&path)).then_some(path)
+}
+
+/// The OCI `os` and `architecture` of a conda platform.
+fn oci_platform(platform: Platform) -> Option<(&'static str, &'static str)> {
+    Some(match platform {
+        Platform::Linux64 => ("linux", "amd64"),
+        Platform::LinuxAarch64 => ("linux", "arm64"),
+        Platform::LinuxPpc64le => ("linux", "ppc64le"),
+        Platform::LinuxS390X => ("linux", "s390x"),
+        Platform::Osx64 => ("darwin", "amd64"),
+        Platform::OsxArm64 => ("darwin", "arm64"),
+        Platform::Win64 => ("windows", "amd64"),
+        Platform::WinArm64 => ("windows", "arm64"),
+        _ => return None,
+    })
+}
+
+/// Parse the parameters of a `WWW-Authenticate: Bearer realm="...",service="..."`
+/// challenge.
+pub(crate) fn parse_challenge(challenge: &str) -> Option<BTreeMap<String, String>> {
+    let mut rest = challenge.strip_prefix("Bearer ")?.trim();
+    let mut params = BTreeMap::new();
+    while let Some((key, value)) = rest.split_once("=\"") {
+        let (value, tail) = value.split_once('"')?;
+        params.insert(key.trim().to_string(), value.to_string());
+        rest = tail.trim_start_matches(',').trim();
+    }
+    Some(params)
+}
+
+/// A client for the repository of a reference in a registry.
+struct Registry<'a> {
+    reference: &'a OciReference,
+    client: reqwest::Client,
+    credentials: Option<Authentication>,
+    token: Option<String>,
+}
+
+impl<'a> Registry<'a> {
+    fn new(
+        reference: &'a OciReference,
+        tool_configuration: &tool_configuration::Configuration,
+    ) -> Result<Self, SourceError> {
+        let client = tool_configuration
+            .network
+            .client()
+            .map_err(|e| SourceError::UnknownError(e.to_string()))?;
+
+        // credentials for the registry from the authentication storage, if any
+        let credentials = Url::parse(&format!("https://{}", reference.registry))
+            .ok()
+            .and_then(|url| {
+                get_auth_store(tool_configuration.network.auth_file.clone())
+                    .ok()?
+                    .get_by_url(url)
+                    .ok()?
+                    .1
+            });
+
+        Ok(Self {
+            reference,
+            client,
+            credentials,
+            token: None,
+        })
+    }
+
+    /// The url of the repository in the registry API.
+    fn repository_url(&self) -> String {
+        let host = match self.reference.registry.as_str() {
+            "docker.io" => "registry-1.docker.io",
+            host => host,
+        };
+        let scheme = if host.starts_with("localhost") || host.starts_with("127.0.0.1") {
+            "http"
+        } else {
+            "https"
+        };
+        format!("{scheme}://{host}/v2/{}", self.reference.repository)
+    }
+
+    /// Send a GET request to the repository. If the registry asks for a token, it is
+    /// requested (with the credentials of the registry, if any) and the request is retried.
+    async fn get(
+        &mut self,
+        path: &str,
+        accept: Option<&str>,
+    ) -> Result<reqwest::Response, SourceError> {
+        let url = format!("{}/{}", self.repository_url(), path);
+        let mut authenticated = false;
+        loop {
+            let mut request = self.client.get(&url);
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            request = match (&self.token, &self.credentials) {
+                (Some(token), _) | (None, Some(Authentication::BearerToken(token))) => {
+                    request.bearer_auth(token)
+                }
+                (None, Some(Authentication::BasicHTTP { username, password })) => {
+                    request.basic_auth(username, Some(password))
+                }
+                _ => request,
+            };
+
+            let response = request.send().await?;
+            if response.status() == StatusCode::UNAUTHORIZED && !authenticated {
+                let challenge = response
+                    .headers()
+                    .get(header::WWW_AUTHENTICATE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_challenge);
+                if let Some(challenge) = challenge {
+                    self.token = Some(self.request_token(&challenge).await?);
+                    authenticated = true;
+                    continue;
+                }
+            }
+
+            return response
+                .error_for_status()
+                .map_err(|e| oci_error(self.reference, e.to_string()));
+        }
+    }
+
+    /// Request a pull token from the token endpoint of the registry.
+    async fn request_token(
+        &self,
+        challenge: &BTreeMap<String, String>,
+    ) -> Result<String, SourceError> {
+        let realm = challenge
+            .get("realm")
+            .ok_or_else(|| oci_error(self.reference, "the registry did not send a token realm"))?;
+        let scope = challenge
+            .get("scope")
+            .cloned()
+            .unwrap_or_else(|| format!("repository:{}:pull", self.reference.repository));
+
+        let mut query = vec![("scope", scope)];
+        if let Some(service) = challenge.get("service") {
+            query.push(("service", service.clone()));
+        }
+        let mut request = self.client.get(realm).query(&query);
+        if let Some(Authentication::BasicHTTP { username, password }) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        let response = request
+            .send()
+            .await?
+            .error_for_status()
+            .map_err(|e| oci_error(self.reference, format!("failed to get a token: {e}")))?;
+        let token: TokenResponse = serde_json::from_slice(&response.bytes().await?)
+            .map_err(|e| oci_error(self.reference, format!("invalid token response: {e}")))?;
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| oci_error(self.reference, "the registry did not send a token"))
+    }
+
+    /// Get a manifest by tag or digest, and its digest. If the digest is known, the
+    /// manifest is taken from the cache if possible.
+    async fn manifest(
+        &mut self,
+        cache_dir: &Path,
+        reference: &str,
+        expected_digest: Option<Sha256Hash>,
+    ) -> Result<(Manifest, Sha256Hash), SourceError> {
+        let cached = expected_digest.and_then(|digest| find_in_cache(cache_dir, &digest));
+        let (bytes, digest) = match cached {
+            Some(path) => (fs::read(path)?, expected_digest.expect("cached by digest")),
+            None => {
+                let response = self
+                    .get(
+                        &format!("manifests/{reference}"),
+                        Some(MANIFEST_MEDIA_TYPES),
+                    )
+                    .await?;
+                let bytes = response.bytes().await?.to_vec();
+                let digest = compute_bytes_digest::<Sha256>(&bytes);
+                if expected_digest.is_some_and(|expected| expected != digest) {
+                    return Err(oci_error(
+                        self.reference,
+                        format!("the manifest does not match its digest (got sha256:{digest:x})"),
+                    ));
+                }
+                fs::write(cache_path(cache_dir, &digest), &bytes)?;
+                (bytes, digest)
+            }
+        };
+
+        let manifest = serde_json::from_slice(&bytes)
+            .map_err(|e| oci_error(self.reference, format!("invalid manifest: {e}")))?;
+        Ok((manifest, digest))
+    }
+
+    /// Download a blob into the cache and return its path.
+    async fn blob(
+        &mut self,
+        cache_dir: &Path,
+        descriptor: &Descriptor,
+        digest: &Sha256Hash,
+        tool_configuration: &tool_configuration::Configuration,
+    ) -> Result<PathBuf, SourceError> {
+        if let Some(cached) = find_in_cache(cache_dir, digest) {
+            return Ok(cached);
+        }
+
+        let mut response = self
+            .get(&format!("blobs/{}", descriptor.digest), None)
+            .await?;
+
+        let log_handler = &tool_configuration.fancy_log_handler;
+        let progress_bar = log_handler.add_progress_bar(
+            indicatif::ProgressBar::new(descriptor.size)
+                .with_prefix("Downloading")
+                .with_style(log_handler.default_bytes_style()),
+        );
+        progress_bar.set_message(descriptor.digest.clone());
+
+        // download to a temporary file so that an interrupted download is never used
+        let path = cache_path(cache_dir, digest);
+        let partial_path = path.with_extension("part");
+        let mut file = tokio::fs::File::create(&partial_path).await?;
+        while let Some(chunk) = response.chunk().await? {
+            progress_bar.inc(chunk.len() as u64);
+            file.write_all(&chunk).await?;
+        }
+        progress_bar.finish();
+        file.flush().await?;
+        drop(file);
+
+        if !Checksum::Sha256(*digest).validate(&partial_path) {
+            fs::remove_file(&partial_path)?;
+            return Err(SourceError::ValidationFailed);
+        }
+        fs::rename(&partial_path, &path)?;
+        Ok(path)
+    }
+}
+
+/// Select the manifest for the platform from an image index.
+fn select_manifest<'m>(
+    reference: &OciReference,
+    manifests: &'m [Descriptor],
+    platform: Platform,
+) -> Result<&'m Descriptor, SourceError> {
+    let wanted = oci_platform(platform);
+    manifests
+        .iter()
+        .find(|manifest| match (&manifest.platform, wanted) {
+            (Some(p), Some((os, arch))) => p.os == os && p.architecture == arch,
+            // a single manifest without a platform is platform independent
+            (None, _) => manifests.len() == 1,
+            (Some(_), None) => false,
+        })
+        .ok_or_else(|| {
+            let available = manifests
+                .iter()
+                .filter_map(|manifest| manifest.platform.as_ref())
+                .map(|p| format!("{}/{}", p.os, p.architecture))
+                .collect::<Vec<_>>()
+                .join(", ");
+            oci_error(
+                reference,
+                format!("no manifest for {platform} in the image index (available: {available})"),
+            )
+        })
+}
+
+/// Pull an OCI source into the destination directory. The layers of an image are
+/// extracted on top of each other; the blobs of an artifact are placed under their file
+/// name (archives are extracted). Returns the source pinned to the digest of its manifest.
+pub(crate) async fn oci_src(
+    source: &OciSource,
+    cache_dir: &Path,
+    dest_dir: &Path,
+    platform: Platform,
+    system_tools: &SystemTools,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<OciSource, SourceError> {
+    let reference = source.reference();
+    let cache_dir = cache_dir.join("oci");
+    fs::create_dir_all(&cache_dir)?;
+
+    let mut registry = Registry::new(reference, tool_configuration)?;
+    let (mut manifest, digest) = registry
+        .manifest(
+            &cache_dir,
+            &reference.manifest_reference(),
+            reference.digest,
+        )
+        .await?;
+
+    if !manifest.manifests.is_empty() {
+        let descriptor = select_manifest(reference, &manifest.manifests, platform)?;
+        let manifest_digest = parse_digest(reference, &descriptor.digest)?;
+        manifest = registry
+            .manifest(&cache_dir, &descriptor.digest, Some(manifest_digest))
+            .await?
+            .0;
+    }
+
+    // the layers are stacked in a separate directory, so that their whiteout files only
+    // remove the files of previous layers and not those of other sources
+    let image_dir = tempfile::tempdir_in(dest_dir.parent().unwrap_or(dest_dir))?;
+    for layer in &manifest.layers {
+        let layer_digest = parse_digest(reference, &layer.digest)?;
+        let blob = registry
+            .blob(&cache_dir, layer, &layer_digest, tool_configuration)
+            .await?;
+
+        let file_name = layer
+            .annotations
+            .get(TITLE_ANNOTATION)
+            .and_then(|title| Path::new(title).file_name());
+        match file_name {
+            Some(file_name) => {
+                if let Some(format) = ArchiveFormat::from_path(Path::new(file_name)) {
+                    // the archive format is detected from the file name
+                    let tmp_dir = tempfile::tempdir_in(&cache_dir)?;
+                    let archive = tmp_dir.path().join(file_name);
+                    fs::copy(&blob, &archive)?;
+                    extract_archive(
+                        format,
+                        &archive,
+                        dest_dir,
+                        system_tools,
+                        &tool_configuration.fancy_log_handler,
+                        tool_configuration.extraction_policy,
+                    )?;
+                } else {
+                    fs::copy(&blob, dest_dir.join(file_name))?;
+                }
+            }
+            None if layer.media_type.contains("tar") => extract_oci_layer(
+                &blob,
+                &layer.media_type,
+                image_dir.path(),
+                &tool_configuration.fancy_log_handler,
+                tool_configuration.extraction_policy,
+            )?,
+            None => {
+                tracing::warn!(
+                    "Copying blob {} with unknown media type `{}`",
+                    layer.digest,
+                    layer.media_type
+                );
+                fs::copy(&blob, dest_dir.join(format!("sha256-{layer_digest:x}")))?;
+            }
+        }
+    }
+
+    merge_layer(image_dir.path(), dest_dir)?;
+
+    tracing::info!("Pulled {} at sha256:{:x}", reference, digest);
+    Ok(OciSource {
+        reference: reference.with_digest(digest),
+        ..source.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_challenge() {
+        let params = parse_challenge(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:org/sdk:pull,push""#,
+        )
+        .unwrap();
+        assert_eq!(params["realm"], "https://ghcr.io/token");
+        assert_eq!(params["service"], "ghcr.io");
+        assert_eq!(params["scope"], "repository:org/sdk:pull,push");
+
+        assert!(parse_challenge(r#"Basic realm="registry""#).is_none());
+    }
+
+    #[test]
+    fn test_select_manifest() {
+        let index: Manifest = serde_json::from_str(
+            r#"{
+                "schemaVersion": 2,
+                "manifests": [
+                    {
+                        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                        "digest": "sha256:1111111111111111111111111111111111111111111111111111111111111111",
+                        "size": 100,
+                        "platform": { "architecture": "amd64", "os": "linux" }
+                    },
+                    {
+                        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                        "digest": "sha256:2222222222222222222222222222222222222222222222222222222222222222",
+                        "size": 100,
+                        "platform": { "architecture": "arm64", "os": "linux" }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let reference: OciReference = "ghcr.io/org/sdk:1.0".parse().unwrap();
+
+        let selected =
+            select_manifest(&reference, &index.manifests, Platform::LinuxAarch64).unwrap();
+        assert!(selected.digest.ends_with("2222"));
+        assert!(select_manifest(&reference, &index.manifests, Platform::Win64).is_err());
+
+        // a single manifest is only selected for its own platform
+        let single = &index.manifests[..1];
+        assert!(select_manifest(&reference, single, Platform::Linux64).is_ok());
+        assert!(select_manifest(&reference, single, Platform::LinuxAarch64).is_err());
+    }
+}
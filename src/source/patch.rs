@@ -7,7 +7,10 @@ use std::{
 use patch::Patch;
 
 use super::SourceError;
-use crate::system_tools::{SystemTools, Tool};
+use crate::{
+    recipe::parser::SourcePatch,
+    system_tools::{SystemTools, Tool},
+};
 
 /// We try to guess the "strip level" for a patch application. This is done by checking
 /// what files are present in the work directory and comparing them to the paths in the patch.
@@ -44,41 +47,171 @@ fn guess_strip_level(patch: &Path, work_dir: &Path) -> Result<usize, std::io::Er
     Ok(1)
 }
 
-/// Applies all patches in a list of patches to the specified work directory
-/// Currently only supports patching with the `patch` command.
-pub(crate) fn apply_patches(
+/// Apply a patch with the `patch` command. The patch is first applied with `--dry-run`,
+/// so that a patch that does not apply cleanly leaves the work directory untouched.
+fn apply_with_patch(
     system_tools: &SystemTools,
-    patches: &[PathBuf],
+    patch: &SourcePatch,
+    patch_file: &Path,
+    strip_level: usize,
     work_dir: &Path,
-    recipe_dir: &Path,
-) -> Result<(), SourceError> {
-    for patch in patches {
-        let patch = recipe_dir.join(patch);
-
-        if !patch.exists() {
-            return Err(SourceError::PatchNotFound(patch));
-        }
-
-        let strip_level = guess_strip_level(&patch, work_dir)?;
-
-        let output = system_tools
+) -> Result<(), String> {
+    let run = |dry_run: bool| -> Result<(), String> {
+        let mut command = system_tools
             .call(Tool::Patch)
-            .map_err(|_| SourceError::PatchExeNotFound)?
+            .map_err(|_| "could not find the `patch` executable".to_string())?;
+        command
             .arg(format!("-p{}", strip_level))
             .arg("-i")
-            .arg(String::from(patch.to_string_lossy()))
+            .arg(patch_file)
             .arg("-d")
-            .arg(String::from(work_dir.to_string_lossy()))
-            .output()?;
-
-        if !output.status.success() {
-            tracing::error!("Failed to apply patch: {}", patch.to_string_lossy());
-            tracing::error!("Stdout: {}", String::from_utf8_lossy(&output.stdout));
-            tracing::error!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
-            return Err(SourceError::PatchFailed(
-                patch.to_string_lossy().to_string(),
-            ));
+            .arg(work_dir);
+        if let Some(fuzz) = patch.fuzz() {
+            command.arg("-F").arg(fuzz.to_string());
+        }
+        if dry_run {
+            command.arg("--dry-run");
         }
+        let output = command.output().map_err(|err| err.to_string())?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ))
+        }
+    };
+
+    run(true)?;
+    run(false)
+}
+
+/// Apply a patch with `git apply`, which also handles renames and binary diffs. If the
+/// work directory is a git repository (e.g. a git source), a three-way merge is used.
+fn apply_with_git(
+    system_tools: &SystemTools,
+    patch_file: &Path,
+    strip_level: usize,
+    work_dir: &Path,
+) -> Result<(), String> {
+    let mut command = system_tools
+        .call(Tool::Git)
+        .map_err(|_| "could not find the `git` executable".to_string())?;
+    command
+        .arg("apply")
+        .arg(format!("-p{}", strip_level))
+        .current_dir(work_dir);
+    // do not pick up a repository that contains the output directory
+    if let Some(parent) = work_dir.parent() {
+        command.env("GIT_CEILING_DIRECTORIES", parent);
+    }
+    if work_dir.join(".git").exists() {
+        command.arg("--3way");
+    }
+    let output = command
+        .arg(patch_file)
+        .output()
+        .map_err(|err| err.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).to_string())
+    }
+}
+
+/// Apply a single patch to the work directory, with the `patch` command, and with
+/// `git apply` if that fails. A patch that does not apply leaves the work directory
+/// untouched.
+pub(crate) fn apply_patch(
+    system_tools: &SystemTools,
+    patch: &SourcePatch,
+    work_dir: &Path,
+    recipe_dir: &Path,
+) -> Result<(), SourceError> {
+    let patch_file = recipe_dir.join(patch.path());
+
+    if !patch_file.exists() {
+        return Err(SourceError::PatchNotFound(patch_file));
+    }
+
+    let strip_level = match patch.strip() {
+        Some(strip_level) => strip_level,
+        None => guess_strip_level(&patch_file, work_dir)?,
+    };
+
+    let patch_error =
+        match apply_with_patch(system_tools, patch, &patch_file, strip_level, work_dir) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+    tracing::warn!(
+        "`patch` could not apply {}, trying `git apply`",
+        patch.describe()
+    );
+
+    if let Err(git_error) = apply_with_git(system_tools, &patch_file, strip_level, work_dir) {
+        return Err(SourceError::PatchFailed(
+            patch.describe(),
+            format!(
+                "with -p{strip_level}\n`patch`: {}\n`git apply`: {}",
+                patch_error.trim(),
+                git_error.trim()
+            ),
+        ));
+    }
+    tracing::info!("Applied {} with `git apply`", patch.describe());
+    Ok(())
+}
+
+/// Apply the hunks of a patch that apply with the `patch` command and write the rejected
+/// hunks to `.rej` files next to the patched files (to fix a patch that no longer applies
+/// by hand). Returns the output of `patch`.
+pub(crate) fn apply_patch_with_rejects(
+    system_tools: &SystemTools,
+    patch: &SourcePatch,
+    work_dir: &Path,
+    recipe_dir: &Path,
+) -> Result<String, SourceError> {
+    let patch_file = recipe_dir.join(patch.path());
+    let strip_level = match patch.strip() {
+        Some(strip_level) => strip_level,
+        None => guess_strip_level(&patch_file, work_dir)?,
+    };
+
+    let mut command = system_tools
+        .call(Tool::Patch)
+        .map_err(|_| SourceError::PatchExeNotFound)?;
+    command
+        .arg(format!("-p{}", strip_level))
+        .arg("--forward")
+        .arg("-i")
+        .arg(&patch_file)
+        .arg("-d")
+        .arg(work_dir);
+    if let Some(fuzz) = patch.fuzz() {
+        command.arg("-F").arg(fuzz.to_string());
+    }
+    // `patch` exits with 1 if some hunks were rejected
+    let output = command.output()?;
+    Ok(format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    ))
+}
+
+/// Applies all patches in a list of patches to the specified work directory. Patches are
+/// applied with the `patch` command, and with `git apply` if that fails.
+pub(crate) fn apply_patches(
+    system_tools: &SystemTools,
+    patches: &[SourcePatch],
+    work_dir: &Path,
+    recipe_dir: &Path,
+) -> Result<(), SourceError> {
+    for patch in patches {
+        apply_patch(system_tools, patch, work_dir, recipe_dir)?;
     }
     Ok(())
 }
@@ -43,6 +43,26 @@ fn cache_name_from_url(url: &url::Url, checksum: &Checksum) -> Option<String> {
     Some(format!("{}_{}{}", stem, &checksum[0..8], extension))
 }
 
+/// The directory of the content-addressed store that holds the downloads with the
+/// given checksum. Downloads are reused for every url with the same checksum.
+fn content_dir(cache_dir: &Path, checksum: &Checksum) -> PathBuf {
+    let algorithm = match checksum {
+        Checksum::Sha256(_) => "sha256",
+        Checksum::Md5(_) => "md5",
+    };
+    cache_dir.join(format!("{}-{}", algorithm, checksum.to_hex()))
+}
+
+/// Find a previous download with the given checksum in the source cache.
+fn find_in_cache(content_dir: &Path, checksum: &Checksum) -> Option<PathBuf> {
+    fs::read_dir(content_dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().map_or(true, |ext| ext != "part"))
+        .find(|path| checksum.validate(path))
+}
+
 pub(crate) async fn url_src(
     source: &UrlSource,
     cache_dir: &Path,
@@ -54,6 +74,7 @@ pub(crate) async fn url_src(
     })?;
 
     let mut last_error = None;
+    let mut refused_offline = None;
     for url in source.urls() {
         if url.scheme() == "file" {
             let local_path = url.to_file_path().map_err(|_| {
@@ -75,18 +96,33 @@ pub(crate) async fn url_src(
             return Ok(local_path);
         }
 
-        let cache_name = PathBuf::from(cache_name_from_url(url, &checksum).ok_or(
-            SourceError::UnknownErrorStr("Failed to build cache name from url"),
-        )?);
-        let cache_name = cache_dir.join(cache_name);
-
-        let metadata = fs::metadata(&cache_name);
-        if metadata.is_ok() && metadata?.is_file() && checksum.validate(&cache_name) {
+        let content_dir = content_dir(cache_dir, &checksum);
+        if let Some(cached) = find_in_cache(&content_dir, &checksum) {
             tracing::info!("Found valid source cache file.");
-            return Ok(cache_name.clone());
+            return Ok(cached);
         }
+        let url = &tool_configuration.network.mirror_url(url);
+        if tool_configuration
+            .network
+            .refuse_offline(format!("GET {url}"))
+        {
+            refused_offline = Some(url.clone());
+            continue;
+        }
+        fs::create_dir_all(&content_dir)?;
+        let file_name = url
+            .path_segments()
+            .and_then(|segments| segments.filter(|s| !s.is_empty()).last())
+            .ok_or(SourceError::UnknownErrorStr(
+                "Failed to build cache name from url",
+            ))?;
+        // download to a temporary file so that an interrupted download is never used
+        let partial_name = content_dir.join(format!("{}.part", file_name));
 
-        let client = reqwest::Client::new();
+        let client = tool_configuration
+            .network
+            .client()
+            .map_err(|e| SourceError::UnknownError(e.to_string()))?;
         let download_size = {
             let resp = client.head(url.as_str()).send().await?;
             if resp.status().is_success() {
@@ -111,13 +147,8 @@ pub(crate) async fn url_src(
                 .with_prefix("Downloading")
                 .with_style(tool_configuration.fancy_log_handler.default_bytes_style()),
         );
-        progress_bar.set_message(
-            url.path_segments()
-                .and_then(|segs| segs.last())
-                .map(str::to_string)
-                .unwrap_or_else(|| "Unknown File".to_string()),
-        );
-        let mut file = tokio::fs::File::create(&cache_name).await?;
+        progress_bar.set_message(file_name.to_string());
+        let mut file = tokio::fs::File::create(&partial_name).await?;
 
         let request = client.get(url.clone());
         let mut download = request.send().await?;
@@ -130,17 +161,24 @@ pub(crate) async fn url_src(
         progress_bar.finish();
 
         file.flush().await?;
+        drop(file);
 
-        if !checksum.validate(&cache_name) {
+        if !checksum.validate(&partial_name) {
             tracing::error!("Checksum validation failed!");
-            fs::remove_file(&cache_name)?;
+            fs::remove_file(&partial_name)?;
             return Err(SourceError::ValidationFailed);
         }
 
+        let cache_name = content_dir.join(cache_name_from_url(url, &checksum).ok_or(
+            SourceError::UnknownErrorStr("Failed to build cache name from url"),
+        )?);
+        fs::rename(&partial_name, &cache_name)?;
         return Ok(cache_name);
     }
 
-    if let Some(Err(last_error)) = last_error {
+    if let Some(url) = refused_offline {
+        Err(SourceError::Offline(url.to_string()))
+    } else if let Some(Err(last_error)) = last_error {
         Err(SourceError::Url(last_error))
     } else {
         Err(SourceError::UnknownError(
@@ -211,4 +249,30 @@ mod tests {
             assert_eq!(name, expected);
         }
     }
+
+    #[test]
+    fn test_find_in_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let content = b"some source archive";
+        let checksum = Checksum::Sha256(rattler_digest::compute_bytes_digest::<Sha256>(content));
+
+        let content_dir = content_dir(cache_dir.path(), &checksum);
+        assert_eq!(
+            content_dir,
+            cache_dir
+                .path()
+                .join(format!("sha256-{}", checksum.to_hex()))
+        );
+        assert!(find_in_cache(&content_dir, &checksum).is_none());
+
+        // interrupted downloads are not used
+        fs::create_dir_all(&content_dir).unwrap();
+        fs::write(content_dir.join("example_12345678.tar.gz.part"), content).unwrap();
+        assert!(find_in_cache(&content_dir, &checksum).is_none());
+
+        // a download from another url with the same checksum is reused
+        let cached = content_dir.join("mirror_12345678.tar.gz");
+        fs::write(&cached, content).unwrap();
+        assert_eq!(find_in_cache(&content_dir, &checksum), Some(cached));
+    }
 }
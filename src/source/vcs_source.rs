@@ -0,0 +1,135 @@
+//! This module contains the fetching of `HgSource` (Mercurial) and `SvnSource`
+//! (Subversion) sources with the host `hg` and `svn` executables.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use rattler_digest::compute_bytes_digest;
+use sha2::Sha256;
+
+use crate::{
+    recipe::parser::{HgSource, SvnSource},
+    system_tools::{SystemTools, Tool},
+};
+
+use super::SourceError;
+
+/// The name of the checkout of a repository in the source cache: the last segment of
+/// the url and a short hash of the full url.
+fn checkout_name(url: &str) -> String {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', '\\', ':'])
+        .find(|segment| !segment.is_empty())
+        .unwrap_or("repository");
+    let hash = format!("{:x}", compute_bytes_digest::<Sha256>(url.as_bytes()));
+    format!("{}_{}", name, &hash[..8])
+}
+
+/// Run a command and return its (trimmed) standard output.
+fn run(mut command: Command, error: fn(String) -> SourceError) -> Result<String, SourceError> {
+    tracing::debug!("Running {:?}", command);
+    let output = command
+        .output()
+        .map_err(|e| error(format!("failed to execute {:?}: {}", command, e)))?;
+    if !output.status.success() {
+        return Err(error(format!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Clone (or update) the Mercurial repository of the source in the cache directory and
+/// update it to the requested revision. Returns the path of the checkout and the id of
+/// the checked out changeset.
+pub fn hg_src(
+    system_tools: &SystemTools,
+    source: &HgSource,
+    cache_dir: &Path,
+) -> Result<(PathBuf, String), SourceError> {
+    let cache_path = cache_dir.join("hg").join(checkout_name(source.url()));
+
+    if cache_path.exists() {
+        let mut command = system_tools.call(Tool::Mercurial)?;
+        command
+            .args(["pull", source.url()])
+            .current_dir(&cache_path);
+        run(command, SourceError::HgError)?;
+    } else {
+        fs_err::create_dir_all(cache_dir.join("hg"))?;
+        let mut command = system_tools.call(Tool::Mercurial)?;
+        command
+            .args(["clone", "--noupdate", source.url()])
+            .arg(&cache_path);
+        run(command, SourceError::HgError)?;
+    }
+
+    let mut command = system_tools.call(Tool::Mercurial)?;
+    command
+        .args(["update", "--clean", "--rev", source.rev()])
+        .current_dir(&cache_path);
+    run(command, SourceError::HgError)?;
+
+    let mut command = system_tools.call(Tool::Mercurial)?;
+    command
+        .args(["log", "--rev", ".", "--template", "{node}"])
+        .current_dir(&cache_path);
+    let node = run(command, SourceError::HgError)?;
+
+    tracing::info!("Checked out revision: '{}' at '{}'", source.rev(), node);
+    Ok((cache_path, node))
+}
+
+/// Check out (or update) the Subversion repository of the source in the cache directory
+/// at the requested revision. Returns the path of the checkout and the revision number.
+pub fn svn_src(
+    system_tools: &SystemTools,
+    source: &SvnSource,
+    cache_dir: &Path,
+) -> Result<(PathBuf, String), SourceError> {
+    let cache_path = cache_dir.join("svn").join(checkout_name(source.url()));
+
+    let mut command = system_tools.call(Tool::Subversion)?;
+    if cache_path.exists() {
+        command
+            .args(["update", "--non-interactive", "--revision", source.rev()])
+            .current_dir(&cache_path);
+    } else {
+        fs_err::create_dir_all(cache_dir.join("svn"))?;
+        command
+            .args(["checkout", "--non-interactive", "--revision", source.rev()])
+            .arg(source.url())
+            .arg(&cache_path);
+    }
+    if source.ignore_externals() {
+        command.arg("--ignore-externals");
+    }
+    run(command, SourceError::SvnError)?;
+
+    let mut command = system_tools.call(Tool::Subversion)?;
+    command
+        .args(["info", "--show-item", "revision"])
+        .current_dir(&cache_path);
+    let revision = run(command, SourceError::SvnError)?;
+
+    tracing::info!("Checked out revision: '{}' at '{}'", source.rev(), revision);
+    Ok((cache_path, revision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::checkout_name;
+
+    #[test]
+    fn test_checkout_name() {
+        let name = checkout_name("https://hg.example.org/project/");
+        assert!(name.starts_with("project_"));
+        assert_eq!(name.len(), "project_".len() + 8);
+        assert_ne!(name, checkout_name("https://hg.example.org/other/project"));
+    }
+}
@@ -36,6 +36,14 @@ pub enum Tool {
     InstallNameTool,
     /// The git tool
     Git,
+    /// The 7-Zip tool (to extract `.7z` archives)
+    SevenZip,
+    /// The lz4 tool (to extract `.lz4` compressed files)
+    Lz4,
+    /// The Mercurial tool (for `hg` sources)
+    Mercurial,
+    /// The Subversion tool (for `svn` sources)
+    Subversion,
 }
 
 impl std::fmt::Display for Tool {
@@ -50,6 +58,10 @@ impl std::fmt::Display for Tool {
                 Tool::Patchelf => "patchelf".to_string(),
                 Tool::InstallNameTool => "install_name_tool".to_string(),
                 Tool::Git => "git".to_string(),
+                Tool::SevenZip => "7z".to_string(),
+                Tool::Lz4 => "lz4".to_string(),
+                Tool::Mercurial => "hg".to_string(),
+                Tool::Subversion => "svn".to_string(),
             }
         )
     }
@@ -170,6 +182,40 @@ impl SystemTools {
                 let version = String::from_utf8_lossy(&version.stdout);
                 (path, version.to_string())
             }
+            Tool::SevenZip => {
+                // different distributions ship 7-Zip under different names
+                let path = which("7z")
+                    .or_else(|_| which("7zz"))
+                    .or_else(|_| which("7za"))?;
+                (path, "".to_string())
+            }
+            Tool::Lz4 => {
+                let path = which("lz4")?;
+                let version = std::process::Command::new(&path)
+                    .arg("--version")
+                    .output()
+                    .expect("Failed to execute `lz4` command");
+                let version = String::from_utf8_lossy(&version.stdout);
+                (path, version.to_string())
+            }
+            Tool::Mercurial => {
+                let path = which("hg")?;
+                let version = std::process::Command::new(&path)
+                    .args(["--version", "--quiet"])
+                    .output()
+                    .expect("Failed to execute `hg` command");
+                let version = String::from_utf8_lossy(&version.stdout);
+                (path, version.to_string())
+            }
+            Tool::Subversion => {
+                let path = which("svn")?;
+                let version = std::process::Command::new(&path)
+                    .args(["--version", "--quiet"])
+                    .output()
+                    .expect("Failed to execute `svn` command");
+                let version = String::from_utf8_lossy(&version.stdout);
+                (path, version.to_string())
+            }
             Tool::RattlerBuild => {
                 let path = std::env::current_exe().expect("Failed to get current executable path");
                 (path, env!("CARGO_PKG_VERSION").to_string())
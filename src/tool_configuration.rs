@@ -1,16 +1,33 @@
 //! Configuration for the rattler-build tool
 //! This is useful when using rattler-build as a library
 
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use crate::console_utils::LoggingOutputHandler;
+use crate::{
+    cache_lock::CacheLock,
+    console_utils::LoggingOutputHandler,
+    emulation,
+    metadata::SkippedOutput,
+    mirror::{self, Mirror, MirrorMiddleware, OfflineRequests},
+    recipe::parser::ScriptPhase,
+    script::LogSink,
+    signing::SigningConfig,
+    source::extract::ExtractionPolicy,
+};
 use clap::ValueEnum;
-use rattler_conda_types::ChannelConfig;
+use rattler_conda_types::{ChannelConfig, GenericVirtualPackage, Platform};
 use rattler_networking::{
     authentication_storage::{self, backends::file::FileStorageError},
     AuthenticationMiddleware, AuthenticationStorage,
 };
+use rattler_repodata_gateway::{fetch::CacheAction, Gateway, SourceConfig};
 use reqwest_middleware::ClientWithMiddleware;
+use url::Url;
 
 /// The user agent to use for the reqwest client
 pub const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
@@ -26,8 +43,56 @@ pub enum SkipExisting {
     All,
 }
 
+/// What happens with the tests of a package for a platform that the machine cannot run
+/// natively (e.g. `linux-aarch64` on `linux-64`). Packages that the machine can run are
+/// always tested, and `noarch` packages are tested on the platform of the machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TestPolicy {
+    /// Skip the tests (the reason is recorded in the build summary)
+    #[default]
+    NativeOnly,
+    /// Run the tests with QEMU emulation, skip them if the platform cannot be emulated
+    Emulate,
+    /// Fail the build if the tests cannot run natively
+    Required,
+}
+
+impl TestPolicy {
+    /// Check whether the tests of packages for the platform can run on the machine. Returns
+    /// the reason why the tests are skipped, or an error if the policy requires tests that
+    /// cannot run.
+    pub fn skipped_reason(
+        &self,
+        machine: Platform,
+        platform: Platform,
+    ) -> miette::Result<Option<String>> {
+        if emulation::can_run_natively(machine, platform) {
+            return Ok(None);
+        }
+
+        match self {
+            TestPolicy::NativeOnly => Ok(Some(format!(
+                "{} packages cannot run on this {} machine (run them with `--test emulate`)",
+                platform, machine
+            ))),
+            TestPolicy::Emulate => match emulation::setup(machine, platform) {
+                Ok(_) => Ok(None),
+                Err(err) => Ok(Some(format!(
+                    "{} packages cannot be emulated on this {} machine: {}",
+                    platform, machine, err
+                ))),
+            },
+            TestPolicy::Required => Err(miette::miette!(
+                "The tests of {} cannot run on this {} machine, but `--test required` was given",
+                platform,
+                machine
+            )),
+        }
+    }
+}
+
 /// Global configuration for the build
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Configuration {
     /// If set to a value, a progress bar will be shown
     pub fancy_log_handler: LoggingOutputHandler,
@@ -41,6 +106,13 @@ pub struct Configuration {
     /// Whether to skip the test phase
     pub no_test: bool,
 
+    /// What happens with the tests of packages that the machine cannot run natively
+    pub test_policy: TestPolicy,
+
+    /// An additional sink for the output of the build and test scripts, e.g. to
+    /// capture the output when using rattler-build as a library
+    pub log_sink: Option<Arc<dyn LogSink>>,
+
     /// Whether to use zstd
     pub use_zstd: bool,
 
@@ -53,8 +125,149 @@ pub struct Configuration {
     /// Wether to skip existing packages
     pub skip_existing: SkipExisting,
 
+    /// Whether to start an interactive shell in the build environment when the
+    /// build script fails
+    pub debug_shell: bool,
+
+    /// How strictly the members of source archives are checked before extraction
+    pub extraction_policy: ExtractionPolicy,
+
+    /// Whether to reuse the build and host environments of a previous build if the
+    /// requirements did not change
+    pub incremental: bool,
+
+    /// The phase of the build script to start at (in an incremental build). The work
+    /// directory of the previous build is reused and earlier phases are skipped.
+    pub from_phase: Option<ScriptPhase>,
+
+    /// The number of outputs that are built in parallel
+    pub jobs: NonZeroUsize,
+
+    /// A command that is run for every created package (with the path of the package
+    /// as its last argument). The build fails if the command does not exit successfully.
+    pub scan_cmd: Option<String>,
+
+    /// The file that the resource usage of the builds and tests is written to, in the
+    /// format of the conda-build stats file
+    pub stats_file: Option<PathBuf>,
+
+    /// How the created packages are signed, if they are signed
+    pub signing: Option<SigningConfig>,
+
     /// The channel configuration to use when parsing channels.
     pub channel_config: ChannelConfig,
+
+    /// The gateway used to fetch repodata. The gateway caches repodata in memory,
+    /// clones of the configuration share that cache.
+    pub repodata_gateway: Gateway,
+
+    /// The outputs that were skipped while rendering the recipes, together with the
+    /// condition that caused them to be skipped
+    pub skipped_outputs: Arc<Mutex<Vec<SkippedOutput>>>,
+
+    /// The proxy and CA certificates for outbound requests. These are applied to the
+    /// `client` and to the `git` commands that fetch sources.
+    pub network: NetworkSettings,
+
+    /// The directory of the source cache (`--source-cache-dir`). Defaults to `src_cache`
+    /// in the output directory.
+    pub source_cache: Option<PathBuf>,
+
+    /// A cache directory that is private to this invocation (with `--no-shared-cache`).
+    /// If unset, the package cache is shared with other processes and the source cache
+    /// is shared by all builds in the same output directory.
+    pub private_cache_dir: Option<Arc<tempfile::TempDir>>,
+
+    /// The directory of the package and repodata caches (`--cache-dir`). Defaults to the
+    /// cache directory of rattler, which is shared with pixi.
+    pub cache_dir: Option<PathBuf>,
+
+    /// Virtual packages that are assumed to exist in addition to (or instead of) the
+    /// detected ones, e.g. `__cuda` to test a CUDA variant on a machine without a GPU
+    pub mock_virtual_packages: Vec<GenericVirtualPackage>,
+
+    /// The environment variables that the recipe and the build scripts see instead of the
+    /// ones of the process, e.g. the environment of a client of the build daemon
+    pub environment: Option<Arc<HashMap<String, String>>>,
+}
+
+impl Configuration {
+    /// Whether the package and source caches are shared with other processes, and thus
+    /// have to be locked while they are modified.
+    pub fn shared_cache(&self) -> bool {
+        self.private_cache_dir.is_none()
+    }
+
+    /// The directory of the package cache.
+    pub fn package_cache_dir(&self) -> PathBuf {
+        match &self.private_cache_dir {
+            Some(dir) => dir.path().join("pkgs"),
+            None => rattler_cache_dir(self.cache_dir.as_deref()).join("pkgs"),
+        }
+    }
+
+    /// The directory of the repodata cache.
+    pub fn repodata_cache_dir(&self) -> PathBuf {
+        rattler_cache_dir(self.cache_dir.as_deref()).join("repodata")
+    }
+
+    /// The directory of the source cache for builds in the given output directory.
+    pub fn source_cache_dir(&self, output_dir: &Path) -> PathBuf {
+        match (&self.private_cache_dir, &self.source_cache) {
+            (Some(dir), _) => dir.path().join("src_cache"),
+            (None, Some(dir)) => dir.clone(),
+            (None, None) => output_dir.join("src_cache"),
+        }
+    }
+
+    /// Lock a cache directory against concurrent modification by other processes.
+    /// Returns `None` if the caches are private to this invocation.
+    pub async fn lock_cache(
+        &self,
+        cache_dir: &Path,
+        what: &str,
+    ) -> std::io::Result<Option<CacheLock>> {
+        if !self.shared_cache() {
+            return Ok(None);
+        }
+        CacheLock::acquire_async(&cache_dir.join(CACHE_LOCK_FILE), what)
+            .await
+            .map(Some)
+    }
+}
+
+/// The name of the lock file in a shared cache directory.
+pub const CACHE_LOCK_FILE: &str = ".rattler-build.lock";
+
+impl std::fmt::Debug for Configuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Configuration")
+            .field("fancy_log_handler", &self.fancy_log_handler)
+            .field("client", &self.client)
+            .field("no_clean", &self.no_clean)
+            .field("no_test", &self.no_test)
+            .field("test_policy", &self.test_policy)
+            .field("log_sink", &self.log_sink.is_some())
+            .field("use_zstd", &self.use_zstd)
+            .field("use_bz2", &self.use_bz2)
+            .field("render_only", &self.render_only)
+            .field("skip_existing", &self.skip_existing)
+            .field("debug_shell", &self.debug_shell)
+            .field("extraction_policy", &self.extraction_policy)
+            .field("incremental", &self.incremental)
+            .field("from_phase", &self.from_phase)
+            .field("jobs", &self.jobs)
+            .field("scan_cmd", &self.scan_cmd)
+            .field("stats_file", &self.stats_file)
+            .field("signing", &self.signing)
+            .field("channel_config", &self.channel_config)
+            .field("skipped_outputs", &self.skipped_outputs)
+            .field("network", &self.network)
+            .field("source_cache", &self.source_cache)
+            .field("private_cache_dir", &self.private_cache_dir)
+            .field("cache_dir", &self.cache_dir)
+            .finish_non_exhaustive()
+    }
 }
 
 /// Get the authentication storage from the given file
@@ -73,40 +286,272 @@ pub fn get_auth_store(
     }
 }
 
+/// Settings for the outbound network requests (repodata, packages and sources).
+#[derive(Debug, Clone, Default)]
+pub struct NetworkSettings {
+    /// The proxy for all requests. If unset, the `HTTPS_PROXY` / `HTTP_PROXY` / `ALL_PROXY`
+    /// environment variables are used.
+    pub proxy: Option<Url>,
+    /// A PEM file with additional CA certificates to trust, e.g. the certificate of a
+    /// TLS-intercepting proxy
+    pub ca_cert: Option<PathBuf>,
+    /// The authentication file (`--auth-file`). The credentials are also passed to `git`
+    /// for sources from private repositories.
+    pub auth_file: Option<PathBuf>,
+    /// Never prompt for input (`--non-interactive`). Tools like `git` fail instead of
+    /// asking for credentials.
+    pub non_interactive: bool,
+    /// Url prefixes (e.g. of channels) that are replaced by the urls of mirrors
+    /// (`--channel-mirror`)
+    pub mirrors: Vec<Mirror>,
+    /// Never access the network (`--offline`). Repodata, packages and sources are only
+    /// taken from the caches.
+    pub offline: bool,
+    /// The requests that were refused in offline mode
+    pub offline_requests: OfflineRequests,
+}
+
+/// An input that rattler-build would have to ask for, but that was not given. In
+/// non-interactive mode, this is reported instead of prompting for the input.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{input} is required: {reason}")]
+#[diagnostic(code(rattler_build::input_required), help("{help}"))]
+pub struct InputRequired {
+    /// The input that is required, e.g. `credentials for https://prefix.dev/`
+    pub input: String,
+    /// Why the input is required
+    pub reason: String,
+    /// How the input can be given without a prompt
+    pub help: String,
+}
+
+/// An error that occurs while creating the download client
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    AuthStorage(#[from] FileStorageError),
+
+    #[error("Failed to read the CA certificates from {0}")]
+    ReadCaCert(PathBuf, #[source] std::io::Error),
+
+    #[error("Invalid CA certificates in {0}")]
+    InvalidCaCert(PathBuf, #[source] reqwest::Error),
+
+    #[error("Invalid proxy url: {0}")]
+    InvalidProxy(Url, #[source] reqwest::Error),
+
+    #[error("Failed to create the download client")]
+    Client(#[from] reqwest::Error),
+}
+
+impl NetworkSettings {
+    /// Apply the proxy and the CA certificates to a reqwest client builder. Hosts in
+    /// `NO_PROXY` are not proxied.
+    pub fn apply(
+        &self,
+        mut builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, ClientError> {
+        if let Some(proxy) = &self.proxy {
+            let reqwest_proxy = reqwest::Proxy::all(proxy.as_str())
+                .map_err(|e| ClientError::InvalidProxy(proxy.clone(), e))?
+                .no_proxy(reqwest::NoProxy::from_env());
+            builder = builder.proxy(reqwest_proxy);
+        }
+
+        if let Some(path) = &self.ca_cert {
+            let pem = fs_err::read(path).map_err(|e| ClientError::ReadCaCert(path.clone(), e))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| ClientError::InvalidCaCert(path.clone(), e))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// The url of a download on the mirror, or the url itself if no mirror matches.
+    pub fn mirror_url(&self, url: &Url) -> Url {
+        mirror::mirror_url(&self.mirrors, url).unwrap_or_else(|| url.clone())
+    }
+
+    /// Whether a request is refused because of the offline mode. A refused request is
+    /// recorded, so that all of them can be reported when the build fails.
+    pub fn refuse_offline(&self, request: impl Into<String>) -> bool {
+        if self.offline {
+            self.offline_requests.record(request);
+        }
+        self.offline
+    }
+
+    /// A plain reqwest client (without authentication) that uses these settings.
+    pub fn client(&self) -> Result<reqwest::Client, ClientError> {
+        Ok(self
+            .apply(reqwest::Client::builder().user_agent(APP_USER_AGENT))?
+            .build()?)
+    }
+
+    /// The environment variables that make `git` (and `git lfs`) use these settings.
+    pub fn git_env(&self) -> Vec<(&'static str, String)> {
+        let mut env = Vec::new();
+        if let Some(proxy) = &self.proxy {
+            env.push(("HTTPS_PROXY", proxy.to_string()));
+            env.push(("HTTP_PROXY", proxy.to_string()));
+        }
+        if let Some(ca_cert) = &self.ca_cert {
+            env.push(("GIT_SSL_CAINFO", ca_cert.to_string_lossy().to_string()));
+        }
+        if self.non_interactive {
+            // fail instead of asking for a username, password or passphrase
+            env.push(("GIT_TERMINAL_PROMPT", "0".to_string()));
+            env.push(("GCM_INTERACTIVE", "never".to_string()));
+            if std::env::var_os("GIT_SSH_COMMAND").is_none() {
+                env.push(("GIT_SSH_COMMAND", "ssh -o BatchMode=yes".to_string()));
+            }
+        }
+        env
+    }
+}
+
 /// Create a reqwest client with the authentication middleware
 pub fn reqwest_client_from_auth_storage(
     auth_file: Option<PathBuf>,
-) -> Result<ClientWithMiddleware, FileStorageError> {
+    network: &NetworkSettings,
+) -> Result<ClientWithMiddleware, ClientError> {
     let auth_storage = get_auth_store(auth_file)?;
 
     let timeout = 5 * 60;
-    Ok(reqwest_middleware::ClientBuilder::new(
-        reqwest::Client::builder()
-            .no_gzip()
-            .pool_max_idle_per_host(20)
-            .user_agent(APP_USER_AGENT)
-            .timeout(std::time::Duration::from_secs(timeout))
-            .build()
-            .expect("failed to create client"),
-    )
-    .with_arc(Arc::new(AuthenticationMiddleware::new(auth_storage)))
-    .build())
+    let builder = reqwest::Client::builder()
+        .no_gzip()
+        .pool_max_idle_per_host(20)
+        .user_agent(APP_USER_AGENT)
+        .timeout(std::time::Duration::from_secs(timeout));
+
+    let mut client = reqwest_middleware::ClientBuilder::new(network.apply(builder)?.build()?);
+    // the mirror is selected first, so that the credentials of the mirror are used
+    if network.offline || !network.mirrors.is_empty() {
+        client = client.with(MirrorMiddleware::new(
+            network.mirrors.clone(),
+            network.offline.then(|| network.offline_requests.clone()),
+        ));
+    }
+    Ok(client
+        .with_arc(Arc::new(AuthenticationMiddleware::new(auth_storage)))
+        .build())
+}
+
+/// The root of the package and repodata caches: the given directory, or the default cache
+/// directory of rattler (`RATTLER_CACHE_DIR` or e.g. `~/.cache/rattler/cache`). pixi uses
+/// the same directory by default, so packages and repodata are only downloaded once.
+pub fn rattler_cache_dir(cache_dir: Option<&Path>) -> PathBuf {
+    match cache_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            rattler::default_cache_dir().unwrap_or_else(|_err| std::env::temp_dir().join("rattler"))
+        }
+    }
+}
+
+/// Create a repodata gateway that caches repodata in the `repodata` directory of the
+/// cache directory and downloads it with the given client. In offline mode, only the
+/// cached repodata is used.
+pub fn repodata_gateway(
+    client: ClientWithMiddleware,
+    network: &NetworkSettings,
+    cache_dir: &Path,
+) -> Gateway {
+    let mut builder = Gateway::builder()
+        .with_cache_dir(cache_dir.join("repodata"))
+        .with_client(client);
+    if network.offline {
+        builder = builder.with_channel_config(rattler_repodata_gateway::ChannelConfig {
+            default: SourceConfig {
+                cache_action: CacheAction::ForceCacheOnly,
+                ..SourceConfig::default()
+            },
+            ..Default::default()
+        });
+    }
+    builder.finish()
 }
 
 impl Default for Configuration {
     fn default() -> Self {
+        let client = reqwest_client_from_auth_storage(None, &NetworkSettings::default())
+            .expect("failed to create client");
         Self {
             fancy_log_handler: LoggingOutputHandler::default(),
-            client: reqwest_client_from_auth_storage(None).expect("failed to create client"),
+            repodata_gateway: repodata_gateway(
+                client.clone(),
+                &NetworkSettings::default(),
+                &rattler_cache_dir(None),
+            ),
+            client,
             no_clean: false,
             no_test: false,
+            test_policy: TestPolicy::default(),
+            log_sink: None,
             use_zstd: true,
             use_bz2: true,
             render_only: false,
             skip_existing: SkipExisting::None,
+            debug_shell: false,
+            extraction_policy: ExtractionPolicy::default(),
+            incremental: false,
+            from_phase: None,
+            jobs: NonZeroUsize::MIN,
+            scan_cmd: None,
+            stats_file: None,
+            signing: None,
             channel_config: ChannelConfig::default_with_root_dir(
                 std::env::current_dir().unwrap_or_else(|_err| PathBuf::from("/")),
             ),
+            skipped_outputs: Arc::default(),
+            network: NetworkSettings::default(),
+            source_cache: None,
+            private_cache_dir: None,
+            cache_dir: None,
+            mock_virtual_packages: Vec::new(),
+            environment: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rattler_conda_types::Platform;
+
+    use super::TestPolicy;
+
+    #[test]
+    fn test_policy_skipped_reason() {
+        for policy in [
+            TestPolicy::NativeOnly,
+            TestPolicy::Emulate,
+            TestPolicy::Required,
+        ] {
+            for platform in [Platform::Linux64, Platform::Linux32, Platform::NoArch] {
+                assert_eq!(
+                    policy.skipped_reason(Platform::Linux64, platform).unwrap(),
+                    None
+                );
+            }
+        }
+
+        let reason = TestPolicy::NativeOnly
+            .skipped_reason(Platform::Linux64, Platform::Win64)
+            .unwrap()
+            .unwrap();
+        assert!(reason.contains("win-64 packages cannot run on this linux-64 machine"));
+
+        // Windows packages cannot be emulated on Linux
+        assert!(TestPolicy::Emulate
+            .skipped_reason(Platform::Linux64, Platform::Win64)
+            .unwrap()
+            .is_some());
+
+        assert!(TestPolicy::Required
+            .skipped_reason(Platform::Linux64, Platform::Win64)
+            .is_err());
+    }
+}
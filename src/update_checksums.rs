@@ -0,0 +1,766 @@
+//! The `update-checksums` subcommand downloads the URL sources of a recipe and writes
+//! their checksums into the recipe. The `fetch-checksums` subcommand does the same for
+//! the sources that have no checksum yet or a placeholder (e.g. `TODO`), so that only
+//! those are downloaded.
+//!
+//! The recipe is edited in place: only the values of existing `sha256` and `md5` fields
+//! are replaced, and a `sha256` field is added below the `url` of sources without a
+//! checksum. Comments, quoting and the rest of the formatting are left untouched.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use fs_err as fs;
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+use rattler_digest::{compute_file_digest, Md5};
+use sha2::Sha256;
+use url::Url;
+
+use crate::{
+    get_recipe_path,
+    lint::lint_selector_config,
+    recipe::{
+        custom_yaml::{HasSpan, Node, RenderedMappingNode, RenderedNode, RenderedScalarNode},
+        ParsingError, Recipe,
+    },
+    tool_configuration::NetworkSettings,
+};
+
+/// Options for the `update-checksums` subcommand.
+#[derive(Parser)]
+pub struct UpdateChecksumsOpts {
+    /// The recipe file or the directory that contains `recipe.yaml`.
+    #[arg(default_value = ".")]
+    pub recipe: PathBuf,
+
+    /// The target platform used to evaluate selectors in the recipe. Sources that are
+    /// not selected for this platform are left unchanged.
+    #[arg(long, default_value_t = Platform::current())]
+    pub target_platform: Platform,
+}
+
+/// Options for the `fetch-checksums` subcommand.
+#[derive(Parser)]
+pub struct FetchChecksumsOpts {
+    /// The recipe file or the directory that contains `recipe.yaml`.
+    #[arg(short, long, default_value = ".")]
+    pub recipe: PathBuf,
+
+    /// The target platform used to evaluate selectors in the recipe. Sources that are
+    /// not selected for this platform are left unchanged.
+    #[arg(long, default_value_t = Platform::current())]
+    pub target_platform: Platform,
+}
+
+/// The checksums of a downloaded file.
+#[derive(Debug, Clone)]
+struct Digests {
+    sha256: String,
+    md5: String,
+}
+
+impl Digests {
+    /// The checksum for a checksum field of a source (`sha256` or `md5`).
+    fn get(&self, field: &str) -> &str {
+        match field {
+            "md5" => &self.md5,
+            _ => &self.sha256,
+        }
+    }
+}
+
+/// Whether the value of a checksum field is a placeholder for a checksum that still has
+/// to be filled in: anything that is neither a template nor a valid (non-zero) checksum,
+/// e.g. `TODO`, `<sha256>` or `0000...`.
+fn is_placeholder(value: &str, field: &str) -> bool {
+    if value.contains("{{") {
+        return false;
+    }
+    let length = if field == "md5" { 32 } else { 64 };
+    value.len() != length
+        || !value.chars().all(|c| c.is_ascii_hexdigit())
+        || value.chars().all(|c| c == '0')
+}
+
+/// A change of the recipe text: replace `len` bytes at `offset` with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Edit {
+    offset: usize,
+    len: usize,
+    text: String,
+}
+
+/// The URL sources in the `source` of a rendered recipe and of its outputs.
+fn url_sources(root: &RenderedMappingNode) -> Vec<&RenderedMappingNode> {
+    let outputs = root
+        .get("outputs")
+        .and_then(RenderedNode::as_sequence)
+        .into_iter()
+        .flat_map(|outputs| outputs.iter())
+        .filter_map(RenderedNode::as_mapping);
+
+    let mut sources = Vec::new();
+    for node in std::iter::once(root).chain(outputs) {
+        match node.get("source") {
+            Some(RenderedNode::Mapping(source)) => sources.push(source),
+            Some(RenderedNode::Sequence(seq)) => {
+                sources.extend(seq.iter().filter_map(RenderedNode::as_mapping))
+            }
+            _ => {}
+        }
+    }
+    sources.retain(|source| source.contains_key("url"));
+    sources
+}
+
+/// The urls of a `url` field (a single url or a list of mirrors).
+fn urls_of(node: &RenderedNode) -> miette::Result<Vec<Url>> {
+    let scalars = match node {
+        RenderedNode::Scalar(url) => vec![url],
+        RenderedNode::Sequence(urls) => urls.iter().filter_map(RenderedNode::as_scalar).collect(),
+        _ => {
+            return Err(miette::miette!(
+                "`url` must be a string or a list of strings"
+            ))
+        }
+    };
+    scalars
+        .into_iter()
+        .map(|url| {
+            Url::parse(url.as_str())
+                .map_err(|e| miette::miette!("invalid url `{}`: {}", url.as_str(), e))
+        })
+        .collect()
+}
+
+/// The urls of a source, one entry per platform for a per-platform `url` (and a single
+/// entry without platform otherwise).
+fn source_urls(source: &RenderedMappingNode) -> miette::Result<Vec<(Option<String>, Vec<Url>)>> {
+    match source.get("url") {
+        Some(RenderedNode::Mapping(per_platform)) => per_platform
+            .iter()
+            .map(|(platform, urls)| Ok((Some(platform.as_str().to_string()), urls_of(urls)?)))
+            .collect(),
+        Some(urls) => Ok(vec![(None, urls_of(urls)?)]),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Download a file and compute its checksums.
+async fn download_digests(client: &reqwest::Client, url: &Url) -> miette::Result<Digests> {
+    let digests = |path: &std::path::Path| -> miette::Result<Digests> {
+        Ok(Digests {
+            sha256: format!(
+                "{:x}",
+                compute_file_digest::<Sha256>(path).into_diagnostic()?
+            ),
+            md5: format!("{:x}", compute_file_digest::<Md5>(path).into_diagnostic()?),
+        })
+    };
+
+    if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|_| miette::miette!("invalid local file path: {}", url))?;
+        return digests(&path);
+    }
+
+    eprintln!("Downloading {}", url);
+    let mut response = client
+        .get(url.clone())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .into_diagnostic()?;
+    let mut file = tempfile::NamedTempFile::new().into_diagnostic()?;
+    while let Some(chunk) = response.chunk().await.into_diagnostic()? {
+        file.write_all(&chunk).into_diagnostic()?;
+    }
+    file.flush().into_diagnostic()?;
+    digests(file.path())
+}
+
+/// Download the first reachable url of a list of mirrors and compute its checksums.
+async fn digests_of_any(client: &reqwest::Client, urls: &[Url]) -> miette::Result<Digests> {
+    let mut last_error = None;
+    for url in urls {
+        match download_digests(client, url).await {
+            Ok(digests) => return Ok(digests),
+            Err(err) => {
+                tracing::warn!("Could not download {}: {}", url, err);
+                last_error = Some(err);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| miette::miette!("the source has no url")))
+}
+
+/// Computes the edits of the recipe text for the checksums of the downloaded urls.
+struct ChecksumEditor<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+    newline: &'static str,
+    edits: BTreeMap<usize, Edit>,
+    /// Only fill in missing and placeholder checksums (`fetch-checksums`)
+    missing_only: bool,
+}
+
+impl<'a> ChecksumEditor<'a> {
+    fn new(text: &'a str, missing_only: bool) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self {
+            text,
+            line_starts,
+            newline: if text.contains("\r\n") { "\r\n" } else { "\n" },
+            edits: BTreeMap::new(),
+            missing_only,
+        }
+    }
+
+    /// The byte offset of a (1-based) line and column of the recipe text.
+    fn offset(&self, line: usize, column: usize) -> usize {
+        let start = self.line_starts[line.saturating_sub(1).min(self.line_starts.len() - 1)];
+        self.text[start..]
+            .char_indices()
+            .nth(column.saturating_sub(1))
+            .map_or(self.text.len(), |(idx, _)| start + idx)
+    }
+
+    /// The line and column where a node starts.
+    fn position(node: &impl HasSpan) -> (usize, usize) {
+        node.span()
+            .start()
+            .map_or((1, 1), |marker| (marker.line(), marker.column()))
+    }
+
+    /// The last line that a node (or any of its children) starts on.
+    fn last_line(node: &RenderedNode) -> usize {
+        let own = Self::position(node).0;
+        match node {
+            RenderedNode::Scalar(_) | RenderedNode::Null(_) => own,
+            RenderedNode::Sequence(seq) => seq.iter().map(Self::last_line).max().unwrap_or(own),
+            RenderedNode::Mapping(map) => map.values().map(Self::last_line).max().unwrap_or(own),
+        }
+    }
+
+    /// Add an edit, identical edits at the same place are only applied once.
+    fn add(&mut self, edit: Edit) -> miette::Result<()> {
+        match self.edits.get(&edit.offset) {
+            Some(existing) if *existing != edit => Err(miette::miette!(
+                "conflicting checksums at byte {} of the recipe",
+                edit.offset
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.edits.insert(edit.offset, edit);
+                Ok(())
+            }
+        }
+    }
+
+    /// The byte range of the (unquoted) value of a scalar in the recipe text.
+    fn value_range(&self, value: &RenderedScalarNode) -> (usize, usize) {
+        let (line, column) = Self::position(value);
+        let mut start = self.offset(line, column);
+        let rest = &self.text[start..];
+        let len = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                start += 1;
+                rest[1..].find(quote).unwrap_or(0)
+            }
+            _ => {
+                // a plain scalar ends at the end of the line or at a comment
+                let line = &rest[..rest.find(|c| c == '\r' || c == '\n').unwrap_or(rest.len())];
+                line[..line.find(" #").unwrap_or(line.len())]
+                    .trim_end()
+                    .len()
+            }
+        };
+        (start, len)
+    }
+
+    /// Whether a checksum field has no value or a placeholder value.
+    fn is_missing(&self, node: &RenderedNode, field: &str) -> bool {
+        match node {
+            RenderedNode::Null(_) => true,
+            RenderedNode::Scalar(value) => {
+                let (start, len) = self.value_range(value);
+                is_placeholder(&self.text[start..start + len], field)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a URL source has a missing or placeholder checksum and has to be
+    /// downloaded by `fetch-checksums`.
+    fn needs_checksum(&self, source: &RenderedMappingNode) -> bool {
+        if !source.contains_key("sha256") {
+            return !source.contains_key("md5");
+        }
+        match (&source["sha256"], source.get("url")) {
+            (RenderedNode::Mapping(values), Some(RenderedNode::Mapping(urls))) => {
+                urls.keys().any(|platform| {
+                    values
+                        .iter()
+                        .find(|(key, _)| key.as_str() == platform.as_str())
+                        .map_or(true, |(_, value)| self.is_missing(value, "sha256"))
+                })
+            }
+            (value, _) => self.is_missing(value, "sha256"),
+        }
+    }
+
+    /// Write a checksum after the colon of a key without a value (`sha256:`).
+    fn fill(&mut self, key: &RenderedScalarNode, checksum: &str) -> miette::Result<()> {
+        let (line, column) = Self::position(key);
+        let start = self.offset(line, column);
+        let Some(colon) = self.text[start..].find(':') else {
+            return Err(miette::miette!(
+                "line {}: expected a `:` after the key",
+                line
+            ));
+        };
+        // replace an explicit null (`~`) but keep a comment
+        let offset = start + colon + 1;
+        let rest = &self.text[offset..];
+        let line = &rest[..rest.find(|c| c == '\r' || c == '\n').unwrap_or(rest.len())];
+        let len = line[..line.find(" #").unwrap_or(line.len())]
+            .trim_end()
+            .len();
+        self.add(Edit {
+            offset,
+            len,
+            text: format!(" {}", checksum),
+        })
+    }
+
+    /// Replace the value of a checksum field with a new checksum. Templates are not
+    /// touched. With `missing_only`, only placeholders are replaced.
+    fn replace(
+        &mut self,
+        value: &RenderedScalarNode,
+        field: &str,
+        checksum: &str,
+    ) -> miette::Result<()> {
+        let line = Self::position(value).0;
+        let (start, len) = self.value_range(value);
+        let old = &self.text[start..start + len];
+        let placeholder = is_placeholder(old, field);
+        if self.missing_only && !placeholder {
+            return Ok(());
+        }
+        if old.contains("{{") {
+            tracing::warn!(
+                "The checksum on line {} is not a plain value (`{}`), update it to `{}` by hand",
+                line,
+                old,
+                checksum
+            );
+            return Ok(());
+        }
+        if old != checksum {
+            self.add(Edit {
+                offset: start,
+                len,
+                text: checksum.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Insert lines (each given with its indentation) after the line of a node.
+    fn insert_after(&mut self, node: &RenderedNode, lines: &[String]) -> miette::Result<()> {
+        let line = Self::last_line(node);
+        let mut text = String::new();
+        let offset = match self.line_starts.get(line) {
+            Some(offset) => *offset,
+            None => {
+                if !self.text.is_empty() && !self.text.ends_with('\n') {
+                    text.push_str(self.newline);
+                }
+                self.text.len()
+            }
+        };
+        for line in lines {
+            text.push_str(line);
+            text.push_str(self.newline);
+        }
+        self.add(Edit {
+            offset,
+            len: 0,
+            text,
+        })
+    }
+
+    /// Update (or add) the checksums of a URL source.
+    fn update_source(
+        &mut self,
+        source: &RenderedMappingNode,
+        digests: &HashMap<Url, Digests>,
+    ) -> miette::Result<()> {
+        let Some((url_key, url_node)) = source.iter().find(|(key, _)| key.as_str() == "url") else {
+            return Ok(());
+        };
+        let indent = " ".repeat(Self::position(url_key).1.saturating_sub(1));
+
+        let mut source_digests = Vec::new();
+        for (platform, urls) in source_urls(source)? {
+            if let Some(found) = urls.iter().find_map(|url| digests.get(url)) {
+                source_digests.push((platform, found.clone()));
+            }
+        }
+        let fields = ["sha256", "md5"]
+            .into_iter()
+            .filter(|field| source.contains_key(*field))
+            .filter(|field| !self.missing_only || *field == "sha256")
+            .collect::<Vec<_>>();
+        if self.missing_only && fields.is_empty() && source.contains_key("md5") {
+            return Ok(());
+        }
+
+        if fields.is_empty() {
+            let lines = match source_digests.as_slice() {
+                [(None, found)] => vec![format!("{indent}sha256: {}", found.sha256)],
+                _ => std::iter::once(format!("{indent}sha256:"))
+                    .chain(source_digests.iter().map(|(platform, found)| {
+                        format!(
+                            "{indent}  {}: {}",
+                            platform.as_deref().unwrap_or_default(),
+                            found.sha256
+                        )
+                    }))
+                    .collect(),
+            };
+            return self.insert_after(url_node, &lines);
+        }
+
+        for field in fields {
+            let key = source
+                .keys()
+                .find(|key| key.as_str() == field)
+                .expect("the field exists");
+            match (&source[field], url_node) {
+                (RenderedNode::Null(_), RenderedNode::Mapping(_)) => {
+                    let column = Self::position(key).1 + 2;
+                    let key_indent = " ".repeat(column.saturating_sub(1));
+                    let lines = source_digests
+                        .iter()
+                        .map(|(platform, found)| {
+                            format!(
+                                "{key_indent}{}: {}",
+                                platform.as_deref().unwrap_or_default(),
+                                found.get(field)
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    self.insert_after(&RenderedNode::Scalar(key.clone()), &lines)?;
+                }
+                (RenderedNode::Null(_), _) => {
+                    if let Some((_, found)) = source_digests.first() {
+                        self.fill(key, found.get(field))?;
+                    }
+                }
+                (RenderedNode::Scalar(value), RenderedNode::Mapping(_)) => {
+                    return Err(miette::miette!(
+                        "line {}: `{field}` must be a per-platform mapping like `url`",
+                        Self::position(value).0
+                    ));
+                }
+                (RenderedNode::Scalar(value), _) => {
+                    if let Some((_, found)) = source_digests.first() {
+                        self.replace(value, field, found.get(field))?;
+                    }
+                }
+                (RenderedNode::Mapping(values), _) => {
+                    let mut missing = Vec::new();
+                    for (platform, found) in &source_digests {
+                        let platform = platform.as_deref().unwrap_or_default();
+                        match values.iter().find(|(key, _)| key.as_str() == platform) {
+                            Some((_, RenderedNode::Scalar(value))) => {
+                                self.replace(value, field, found.get(field))?
+                            }
+                            Some((key, RenderedNode::Null(_))) => {
+                                self.fill(key, found.get(field))?
+                            }
+                            _ => missing.push((platform, found.get(field))),
+                        }
+                    }
+                    if !missing.is_empty() {
+                        let column = values
+                            .keys()
+                            .next()
+                            .map_or(indent.len() + 3, |key| Self::position(key).1);
+                        let key_indent = " ".repeat(column.saturating_sub(1));
+                        let lines = missing
+                            .into_iter()
+                            .map(|(platform, checksum)| {
+                                format!("{key_indent}{platform}: {checksum}")
+                            })
+                            .collect::<Vec<_>>();
+                        self.insert_after(&source[field], &lines)?;
+                    }
+                }
+                (other, _) => {
+                    return Err(miette::miette!(
+                        "line {}: `{field}` must be a string",
+                        Self::position(other).0
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the edits to the recipe text.
+    fn apply(self) -> String {
+        let mut text = self.text.to_string();
+        for edit in self.edits.into_values().rev() {
+            text.replace_range(edit.offset..edit.offset + edit.len, &edit.text);
+        }
+        text
+    }
+}
+
+/// Render the recipe for the given platform. The spans of the rendered nodes point into
+/// the recipe text.
+fn render_recipe(
+    recipe_text: &str,
+    target_platform: Platform,
+) -> miette::Result<RenderedMappingNode> {
+    let root = Node::parse_yaml(0, recipe_text)?;
+    Recipe::render_node(&root, lint_selector_config(target_platform)).map_err(|errs| {
+        match errs.into_iter().next() {
+            Some(err) => ParsingError::from_partial(recipe_text, err).into(),
+            None => miette::miette!("failed to render the recipe"),
+        }
+    })
+}
+
+/// Rewrite the checksums of the URL sources of a recipe text with the checksums of the
+/// downloaded files.
+fn update_recipe_text(
+    recipe_text: &str,
+    rendered: &RenderedMappingNode,
+    digests: &HashMap<Url, Digests>,
+    missing_only: bool,
+) -> miette::Result<String> {
+    let mut editor = ChecksumEditor::new(recipe_text, missing_only);
+    for source in url_sources(rendered) {
+        if missing_only && !editor.needs_checksum(source) {
+            continue;
+        }
+        editor.update_source(source, digests)?;
+    }
+    Ok(editor.apply())
+}
+
+/// Download the URL sources of a recipe and write their checksums into the recipe. With
+/// `missing_only`, only the sources with a missing or placeholder checksum are
+/// downloaded and updated.
+async fn write_checksums(
+    recipe: &Path,
+    target_platform: Platform,
+    missing_only: bool,
+) -> miette::Result<()> {
+    let recipe_path = get_recipe_path(recipe)?;
+    let recipe_text = fs::read_to_string(&recipe_path).into_diagnostic()?;
+    let rendered = render_recipe(&recipe_text, target_platform)?;
+
+    let client = NetworkSettings::default().client().into_diagnostic()?;
+    let editor = ChecksumEditor::new(&recipe_text, missing_only);
+    let mut digests = HashMap::new();
+    for source in url_sources(&rendered) {
+        if missing_only && !editor.needs_checksum(source) {
+            continue;
+        }
+        for (_, urls) in source_urls(source)? {
+            if urls.iter().any(|url| digests.contains_key(url)) {
+                continue;
+            }
+            let found = digests_of_any(&client, &urls).await?;
+            for url in urls {
+                digests.insert(url, found.clone());
+            }
+        }
+    }
+
+    let updated = update_recipe_text(&recipe_text, &rendered, &digests, missing_only)?;
+    if updated == recipe_text {
+        println!("All checksums in {} are up to date.", recipe_path.display());
+    } else {
+        fs::write(&recipe_path, updated).into_diagnostic()?;
+        println!("Updated the checksums in {}", recipe_path.display());
+    }
+    Ok(())
+}
+
+/// Download the URL sources of a recipe and write their checksums into the recipe.
+pub async fn update_checksums_from_args(args: UpdateChecksumsOpts) -> miette::Result<()> {
+    write_checksums(&args.recipe, args.target_platform, false).await
+}
+
+/// Download the URL sources of a recipe without a checksum (or with a placeholder) and
+/// write their checksums into the recipe.
+pub async fn fetch_checksums_from_args(args: FetchChecksumsOpts) -> miette::Result<()> {
+    write_checksums(&args.recipe, args.target_platform, true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digests(sha256: &str) -> Digests {
+        Digests {
+            sha256: sha256.repeat(64),
+            md5: sha256.repeat(32),
+        }
+    }
+
+    #[test]
+    fn test_update_recipe_text() {
+        let recipe = r#"context:
+  version: "1.0"
+
+package:
+  name: foo
+  version: ${{ version }}
+
+source:
+  - url: https://example.com/foo-${{ version }}.tar.gz
+    sha256: "0000000000000000000000000000000000000000000000000000000000000000"  # old
+  - url: https://example.com/bar.tar.gz
+    target_directory: bar
+  - url:
+      linux-64: https://example.com/sdk-linux-64.tar.gz
+      win-64: https://example.com/sdk-win-64.zip
+    md5:
+      linux-64: 00000000000000000000000000000000
+"#;
+        let rendered = render_recipe(recipe, Platform::Linux64).unwrap();
+        let digests = HashMap::from([
+            (
+                Url::parse("https://example.com/foo-1.0.tar.gz").unwrap(),
+                digests("a"),
+            ),
+            (
+                Url::parse("https://example.com/bar.tar.gz").unwrap(),
+                digests("b"),
+            ),
+            (
+                Url::parse("https://example.com/sdk-linux-64.tar.gz").unwrap(),
+                digests("c"),
+            ),
+            (
+                Url::parse("https://example.com/sdk-win-64.zip").unwrap(),
+                digests("d"),
+            ),
+        ]);
+
+        let updated = update_recipe_text(recipe, &rendered, &digests, false).unwrap();
+        let expected = recipe
+            .replace(&"0".repeat(64), &"a".repeat(64))
+            .replace(
+                "    target_directory: bar\n",
+                &format!(
+                    "    sha256: {}\n    target_directory: bar\n",
+                    "b".repeat(64)
+                ),
+            )
+            .replace(
+                &format!("      linux-64: {}\n", "0".repeat(32)),
+                &format!(
+                    "      linux-64: {}\n      win-64: {}\n",
+                    "c".repeat(32),
+                    "d".repeat(32)
+                ),
+            );
+        assert_eq!(updated, expected);
+
+        // nothing changes the second time
+        let rendered = render_recipe(&updated, Platform::Linux64).unwrap();
+        assert_eq!(
+            update_recipe_text(&updated, &rendered, &digests, false).unwrap(),
+            updated
+        );
+    }
+
+    #[test]
+    fn test_fetch_missing_checksums() {
+        let recipe = r#"package:
+  name: foo
+  version: "1.0"
+
+source:
+  - url: https://example.com/foo.tar.gz
+    sha256: 1111111111111111111111111111111111111111111111111111111111111111
+  - url: https://example.com/bar.tar.gz
+    sha256: TODO  # fill in
+  - url: https://example.com/baz.tar.gz
+    sha256:
+  - url: https://example.com/qux.tar.gz
+"#;
+        let rendered = render_recipe(recipe, Platform::Linux64).unwrap();
+        let editor = ChecksumEditor::new(recipe, true);
+        let needed = url_sources(&rendered)
+            .into_iter()
+            .map(|source| editor.needs_checksum(source))
+            .collect::<Vec<_>>();
+        assert_eq!(needed, [false, true, true, true]);
+
+        let digests = ["foo", "bar", "baz", "qux"]
+            .into_iter()
+            .zip(["a", "b", "c", "d"])
+            .map(|(name, sha256)| {
+                (
+                    Url::parse(&format!("https://example.com/{name}.tar.gz")).unwrap(),
+                    digests(sha256),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        let updated = update_recipe_text(recipe, &rendered, &digests, true).unwrap();
+        let expected = recipe
+            .replace("TODO", &"b".repeat(64))
+            .replace(
+                "    sha256:\n",
+                &format!("    sha256: {}\n", "c".repeat(64)),
+            )
+            .replace(
+                "qux.tar.gz\n",
+                &format!("qux.tar.gz\n    sha256: {}\n", "d".repeat(64)),
+            );
+        assert_eq!(updated, expected);
+    }
+
+    #[test]
+    fn test_render_without_checksum() {
+        let recipe = r#"package:
+  name: foo
+  version: "1.0"
+
+source:
+  - url: https://example.com/foo.tar.gz
+"#;
+        // a build refuses the source, but its checksum can still be filled in
+        assert!(Recipe::from_yaml(recipe, lint_selector_config(Platform::Linux64)).is_err());
+        let rendered = render_recipe(recipe, Platform::Linux64).unwrap();
+        assert_eq!(url_sources(&rendered).len(), 1);
+    }
+
+    #[test]
+    fn test_is_placeholder() {
+        assert!(is_placeholder("", "sha256"));
+        assert!(is_placeholder("TODO", "sha256"));
+        assert!(is_placeholder(&"0".repeat(64), "sha256"));
+        assert!(is_placeholder(&"a".repeat(64), "md5"));
+        assert!(!is_placeholder(&"a".repeat(64), "sha256"));
+        assert!(!is_placeholder("${{ sha256 }}", "sha256"));
+    }
+}
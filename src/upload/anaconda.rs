@@ -1,9 +1,11 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, path::Path};
 
+use futures::future::BoxFuture;
 use miette::miette;
 use miette::IntoDiagnostic;
 use rattler_conda_types::package::AboutJson;
 use rattler_conda_types::PackageName;
+use rattler_networking::{Authentication, AuthenticationStorage};
 use reqwest::multipart::Form;
 use reqwest::multipart::Part;
 use reqwest::Client;
@@ -12,11 +14,14 @@ use tracing::debug;
 use tracing::info;
 use url::Url;
 
+use super::backend::{scheme_backend, UploadBackend, UploadTarget};
 use super::package::ExtractedPackage;
-use super::VERSION;
+use super::{UploadContext, VERSION};
+use crate::tool_configuration::{ClientError, NetworkSettings};
 
 pub struct Anaconda {
     client: Client,
+    storage_client: Client,
     url: Url,
 }
 
@@ -45,7 +50,7 @@ struct FileStageResponse {
 }
 
 impl Anaconda {
-    pub fn new(token: String, url: Url) -> Self {
+    pub fn new(token: String, url: Url, network: &NetworkSettings) -> Result<Self, ClientError> {
         let mut default_headers = reqwest::header::HeaderMap::new();
 
         default_headers.append(
@@ -62,14 +67,22 @@ impl Anaconda {
             "1.12.2".parse().expect("failed to parse"),
         );
 
-        let client = Client::builder()
-            .no_gzip()
-            .user_agent(format!("rattler-build/{}", VERSION))
-            .default_headers(default_headers)
-            .build()
-            .expect("failed to create client");
-
-        Self { client, url }
+        let client = network
+            .apply(
+                Client::builder()
+                    .no_gzip()
+                    .user_agent(format!("rattler-build/{}", VERSION))
+                    .default_headers(default_headers),
+            )?
+            .build()?;
+        // the files are uploaded to the storage without the token
+        let storage_client = network.apply(Client::builder())?.build()?;
+
+        Ok(Self {
+            client,
+            storage_client,
+            url,
+        })
     }
 }
 
@@ -419,7 +432,7 @@ impl Anaconda {
         form_data = form_data.text("Content-MD5", base64_md5.to_string());
         form_data = form_data.part("file", Part::bytes(content));
 
-        reqwest::Client::new()
+        self.storage_client
             .post(parsed_response.post_url)
             .multipart(form_data)
             .header("Accept", "application/json")
@@ -463,3 +476,110 @@ impl Anaconda {
         Ok(true)
     }
 }
+
+/// The backend for anaconda.org, authenticated with a Conda token. The channel of the
+/// target is the owner of the distribution, the packages are added to the `labels`.
+pub struct AnacondaBackend {
+    /// The labels (channels) of the owner to add the packages to
+    pub labels: Vec<String>,
+    /// Replace files on conflict
+    pub force: bool,
+}
+
+impl Default for AnacondaBackend {
+    fn default() -> Self {
+        Self {
+            labels: vec!["main".to_string()],
+            force: false,
+        }
+    }
+}
+
+impl UploadBackend for AnacondaBackend {
+    fn name(&self) -> &str {
+        "anaconda"
+    }
+
+    fn resolve_target(&self, url: &Url) -> Option<UploadTarget> {
+        if scheme_backend(url) == Some(self.name()) {
+            return UploadTarget::from_url(url);
+        }
+        if scheme_backend(url).is_some()
+            || !matches!(url.host_str(), Some("anaconda.org" | "api.anaconda.org"))
+        {
+            return None;
+        }
+        let owner = url.path_segments()?.find(|segment| !segment.is_empty())?;
+        Some(UploadTarget::new(
+            Url::parse("https://api.anaconda.org").expect("valid url"),
+            owner,
+        ))
+    }
+
+    fn authenticate(
+        &self,
+        storage: &AuthenticationStorage,
+        target: &UploadTarget,
+    ) -> miette::Result<Authentication> {
+        let authentication = match &target.credentials {
+            Some(credentials) => credentials.clone(),
+            None => match storage.get("anaconda.org") {
+                Ok(Some(authentication)) => authentication,
+                Ok(None) => {
+                    return Err(miette!(
+                        "No anaconda.org api key was given and no token were found in the keychain / auth file. Please create a token on anaconda.org"
+                    ));
+                }
+                Err(e) => {
+                    return Err(miette!(
+                        "Failed to get authentication information form keychain: {e}"
+                    ));
+                }
+            },
+        };
+        match authentication {
+            Authentication::CondaToken(_) => Ok(authentication),
+            _ => Err(miette!(
+                "A Conda token is required for authentication with anaconda.org.
+                        Authentication information found in the keychain / auth file, but it was not a Conda token.
+                        Please create a token on anaconda.org"
+            )),
+        }
+    }
+
+    fn upload<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            let Authentication::CondaToken(token) = authentication else {
+                return Err(miette!("A Conda token is required for anaconda.org"));
+            };
+            let anaconda = Anaconda::new(token.clone(), target.url.clone(), context.network())
+                .into_diagnostic()?;
+            let owner = &target.channel;
+
+            loop {
+                let package = ExtractedPackage::from_package_file(package_file)?;
+
+                anaconda.create_or_update_package(owner, &package).await?;
+
+                anaconda.create_or_update_release(owner, &package).await?;
+
+                let successful = anaconda
+                    .upload_file(owner, &self.labels, self.force, &package)
+                    .await?;
+
+                // When running with --force and experiencing a conflict error, we delete the conflicting file.
+                // Anaconda automatically deletes releases / packages when the deletion of a file would leave them empty.
+                // Therefore, we need to ensure that the release / package still exists before trying to upload again.
+                if successful {
+                    return Ok(());
+                }
+            }
+        })
+    }
+}
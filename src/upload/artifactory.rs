@@ -0,0 +1,140 @@
+//! Uploading packages to a JFrog Artifactory server.
+
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use miette::IntoDiagnostic;
+use rattler_networking::{Authentication, AuthenticationStorage};
+use reqwest::Method;
+
+use super::{
+    backend::{stored_authentication, UploadBackend, UploadTarget},
+    package::ExtractedPackage,
+    UploadContext,
+};
+
+/// The backend for Artifactory servers, authenticated with a username and password.
+pub struct Artifactory;
+
+impl UploadBackend for Artifactory {
+    fn name(&self) -> &str {
+        "artifactory"
+    }
+
+    fn idempotent_upload(&self) -> bool {
+        // the package is `PUT` to its path
+        true
+    }
+
+    fn authenticate(
+        &self,
+        storage: &AuthenticationStorage,
+        target: &UploadTarget,
+    ) -> miette::Result<Authentication> {
+        let authentication = match &target.credentials {
+            Some(credentials) => credentials.clone(),
+            None => stored_authentication(storage, &target.url, "artifactory")?,
+        };
+        match authentication {
+            Authentication::BasicHTTP { .. } => Ok(authentication),
+            _ => Err(miette::miette!("A username and password is required for authentication with artifactory.
+                            Authentication information found in the keychain / auth file, but it was not a username and password")),
+        }
+    }
+
+    fn upload<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            upload_to_subdir(
+                context,
+                target,
+                authentication,
+                package_file,
+                package_file,
+                None,
+            )
+            .await
+        })
+    }
+
+    fn upload_attachment<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+        attachment: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<bool>> {
+        Box::pin(async move {
+            let file_name = attachment
+                .file_name()
+                .ok_or_else(|| miette::miette!("{} has no file name", attachment.display()))?
+                .to_string_lossy()
+                .to_string();
+            upload_to_subdir(
+                context,
+                target,
+                authentication,
+                package_file,
+                attachment,
+                Some(&file_name),
+            )
+            .await?;
+            Ok(true)
+        })
+    }
+}
+
+/// Upload a file to the subdirectory of the package file in the channel, with the given
+/// file name or the file name of the package.
+async fn upload_to_subdir(
+    context: &UploadContext,
+    target: &UploadTarget,
+    authentication: &Authentication,
+    package_file: &Path,
+    file: &Path,
+    file_name: Option<&str>,
+) -> miette::Result<()> {
+    let Authentication::BasicHTTP { username, password } = authentication else {
+        return Err(miette::miette!(
+            "A username and password is required for artifactory"
+        ));
+    };
+
+    let package = ExtractedPackage::from_package_file(package_file)?;
+
+    let subdir = package.subdir().ok_or_else(|| {
+        miette::miette!(
+            "index.json of package {} has no subdirectory. Cannot determine which directory to upload to",
+            package_file.display()
+        )
+    })?;
+
+    let package_name = package.filename().ok_or(miette::miette!(
+        "Package file {} has no filename",
+        package_file.display()
+    ))?;
+
+    let upload_url = target
+        .url
+        .join(&format!(
+            "{}/{}/{}",
+            target.channel,
+            subdir,
+            file_name.unwrap_or(package_name)
+        ))
+        .into_diagnostic()?;
+
+    let prepared_request = context
+        .client()
+        .request(Method::PUT, upload_url)
+        .basic_auth(username.clone(), Some(password.clone()));
+
+    context.send_request(prepared_request, file).await?;
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! The `UploadBackend` trait and the registry of the available backends.
+//!
+//! A backend knows how to talk to one kind of server: it resolves the server url and
+//! channel from a url, finds the credentials and uploads a single package file. The
+//! retries, progress bars and parallel uploads are handled by
+//! [`upload_packages`](super::upload_packages) for all backends alike.
+
+use std::{path::Path, sync::Arc};
+
+use futures::future::BoxFuture;
+use rattler_networking::{Authentication, AuthenticationStorage};
+use url::Url;
+
+use super::UploadContext;
+use crate::tool_configuration::InputRequired;
+
+/// The server and channel that packages are uploaded to.
+#[derive(Debug, Clone)]
+pub struct UploadTarget {
+    /// The url of the server
+    pub url: Url,
+    /// The channel on the server (the owner for anaconda.org)
+    pub channel: String,
+    /// Credentials that were given explicitly (e.g. on the command line). If not set, the
+    /// credentials are read from the keychain / auth-file.
+    pub credentials: Option<Authentication>,
+}
+
+impl UploadTarget {
+    /// Create a target for the given server url and channel.
+    pub fn new(url: Url, channel: impl Into<String>) -> Self {
+        Self {
+            url,
+            channel: channel.into(),
+            credentials: None,
+        }
+    }
+
+    /// Use the given credentials instead of the ones from the keychain / auth-file.
+    pub fn with_credentials(self, credentials: Option<Authentication>) -> Self {
+        Self {
+            credentials,
+            ..self
+        }
+    }
+
+    /// Split a url like `https://example.com/my-channel` into the server url and the
+    /// channel (the last path segment). The scheme may carry the name of a backend, e.g.
+    /// `quetz+https://example.com/my-channel`, which is removed.
+    pub fn from_url(url: &Url) -> Option<Self> {
+        let scheme = url
+            .scheme()
+            .split_once('+')
+            .map_or(url.scheme(), |(_, scheme)| scheme);
+        let path = url.path().trim_end_matches('/');
+        let (server_path, channel) = path.rsplit_once('/')?;
+        if channel.is_empty() {
+            return None;
+        }
+        let server = format!(
+            "{}://{}{}/",
+            scheme,
+            &url[url::Position::BeforeUsername..url::Position::AfterPort],
+            server_path
+        );
+        Some(Self::new(Url::parse(&server).ok()?, channel))
+    }
+}
+
+/// The name of the backend in the scheme of a url (e.g. `quetz` in
+/// `quetz+https://example.com/my-channel`).
+pub fn scheme_backend(url: &Url) -> Option<&str> {
+    url.scheme().split_once('+').map(|(backend, _)| backend)
+}
+
+/// Look up the credentials for a url in the keychain / auth-file.
+pub fn stored_authentication(
+    storage: &AuthenticationStorage,
+    url: &Url,
+    server: &str,
+) -> miette::Result<Authentication> {
+    match storage.get_by_url(url.clone()) {
+        Ok((_, Some(authentication))) => Ok(authentication),
+        Ok((_, None)) => Err(InputRequired {
+            input: format!("credentials for {server}"),
+            reason: format!("no credentials for {url} were given and none were found in the keychain / auth file"),
+            help: "pass an API key on the command line or store the credentials with `rattler-build auth login`".to_string(),
+        }
+        .into()),
+        Err(e) => Err(miette::miette!(
+            "Failed to get authentication information form keychain: {e}"
+        )),
+    }
+}
+
+/// A kind of server that packages can be uploaded to.
+pub trait UploadBackend: Send + Sync {
+    /// The name of the backend (e.g. `quetz`), also used as the scheme prefix of urls
+    fn name(&self) -> &str;
+
+    /// Resolve the target of an upload from a url, or `None` if the backend does not
+    /// handle the url. By default, urls whose scheme starts with the name of the backend
+    /// (e.g. `quetz+https://example.com/my-channel`) are handled.
+    fn resolve_target(&self, url: &Url) -> Option<UploadTarget> {
+        if scheme_backend(url) == Some(self.name()) {
+            UploadTarget::from_url(url)
+        } else {
+            None
+        }
+    }
+
+    /// The credentials to use for the target, either the ones given explicitly or the
+    /// ones from the keychain / auth-file.
+    fn authenticate(
+        &self,
+        storage: &AuthenticationStorage,
+        target: &UploadTarget,
+    ) -> miette::Result<Authentication>;
+
+    /// Upload a single package file. This is called again if the upload fails and
+    /// should be retried. Backends that upload large files in chunks can keep the state to
+    /// resume the upload in the [`UploadContext`], and should return a
+    /// [`RejectedUpload`](super::RejectedUpload) for errors that a retry cannot fix.
+    fn upload<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>>;
+
+    /// Whether uploading a package file again after a failed attempt is safe, e.g. a `PUT`
+    /// to the path of the package. Uploads that are not idempotent are only retried if the
+    /// request did not reach the server (see [`ConnectionFailed`](super::ConnectionFailed)).
+    /// Defaults to `false`.
+    fn idempotent_upload(&self) -> bool {
+        false
+    }
+
+    /// Clean up after the last attempt to upload a package file failed, e.g. abort the
+    /// multipart upload whose state is kept in the [`UploadContext`], so that the server
+    /// does not keep the uploaded parts. Does nothing by default.
+    fn abort<'a>(
+        &'a self,
+        _context: &'a UploadContext,
+        _target: &'a UploadTarget,
+        _authentication: &'a Authentication,
+        _package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Upload a file that belongs to a package file (e.g. its provenance statement) next
+    /// to it. Returns `false` if the backend cannot store such files, which is the
+    /// default.
+    fn upload_attachment<'a>(
+        &'a self,
+        _context: &'a UploadContext,
+        _target: &'a UploadTarget,
+        _authentication: &'a Authentication,
+        _package_file: &'a Path,
+        _attachment: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<bool>> {
+        Box::pin(async { Ok(false) })
+    }
+
+    /// Check that a package file was uploaded correctly. Does nothing by default.
+    fn verify<'a>(
+        &'a self,
+        _context: &'a UploadContext,
+        _target: &'a UploadTarget,
+        _authentication: &'a Authentication,
+        _package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The registry of upload backends. Library users can register their own backends
+/// next to the built-in ones.
+#[derive(Default, Clone)]
+pub struct UploadBackendRegistry {
+    backends: Vec<Arc<dyn UploadBackend>>,
+}
+
+impl UploadBackendRegistry {
+    /// A registry with the built-in backends (quetz, artifactory, prefix.dev,
+    /// anaconda.org, OCI registries and S3 buckets).
+    pub fn with_default_backends() -> Self {
+        let mut registry = Self::default();
+        registry.register(super::quetz::Quetz);
+        registry.register(super::artifactory::Artifactory);
+        registry.register(super::prefix::Prefix);
+        registry.register(super::anaconda::AnacondaBackend::default());
+        registry.register(super::oci::Oci);
+        registry.register(super::s3::S3::default());
+        registry
+    }
+
+    /// Register a backend. A backend with the same name replaces the existing one.
+    pub fn register(&mut self, backend: impl UploadBackend + 'static) {
+        self.backends
+            .retain(|existing| existing.name() != backend.name());
+        self.backends.push(Arc::new(backend));
+    }
+
+    /// The backend with the given name.
+    pub fn get(&self, name: &str) -> Option<Arc<dyn UploadBackend>> {
+        self.backends
+            .iter()
+            .find(|backend| backend.name() == name)
+            .cloned()
+    }
+
+    /// The first backend that handles the url, together with the resolved target.
+    pub fn resolve(&self, url: &Url) -> Option<(Arc<dyn UploadBackend>, UploadTarget)> {
+        self.backends.iter().find_map(|backend| {
+            backend
+                .resolve_target(url)
+                .map(|target| (backend.clone(), target))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_target() {
+        let registry = UploadBackendRegistry::with_default_backends();
+
+        let url = Url::parse("quetz+https://quetz.example.com/api/my-channel").unwrap();
+        let (backend, target) = registry.resolve(&url).unwrap();
+        assert_eq!(backend.name(), "quetz");
+        assert_eq!(target.url.as_str(), "https://quetz.example.com/api/");
+        assert_eq!(target.channel, "my-channel");
+
+        let url = Url::parse("https://prefix.dev/my-channel").unwrap();
+        let (backend, target) = registry.resolve(&url).unwrap();
+        assert_eq!(backend.name(), "prefix");
+        assert_eq!(target.url.as_str(), "https://prefix.dev/");
+        assert_eq!(target.channel, "my-channel");
+
+        let url = Url::parse("https://anaconda.org/my-user").unwrap();
+        let (backend, target) = registry.resolve(&url).unwrap();
+        assert_eq!(backend.name(), "anaconda");
+        assert_eq!(target.url.as_str(), "https://api.anaconda.org/");
+        assert_eq!(target.channel, "my-user");
+
+        let url = Url::parse("oci://ghcr.io/my-org/my-channel").unwrap();
+        let (backend, target) = registry.resolve(&url).unwrap();
+        assert_eq!(backend.name(), "oci");
+        assert_eq!(target.channel, "my-org/my-channel");
+
+        let url = Url::parse("s3://my-bucket/my-channel").unwrap();
+        let (backend, target) = registry.resolve(&url).unwrap();
+        assert_eq!(backend.name(), "s3");
+        assert_eq!(target.channel, "my-channel");
+
+        let url = Url::parse("https://example.com/my-channel").unwrap();
+        assert!(registry.resolve(&url).is_none());
+    }
+}
@@ -8,7 +8,7 @@ use std::{
 use miette::{miette, IntoDiagnostic};
 use tracing::{debug, info};
 
-use crate::{opt::CondaForgeOpts, upload::get_default_client};
+use crate::{opt::CondaForgeOpts, tool_configuration::NetworkSettings, upload::get_default_client};
 
 use super::{
     anaconda,
@@ -47,8 +47,10 @@ async fn get_channel_target_from_variant_config(
 pub async fn upload_packages_to_conda_forge(
     opts: CondaForgeOpts,
     package_files: &Vec<PathBuf>,
+    network: &NetworkSettings,
 ) -> miette::Result<()> {
-    let anaconda = anaconda::Anaconda::new(opts.staging_token, opts.anaconda_url);
+    let anaconda = anaconda::Anaconda::new(opts.staging_token, opts.anaconda_url, network)
+        .into_diagnostic()?;
 
     let mut channels: HashMap<String, HashMap<_, _>> = HashMap::new();
 
@@ -118,7 +120,7 @@ pub async fn upload_packages_to_conda_forge(
             "provider": opts.provider
         });
 
-        let client = get_default_client().into_diagnostic()?;
+        let client = get_default_client(network).into_diagnostic()?;
 
         debug!(
             "Sending payload to validation endpoint: {}",
@@ -1,25 +1,45 @@
 //! The upload module provides the package upload functionality.
+//!
+//! Every kind of server is an [`UploadBackend`], the backends share the retries,
+//! progress bars and parallel uploads of [`upload_packages`].
 
-use crate::tool_configuration::APP_USER_AGENT;
-use futures::TryStreamExt;
-use indicatif::{style::TemplateError, HumanBytes, ProgressState};
+use crate::tool_configuration::{ClientError, NetworkSettings, APP_USER_AGENT};
+use futures::{StreamExt, TryStreamExt};
+use indicatif::{style::TemplateError, HumanBytes, MultiProgress, ProgressState};
 use std::{
+    collections::HashMap,
     fmt::Write,
+    ops::Range,
     path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio_util::io::ReaderStream;
 
 use miette::{Context, IntoDiagnostic};
+use rattler_conda_types::package::ArchiveType;
 use rattler_networking::{Authentication, AuthenticationStorage, Redact};
-use reqwest::Method;
+use reqwest::StatusCode;
 use tracing::info;
 use url::Url;
 
-use crate::upload::package::{sha256_sum, ExtractedPackage};
+pub use anaconda::AnacondaBackend;
+pub use artifactory::Artifactory;
+pub use backend::{UploadBackend, UploadBackendRegistry, UploadTarget};
+pub use oci::Oci;
+pub use prefix::Prefix;
+pub use quetz::Quetz;
+pub use s3::{RepodataUpdate, S3Config, S3};
 
 mod anaconda;
+mod artifactory;
+pub mod backend;
 pub mod conda_forge;
+mod oci;
 mod package;
+mod prefix;
+mod quetz;
+mod s3;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -42,256 +62,498 @@ fn default_bytes_style() -> Result<indicatif::ProgressStyle, TemplateError> {
             ))
 }
 
-fn get_default_client() -> Result<reqwest::Client, reqwest::Error> {
-    reqwest::Client::builder()
-        .no_gzip()
-        .user_agent(APP_USER_AGENT)
-        .build()
+/// The client for uploads, with the proxy and CA certificates of the network settings.
+fn get_default_client(network: &NetworkSettings) -> Result<reqwest::Client, ClientError> {
+    Ok(network
+        .apply(
+            reqwest::Client::builder()
+                .no_gzip()
+                .user_agent(APP_USER_AGENT),
+        )?
+        .build()?)
 }
 
-/// Uploads package files to a Quetz server.
-pub async fn upload_package_to_quetz(
-    storage: &AuthenticationStorage,
-    api_key: Option<String>,
-    package_files: &Vec<PathBuf>,
-    url: Url,
-    channel: String,
-) -> miette::Result<()> {
-    let token = match api_key {
-        Some(api_key) => api_key,
-        None => match storage.get_by_url(url.clone()) {
-            Ok((_, Some(Authentication::CondaToken(token)))) => token,
-            Ok((_, Some(_))) => {
-                return Err(miette::miette!("A Conda token is required for authentication with quetz.
-                        Authentication information found in the keychain / auth file, but it was not a Conda token"));
-            }
-            Ok((_, None)) => {
-                return Err(miette::miette!(
-                    "No quetz api key was given and none was found in the keychain / auth file"
-                ));
-            }
-            Err(e) => {
-                return Err(miette::miette!(
-                    "Failed to get authentication information form keychain: {e}"
-                ));
-            }
+/// The default size of the chunks that large packages are uploaded in (by backends that
+/// support chunked uploads).
+pub const DEFAULT_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Settings that are shared by the uploads of all backends.
+#[derive(Debug, Clone)]
+pub struct UploadSettings {
+    /// How often a failed upload of a package is retried
+    pub retries: u32,
+    /// How many packages are uploaded at the same time
+    pub parallelism: usize,
+    /// The size of the chunks that large packages are uploaded in
+    pub chunk_size: u64,
+    /// The proxy and CA certificates of the requests
+    pub network: NetworkSettings,
+}
+
+impl Default for UploadSettings {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            parallelism: 1,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            network: NetworkSettings::default(),
+        }
+    }
+}
+
+/// The server rejected an upload with a response that will not change when the upload
+/// is retried (e.g. `401 Unauthorized` or `409 Conflict`).
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("the server rejected the upload ({status}): {body}")]
+pub struct RejectedUpload {
+    /// The status of the response
+    pub status: StatusCode,
+    /// The body of the response
+    pub body: String,
+}
+
+/// No connection to the server could be made, so the request did not reach it and can be
+/// sent again, even if it is not idempotent.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("failed to connect to the server")]
+pub struct ConnectionFailed(#[source] pub reqwest::Error);
+
+/// Whether a request that failed with the status may succeed when it is retried.
+pub fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Return an error if the response has an error status: a [`RejectedUpload`] if retrying
+/// will not help, a plain error otherwise.
+pub async fn check_response(response: reqwest::Response) -> miette::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() || status.is_redirection() {
+        return Ok(response);
+    }
+    if is_transient(status) {
+        return response
+            .error_for_status()
+            .map_err(|e| e.redact())
+            .into_diagnostic()
+            .wrap_err("Server responded with error");
+    }
+    let body = response.text().await.unwrap_or_default();
+    let body = body.trim();
+    Err(RejectedUpload {
+        status,
+        body: if body.is_empty() {
+            "(empty response)".to_string()
+        } else {
+            body.chars().take(500).collect()
         },
+    }
+    .into())
+}
+
+/// Send a request and check its response (see [`check_response`]). A request that could not
+/// be sent because no connection could be made fails with [`ConnectionFailed`].
+pub async fn send(prepared_request: reqwest::RequestBuilder) -> miette::Result<reqwest::Response> {
+    match prepared_request.send().await {
+        Ok(response) => check_response(response).await,
+        Err(e) if e.is_connect() => Err(ConnectionFailed(e.redact()).into()),
+        Err(e) => Err(e.redact()).into_diagnostic(),
+    }
+}
+
+/// Whether a failed upload is retried: uploads that the server rejected are not, and
+/// uploads that are not idempotent only if the request did not reach the server.
+fn is_retryable(backend: &dyn UploadBackend, error: &miette::Report) -> bool {
+    if error.downcast_ref::<RejectedUpload>().is_some() {
+        return false;
+    }
+    backend.idempotent_upload() || error.downcast_ref::<ConnectionFailed>().is_some()
+}
+
+/// The delay before the `attempt`-th retry: it doubles with every attempt (up to a minute)
+/// and half of it is random, so that uploads that failed at the same time are not retried
+/// at the same time.
+fn backoff(attempt: u32) -> Duration {
+    let max = Duration::from_secs(1 << attempt.clamp(1, 6));
+    let random = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as f64
+        / 1e9;
+    max.mul_f64(0.5 + random / 2.0)
+}
+
+/// An empty `repodata.json` for a subdirectory.
+fn empty_repodata(subdir: &str) -> serde_json::Value {
+    serde_json::json!({
+        "info": { "subdir": subdir },
+        "packages": {},
+        "packages.conda": {},
+        "repodata_version": 1,
+    })
+}
+
+/// Add the record of a package to a `repodata.json` (for the backends that store the
+/// repodata of a channel themselves).
+fn add_to_repodata(
+    repodata: &mut serde_json::Value,
+    package_file: &Path,
+    file_name: &str,
+) -> miette::Result<()> {
+    let (record, section) = match ArchiveType::try_from(package_file) {
+        Some(ArchiveType::Conda) => (
+            rattler_index::package_record_from_conda(package_file),
+            "packages.conda",
+        ),
+        Some(ArchiveType::TarBz2) => (
+            rattler_index::package_record_from_tar_bz2(package_file),
+            "packages",
+        ),
+        None => miette::bail!("{} is not a conda package", package_file.display()),
     };
+    let record = serde_json::to_value(record.into_diagnostic()?).into_diagnostic()?;
+    if !repodata[section].is_object() {
+        repodata[section] = serde_json::json!({});
+    }
+    repodata[section][file_name] = record;
+    Ok(())
+}
 
-    let client = get_default_client().into_diagnostic()?;
+/// The HTTP client and progress bars that are shared by the uploads.
+pub struct UploadContext {
+    client: reqwest::Client,
+    network: NetworkSettings,
+    progress_bars: MultiProgress,
+    chunk_size: u64,
+    resume_state: Mutex<HashMap<PathBuf, String>>,
+}
 
-    for package_file in package_files {
-        let upload_url = url
-            .join(&format!(
-                "api/channels/{}/upload/{}",
-                channel,
-                package_file.file_name().unwrap().to_string_lossy()
-            ))
-            .into_diagnostic()?;
+impl UploadContext {
+    /// Create a new context with a client that uses the proxy and CA certificates of the
+    /// network settings.
+    pub fn new(network: &NetworkSettings) -> miette::Result<Self> {
+        Ok(Self {
+            client: get_default_client(network).into_diagnostic()?,
+            network: network.clone(),
+            progress_bars: MultiProgress::new(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            resume_state: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Use chunks of the given size for chunked uploads.
+    pub fn with_chunk_size(self, chunk_size: u64) -> Self {
+        Self {
+            chunk_size: chunk_size.max(1),
+            ..self
+        }
+    }
+
+    /// The client to create requests with.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// The network settings, for backends that create their own clients.
+    pub fn network(&self) -> &NetworkSettings {
+        &self.network
+    }
+
+    /// The size of the chunks that large files are uploaded in.
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
 
-        let hash = sha256_sum(package_file).into_diagnostic()?;
+    /// The state that a backend stored to resume the upload of a file when the upload is
+    /// retried (e.g. the id of a multipart upload).
+    pub fn resume_state(&self, file: &Path) -> Option<String> {
+        self.resume_state.lock().unwrap().get(file).cloned()
+    }
 
-        let prepared_request = client
-            .request(Method::POST, upload_url)
-            .query(&[("force", "false"), ("sha256", &hash)])
-            .header("X-API-Key", token.clone());
+    /// Store (or with `None`, remove) the state to resume the upload of a file.
+    pub fn set_resume_state(&self, file: &Path, state: Option<String>) {
+        let mut resume_state = self.resume_state.lock().unwrap();
+        match state {
+            Some(state) => resume_state.insert(file.to_path_buf(), state),
+            None => resume_state.remove(file),
+        };
+    }
 
-        send_request(prepared_request, package_file).await?;
+    /// Send a prepared request with the package file as the body (with a progress bar).
+    pub async fn send_request(
+        &self,
+        prepared_request: reqwest::RequestBuilder,
+        package_file: &Path,
+    ) -> miette::Result<reqwest::Response> {
+        send_request(&self.progress_bars, prepared_request, package_file).await
     }
 
-    info!("Packages successfully uploaded to Quetz server");
+    /// A progress bar for the chunked upload of a file, starting at `position`.
+    pub fn progress_bar(
+        &self,
+        file: &Path,
+        size: u64,
+        position: u64,
+    ) -> miette::Result<indicatif::ProgressBar> {
+        info!(
+            "Uploading package file: {} ({}) in chunks of {}{}\n",
+            file.file_name().unwrap_or_default().to_string_lossy(),
+            HumanBytes(size),
+            HumanBytes(self.chunk_size),
+            if position > 0 {
+                format!(", resuming at {}", HumanBytes(position))
+            } else {
+                String::new()
+            }
+        );
+        let progress_bar = self.progress_bars.add(
+            indicatif::ProgressBar::new(size)
+                .with_prefix("Uploading")
+                .with_style(default_bytes_style().into_diagnostic()?),
+        );
+        progress_bar.set_position(position);
+        Ok(progress_bar)
+    }
+
+    /// Send a prepared request with a range of the file as the body.
+    pub async fn send_chunk(
+        &self,
+        prepared_request: reqwest::RequestBuilder,
+        file: &Path,
+        range: Range<u64>,
+        progress_bar: &indicatif::ProgressBar,
+    ) -> miette::Result<reqwest::Response> {
+        let chunk = read_chunk(file, range).await.into_diagnostic()?;
+        let len = chunk.len() as u64;
+        let response = send(
+            prepared_request
+                .header(reqwest::header::CONTENT_LENGTH, len)
+                .body(chunk),
+        )
+        .await?;
+        progress_bar.inc(len);
+        Ok(response)
+    }
+}
+
+/// Read a range of a file.
+pub async fn read_chunk(file: &Path, range: Range<u64>) -> std::io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let mut file = tokio::fs::File::open(file).await?;
+    file.seek(std::io::SeekFrom::Start(range.start)).await?;
+    let mut chunk = Vec::with_capacity((range.end - range.start) as usize);
+    file.take(range.end - range.start)
+        .read_to_end(&mut chunk)
+        .await?;
+    Ok(chunk)
+}
+
+/// Upload package files with the given backend. The credentials are resolved once, every
+/// package is then uploaded and verified. A failed upload is retried with an increasing,
+/// jittered delay, unless the server rejected it (see [`RejectedUpload`]) or the upload is
+/// not idempotent (see [`UploadBackend::idempotent_upload`]) and the request reached the
+/// server. Backends that upload in chunks resume where the previous attempt stopped, and
+/// abort the upload after the last attempt failed (see [`UploadBackend::abort`]). Up to
+/// `settings.parallelism` packages are uploaded at the same time.
+pub async fn upload_packages(
+    backend: &dyn UploadBackend,
+    storage: &AuthenticationStorage,
+    target: &UploadTarget,
+    package_files: &[PathBuf],
+    settings: &UploadSettings,
+) -> miette::Result<()> {
+    let authentication = backend.authenticate(storage, target)?;
+    let context = UploadContext::new(&settings.network)?.with_chunk_size(settings.chunk_size);
+
+    futures::stream::iter(package_files)
+        .map(|package_file| {
+            let context = &context;
+            let authentication = &authentication;
+            async move {
+                let mut attempt = 0;
+                loop {
+                    match backend
+                        .upload(context, target, authentication, package_file)
+                        .await
+                    {
+                        Ok(()) => break,
+                        Err(e) if attempt < settings.retries && is_retryable(backend, &e) => {
+                            attempt += 1;
+                            let delay = backoff(attempt);
+                            tracing::warn!(
+                                "Uploading {} failed ({e}), retrying in {:.1}s ({attempt}/{})",
+                                package_file.display(),
+                                delay.as_secs_f64(),
+                                settings.retries
+                            );
+                            tokio::time::sleep(delay).await;
+                        }
+                        Err(e) => {
+                            if let Err(abort_error) = backend
+                                .abort(context, target, authentication, package_file)
+                                .await
+                            {
+                                tracing::warn!(
+                                    "Failed to abort the upload of {} ({abort_error})",
+                                    package_file.display()
+                                );
+                            }
+                            context.set_resume_state(package_file, None);
+                            return Err(
+                                e.wrap_err(format!("failed to upload {}", package_file.display()))
+                            );
+                        }
+                    }
+                }
+                backend
+                    .verify(context, target, authentication, package_file)
+                    .await
+            }
+        })
+        .buffer_unordered(settings.parallelism.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    info!(
+        "Packages successfully uploaded to {} ({})",
+        target.url,
+        backend.name()
+    );
 
     Ok(())
 }
 
+/// Uploads package files to a Quetz server.
+#[deprecated(note = "use `upload_packages` with the `Quetz` backend")]
+pub async fn upload_package_to_quetz(
+    storage: &AuthenticationStorage,
+    api_key: Option<String>,
+    package_files: &[PathBuf],
+    url: Url,
+    channel: String,
+) -> miette::Result<()> {
+    let target =
+        UploadTarget::new(url, channel).with_credentials(api_key.map(Authentication::CondaToken));
+    upload_packages(
+        &Quetz,
+        storage,
+        &target,
+        package_files,
+        &UploadSettings::default(),
+    )
+    .await
+}
+
 /// Uploads package files to an Artifactory server.
+#[deprecated(note = "use `upload_packages` with the `Artifactory` backend")]
 pub async fn upload_package_to_artifactory(
     storage: &AuthenticationStorage,
     username: Option<String>,
     password: Option<String>,
-    package_files: &Vec<PathBuf>,
+    package_files: &[PathBuf],
     url: Url,
     channel: String,
 ) -> miette::Result<()> {
-    let (username, password) = match (username, password) {
-        (Some(u), Some(p)) => (u, p),
+    let credentials = match (username, password) {
+        (Some(username), Some(password)) => Some(Authentication::BasicHTTP { username, password }),
         (Some(_), _) | (_, Some(_)) => {
             return Err(miette::miette!("A username and password is required for authentication with artifactory, only one was given"));
         }
-        _ => match storage.get_by_url(url.clone()) {
-            Ok((_, Some(Authentication::BasicHTTP { username, password }))) => (username, password),
-            Ok((_, Some(_))) => {
-                return Err(miette::miette!("A username and password is required for authentication with artifactory.
-                            Authentication information found in the keychain / auth file, but it was not a username and password"));
-            }
-            Ok((_, None)) => {
-                return Err(miette::miette!(
-                        "No username and password was given and none was found in the keychain / auth file"
-                    ));
-            }
-            Err(e) => {
-                return Err(miette::miette!(
-                    "Failed to get authentication information form keychain: {e}"
-                ));
-            }
-        },
+        _ => None,
     };
-
-    for package_file in package_files {
-        let package = ExtractedPackage::from_package_file(package_file)?;
-
-        let subdir = package.subdir().ok_or_else(|| {
-            miette::miette!(
-                "index.json of package {} has no subdirectory. Cannot determine which directory to upload to",
-                package_file.display()
-            )
-        })?;
-
-        let package_name = package.filename().ok_or(miette::miette!(
-            "Package file {} has no filename",
-            package_file.display()
-        ))?;
-
-        let client = get_default_client().into_diagnostic()?;
-
-        let upload_url = url
-            .join(&format!("{}/{}/{}", channel, subdir, package_name))
-            .into_diagnostic()?;
-
-        let prepared_request = client
-            .request(Method::PUT, upload_url)
-            .basic_auth(username.clone(), Some(password.clone()));
-
-        send_request(prepared_request, package_file).await?;
-    }
-
-    info!("Packages successfully uploaded to Artifactory server");
-
-    Ok(())
+    let target = UploadTarget::new(url, channel).with_credentials(credentials);
+    upload_packages(
+        &Artifactory,
+        storage,
+        &target,
+        package_files,
+        &UploadSettings::default(),
+    )
+    .await
 }
 
 /// Uploads package files to a prefix.dev server.
+#[deprecated(note = "use `upload_packages` with the `Prefix` backend")]
 pub async fn upload_package_to_prefix(
     storage: &AuthenticationStorage,
     api_key: Option<String>,
-    package_files: &Vec<PathBuf>,
+    package_files: &[PathBuf],
     url: Url,
     channel: String,
 ) -> miette::Result<()> {
-    let token = match api_key {
-        Some(api_key) => api_key,
-        None => match storage.get_by_url(url.clone()) {
-            Ok((_, Some(Authentication::BearerToken(token)))) => token,
-            Ok((_, Some(_))) => {
-                return Err(miette::miette!("A Conda token is required for authentication with prefix.dev.
-                        Authentication information found in the keychain / auth file, but it was not a Bearer token"));
-            }
-            Ok((_, None)) => {
-                return Err(miette::miette!(
-                    "No prefix.dev api key was given and none was found in the keychain / auth file"
-                ));
-            }
-            Err(e) => {
-                return Err(miette::miette!(
-                    "Failed to get authentication information form keychain: {e}"
-                ));
-            }
-        },
-    };
-
-    for package_file in package_files {
-        let filename = package_file
-            .file_name()
-            .expect("no filename found")
-            .to_string_lossy()
-            .to_string();
-
-        let file_size = package_file.metadata().into_diagnostic()?.len();
-
-        let url = url
-            .join(&format!("api/v1/upload/{}", channel))
-            .into_diagnostic()?;
-
-        let client = get_default_client().into_diagnostic()?;
-
-        let hash = sha256_sum(package_file).into_diagnostic()?;
-
-        let prepared_request = client
-            .post(url.clone())
-            .header("X-File-Sha256", hash)
-            .header("X-File-Name", filename)
-            .header("Content-Length", file_size)
-            .header("Content-Type", "application/octet-stream")
-            .bearer_auth(token.clone());
-
-        send_request(prepared_request, package_file).await?;
-    }
-
-    info!("Packages successfully uploaded to prefix.dev server");
-
-    Ok(())
+    let target =
+        UploadTarget::new(url, channel).with_credentials(api_key.map(Authentication::BearerToken));
+    upload_packages(
+        &Prefix,
+        storage,
+        &target,
+        package_files,
+        &UploadSettings::default(),
+    )
+    .await
 }
 
 /// Uploads package files to an Anaconda server.
+#[deprecated(note = "use `upload_packages` with the `AnacondaBackend` backend")]
 pub async fn upload_package_to_anaconda(
     storage: &AuthenticationStorage,
     token: Option<String>,
-    package_files: &Vec<PathBuf>,
+    package_files: &[PathBuf],
     url: Url,
     owner: String,
     channels: Vec<String>,
     force: bool,
 ) -> miette::Result<()> {
-    let token = match token {
-        Some(token) => token,
-        None => match storage.get("anaconda.org") {
-            Ok(Some(Authentication::CondaToken(token))) => token,
-            Ok(Some(_)) => {
-                return Err(miette::miette!(
-                    "A Conda token is required for authentication with anaconda.org.
-                        Authentication information found in the keychain / auth file, but it was not a Conda token.
-                        Please create a token on anaconda.org"
-                ));
-            }
-            Ok(None) => {
-                return Err(miette::miette!(
-                    "No anaconda.org api key was given and no token were found in the keychain / auth file. Please create a token on anaconda.org"
-                ));
-            }
-            Err(e) => {
-                return Err(miette::miette!(
-                    "Failed to get authentication information form keychain: {e}"
-                ));
-            }
-        },
+    let backend = AnacondaBackend {
+        labels: channels,
+        force,
     };
+    let target =
+        UploadTarget::new(url, owner).with_credentials(token.map(Authentication::CondaToken));
+    upload_packages(
+        &backend,
+        storage,
+        &target,
+        package_files,
+        &UploadSettings::default(),
+    )
+    .await
+}
 
-    let anaconda = anaconda::Anaconda::new(token, url);
-
-    for package_file in package_files {
-        loop {
-            let package = package::ExtractedPackage::from_package_file(package_file)?;
-
-            anaconda.create_or_update_package(&owner, &package).await?;
-
-            anaconda.create_or_update_release(&owner, &package).await?;
-
-            let successful = anaconda
-                .upload_file(&owner, &channels, force, &package)
-                .await?;
-
-            // When running with --force and experiencing a conflict error, we delete the conflicting file.
-            // Anaconda automatically deletes releases / packages when the deletion of a file would leave them empty.
-            // Therefore, we need to ensure that the release / package still exists before trying to upload again.
-            if successful {
-                break;
-            }
+/// Upload files that belong to package files (e.g. provenance statements) next to the
+/// packages. Backends that cannot store such files are skipped with a warning.
+pub async fn upload_attachments(
+    backend: &dyn UploadBackend,
+    storage: &AuthenticationStorage,
+    target: &UploadTarget,
+    attachments: &[(PathBuf, PathBuf)],
+    settings: &UploadSettings,
+) -> miette::Result<()> {
+    if attachments.is_empty() {
+        return Ok(());
+    }
+    let authentication = backend.authenticate(storage, target)?;
+    let context = UploadContext::new(&settings.network)?;
+
+    for (package_file, attachment) in attachments {
+        let uploaded = backend
+            .upload_attachment(&context, target, &authentication, package_file, attachment)
+            .await
+            .wrap_err_with(|| format!("failed to upload {}", attachment.display()))?;
+        if !uploaded {
+            tracing::warn!(
+                "The {} backend cannot store {} next to the package, skipping it",
+                backend.name(),
+                attachment.display()
+            );
         }
     }
     Ok(())
 }
 
 async fn send_request(
+    progress_bars: &MultiProgress,
     prepared_request: reqwest::RequestBuilder,
     package_file: &Path,
 ) -> miette::Result<reqwest::Response> {
@@ -308,9 +570,11 @@ async fn send_request(
             .to_string_lossy(),
         HumanBytes(file_size)
     );
-    let progress_bar = indicatif::ProgressBar::new(file_size)
-        .with_prefix("Uploading")
-        .with_style(default_bytes_style().into_diagnostic()?);
+    let progress_bar = progress_bars.add(
+        indicatif::ProgressBar::new(file_size)
+            .with_prefix("Uploading")
+            .with_style(default_bytes_style().into_diagnostic()?),
+    );
 
     let progress_bar_clone = progress_bar.clone();
     let reader_stream = ReaderStream::new(file)
@@ -323,18 +587,7 @@ async fn send_request(
 
     let body = reqwest::Body::wrap_stream(reader_stream);
 
-    let response = prepared_request
-        .body(body)
-        .send()
-        .await
-        .map_err(|e| e.redact())
-        .into_diagnostic()?;
-
-    response
-        .error_for_status_ref()
-        .map_err(|e| e.redact())
-        .into_diagnostic()
-        .wrap_err("Server responded with error")?;
+    let response = send(prepared_request.body(body)).await?;
 
     progress_bar.finish();
     info!(
@@ -347,3 +600,21 @@ async fn send_request(
 
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        for attempt in 1..10 {
+            let max = Duration::from_secs(1 << attempt.min(6));
+            let delay = backoff(attempt);
+            assert!(delay >= max / 2 && delay <= max, "{delay:?} for {attempt}");
+        }
+        assert!(is_transient(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient(StatusCode::CONFLICT));
+        assert!(!is_transient(StatusCode::UNAUTHORIZED));
+    }
+}
@@ -0,0 +1,862 @@
+//! Uploading packages as OCI artifacts to a container registry (e.g. GHCR).
+//!
+//! The packages follow the layout of conda channels on OCI registries (as used by
+//! `conda-oci-mirror` and pixi): a channel `oci://ghcr.io/my-org/my-channel` stores the
+//! package `zlib-1.3.1-h4ab18f5_1.conda` for `linux-64` in the repository
+//! `my-org/my-channel/linux-64/zlib` with the tag `1.3.1-h4ab18f5_1`. The manifest has the
+//! package archive and its `info/index.json` as layers.
+//!
+//! The `repodata.json` of a subdirectory is the artifact
+//! `my-org/my-channel/linux-64/repodata.json:latest`. Every upload adds its package to it
+//! (read, modify and write). If another upload changed the repodata in the meantime (the
+//! digest of its manifest changed), the update is retried with the new repodata.
+//!
+//! The credentials are taken from the command line, the docker configuration
+//! (`~/.docker/config.json`, including credential helpers) or the keychain / auth-file.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use base64::{engine::general_purpose, Engine};
+use futures::future::BoxFuture;
+use miette::{Context, IntoDiagnostic};
+use rattler_conda_types::package::ArchiveType;
+use rattler_digest::{compute_bytes_digest, compute_file_digest, Sha256};
+use rattler_networking::{Authentication, AuthenticationStorage};
+use reqwest::{header, Method, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use super::{
+    add_to_repodata,
+    backend::{UploadBackend, UploadTarget},
+    check_response, empty_repodata,
+    package::ExtractedPackage,
+    UploadContext,
+};
+use crate::source::oci_source::parse_challenge;
+
+/// The media type of the (empty) config of a package manifest.
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.unknown.config.v1+json";
+
+/// The media type of the `info/index.json` layer.
+const INDEX_MEDIA_TYPE: &str = "application/vnd.conda.info.index.v1+json";
+
+/// The annotation with the file name of a layer.
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// The media type of the `repodata.json` layer.
+const REPODATA_MEDIA_TYPE: &str = "application/vnd.conda.repodata.v1+json";
+
+/// The tag of the current `repodata.json` of a subdirectory.
+const REPODATA_TAG: &str = "latest";
+
+/// How often the repodata is read and written again when another upload changed it.
+const REPODATA_ATTEMPTS: u32 = 5;
+
+/// The media type of the manifests.
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// The backend for OCI registries, for urls like `oci://ghcr.io/my-org/my-channel`.
+pub struct Oci;
+
+/// The name of the repository of a package: names that start with `_` are prefixed
+/// with `zzz`, since repository names must start with a letter or digit.
+pub fn repository_name(name: &str) -> String {
+    if name.starts_with('_') {
+        format!("zzz{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// The tag of a package (`<version>-<build>`), with the characters that are not allowed
+/// in tags replaced.
+pub fn package_tag(version: &str, build: &str) -> String {
+    format!("{}-{}", version, build)
+        .replace('+', "__p__")
+        .replace('!', "__e__")
+        .replace('=', "__eq__")
+}
+
+/// The docker configuration (`~/.docker/config.json`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    #[serde(default)]
+    auths: BTreeMap<String, DockerAuth>,
+    #[serde(default)]
+    creds_store: Option<String>,
+    #[serde(default)]
+    cred_helpers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DockerAuth {
+    #[serde(default)]
+    auth: Option<String>,
+}
+
+/// The response of a docker credential helper.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+/// The keys of a registry in the docker configuration.
+fn docker_config_keys(registry: &str) -> Vec<String> {
+    match registry {
+        "docker.io" | "registry-1.docker.io" | "index.docker.io" => vec![
+            "https://index.docker.io/v1/".to_string(),
+            "docker.io".to_string(),
+        ],
+        registry => vec![registry.to_string(), format!("https://{}", registry)],
+    }
+}
+
+/// Run `docker-credential-<helper> get` for a registry.
+fn credentials_from_helper(helper: &str, registry: &str) -> Option<Authentication> {
+    let mut child = Command::new(format!("docker-credential-{}", helper))
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(registry.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let credentials: HelperCredentials = serde_json::from_slice(&output.stdout).ok()?;
+    Some(Authentication::BasicHTTP {
+        username: credentials.username,
+        password: credentials.secret,
+    })
+}
+
+/// Look up the credentials of a registry in a docker configuration.
+fn credentials_from_docker_config(config: &DockerConfig, registry: &str) -> Option<Authentication> {
+    let keys = docker_config_keys(registry);
+    if let Some(helper) = keys.iter().find_map(|key| config.cred_helpers.get(key)) {
+        return credentials_from_helper(helper, &keys[0]);
+    }
+    let encoded = keys
+        .iter()
+        .find_map(|key| config.auths.get(key)?.auth.as_ref());
+    if let Some(encoded) = encoded {
+        let decoded = general_purpose::STANDARD.decode(encoded).ok()?;
+        let (username, password) = String::from_utf8(decoded)
+            .ok()?
+            .split_once(':')
+            .map(|(username, password)| (username.to_string(), password.to_string()))?;
+        return Some(Authentication::BasicHTTP { username, password });
+    }
+    let store = config.creds_store.as_ref()?;
+    credentials_from_helper(store, &keys[0])
+}
+
+/// Read the docker configuration from `$DOCKER_CONFIG/config.json` or
+/// `~/.docker/config.json`.
+fn docker_config() -> Option<DockerConfig> {
+    let dir = std::env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".docker")))?;
+    let content = fs_err::read_to_string(dir.join("config.json")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// A blob to push, with its digest and media type.
+struct Blob<'a> {
+    media_type: &'a str,
+    digest: String,
+    size: u64,
+    title: Option<String>,
+}
+
+impl Blob<'_> {
+    fn descriptor(&self) -> serde_json::Value {
+        let mut descriptor = json!({
+            "mediaType": self.media_type,
+            "digest": self.digest,
+            "size": self.size,
+        });
+        if let Some(title) = &self.title {
+            descriptor["annotations"] = json!({ TITLE_ANNOTATION: title });
+        }
+        descriptor
+    }
+}
+
+/// A client that pushes to one repository of a registry.
+struct RegistryClient<'a> {
+    context: &'a UploadContext,
+    /// The url of the registry
+    server: &'a Url,
+    /// The registry host (with an optional port)
+    registry: String,
+    repository: String,
+    credentials: &'a Authentication,
+    token: Option<String>,
+}
+
+impl<'a> RegistryClient<'a> {
+    /// The base url of the registry API of the repository.
+    fn base_url(&self) -> String {
+        format!("{}v2/{}", self.server, self.repository)
+    }
+
+    /// Add the token or the credentials to a request.
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match (&self.token, self.credentials) {
+            (Some(token), _) | (None, Authentication::BearerToken(token)) => {
+                request.bearer_auth(token)
+            }
+            (None, Authentication::BasicHTTP { username, password }) => {
+                request.basic_auth(username, Some(password))
+            }
+            _ => request,
+        }
+    }
+
+    /// Request a push token for the repository if the registry asks for one.
+    async fn login(&mut self) -> miette::Result<()> {
+        let response = self
+            .context
+            .client()
+            .get(format!("{}v2/", self.server))
+            .send()
+            .await
+            .into_diagnostic()?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(());
+        }
+        let Some(challenge) = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_challenge)
+        else {
+            // basic authentication, the credentials are sent with every request
+            return Ok(());
+        };
+        let realm = challenge
+            .get("realm")
+            .ok_or_else(|| miette::miette!("{} did not send a token realm", self.registry))?;
+        let mut query = vec![("scope", format!("repository:{}:pull,push", self.repository))];
+        if let Some(service) = challenge.get("service") {
+            query.push(("service", service.clone()));
+        }
+
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let mut request = self.context.client().get(realm).query(&query);
+        if let Authentication::BasicHTTP { username, password } = self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let token: TokenResponse = request
+            .send()
+            .await
+            .into_diagnostic()?
+            .error_for_status()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("failed to get a push token from {}", self.registry))?
+            .json()
+            .await
+            .into_diagnostic()?;
+        self.token = token.token.or(token.access_token);
+        Ok(())
+    }
+
+    /// Resolve the (possibly relative) `Location` of an upload session.
+    fn location(&self, response: &reqwest::Response) -> miette::Result<Url> {
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| miette::miette!("{} did not send an upload location", self.registry))?;
+        Url::parse(&self.base_url())
+            .and_then(|base| base.join(location))
+            .into_diagnostic()
+    }
+
+    /// Push a blob, unless the registry already has it. The content is either in memory
+    /// or read from a file (with a progress bar).
+    async fn push_blob(
+        &self,
+        blob: &Blob<'_>,
+        content: Result<&[u8], &Path>,
+    ) -> miette::Result<()> {
+        let exists = self
+            .authorize(self.context.client().head(format!(
+                "{}/blobs/{}",
+                self.base_url(),
+                blob.digest
+            )))
+            .send()
+            .await
+            .into_diagnostic()?;
+        if exists.status().is_success() {
+            tracing::info!("Blob {} already exists", blob.digest);
+            return Ok(());
+        }
+        if let Err(path) = content {
+            if blob.size > self.context.chunk_size() {
+                return self.push_chunked(blob, path).await;
+            }
+        }
+
+        let mut url = self.start_session().await?;
+        url.query_pairs_mut().append_pair("digest", &blob.digest);
+
+        let request = self
+            .authorize(self.context.client().request(Method::PUT, url))
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_LENGTH, blob.size);
+        match content {
+            Ok(bytes) => {
+                request
+                    .body(bytes.to_vec())
+                    .send()
+                    .await
+                    .into_diagnostic()?
+                    .error_for_status()
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("failed to upload blob {}", blob.digest))?;
+            }
+            Err(path) => {
+                self.context.send_request(request, path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start an upload session and return its location.
+    async fn start_session(&self) -> miette::Result<Url> {
+        let session = self
+            .authorize(
+                self.context
+                    .client()
+                    .post(format!("{}/blobs/uploads/", self.base_url())),
+            )
+            .header(header::CONTENT_LENGTH, 0)
+            .send()
+            .await
+            .into_diagnostic()?
+            .error_for_status()
+            .into_diagnostic()
+            .wrap_err("failed to start a blob upload")?;
+        self.location(&session)
+    }
+
+    /// Cancel an upload session, so that the registry does not keep the uploaded chunks.
+    async fn cancel_session(&self, location: Url) -> miette::Result<()> {
+        let response = self
+            .authorize(self.context.client().delete(location))
+            .send()
+            .await
+            .into_diagnostic()?;
+        // the registry may already have dropped the session
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check_response(response)
+            .await
+            .wrap_err("failed to cancel the blob upload")?;
+        Ok(())
+    }
+
+    /// The location and offset of the upload session of a previous attempt, if the
+    /// registry still has it.
+    async fn resume_session(&self, path: &Path) -> Option<(Url, u64)> {
+        let location = Url::parse(&self.context.resume_state(path)?).ok()?;
+        let response = self
+            .authorize(self.context.client().get(location))
+            .send()
+            .await
+            .ok()?;
+        if response.status() != StatusCode::NO_CONTENT {
+            return None;
+        }
+        let range = response.headers().get(header::RANGE)?.to_str().ok()?;
+        let (_, last) = range.split_once('-')?;
+        Some((
+            self.location(&response).ok()?,
+            last.parse::<u64>().ok()? + 1,
+        ))
+    }
+
+    /// Push a large blob in chunks. The upload session is stored in the resume state of
+    /// the context, so that a retry continues after the last chunk the registry received.
+    async fn push_chunked(&self, blob: &Blob<'_>, path: &Path) -> miette::Result<()> {
+        let (mut location, mut offset) = match self.resume_session(path).await {
+            Some(session) => session,
+            None => (self.start_session().await?, 0),
+        };
+        let progress_bar = self.context.progress_bar(path, blob.size, offset)?;
+        while offset < blob.size {
+            let end = (offset + self.context.chunk_size()).min(blob.size);
+            let request = self
+                .authorize(self.context.client().patch(location.clone()))
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .header(header::CONTENT_RANGE, format!("{}-{}", offset, end - 1));
+            let response = self
+                .context
+                .send_chunk(request, path, offset..end, &progress_bar)
+                .await?;
+            location = self.location(&response)?;
+            offset = end;
+            self.context
+                .set_resume_state(path, Some(location.to_string()));
+        }
+
+        location
+            .query_pairs_mut()
+            .append_pair("digest", &blob.digest);
+        let response = self
+            .authorize(self.context.client().put(location))
+            .header(header::CONTENT_LENGTH, 0)
+            .send()
+            .await
+            .into_diagnostic()?;
+        check_response(response)
+            .await
+            .wrap_err_with(|| format!("failed to upload blob {}", blob.digest))?;
+        self.context.set_resume_state(path, None);
+        progress_bar.finish();
+        Ok(())
+    }
+
+    /// Pull the manifest with the given tag and its digest, `None` if the tag does not
+    /// exist.
+    async fn pull_manifest(
+        &self,
+        tag: &str,
+    ) -> miette::Result<Option<(String, serde_json::Value)>> {
+        let response = self
+            .authorize(
+                self.context
+                    .client()
+                    .get(format!("{}/manifests/{}", self.base_url(), tag)),
+            )
+            .header(header::ACCEPT, MANIFEST_MEDIA_TYPE)
+            .send()
+            .await
+            .into_diagnostic()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check_response(response)
+            .await
+            .wrap_err_with(|| format!("failed to pull the manifest {}", tag))?;
+        let digest = response
+            .headers()
+            .get("Docker-Content-Digest")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await.into_diagnostic()?;
+        let digest =
+            digest.unwrap_or_else(|| format!("sha256:{:x}", compute_bytes_digest::<Sha256>(&body)));
+        let manifest = serde_json::from_slice(&body).into_diagnostic()?;
+        Ok(Some((digest, manifest)))
+    }
+
+    /// Pull a blob.
+    async fn pull_blob(&self, digest: &str) -> miette::Result<Vec<u8>> {
+        let response = self
+            .authorize(
+                self.context
+                    .client()
+                    .get(format!("{}/blobs/{}", self.base_url(), digest)),
+            )
+            .send()
+            .await
+            .into_diagnostic()?;
+        let bytes = check_response(response)
+            .await
+            .wrap_err_with(|| format!("failed to pull blob {}", digest))?
+            .bytes()
+            .await
+            .into_diagnostic()?;
+        Ok(bytes.to_vec())
+    }
+
+    /// Push a manifest with the given tag.
+    async fn push_manifest(&self, tag: &str, manifest: &serde_json::Value) -> miette::Result<()> {
+        self.authorize(
+            self.context
+                .client()
+                .put(format!("{}/manifests/{}", self.base_url(), tag)),
+        )
+        .header(header::CONTENT_TYPE, MANIFEST_MEDIA_TYPE)
+        .body(serde_json::to_vec(manifest).into_diagnostic()?)
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("failed to push the manifest {}", tag))?;
+        Ok(())
+    }
+}
+
+/// The config blob of the manifests (an empty JSON object).
+fn config_blob() -> Blob<'static> {
+    Blob {
+        media_type: CONFIG_MEDIA_TYPE,
+        digest: format!("sha256:{:x}", compute_bytes_digest::<Sha256>(b"{}")),
+        size: 2,
+        title: None,
+    }
+}
+
+/// Read the `repodata.json` of a manifest of the repodata artifact.
+async fn pull_repodata(
+    client: &RegistryClient<'_>,
+    manifest: &serde_json::Value,
+) -> miette::Result<serde_json::Value> {
+    let digest = manifest["layers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|layer| layer["mediaType"] == REPODATA_MEDIA_TYPE)
+        .and_then(|layer| layer["digest"].as_str())
+        .ok_or_else(|| {
+            miette::miette!(
+                "the manifest of {}:{} has no repodata layer",
+                client.repository,
+                REPODATA_TAG
+            )
+        })?;
+    let bytes = client.pull_blob(digest).await?;
+    serde_json::from_slice(&bytes)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("failed to parse the repodata of {}", client.repository))
+}
+
+/// Add a package to the `repodata.json` artifact of its subdirectory. The repodata is
+/// only written if its manifest did not change since it was read, otherwise the update
+/// starts again with the new repodata.
+async fn update_repodata(
+    context: &UploadContext,
+    target: &UploadTarget,
+    authentication: &Authentication,
+    subdir: &str,
+    package_file: &Path,
+    file_name: &str,
+) -> miette::Result<()> {
+    let mut client = RegistryClient {
+        context,
+        server: &target.url,
+        registry: registry_host(target),
+        repository: format!("{}/{}/repodata.json", target.channel, subdir),
+        credentials: authentication,
+        token: None,
+    };
+    client.login().await?;
+
+    for attempt in 1..=REPODATA_ATTEMPTS {
+        let current = client.pull_manifest(REPODATA_TAG).await?;
+        let mut repodata = match &current {
+            Some((_, manifest)) => pull_repodata(&client, manifest).await?,
+            None => empty_repodata(subdir),
+        };
+        add_to_repodata(&mut repodata, package_file, file_name)?;
+
+        let repodata = serde_json::to_vec(&repodata).into_diagnostic()?;
+        let config_blob = config_blob();
+        let repodata_blob = Blob {
+            media_type: REPODATA_MEDIA_TYPE,
+            digest: format!("sha256:{:x}", compute_bytes_digest::<Sha256>(&repodata)),
+            size: repodata.len() as u64,
+            title: Some("repodata.json".to_string()),
+        };
+        client.push_blob(&config_blob, Ok(b"{}")).await?;
+        client.push_blob(&repodata_blob, Ok(&repodata)).await?;
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_MEDIA_TYPE,
+            "config": config_blob.descriptor(),
+            "layers": [repodata_blob.descriptor()],
+        });
+
+        // registries cannot update a tag conditionally, so check right before the
+        // write that no other upload changed the repodata
+        let latest = client.pull_manifest(REPODATA_TAG).await?;
+        let digest = |manifest: &Option<(String, serde_json::Value)>| {
+            manifest.as_ref().map(|(digest, _)| digest.clone())
+        };
+        if digest(&latest) != digest(&current) {
+            tracing::info!(
+                "The repodata of {} changed during the update, retrying ({}/{})",
+                client.repository,
+                attempt,
+                REPODATA_ATTEMPTS
+            );
+            tokio::time::sleep(super::backoff(attempt)).await;
+            continue;
+        }
+        client.push_manifest(REPODATA_TAG, &manifest).await?;
+        tracing::info!("Added {} to {}", file_name, client.repository);
+        return Ok(());
+    }
+
+    Err(miette::miette!(
+        "the repodata of {} changed during every update attempt",
+        client.repository
+    ))
+}
+
+/// The repository of an upload session location (`/v2/<repository>/blobs/uploads/<id>`).
+fn session_repository(location: &Url) -> Option<String> {
+    let (repository, _) = location
+        .path()
+        .strip_prefix("/v2/")?
+        .split_once("/blobs/uploads/")?;
+    Some(repository.to_string())
+}
+
+/// The registry host of a target (e.g. `ghcr.io` or `localhost:5000`).
+fn registry_host(target: &UploadTarget) -> String {
+    target.url[url::Position::BeforeHost..url::Position::AfterPort].to_string()
+}
+
+impl UploadBackend for Oci {
+    fn name(&self) -> &str {
+        "oci"
+    }
+
+    fn idempotent_upload(&self) -> bool {
+        // the blobs are addressed by their digest and the manifest is `PUT` to its tag
+        true
+    }
+
+    fn resolve_target(&self, url: &Url) -> Option<UploadTarget> {
+        if url.scheme() != "oci" {
+            return None;
+        }
+        let channel = url.path().trim_matches('/');
+        if channel.is_empty() {
+            return None;
+        }
+        let host = &url[url::Position::BeforeHost..url::Position::AfterPort];
+        let scheme = if host.starts_with("localhost") || host.starts_with("127.0.0.1") {
+            "http"
+        } else {
+            "https"
+        };
+        let server = format!("{}://{}/", scheme, host);
+        Some(UploadTarget::new(Url::parse(&server).ok()?, channel))
+    }
+
+    fn authenticate(
+        &self,
+        storage: &AuthenticationStorage,
+        target: &UploadTarget,
+    ) -> miette::Result<Authentication> {
+        if let Some(credentials) = &target.credentials {
+            return Ok(credentials.clone());
+        }
+        let registry = registry_host(target);
+        if let Some(credentials) =
+            docker_config().and_then(|config| credentials_from_docker_config(&config, &registry))
+        {
+            return Ok(credentials);
+        }
+        super::backend::stored_authentication(storage, &target.url, &registry)
+    }
+
+    fn upload<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            let package = ExtractedPackage::from_package_file(package_file)?;
+            let index_json = package.index_json();
+            let subdir = package.subdir().ok_or_else(|| {
+                miette::miette!(
+                    "index.json of package {} has no subdirectory. Cannot determine which repository to upload to",
+                    package_file.display()
+                )
+            })?;
+            let file_name = package
+                .filename()
+                .ok_or_else(|| miette::miette!("{} has no file name", package_file.display()))?;
+            let package_media_type = match ArchiveType::try_from(package_file) {
+                Some(ArchiveType::Conda) => "application/vnd.conda.package.v2",
+                _ => "application/vnd.conda.package.v1",
+            };
+
+            let mut client = RegistryClient {
+                context,
+                server: &target.url,
+                registry: registry_host(target),
+                repository: format!(
+                    "{}/{}/{}",
+                    target.channel,
+                    subdir,
+                    repository_name(index_json.name.as_normalized())
+                ),
+                credentials: authentication,
+                token: None,
+            };
+            client.login().await?;
+
+            let config_blob = config_blob();
+            let index_bytes =
+                fs_err::read(package.extraction_dir().join("info/index.json")).into_diagnostic()?;
+            let index_blob = Blob {
+                media_type: INDEX_MEDIA_TYPE,
+                digest: format!("sha256:{:x}", compute_bytes_digest::<Sha256>(&index_bytes)),
+                size: index_bytes.len() as u64,
+                title: Some("index.json".to_string()),
+            };
+            let package_blob = Blob {
+                media_type: package_media_type,
+                digest: format!(
+                    "sha256:{:x}",
+                    compute_file_digest::<Sha256>(package_file).into_diagnostic()?
+                ),
+                size: package.file_size().into_diagnostic()?,
+                title: Some(file_name.to_string()),
+            };
+
+            client.push_blob(&config_blob, Ok(b"{}")).await?;
+            client.push_blob(&index_blob, Ok(&index_bytes)).await?;
+            client.push_blob(&package_blob, Err(package_file)).await?;
+
+            let manifest = json!({
+                "schemaVersion": 2,
+                "mediaType": MANIFEST_MEDIA_TYPE,
+                "config": config_blob.descriptor(),
+                "layers": [package_blob.descriptor(), index_blob.descriptor()],
+            });
+            let tag = package_tag(&index_json.version.to_string(), &index_json.build);
+            client.push_manifest(&tag, &manifest).await?;
+
+            tracing::info!(
+                "Pushed {} to {}/{}:{}",
+                file_name,
+                client.registry,
+                client.repository,
+                tag
+            );
+
+            update_repodata(
+                context,
+                target,
+                authentication,
+                subdir,
+                package_file,
+                file_name,
+            )
+            .await
+            .wrap_err("failed to update the repodata of the channel")
+        })
+    }
+
+    fn abort<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            let Some(location) = context
+                .resume_state(package_file)
+                .and_then(|state| Url::parse(&state).ok())
+            else {
+                return Ok(());
+            };
+            let Some(repository) = session_repository(&location) else {
+                return Ok(());
+            };
+            let mut client = RegistryClient {
+                context,
+                server: &target.url,
+                registry: registry_host(target),
+                repository,
+                credentials: authentication,
+                token: None,
+            };
+            client.login().await?;
+            client.cancel_session(location).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_tag() {
+        assert_eq!(repository_name("zlib"), "zlib");
+        assert_eq!(repository_name("_libgcc_mutex"), "zzz_libgcc_mutex");
+        assert_eq!(package_tag("1.3.1", "h4ab18f5_1"), "1.3.1-h4ab18f5_1");
+        assert_eq!(
+            package_tag("1!2.0+local", "py_0"),
+            "1__e__2.0__p__local-py_0"
+        );
+    }
+
+    #[test]
+    fn test_resolve_target() {
+        let url = Url::parse("oci://ghcr.io/my-org/my-channel").unwrap();
+        let target = Oci.resolve_target(&url).unwrap();
+        assert_eq!(target.url.as_str(), "https://ghcr.io/");
+        assert_eq!(target.channel, "my-org/my-channel");
+        assert_eq!(registry_host(&target), "ghcr.io");
+
+        let url = Url::parse("oci://localhost:5000/channel").unwrap();
+        let target = Oci.resolve_target(&url).unwrap();
+        assert_eq!(target.url.as_str(), "http://localhost:5000/");
+        assert_eq!(registry_host(&target), "localhost:5000");
+
+        assert!(Oci
+            .resolve_target(&Url::parse("https://ghcr.io/my-org").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_session_repository() {
+        let location = Url::parse(
+            "https://ghcr.io/v2/my-org/my-channel/linux-64/zlib/blobs/uploads/1234?state=abc",
+        )
+        .unwrap();
+        assert_eq!(
+            session_repository(&location).as_deref(),
+            Some("my-org/my-channel/linux-64/zlib")
+        );
+        assert!(session_repository(&Url::parse("https://ghcr.io/v2/").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_credentials_from_docker_config() {
+        let config: DockerConfig =
+            serde_json::from_str(r#"{"auths": {"ghcr.io": {"auth": "dXNlcjpzZWNyZXQ="}}}"#)
+                .unwrap();
+        assert_eq!(
+            credentials_from_docker_config(&config, "ghcr.io"),
+            Some(Authentication::BasicHTTP {
+                username: "user".to_string(),
+                password: "secret".to_string(),
+            })
+        );
+        assert_eq!(credentials_from_docker_config(&config, "quay.io"), None);
+    }
+}
@@ -0,0 +1,89 @@
+//! Uploading packages to a prefix.dev server.
+
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use miette::IntoDiagnostic;
+use rattler_networking::{Authentication, AuthenticationStorage};
+use url::Url;
+
+use super::{
+    backend::{scheme_backend, stored_authentication, UploadBackend, UploadTarget},
+    package::sha256_sum,
+    UploadContext,
+};
+
+/// The backend for prefix.dev (and self-hosted instances), authenticated with a bearer
+/// token (the prefix.dev API key).
+pub struct Prefix;
+
+impl UploadBackend for Prefix {
+    fn name(&self) -> &str {
+        "prefix"
+    }
+
+    fn resolve_target(&self, url: &Url) -> Option<UploadTarget> {
+        let handled = match scheme_backend(url) {
+            Some(backend) => backend == self.name(),
+            None => url.host_str() == Some("prefix.dev"),
+        };
+        handled.then(|| UploadTarget::from_url(url)).flatten()
+    }
+
+    fn authenticate(
+        &self,
+        storage: &AuthenticationStorage,
+        target: &UploadTarget,
+    ) -> miette::Result<Authentication> {
+        let authentication = match &target.credentials {
+            Some(credentials) => credentials.clone(),
+            None => stored_authentication(storage, &target.url, "prefix.dev")?,
+        };
+        match authentication {
+            Authentication::BearerToken(_) => Ok(authentication),
+            _ => Err(miette::miette!("A Conda token is required for authentication with prefix.dev.
+                        Authentication information found in the keychain / auth file, but it was not a Bearer token")),
+        }
+    }
+
+    fn upload<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            let Authentication::BearerToken(token) = authentication else {
+                return Err(miette::miette!("A bearer token is required for prefix.dev"));
+            };
+
+            let filename = package_file
+                .file_name()
+                .expect("no filename found")
+                .to_string_lossy()
+                .to_string();
+
+            let file_size = package_file.metadata().into_diagnostic()?.len();
+
+            let url = target
+                .url
+                .join(&format!("api/v1/upload/{}", target.channel))
+                .into_diagnostic()?;
+
+            let hash = sha256_sum(package_file).into_diagnostic()?;
+
+            let prepared_request = context
+                .client()
+                .post(url)
+                .header("X-File-Sha256", hash)
+                .header("X-File-Name", filename)
+                .header("Content-Length", file_size)
+                .header("Content-Type", "application/octet-stream")
+                .bearer_auth(token.clone());
+
+            context.send_request(prepared_request, package_file).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,73 @@
+//! Uploading packages to a Quetz server.
+
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use miette::IntoDiagnostic;
+use rattler_networking::{Authentication, AuthenticationStorage};
+use reqwest::Method;
+
+use super::{
+    backend::{stored_authentication, UploadBackend, UploadTarget},
+    package::sha256_sum,
+    UploadContext,
+};
+
+/// The backend for Quetz servers, authenticated with a Conda token (the Quetz API key).
+pub struct Quetz;
+
+impl UploadBackend for Quetz {
+    fn name(&self) -> &str {
+        "quetz"
+    }
+
+    fn authenticate(
+        &self,
+        storage: &AuthenticationStorage,
+        target: &UploadTarget,
+    ) -> miette::Result<Authentication> {
+        let authentication = match &target.credentials {
+            Some(credentials) => credentials.clone(),
+            None => stored_authentication(storage, &target.url, "quetz")?,
+        };
+        match authentication {
+            Authentication::CondaToken(_) => Ok(authentication),
+            _ => Err(miette::miette!("A Conda token is required for authentication with quetz.
+                        Authentication information found in the keychain / auth file, but it was not a Conda token")),
+        }
+    }
+
+    fn upload<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            let Authentication::CondaToken(token) = authentication else {
+                return Err(miette::miette!("A Conda token is required for quetz"));
+            };
+
+            let upload_url = target
+                .url
+                .join(&format!(
+                    "api/channels/{}/upload/{}",
+                    target.channel,
+                    package_file.file_name().unwrap().to_string_lossy()
+                ))
+                .into_diagnostic()?;
+
+            let hash = sha256_sum(package_file).into_diagnostic()?;
+
+            let prepared_request = context
+                .client()
+                .request(Method::POST, upload_url)
+                .query(&[("force", "false"), ("sha256", &hash)])
+                .header("X-API-Key", token.clone());
+
+            context.send_request(prepared_request, package_file).await?;
+            Ok(())
+        })
+    }
+}
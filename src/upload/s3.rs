@@ -0,0 +1,796 @@
+//! Uploading packages to an S3 bucket (or an S3 compatible object store like MinIO) that
+//! is laid out as a conda channel.
+//!
+//! The channel `s3://my-bucket/my-channel` stores `linux-64/zlib-1.3.1-h4ab18f5_1.conda` at
+//! the key `my-channel/linux-64/zlib-1.3.1-h4ab18f5_1.conda`. After a package is uploaded,
+//! the `repodata.json` (and `repodata.json.zst`) of its subdirectory is updated, either by
+//! adding the package to the existing repodata or by synchronizing the repodata with the
+//! packages in the subdirectory.
+//!
+//! The repodata is written with a conditional request (`If-Match` with the ETag that was
+//! read, or `If-None-Match: *` if there was no repodata yet), so an update that raced with
+//! another upload is retried instead of dropping the other package.
+//!
+//! The requests are made with the AWS SDK, the same credential chain that is used to
+//! download from `s3://` channels: the credentials, region and endpoint are taken from the
+//! command line, the `AWS_*` environment variables, the AWS profile (including SSO and
+//! `role_arn`) or the instance metadata, and a role can be assumed with STS.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use async_once_cell::OnceCell;
+use aws_sdk_s3::{
+    config::{Credentials, Region},
+    error::{DisplayErrorContext, SdkError},
+    operation::put_object::PutObjectError,
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use clap::ValueEnum;
+use futures::future::BoxFuture;
+use miette::{Context, IntoDiagnostic};
+use rattler_conda_types::{package::ArchiveType, Platform};
+use rattler_networking::{Authentication, AuthenticationStorage};
+use url::Url;
+
+use super::{
+    add_to_repodata,
+    backend::{UploadBackend, UploadTarget},
+    empty_repodata,
+    package::ExtractedPackage,
+    read_chunk, UploadContext,
+};
+
+/// The region that is used if none is configured.
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// The minimum size of the parts of a multipart upload (except for the last part).
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// How often the repodata is read and written again when another upload changed it.
+const REPODATA_ATTEMPTS: u32 = 5;
+
+/// The sections of the repodata that contain the package records.
+const PACKAGE_SECTIONS: [&str; 2] = ["packages", "packages.conda"];
+
+/// How the repodata of a subdirectory is updated after a package is uploaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum RepodataUpdate {
+    /// Add the package to the existing `repodata.json`
+    #[default]
+    Patch,
+    /// Drop the records of packages that were removed from the subdirectory and index
+    /// the packages that are not in the `repodata.json` yet
+    Regenerate,
+}
+
+/// The settings of the S3 backend. Settings that are not given are read from the `AWS_*`
+/// environment variables and the AWS profile.
+#[derive(Debug, Clone, Default)]
+pub struct S3Config {
+    /// The endpoint of an S3 compatible object store (e.g. `http://localhost:9000`)
+    pub endpoint_url: Option<Url>,
+    /// The region of the bucket
+    pub region: Option<String>,
+    /// The AWS profile to read the settings and credentials from
+    pub profile: Option<String>,
+    /// A role to assume with STS before uploading
+    pub role_arn: Option<String>,
+    /// The session token of temporary credentials that were given explicitly
+    pub session_token: Option<String>,
+    /// Address the bucket in the path instead of the host name. This is always done for
+    /// custom endpoints.
+    pub force_path_style: bool,
+    /// How the repodata is updated
+    pub repodata: RepodataUpdate,
+}
+
+/// The backend for S3 buckets, for urls like `s3://my-bucket/my-channel`.
+pub struct S3 {
+    /// The settings of the backend
+    pub config: S3Config,
+    client: OnceCell<Client>,
+    repodata_lock: tokio::sync::Mutex<()>,
+}
+
+impl Default for S3 {
+    fn default() -> Self {
+        Self::new(S3Config::default())
+    }
+}
+
+impl S3 {
+    /// Create a backend with the given settings.
+    pub fn new(config: S3Config) -> Self {
+        Self {
+            config,
+            client: OnceCell::new(),
+            repodata_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// The client of the bucket. Explicit credentials (an access key id and a secret
+    /// access key) are used as they are, any other authentication leaves the credentials
+    /// to the default chain of the AWS SDK.
+    async fn client(&self, authentication: &Authentication) -> miette::Result<&Client> {
+        self.client
+            .get_or_try_init(async {
+                let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+                if let Some(profile) = &self.config.profile {
+                    loader = loader.profile_name(profile);
+                }
+                if let Some(region) = &self.config.region {
+                    loader = loader.region(Region::new(region.clone()));
+                }
+                if let Some(endpoint) = &self.config.endpoint_url {
+                    loader = loader.endpoint_url(endpoint.as_str());
+                }
+                if let Authentication::BasicHTTP { username, password } = authentication {
+                    loader = loader.credentials_provider(Credentials::new(
+                        username,
+                        password,
+                        self.config.session_token.clone(),
+                        None,
+                        "rattler-build",
+                    ));
+                }
+                let sdk_config = loader.load().await;
+
+                let mut config = aws_sdk_s3::config::Builder::from(&sdk_config).force_path_style(
+                    self.config.force_path_style || sdk_config.endpoint_url().is_some(),
+                );
+                if sdk_config.region().is_none() {
+                    config = config.region(Region::new(DEFAULT_REGION));
+                }
+                if let Some(role_arn) = &self.config.role_arn {
+                    let provider = aws_config::sts::AssumeRoleProvider::builder(role_arn)
+                        .session_name("rattler-build")
+                        .configure(&sdk_config)
+                        .build()
+                        .await;
+                    config = config.credentials_provider(provider);
+                }
+                Ok::<_, miette::Report>(Client::from_conf(config.build()))
+            })
+            .await
+    }
+
+    /// The bucket of the target.
+    async fn bucket(
+        &self,
+        target: &UploadTarget,
+        authentication: &Authentication,
+    ) -> miette::Result<Bucket<'_>> {
+        Ok(Bucket {
+            client: self.client(authentication).await?,
+            name: target
+                .url
+                .host_str()
+                .ok_or_else(|| miette::miette!("{} has no bucket", target.url))?
+                .to_string(),
+        })
+    }
+}
+
+/// The key of an object in the channel, e.g. `my-channel/linux-64/repodata.json`.
+fn object_key(channel: &str, parts: &[&str]) -> String {
+    std::iter::once(channel)
+        .chain(parts.iter().copied())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The file name of a package directly in the directory `prefix` (which ends with `/`),
+/// or `None` for other objects.
+fn package_file_name<'a>(prefix: &str, key: &'a str) -> Option<&'a str> {
+    let file_name = key.strip_prefix(prefix)?;
+    (!file_name.contains('/') && ArchiveType::try_from(Path::new(file_name)).is_some())
+        .then_some(file_name)
+}
+
+/// The resume state of a multipart upload: the id of the upload and the key of the object.
+fn multipart_state(upload_id: &str, key: &str) -> String {
+    format!("{upload_id}\n{key}")
+}
+
+/// The id of the upload and the key of the object of a multipart upload resume state.
+fn parse_multipart_state(state: &str) -> Option<(&str, &str)> {
+    state.split_once('\n')
+}
+
+/// An error of the AWS SDK with the message of the service.
+fn sdk_error<E, R>(error: SdkError<E, R>, what: String) -> miette::Report
+where
+    E: std::error::Error + Send + Sync + 'static,
+    R: std::fmt::Debug + Send + Sync + 'static,
+{
+    miette::miette!("failed to {}: {}", what, DisplayErrorContext(&error))
+}
+
+/// The condition of a conditional write.
+enum WriteCondition {
+    /// The object still has the ETag it had when it was read
+    Unchanged(String),
+    /// The object does not exist
+    Missing,
+}
+
+/// A bucket of the object store.
+struct Bucket<'a> {
+    client: &'a Client,
+    name: String,
+}
+
+impl Bucket<'_> {
+    /// Get an object and its ETag, or `None` if it does not exist.
+    async fn get(&self, key: &str) -> miette::Result<Option<(Vec<u8>, Option<String>)>> {
+        match self
+            .client
+            .get_object()
+            .bucket(&self.name)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => {
+                let etag = output.e_tag().map(str::to_string);
+                let bytes = output.body.collect().await.into_diagnostic()?.into_bytes();
+                Ok(Some((bytes.to_vec(), etag)))
+            }
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => Err(sdk_error(e, format!("get s3://{}/{}", self.name, key))),
+        }
+    }
+
+    /// Write an object. Returns `false` if the condition did not hold, i.e. the object was
+    /// changed by someone else.
+    async fn put(
+        &self,
+        key: &str,
+        body: Vec<u8>,
+        content_type: &str,
+        condition: Option<&WriteCondition>,
+    ) -> miette::Result<bool> {
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.name)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(body));
+        let request = match condition {
+            Some(WriteCondition::Unchanged(etag)) => request.if_match(etag),
+            Some(WriteCondition::Missing) => request.if_none_match("*"),
+            None => request,
+        };
+        match request.send().await {
+            Ok(_) => Ok(true),
+            Err(e) if condition.is_some() && precondition_failed(&e) => Ok(false),
+            Err(e) => Err(sdk_error(e, format!("upload s3://{}/{}", self.name, key))),
+        }
+    }
+
+    /// Upload a file (with a progress bar). Files larger than the chunk size are uploaded
+    /// in parts.
+    async fn put_file(
+        &self,
+        context: &UploadContext,
+        key: &str,
+        path: &Path,
+    ) -> miette::Result<()> {
+        let size = fs_err::metadata(path).into_diagnostic()?.len();
+        let part_size = context.chunk_size().max(MIN_PART_SIZE);
+        if size > part_size {
+            return self
+                .put_multipart(context, key, path, size, part_size)
+                .await;
+        }
+        let progress_bar = context.progress_bar(path, size, 0)?;
+        let body = ByteStream::from_path(path).await.into_diagnostic()?;
+        self.client
+            .put_object()
+            .bucket(&self.name)
+            .key(key)
+            .content_type("application/octet-stream")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| sdk_error(e, format!("upload s3://{}/{}", self.name, key)))?;
+        progress_bar.inc(size);
+        progress_bar.finish();
+        Ok(())
+    }
+
+    /// Upload a file with a multipart upload. The id of the upload is stored in the resume
+    /// state of the context, so that a retry only uploads the parts that are missing (and
+    /// the upload can be aborted after the last attempt failed).
+    async fn put_multipart(
+        &self,
+        context: &UploadContext,
+        key: &str,
+        path: &Path,
+        size: u64,
+        part_size: u64,
+    ) -> miette::Result<()> {
+        let upload_id = context.resume_state(path).and_then(|state| {
+            parse_multipart_state(&state).map(|(upload_id, _)| upload_id.to_string())
+        });
+        let resumed = match upload_id {
+            Some(upload_id) => match self.list_parts(key, &upload_id).await {
+                Ok(parts) => Some((upload_id, parts)),
+                Err(e) => {
+                    tracing::warn!("Cannot resume the upload of {} ({e})", path.display());
+                    None
+                }
+            },
+            None => None,
+        };
+        let (upload_id, uploaded) = match resumed {
+            Some(resumed) => resumed,
+            None => (self.create_multipart_upload(key).await?, HashMap::new()),
+        };
+        context.set_resume_state(path, Some(multipart_state(&upload_id, key)));
+
+        let ranges = (0..size.div_ceil(part_size))
+            .map(|index| index * part_size..((index + 1) * part_size).min(size))
+            .collect::<Vec<_>>();
+        let is_uploaded = |number: usize, range: &std::ops::Range<u64>| {
+            uploaded
+                .get(&(number as i32 + 1))
+                .filter(|(_, part_size)| *part_size == range.end - range.start)
+        };
+        let position = ranges
+            .iter()
+            .enumerate()
+            .filter(|(number, range)| is_uploaded(*number, range).is_some())
+            .map(|(_, range)| range.end - range.start)
+            .sum();
+        let progress_bar = context.progress_bar(path, size, position)?;
+
+        let mut parts = Vec::new();
+        for (number, range) in ranges.iter().enumerate() {
+            let part_number = number as i32 + 1;
+            let etag = match is_uploaded(number, range) {
+                Some((etag, _)) => etag.clone(),
+                None => {
+                    let chunk = read_chunk(path, range.clone()).await.into_diagnostic()?;
+                    let output = self
+                        .client
+                        .upload_part()
+                        .bucket(&self.name)
+                        .key(key)
+                        .upload_id(&upload_id)
+                        .part_number(part_number)
+                        .body(ByteStream::from(chunk))
+                        .send()
+                        .await
+                        .map_err(|e| {
+                            sdk_error(e, format!("upload part {} of {}", part_number, key))
+                        })?;
+                    progress_bar.inc(range.end - range.start);
+                    output
+                        .e_tag()
+                        .ok_or_else(|| miette::miette!("S3 did not return the ETag of a part"))?
+                        .to_string()
+                }
+            };
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .e_tag(etag)
+                    .build(),
+            );
+        }
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.name)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| sdk_error(e, format!("complete the upload of {}", key)))?;
+
+        context.set_resume_state(path, None);
+        progress_bar.finish();
+        Ok(())
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> miette::Result<String> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.name)
+            .key(key)
+            .content_type("application/octet-stream")
+            .send()
+            .await
+            .map_err(|e| sdk_error(e, format!("start the upload of {}", key)))?;
+        output
+            .upload_id()
+            .map(str::to_string)
+            .ok_or_else(|| miette::miette!("S3 did not return the id of the multipart upload"))
+    }
+
+    /// Abort a multipart upload, so that the bucket does not keep its parts.
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> miette::Result<()> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.name)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| sdk_error(e, format!("abort the upload of {}", key)))?;
+        Ok(())
+    }
+
+    /// The ETags and sizes of the parts of a multipart upload, by part number.
+    async fn list_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+    ) -> miette::Result<HashMap<i32, (String, u64)>> {
+        let mut pages = self
+            .client
+            .list_parts()
+            .bucket(&self.name)
+            .key(key)
+            .upload_id(upload_id)
+            .into_paginator()
+            .send();
+        let mut parts = HashMap::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| sdk_error(e, format!("list the parts of {}", key)))?;
+            parts.extend(page.parts().iter().filter_map(|part| {
+                Some((
+                    part.part_number()?,
+                    (part.e_tag()?.to_string(), u64::try_from(part.size()?).ok()?),
+                ))
+            }));
+        }
+        Ok(parts)
+    }
+
+    /// The keys of all objects that start with the prefix.
+    async fn list(&self, prefix: &str) -> miette::Result<Vec<String>> {
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.name)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        let mut keys = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page =
+                page.map_err(|e| sdk_error(e, format!("list s3://{}/{}", self.name, prefix)))?;
+            keys.extend(
+                page.contents()
+                    .iter()
+                    .filter_map(|object| object.key().map(str::to_string)),
+            );
+        }
+        Ok(keys)
+    }
+
+    /// Write the `repodata.json` of a subdirectory if the condition holds, followed by its
+    /// `repodata.json.zst`. Returns `false` if the repodata was changed by someone else.
+    async fn put_repodata(
+        &self,
+        key: &str,
+        repodata: &[u8],
+        condition: &WriteCondition,
+    ) -> miette::Result<bool> {
+        if !self
+            .put(key, repodata.to_vec(), "application/json", Some(condition))
+            .await?
+        {
+            return Ok(false);
+        }
+        let compressed =
+            zstd::stream::encode_all(repodata, crate::index::ZSTD_LEVEL).into_diagnostic()?;
+        self.put(
+            &format!("{}.zst", key),
+            compressed,
+            "application/zstd",
+            None,
+        )
+        .await?;
+        Ok(true)
+    }
+}
+
+/// Whether a conditional write failed because the object was changed (`412 Precondition
+/// Failed`, or `409 Conflict` for a concurrent conditional write).
+fn precondition_failed(error: &SdkError<PutObjectError>) -> bool {
+    error
+        .raw_response()
+        .is_some_and(|response| matches!(response.status().as_u16(), 409 | 412))
+}
+
+/// Drop the records of packages that are no longer in the subdirectory and add the
+/// packages that are not in the repodata yet. Only the new packages are downloaded.
+async fn sync_repodata(
+    bucket: &Bucket<'_>,
+    channel: &str,
+    subdir: &str,
+    repodata: &mut serde_json::Value,
+) -> miette::Result<()> {
+    let prefix = format!("{}/", object_key(channel, &[subdir]));
+    let packages = bucket
+        .list(&prefix)
+        .await?
+        .iter()
+        .filter_map(|key| package_file_name(&prefix, key))
+        .map(str::to_string)
+        .collect::<BTreeSet<_>>();
+
+    for section in PACKAGE_SECTIONS {
+        if let Some(records) = repodata[section].as_object_mut() {
+            records.retain(|file_name, _| packages.contains(file_name));
+        }
+    }
+
+    let temp_dir = tempfile::tempdir().into_diagnostic()?;
+    for file_name in &packages {
+        if PACKAGE_SECTIONS
+            .iter()
+            .any(|section| repodata[*section].get(file_name).is_some())
+        {
+            continue;
+        }
+        let Some((bytes, _)) = bucket.get(&format!("{}{}", prefix, file_name)).await? else {
+            continue;
+        };
+        let path = temp_dir.path().join(file_name);
+        fs_err::write(&path, bytes).into_diagnostic()?;
+        add_to_repodata(repodata, &path, file_name)?;
+        fs_err::remove_file(&path).into_diagnostic()?;
+    }
+    Ok(())
+}
+
+/// Update the repodata of a subdirectory after a package was uploaded. The repodata is
+/// read with its ETag and written back only if it was not changed in the meantime,
+/// otherwise the update starts again with the new repodata.
+async fn update_repodata(
+    bucket: &Bucket<'_>,
+    channel: &str,
+    subdir: &str,
+    mode: RepodataUpdate,
+    package_file: &Path,
+    file_name: &str,
+) -> miette::Result<()> {
+    let key = object_key(channel, &[subdir, "repodata.json"]);
+    for attempt in 1..=REPODATA_ATTEMPTS {
+        let (mut repodata, condition) = match bucket.get(&key).await? {
+            Some((bytes, etag)) => {
+                let repodata = serde_json::from_slice(&bytes)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("failed to parse s3://{}/{}", bucket.name, key))?;
+                let etag = etag.ok_or_else(|| {
+                    miette::miette!("S3 did not return the ETag of s3://{}/{}", bucket.name, key)
+                })?;
+                (repodata, WriteCondition::Unchanged(etag))
+            }
+            None => (empty_repodata(subdir), WriteCondition::Missing),
+        };
+        match mode {
+            RepodataUpdate::Patch => add_to_repodata(&mut repodata, package_file, file_name)?,
+            RepodataUpdate::Regenerate => {
+                sync_repodata(bucket, channel, subdir, &mut repodata).await?
+            }
+        }
+
+        let repodata = serde_json::to_vec(&repodata).into_diagnostic()?;
+        if bucket.put_repodata(&key, &repodata, &condition).await? {
+            tracing::info!("Added {} to s3://{}/{}", file_name, bucket.name, key);
+            return Ok(());
+        }
+        tracing::info!(
+            "The repodata of s3://{}/{} changed during the update, retrying ({}/{})",
+            bucket.name,
+            key,
+            attempt,
+            REPODATA_ATTEMPTS
+        );
+        tokio::time::sleep(super::backoff(attempt)).await;
+    }
+
+    Err(miette::miette!(
+        "the repodata of s3://{}/{} changed during every update attempt",
+        bucket.name,
+        key
+    ))
+}
+
+impl UploadBackend for S3 {
+    fn name(&self) -> &str {
+        "s3"
+    }
+
+    fn idempotent_upload(&self) -> bool {
+        // the objects are `PUT` to their keys, and the repodata is updated conditionally
+        true
+    }
+
+    fn resolve_target(&self, url: &Url) -> Option<UploadTarget> {
+        if url.scheme() != "s3" {
+            return None;
+        }
+        let bucket = url.host_str()?;
+        let server = Url::parse(&format!("s3://{}/", bucket)).ok()?;
+        Some(UploadTarget::new(server, url.path().trim_matches('/')))
+    }
+
+    fn authenticate(
+        &self,
+        storage: &AuthenticationStorage,
+        target: &UploadTarget,
+    ) -> miette::Result<Authentication> {
+        if let Some(credentials) = &target.credentials {
+            return Ok(credentials.clone());
+        }
+        match storage.get_by_url(target.url.clone()) {
+            Ok((_, Some(authentication))) => Ok(authentication),
+            Ok((_, None)) => {
+                // an empty token leaves the credentials to the AWS SDK (environment
+                // variables, profile, SSO, instance metadata)
+                Ok(Authentication::CondaToken(String::new()))
+            }
+            Err(e) => Err(miette::miette!(
+                "failed to read the stored credentials of {}: {}",
+                target.url,
+                e
+            )),
+        }
+    }
+
+    fn upload<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            let bucket = self.bucket(target, authentication).await?;
+
+            let package = ExtractedPackage::from_package_file(package_file)?;
+            let subdir = package.subdir().ok_or_else(|| {
+                miette::miette!(
+                    "index.json of package {} has no subdirectory. Cannot determine which directory to upload to",
+                    package_file.display()
+                )
+            })?;
+            let file_name = package
+                .filename()
+                .ok_or_else(|| miette::miette!("{} has no file name", package_file.display()))?;
+
+            bucket
+                .put_file(
+                    context,
+                    &object_key(&target.channel, &[subdir, file_name]),
+                    package_file,
+                )
+                .await?;
+
+            // the conditional write protects against other processes, this lock avoids
+            // pointless conflicts between the uploads of this process
+            let _lock = self.repodata_lock.lock().await;
+            update_repodata(
+                &bucket,
+                &target.channel,
+                subdir,
+                self.config.repodata,
+                package_file,
+                file_name,
+            )
+            .await?;
+
+            // clients always request `noarch`, so it has to exist even if it is empty
+            let noarch = Platform::NoArch.as_str();
+            let noarch_key = object_key(&target.channel, &[noarch, "repodata.json"]);
+            if subdir != noarch && bucket.get(&noarch_key).await?.is_none() {
+                let repodata = serde_json::to_vec(&empty_repodata(noarch)).into_diagnostic()?;
+                // if the condition fails, another upload created it in the meantime
+                bucket
+                    .put_repodata(&noarch_key, &repodata, &WriteCondition::Missing)
+                    .await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn abort<'a>(
+        &'a self,
+        context: &'a UploadContext,
+        target: &'a UploadTarget,
+        authentication: &'a Authentication,
+        package_file: &'a Path,
+    ) -> BoxFuture<'a, miette::Result<()>> {
+        Box::pin(async move {
+            let Some(state) = context.resume_state(package_file) else {
+                return Ok(());
+            };
+            let Some((upload_id, key)) = parse_multipart_state(&state) else {
+                return Ok(());
+            };
+            tracing::info!("Aborting the multipart upload of {}", key);
+            self.bucket(target, authentication)
+                .await?
+                .abort_multipart_upload(key, upload_id)
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_target() {
+        let url = Url::parse("s3://my-bucket/conda/my-channel").unwrap();
+        let target = S3::default().resolve_target(&url).unwrap();
+        assert_eq!(target.url.as_str(), "s3://my-bucket/");
+        assert_eq!(target.channel, "conda/my-channel");
+        assert_eq!(
+            object_key(&target.channel, &["linux-64", "repodata.json"]),
+            "conda/my-channel/linux-64/repodata.json"
+        );
+        assert_eq!(
+            object_key("", &["noarch", "repodata.json"]),
+            "noarch/repodata.json"
+        );
+    }
+
+    #[test]
+    fn test_package_file_name() {
+        let prefix = "my-channel/linux-64/";
+        assert_eq!(
+            package_file_name(prefix, "my-channel/linux-64/zlib-1.3.1-h4ab18f5_1.conda"),
+            Some("zlib-1.3.1-h4ab18f5_1.conda")
+        );
+        assert_eq!(
+            package_file_name(prefix, "my-channel/linux-64/zlib-1.2.13-hd590300_5.tar.bz2"),
+            Some("zlib-1.2.13-hd590300_5.tar.bz2")
+        );
+        assert_eq!(
+            package_file_name(prefix, "my-channel/linux-64/repodata.json"),
+            None
+        );
+        assert_eq!(
+            package_file_name(
+                prefix,
+                "my-channel/linux-64/.cache/zlib-1.3.1-h4ab18f5_1.conda"
+            ),
+            None
+        );
+        assert_eq!(
+            package_file_name(prefix, "other/linux-64/zlib-1.3.1-h4ab18f5_1.conda"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_multipart_state() {
+        let state = multipart_state("VXBsb2FkIElE", "my-channel/linux-64/foo-1.0-h123_0.conda");
+        assert_eq!(
+            parse_multipart_state(&state),
+            Some(("VXBsb2FkIElE", "my-channel/linux-64/foo-1.0-h123_0.conda"))
+        );
+        assert_eq!(parse_multipart_state("VXBsb2FkIElE"), None);
+    }
+}
@@ -65,26 +65,37 @@ fn extract_variable_from_expression(expr: &Expr, variables: &mut HashSet<String>
                     if let Expr::Const(constant) = &call.args[0] {
                         variables.insert(format!("{}_compiler", &constant.value));
                         variables.insert(format!("{}_compiler_version", &constant.value));
+                        variables.insert(format!("{}_compiler_template", &constant.value));
+                        variables.insert("compiler_template".into());
+                        if constant.value.as_str() == Some("hip") {
+                            variables.insert(crate::gpu::ROCM_VERSION_KEY.into());
+                        }
                     }
                 } else if function == "stdlib" {
                     if let Expr::Const(constant) = &call.args[0] {
                         variables.insert(format!("{}_stdlib", &constant.value));
                         variables.insert(format!("{}_stdlib_version", &constant.value));
+                        variables.insert(format!("{}_stdlib_template", &constant.value));
+                        variables.insert("stdlib_template".into());
                     }
-                } else if function == "pin_subpackage" {
+                } else if function == "pin_subpackage" || function == "outputs" {
+                    // the other output has to be rendered (and built) first
                     if let Expr::Const(constant) = &call.args[0] {
                         variables.insert(format!("{}", &constant.value));
                     }
                 } else if function == "cdt" {
                     variables.insert("cdt_name".into());
                     variables.insert("cdt_arch".into());
-                } else if function == "cmp" {
+                } else if function == "cmp" || function == "match" {
                     if let Expr::Var(var) = &call.args[0] {
                         variables.insert(var.id.to_string());
                     }
                 }
             }
         }
+        Expr::GetAttr(getattr) => {
+            extract_variable_from_expression(&getattr.expr, variables);
+        }
         Expr::IfExpr(ifexpr) => {
             extract_variable_from_expression(&ifexpr.test_expr, variables);
             extract_variable_from_expression(&ifexpr.true_expr, variables);
@@ -199,6 +210,68 @@ fn find_jinja(
     Ok(())
 }
 
+/// Returns the variables used in a single jinja expression (without `${{ }}`)
+pub(crate) fn used_vars_from_expression(expr: &str) -> Result<HashSet<String>, minijinja::Error> {
+    let ast = parse(&format!("${{{{ {} }}}}", expr), "expression.yaml")?;
+    let mut variables = HashSet::new();
+    extract_variables(&ast, &mut variables);
+    Ok(variables)
+}
+
+/// Collect the variables that an expression reads. Unlike [`extract_variable_from_expression`]
+/// this does not add the variant keys that are implied by functions like `compiler`.
+fn extract_referenced_variables(expr: &Expr, variables: &mut HashSet<String>) {
+    match expr {
+        Expr::Var(var) => {
+            variables.insert(var.id.into());
+        }
+        Expr::BinOp(binop) => {
+            extract_referenced_variables(&binop.left, variables);
+            extract_referenced_variables(&binop.right, variables);
+        }
+        Expr::UnaryOp(unaryop) => extract_referenced_variables(&unaryop.expr, variables),
+        Expr::Filter(filter) => {
+            if let Some(expr) = &filter.expr {
+                extract_referenced_variables(expr, variables);
+            }
+            filter
+                .args
+                .iter()
+                .for_each(|arg| extract_referenced_variables(arg, variables));
+        }
+        Expr::Call(call) => call
+            .args
+            .iter()
+            .for_each(|arg| extract_referenced_variables(arg, variables)),
+        Expr::IfExpr(ifexpr) => {
+            extract_referenced_variables(&ifexpr.test_expr, variables);
+            extract_referenced_variables(&ifexpr.true_expr, variables);
+            if let Some(false_expr) = &ifexpr.false_expr {
+                extract_referenced_variables(false_expr, variables);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the variables that are read by the expressions of a jinja template (e.g. a
+/// YAML scalar that contains `${{ ... }}`). Functions like `compiler` are not resolved to
+/// the variant keys they use.
+pub(crate) fn referenced_vars_from_template(
+    template: &str,
+) -> Result<HashSet<String>, minijinja::Error> {
+    let ast = parse(template, "jinja.yaml")?;
+    let mut variables = HashSet::new();
+    if let Stmt::Template(template) = &ast {
+        for child in &template.children {
+            if let Stmt::EmitExpr(emit) = child {
+                extract_referenced_variables(&emit.expr, &mut variables);
+            }
+        }
+    }
+    Ok(variables)
+}
+
 fn variables_from_raw_expr(
     expr: &str,
     src: &str,
@@ -305,6 +378,7 @@ mod test {
             - ${{ compiler('c') }}
             - ${{ stdlib('c') }}
             - ${{ pin_subpackage('abcdef') }}
+            - foo-tools ${{ outputs('libfoo').version }}
         "#;
 
         let recipe_node = crate::recipe::custom_yaml::Node::parse_yaml(0, recipe).unwrap();
@@ -316,7 +390,10 @@ mod test {
         assert!(used_vars.contains("c_compiler_version"));
         assert!(used_vars.contains("c_stdlib"));
         assert!(used_vars.contains("c_stdlib_version"));
+        assert!(used_vars.contains("c_compiler_template"));
+        assert!(used_vars.contains("stdlib_template"));
         assert!(used_vars.contains("abcdef"));
+        assert!(used_vars.contains("libfoo"));
     }
 
     #[test]
@@ -1,13 +1,16 @@
 //! Functions to read and parse variant configuration files.
 
+mod conda_build_config;
+
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     path::PathBuf,
+    str::FromStr,
 };
 
 use indexmap::IndexSet;
 use miette::Diagnostic;
-use rattler_conda_types::{NoArchType, ParseVersionError, Platform};
+use rattler_conda_types::{NoArchType, PackageName, ParseVersionError, Platform, Version};
 use serde::{Deserialize, Serialize};
 
 use thiserror::Error;
@@ -15,6 +18,7 @@ use thiserror::Error;
 use crate::{
     _partialerror,
     hash::HashInfo,
+    metadata::PackageIdentifier,
     recipe::{
         custom_yaml::{HasSpan, Node, RenderedMappingNode, RenderedNode, TryConvertNode},
         error::{ErrorKind, ParsingError, PartialParsingError},
@@ -27,6 +31,33 @@ use crate::{
 use crate::{recipe::parser::Dependency, utils::NormalizedKeyBTreeMap};
 use petgraph::{algo::toposort, graph::DiGraph};
 
+/// A `key=value` override of a variant key, passed with `--variant` on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantOverride {
+    /// The (normalized) variant key
+    pub key: String,
+    /// The value that the key is restricted to
+    pub value: String,
+}
+
+impl FromStr for VariantOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `key=value`, got `{}`", s))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            return Err(format!("expected `key=value`, got `{}`", s));
+        }
+        Ok(VariantOverride {
+            key: NormalizedKeyBTreeMap::normalize_key(key),
+            value: value.to_string(),
+        })
+    }
+}
+
 #[allow(missing_docs)]
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct DiscoveredOutput {
@@ -150,6 +181,63 @@ pub struct VariantConfig {
     /// a variant for the build matrix.
     #[serde(flatten)]
     pub variants: NormalizedKeyBTreeMap,
+
+    /// Where the value of each variant key (and of `zip_keys`) comes from
+    #[serde(skip)]
+    pub sources: BTreeMap<String, VariantSource>,
+}
+
+/// Where the value of a variant key comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariantSource {
+    /// The key was set in a variant config file
+    File(PathBuf),
+    /// The key is set from the platforms of the build (`target_platform` and `build_platform`)
+    Platform,
+    /// The key was restricted with `--variant` on the command line
+    CommandLine,
+}
+
+impl std::fmt::Display for VariantSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VariantSource::File(path) => write!(f, "{}", path.display()),
+            VariantSource::Platform => write!(f, "platform"),
+            VariantSource::CommandLine => write!(f, "--variant"),
+        }
+    }
+}
+
+impl Serialize for VariantSource {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A variant key together with its values and where they come from, as shown by
+/// `render --show-variant-sources`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantKeySource {
+    /// The variant key (or `zip_keys`)
+    pub key: String,
+    /// The values of the key
+    pub values: serde_json::Value,
+    /// The file (or other source) that set the key
+    pub source: Option<VariantSource>,
+}
+
+/// The name of the variant config file in the user configuration directory.
+const USER_VARIANT_CONFIG: &str = "rattler-build/variants.yaml";
+
+/// Returns the variant config of the user (`$XDG_CONFIG_HOME/rattler-build/variants.yaml`,
+/// or `~/.config/rattler-build/variants.yaml`), if it exists.
+pub fn user_variant_config_file() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+    let path = config_dir.join(USER_VARIANT_CONFIG);
+    path.is_file().then_some(path)
 }
 
 #[allow(missing_docs)]
@@ -165,6 +253,9 @@ pub enum VariantConfigError {
     #[error("Could not open file ({0}): {1}")]
     IOError(PathBuf, std::io::Error),
 
+    #[error("Could not translate the conda-build config ({0}): {1}")]
+    CondaBuildConfigError(PathBuf, String),
+
     #[error(transparent)]
     #[diagnostic(transparent)]
     NewParseError(#[from] ParsingError),
@@ -188,6 +279,10 @@ impl VariantConfig {
     /// The above configuration file will select the `python` package with the variants `3.9` and
     /// `3.8`.
     ///
+    /// A `conda_build_config.yaml` of conda-build is translated into a variant configuration
+    /// first: its selectors in comments (e.g. `# [linux]`) are evaluated for the target
+    /// platform, including `os.environ` lookups.
+    ///
     /// The `selector_config` argument is used to select the correct configuration for the target
     /// platform. For example, if the `selector_config` is `unix`, the following configuration file:
     ///
@@ -211,7 +306,9 @@ impl VariantConfig {
     ///
     /// The `files` argument is a list of paths to the variant configuration files. The files are
     /// loaded in the order they are provided in the `files` argument. The keys of a later file
-    /// replace keys from an earlier file (values are _not_ merged).
+    /// replace keys from an earlier file (values are _not_ merged). The same holds for the
+    /// `zip_keys`, while the `pin_run_as_build` entries are merged per package. The file that
+    /// set each key is recorded in [`VariantConfig::sources`].
     ///
     /// A special key, the `zip_keys` is used to "zip" the values of two keys. For example, if the
     /// following configuration file is loaded:
@@ -243,8 +340,18 @@ impl VariantConfig {
         for filename in files {
             let file = std::fs::read_to_string(filename)
                 .map_err(|e| VariantConfigError::IOError(filename.clone(), e))?;
-            let yaml_node = Node::parse_yaml(0, &file)?;
             let jinja = Jinja::new(selector_config.clone());
+            let file = if conda_build_config::is_conda_build_config(filename) {
+                let environment = match &selector_config.environment {
+                    Some(environment) => environment.as_ref().clone(),
+                    None => std::env::vars().collect(),
+                };
+                conda_build_config::translate(&file, &jinja, &environment)
+                    .map_err(|e| VariantConfigError::CondaBuildConfigError(filename.clone(), e))?
+            } else {
+                file
+            };
+            let yaml_node = Node::parse_yaml(0, &file)?;
             let rendered_node: RenderedNode = yaml_node
                 .render(&jinja, filename.to_string_lossy().as_ref())
                 .map_err(|e| ParseErrors::from_partial_vec(&file, e))?;
@@ -255,12 +362,17 @@ impl VariantConfig {
                     parse_errors
                 })?;
 
-            variant_configs.push(config);
+            variant_configs.push((filename, config));
         }
 
         let mut final_config = VariantConfig::default();
-        for config in variant_configs {
-            final_config.variants.extend(config.variants);
+        for (filename, config) in variant_configs {
+            for (key, values) in config.variants {
+                final_config
+                    .sources
+                    .insert(key.clone(), VariantSource::File(filename.clone()));
+                final_config.variants.insert(key, values);
+            }
             if let Some(pin_run_as_build) = config.pin_run_as_build {
                 if let Some(final_pin_run_as_build) = &mut final_config.pin_run_as_build {
                     final_pin_run_as_build.extend(pin_run_as_build);
@@ -268,7 +380,13 @@ impl VariantConfig {
                     final_config.pin_run_as_build = Some(pin_run_as_build);
                 }
             }
-            final_config.zip_keys = config.zip_keys;
+            if config.zip_keys.is_some() {
+                final_config.sources.insert(
+                    "zip_keys".to_string(),
+                    VariantSource::File(filename.clone()),
+                );
+                final_config.zip_keys = config.zip_keys;
+            }
         }
 
         // always insert target_platform and build_platform
@@ -280,10 +398,34 @@ impl VariantConfig {
             "build_platform".into(),
             vec![selector_config.build_platform.to_string()],
         );
+        for key in ["target_platform", "build_platform"] {
+            final_config
+                .sources
+                .insert(key.to_string(), VariantSource::Platform);
+        }
 
         Ok(final_config)
     }
 
+    /// Returns every variant key (and the `zip_keys`) with its values and the file that
+    /// set it.
+    pub fn key_sources(&self) -> Vec<VariantKeySource> {
+        let zip_keys = self.zip_keys.as_ref().map(|zip_keys| VariantKeySource {
+            key: "zip_keys".to_string(),
+            values: serde_json::json!(zip_keys),
+            source: self.sources.get("zip_keys").cloned(),
+        });
+        self.variants
+            .iter()
+            .map(|(key, values)| VariantKeySource {
+                key: key.clone(),
+                values: serde_json::json!(values),
+                source: self.sources.get(key).cloned(),
+            })
+            .chain(zip_keys)
+            .collect()
+    }
+
     fn validate_zip_keys(&self) -> Result<(), VariantError> {
         if let Some(zip_keys) = &self.zip_keys {
             for zip in zip_keys {
@@ -306,6 +448,108 @@ impl VariantConfig {
         Ok(())
     }
 
+    /// Restrict the variant matrix to the values given on the command line (`--variant key=value`).
+    /// Overrides for the same key are combined, so `--variant python=3.11 --variant python=3.12`
+    /// keeps both versions.
+    ///
+    /// The matrix is pruned before the zip keys are expanded: if a key is part of a zip, the
+    /// values of all zipped keys at the pruned positions are removed as well. A key that is not
+    /// zipped is set to the given values, even if they are not in the variant config. Returns
+    /// the pruned (partial) combinations.
+    pub fn apply_overrides(
+        &mut self,
+        overrides: &[VariantOverride],
+    ) -> Result<Vec<BTreeMap<String, String>>, VariantError> {
+        self.validate_zip_keys()?;
+
+        let mut requested: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for variant_override in overrides {
+            let values = requested.entry(variant_override.key.as_str()).or_default();
+            if !values.contains(&variant_override.value.as_str()) {
+                values.push(variant_override.value.as_str());
+            }
+        }
+
+        let mut pruned = Vec::new();
+        for (key, values) in requested {
+            let zip = self
+                .zip_keys
+                .iter()
+                .flatten()
+                .find(|zip| {
+                    zip.len() > 1
+                        && zip
+                            .iter()
+                            .any(|k| NormalizedKeyBTreeMap::normalize_key(k) == key)
+                })
+                .cloned();
+
+            let current = self.variants.get(key).cloned().unwrap_or_default();
+
+            let Some(zip) = zip else {
+                pruned.extend(
+                    current
+                        .iter()
+                        .filter(|value| !values.contains(&value.as_str()))
+                        .map(|value| BTreeMap::from([(key.to_string(), value.clone())])),
+                );
+                self.variants.insert(
+                    key.to_string(),
+                    values.iter().map(|value| value.to_string()).collect(),
+                );
+                self.sources
+                    .insert(key.to_string(), VariantSource::CommandLine);
+                continue;
+            };
+
+            // the values of the other zipped keys are only known for values in the config
+            if let Some(missing) = values
+                .iter()
+                .find(|value| !current.iter().any(|c| c == *value))
+            {
+                return Err(VariantError::VariantOverrideNotFound {
+                    key: key.to_string(),
+                    value: missing.to_string(),
+                    available: current.join(", "),
+                });
+            }
+
+            let keep = current
+                .iter()
+                .map(|value| values.contains(&value.as_str()))
+                .collect::<Vec<_>>();
+
+            for (position, _) in keep.iter().enumerate().filter(|(_, keep)| !**keep) {
+                pruned.push(
+                    zip.iter()
+                        .filter_map(|k| {
+                            let value = self.variants.get(k)?.get(position)?;
+                            Some((NormalizedKeyBTreeMap::normalize_key(k), value.clone()))
+                        })
+                        .collect(),
+                );
+            }
+
+            for k in &zip {
+                if let Some(zipped_values) = self
+                    .variants
+                    .map
+                    .get_mut(&NormalizedKeyBTreeMap::normalize_key(k))
+                {
+                    let mut position = 0;
+                    zipped_values.retain(|_| {
+                        position += 1;
+                        keep[position - 1]
+                    });
+                }
+            }
+            self.sources
+                .insert(key.to_string(), VariantSource::CommandLine);
+        }
+
+        Ok(pruned)
+    }
+
     /// This function returns all possible combinations of variants for the given set of used
     /// variables.
     pub fn combinations(
@@ -366,6 +610,24 @@ impl VariantConfig {
         Ok(result)
     }
 
+    /// Returns the `python` variant value with the lowest version, if any. Values that cannot
+    /// be parsed as a version are ignored.
+    fn lowest_python_variant(&self) -> Option<String> {
+        self.variants
+            .get("python")?
+            .iter()
+            .filter_map(|value| {
+                let version = value
+                    .split_whitespace()
+                    .next()?
+                    .trim_start_matches('=')
+                    .trim_end_matches(".*");
+                Version::from_str(version).ok().map(|v| (v, value))
+            })
+            .min_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, value)| value.clone())
+    }
+
     /// This function finds all used variables in a recipe and expands the recipe to the full
     /// build matrix based on the variant configuration (loaded in the `SelectorConfig`).
     ///
@@ -445,6 +707,21 @@ impl VariantConfig {
             }
         }
 
+        // check the pin expressions now that all outputs are known
+        let output_names = outputs_map.keys().cloned().collect::<BTreeSet<_>>();
+        for output in outputs {
+            Recipe::validate_pins(output, selector_config.clone(), &output_names).map_err(
+                |err| {
+                    let errs: ParseErrors = err
+                        .into_iter()
+                        .map(|err| ParsingError::from_partial(recipe, err))
+                        .collect::<Vec<ParsingError>>()
+                        .into();
+                    errs
+                },
+            )?;
+        }
+
         // now topologically sort the outputs and find cycles
 
         // Create an empty directed graph
@@ -587,6 +864,7 @@ impl VariantConfig {
         for combination in combinations {
             let mut other_recipes =
                 HashMap::<String, (String, String, BTreeMap<String, String>)>::new();
+            let mut subpackages = BTreeMap::<PackageName, PackageIdentifier>::new();
 
             for (_, (name, output, used_vars, target_platform)) in outputs_map.iter() {
                 let mut used_variables = used_vars.clone();
@@ -606,8 +884,10 @@ impl VariantConfig {
                 // we need to overwrite the target_platform in case of `noarch`.
                 combination.insert("target_platform".to_string(), target_platform.to_string());
 
-                let selector_config_with_variant =
-                    selector_config.new_with_variant(combination.clone(), *target_platform);
+                let selector_config_with_variant = SelectorConfig {
+                    subpackages: subpackages.clone(),
+                    ..selector_config.new_with_variant(combination.clone(), *target_platform)
+                };
 
                 let parsed_recipe = Recipe::from_node(output, selector_config_with_variant.clone())
                     .map_err(|err| {
@@ -651,6 +931,13 @@ impl VariantConfig {
                     .filter(|(k, _)| used_variables.contains(k))
                     .collect::<BTreeMap<_, _>>();
 
+                // abi3 packages are built once, against the lowest python version
+                if parsed_recipe.build().python().abi3 && used_filtered.contains_key("python") {
+                    if let Some(lowest) = self.lowest_python_variant() {
+                        used_filtered.insert("python".to_string(), lowest);
+                    }
+                }
+
                 // exact pins
                 for p in exact_pins {
                     match other_recipes.get(&p) {
@@ -728,6 +1015,14 @@ impl VariantConfig {
                         used_filtered.clone(),
                     ),
                 );
+                subpackages.insert(
+                    parsed_recipe.package().name().clone(),
+                    PackageIdentifier {
+                        name: parsed_recipe.package().name().clone(),
+                        version: parsed_recipe.package().version().to_string(),
+                        build_string: build_string.clone(),
+                    },
+                );
                 let version = parsed_recipe.package().version().to_string();
 
                 let ignore_keys = &parsed_recipe.build().variant().ignore_keys;
@@ -869,6 +1164,16 @@ pub enum VariantError {
 
     #[error("Found a cycle in the recipe outputs: {0}")]
     CycleInRecipeOutputs(String),
+
+    #[error("The value `{value}` of `--variant {key}={value}` is not in the variant config (available: {available})")]
+    #[diagnostic(help(
+        "`{key}` is zipped with other keys, so only the values of the variant config can be selected"
+    ))]
+    VariantOverrideNotFound {
+        key: String,
+        value: String,
+        available: String,
+    },
 }
 
 fn find_combinations(
@@ -992,6 +1297,7 @@ mod tests {
             variants,
             zip_keys: Some(zip_keys),
             pin_run_as_build: None,
+            sources: BTreeMap::new(),
         };
 
         let combinations = config.combinations(&used_vars).unwrap();
@@ -1019,6 +1325,136 @@ mod tests {
         assert_eq!(combinations.len(), 2 * 2 * 3);
     }
 
+    #[test]
+    fn test_variant_overrides() {
+        let mut variants = NormalizedKeyBTreeMap::new();
+        variants.insert(
+            "python".to_string(),
+            vec!["3.10".to_string(), "3.11".to_string(), "3.12".to_string()],
+        );
+        variants.insert(
+            "numpy".to_string(),
+            vec!["1.26".to_string(), "1.26".to_string(), "2".to_string()],
+        );
+        variants.insert("c-compiler".to_string(), vec!["gcc".to_string()]);
+        let mut config = VariantConfig {
+            variants,
+            zip_keys: Some(vec![vec!["python".to_string(), "numpy".to_string()]]),
+            pin_run_as_build: None,
+            sources: BTreeMap::new(),
+        };
+
+        let overrides = ["python=3.11", "python=3.12", "c-compiler=clang"]
+            .iter()
+            .map(|s| VariantOverride::from_str(s).unwrap())
+            .collect::<Vec<_>>();
+        let pruned = config.apply_overrides(&overrides).unwrap();
+        assert_eq!(
+            pruned,
+            vec![
+                BTreeMap::from([("c_compiler".to_string(), "gcc".to_string())]),
+                BTreeMap::from([
+                    ("numpy".to_string(), "1.26".to_string()),
+                    ("python".to_string(), "3.10".to_string()),
+                ]),
+            ]
+        );
+        assert_eq!(config.variants.get("python").unwrap(), &["3.11", "3.12"]);
+        assert_eq!(config.variants.get("numpy").unwrap(), &["1.26", "2"]);
+        assert_eq!(config.variants.get("c_compiler").unwrap(), &["clang"]);
+
+        let used_vars = vec!["python".to_string(), "numpy".to_string()]
+            .into_iter()
+            .collect();
+        assert_eq!(config.combinations(&used_vars).unwrap().len(), 2);
+
+        // zipped keys can only be restricted to values of the config
+        let overrides = vec![VariantOverride::from_str("numpy=3").unwrap()];
+        assert!(matches!(
+            config.apply_overrides(&overrides),
+            Err(VariantError::VariantOverrideNotFound { .. })
+        ));
+
+        assert!(VariantOverride::from_str("python").is_err());
+        assert!(VariantOverride::from_str("=3.12").is_err());
+    }
+
+    #[test]
+    fn test_variant_config_layering() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let base = tempdir.path().join("base.yaml");
+        let pins = tempdir.path().join("pins.yaml");
+        std::fs::write(
+            &base,
+            "python:\n  - \"3.11\"\n  - \"3.12\"\nnumpy:\n  - \"1.26\"\n  - \"2\"\nzip_keys:\n  - [python, numpy]\n",
+        )
+        .unwrap();
+        std::fs::write(&pins, "numpy:\n  - \"2.1\"\n  - \"2.1\"\nzlib: \"1.3\"\n").unwrap();
+
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            ..Default::default()
+        };
+        let config =
+            VariantConfig::from_files(&vec![base.clone(), pins.clone()], &selector_config).unwrap();
+
+        // later files replace the keys of earlier files
+        assert_eq!(config.variants.get("numpy").unwrap(), &["2.1", "2.1"]);
+        assert_eq!(config.variants.get("python").unwrap(), &["3.11", "3.12"]);
+        assert_eq!(config.sources["numpy"], VariantSource::File(pins.clone()));
+        assert_eq!(config.sources["zlib"], VariantSource::File(pins));
+        assert_eq!(config.sources["python"], VariantSource::File(base.clone()));
+        assert_eq!(config.sources["zip_keys"], VariantSource::File(base));
+        assert_eq!(config.sources["target_platform"], VariantSource::Platform);
+
+        let keys = config
+            .key_sources()
+            .into_iter()
+            .map(|source| source.key)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "build_platform",
+                "numpy",
+                "python",
+                "target_platform",
+                "zlib",
+                "zip_keys"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conda_build_config() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("conda_build_config.yaml");
+        std::fs::write(
+            &path,
+            "c_compiler:\n  - gcc  # [linux]\n  - vs2019  # [win]\npython:\n  - \"3.11\"\n  - \"3.12\"\nnumpy:\n  - \"1.26\"\n  - \"2\"\nzip_keys:\n  - python\n  - numpy\n",
+        )
+        .unwrap();
+
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Win64,
+            host_platform: Platform::Win64,
+            build_platform: Platform::Linux64,
+            ..Default::default()
+        };
+        let config = VariantConfig::from_files(&vec![path], &selector_config).unwrap();
+        assert_eq!(config.variants.get("c_compiler").unwrap(), &["vs2019"]);
+        assert_eq!(
+            config.zip_keys,
+            Some(vec![vec!["python".to_string(), "numpy".to_string()]])
+        );
+        let used_vars = vec!["python".to_string(), "numpy".to_string()]
+            .into_iter()
+            .collect();
+        assert_eq!(config.combinations(&used_vars).unwrap().len(), 2);
+    }
+
     #[test]
     fn test_order() {
         let test_data_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data");
@@ -1050,6 +1486,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_outputs_function_order() {
+        // like the selector config that is used to find the variants
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            allow_undefined: true,
+            ..Default::default()
+        };
+        let recipe = |tools_about: &str, libfoo_about: &str| {
+            format!(
+                r#"
+recipe:
+  name: foo-split
+  version: 1.2.3
+
+outputs:
+  - package:
+      name: foo-tools
+    about:
+      summary: {tools_about}
+  - package:
+      name: libfoo
+    about:
+      summary: {libfoo_about}
+"#
+            )
+        };
+
+        // `foo-tools` references `libfoo`, so `libfoo` is rendered first
+        let recipe_text = recipe(
+            "Tools for libfoo ${{ outputs('libfoo').version }}",
+            "libfoo",
+        );
+        let outputs = crate::recipe::parser::find_outputs_from_src(&recipe_text).unwrap();
+        let variant_config = VariantConfig::from_files(&vec![], &selector_config).unwrap();
+        let outputs_and_variants = variant_config
+            .find_variants(&outputs, &recipe_text, &selector_config)
+            .unwrap();
+        let order: Vec<_> = outputs_and_variants
+            .iter()
+            .map(|o| o.name.clone())
+            .collect();
+        assert_eq!(order, vec!["libfoo", "foo-tools"]);
+
+        // outputs that reference each other cannot be ordered
+        let recipe_text = recipe(
+            "${{ outputs('libfoo').version }}",
+            "${{ outputs('foo-tools').version }}",
+        );
+        let outputs = crate::recipe::parser::find_outputs_from_src(&recipe_text).unwrap();
+        let result = variant_config.find_variants(&outputs, &recipe_text, &selector_config);
+        assert!(matches!(result, Err(VariantError::CycleInRecipeOutputs(_))));
+    }
+
     #[test]
     fn test_python_is_not_used_as_variant_when_noarch() {
         let test_data_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data");
@@ -1079,4 +1571,31 @@ mod tests {
 
         insta::assert_yaml_snapshot!(used_variables_all);
     }
+
+    #[test]
+    fn test_abi3_builds_single_variant() {
+        let test_data_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data");
+        let yaml_file = test_data_dir.join("recipes/variants/python_variant.yaml");
+        let selector_config = SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            ..Default::default()
+        };
+
+        let recipe_text =
+            std::fs::read_to_string(test_data_dir.join("recipes/variants/abi3_recipe.yaml"))
+                .unwrap();
+        let outputs = crate::recipe::parser::find_outputs_from_src(&recipe_text).unwrap();
+        let variant_config = VariantConfig::from_files(&vec![yaml_file], &selector_config).unwrap();
+        let outputs_and_variants = variant_config
+            .find_variants(&outputs, &recipe_text, &selector_config)
+            .unwrap();
+
+        assert_eq!(outputs_and_variants.len(), 1);
+        assert_eq!(
+            outputs_and_variants[0].used_vars.get("python").unwrap(),
+            "3.8.* *_cpython"
+        );
+    }
 }
@@ -0,0 +1,227 @@
+//! Support for the `conda_build_config.yaml` files of conda-build (e.g. the global
+//! pinning of conda-forge) as variant config files.
+//!
+//! The selectors in comments (`# [linux]`) are translated like the ones of a `meta.yaml`
+//! (see [`crate::convert`]), with `os.environ` lookups replaced by the values of the
+//! environment variables. Lines with a false selector are dropped, like conda-build
+//! does. The result is a native variant config: a flat list of `zip_keys` is turned into
+//! a single group, and the keys that only have a meaning for conda-build are dropped.
+//! All values are kept as strings, so that e.g. `1.10` does not become `1.1`.
+
+use std::{collections::HashMap, path::Path};
+
+use regex::{Captures, Regex};
+use serde_yaml::{Mapping, Value};
+
+use crate::{convert::translate_selector, recipe::Jinja};
+
+/// The name of the variant config files of conda-build.
+const CONDA_BUILD_CONFIG: &str = "conda_build_config.yaml";
+
+/// Keys of a `conda_build_config.yaml` that configure conda-build itself and have no
+/// equivalent in rattler-build.
+const CONDA_BUILD_ONLY_KEYS: [&str; 3] =
+    ["extend_keys", "ignore_version", "ignore_build_only_deps"];
+
+/// Whether the file is a `conda_build_config.yaml` of conda-build.
+pub(crate) fn is_conda_build_config(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name == CONDA_BUILD_CONFIG)
+}
+
+/// Replace the `os.environ` lookups of a selector with the (quoted) values of the
+/// environment variables.
+fn replace_environ(selector: &str, env: &HashMap<String, String>) -> String {
+    let lookup = Regex::new(
+        r#"(?:os\.)?environ(?:\.get\(\s*['"]([^'"]+)['"]\s*(?:,\s*['"]([^'"]*)['"]\s*)?\)|\[\s*['"]([^'"]+)['"]\s*\])"#,
+    )
+    .unwrap();
+    let selector = lookup.replace_all(selector, |caps: &Captures| {
+        let name = caps.get(1).or(caps.get(3)).map_or("", |m| m.as_str());
+        let default = caps.get(2).map_or("", |m| m.as_str());
+        let value = env.get(name).map_or(default, String::as_str);
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    });
+
+    let contains = Regex::new(r#"['"]([^'"]+)['"]\s+(not\s+)?in\s+(?:os\.)?environ\b"#).unwrap();
+    contains
+        .replace_all(&selector, |caps: &Captures| {
+            let defined = env.contains_key(&caps[1]);
+            let negated = caps.get(2).is_some();
+            if defined != negated { "true" } else { "false" }.to_string()
+        })
+        .to_string()
+}
+
+/// Convert a YAML node into a value in which every scalar is a string.
+fn to_value(node: &marked_yaml::Node) -> Value {
+    match node {
+        marked_yaml::Node::Scalar(scalar) => Value::String(scalar.as_str().to_string()),
+        marked_yaml::Node::Sequence(sequence) => {
+            Value::Sequence(sequence.iter().map(to_value).collect())
+        }
+        marked_yaml::Node::Mapping(mapping) => Value::Mapping(
+            mapping
+                .iter()
+                .map(|(key, value)| (Value::String(key.as_str().to_string()), to_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Translate the content of a `conda_build_config.yaml` into a native variant config,
+/// with the `os.environ` lookups of the selectors resolved from `env`. Returns an error
+/// message if a selector cannot be evaluated or the result is not a mapping.
+pub(crate) fn translate(
+    content: &str,
+    jinja: &Jinja,
+    env: &HashMap<String, String>,
+) -> Result<String, String> {
+    let selector_comment = Regex::new(r"^(.*?)\s*#\s*\[(.+)\]\s*$").unwrap();
+
+    let mut lines = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let Some(caps) = selector_comment.captures(line) else {
+            lines.push(line.to_string());
+            continue;
+        };
+        let selector = replace_environ(&translate_selector(&caps[2]), env);
+        let selected = jinja.eval(&selector).map_err(|err| {
+            format!(
+                "line {}: could not evaluate the selector `{}`: {}",
+                index + 1,
+                &caps[2],
+                err
+            )
+        })?;
+        if selected.is_true() {
+            lines.push(caps[1].to_string());
+        }
+    }
+
+    let content = lines.join("\n");
+    let mut config: Mapping = if content.trim().is_empty() {
+        Mapping::new()
+    } else {
+        match marked_yaml::parse_yaml(0, &content).map(|node| to_value(&node)) {
+            Ok(Value::Mapping(config)) => config,
+            Ok(_) => return Err("expected a mapping".to_string()),
+            Err(err) => return Err(err.to_string()),
+        }
+    };
+
+    for key in CONDA_BUILD_ONLY_KEYS {
+        if config.remove(key).is_some() {
+            tracing::warn!(
+                "Ignoring `{}` of {} (not supported)",
+                key,
+                CONDA_BUILD_CONFIG
+            );
+        }
+    }
+
+    // conda-build also accepts a single group of zip keys as a flat list
+    if let Some(Value::Sequence(zip_keys)) = config.get_mut("zip_keys") {
+        if zip_keys.iter().all(|group| !group.is_sequence()) {
+            *zip_keys = vec![Value::Sequence(std::mem::take(zip_keys))];
+        }
+    }
+
+    serde_yaml::to_string(&config).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rattler_conda_types::Platform;
+
+    use super::*;
+    use crate::selectors::SelectorConfig;
+
+    #[test]
+    fn test_translate_conda_build_config() {
+        let content = r#"
+c_compiler:
+  - gcc                        # [linux]
+  - clang                      # [osx]
+  - vs2019                     # [win]
+cuda_compiler_version:
+  - None
+  - 12.0                       # [linux64 and os.environ.get("RATTLER_BUILD_TEST_CUDA", "False") == "True"]
+python:
+  - 3.11.* *_cpython
+  - 3.12.* *_cpython
+numpy:
+  - 1.26
+  - 2
+libxml2:
+  - 2.10
+zip_keys:
+  - python
+  - numpy
+pin_run_as_build:
+  python:
+    min_pin: x.x
+    max_pin: x.x
+extend_keys:
+  - ignore_version
+"#;
+        let jinja = Jinja::new(SelectorConfig {
+            target_platform: Platform::Linux64,
+            host_platform: Platform::Linux64,
+            build_platform: Platform::Linux64,
+            ..Default::default()
+        });
+
+        let env = HashMap::from([("RATTLER_BUILD_TEST_CUDA".to_string(), "False".to_string())]);
+
+        let translated: Value =
+            serde_yaml::from_str(&translate(content, &jinja, &env).unwrap()).unwrap();
+        let expected: Value = serde_yaml::from_str(
+            r#"
+c_compiler: [gcc]
+cuda_compiler_version: [None]
+python: ["3.11.* *_cpython", "3.12.* *_cpython"]
+numpy: ["1.26", "2"]
+libxml2: ["2.10"]
+zip_keys: [[python, numpy]]
+pin_run_as_build:
+  python:
+    min_pin: x.x
+    max_pin: x.x
+"#,
+        )
+        .unwrap();
+        assert_eq!(translated, expected);
+    }
+
+    #[test]
+    fn test_replace_environ() {
+        let env = HashMap::from([("RATTLER_BUILD_TEST_ENVIRON".to_string(), "yes".to_string())]);
+        assert_eq!(
+            replace_environ(
+                r#"os.environ.get('RATTLER_BUILD_TEST_ENVIRON') == "yes""#,
+                &env
+            ),
+            r#""yes" == "yes""#
+        );
+        assert_eq!(
+            replace_environ(r#"environ["RATTLER_BUILD_TEST_UNSET"] == "x""#, &env),
+            r#""" == "x""#
+        );
+        assert_eq!(
+            replace_environ(
+                r#"os.environ.get("RATTLER_BUILD_TEST_UNSET", "False")"#,
+                &env
+            ),
+            r#""False""#
+        );
+        assert_eq!(
+            replace_environ(r#""RATTLER_BUILD_TEST_ENVIRON" in os.environ"#, &env),
+            "true"
+        );
+        assert_eq!(
+            replace_environ(r#"'RATTLER_BUILD_TEST_UNSET' not in environ"#, &env),
+            "true"
+        );
+    }
+}
@@ -714,6 +714,27 @@ def test_read_only_removal(rattler_build: RattlerBuild, recipes: Path, tmp_path:
     assert (pkg / "info/index.json").exists()
 
 
+def test_render_subcommand(rattler_build: RattlerBuild, recipes: Path, tmp_path: Path):
+    path_to_recipe = recipes / "noarch_variant"
+    args = ["render", "--recipe", str(path_to_recipe), "--target-platform=linux-64"]
+
+    rendered = json.loads(rattler_build(*args, stderr=DEVNULL))
+    assert len(rendered) == 2
+    assert rendered[0]["target_platform"] == "linux-64"
+    assert rendered[0]["recipe"]["build"]["string"] == "unix_4616a5c_0"
+    assert rendered[1]["target_platform"] == "noarch"
+    assert rendered[1]["variant"] == {
+        "rattler-build-demo": "1 unix_4616a5c_0",
+        "target_platform": "noarch",
+    }
+    assert "finalized_dependencies" not in rendered[0]
+
+    rendered_yaml = yaml.safe_load(
+        rattler_build(*args, "--format=yaml", stderr=DEVNULL)
+    )
+    assert rendered_yaml == rendered
+
+
 def test_noarch_variants(rattler_build: RattlerBuild, recipes: Path, tmp_path: Path):
     path_to_recipe = recipes / "noarch_variant"
     args = rattler_build.build_args(